@@ -16,6 +16,8 @@ pub enum ConnectionType {
     /// TCP (Stream)
     #[default]
     Tcp,
+    /// UDP, racing TCP started shortly after (client only)
+    Auto,
 }
 
 impl ConnectionType {
@@ -28,13 +30,29 @@ impl ConnectionType {
     pub fn is_udp(&self) -> bool {
         *self == ConnectionType::Udp
     }
+
+    #[allow(missing_docs)]
+    pub fn is_auto(&self) -> bool {
+        *self == ConnectionType::Auto
+    }
 }
 
-impl From<ConnectionType> for LWConnectionType {
-    fn from(item: ConnectionType) -> LWConnectionType {
+/// Error converting [`ConnectionType::Auto`], which stands for both
+/// [`lightway_core::ConnectionType`]s
+#[derive(Debug, thiserror::Error)]
+#[error("Auto connection type is not a single transport")]
+pub struct TryFromError;
+
+/// Was `From` before [`ConnectionType::Auto`] was added, callers must
+/// now handle `Auto`, e.g. by racing both transports.
+impl TryFrom<ConnectionType> for LWConnectionType {
+    type Error = TryFromError;
+
+    fn try_from(item: ConnectionType) -> Result<LWConnectionType, Self::Error> {
         match item {
-            ConnectionType::Udp => LWConnectionType::Datagram,
-            ConnectionType::Tcp => LWConnectionType::Stream,
+            ConnectionType::Udp => Ok(LWConnectionType::Datagram),
+            ConnectionType::Tcp => Ok(LWConnectionType::Stream),
+            ConnectionType::Auto => Err(TryFromError),
         }
     }
 }
@@ -6,7 +6,9 @@ pub mod args;
 pub mod cmsg;
 #[cfg(apple)]
 pub mod recvmsg_x;
+pub mod obfuscation;
 pub mod sockopt;
+pub mod split_dns;
 #[cfg(apple)]
 pub mod udp_disconnect;
 
@@ -35,7 +37,7 @@ pub use event_stream::{EventStream, EventStreamCallback};
 pub use iouring::IOUring;
 
 #[cfg(all(feature = "tokio", desktop))]
-pub use network_change_monitor::NetworkChangeMonitor;
+pub use network_change_monitor::{NetworkChangeMonitor, NetworkId};
 
 #[cfg(feature = "io-uring")]
 pub use tun::TunIoUring;
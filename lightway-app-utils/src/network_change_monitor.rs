@@ -7,12 +7,15 @@
 //! an applicable route *arrival* — add or change, not delete — within a short
 //! window; on Windows any detected change, since route changes are not always
 //! published there; never fired on platforms without an address listener).
+//!
+//! The network itself is told apart by its default route, see [`NetworkId`].
 
 #[cfg(windows)]
 mod addr_monitor;
 
 use anyhow::Result;
-use route_manager::{AsyncRouteListener, Route, RouteChange};
+use route_manager::{AsyncRouteListener, Route, RouteChange, RouteManager};
+use std::net::IpAddr;
 use tokio::sync::watch;
 use tokio::task::JoinHandle;
 
@@ -31,6 +34,16 @@ const TRANSITION_WINDOW: Duration = Duration::from_secs(15);
 #[cfg(macos)]
 const TRANSITION_COOLDOWN: Duration = Duration::from_secs(3);
 
+/// A network the machine is attached to, identified by the gateway and
+/// interface of its default route.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NetworkId {
+    /// Gateway of the default route
+    pub gateway: IpAddr,
+    /// Interface of the default route
+    pub if_index: Option<u32>,
+}
+
 /// Monitors network changes and publishes route and transition notifications.
 pub struct NetworkChangeMonitor {
     route_rx: watch::Receiver<()>,
@@ -179,6 +192,26 @@ impl NetworkChangeMonitor {
     pub fn subscribe_transitions(&self) -> watch::Receiver<()> {
         self.transition_rx.clone()
     }
+
+    /// The network currently used to reach `peer`: the applicable default
+    /// route of the peer's address family with the least metric (metrics
+    /// are only known on Linux and Windows, elsewhere the first one).
+    /// Reads the routing table, so blocks.
+    pub fn current_network(peer: IpAddr) -> Result<Option<NetworkId>> {
+        let routes = RouteManager::new()?.list()?;
+        let route = routes
+            .iter()
+            .filter(|route| {
+                is_applicable_route(route) && route.destination().is_ipv4() == peer.is_ipv4()
+            })
+            .min_by_key(|route| route_metric(route));
+        Ok(route.and_then(|route| {
+            Some(NetworkId {
+                gateway: route.gateway()?,
+                if_index: route.if_index(),
+            })
+        }))
+    }
 }
 
 impl Drop for NetworkChangeMonitor {
@@ -199,6 +232,16 @@ fn is_applicable_route(route: &Route) -> bool {
     route.prefix() == 0 && route.gateway().is_some_and(|gw| !gw.is_unspecified())
 }
 
+#[cfg(any(linux, windows))]
+fn route_metric(route: &Route) -> u32 {
+    route.metric().unwrap_or(0)
+}
+
+#[cfg(macos)]
+fn route_metric(_route: &Route) -> u32 {
+    0
+}
+
 /// Bridge netwatcher's interface updates to a unit-event channel, diffing our
 /// own snapshot of the relevant address set rather than trusting the update's
 /// diff (which can be empty, e.g. on address reordering).
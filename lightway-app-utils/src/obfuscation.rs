@@ -0,0 +1,156 @@
+//! Outside traffic obfuscation
+//!
+//! Lightway UDP packets start with a recognisable header and TCP
+//! connections are plain TLS, both easy for deep packet inspection to
+//! fingerprint. The transports here disguise the outside traffic of a
+//! connection, see `docs/obfuscation.md`:
+//!
+//! - UDP: datagrams are scrambled with a stream cipher keyed by a key
+//!   shared by client and server ([`ObfuscationKey`]), padded with a
+//!   random number of bytes and sent after a random delay.
+//! - TCP: the stream is wrapped in a WebSocket connection.
+//!
+//! Client and server must use the same transports.
+
+mod padding;
+mod scramble;
+mod websocket;
+
+#[cfg(feature = "tokio")]
+mod jitter;
+
+use std::time::Duration;
+
+use lightway_core::{ConnectionType, PluginFactoryList};
+
+#[cfg(feature = "tokio")]
+pub use jitter::Jitter;
+pub use scramble::ObfuscationKey;
+pub use websocket::WebSocket;
+
+/// Obfuscation transports of a client or server
+#[derive(Clone, Debug, Default)]
+pub struct Obfuscation {
+    /// Scramble UDP datagrams with this key, not scrambled when `None`
+    pub key: Option<ObfuscationKey>,
+    /// Pad UDP datagrams with up to this many random bytes
+    pub max_padding: u16,
+    /// Delay sending UDP datagrams by up to this long
+    pub max_jitter: Duration,
+    /// Wrap TCP connections in a WebSocket connection
+    pub websocket: Option<WebSocket>,
+}
+
+impl Obfuscation {
+    /// Whether any transport obfuscates connections of `connection_type`
+    pub fn is_enabled(&self, connection_type: ConnectionType) -> bool {
+        match connection_type {
+            ConnectionType::Datagram => {
+                self.key.is_some() || self.max_padding > 0 || !self.max_jitter.is_zero()
+            }
+            ConnectionType::Stream => self.websocket.is_some(),
+        }
+    }
+
+    /// Bytes added to each UDP datagram at most, to be taken off the
+    /// outside MTU
+    pub fn datagram_overhead(&self) -> usize {
+        let scramble = if self.key.is_some() {
+            scramble::OVERHEAD
+        } else {
+            0
+        };
+        let padding = if self.max_padding > 0 {
+            padding::OVERHEAD + usize::from(self.max_padding)
+        } else {
+            0
+        };
+        scramble + padding
+    }
+
+    /// Add the outside plugins obfuscating connections of
+    /// `connection_type` in front of `plugins`, so they see packets as
+    /// sent and received on the wire.
+    pub fn add_outside_plugins(
+        &self,
+        connection_type: ConnectionType,
+        plugins: &mut PluginFactoryList,
+    ) {
+        match connection_type {
+            ConnectionType::Datagram => {
+                // Padded before being scrambled
+                if self.max_padding > 0 {
+                    plugins.insert(0, Box::new(padding::PaddingFactory::new(self.max_padding)));
+                }
+                if let Some(key) = &self.key {
+                    plugins.insert(0, Box::new(scramble::ScrambleFactory::new(key.clone())));
+                }
+            }
+            ConnectionType::Stream => {
+                if let Some(websocket) = &self.websocket {
+                    plugins.insert(
+                        0,
+                        Box::new(websocket::WebSocketFactory::new(websocket.clone())),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Wrap the `outside_io` of a UDP connection to delay sends by up
+    /// to [`Self::max_jitter`]. Must be called within a tokio runtime.
+    #[cfg(feature = "tokio")]
+    pub fn wrap_outside_io(
+        &self,
+        connection_type: ConnectionType,
+        outside_io: lightway_core::OutsideIOSendCallbackArg,
+    ) -> lightway_core::OutsideIOSendCallbackArg {
+        if connection_type.is_datagram() && !self.max_jitter.is_zero() {
+            Jitter::wrap(outside_io, self.max_jitter)
+        } else {
+            outside_io
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn obfuscation() -> Obfuscation {
+        Obfuscation {
+            key: Some(ObfuscationKey::generate()),
+            max_padding: 100,
+            max_jitter: Duration::ZERO,
+            websocket: None,
+        }
+    }
+
+    #[test_case(ConnectionType::Datagram => 2; "datagram")]
+    #[test_case(ConnectionType::Stream => 0; "stream")]
+    fn plugins_by_connection_type(connection_type: ConnectionType) -> usize {
+        let mut plugins = PluginFactoryList::new();
+        obfuscation().add_outside_plugins(connection_type, &mut plugins);
+        plugins.len()
+    }
+
+    #[test]
+    fn datagram_overhead() {
+        assert_eq!(Obfuscation::default().datagram_overhead(), 0);
+        assert_eq!(
+            obfuscation().datagram_overhead(),
+            scramble::OVERHEAD + padding::OVERHEAD + 100
+        );
+    }
+
+    #[test]
+    fn websocket_only_for_streams() {
+        let obfuscation = Obfuscation {
+            websocket: Some(WebSocket::Server),
+            ..Default::default()
+        };
+        assert!(obfuscation.is_enabled(ConnectionType::Stream));
+        assert!(!obfuscation.is_enabled(ConnectionType::Datagram));
+    }
+}
@@ -0,0 +1,210 @@
+//! Delays UDP datagrams by a random time, hiding the timing of the
+//! packets inside.
+//!
+//! Datagrams are queued to a task sending each after its delay. A
+//! datagram is never sent before the one queued ahead of it, so the
+//! order is kept and no datagram is delayed by more than the maximum.
+
+use std::{
+    io::IoSlice,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use lightway_core::{
+    IOCallbackResult, MultipathMode, OutsideIOSendCallback, OutsideIOSendCallbackArg, PeerPath,
+};
+use rand::RngExt;
+use tokio::{
+    sync::mpsc::{self, error::TrySendError},
+    time::Instant,
+};
+
+/// Datagrams queued at most, sends block beyond
+const QUEUE_LEN: usize = 1024;
+
+/// Outside IO delaying each send by a random time up to a maximum
+pub struct Jitter {
+    inner: OutsideIOSendCallbackArg,
+    max_jitter: Duration,
+    queue: mpsc::Sender<Queued>,
+    /// When the last datagram queued is sent
+    last_send_at: Mutex<Instant>,
+    /// Path the datagrams queued now are pinned to, see
+    /// [`OutsideIOSendCallback::pin_peer_path`]
+    pinned: Mutex<Option<SocketAddr>>,
+}
+
+/// A datagram queued: when to send it, to which pinned path, and itself
+type Queued = (Instant, Option<SocketAddr>, Vec<u8>);
+
+impl Jitter {
+    /// Wrap `inner` to delay its sends by up to `max_jitter`. Must be
+    /// called within a tokio runtime.
+    pub fn wrap(inner: OutsideIOSendCallbackArg, max_jitter: Duration) -> OutsideIOSendCallbackArg {
+        let (queue, mut queued) = mpsc::channel::<Queued>(QUEUE_LEN);
+        let sender = inner.clone();
+        tokio::spawn(async move {
+            while let Some((send_at, pinned, buf)) = queued.recv().await {
+                tokio::time::sleep_until(send_at).await;
+                // The pin must outlast the delay, so is only applied around the send
+                if pinned.is_some() {
+                    sender.pin_peer_path(pinned);
+                }
+                if let IOCallbackResult::Err(e) = sender.send(&buf) {
+                    tracing::debug!("Delayed send failed: {e}");
+                }
+                if pinned.is_some() {
+                    sender.pin_peer_path(None);
+                }
+            }
+        });
+
+        Arc::new(Self {
+            inner,
+            max_jitter,
+            queue,
+            last_send_at: Mutex::new(Instant::now()),
+            pinned: Mutex::new(None),
+        })
+    }
+
+    /// When to send a datagram queued now
+    fn send_at(&self) -> Instant {
+        let delay = rand::rng().random_range(Duration::ZERO..=self.max_jitter);
+        let mut last_send_at = self.last_send_at.lock().unwrap();
+        *last_send_at = (Instant::now() + delay).max(*last_send_at);
+        *last_send_at
+    }
+}
+
+impl OutsideIOSendCallback for Jitter {
+    fn send(&self, buf: &[u8]) -> IOCallbackResult<usize> {
+        let pinned = *self.pinned.lock().unwrap();
+        match self.queue.try_send((self.send_at(), pinned, buf.to_vec())) {
+            Ok(()) => IOCallbackResult::Ok(buf.len()),
+            Err(TrySendError::Full(_)) => IOCallbackResult::WouldBlock,
+            Err(TrySendError::Closed(_)) => {
+                IOCallbackResult::Err(std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+            }
+        }
+    }
+
+    fn send_gso(&self, _bufs: &[IoSlice<'_>], _gso_size: u16) -> IOCallbackResult<usize> {
+        IOCallbackResult::Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+
+    fn peer_addr(&self) -> SocketAddr {
+        self.inner.peer_addr()
+    }
+
+    fn set_peer_addr(&self, addr: SocketAddr) -> SocketAddr {
+        self.inner.set_peer_addr(addr)
+    }
+
+    fn multipath_mode(&self) -> MultipathMode {
+        self.inner.multipath_mode()
+    }
+
+    fn use_peer_path(&self, addr: SocketAddr, mode: MultipathMode) -> PeerPath {
+        self.inner.use_peer_path(addr, mode)
+    }
+
+    fn challenge_peer_path(&self, addr: SocketAddr, token: u64) -> bool {
+        self.inner.challenge_peer_path(addr, token)
+    }
+
+    fn peer_path_validated(&self, token: u64) {
+        self.inner.peer_path_validated(token)
+    }
+
+    fn pin_peer_path(&self, addr: Option<SocketAddr>) {
+        *self.pinned.lock().unwrap() = addr;
+    }
+
+    fn peer_paths(&self) -> Vec<SocketAddr> {
+        self.inner.peer_paths()
+    }
+
+    fn expire_peer_paths(&self, max_idle: Duration) -> Vec<SocketAddr> {
+        self.inner.expire_peer_paths(max_idle)
+    }
+
+    fn enable_pmtud_probe(&self) -> std::io::Result<()> {
+        self.inner.enable_pmtud_probe()
+    }
+
+    fn disable_pmtud_probe(&self) -> std::io::Result<()> {
+        self.inner.disable_pmtud_probe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder {
+        sent: Mutex<Vec<(Instant, Option<SocketAddr>, Vec<u8>)>>,
+        pinned: Mutex<Option<SocketAddr>>,
+    }
+
+    impl OutsideIOSendCallback for Recorder {
+        fn send(&self, buf: &[u8]) -> IOCallbackResult<usize> {
+            let pinned = *self.pinned.lock().unwrap();
+            let sent = (Instant::now(), pinned, buf.to_vec());
+            self.sent.lock().unwrap().push(sent);
+            IOCallbackResult::Ok(buf.len())
+        }
+
+        fn pin_peer_path(&self, addr: Option<SocketAddr>) {
+            *self.pinned.lock().unwrap() = addr;
+        }
+
+        fn send_gso(&self, _bufs: &[IoSlice<'_>], _gso_size: u16) -> IOCallbackResult<usize> {
+            unimplemented!()
+        }
+
+        fn peer_addr(&self) -> SocketAddr {
+            "127.0.0.1:27690".parse().unwrap()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn delays_in_order() {
+        let max_jitter = Duration::from_millis(50);
+        let recorder = Arc::new(Recorder::default());
+        let jitter = Jitter::wrap(recorder.clone(), max_jitter);
+
+        let start = Instant::now();
+        for i in 0..32u8 {
+            assert!(matches!(jitter.send(&[i]), IOCallbackResult::Ok(1)));
+        }
+        tokio::time::sleep(max_jitter * 2).await;
+
+        let sent = recorder.sent.lock().unwrap();
+        let order: Vec<u8> = sent.iter().map(|(_, _, buf)| buf[0]).collect();
+        assert_eq!(order, (0..32).collect::<Vec<_>>());
+        assert!(sent.iter().all(|(at, _, _)| *at - start <= max_jitter));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keeps_pin_over_delay() {
+        let max_jitter = Duration::from_millis(50);
+        let recorder = Arc::new(Recorder::default());
+        let jitter = Jitter::wrap(recorder.clone(), max_jitter);
+        let path: SocketAddr = "127.0.0.2:27690".parse().unwrap();
+
+        jitter.pin_peer_path(Some(path));
+        assert!(matches!(jitter.send(&[0]), IOCallbackResult::Ok(1)));
+        jitter.pin_peer_path(None);
+        assert!(matches!(jitter.send(&[1]), IOCallbackResult::Ok(1)));
+        tokio::time::sleep(max_jitter * 2).await;
+
+        let sent = recorder.sent.lock().unwrap();
+        let pins: Vec<_> = sent.iter().map(|(_, pinned, _)| *pinned).collect();
+        assert_eq!(pins, [Some(path), None]);
+        assert_eq!(*recorder.pinned.lock().unwrap(), None);
+    }
+}
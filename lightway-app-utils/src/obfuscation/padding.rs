@@ -0,0 +1,95 @@
+//! Pads UDP datagrams with a random number of random bytes, hiding the
+//! sizes of the packets inside.
+//!
+//! A padded datagram is the datagram, the padding and the length of the
+//! padding as a big endian `u16`. Scrambled after padding, the length is
+//! not recognisable on the wire.
+
+use bytes::{BufMut, BytesMut};
+use lightway_core::{Plugin, PluginFactory, PluginFactoryError, PluginResult, PluginType};
+use rand::RngExt;
+
+/// Length of the padding length trailing every datagram
+const LEN_SIZE: usize = std::mem::size_of::<u16>();
+
+/// Bytes added to each datagram besides the padding
+pub(super) const OVERHEAD: usize = LEN_SIZE;
+
+struct Padding {
+    max_padding: u16,
+}
+
+impl Plugin for Padding {
+    fn ingress(&self, data: &mut BytesMut) -> PluginResult {
+        let Some(len_at) = data.len().checked_sub(LEN_SIZE) else {
+            return PluginResult::Drop;
+        };
+        let padding = u16::from_be_bytes([data[len_at], data[len_at + 1]]);
+        let Some(padding_at) = len_at.checked_sub(padding.into()) else {
+            return PluginResult::Drop;
+        };
+        data.truncate(padding_at);
+        PluginResult::Accept
+    }
+
+    fn egress(&self, data: &mut BytesMut) -> PluginResult {
+        let mut rng = rand::rng();
+        let padding = rng.random_range(0..=self.max_padding);
+        let start = data.len();
+        data.resize(start + usize::from(padding), 0);
+        rng.fill(&mut data[start..]);
+        data.put_u16(padding);
+        PluginResult::Accept
+    }
+}
+
+pub(super) struct PaddingFactory {
+    max_padding: u16,
+}
+
+impl PaddingFactory {
+    pub(super) fn new(max_padding: u16) -> Self {
+        Self { max_padding }
+    }
+}
+
+impl PluginFactory for PaddingFactory {
+    fn build(&self) -> Result<PluginType, PluginFactoryError> {
+        Ok(Box::new(Padding {
+            max_padding: self.max_padding,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test]
+    fn round_trip() {
+        let padding = Padding { max_padding: 64 };
+        let datagram = b"datagram".as_slice();
+        for _ in 0..32 {
+            let mut data = BytesMut::from(datagram);
+            assert!(matches!(padding.egress(&mut data), PluginResult::Accept));
+            assert!(
+                (datagram.len() + OVERHEAD..=datagram.len() + OVERHEAD + 64).contains(&data.len())
+            );
+
+            assert!(matches!(padding.ingress(&mut data), PluginResult::Accept));
+            assert_eq!(&data[..], datagram);
+        }
+    }
+
+    #[test_case(b""; "empty")]
+    #[test_case(b"\x00"; "no length")]
+    #[test_case(b"ab\x00\x03"; "padding longer than datagram")]
+    fn malformed_dropped(datagram: &[u8]) {
+        let mut data = BytesMut::from(datagram);
+        assert!(matches!(
+            Padding { max_padding: 64 }.ingress(&mut data),
+            PluginResult::Drop
+        ));
+    }
+}
@@ -0,0 +1,207 @@
+//! Scrambles UDP datagrams with ChaCha20.
+//!
+//! Each datagram is sent as a random nonce followed by the datagram
+//! XORed with the ChaCha20 keystream of the [`ObfuscationKey`] and the
+//! nonce. The whole datagram is scrambled, not just the Lightway and
+//! DTLS headers, since handshake records carry the certificates in the
+//! clear. This hides the traffic, it does not protect it: the DTLS
+//! records inside are what is encrypted and authenticated.
+
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use bytes::{BufMut, BytesMut};
+use chacha20::{
+    ChaCha20,
+    cipher::{KeyIvInit, StreamCipher},
+};
+use lightway_core::{Plugin, PluginFactory, PluginFactoryError, PluginResult, PluginType};
+use rand::RngExt;
+
+/// Length of the nonce preceding every datagram
+const NONCE_LEN: usize = 12;
+
+/// Bytes added to each datagram
+pub(super) const OVERHEAD: usize = NONCE_LEN;
+
+/// Key shared by client and server to scramble UDP datagrams with.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ObfuscationKey([u8; Self::LEN]);
+
+impl ObfuscationKey {
+    /// Length of a key in bytes
+    pub const LEN: usize = 32;
+
+    /// Random key
+    pub fn generate() -> Self {
+        let mut key = [0; Self::LEN];
+        rand::rng().fill(&mut key);
+        Self(key)
+    }
+
+    /// Read a hex encoded key from `path`.
+    pub fn read(path: &Path) -> Result<Self> {
+        let key = std::fs::read_to_string(path)
+            .with_context(|| format!("Reading obfuscation key {}", path.display()))?;
+        key.parse()
+            .with_context(|| format!("Parsing obfuscation key {}", path.display()))
+    }
+}
+
+/// Parses `LEN` bytes of hex, e.g. the output of `openssl rand -hex 32`.
+impl FromStr for ObfuscationKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        anyhow::ensure!(
+            s.is_ascii() && s.len() == Self::LEN * 2,
+            "Obfuscation key must be {} hex digits",
+            Self::LEN * 2
+        );
+        let mut key = [0; Self::LEN];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16)
+                .context("Obfuscation key must be hex digits")?;
+        }
+        Ok(Self(key))
+    }
+}
+
+impl std::fmt::Debug for ObfuscationKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ObfuscationKey(..)")
+    }
+}
+
+struct Scramble {
+    key: ObfuscationKey,
+}
+
+impl Scramble {
+    fn apply_keystream(&self, nonce: &[u8; NONCE_LEN], data: &mut [u8]) {
+        ChaCha20::new(&self.key.0.into(), &(*nonce).into()).apply_keystream(data);
+    }
+}
+
+impl Plugin for Scramble {
+    fn ingress(&self, data: &mut BytesMut) -> PluginResult {
+        if data.len() <= NONCE_LEN {
+            return PluginResult::Drop;
+        }
+        let nonce = data.split_to(NONCE_LEN);
+        self.apply_keystream(nonce[..].try_into().unwrap(), data);
+        PluginResult::Accept
+    }
+
+    fn egress(&self, data: &mut BytesMut) -> PluginResult {
+        let mut nonce = [0; NONCE_LEN];
+        rand::rng().fill(&mut nonce);
+        self.apply_keystream(&nonce, data);
+
+        let mut scrambled = BytesMut::with_capacity(NONCE_LEN + data.len());
+        scrambled.put_slice(&nonce);
+        scrambled.put_slice(data);
+        *data = scrambled;
+        PluginResult::Accept
+    }
+}
+
+pub(super) struct ScrambleFactory {
+    key: ObfuscationKey,
+}
+
+impl ScrambleFactory {
+    pub(super) fn new(key: ObfuscationKey) -> Self {
+        Self { key }
+    }
+}
+
+impl PluginFactory for ScrambleFactory {
+    fn build(&self) -> Result<PluginType, PluginFactoryError> {
+        Ok(Box::new(Scramble {
+            key: self.key.clone(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn scramble(key: &ObfuscationKey) -> PluginType {
+        ScrambleFactory::new(key.clone()).build().unwrap()
+    }
+
+    #[test]
+    fn key_from_hex() {
+        let key: ObfuscationKey = format!("{}\n", "0a".repeat(32)).parse().unwrap();
+        assert_eq!(key.0, [10; 32]);
+    }
+
+    #[test_case(""; "empty")]
+    #[test_case(&"0a".repeat(31); "short")]
+    #[test_case(&"zz".repeat(32); "not hex")]
+    #[test_case(&"é".repeat(32); "not ascii")]
+    fn invalid_key(s: &str) {
+        assert!(s.parse::<ObfuscationKey>().is_err());
+    }
+
+    #[test]
+    fn debug_hides_key() {
+        assert_eq!(
+            format!("{:?}", ObfuscationKey::generate()),
+            "ObfuscationKey(..)"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let key = ObfuscationKey::generate();
+        let datagram = b"\x48\x45\x01\x00datagram".as_slice();
+
+        let mut data = BytesMut::from(datagram);
+        assert!(matches!(
+            scramble(&key).egress(&mut data),
+            PluginResult::Accept
+        ));
+        assert_eq!(data.len(), datagram.len() + OVERHEAD);
+        assert_ne!(&data[NONCE_LEN..], datagram);
+
+        assert!(matches!(
+            scramble(&key).ingress(&mut data),
+            PluginResult::Accept
+        ));
+        assert_eq!(&data[..], datagram);
+    }
+
+    #[test]
+    fn nonce_differs_per_datagram() {
+        let scramble = scramble(&ObfuscationKey::generate());
+        let mut first = BytesMut::from(&b"datagram"[..]);
+        let mut second = first.clone();
+        scramble.egress(&mut first);
+        scramble.egress(&mut second);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn wrong_key_garbles() {
+        let datagram = b"datagram".as_slice();
+        let mut data = BytesMut::from(datagram);
+        scramble(&ObfuscationKey::generate()).egress(&mut data);
+        scramble(&ObfuscationKey::generate()).ingress(&mut data);
+        assert_ne!(&data[..], datagram);
+    }
+
+    #[test]
+    fn short_datagram_dropped() {
+        let mut data = BytesMut::from(&[0; NONCE_LEN][..]);
+        assert!(matches!(
+            scramble(&ObfuscationKey::generate()).ingress(&mut data),
+            PluginResult::Drop
+        ));
+    }
+}
@@ -0,0 +1,546 @@
+//! Wraps TCP connections in a WebSocket connection
+//! ([RFC 6455](https://www.rfc-editor.org/rfc/rfc6455)).
+//!
+//! The client opens the connection with an HTTP/1.1 upgrade request and
+//! the server answers with `101 Switching Protocols`, after which the
+//! stream is carried in binary frames. To save a round trip the client
+//! sends its first frame right after the request, without waiting for
+//! the response, and the server sends the response along with its first
+//! frame.
+//!
+//! The plugins see the stream in arbitrary chunks: ingress keeps any
+//! partial HTTP header or frame header until the rest arrives, and
+//! passes on the frame payloads received so far.
+
+use std::sync::Mutex;
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use bytes::{Buf, BufMut, BytesMut};
+use lightway_core::{Plugin, PluginFactory, PluginFactoryError, PluginResult, PluginType};
+use rand::RngExt;
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+
+/// Appended to the `Sec-WebSocket-Key` to derive `Sec-WebSocket-Accept`
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Longest HTTP request or response header accepted
+const MAX_HTTP_HEADER_LEN: usize = 8192;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+/// WebSocket side of a connection
+#[derive(Clone, Debug)]
+pub enum WebSocket {
+    /// Client connecting to the WebSocket at `path` on `host`
+    Client {
+        /// Value of the `Host` header
+        host: String,
+        /// Path requested
+        path: String,
+    },
+    /// Server accepting WebSocket connections on any path
+    Server,
+}
+
+/// An error with the WebSocket connection
+#[derive(Debug, Error)]
+enum WebSocketError {
+    #[error("HTTP header too long")]
+    HeaderTooLong,
+    #[error("Invalid WebSocket upgrade request")]
+    InvalidRequest,
+    #[error("WebSocket upgrade refused: {0}")]
+    Refused(String),
+    #[error("Invalid Sec-WebSocket-Accept")]
+    InvalidAccept,
+    #[error("Unexpected WebSocket frame opcode {0:#x}")]
+    UnexpectedOpcode(u8),
+    #[error("WebSocket frame masking is wrong for the direction")]
+    InvalidMask,
+    #[error("WebSocket closed by peer")]
+    Closed,
+    #[error("Server sent data before the upgrade request")]
+    NotUpgraded,
+}
+
+/// HTTP upgrade progress
+enum Handshake {
+    /// Client: request to send, with the key it carries
+    Request { key: String },
+    /// Client: request sent, response awaited
+    AwaitResponse { accept: String },
+    /// Server: request awaited
+    AwaitRequest,
+    /// Server: response to send
+    Response { accept: String },
+    /// Server: response sent, or client: response received
+    Done,
+}
+
+/// A frame whose payload is being received
+struct Frame {
+    /// Whether the payload is stream data, or else a control frame's
+    payload_is_data: bool,
+    mask: Option<[u8; 4]>,
+    /// Offset into the payload, for unmasking
+    offset: usize,
+    remaining: u64,
+}
+
+struct State {
+    handshake: Handshake,
+    /// Received bytes not processed yet
+    received: BytesMut,
+    /// Frame whose payload is being received
+    frame: Option<Frame>,
+}
+
+struct WebSocketPlugin {
+    websocket: WebSocket,
+    state: Mutex<State>,
+}
+
+impl WebSocketPlugin {
+    fn new(websocket: WebSocket) -> Self {
+        let handshake = match websocket {
+            WebSocket::Client { .. } => {
+                let mut key = [0; 16];
+                rand::rng().fill(&mut key);
+                Handshake::Request {
+                    key: BASE64.encode(key),
+                }
+            }
+            WebSocket::Server => Handshake::AwaitRequest,
+        };
+        Self {
+            websocket,
+            state: Mutex::new(State {
+                handshake,
+                received: BytesMut::new(),
+                frame: None,
+            }),
+        }
+    }
+
+    fn is_client(&self) -> bool {
+        matches!(self.websocket, WebSocket::Client { .. })
+    }
+
+    /// Process `received`, appending stream data to `data`. Returns once
+    /// more bytes are needed.
+    fn receive(&self, state: &mut State, data: &mut BytesMut) -> Result<(), WebSocketError> {
+        loop {
+            match &state.handshake {
+                Handshake::AwaitRequest | Handshake::AwaitResponse { .. } => {
+                    let Some(header) = split_http_header(&mut state.received)? else {
+                        return Ok(());
+                    };
+                    state.handshake = match &state.handshake {
+                        Handshake::AwaitRequest => Handshake::Response {
+                            accept: parse_request(&header)?,
+                        },
+                        Handshake::AwaitResponse { accept } => {
+                            parse_response(&header, accept)?;
+                            Handshake::Done
+                        }
+                        _ => unreachable!(),
+                    };
+                }
+                // Clients send frames before the response, which
+                // servers only send along with their first frame
+                Handshake::Request { .. } | Handshake::Response { .. } | Handshake::Done => {}
+            }
+
+            if state.frame.is_none() {
+                match self.parse_frame_header(&mut state.received)? {
+                    Some(frame) => state.frame = Some(frame),
+                    None => return Ok(()),
+                }
+            }
+            let frame = state.frame.as_mut().unwrap();
+
+            let len = state
+                .received
+                .len()
+                .min(usize::try_from(frame.remaining).unwrap_or(usize::MAX));
+            let mut payload = state.received.split_to(len);
+            if let Some(mask) = frame.mask {
+                for (i, byte) in payload.iter_mut().enumerate() {
+                    *byte ^= mask[(frame.offset + i) % 4];
+                }
+            }
+            frame.offset += len;
+            frame.remaining -= len as u64;
+            if frame.payload_is_data {
+                data.extend_from_slice(&payload);
+            }
+            if frame.remaining == 0 {
+                state.frame = None;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Parse a frame header off `received`, `None` until complete.
+    fn parse_frame_header(&self, received: &mut BytesMut) -> Result<Option<Frame>, WebSocketError> {
+        if received.len() < 2 {
+            return Ok(None);
+        }
+        let opcode = received[0] & 0x0f;
+        let masked = received[1] & 0x80 != 0;
+        let (len_size, len) = match received[1] & 0x7f {
+            126 => (2, None),
+            127 => (8, None),
+            len => (0, Some(u64::from(len))),
+        };
+        let header_len = 2 + len_size + if masked { 4 } else { 0 };
+        if received.len() < header_len {
+            return Ok(None);
+        }
+
+        // Servers mask nothing, clients everything
+        if masked == self.is_client() {
+            return Err(WebSocketError::InvalidMask);
+        }
+        let payload_is_data = match opcode {
+            OPCODE_BINARY | OPCODE_CONTINUATION => true,
+            OPCODE_PING | OPCODE_PONG => false,
+            OPCODE_CLOSE => return Err(WebSocketError::Closed),
+            opcode => return Err(WebSocketError::UnexpectedOpcode(opcode)),
+        };
+
+        received.advance(2);
+        let remaining = match len {
+            Some(len) => len,
+            None if len_size == 2 => received.get_u16().into(),
+            None => received.get_u64(),
+        };
+        let mask = masked.then(|| {
+            let mut mask = [0; 4];
+            received.copy_to_slice(&mut mask);
+            mask
+        });
+        Ok(Some(Frame {
+            payload_is_data,
+            mask,
+            offset: 0,
+            remaining,
+        }))
+    }
+
+    /// Frame `data` as a binary frame, preceded by the HTTP header if
+    /// not sent yet.
+    fn send(&self, state: &mut State, data: &mut BytesMut) -> Result<(), WebSocketError> {
+        let mut framed = BytesMut::with_capacity(data.len() + 14);
+
+        match &state.handshake {
+            Handshake::Request { key } => {
+                let WebSocket::Client { host, path } = &self.websocket else {
+                    unreachable!("servers do not send requests");
+                };
+                framed.put_slice(
+                    format!(
+                        "GET {path} HTTP/1.1\r\n\
+                         Host: {host}\r\n\
+                         Upgrade: websocket\r\n\
+                         Connection: Upgrade\r\n\
+                         Sec-WebSocket-Key: {key}\r\n\
+                         Sec-WebSocket-Version: 13\r\n\r\n"
+                    )
+                    .as_bytes(),
+                );
+                state.handshake = Handshake::AwaitResponse {
+                    accept: accept_key(key),
+                };
+            }
+            Handshake::Response { accept } => {
+                framed.put_slice(
+                    format!(
+                        "HTTP/1.1 101 Switching Protocols\r\n\
+                         Upgrade: websocket\r\n\
+                         Connection: Upgrade\r\n\
+                         Sec-WebSocket-Accept: {accept}\r\n\r\n"
+                    )
+                    .as_bytes(),
+                );
+                state.handshake = Handshake::Done;
+            }
+            Handshake::AwaitRequest => return Err(WebSocketError::NotUpgraded),
+            Handshake::AwaitResponse { .. } | Handshake::Done => {}
+        }
+
+        framed.put_u8(0x80 | OPCODE_BINARY);
+        let mask_bit = if self.is_client() { 0x80 } else { 0 };
+        match data.len() {
+            len @ 0..=125 => framed.put_u8(mask_bit | len as u8),
+            len @ 126..=0xffff => {
+                framed.put_u8(mask_bit | 126);
+                framed.put_u16(len as u16);
+            }
+            len => {
+                framed.put_u8(mask_bit | 127);
+                framed.put_u64(len as u64);
+            }
+        }
+
+        if self.is_client() {
+            let mut mask = [0; 4];
+            rand::rng().fill(&mut mask);
+            framed.put_slice(&mask);
+            let payload_at = framed.len();
+            framed.put_slice(data);
+            for (i, byte) in framed[payload_at..].iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+        } else {
+            framed.put_slice(data);
+        }
+
+        *data = framed;
+        Ok(())
+    }
+}
+
+impl Plugin for WebSocketPlugin {
+    fn ingress(&self, data: &mut BytesMut) -> PluginResult {
+        let mut state = self.state.lock().unwrap();
+        state.received.extend_from_slice(data);
+        data.clear();
+        match self.receive(&mut state, data) {
+            Ok(()) => PluginResult::Accept,
+            Err(e) => PluginResult::Error(Box::new(e)),
+        }
+    }
+
+    fn egress(&self, data: &mut BytesMut) -> PluginResult {
+        let mut state = self.state.lock().unwrap();
+        match self.send(&mut state, data) {
+            Ok(()) => PluginResult::Accept,
+            Err(e) => PluginResult::Error(Box::new(e)),
+        }
+    }
+}
+
+/// `Sec-WebSocket-Accept` answering `key`
+fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(ACCEPT_GUID.as_bytes());
+    BASE64.encode(sha1.finalize())
+}
+
+/// Split a complete HTTP header off `received`, `None` until complete.
+fn split_http_header(received: &mut BytesMut) -> Result<Option<String>, WebSocketError> {
+    match received.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(end) => {
+            let header = received.split_to(end + 4);
+            Ok(Some(String::from_utf8_lossy(&header).into_owned()))
+        }
+        None if received.len() > MAX_HTTP_HEADER_LEN => Err(WebSocketError::HeaderTooLong),
+        None => Ok(None),
+    }
+}
+
+/// Value of the header field `name` of the HTTP `header`
+fn header_field<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+    header.lines().skip(1).find_map(|line| {
+        let (field, value) = line.split_once(':')?;
+        field
+            .trim()
+            .eq_ignore_ascii_case(name)
+            .then_some(value.trim())
+    })
+}
+
+/// Parse an upgrade request, returning the `Sec-WebSocket-Accept` to
+/// answer with.
+fn parse_request(header: &str) -> Result<String, WebSocketError> {
+    let is_upgrade = header.starts_with("GET ")
+        && header_field(header, "Upgrade").is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+    let key = header_field(header, "Sec-WebSocket-Key").filter(|_| is_upgrade);
+    key.map(accept_key).ok_or(WebSocketError::InvalidRequest)
+}
+
+/// Check an upgrade response accepts the request `accept` answers.
+fn parse_response(header: &str, accept: &str) -> Result<(), WebSocketError> {
+    let status = header.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("101") {
+        return Err(WebSocketError::Refused(status.to_string()));
+    }
+    if header_field(header, "Sec-WebSocket-Accept") != Some(accept) {
+        return Err(WebSocketError::InvalidAccept);
+    }
+    Ok(())
+}
+
+pub(super) struct WebSocketFactory {
+    websocket: WebSocket,
+}
+
+impl WebSocketFactory {
+    pub(super) fn new(websocket: WebSocket) -> Self {
+        Self { websocket }
+    }
+}
+
+impl PluginFactory for WebSocketFactory {
+    fn build(&self) -> Result<PluginType, PluginFactoryError> {
+        Ok(Box::new(WebSocketPlugin::new(self.websocket.clone())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn client() -> WebSocketPlugin {
+        WebSocketPlugin::new(WebSocket::Client {
+            host: "vpn.example.com".to_string(),
+            path: "/ws".to_string(),
+        })
+    }
+
+    fn server() -> WebSocketPlugin {
+        WebSocketPlugin::new(WebSocket::Server)
+    }
+
+    fn egress(plugin: &WebSocketPlugin, data: &[u8]) -> BytesMut {
+        let mut data = BytesMut::from(data);
+        assert!(matches!(plugin.egress(&mut data), PluginResult::Accept));
+        data
+    }
+
+    /// Feed `wire` to `plugin` in chunks of `chunk` bytes, returning the
+    /// stream data received.
+    fn ingress(plugin: &WebSocketPlugin, wire: &[u8], chunk: usize) -> Vec<u8> {
+        let mut received = Vec::new();
+        for chunk in wire.chunks(chunk) {
+            let mut data = BytesMut::from(chunk);
+            let result = plugin.ingress(&mut data);
+            assert!(matches!(result, PluginResult::Accept), "{result:?}");
+            received.extend_from_slice(&data);
+        }
+        received
+    }
+
+    #[test]
+    fn accept_key_from_rfc() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kCmOPbYGqbxzoo="
+        );
+    }
+
+    #[test_case(usize::MAX; "whole")]
+    #[test_case(1; "byte by byte")]
+    #[test_case(7; "odd chunks")]
+    fn client_to_server(chunk: usize) {
+        let (client, server) = (client(), server());
+        let hello = vec![0x16; 300];
+        let wire = [egress(&client, &hello), egress(&client, b"more")].concat();
+        assert!(wire.starts_with(b"GET /ws HTTP/1.1\r\nHost: vpn.example.com\r\n"));
+
+        assert_eq!(
+            ingress(&server, &wire, chunk),
+            [hello, b"more".to_vec()].concat()
+        );
+    }
+
+    #[test_case(usize::MAX; "whole")]
+    #[test_case(1; "byte by byte")]
+    fn server_to_client(chunk: usize) {
+        let (client, server) = (client(), server());
+        let request = egress(&client, b"hello");
+        ingress(&server, &request, usize::MAX);
+
+        let payload = vec![0x17; 70_000];
+        let wire = [egress(&server, &payload), egress(&server, b"more")].concat();
+        assert!(wire.starts_with(b"HTTP/1.1 101 Switching Protocols\r\n"));
+
+        assert_eq!(
+            ingress(&client, &wire, chunk),
+            [payload, b"more".to_vec()].concat()
+        );
+    }
+
+    #[test]
+    fn control_frames_skipped() {
+        let client = client();
+        let request = egress(&client, b"hello");
+        let server = server();
+        ingress(&server, &request, usize::MAX);
+        let response = egress(&server, b"data");
+
+        let ping = [0x80 | OPCODE_PING, 2, b'h', b'i'];
+        let wire = [&response[..], &ping, &egress(&server, b"more")].concat();
+        assert_eq!(ingress(&client, &wire, usize::MAX), b"datamore");
+    }
+
+    #[test]
+    fn refused_upgrade() {
+        let client = client();
+        egress(&client, b"hello");
+        let mut data = BytesMut::from(&b"HTTP/1.1 403 Forbidden\r\n\r\n"[..]);
+        assert!(matches!(client.ingress(&mut data), PluginResult::Error(_)));
+    }
+
+    #[test]
+    fn wrong_accept() {
+        let client = client();
+        egress(&client, b"hello");
+        let mut data = BytesMut::from(
+            &b"HTTP/1.1 101 Switching Protocols\r\nSec-WebSocket-Accept: nope\r\n\r\n"[..],
+        );
+        assert!(matches!(client.ingress(&mut data), PluginResult::Error(_)));
+    }
+
+    #[test_case(b"\x16\x03\x01\x00\x10\r\n\r\n"; "not http")]
+    #[test_case(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n"; "not an upgrade")]
+    fn invalid_request(wire: &[u8]) {
+        let mut data = BytesMut::from(wire);
+        assert!(matches!(
+            server().ingress(&mut data),
+            PluginResult::Error(_)
+        ));
+    }
+
+    #[test]
+    fn unmasked_client_frame_rejected() {
+        let client = client();
+        let server = server();
+        let request = egress(&client, b"hello");
+        ingress(&server, &request, usize::MAX);
+
+        let mut data = BytesMut::from(&[0x80 | OPCODE_BINARY, 1, 0][..]);
+        assert!(matches!(server.ingress(&mut data), PluginResult::Error(_)));
+    }
+
+    #[test]
+    fn header_too_long() {
+        let mut data = BytesMut::from(&[b'a'; MAX_HTTP_HEADER_LEN + 1][..]);
+        assert!(matches!(
+            server().ingress(&mut data),
+            PluginResult::Error(_)
+        ));
+    }
+
+    #[test]
+    fn close_frame_errors() {
+        let client = client();
+        let server = server();
+        let request = egress(&client, b"hello");
+        ingress(&server, &request, usize::MAX);
+        let response = egress(&server, b"data");
+        ingress(&client, &response, usize::MAX);
+
+        let mut data = BytesMut::from(&[0x80 | OPCODE_CLOSE, 0][..]);
+        assert!(matches!(client.ingress(&mut data), PluginResult::Error(_)));
+    }
+}
@@ -0,0 +1,197 @@
+//! Split DNS configuration, pushed by the server to clients.
+//!
+//! The server sends a JSON encoded [`ServerConfigPayload`] as the
+//! opaque data of the Lightway `ServerConfig` frame, and the client
+//! receives it as [`lightway_core::Event::ServerConfig`].
+
+use std::net::IpAddr;
+
+use anyhow::{Result, ensure};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+/// Resolvers to use for a single DNS domain (and all its subdomains).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DomainResolver {
+    /// The domain, e.g. `corp.example`
+    pub domain: String,
+    /// Resolvers queries within `domain` are sent to
+    pub servers: Vec<IpAddr>,
+}
+
+/// Per-domain resolvers and search domains.
+///
+/// Only queries for the listed domains are sent to the listed
+/// resolvers, everything else keeps using the system resolver.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitDnsConfig {
+    /// Domains resolved by dedicated resolvers
+    #[serde(default)]
+    pub resolvers: Vec<DomainResolver>,
+    /// Search domains appended to single label names
+    #[serde(default)]
+    pub search_domains: Vec<String>,
+}
+
+impl SplitDnsConfig {
+    /// Ensure every entry names a domain and at least one resolver.
+    pub fn validate(&self) -> Result<()> {
+        for resolver in &self.resolvers {
+            ensure!(
+                !domain_name(&resolver.domain).is_empty(),
+                "Split DNS domain must not be empty"
+            );
+            ensure!(
+                !resolver.servers.is_empty(),
+                "Split DNS domain {} has no resolvers",
+                resolver.domain
+            );
+        }
+        ensure!(
+            self.search_domains
+                .iter()
+                .all(|domain| !domain_name(domain).is_empty()),
+            "Split DNS search domain must not be empty"
+        );
+        Ok(())
+    }
+
+    /// All resolvers, in order of first appearance and without duplicates.
+    pub fn servers(&self) -> Vec<IpAddr> {
+        let mut servers = Vec::new();
+        for server in self.resolvers.iter().flat_map(|r| &r.servers) {
+            if !servers.contains(server) {
+                servers.push(*server);
+            }
+        }
+        servers
+    }
+
+    /// Domains which must be routed to [`Self::servers`], without any
+    /// leading `~` or trailing `.`.
+    pub fn routing_domains(&self) -> impl Iterator<Item = &str> {
+        self.resolvers.iter().map(|r| domain_name(&r.domain))
+    }
+
+    /// Search domains, without any leading `~` or trailing `.`.
+    pub fn search_domain_names(&self) -> impl Iterator<Item = &str> {
+        self.search_domains.iter().map(|d| domain_name(d))
+    }
+}
+
+fn domain_name(domain: &str) -> &str {
+    domain.trim_start_matches('~').trim_end_matches('.')
+}
+
+/// Data carried in the Lightway `ServerConfig` frame.
+///
+/// Unknown fields are ignored so that servers can push additional
+/// settings without breaking older clients.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerConfigPayload {
+    /// Split DNS configuration, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub split_dns: Option<SplitDnsConfig>,
+}
+
+impl ServerConfigPayload {
+    /// Encode for use with `ServerContextBuilder::with_server_config`
+    pub fn to_bytes(&self) -> Bytes {
+        // Serializing plain structs of strings and addresses cannot fail
+        serde_json::to_vec(self)
+            .expect("ServerConfigPayload is always serializable")
+            .into()
+    }
+
+    /// Decode the data of a [`lightway_core::Event::ServerConfig`]
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(data)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use test_case::test_case;
+
+    fn corp() -> SplitDnsConfig {
+        SplitDnsConfig {
+            resolvers: vec![
+                DomainResolver {
+                    domain: "corp.example.".to_string(),
+                    servers: vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 53))],
+                },
+                DomainResolver {
+                    domain: "~lab.example".to_string(),
+                    servers: vec![
+                        IpAddr::V4(Ipv4Addr::new(10, 0, 0, 53)),
+                        IpAddr::V4(Ipv4Addr::new(10, 1, 0, 53)),
+                    ],
+                },
+            ],
+            search_domains: vec!["corp.example".to_string()],
+        }
+    }
+
+    #[test]
+    fn payload_round_trip() {
+        let payload = ServerConfigPayload {
+            split_dns: Some(corp()),
+        };
+        assert_eq!(
+            ServerConfigPayload::from_bytes(&payload.to_bytes()).unwrap(),
+            payload
+        );
+    }
+
+    #[test]
+    fn payload_ignores_unknown_fields() {
+        let payload = ServerConfigPayload::from_bytes(br#"{"something_new": 1}"#).unwrap();
+        assert_eq!(payload, ServerConfigPayload::default());
+    }
+
+    #[test]
+    fn payload_rejects_garbage() {
+        assert!(ServerConfigPayload::from_bytes(b"\x00\x01").is_err());
+    }
+
+    #[test]
+    fn servers_are_deduplicated() {
+        assert_eq!(
+            corp().servers(),
+            [
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 53)),
+                IpAddr::V4(Ipv4Addr::new(10, 1, 0, 53)),
+            ]
+        );
+    }
+
+    #[test]
+    fn domains_are_normalized() {
+        let config = corp();
+        assert_eq!(
+            config.routing_domains().collect::<Vec<_>>(),
+            ["corp.example", "lab.example"]
+        );
+        assert_eq!(
+            config.search_domain_names().collect::<Vec<_>>(),
+            ["corp.example"]
+        );
+    }
+
+    #[test_case("corp.example", vec![IpAddr::V4(Ipv4Addr::LOCALHOST)] => true; "valid")]
+    #[test_case("~.", vec![IpAddr::V4(Ipv4Addr::LOCALHOST)] => false; "root domain")]
+    #[test_case("corp.example", vec![] => false; "no servers")]
+    fn validate(domain: &str, servers: Vec<IpAddr>) -> bool {
+        SplitDnsConfig {
+            resolvers: vec![DomainResolver {
+                domain: domain.to_string(),
+                servers,
+            }],
+            search_domains: vec![],
+        }
+        .validate()
+        .is_ok()
+    }
+}
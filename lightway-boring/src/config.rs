@@ -1,8 +1,8 @@
 //! Session configuration for TLS/DTLS connections.
 //!
 //! [`SessionConfig`] carries per-connection parameters such as the I/O adapter,
-//! DTLS MTU, domain name verification, SNI, and post-quantum key share group
-//! preferences.
+//! DTLS MTU, domain name verification, SNI, post-quantum key share group
+//! preferences and the session ticket to resume.
 
 use super::{CurveGroup, SslVerifyMode};
 
@@ -26,6 +26,9 @@ pub struct SessionConfig<IOCB> {
     /// Per-session certificate verification mode. When set, overrides the
     /// context-level mode for this session via `SSL_set_verify`.
     pub ssl_verify_mode: Option<SslVerifyMode>,
+    /// Session ticket to resume in client mode, as returned by
+    /// `Session::take_session_ticket`.
+    pub session_ticket: Option<Vec<u8>>,
 }
 
 impl<IOCB> SessionConfig<IOCB> {
@@ -38,6 +41,7 @@ impl<IOCB> SessionConfig<IOCB> {
             server_name_indication: None,
             keyshare_group: None,
             ssl_verify_mode: None,
+            session_ticket: None,
         }
     }
 
@@ -95,6 +99,16 @@ impl<IOCB> SessionConfig<IOCB> {
         self
     }
 
+    /// Try to resume the session the ticket was issued for.
+    ///
+    /// Only offer a ticket to the server it came from: a resumed session
+    /// skips the certificate (and so domain name) verification. An
+    /// unusable ticket falls back to a full handshake.
+    pub fn with_session_ticket(mut self, ticket: &[u8]) -> Self {
+        self.session_ticket = Some(ticket.to_vec());
+        self
+    }
+
     /// Apply a function conditionally
     pub fn when<F>(self, condition: bool, f: F) -> Self
     where
@@ -147,6 +161,15 @@ mod tests {
         assert_eq!(config.keyshare_group, Some(CurveGroup::X25519MLKEM768));
     }
 
+    #[test]
+    fn test_session_config_session_ticket() {
+        let mock_io = MockIOAdapter::new();
+
+        let config = SessionConfig::new(mock_io).with_session_ticket(b"ticket");
+
+        assert_eq!(config.session_ticket, Some(b"ticket".to_vec()));
+    }
+
     #[test]
     fn test_session_config_ssl_verify_mode() {
         let mock_io = MockIOAdapter::new();
@@ -17,13 +17,16 @@ use crate::debug::Tls13SecretCallbacksArg;
 use super::{CurveGroup, IOCallbacks, Method, RootCertificate, Secret, SslVerifyMode, TlsError};
 use boring::error::ErrorStack;
 use boring::pkey::PKey;
-use boring::ssl::{SslContext, SslContextBuilder, SslMethod, SslVersion};
+use boring::ssl::{
+    SslContext, SslContextBuilder, SslMethod, SslOptions, SslSessionCacheMode, SslVersion,
+};
 use boring::x509::X509;
 use boring::x509::store::X509StoreBuilder;
 use zeroize::Zeroizing;
 
 use super::config::SessionConfig;
 use super::session::Session;
+use super::session_ticket::{self, TICKET_KEYS_INDEX, TicketKeys};
 
 /// BoringSSL context builder.
 ///
@@ -71,6 +74,12 @@ impl ContextBuilder {
                 .map_err(|e| NewContextBuilderError(e.to_string()))?;
         }
 
+        // Servers only issue session tickets once a ticket key is
+        // configured, see `with_session_ticket_key`.
+        if !method.is_client() {
+            builder.set_options(SslOptions::NO_TICKET);
+        }
+
         Ok(Self { builder, method })
     }
 
@@ -239,6 +248,54 @@ impl ContextBuilder {
         Ok(self)
     }
 
+    /// Issue session tickets encrypted under `key` (server only).
+    ///
+    /// `key` is [`crate::SESSION_TICKET_KEY_LEN`] bytes. Servers sharing
+    /// the key resume each other's sessions. Replace it at runtime with
+    /// [`Context::rotate_session_ticket_key`]. Early data stays disabled.
+    pub fn with_session_ticket_key(mut self, key: &[u8]) -> Result<Self> {
+        if self.method.is_client() {
+            return Err(TlsError::InvalidParameter(
+                "session ticket key set on a client context".into(),
+            )
+            .into());
+        }
+        let keys = TicketKeys::new(key)?;
+        self.builder.set_ex_data(*TICKET_KEYS_INDEX, keys);
+        // SAFETY: `builder.as_ptr()` is the SSL_CTX owned by this builder,
+        // valid for the call. The callback only reads the ex_data set above,
+        // which lives as long as the SSL_CTX.
+        unsafe {
+            let ret = boring_sys::SSL_CTX_set_tlsext_ticket_key_cb(
+                self.builder.as_ptr(),
+                Some(session_ticket::ticket_key_callback),
+            );
+            if ret != 1 {
+                return Err(TlsError::BoringSSL(ErrorStack::get()).into());
+            }
+        }
+        self.builder.clear_options(SslOptions::NO_TICKET);
+        Ok(self)
+    }
+
+    /// Collect the session tickets sent by the server (client only), see
+    /// [`Session::take_session_ticket`].
+    pub fn with_session_resumption(mut self) -> Result<Self> {
+        if !self.method.is_client() {
+            return Err(TlsError::InvalidParameter(
+                "session resumption enabled on a server context".into(),
+            )
+            .into());
+        }
+        // BoringSSL only hands new sessions of a client to the callback in
+        // client cache mode, it has no internal cache for clients.
+        self.builder
+            .set_session_cache_mode(SslSessionCacheMode::CLIENT);
+        self.builder
+            .set_new_session_callback(session_ticket::store_new_ticket);
+        Ok(self)
+    }
+
     /// Register a TLS 1.3 key logger on the context.
     ///
     /// BoringSSL exposes keylog only at the `SSL_CTX` level
@@ -299,6 +356,18 @@ impl Context {
     {
         Session::new(self, config).map_err(|e| super::NewSessionError(e.to_string()))
     }
+
+    /// Issue new session tickets under `key`, see
+    /// [`ContextBuilder::with_session_ticket_key`]. Tickets issued under
+    /// the key being replaced still resume until the next rotation.
+    /// Returns false when `key` is already in use.
+    pub fn rotate_session_ticket_key(&self, key: &[u8]) -> Result<bool> {
+        let keys = self
+            .ssl_ctx
+            .ex_data(*TICKET_KEYS_INDEX)
+            .ok_or_else(|| TlsError::InvalidParameter("no session ticket key configured".into()))?;
+        Ok(keys.rotate(key)?)
+    }
 }
 
 #[cfg(test)]
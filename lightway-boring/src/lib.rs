@@ -23,6 +23,7 @@ mod context;
 mod debug;
 mod error;
 mod session;
+mod session_ticket;
 #[cfg(test)]
 mod test_utils;
 mod types;
@@ -36,6 +37,7 @@ pub use config::SessionConfig;
 pub use context::{Context, ContextBuilder};
 pub use error::{Error, ErrorKind, NewContextBuilderError, NewSessionError, TlsError};
 pub use session::Session;
+pub use session_ticket::SESSION_TICKET_KEY_LEN;
 pub use types::{
     CurveGroup, IOCallbackResult, IOCallbacks, Method, Poll, PollResult, ProtocolVersion,
     SslVerifyMode,
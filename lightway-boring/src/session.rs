@@ -9,7 +9,7 @@
 
 // Required for BoringSSL FFI
 use super::{IOCallbackResult, IOCallbacks, ProtocolVersion, TlsError};
-use boring::ssl::{ErrorCode, ShutdownResult, Ssl, SslMode, SslStream};
+use boring::ssl::{ErrorCode, ShutdownResult, Ssl, SslMode, SslSession, SslStream};
 use boring::x509::X509VerifyError;
 use boring::x509::verify::X509VerifyFlags;
 use bytes::{Buf, BytesMut};
@@ -17,6 +17,7 @@ use foreign_types::ForeignTypeRef;
 
 use super::config::SessionConfig;
 use super::context::Context;
+use super::session_ticket::{NEW_TICKET_INDEX, NewTicketSlot};
 use std::time::Duration;
 
 /// Poll interval reported when DTLS has no retransmit timer armed (handshake not
@@ -163,6 +164,19 @@ where
             }
         }
 
+        if is_client {
+            ssl.set_ex_data(*NEW_TICKET_INDEX, NewTicketSlot::default());
+        }
+
+        if is_client && let Some(ref ticket) = config.session_ticket {
+            match SslSession::from_der(ticket) {
+                // SAFETY: the session was decoded above and is not shared
+                // with any other SSL_CTX.
+                Ok(session) => unsafe { ssl.set_session(&session)? },
+                Err(e) => tracing::warn!("Ignoring invalid session ticket: {e}"),
+            }
+        }
+
         // No per-session keylog hook: BoringSSL only exposes keylog at the
         // SSL_CTX level, wired via ContextBuilder::with_key_logger.
 
@@ -304,6 +318,27 @@ where
         self.ssl_stream.ssl_mut().set_verify(mode.into());
     }
 
+    /// Take the latest session ticket the server sent, if any since the
+    /// last call. Only client sessions of a context built
+    /// `with_session_resumption` collect tickets.
+    ///
+    /// The ticket holds the session's resumption secret: keep it private.
+    pub fn take_session_ticket(&mut self) -> Option<Vec<u8>> {
+        let session = self
+            .ssl_stream
+            .ssl()
+            .ex_data(*NEW_TICKET_INDEX)?
+            .lock()
+            .ok()?
+            .take()?;
+        session.to_der().ok()
+    }
+
+    /// Whether the handshake resumed a previous session
+    pub fn session_reused(&self) -> bool {
+        self.ssl_stream.ssl().session_reused()
+    }
+
     /// Get current cipher name
     pub fn get_current_cipher_name(&self) -> Option<String> {
         self.ssl_stream
@@ -489,10 +524,11 @@ unsafe extern "C" {
 mod tests {
     use crate::test_utils::mock::{
         MockIOAdapter, TcpIOCallbacks, UdpIOCallbacks, make_connected_dtls_pair,
-        make_connected_tls_pair,
+        make_connected_tls_pair, root_cert, server_cert, server_key,
     };
     use crate::{
-        ContextBuilder, CurveGroup, IOCallbackResult, IOCallbacks, Method, Poll, SessionConfig,
+        ContextBuilder, CurveGroup, IOCallbackResult, IOCallbacks, Method, Poll, RootCertificate,
+        SESSION_TICKET_KEY_LEN, Secret, SessionConfig,
     };
     use std::time::Duration;
 
@@ -855,4 +891,133 @@ mod tests {
             "checked_domain_name leaked into the ClientHello as SNI"
         );
     }
+
+    /// Handshake a client of `client_ctx` offering `ticket` with a server
+    /// of `server_ctx`, then read once so the client sees the server's
+    /// tickets. Returns whether the session was resumed and the latest
+    /// ticket received.
+    fn resume_with(
+        client_ctx: &crate::Context,
+        server_ctx: &crate::Context,
+        ticket: Option<&[u8]>,
+    ) -> (bool, Option<Vec<u8>>) {
+        let (client_io, server_io) = TcpIOCallbacks::pair();
+        let mut client = client_ctx
+            .new_session(
+                SessionConfig::new(client_io)
+                    .with_checked_domain_name("example.com")
+                    .when_some(ticket, |c, t| c.with_session_ticket(t)),
+            )
+            .unwrap();
+        let mut server = server_ctx
+            .new_session(SessionConfig::new(server_io))
+            .unwrap();
+        for _ in 0..20 {
+            let _ = client.try_negotiate();
+            let _ = server.try_negotiate();
+            if client.is_init_finished() && server.is_init_finished() {
+                break;
+            }
+        }
+        assert!(client.is_init_finished() && server.is_init_finished());
+
+        let _ = client.try_read(&mut bytes::BytesMut::new());
+        assert_eq!(client.session_reused(), server.session_reused());
+        (client.session_reused(), client.take_session_ticket())
+    }
+
+    fn ticket_contexts(key: &[u8]) -> (crate::Context, crate::Context) {
+        let client_ctx = ContextBuilder::new(Method::TlsClientV1_3)
+            .unwrap()
+            .with_root_certificate(RootCertificate::Asn1Buffer(root_cert()))
+            .unwrap()
+            .with_session_resumption()
+            .unwrap()
+            .build();
+        let server_ctx = ContextBuilder::new(Method::TlsServerV1_3)
+            .unwrap()
+            .with_certificate(Secret::Asn1Buffer(server_cert()))
+            .unwrap()
+            .with_private_key(Secret::Asn1Buffer(server_key()))
+            .unwrap()
+            .with_session_ticket_key(key)
+            .unwrap()
+            .build();
+        (client_ctx, server_ctx)
+    }
+
+    #[test]
+    fn session_ticket_resumes() {
+        let (client_ctx, server_ctx) = ticket_contexts(&[1; SESSION_TICKET_KEY_LEN]);
+
+        let (resumed, ticket) = resume_with(&client_ctx, &server_ctx, None);
+        assert!(!resumed);
+        let ticket = ticket.expect("server sent a ticket");
+
+        let (resumed, ticket) = resume_with(&client_ctx, &server_ctx, Some(ticket.as_slice()));
+        assert!(resumed);
+        assert!(ticket.is_some(), "resumed session gets a fresh ticket");
+    }
+
+    #[test]
+    fn session_ticket_resumes_on_server_sharing_the_key() {
+        let key = [1; SESSION_TICKET_KEY_LEN];
+        let (client_ctx, server_ctx) = ticket_contexts(&key);
+        let (_, other_server_ctx) = ticket_contexts(&key);
+
+        let (_, ticket) = resume_with(&client_ctx, &server_ctx, None);
+        let (resumed, _) = resume_with(&client_ctx, &other_server_ctx, ticket.as_deref());
+        assert!(resumed);
+    }
+
+    #[test]
+    fn session_ticket_survives_one_key_rotation() {
+        let (client_ctx, server_ctx) = ticket_contexts(&[1; SESSION_TICKET_KEY_LEN]);
+        let (_, ticket) = resume_with(&client_ctx, &server_ctx, None);
+        let ticket = ticket.unwrap();
+
+        assert!(
+            server_ctx
+                .rotate_session_ticket_key(&[2; SESSION_TICKET_KEY_LEN])
+                .unwrap()
+        );
+        let (resumed, _) = resume_with(&client_ctx, &server_ctx, Some(ticket.as_slice()));
+        assert!(resumed);
+
+        server_ctx
+            .rotate_session_ticket_key(&[3; SESSION_TICKET_KEY_LEN])
+            .unwrap();
+        let (resumed, new_ticket) = resume_with(&client_ctx, &server_ctx, Some(ticket.as_slice()));
+        assert!(!resumed, "ticket key retired, full handshake");
+        assert!(new_ticket.is_some());
+    }
+
+    #[test]
+    fn no_session_ticket_without_key() {
+        let client_ctx = ContextBuilder::new(Method::TlsClientV1_3)
+            .unwrap()
+            .with_root_certificate(RootCertificate::Asn1Buffer(root_cert()))
+            .unwrap()
+            .with_session_resumption()
+            .unwrap()
+            .build();
+        let server_ctx = ContextBuilder::new(Method::TlsServerV1_3)
+            .unwrap()
+            .with_certificate(Secret::Asn1Buffer(server_cert()))
+            .unwrap()
+            .with_private_key(Secret::Asn1Buffer(server_key()))
+            .unwrap()
+            .build();
+
+        let (_, ticket) = resume_with(&client_ctx, &server_ctx, None);
+        assert!(ticket.is_none());
+        assert!(server_ctx.rotate_session_ticket_key(&[1; 48]).is_err());
+    }
+
+    #[test]
+    fn invalid_session_ticket_falls_back_to_full_handshake() {
+        let (client_ctx, server_ctx) = ticket_contexts(&[1; SESSION_TICKET_KEY_LEN]);
+        let (resumed, _) = resume_with(&client_ctx, &server_ctx, Some(b"not a ticket".as_slice()));
+        assert!(!resumed);
+    }
 }
@@ -0,0 +1,206 @@
+//! Session tickets for TLS/DTLS 1.3 resumption.
+//!
+//! Servers encrypt tickets with a [`TicketKeys`] ring installed on the
+//! `SSL_CTX` through `SSL_CTX_set_tlsext_ticket_key_cb`, so the key can be
+//! shared between servers and replaced at runtime while tickets issued
+//! under the previous key still resume. Clients collect the tickets the
+//! server sends into a per-`SSL` [`NewTicketSlot`].
+//!
+//! Early data (0-RTT) is never enabled: it is replayable, and Lightway
+//! has nothing to send before the handshake completes anyway.
+
+#![allow(unsafe_code)]
+
+use std::sync::{LazyLock, Mutex, RwLock};
+
+use boring::ex_data::Index;
+use boring::ssl::{Ssl, SslContext, SslRef, SslSession};
+use foreign_types::ForeignTypeRef;
+use zeroize::Zeroizing;
+
+use super::TlsError;
+
+/// Length of a ticket key: 16 byte key name, 16 byte HMAC-SHA256 secret
+/// and 16 byte AES-128 key, the layout of `SSL_CTX_set_tlsext_ticket_keys`.
+pub const SESSION_TICKET_KEY_LEN: usize = 48;
+
+const KEY_NAME_LEN: usize = 16;
+const IV_LEN: usize = 16;
+
+struct TicketKey(Zeroizing<[u8; SESSION_TICKET_KEY_LEN]>);
+
+impl TicketKey {
+    fn new(key: &[u8]) -> Result<Self, TlsError> {
+        let key: [u8; SESSION_TICKET_KEY_LEN] = key.try_into().map_err(|_| {
+            TlsError::InvalidParameter(format!(
+                "session ticket key must be {SESSION_TICKET_KEY_LEN} bytes, got {}",
+                key.len()
+            ))
+        })?;
+        Ok(Self(Zeroizing::new(key)))
+    }
+
+    fn name(&self) -> &[u8] {
+        &self.0[..KEY_NAME_LEN]
+    }
+
+    fn hmac_key(&self) -> &[u8] {
+        &self.0[KEY_NAME_LEN..32]
+    }
+
+    fn aes_key(&self) -> &[u8] {
+        &self.0[32..]
+    }
+}
+
+/// The key new tickets are issued under and the one it replaced.
+struct KeyRing {
+    current: TicketKey,
+    previous: Option<TicketKey>,
+}
+
+/// Ticket keys of a server context.
+pub(crate) struct TicketKeys(RwLock<KeyRing>);
+
+impl TicketKeys {
+    pub(crate) fn new(key: &[u8]) -> Result<Self, TlsError> {
+        Ok(Self(RwLock::new(KeyRing {
+            current: TicketKey::new(key)?,
+            previous: None,
+        })))
+    }
+
+    /// Issue new tickets under `key`, keeping the current key to decrypt
+    /// tickets issued before. Returns false when `key` is already current.
+    pub(crate) fn rotate(&self, key: &[u8]) -> Result<bool, TlsError> {
+        let key = TicketKey::new(key)?;
+        let mut ring = self
+            .0
+            .write()
+            .map_err(|_| TlsError::InvalidParameter("session ticket keys poisoned".into()))?;
+        if *ring.current.0 == *key.0 {
+            return Ok(false);
+        }
+        ring.previous = Some(std::mem::replace(&mut ring.current, key));
+        Ok(true)
+    }
+}
+
+pub(crate) static TICKET_KEYS_INDEX: LazyLock<Index<SslContext, TicketKeys>> =
+    LazyLock::new(|| SslContext::new_ex_index().expect("allocate SSL_CTX ex_data index"));
+
+/// The latest ticket received by a client session.
+pub(crate) type NewTicketSlot = Mutex<Option<SslSession>>;
+
+pub(crate) static NEW_TICKET_INDEX: LazyLock<Index<Ssl, NewTicketSlot>> =
+    LazyLock::new(|| Ssl::new_ex_index().expect("allocate SSL ex_data index"));
+
+/// `SSL_CTX_set_new_session_cb` handler for client contexts.
+pub(crate) fn store_new_ticket(ssl: &mut SslRef, session: SslSession) {
+    if let Some(slot) = ssl.ex_data(*NEW_TICKET_INDEX)
+        && let Ok(mut slot) = slot.lock()
+    {
+        *slot = Some(session);
+    }
+}
+
+/// `SSL_CTX_set_tlsext_ticket_key_cb` handler for server contexts.
+///
+/// Returns 1 when the ticket key was set up, 2 when decrypting a ticket
+/// issued under the previous key (so that a fresh one is sent), 0 when the
+/// ticket's key is unknown (full handshake) and -1 on error. Must not
+/// panic: it is called from C.
+///
+/// Ref: <https://github.com/google/boringssl/blob/master/include/openssl/ssl.h> (SSL_CTX_set_tlsext_ticket_key_cb)
+pub(crate) unsafe extern "C" fn ticket_key_callback(
+    ssl: *mut boring_sys::SSL,
+    key_name: *mut u8,
+    iv: *mut u8,
+    cipher_ctx: *mut boring_sys::EVP_CIPHER_CTX,
+    hmac_ctx: *mut boring_sys::HMAC_CTX,
+    encrypt: std::ffi::c_int,
+) -> std::ffi::c_int {
+    // SAFETY: BoringSSL passes the SSL performing the handshake, valid for
+    // the duration of the callback.
+    let ssl = unsafe { SslRef::from_ptr(ssl) };
+    let Some(keys) = ssl.ssl_context().ex_data(*TICKET_KEYS_INDEX) else {
+        return -1;
+    };
+    let Ok(ring) = keys.0.read() else {
+        return -1;
+    };
+
+    // SAFETY: `key_name` points at 16 bytes and `iv` at EVP_MAX_IV_LENGTH
+    // (16) bytes, both writable when encrypting and readable otherwise.
+    // `cipher_ctx` and `hmac_ctx` are initialised contexts owned by
+    // BoringSSL for this call. The key slices outlive the init calls,
+    // which copy the key material.
+    unsafe {
+        let (key, ret) = if encrypt == 1 {
+            if boring_sys::RAND_bytes(iv, IV_LEN) != 1 {
+                return -1;
+            }
+            std::ptr::copy_nonoverlapping(ring.current.name().as_ptr(), key_name, KEY_NAME_LEN);
+            (&ring.current, 1)
+        } else {
+            let name = std::slice::from_raw_parts(key_name, KEY_NAME_LEN);
+            if ring.current.name() == name {
+                (&ring.current, 1)
+            } else if let Some(previous) = &ring.previous
+                && previous.name() == name
+            {
+                (previous, 2)
+            } else {
+                return 0;
+            }
+        };
+
+        let cipher = boring_sys::EVP_aes_128_cbc();
+        let aes_key = key.aes_key().as_ptr();
+        let cipher_ret = if encrypt == 1 {
+            boring_sys::EVP_EncryptInit_ex(cipher_ctx, cipher, std::ptr::null_mut(), aes_key, iv)
+        } else {
+            boring_sys::EVP_DecryptInit_ex(cipher_ctx, cipher, std::ptr::null_mut(), aes_key, iv)
+        };
+        if cipher_ret != 1 {
+            return -1;
+        }
+        if boring_sys::HMAC_Init_ex(
+            hmac_ctx,
+            key.hmac_key().as_ptr().cast(),
+            key.hmac_key().len(),
+            boring_sys::EVP_sha256(),
+            std::ptr::null_mut(),
+        ) != 1
+        {
+            return -1;
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_length_is_checked() {
+        for len in [0, 32, 80] {
+            assert!(matches!(
+                TicketKeys::new(&vec![0; len]),
+                Err(TlsError::InvalidParameter(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn rotate_keeps_previous_key() {
+        let keys = TicketKeys::new(&[1; SESSION_TICKET_KEY_LEN]).unwrap();
+        assert!(!keys.rotate(&[1; SESSION_TICKET_KEY_LEN]).unwrap());
+        assert!(keys.rotate(&[2; SESSION_TICKET_KEY_LEN]).unwrap());
+
+        let ring = keys.0.read().unwrap();
+        assert_eq!(ring.current.name(), &[2; KEY_NAME_LEN]);
+        assert_eq!(ring.previous.as_ref().unwrap().name(), &[1; KEY_NAME_LEN]);
+    }
+}
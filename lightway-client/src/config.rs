@@ -1,18 +1,31 @@
 #[cfg(desktop)]
 use super::dns_manager::DnsConfigMode;
+use super::dns_proxy::DnsUpstream;
+use super::happy_eyeballs::DEFAULT_CONNECTION_ATTEMPT_DELAY;
+#[cfg(desktop)]
+use super::io::outside::MultipathPolicy;
+#[cfg(linux)]
+use super::platform::linux::kill_switch::KillSwitchMode;
 #[cfg(desktop)]
 use super::route_manager::RouteMode;
+#[cfg(desktop)]
+use super::server_selection::{
+    DEFAULT_SERVER_SELECTION_PRIORITY_WEIGHT, DEFAULT_SERVER_SELECTION_PROBES, ServerSelection,
+};
 use bytesize::ByteSize;
 use clap::Parser;
+use ipnet::Ipv4Net;
 #[cfg(feature = "postquantum")]
 use lightway_app_utils::args::KeyShare;
 use lightway_app_utils::args::{
     Cipher, ConfigFormat, ConnectionType, Duration, LogLevel, NonZeroDuration,
 };
-use lightway_core::{AuthMethod, MAX_OUTSIDE_MTU, Version};
+use lightway_core::{AuthMethod, MAX_OUTSIDE_MTU, MAX_SITE_SUBNETS, Version};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+#[cfg(desktop)]
+use std::net::SocketAddr;
 use std::time::Duration as StdDuration;
 use std::{net::Ipv4Addr, path::PathBuf};
 use struct_patch::{Patch, Substrate};
@@ -131,6 +144,17 @@ pub struct Config {
     #[patch(attribute(doc = "DNS IP to use in Tun device"))]
     pub tun_dns_ip: Ipv4Addr,
 
+    #[patch(attribute(clap(long, value_delimiter = ',')))]
+    #[patch(
+        attribute(doc = r#"Subnets behind this client, routed to it by the server
+    Packets from and to them are forwarded without translating their
+    addresses. They are advertised to the server when authenticating,
+    which only routes those its auth backend grants the client"#)
+    )]
+    #[schemars(with = "Vec<String>")]
+    /// ex: 192.168.10.0/24
+    pub site_subnets: Vec<Ipv4Net>,
+
     #[cfg(feature = "postquantum")]
     #[patch(attribute(clap(long, value_enum)))]
     #[patch(attribute(doc = "Enable Post Quantum Crypto"))]
@@ -155,6 +179,58 @@ pub struct Config {
     #[patch(attribute(doc = "Enable continuous Keepalive"))]
     pub keepalive_continuous: bool,
 
+    #[patch(attribute(clap(long)))]
+    #[patch(empty_value = false)]
+    #[patch(attribute(serde(default)))]
+    #[patch(attribute(doc = r#"Adapt keepalives to the link.
+    The timeout follows measured round trip times, between
+    `keepalive_min_timeout` and `keepalive_timeout`. With continuous
+    keepalives the interval backs off on idle links up to
+    `keepalive_max_interval`"#))]
+    pub keepalive_adaptive: bool,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = "Least keepalive timeout with `keepalive_adaptive`"))]
+    #[schemars(schema_with = "lightway_app_utils::args::nonzero_duration_schema")]
+    /// ex: 15s
+    pub keepalive_min_timeout: NonZeroDuration,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = "Most keepalive interval with `keepalive_adaptive`"))]
+    #[schemars(schema_with = "lightway_app_utils::args::nonzero_duration_schema")]
+    /// ex: 60s
+    pub keepalive_max_interval: NonZeroDuration,
+
+    #[cfg(desktop)]
+    #[patch(attribute(clap(long)))]
+    #[patch(empty_value = false)]
+    #[patch(attribute(serde(default)))]
+    #[patch(attribute(doc = r#"Probe the NAT binding lifetime of UDP connections.
+    Keepalives are sent after growing idle times, from
+    `keepalive_nat_min_interval` up to `keepalive_nat_max_interval`, until
+    the binding expires, holding back regular keepalives meanwhile. Keepalives
+    on an idle connection then follow the lifetime in place of
+    `keepalive_interval`, and the smallest frame refreshes the binding just
+    before it would expire. Lifetimes are learned once per network"#))]
+    #[schemars(extend("x-cfg" = "desktop"))]
+    pub keepalive_nat_probe: bool,
+
+    #[cfg(desktop)]
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = "Least NAT binding lifetime with `keepalive_nat_probe`"))]
+    #[schemars(extend("x-cfg" = "desktop"))]
+    #[schemars(schema_with = "lightway_app_utils::args::nonzero_duration_schema")]
+    /// ex: 20s
+    pub keepalive_nat_min_interval: NonZeroDuration,
+
+    #[cfg(desktop)]
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = "Most NAT binding lifetime with `keepalive_nat_probe`"))]
+    #[schemars(extend("x-cfg" = "desktop"))]
+    #[schemars(schema_with = "lightway_app_utils::args::nonzero_duration_schema")]
+    /// ex: 300s
+    pub keepalive_nat_max_interval: NonZeroDuration,
+
     #[patch(attribute(clap(long)))]
     #[patch(attribute(doc = r#"Time it takes to trigger a tracer packet
     when we haven't received an outside packet"#))]
@@ -173,6 +249,51 @@ pub struct Config {
     /// ex: 2000ms
     pub preferred_connection_wait_interval: Duration,
 
+    #[cfg(desktop)]
+    #[patch(attribute(clap(long, value_enum)))]
+    #[patch(
+        attribute(doc = r#"How to select the best connection among those online
+    Strategies:
+        priority: Earliest in the server list
+        handshake_rtt: Lowest handshake round trip time
+        keepalive_rtt: Lowest round trip time of keepalive probes
+        weighted: Lowest latency plus `server_selection_priority_weight`
+            per position in the server list
+    All but priority compare the connections online within
+    `preferred_connection_wait_interval`"#)
+    )]
+    #[schemars(extend("x-cfg" = "desktop"))]
+    pub server_selection: ServerSelection,
+
+    #[cfg(desktop)]
+    #[patch(attribute(clap(long)))]
+    #[patch(
+        attribute(doc = r#"Keepalives sent to each connection by the keepalive_rtt
+    and weighted server selection. Weighted uses the handshake round trip time when 0"#)
+    )]
+    #[schemars(extend("x-cfg" = "desktop"))]
+    pub server_selection_probes: usize,
+
+    #[cfg(desktop)]
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(
+        doc = "Latency the weighted server selection adds per position in the server list"
+    ))]
+    #[schemars(schema_with = "lightway_app_utils::args::duration_schema")]
+    #[schemars(extend("x-cfg" = "desktop"))]
+    /// ex: 20ms
+    pub server_selection_priority_weight: Duration,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(
+        attribute(doc = r#"Delay between starting two connection attempts to an
+    `auto` mode server, e.g. TCP after UDP when UDP is not yet online"#)
+    )]
+    #[schemars(schema_with = "lightway_app_utils::args::duration_schema")]
+    #[schemars(extend("x-cfg" = "desktop"))]
+    /// ex: 250ms
+    pub connection_attempt_delay: Duration,
+
     #[patch(attribute(clap(long)))]
     #[patch(attribute(doc = r#"Socket send buffer size.
     Always applied for UDP.
@@ -203,13 +324,33 @@ pub struct Config {
     #[schemars(extend("x-cfg" = "batch_receive"))]
     pub enable_batch_receive: bool,
 
+    #[cfg(desktop)]
+    #[patch(attribute(clap(long, value_delimiter = ',')))]
+    #[patch(attribute(doc = r#"Outside links to spread a UDP connection over
+    Each is an interface name (Linux and macOS) or a local IP address,
+    e.g. `wlan0,wwan0`. A single outside socket is used when empty"#))]
+    #[schemars(extend("x-cfg" = "desktop"))]
+    pub multipath_links: Vec<String>,
+
+    #[cfg(desktop)]
+    #[patch(attribute(clap(long, value_enum)))]
+    #[patch(attribute(doc = r#"How packets are spread over `multipath_links`
+    Policies:
+        active_backup : First link answering keepalive probes
+        lowest_rtt    : Link with the lowest probed round trip time
+        redundant     : Every packet on every link"#))]
+    #[schemars(extend("x-cfg" = "desktop"))]
+    pub multipath_policy: MultipathPolicy,
+
     #[cfg(desktop)]
     #[patch(attribute(clap(long, value_enum)))]
     #[patch(attribute(doc = r#"Setup of route table
     Modes:
         default: Sets up routes as specified in server, tun_local_ip, tun_peer_ip, tun_dns_ip
         noexec : Does not setup any routes
-        lan    : Sets up default + additional lan routes"#))]
+        lan    : Sets up default + additional lan routes
+        policy : Routes via a dedicated table and a `not fwmark` rule,
+                 requires `fwmark` (Linux only)"#))]
     #[schemars(extend("x-cfg" = "desktop"))]
     pub route_mode: RouteMode,
 
@@ -226,15 +367,123 @@ pub struct Config {
     #[schemars(extend("x-cfg" = "linux"))]
     pub fwmark: u32,
 
+    #[cfg(linux)]
+    #[patch(attribute(clap(long, value_enum)))]
+    #[patch(attribute(doc = r#"Kill switch (nftables leak protection)
+    Modes:
+        disabled: No firewall rules are installed
+        enabled : Only the tunnel, loopback and the Lightway server are reachable
+        lan     : Same as enabled, but LAN networks stay reachable
+    Rules persist across reconnects and are removed on user disconnect only"#))]
+    #[schemars(extend("x-cfg" = "linux"))]
+    pub kill_switch: KillSwitchMode,
+
     #[cfg(desktop)]
     #[patch(attribute(clap(long, value_enum)))]
     #[patch(attribute(doc = r#"DNS configuration mode
     Modes:
         default: Sets up DNS Configuration based on target platform
-        noexec : Skips DNS Configuration setup"#))]
+        noexec : Skips DNS Configuration setup
+        resolved: (Linux) Use systemd-resolved over D-Bus
+        networkmanager: (Linux) Use NetworkManager over D-Bus"#))]
     #[schemars(extend("x-cfg" = "desktop"))]
     pub dns_config_mode: DnsConfigMode,
 
+    #[patch(attribute(clap(long)))]
+    #[patch(empty_value = false)]
+    #[patch(attribute(serde(default)))]
+    #[patch(attribute(doc = r#"Enable the built-in DNS proxy
+    Every IPv4 DNS query over UDP entering the tunnel is answered by the
+    client, whatever resolver it was sent to, using `dns_proxy_upstream`.
+    IPv6 queries do not enter the IPv4 only tunnel and bypass the proxy"#))]
+    pub dns_proxy: bool,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = r#"Upstream of the DNS proxy
+    Formats:
+        tunnel                         : Server DNS, over the tunnel
+        tls://<host>[:<port>]          : DNS over TLS
+        https://<host>[:<port>]/<path> : DNS over HTTPS
+    DoT and DoH connections are routed over the tunnel as well"#))]
+    #[schemars(with = "String")]
+    /// ex: tls://1.1.1.1
+    pub dns_proxy_upstream: DnsUpstream,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = "Number of answers cached by the DNS proxy, 0 disables caching"))]
+    pub dns_proxy_cache_size: usize,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = r#"File of domains the DNS proxy answers with NXDOMAIN
+    One domain per line, subdomains are blocked too"#))]
+    pub dns_proxy_blocklist: Option<PathBuf>,
+
+    #[cfg(desktop)]
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = r#"Serve a SOCKS5 proxy on this address
+    Traffic goes through a userspace network stack instead of a tunnel
+    device, and no routes or DNS are configured. Must be a loopback
+    address, the proxy has no authentication"#))]
+    #[schemars(extend("x-cfg" = "desktop"))]
+    /// ex: 127.0.0.1:1080
+    pub socks_proxy: Option<SocketAddr>,
+
+    #[cfg(desktop)]
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = r#"Serve an HTTP CONNECT proxy on this address
+    Traffic goes through a userspace network stack instead of a tunnel
+    device, and no routes or DNS are configured. Must be a loopback
+    address, the proxy has no authentication"#))]
+    #[schemars(extend("x-cfg" = "desktop"))]
+    /// ex: 127.0.0.1:8080
+    pub http_proxy: Option<SocketAddr>,
+
+    #[cfg(desktop)]
+    #[patch(attribute(clap(long)))]
+    #[patch(empty_value = false)]
+    #[patch(attribute(serde(default)))]
+    #[patch(
+        attribute(doc = r#"Reconnect when the connection ends, instead of exiting
+    The tunnel device, routes and DNS stay in place between attempts"#)
+    )]
+    #[schemars(extend("x-cfg" = "desktop"))]
+    pub reconnect: bool,
+
+    #[cfg(desktop)]
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = r#"Delay before the first reconnect attempt.
+    Doubled after every failed attempt, with jitter"#))]
+    #[schemars(schema_with = "lightway_app_utils::args::duration_schema")]
+    #[schemars(extend("x-cfg" = "desktop"))]
+    /// ex: 1s
+    pub reconnect_initial_delay: Duration,
+
+    #[cfg(desktop)]
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = "Upper bound for the delay between reconnect attempts"))]
+    #[schemars(schema_with = "lightway_app_utils::args::duration_schema")]
+    #[schemars(extend("x-cfg" = "desktop"))]
+    /// ex: 60s
+    pub reconnect_max_delay: Duration,
+
+    #[cfg(desktop)]
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(
+        doc = "Give up after this many consecutive failed reconnect attempts, 0 retries forever"
+    ))]
+    #[schemars(extend("x-cfg" = "desktop"))]
+    pub reconnect_max_attempts: u32,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(empty_value = false)]
+    #[patch(attribute(serde(default)))]
+    #[patch(
+        attribute(doc = r#"Resume the TLS session when connecting again to a server.
+    Requires a server issuing session tickets and the BoringSSL backend,
+    enabled by default with it. Rejected with wolfSSL"#)
+    )]
+    pub session_resumption: bool,
+
     #[patch(attribute(clap(long, value_enum)))]
     #[patch(attribute(doc = "Log level to use"))]
     pub log_level: LogLevel,
@@ -245,6 +494,16 @@ pub struct Config {
     #[patch(attribute(doc = "Enable Expresslane for [`ConnectionType::Udp`] connections"))]
     pub enable_expresslane: bool,
 
+    #[patch(attribute(clap(long)))]
+    #[patch(empty_value = false)]
+    #[patch(attribute(serde(default)))]
+    #[patch(attribute(
+        doc = r#"Move UDP connections to a new outside socket on network change.
+    The connection and its DTLS state are kept, the server follows the
+    session ID to the new address. Not applied to multipath connections"#
+    ))]
+    pub udp_migration: bool,
+
     #[cfg(apple)]
     #[patch(attribute(clap(long)))]
     #[patch(empty_value = false)]
@@ -274,6 +533,38 @@ pub struct Config {
     #[schemars(extend("x-cfg" = "desktop"))]
     pub pmtud_base_mtu: Option<u16>,
 
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = r#"Key file to scramble UDP datagrams with.
+    64 hex digits, e.g. from `openssl rand -hex 32`, same as the server's"#))]
+    pub obfuscation_key: Option<PathBuf>,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = r#"Pad UDP datagrams with up to this many bytes.
+    0 disables, the server must pad as well"#))]
+    pub obfuscation_padding: u16,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = "Delay sending UDP datagrams by up to this long, 0 disables"))]
+    #[schemars(schema_with = "lightway_app_utils::args::duration_schema")]
+    /// ex: 5ms
+    pub obfuscation_jitter: Duration,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(empty_value = false)]
+    #[patch(attribute(serde(default)))]
+    #[patch(attribute(doc = r#"Wrap TCP connections in a WebSocket connection.
+    The server must have `websocket` enabled"#))]
+    pub websocket: bool,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = r#"Host header of the WebSocket upgrade request.
+    Defaults to the server address"#))]
+    pub websocket_host: Option<String>,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = "Path of the WebSocket upgrade request"))]
+    pub websocket_path: String,
+
     #[patch(attribute(clap(long)))]
     #[patch(empty_value = false)]
     #[patch(attribute(serde(default)))]
@@ -401,6 +692,11 @@ impl Config {
             }
         };
         for server in self.servers.iter_mut() {
+            // Racing transports needs the desktop connection setup
+            if !cfg!(desktop) && server.mode.is_auto() {
+                return Err(Error::InvalidProtocol);
+            }
+
             if server.user.is_none() && server.password.is_none() && server.token.is_none() {
                 server.user = self.user.clone();
                 server.password = self.password.clone();
@@ -469,6 +765,31 @@ impl Config {
                 }
             }
         }
+        anyhow::ensure!(
+            !self.keepalive_adaptive
+                || StdDuration::from(self.keepalive_interval)
+                    <= StdDuration::from(self.keepalive_max_interval),
+            "keepalive_max_interval must not be less than keepalive_interval"
+        );
+        #[cfg(desktop)]
+        anyhow::ensure!(
+            !self.keepalive_nat_probe
+                || StdDuration::from(self.keepalive_nat_min_interval)
+                    <= StdDuration::from(self.keepalive_nat_max_interval),
+            "keepalive_nat_min_interval must not exceed keepalive_nat_max_interval"
+        );
+        #[cfg(linux)]
+        anyhow::ensure!(
+            self.route_mode != RouteMode::Policy || self.fwmark != 0,
+            "route_mode policy requires a non-zero fwmark"
+        );
+        #[cfg(desktop)]
+        anyhow::ensure!(
+            !self.reconnect
+                || StdDuration::from(self.reconnect_initial_delay)
+                    <= StdDuration::from(self.reconnect_max_delay),
+            "reconnect_initial_delay must not exceed reconnect_max_delay"
+        );
         #[cfg(windows)]
         if let Some(guid) = &self.device_guid {
             anyhow::ensure!(
@@ -486,6 +807,56 @@ impl Config {
                 }
             }
         }
+        if self.obfuscation_padding > 0 || !self.obfuscation_jitter.is_zero() {
+            for (server, mode) in &all_servers {
+                if mode.is_tcp() {
+                    tracing::warn!(
+                        server,
+                        "obfuscation_padding and obfuscation_jitter cannot be applied to this TCP connections"
+                    );
+                }
+            }
+        }
+        if self.websocket {
+            for (server, mode) in &all_servers {
+                if mode.is_udp() {
+                    tracing::warn!(
+                        server,
+                        "websocket is set but cannot be applied to this UDP connections"
+                    );
+                }
+            }
+        }
+        anyhow::ensure!(
+            !self.session_resumption || lightway_core::tls::SESSION_TICKETS_SUPPORTED,
+            "session_resumption requires the BoringSSL backend"
+        );
+        anyhow::ensure!(
+            self.websocket_path.starts_with('/'),
+            "websocket_path must start with '/'"
+        );
+        anyhow::ensure!(
+            self.site_subnets.len() <= MAX_SITE_SUBNETS,
+            "At most {MAX_SITE_SUBNETS} site_subnets are supported"
+        );
+        #[cfg(desktop)]
+        if !self.multipath_links.is_empty() {
+            for (server, mode) in &all_servers {
+                if mode.is_tcp() {
+                    tracing::warn!(
+                        server,
+                        "multipath_links is set but cannot be applied to this TCP connections"
+                    );
+                }
+            }
+        }
+        #[cfg(windows)]
+        anyhow::ensure!(
+            self.multipath_links
+                .iter()
+                .all(|link| link.parse::<std::net::IpAddr>().is_ok()),
+            "multipath_links must be local IP addresses on Windows"
+        );
         #[cfg(windows)]
         anyhow::ensure!(
             self.wintun_ring_capacity.0.is_power_of_two()
@@ -493,6 +864,30 @@ impl Config {
                 && self.wintun_ring_capacity <= ByteSize::mib(64),
             "wintun_ring_capacity must be a power of two between 128KiB and 64MiB"
         );
+        #[cfg(desktop)]
+        if self.socks_proxy.is_some() || self.http_proxy.is_some() {
+            // The proxies have no authentication
+            anyhow::ensure!(
+                self.socks_proxy
+                    .iter()
+                    .chain(&self.http_proxy)
+                    .all(|addr| addr.ip().is_loopback()),
+                "socks_proxy and http_proxy must be bound to a loopback address"
+            );
+            anyhow::ensure!(
+                !self.enable_tun_iouring,
+                "socks_proxy and http_proxy cannot be used with enable_tun_iouring"
+            );
+            #[cfg(linux)]
+            anyhow::ensure!(
+                self.kill_switch == KillSwitchMode::Disabled,
+                "socks_proxy and http_proxy cannot be used with kill_switch"
+            );
+            anyhow::ensure!(
+                self.site_subnets.is_empty(),
+                "socks_proxy and http_proxy cannot be used with site_subnets"
+            );
+        }
         Ok(())
     }
 }
@@ -523,27 +918,74 @@ impl Default for Config {
             tun_local_ip: Ipv4Addr::new(100, 64, 0, 6),
             tun_peer_ip: Ipv4Addr::new(100, 64, 0, 5),
             tun_dns_ip: Ipv4Addr::new(100, 64, 0, 1),
+            site_subnets: Vec::new(),
             #[cfg(feature = "postquantum")]
             keyshare: KeyShare::default(),
             keepalive_interval: NonZeroDuration::from_std_duration(StdDuration::from_secs(10)),
             keepalive_timeout: NonZeroDuration::from_std_duration(StdDuration::from_secs(60)),
             keepalive_continuous: true,
+            keepalive_adaptive: false,
+            keepalive_min_timeout: NonZeroDuration::from_std_duration(StdDuration::from_secs(15)),
+            keepalive_max_interval: NonZeroDuration::from_std_duration(StdDuration::from_secs(60)),
+            #[cfg(desktop)]
+            keepalive_nat_probe: false,
+            #[cfg(desktop)]
+            keepalive_nat_min_interval: NonZeroDuration::from_std_duration(StdDuration::from_secs(
+                20,
+            )),
+            #[cfg(desktop)]
+            keepalive_nat_max_interval: NonZeroDuration::from_std_duration(StdDuration::from_secs(
+                300,
+            )),
             tracer_packet_timeout: NonZeroDuration::from_std_duration(StdDuration::from_secs(10)),
             preferred_connection_wait_interval: Duration::from_std_duration(
                 StdDuration::from_secs(0),
             ),
+            #[cfg(desktop)]
+            server_selection: ServerSelection::default(),
+            #[cfg(desktop)]
+            server_selection_probes: DEFAULT_SERVER_SELECTION_PROBES,
+            #[cfg(desktop)]
+            server_selection_priority_weight: Duration::from_std_duration(
+                DEFAULT_SERVER_SELECTION_PRIORITY_WEIGHT,
+            ),
+            connection_attempt_delay: Duration::from_std_duration(DEFAULT_CONNECTION_ATTEMPT_DELAY),
             sndbuf: DEFAULT_SNDBUF,
             rcvbuf: DEFAULT_RCVBUF,
             #[cfg(batch_receive)]
             enable_batch_receive: false,
             #[cfg(desktop)]
+            multipath_links: Vec::new(),
+            #[cfg(desktop)]
+            multipath_policy: MultipathPolicy::default(),
+            #[cfg(desktop)]
             route_mode: RouteMode::default(),
             #[cfg(linux)]
             fwmark: 0,
+            #[cfg(linux)]
+            kill_switch: KillSwitchMode::default(),
             #[cfg(desktop)]
             dns_config_mode: DnsConfigMode::default(),
+            dns_proxy: false,
+            dns_proxy_upstream: DnsUpstream::default(),
+            dns_proxy_cache_size: 1024,
+            dns_proxy_blocklist: None,
+            #[cfg(desktop)]
+            socks_proxy: None,
+            #[cfg(desktop)]
+            http_proxy: None,
+            #[cfg(desktop)]
+            reconnect: false,
+            #[cfg(desktop)]
+            reconnect_initial_delay: Duration::from_std_duration(StdDuration::from_secs(1)),
+            #[cfg(desktop)]
+            reconnect_max_delay: Duration::from_std_duration(StdDuration::from_secs(60)),
+            #[cfg(desktop)]
+            reconnect_max_attempts: 0,
+            session_resumption: lightway_core::tls::SESSION_TICKETS_SUPPORTED,
             log_level: LogLevel::Info,
             enable_expresslane: false,
+            udp_migration: true,
             #[cfg(apple)]
             enable_connected_udp: false,
             expresslane_keys_rotation_interval: Duration::from_std_duration(
@@ -551,6 +993,12 @@ impl Default for Config {
             ),
             enable_pmtud: false,
             pmtud_base_mtu: None,
+            obfuscation_key: None,
+            obfuscation_padding: 0,
+            obfuscation_jitter: Duration::from_std_duration(StdDuration::ZERO),
+            websocket: false,
+            websocket_host: None,
+            websocket_path: "/".to_string(),
             enable_tun_iouring: false,
             iouring_entry_count: 1024,
             iouring_sqpoll_idle_time: Duration::from_std_duration(StdDuration::from_millis(100)),
@@ -652,7 +1100,6 @@ impl ConnectionConfig {
 pub enum Error {
     /// Invalid network protocol
     #[error("Invalid network protocol")]
-    #[allow(dead_code)]
     InvalidProtocol,
 
     /// Unable to load certificate
@@ -833,6 +1280,20 @@ mod tests {
         assert!(config.validate().is_ok());
     }
 
+    // Refers to fields gated on different platforms, so that a default
+    // set under the wrong `cfg` fails to build on every platform
+    #[test]
+    fn platform_gated_defaults() {
+        let config = Config::default();
+        assert!(config.udp_migration);
+        #[cfg(apple)]
+        assert!(!config.enable_connected_udp);
+        #[cfg(linux)]
+        assert_eq!(config.fwmark, 0);
+        #[cfg(windows)]
+        assert!(!config.enable_dpapi);
+    }
+
     #[test]
     fn config_unknown_fields_cause_error() {
         let yaml = "unknown_field: true\n";
@@ -908,6 +1369,117 @@ mod tests {
         assert!(logs_contain("127.0.0.1:27690"));
     }
 
+    #[tracing_test::traced_test]
+    #[test]
+    fn validate_websocket_on_udp_server() {
+        let mut config = Config::default();
+        config.server = "127.0.0.1:27690".to_string();
+        config.websocket = true;
+        config.mode = ConnectionType::Udp;
+        assert!(config.validate().is_ok());
+        assert!(logs_contain(
+            "websocket is set but cannot be applied to this UDP connections"
+        ));
+
+        config.websocket_path = "ws".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_session_resumption() {
+        let mut config = Config::default();
+        config.session_resumption = true;
+        assert_eq!(
+            config.validate().is_ok(),
+            lightway_core::tls::SESSION_TICKETS_SUPPORTED
+        );
+
+        config.session_resumption = false;
+        assert!(config.validate().is_ok());
+    }
+
+    #[cfg(desktop)]
+    #[tracing_test::traced_test]
+    #[test]
+    fn validate_multipath_on_tcp_server() {
+        let mut config = Config::default();
+        config.server = "127.0.0.1:27690".to_string();
+        config.multipath_links = vec!["127.0.0.1".to_string()];
+        config.mode = ConnectionType::Tcp;
+        assert!(config.validate().is_ok());
+        assert!(logs_contain(
+            "multipath_links is set but cannot be applied to this TCP connections"
+        ));
+    }
+
+    #[test]
+    fn validate_keepalive_max_interval() {
+        let mut config = Config::default();
+        config.keepalive_adaptive = true;
+        config.keepalive_max_interval =
+            NonZeroDuration::from_std_duration(StdDuration::from_secs(5));
+        assert!(config.validate().is_err());
+        config.keepalive_adaptive = false;
+        assert!(config.validate().is_ok());
+    }
+
+    #[cfg(desktop)]
+    #[test]
+    fn validate_keepalive_nat_intervals() {
+        let mut config = Config::default();
+        config.keepalive_nat_probe = true;
+        config.keepalive_nat_min_interval =
+            NonZeroDuration::from_std_duration(StdDuration::from_secs(600));
+        assert!(config.validate().is_err());
+        config.keepalive_nat_probe = false;
+        assert!(config.validate().is_ok());
+    }
+
+    #[cfg(linux)]
+    #[test]
+    fn validate_policy_route_mode_requires_fwmark() {
+        let mut config = Config::default();
+        config.route_mode = RouteMode::Policy;
+        assert!(config.validate().is_err());
+        config.fwmark = 51820;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_site_subnets() {
+        let mut config = Config::default();
+        config.site_subnets = (0..=MAX_SITE_SUBNETS as u8)
+            .map(|i| Ipv4Net::new(Ipv4Addr::new(192, 168, i, 0), 24).unwrap())
+            .collect();
+        assert!(config.validate().is_err());
+        config.site_subnets.pop();
+        assert!(config.validate().is_ok());
+    }
+
+    #[cfg(desktop)]
+    #[test]
+    fn validate_proxies() {
+        let mut config = Config::default();
+        config.socks_proxy = Some("127.0.0.1:1080".parse().unwrap());
+        config.http_proxy = Some("127.0.0.1:8080".parse().unwrap());
+        assert!(config.validate().is_ok());
+        config.http_proxy = Some("0.0.0.0:8080".parse().unwrap());
+        assert!(config.validate().is_err());
+        config.http_proxy = Some("[::1]:8080".parse().unwrap());
+        assert!(config.validate().is_ok());
+        config.enable_tun_iouring = true;
+        assert!(config.validate().is_err());
+        config.enable_tun_iouring = false;
+        #[cfg(linux)]
+        {
+            config.kill_switch = KillSwitchMode::Enabled;
+            assert!(config.validate().is_err());
+            config.kill_switch = KillSwitchMode::Disabled;
+        }
+        config.site_subnets = vec!["192.168.10.0/24".parse().unwrap()];
+        assert!(config.validate().is_err());
+    }
+
     #[cfg(windows)]
     #[test]
     fn validate_wintun_ring_capacity() {
@@ -1,5 +1,6 @@
 use std::net::IpAddr;
 
+use lightway_app_utils::split_dns::SplitDnsConfig;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -15,6 +16,12 @@ pub enum DnsConfigMode {
     #[default]
     Default,
     NoExec,
+    /// Force the systemd-resolved D-Bus backend
+    #[cfg(linux)]
+    Resolved,
+    /// Force the NetworkManager D-Bus backend
+    #[cfg(linux)]
+    NetworkManager,
 }
 #[derive(Error, Debug)]
 pub enum DnsManagerError {
@@ -45,13 +52,23 @@ pub enum DnsManagerError {
     #[error("DNS has already been configured")]
     #[cfg(any(linux, windows))]
     DnsAlreadyConfigured,
+    #[error("Split DNS is not supported by the DNS backend")]
+    SplitDnsUnsupported,
 }
 
 pub trait DnsSetup {
     /// Set system DNS to the specified server
     fn set_dns(&mut self, dns_server: IpAddr) -> Result<(), DnsManagerError>;
+    /// Route only the domains in `config` to its resolvers, leaving
+    /// the system resolver in place for everything else
+    fn set_split_dns(&mut self, _config: &SplitDnsConfig) -> Result<(), DnsManagerError> {
+        Err(DnsManagerError::SplitDnsUnsupported)
+    }
     /// Clear system DNS configuration
     fn reset_dns(&mut self) -> Result<(), DnsManagerError>;
+    /// Forget the DNS configuration without clearing it, once another
+    /// manager configured the same one over it
+    fn hand_over(&mut self);
 }
 #[derive(Default)]
 pub struct DnsManager {
@@ -67,20 +84,34 @@ impl DnsManager {
     /// Create a DNS manager using `ifindex` on Linux to select the best backend.
     ///
     /// On Linux, auto-detection order:
-    /// 1. `resolvectl` (systemd-resolved) — preferred on modern distros
-    /// 2. `resolvconf` (openresolv)
-    /// 3. Direct `/etc/resolv.conf` manipulation (fallback)
+    /// 1. systemd-resolved over D-Bus (`org.freedesktop.resolve1`)
+    /// 2. `resolvectl` (systemd-resolved) — when D-Bus is unavailable
+    /// 3. NetworkManager over D-Bus, if it manages the interface
+    /// 4. `resolvconf` (openresolv)
+    /// 5. Direct `/etc/resolv.conf` manipulation (fallback)
     #[cfg(linux)]
     pub fn new(ifindex: u32) -> Self {
-        Self {
-            dns_manager: super::platform::linux::dns_manager::DnsManager::new(ifindex),
-        }
+        Self::with_mode(ifindex, DnsConfigMode::Default)
     }
 
     #[cfg(not(linux))]
     pub fn new(_ifindex: u32) -> Self {
         Self::default()
     }
+
+    /// Create a DNS manager for `mode`, which on Linux may force a D-Bus
+    /// backend instead of auto-detecting one.
+    #[cfg(linux)]
+    pub fn with_mode(ifindex: u32, mode: DnsConfigMode) -> Self {
+        Self {
+            dns_manager: super::platform::linux::dns_manager::DnsManager::with_mode(ifindex, mode),
+        }
+    }
+
+    #[cfg(not(linux))]
+    pub fn with_mode(ifindex: u32, _mode: DnsConfigMode) -> Self {
+        Self::new(ifindex)
+    }
 }
 
 impl DnsSetup for DnsManager {
@@ -88,9 +119,17 @@ impl DnsSetup for DnsManager {
         self.dns_manager.set_dns(dns_server)
     }
 
+    fn set_split_dns(&mut self, config: &SplitDnsConfig) -> Result<(), DnsManagerError> {
+        self.dns_manager.set_split_dns(config)
+    }
+
     fn reset_dns(&mut self) -> Result<(), DnsManagerError> {
         self.dns_manager.reset_dns()
     }
+
+    fn hand_over(&mut self) {
+        self.dns_manager.hand_over()
+    }
 }
 
 impl Drop for DnsManager {
@@ -0,0 +1,552 @@
+//! Built-in DNS forwarder.
+//!
+//! Every IPv4 UDP query to port 53 entering the inside path is answered
+//! by the client itself, whatever its destination, so queries sent to
+//! other resolvers by platforms which ignore the interface DNS do not
+//! leak to them. The tunnel is IPv4 only: IPv6 queries never enter the
+//! inside path, and so are not seen by the proxy. Queries are answered
+//! from a cache, with NXDOMAIN for blocked domains, or forwarded to the
+//! [`DnsUpstream`].
+//!
+//! Queries for the [`DnsUpstream::Tunnel`] upstream are rewritten in
+//! place to the server's DNS IP and their responses are mapped back by
+//! [`DnsProxyInsideIO`]. Queries for DoT and DoH upstreams are resolved
+//! by a spawned task, and answers are written to the inside IO by
+//! [`reply_task`].
+
+mod blocklist;
+mod cache;
+mod message;
+mod upstream;
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddrV4};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use bytes::BytesMut;
+use lightway_app_utils::split_dns::SplitDnsConfig;
+use lightway_core::{IOCallbackResult, InsideIOSendCallback, InsideIOSendCallbackArg};
+use pnet_packet::ip::IpNextHeaderProtocols;
+use pnet_packet::ipv4::{self, Ipv4Packet, MutableIpv4Packet};
+use pnet_packet::udp::{self, MutableUdpPacket, UdpPacket};
+use tokio::sync::mpsc;
+
+pub use blocklist::Blocklist;
+pub use upstream::DnsUpstream;
+
+use crate::ConnectionState;
+use crate::io::inside::InsideIO;
+use cache::DnsCache;
+use message::Message;
+use upstream::{HttpsResolver, TlsResolver};
+
+const DNS_PORT: u16 = 53;
+
+/// How long to wait for an upstream answer.
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Bound on queries forwarded over the tunnel and not answered yet.
+const MAX_PENDING_QUERIES: usize = 1024;
+
+const IPV4_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+
+/// DNS proxy configuration.
+#[derive(Clone, Debug, Default)]
+pub struct DnsProxyConfig {
+    /// Where cache misses are sent
+    pub upstream: DnsUpstream,
+    /// Maximum number of cached answers, caching is disabled when `None`
+    pub cache_size: Option<NonZeroUsize>,
+    /// Domains answered with NXDOMAIN
+    pub blocklist: Blocklist,
+}
+
+/// What the inside path must do with a packet after [`DnsProxy::handle_query`].
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum QueryAction {
+    /// Not a DNS query, process as usual
+    Pass,
+    /// Answered by the proxy, drop the packet
+    Consumed,
+    /// Rewritten to the server's DNS IP, send it into the tunnel
+    Forward,
+}
+
+enum Resolver {
+    Tunnel,
+    Tls(TlsResolver),
+    Https(HttpsResolver),
+}
+
+/// A query forwarded over the tunnel, by the id it was sent with.
+struct PendingQuery {
+    id: u16,
+    /// Where the query was originally sent, the answer must come from there
+    destination: Ipv4Addr,
+    sent: Instant,
+}
+
+pub struct DnsProxy {
+    resolver: Resolver,
+    cache: Option<Mutex<DnsCache>>,
+    blocklist: Blocklist,
+    pending: Mutex<HashMap<u16, PendingQuery>>,
+    replies: mpsc::UnboundedSender<BytesMut>,
+}
+
+impl DnsProxy {
+    /// Create the proxy, resolving the DoT/DoH upstream's address with
+    /// the system resolver.
+    ///
+    /// Answers generated by the proxy are delivered on the returned
+    /// receiver, to be handed to [`reply_task`].
+    pub async fn new(
+        config: DnsProxyConfig,
+    ) -> Result<(Arc<Self>, mpsc::UnboundedReceiver<BytesMut>)> {
+        let resolver = match &config.upstream {
+            DnsUpstream::Tunnel => Resolver::Tunnel,
+            DnsUpstream::Tls { host, port } => Resolver::Tls(TlsResolver::new(host, *port).await?),
+            DnsUpstream::Https { host, port, path } => {
+                Resolver::Https(HttpsResolver::new(host, *port, path, UPSTREAM_TIMEOUT).await?)
+            }
+        };
+        tracing::info!(
+            upstream = %config.upstream,
+            cache_size = ?config.cache_size,
+            blocked_domains = config.blocklist.len(),
+            "DNS proxy enabled"
+        );
+        let (replies, replies_rx) = mpsc::unbounded_channel();
+        let proxy = Self {
+            resolver,
+            cache: config
+                .cache_size
+                .map(|size| Mutex::new(DnsCache::new(size))),
+            blocklist: config.blocklist,
+            pending: Mutex::new(HashMap::new()),
+            replies,
+        };
+        Ok((Arc::new(proxy), replies_rx))
+    }
+
+    /// Handle a packet read from the inside IO, before any address
+    /// translation. `dns_ip` is the server's DNS IP, queries to the
+    /// resolvers of `split_dns` are left alone.
+    pub(crate) fn handle_query(
+        self: &Arc<Self>,
+        buf: &mut BytesMut,
+        dns_ip: Ipv4Addr,
+        split_dns: Option<&SplitDnsConfig>,
+    ) -> QueryAction {
+        let Some((src, dst, payload)) = parse_udp(buf) else {
+            return QueryAction::Pass;
+        };
+        if dst.port() != DNS_PORT
+            || split_dns.is_some_and(|c| c.servers().contains(&IpAddr::V4(*dst.ip())))
+        {
+            return QueryAction::Pass;
+        }
+        let Some(query) = Message::parse(payload).filter(Message::is_query) else {
+            // Anything the proxy does not understand goes to the server as is
+            return QueryAction::Pass;
+        };
+
+        if self.blocklist.is_blocked(&query.question.name) {
+            tracing::debug!(name = query.question.name, "DNS query blocked");
+            self.reply(dst, src, &query.nxdomain());
+            return QueryAction::Consumed;
+        }
+
+        if let Some(cache) = &self.cache
+            && let Some(response) = cache.lock().unwrap().get(&query, Instant::now())
+        {
+            self.reply(dst, src, &response);
+            return QueryAction::Consumed;
+        }
+
+        match &self.resolver {
+            Resolver::Tunnel => {
+                let Some(id) = self.add_pending(query.id(), *dst.ip()) else {
+                    return QueryAction::Pass;
+                };
+                let mut forwarded = payload.to_vec();
+                message::set_id(&mut forwarded, id);
+                *buf = udp_packet(src, SocketAddrV4::new(dns_ip, DNS_PORT), &forwarded);
+                QueryAction::Forward
+            }
+            Resolver::Tls(_) | Resolver::Https(_) => {
+                let proxy = self.clone();
+                let query = payload.to_vec();
+                tokio::spawn(async move { proxy.resolve(query, src, dst).await });
+                QueryAction::Consumed
+            }
+        }
+    }
+
+    /// Handle a packet received from the tunnel, mapping answers to
+    /// queries forwarded by [`Self::handle_query`] back to the original
+    /// query.
+    pub(crate) fn handle_response(&self, buf: &mut BytesMut, dns_ip: Ipv4Addr) {
+        let Some((src, dst, payload)) = parse_udp(buf) else {
+            return;
+        };
+        if src != SocketAddrV4::new(dns_ip, DNS_PORT) {
+            return;
+        }
+        let Some(response) = Message::parse(payload).filter(Message::is_response) else {
+            return;
+        };
+        let Some(pending) = self.pending.lock().unwrap().remove(&response.id()) else {
+            return;
+        };
+        self.cache_response(&response);
+
+        let mut answer = payload.to_vec();
+        message::set_id(&mut answer, pending.id);
+        *buf = udp_packet(
+            SocketAddrV4::new(pending.destination, DNS_PORT),
+            dst,
+            &answer,
+        );
+    }
+
+    /// Remember a query forwarded over the tunnel, returning the id to
+    /// send it with, or `None` if too many queries are outstanding.
+    fn add_pending(&self, id: u16, destination: Ipv4Addr) -> Option<u16> {
+        let now = Instant::now();
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, query| now.duration_since(query.sent) < UPSTREAM_TIMEOUT);
+        if pending.len() >= MAX_PENDING_QUERIES {
+            tracing::warn!("Too many outstanding DNS queries, bypassing the DNS proxy");
+            return None;
+        }
+        // Queries from different sources may share an id
+        let forwarded_id =
+            std::iter::repeat_with(rand::random::<u16>).find(|id| !pending.contains_key(id))?;
+        pending.insert(
+            forwarded_id,
+            PendingQuery {
+                id,
+                destination,
+                sent: now,
+            },
+        );
+        Some(forwarded_id)
+    }
+
+    async fn resolve(&self, query: Vec<u8>, client: SocketAddrV4, server: SocketAddrV4) {
+        let result = match &self.resolver {
+            Resolver::Tunnel => unreachable!("tunnel queries are forwarded by the inside path"),
+            Resolver::Tls(tls) => tokio::time::timeout(UPSTREAM_TIMEOUT, tls.resolve(&query)).await,
+            Resolver::Https(https) => {
+                tokio::time::timeout(UPSTREAM_TIMEOUT, https.resolve(&query)).await
+            }
+        };
+        // `handle_query` only spawns for valid queries
+        let query = Message::parse(&query).expect("query was parsed before");
+        let response = match result {
+            Ok(Ok(response)) => match Message::parse(&response) {
+                Some(parsed) if parsed.id() == query.id() && parsed.question == query.question => {
+                    self.cache_response(&parsed);
+                    response
+                }
+                _ => {
+                    tracing::warn!("Mismatched answer from DNS upstream");
+                    query.servfail()
+                }
+            },
+            Ok(Err(e)) => {
+                tracing::warn!("DNS upstream query failed: {e}");
+                query.servfail()
+            }
+            Err(_) => {
+                tracing::warn!("DNS upstream query timed out");
+                query.servfail()
+            }
+        };
+        self.reply(server, client, &response);
+    }
+
+    fn cache_response(&self, response: &Message<'_>) {
+        if let Some(cache) = &self.cache {
+            cache.lock().unwrap().insert(response, Instant::now());
+        }
+    }
+
+    fn reply(&self, src: SocketAddrV4, dst: SocketAddrV4, response: &[u8]) {
+        // Only fails once the reply task is gone, i.e. on shutdown
+        let _ = self.replies.send(udp_packet(src, dst, response));
+    }
+}
+
+/// Write answers generated by the proxy to the inside IO.
+pub async fn reply_task<ExtAppState: Send + Sync>(
+    mut replies: mpsc::UnboundedReceiver<BytesMut>,
+    inside_io: Arc<dyn InsideIO<ExtAppState>>,
+) {
+    while let Some(reply) = replies.recv().await {
+        if let Err(e) = inside_io.try_send(reply, None) {
+            tracing::debug!("Failed to send DNS proxy answer: {e}");
+        }
+    }
+}
+
+/// Inside IO send callback wrapper which hands every packet from the
+/// tunnel to [`DnsProxy::handle_response`] first.
+pub(crate) struct DnsProxyInsideIO<AppState> {
+    inner: InsideIOSendCallbackArg<AppState>,
+    proxy: Arc<DnsProxy>,
+}
+
+impl<AppState> DnsProxyInsideIO<AppState> {
+    pub(crate) fn new(inner: InsideIOSendCallbackArg<AppState>, proxy: Arc<DnsProxy>) -> Self {
+        Self { inner, proxy }
+    }
+}
+
+impl<ExtAppState: Send + Sync> InsideIOSendCallback<ConnectionState<ExtAppState>>
+    for DnsProxyInsideIO<ConnectionState<ExtAppState>>
+{
+    fn send(
+        &self,
+        mut buf: BytesMut,
+        state: &mut ConnectionState<ExtAppState>,
+    ) -> IOCallbackResult<usize> {
+        if let Some(ip_config) = state.ip_config {
+            self.proxy.handle_response(&mut buf, ip_config.dns_ip);
+        }
+        self.inner.send(buf, state)
+    }
+
+    fn mtu(&self) -> usize {
+        self.inner.mtu()
+    }
+
+    fn if_index(&self) -> std::io::Result<u32> {
+        self.inner.if_index()
+    }
+
+    fn name(&self) -> std::io::Result<String> {
+        self.inner.name()
+    }
+}
+
+/// Addresses and payload of an unfragmented IPv4 UDP packet.
+fn parse_udp(buf: &[u8]) -> Option<(SocketAddrV4, SocketAddrV4, &[u8])> {
+    let ip = Ipv4Packet::new(buf)?;
+    if ip.get_version() != 4
+        || ip.get_next_level_protocol() != IpNextHeaderProtocols::Udp
+        || ip.get_fragment_offset() != 0
+        || ip.get_flags() & ipv4::Ipv4Flags::MoreFragments != 0
+    {
+        return None;
+    }
+    let header_len = usize::from(ip.get_header_length()) * 4;
+    let total_len = usize::from(ip.get_total_length()).min(buf.len());
+    let udp = UdpPacket::new(buf.get(header_len..total_len)?)?;
+    let udp_len = usize::from(udp.get_length());
+    let payload = buf.get(header_len + UDP_HEADER_LEN..header_len + udp_len)?;
+    Some((
+        SocketAddrV4::new(ip.get_source(), udp.get_source()),
+        SocketAddrV4::new(ip.get_destination(), udp.get_destination()),
+        payload,
+    ))
+}
+
+/// Build an IPv4 UDP packet, checksums included.
+fn udp_packet(src: SocketAddrV4, dst: SocketAddrV4, payload: &[u8]) -> BytesMut {
+    let udp_len = UDP_HEADER_LEN + payload.len();
+    let total_len = IPV4_HEADER_LEN + udp_len;
+    let mut buf = BytesMut::zeroed(total_len);
+
+    let mut udp = MutableUdpPacket::new(&mut buf[IPV4_HEADER_LEN..]).expect("buffer fits header");
+    udp.set_source(src.port());
+    udp.set_destination(dst.port());
+    udp.set_length(udp_len as u16);
+    udp.set_payload(payload);
+    let checksum = udp::ipv4_checksum(&udp.to_immutable(), src.ip(), dst.ip());
+    udp.set_checksum(checksum);
+
+    let mut ip = MutableIpv4Packet::new(&mut buf).expect("buffer fits header");
+    ip.set_version(4);
+    ip.set_header_length((IPV4_HEADER_LEN / 4) as u8);
+    ip.set_total_length(total_len as u16);
+    ip.set_ttl(64);
+    ip.set_next_level_protocol(IpNextHeaderProtocols::Udp);
+    ip.set_source(*src.ip());
+    ip.set_destination(*dst.ip());
+    let checksum = ipv4::checksum(&ip.to_immutable());
+    ip.set_checksum(checksum);
+
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use message::tests::{TYPE_A, query, response};
+    use pnet_packet::Packet;
+
+    const TUN_LOCAL: Ipv4Addr = Ipv4Addr::new(100, 64, 0, 6);
+    const TUN_DNS: Ipv4Addr = Ipv4Addr::new(100, 64, 0, 1);
+    const SERVER_DNS: Ipv4Addr = Ipv4Addr::new(10, 125, 0, 1);
+    const CLIENT: SocketAddrV4 = SocketAddrV4::new(TUN_LOCAL, 40000);
+
+    async fn proxy(
+        cache_size: usize,
+        blocklist: &str,
+    ) -> (Arc<DnsProxy>, mpsc::UnboundedReceiver<BytesMut>) {
+        DnsProxy::new(DnsProxyConfig {
+            upstream: DnsUpstream::Tunnel,
+            cache_size: NonZeroUsize::new(cache_size),
+            blocklist: Blocklist::parse(blocklist),
+        })
+        .await
+        .unwrap()
+    }
+
+    fn payload(buf: &[u8]) -> Vec<u8> {
+        parse_udp(buf).unwrap().2.to_vec()
+    }
+
+    #[test]
+    fn udp_packet_round_trip() {
+        let dst = SocketAddrV4::new(TUN_DNS, DNS_PORT);
+        let buf = udp_packet(CLIENT, dst, b"hello");
+        let ip = Ipv4Packet::new(&buf).unwrap();
+        assert_eq!(ip.get_checksum(), ipv4::checksum(&ip));
+        let udp = UdpPacket::new(ip.payload()).unwrap();
+        assert_eq!(
+            udp.get_checksum(),
+            udp::ipv4_checksum(&udp, CLIENT.ip(), &TUN_DNS)
+        );
+        assert_eq!(parse_udp(&buf), Some((CLIENT, dst, &b"hello"[..])));
+    }
+
+    #[tokio::test]
+    async fn non_dns_packets_pass() {
+        let (proxy, _) = proxy(16, "").await;
+        let mut buf = udp_packet(CLIENT, SocketAddrV4::new(TUN_DNS, 443), b"quic");
+        assert_eq!(
+            proxy.handle_query(&mut buf, SERVER_DNS, None),
+            QueryAction::Pass
+        );
+
+        let mut buf = udp_packet(CLIENT, SocketAddrV4::new(TUN_DNS, DNS_PORT), b"junk");
+        assert_eq!(
+            proxy.handle_query(&mut buf, SERVER_DNS, None),
+            QueryAction::Pass
+        );
+    }
+
+    #[tokio::test]
+    async fn blocked_domain_gets_nxdomain() {
+        let (proxy, mut replies) = proxy(16, "ads.example").await;
+        let dst = SocketAddrV4::new(TUN_DNS, DNS_PORT);
+        let mut buf = udp_packet(CLIENT, dst, &query(7, "x.ads.example", TYPE_A));
+        assert_eq!(
+            proxy.handle_query(&mut buf, SERVER_DNS, None),
+            QueryAction::Consumed
+        );
+
+        let reply = replies.try_recv().unwrap();
+        let (src, to, answer) = parse_udp(&reply).unwrap();
+        assert_eq!((src, to), (dst, CLIENT));
+        let answer = Message::parse(answer).unwrap();
+        assert_eq!(answer.id(), 7);
+        assert_eq!(answer.rcode(), 3);
+    }
+
+    #[tokio::test]
+    async fn tunnel_forward_and_cache() {
+        let (proxy, mut replies) = proxy(16, "").await;
+        // A query to a resolver other than the TUN DNS IP is intercepted too
+        let public_dns = SocketAddrV4::new(Ipv4Addr::new(8, 8, 8, 8), DNS_PORT);
+        let q = query(7, "example.com", TYPE_A);
+        let mut buf = udp_packet(CLIENT, public_dns, &q);
+        assert_eq!(
+            proxy.handle_query(&mut buf, SERVER_DNS, None),
+            QueryAction::Forward
+        );
+
+        let (src, dst, forwarded) = parse_udp(&buf).unwrap();
+        assert_eq!(src, CLIENT);
+        assert_eq!(dst, SocketAddrV4::new(SERVER_DNS, DNS_PORT));
+        let forwarded_id = Message::parse(forwarded).unwrap().id();
+
+        // The answer as received from the tunnel
+        let mut answer = response(forwarded, &[300]);
+        message::set_id(&mut answer, forwarded_id);
+        let mut buf = udp_packet(SocketAddrV4::new(SERVER_DNS, DNS_PORT), CLIENT, &answer);
+        proxy.handle_response(&mut buf, SERVER_DNS);
+        let (src, dst, delivered) = parse_udp(&buf).unwrap();
+        assert_eq!((src, dst), (public_dns, CLIENT));
+        assert_eq!(Message::parse(delivered).unwrap().id(), 7);
+
+        // Now answered from the cache
+        let mut buf = udp_packet(CLIENT, public_dns, &query(8, "example.com", TYPE_A));
+        assert_eq!(
+            proxy.handle_query(&mut buf, SERVER_DNS, None),
+            QueryAction::Consumed
+        );
+        let cached = replies.try_recv().unwrap();
+        assert_eq!(Message::parse(&payload(&cached)).unwrap().id(), 8);
+    }
+
+    #[tokio::test]
+    async fn split_dns_resolvers_are_bypassed() {
+        use lightway_app_utils::split_dns::DomainResolver;
+
+        let (proxy, _) = proxy(16, "").await;
+        let corp_dns = Ipv4Addr::new(10, 0, 0, 53);
+        let split_dns = SplitDnsConfig {
+            resolvers: vec![DomainResolver {
+                domain: "corp.example".to_string(),
+                servers: vec![IpAddr::V4(corp_dns)],
+            }],
+            search_domains: vec![],
+        };
+        let q = query(7, "intranet.corp.example", TYPE_A);
+        let mut buf = udp_packet(CLIENT, SocketAddrV4::new(corp_dns, DNS_PORT), &q);
+        assert_eq!(
+            proxy.handle_query(&mut buf, SERVER_DNS, Some(&split_dns)),
+            QueryAction::Pass
+        );
+    }
+
+    #[tokio::test]
+    async fn unsolicited_response_is_untouched() {
+        let (proxy, _) = proxy(16, "").await;
+        let answer = response(&query(9, "example.com", TYPE_A), &[300]);
+        let mut buf = udp_packet(SocketAddrV4::new(SERVER_DNS, DNS_PORT), CLIENT, &answer);
+        let before = buf.clone();
+        proxy.handle_response(&mut buf, SERVER_DNS);
+        assert_eq!(buf, before);
+    }
+
+    #[tokio::test]
+    async fn caching_can_be_disabled() {
+        let (proxy, mut replies) = proxy(0, "").await;
+        let dst = SocketAddrV4::new(TUN_DNS, DNS_PORT);
+        let q = query(7, "example.com", TYPE_A);
+        for _ in 0..2 {
+            let mut buf = udp_packet(CLIENT, dst, &q);
+            assert_eq!(
+                proxy.handle_query(&mut buf, SERVER_DNS, None),
+                QueryAction::Forward
+            );
+            let forwarded = payload(&buf);
+            let mut buf = udp_packet(
+                SocketAddrV4::new(SERVER_DNS, DNS_PORT),
+                CLIENT,
+                &response(&forwarded, &[300]),
+            );
+            proxy.handle_response(&mut buf, SERVER_DNS);
+        }
+        assert!(replies.try_recv().is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+/// Domains the proxy answers with NXDOMAIN, including their subdomains.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Blocklist {
+    domains: HashSet<String>,
+}
+
+// Lists can hold hundreds of thousands of domains, keep logs readable
+impl fmt::Debug for Blocklist {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Blocklist")
+            .field("domains", &self.domains.len())
+            .finish()
+    }
+}
+
+impl Blocklist {
+    /// Load a block list file, see [`Self::parse`].
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read DNS block list {}", path.display()))?;
+        Ok(Self::parse(&content))
+    }
+
+    /// Parse one domain per line. `#` starts a comment, and hosts file
+    /// style lines (`0.0.0.0 ads.example`) are accepted as well.
+    pub fn parse(content: &str) -> Self {
+        let domains = content
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default())
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let first = fields.next()?;
+                Some(fields.next().unwrap_or(first))
+            })
+            .map(|domain| domain.trim_end_matches('.').to_ascii_lowercase())
+            .filter(|domain| !domain.is_empty())
+            .collect();
+        Self { domains }
+    }
+
+    pub fn len(&self) -> usize {
+        self.domains.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
+
+    /// Whether `name` (lowercase, without trailing `.`) or one of its
+    /// parent domains is blocked.
+    pub fn is_blocked(&self, name: &str) -> bool {
+        if self.domains.is_empty() {
+            return false;
+        }
+        let mut name = name;
+        loop {
+            if self.domains.contains(name) {
+                return true;
+            }
+            match name.split_once('.') {
+                Some((_, parent)) => name = parent,
+                None => return false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    const LIST: &str = r#"
+# Ads
+ads.example
+Tracker.Example.  # trailing comment
+0.0.0.0 telemetry.example
+"#;
+
+    #[test_case("ads.example" => true; "exact")]
+    #[test_case("cdn.ads.example" => true; "subdomain")]
+    #[test_case("tracker.example" => true; "normalized")]
+    #[test_case("telemetry.example" => true; "hosts format")]
+    #[test_case("example" => false; "parent")]
+    #[test_case("badads.example" => false; "suffix without dot")]
+    #[test_case("0.0.0.0" => false; "hosts address")]
+    fn is_blocked(name: &str) -> bool {
+        Blocklist::parse(LIST).is_blocked(name)
+    }
+
+    #[test]
+    fn parse_skips_comments_and_blank_lines() {
+        assert_eq!(Blocklist::parse(LIST).len(), 3);
+    }
+}
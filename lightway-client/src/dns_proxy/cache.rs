@@ -0,0 +1,158 @@
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+
+use lru::LruCache;
+
+use super::message::{self, Message, Question};
+
+/// Upper bound for how long an answer is cached, whatever its TTL.
+const MAX_TTL: u32 = 24 * 60 * 60;
+
+struct Entry {
+    response: Vec<u8>,
+    ttl_offsets: Vec<usize>,
+    inserted: Instant,
+    expires: Instant,
+}
+
+/// LRU cache of upstream responses, honouring record TTLs.
+pub(super) struct DnsCache {
+    entries: LruCache<Question, Entry>,
+}
+
+impl DnsCache {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            entries: LruCache::new(capacity),
+        }
+    }
+
+    /// Cache `response` if it is cacheable and has a non-zero TTL.
+    pub fn insert(&mut self, response: &Message<'_>, now: Instant) {
+        if !response.is_cacheable() {
+            return;
+        }
+        let Some(ttl_offsets) = response.ttl_offsets() else {
+            return;
+        };
+        // Negative answers without an SOA record have no TTL to honour
+        let Some(ttl) = response.min_ttl().map(|ttl| ttl.min(MAX_TTL)) else {
+            return;
+        };
+        if ttl == 0 {
+            return;
+        }
+        self.entries.put(
+            response.question.clone(),
+            Entry {
+                response: response.bytes.to_vec(),
+                ttl_offsets,
+                inserted: now,
+                expires: now + Duration::from_secs(ttl.into()),
+            },
+        );
+    }
+
+    /// Cached answer for `query`, with its id and question copied from
+    /// the query and its TTLs reduced by the time spent in the cache.
+    pub fn get(&mut self, query: &Message<'_>, now: Instant) -> Option<Vec<u8>> {
+        let entry = self.entries.get(&query.question)?;
+        if entry.expires <= now {
+            self.entries.pop(&query.question);
+            return None;
+        }
+        let mut response = entry.response.clone();
+        let elapsed = now.duration_since(entry.inserted).as_secs() as u32;
+        message::age_ttls(&mut response, &entry.ttl_offsets, elapsed);
+        // Same name, so the same length: keeps the query's id and the
+        // letter case it asked with (DNS 0x20)
+        response[..query.question_end].copy_from_slice(&query.bytes[..query.question_end]);
+        // ... but the response's flags and counts
+        response[2..message::HEADER_LEN].copy_from_slice(&entry.response[2..message::HEADER_LEN]);
+        Some(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns_proxy::message::tests::{TYPE_A, query, response};
+
+    fn cache() -> DnsCache {
+        DnsCache::new(NonZeroUsize::new(2).unwrap())
+    }
+
+    #[test]
+    fn hit_uses_query_id_and_ages_ttls() {
+        let mut cache = cache();
+        let now = Instant::now();
+        let upstream = response(&query(1, "example.com", TYPE_A), &[300]);
+        cache.insert(&Message::parse(&upstream).unwrap(), now);
+
+        let q = query(2, "Example.COM", TYPE_A);
+        let hit = cache
+            .get(&Message::parse(&q).unwrap(), now + Duration::from_secs(100))
+            .unwrap();
+        let hit = Message::parse(&hit).unwrap();
+        assert_eq!(hit.id(), 2);
+        assert!(hit.is_response());
+        assert_eq!(hit.min_ttl(), Some(200));
+        assert_eq!(&hit.bytes[13..20], b"Example");
+    }
+
+    #[test]
+    fn miss_after_expiry() {
+        let mut cache = cache();
+        let now = Instant::now();
+        let q = query(1, "example.com", TYPE_A);
+        cache.insert(&Message::parse(&response(&q, &[60])).unwrap(), now);
+
+        let q = Message::parse(&q).unwrap();
+        assert!(cache.get(&q, now + Duration::from_secs(59)).is_some());
+        assert!(cache.get(&q, now + Duration::from_secs(60)).is_none());
+    }
+
+    #[test]
+    fn different_type_misses() {
+        let mut cache = cache();
+        let now = Instant::now();
+        let q = query(1, "example.com", TYPE_A);
+        cache.insert(&Message::parse(&response(&q, &[60])).unwrap(), now);
+
+        let aaaa = query(1, "example.com", 28);
+        assert!(cache.get(&Message::parse(&aaaa).unwrap(), now).is_none());
+    }
+
+    #[test]
+    fn zero_ttl_and_empty_answers_are_not_cached() {
+        let mut cache = cache();
+        let now = Instant::now();
+        let q = query(1, "example.com", TYPE_A);
+        cache.insert(&Message::parse(&response(&q, &[0])).unwrap(), now);
+        cache.insert(&Message::parse(&response(&q, &[])).unwrap(), now);
+        assert!(cache.get(&Message::parse(&q).unwrap(), now).is_none());
+    }
+
+    #[test]
+    fn least_recently_used_is_evicted() {
+        let mut cache = cache();
+        let now = Instant::now();
+        let queries: Vec<_> = ["a.example", "b.example", "c.example"]
+            .iter()
+            .map(|name| query(1, name, TYPE_A))
+            .collect();
+        for q in &queries {
+            cache.insert(&Message::parse(&response(q, &[60])).unwrap(), now);
+        }
+        assert!(
+            cache
+                .get(&Message::parse(&queries[0]).unwrap(), now)
+                .is_none()
+        );
+        assert!(
+            cache
+                .get(&Message::parse(&queries[2]).unwrap(), now)
+                .is_some()
+        );
+    }
+}
@@ -0,0 +1,305 @@
+//! Just enough of the DNS wire format (RFC 1035) for the proxy: the
+//! header, a single question and the TTLs of resource records.
+
+pub(super) const HEADER_LEN: usize = 12;
+
+/// Maximum length of a domain name in presentation format
+const MAX_NAME_LEN: usize = 255;
+
+const RCODE_SERVFAIL: u8 = 2;
+const RCODE_NXDOMAIN: u8 = 3;
+
+/// EDNS(0) pseudo record, its "TTL" carries flags.
+const TYPE_OPT: u16 = 41;
+
+/// The question a query asks, used as cache key.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(super) struct Question {
+    /// Lowercase name without trailing `.`, empty for the root
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+}
+
+/// A message with exactly one question, as sent by stub resolvers.
+#[derive(Debug)]
+pub(super) struct Message<'a> {
+    pub bytes: &'a [u8],
+    pub question: Question,
+    /// Offset of the first byte after the question section
+    pub question_end: usize,
+}
+
+impl<'a> Message<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Option<Self> {
+        if bytes.len() < HEADER_LEN || u16_at(bytes, 4) != 1 {
+            return None;
+        }
+        let (name, name_end) = parse_name(bytes, HEADER_LEN)?;
+        let question_end = name_end + 4;
+        if bytes.len() < question_end {
+            return None;
+        }
+        Some(Self {
+            bytes,
+            question: Question {
+                name,
+                qtype: u16_at(bytes, name_end),
+                qclass: u16_at(bytes, name_end + 2),
+            },
+            question_end,
+        })
+    }
+
+    pub fn id(&self) -> u16 {
+        u16_at(self.bytes, 0)
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.bytes[2] & 0x80 != 0
+    }
+
+    /// Standard query (opcode 0)
+    pub fn is_query(&self) -> bool {
+        !self.is_response() && (self.bytes[2] >> 3) & 0x0f == 0
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.bytes[2] & 0x02 != 0
+    }
+
+    pub fn rcode(&self) -> u8 {
+        self.bytes[3] & 0x0f
+    }
+
+    /// Whether the answer may be cached: successful or authoritative
+    /// negative answers only, never truncated ones.
+    pub fn is_cacheable(&self) -> bool {
+        self.is_response() && !self.is_truncated() && matches!(self.rcode(), 0 | RCODE_NXDOMAIN)
+    }
+
+    /// Offsets of the TTL field of every record after the question,
+    /// `None` if the message is malformed.
+    pub fn ttl_offsets(&self) -> Option<Vec<usize>> {
+        let records = (6..12)
+            .step_by(2)
+            .map(|at| u16_at(self.bytes, at) as usize)
+            .sum::<usize>();
+        let mut offsets = Vec::with_capacity(records);
+        let mut pos = self.question_end;
+        for _ in 0..records {
+            pos = skip_name(self.bytes, pos)?;
+            // type, class, ttl, rdlength
+            if self.bytes.len() < pos + 10 {
+                return None;
+            }
+            if u16_at(self.bytes, pos) != TYPE_OPT {
+                offsets.push(pos + 4);
+            }
+            let rdlength = u16_at(self.bytes, pos + 8) as usize;
+            pos += 10 + rdlength;
+            if self.bytes.len() < pos {
+                return None;
+            }
+        }
+        Some(offsets)
+    }
+
+    /// Smallest TTL of all records, `None` if there are no records.
+    pub fn min_ttl(&self) -> Option<u32> {
+        self.ttl_offsets()?
+            .into_iter()
+            .map(|at| u32_at(self.bytes, at))
+            .min()
+    }
+
+    /// Answer this query with NXDOMAIN.
+    pub fn nxdomain(&self) -> Vec<u8> {
+        self.error_response(RCODE_NXDOMAIN)
+    }
+
+    /// Answer this query with SERVFAIL.
+    pub fn servfail(&self) -> Vec<u8> {
+        self.error_response(RCODE_SERVFAIL)
+    }
+
+    fn error_response(&self, rcode: u8) -> Vec<u8> {
+        let mut response = self.bytes[..self.question_end].to_vec();
+        // QR, keep opcode and RD
+        response[2] = 0x80 | (self.bytes[2] & 0x79);
+        // RA
+        response[3] = 0x80 | rcode;
+        // No answer, authority or additional records
+        response[6..HEADER_LEN].fill(0);
+        response
+    }
+}
+
+pub(super) fn set_id(bytes: &mut [u8], id: u16) {
+    bytes[..2].copy_from_slice(&id.to_be_bytes());
+}
+
+/// Decrease every TTL in `bytes` by `elapsed` seconds.
+pub(super) fn age_ttls(bytes: &mut [u8], offsets: &[usize], elapsed: u32) {
+    for &at in offsets {
+        let ttl = u32_at(bytes, at).saturating_sub(elapsed);
+        bytes[at..at + 4].copy_from_slice(&ttl.to_be_bytes());
+    }
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// Parse an uncompressed name, as found in the question section.
+fn parse_name(bytes: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    loop {
+        let len = *bytes.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        // Compression pointers or reserved label types
+        if len > 63 {
+            return None;
+        }
+        let label = bytes.get(pos..pos + len)?;
+        pos += len;
+        if !name.is_empty() {
+            name.push('.');
+        }
+        name.extend(label.iter().map(|b| b.to_ascii_lowercase() as char));
+        if name.len() > MAX_NAME_LEN {
+            return None;
+        }
+    }
+    Some((name, pos))
+}
+
+/// Skip a possibly compressed name.
+fn skip_name(bytes: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *bytes.get(pos)?;
+        match len {
+            0 => return Some(pos + 1),
+            len if len & 0xc0 == 0xc0 => return Some(pos + 2),
+            len if len > 63 => return None,
+            len => pos += 1 + len as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    pub(crate) const TYPE_A: u16 = 1;
+
+    /// A recursive query for `name`
+    pub(crate) fn query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&id.to_be_bytes());
+        // RD
+        msg.extend_from_slice(&[0x01, 0x00]);
+        msg.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+        for label in name.split('.').filter(|l| !l.is_empty()) {
+            msg.push(label.len() as u8);
+            msg.extend_from_slice(label.as_bytes());
+        }
+        msg.push(0);
+        msg.extend_from_slice(&qtype.to_be_bytes());
+        msg.extend_from_slice(&1u16.to_be_bytes());
+        msg
+    }
+
+    /// Response to `query` with one A record per TTL, using compressed names
+    pub(crate) fn response(query: &[u8], ttls: &[u32]) -> Vec<u8> {
+        let mut msg = query.to_vec();
+        msg[2] = 0x81;
+        msg[3] = 0x80;
+        msg[6..8].copy_from_slice(&(ttls.len() as u16).to_be_bytes());
+        for ttl in ttls {
+            // Pointer to the question name
+            msg.extend_from_slice(&[0xc0, HEADER_LEN as u8]);
+            msg.extend_from_slice(&TYPE_A.to_be_bytes());
+            msg.extend_from_slice(&1u16.to_be_bytes());
+            msg.extend_from_slice(&ttl.to_be_bytes());
+            msg.extend_from_slice(&4u16.to_be_bytes());
+            msg.extend_from_slice(&[192, 0, 2, 1]);
+        }
+        msg
+    }
+
+    #[test]
+    fn parse_query() {
+        let bytes = query(0x1234, "WWW.Example.com", TYPE_A);
+        let msg = Message::parse(&bytes).unwrap();
+        assert_eq!(msg.id(), 0x1234);
+        assert!(msg.is_query());
+        assert_eq!(
+            msg.question,
+            Question {
+                name: "www.example.com".to_string(),
+                qtype: TYPE_A,
+                qclass: 1,
+            }
+        );
+        assert_eq!(msg.question_end, bytes.len());
+    }
+
+    #[test_case(&[0; 4]; "short header")]
+    #[test_case(&[0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]; "no question")]
+    #[test_case(&[0, 0, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 3, b'c', b'o']; "truncated name")]
+    #[test_case(&[0, 0, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0xc0, 12, 0, 1, 0, 1]; "compressed question")]
+    fn parse_rejects_malformed(bytes: &[u8]) {
+        assert!(Message::parse(bytes).is_none());
+    }
+
+    #[test]
+    fn min_ttl_and_aging() {
+        let mut bytes = response(&query(1, "example.com", TYPE_A), &[300, 60, 120]);
+        let msg = Message::parse(&bytes).unwrap();
+        assert!(msg.is_cacheable());
+        assert_eq!(msg.min_ttl(), Some(60));
+
+        let offsets = msg.ttl_offsets().unwrap();
+        age_ttls(&mut bytes, &offsets, 100);
+        let msg = Message::parse(&bytes).unwrap();
+        assert_eq!(msg.min_ttl(), Some(0));
+        assert_eq!(u32_at(&bytes, offsets[0]), 200);
+    }
+
+    #[test]
+    fn ttl_offsets_rejects_truncated_records() {
+        let mut bytes = response(&query(1, "example.com", TYPE_A), &[300]);
+        bytes.truncate(bytes.len() - 2);
+        assert!(Message::parse(&bytes).unwrap().ttl_offsets().is_none());
+    }
+
+    #[test]
+    fn nxdomain_response() {
+        let bytes = query(0x4242, "ads.example", TYPE_A);
+        let response = Message::parse(&bytes).unwrap().nxdomain();
+        let msg = Message::parse(&response).unwrap();
+        assert!(msg.is_response());
+        assert_eq!(msg.id(), 0x4242);
+        assert_eq!(msg.rcode(), RCODE_NXDOMAIN);
+        assert_eq!(msg.min_ttl(), None);
+        // RD is kept, RA is set
+        assert_eq!(response[2] & 0x01, 0x01);
+        assert_eq!(response[3] & 0x80, 0x80);
+    }
+
+    #[test]
+    fn truncated_response_is_not_cacheable() {
+        let mut bytes = response(&query(1, "example.com", TYPE_A), &[300]);
+        bytes[2] |= 0x02;
+        assert!(!Message::parse(&bytes).unwrap().is_cacheable());
+    }
+}
@@ -0,0 +1,281 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{self, RootCertStore};
+
+const DOT_PORT: u16 = 853;
+const DOH_PORT: u16 = 443;
+
+/// Where the DNS proxy forwards queries it cannot answer itself.
+///
+/// Formats:
+///   `tunnel`                         Server DNS, over the tunnel
+///   `tls://<host>[:<port>]`          DNS over TLS (RFC 7858)
+///   `https://<host>[:<port>]/<path>` DNS over HTTPS (RFC 8484)
+///
+/// DoT and DoH connections are made from the client itself and so are
+/// routed over the tunnel like any other traffic. `<host>` is resolved
+/// once on startup, before the system DNS points at the proxy.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum DnsUpstream {
+    #[default]
+    Tunnel,
+    Tls {
+        host: String,
+        port: u16,
+    },
+    Https {
+        host: String,
+        port: u16,
+        path: String,
+    },
+}
+
+impl FromStr for DnsUpstream {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "tunnel" {
+            return Ok(Self::Tunnel);
+        }
+        if let Some(rest) = s.strip_prefix("tls://") {
+            let (host, port) = parse_authority(rest, DOT_PORT)?;
+            return Ok(Self::Tls { host, port });
+        }
+        if let Some(rest) = s.strip_prefix("https://") {
+            let (authority, path) = match rest.find('/') {
+                Some(at) => rest.split_at(at),
+                None => bail!("DNS over HTTPS upstream needs a path, e.g. /dns-query"),
+            };
+            let (host, port) = parse_authority(authority, DOH_PORT)?;
+            return Ok(Self::Https {
+                host,
+                port,
+                path: path.to_string(),
+            });
+        }
+        Err(anyhow!(
+            "Invalid DNS upstream {s:?}, expected `tunnel`, `tls://...` or `https://...`"
+        ))
+    }
+}
+
+impl TryFrom<String> for DnsUpstream {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<DnsUpstream> for String {
+    fn from(upstream: DnsUpstream) -> Self {
+        upstream.to_string()
+    }
+}
+
+impl fmt::Display for DnsUpstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tunnel => write!(f, "tunnel"),
+            Self::Tls { host, port } => write!(f, "tls://{}:{port}", bracketed(host)),
+            Self::Https { host, port, path } => {
+                write!(f, "https://{}:{port}{path}", bracketed(host))
+            }
+        }
+    }
+}
+
+/// Split `host[:port]`, where an IPv6 host must be in brackets.
+fn parse_authority(authority: &str, default_port: u16) -> Result<(String, u16)> {
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(rest) => {
+            let (host, rest) = rest
+                .split_once(']')
+                .context("Unterminated IPv6 address in DNS upstream")?;
+            (host, rest.strip_prefix(':'))
+        }
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    if host.is_empty() {
+        bail!("DNS upstream has no host");
+    }
+    let port = match port {
+        Some(port) => port
+            .parse()
+            .with_context(|| format!("Invalid DNS upstream port {port:?}"))?,
+        None => default_port,
+    };
+    Ok((host.to_string(), port))
+}
+
+fn bracketed(host: &str) -> String {
+    if host.contains(':') {
+        format!("[{host}]")
+    } else {
+        host.to_string()
+    }
+}
+
+/// Resolve `host` with the system resolver, unless it is an address.
+async fn lookup(host: &str, port: u16) -> Result<SocketAddr> {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, port));
+    }
+    tokio::net::lookup_host((host, port))
+        .await
+        .with_context(|| format!("Failed to resolve DNS upstream {host}"))?
+        .next()
+        .with_context(|| format!("DNS upstream {host} has no address"))
+}
+
+fn tls_config() -> Result<rustls::ClientConfig> {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.into(),
+    };
+    Ok(rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()?
+    .with_root_certificates(roots)
+    .with_no_client_auth())
+}
+
+/// DNS over TLS, reusing one connection for consecutive queries.
+pub(super) struct TlsResolver {
+    addr: SocketAddr,
+    server_name: ServerName<'static>,
+    connector: TlsConnector,
+    stream: Mutex<Option<TlsStream<TcpStream>>>,
+}
+
+impl TlsResolver {
+    pub async fn new(host: &str, port: u16) -> Result<Self> {
+        let server_name = ServerName::try_from(host.to_string())
+            .with_context(|| format!("Invalid DNS over TLS server name {host}"))?;
+        Ok(Self {
+            addr: lookup(host, port).await?,
+            server_name,
+            connector: TlsConnector::from(Arc::new(tls_config()?)),
+            stream: Mutex::new(None),
+        })
+    }
+
+    pub async fn resolve(&self, query: &[u8]) -> Result<Vec<u8>> {
+        let mut stream = self.stream.lock().await;
+        // The server may have closed an idle connection, retry once on a
+        // fresh one before giving up
+        if let Some(existing) = stream.as_mut() {
+            match exchange(existing, query).await {
+                Ok(response) => return Ok(response),
+                Err(e) => tracing::debug!("DNS over TLS connection lost: {e}"),
+            }
+        }
+        *stream = None;
+        let tcp = TcpStream::connect(self.addr).await?;
+        tcp.set_nodelay(true)?;
+        let mut fresh = self
+            .connector
+            .connect(self.server_name.clone(), tcp)
+            .await?;
+        let response = exchange(&mut fresh, query).await?;
+        *stream = Some(fresh);
+        Ok(response)
+    }
+}
+
+/// One query over a stream, each message prefixed by its length.
+async fn exchange(stream: &mut TlsStream<TcpStream>, query: &[u8]) -> Result<Vec<u8>> {
+    let len = u16::try_from(query.len())?;
+    let mut framed = Vec::with_capacity(query.len() + 2);
+    framed.extend_from_slice(&len.to_be_bytes());
+    framed.extend_from_slice(query);
+    stream.write_all(&framed).await?;
+
+    let len = stream.read_u16().await?;
+    let mut response = vec![0; len.into()];
+    stream.read_exact(&mut response).await?;
+    Ok(response)
+}
+
+/// DNS over HTTPS, POSTing `application/dns-message` bodies.
+pub(super) struct HttpsResolver {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl HttpsResolver {
+    pub async fn new(host: &str, port: u16, path: &str, timeout: Duration) -> Result<Self> {
+        let addr = lookup(host, port).await?;
+        let client = reqwest::Client::builder()
+            .use_preconfigured_tls(tls_config()?)
+            // Pinned so that the proxy never has to resolve its own upstream
+            .resolve(host, addr)
+            .timeout(timeout)
+            .build()?;
+        Ok(Self {
+            client,
+            url: format!("https://{}:{port}{path}", bracketed(host)),
+        })
+    }
+
+    pub async fn resolve(&self, query: &[u8]) -> Result<Vec<u8>> {
+        let response = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/dns-message")
+            .header(reqwest::header::ACCEPT, "application/dns-message")
+            .body(query.to_vec())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.bytes().await?.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("tunnel" => DnsUpstream::Tunnel; "tunnel")]
+    #[test_case("tls://1.1.1.1" => DnsUpstream::Tls { host: "1.1.1.1".to_string(), port: 853 }; "tls default port")]
+    #[test_case("tls://dns.example:8853" => DnsUpstream::Tls { host: "dns.example".to_string(), port: 8853 }; "tls port")]
+    #[test_case("tls://[2606:4700::1111]" => DnsUpstream::Tls { host: "2606:4700::1111".to_string(), port: 853 }; "tls ipv6")]
+    #[test_case("https://dns.example/dns-query" => DnsUpstream::Https { host: "dns.example".to_string(), port: 443, path: "/dns-query".to_string() }; "https")]
+    #[test_case("https://[::1]:8443/q" => DnsUpstream::Https { host: "::1".to_string(), port: 8443, path: "/q".to_string() }; "https ipv6 port")]
+    fn parse(s: &str) -> DnsUpstream {
+        s.parse().unwrap()
+    }
+
+    #[test_case("udp://1.1.1.1"; "unknown scheme")]
+    #[test_case("tls://"; "no host")]
+    #[test_case("tls://dns.example:dot"; "bad port")]
+    #[test_case("tls://[::1"; "unterminated ipv6")]
+    #[test_case("https://dns.example"; "no path")]
+    fn parse_invalid(s: &str) {
+        assert!(s.parse::<DnsUpstream>().is_err());
+    }
+
+    #[test_case("tunnel")]
+    #[test_case("tls://1.1.1.1:853")]
+    #[test_case("tls://[2606:4700::1111]:853")]
+    #[test_case("https://dns.example:443/dns-query")]
+    fn display_round_trip(s: &str) {
+        assert_eq!(s.parse::<DnsUpstream>().unwrap().to_string(), s);
+    }
+}
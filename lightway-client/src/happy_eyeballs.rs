@@ -0,0 +1,228 @@
+//! Happy eyeballs (RFC 8305 style) for servers in `auto` mode: connection
+//! attempts over UDP and TCP and over the server's addresses are started
+//! one after the other, shortly apart, and the first one online wins.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use lightway_app_utils::{PacketCodec, PacketCodecFactory, PacketCodecFactoryType};
+use lightway_core::{ConnectionType, Event, EventCallback};
+
+/// Default delay between starting two connection attempts, the
+/// "Connection Attempt Delay" recommended by RFC 8305
+pub const DEFAULT_CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Reorder resolved addresses so that address families alternate,
+/// starting with the family of the first one (RFC 8305 section 4).
+pub fn interleave_address_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+    let first_is_ipv6 = first.is_ipv6();
+    let (mut first_family, mut other_family): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_is_ipv6);
+    let mut interleaved = Vec::with_capacity(first_family.len() + other_family.len());
+    first_family.reverse();
+    other_family.reverse();
+    while !first_family.is_empty() || !other_family.is_empty() {
+        interleaved.extend(first_family.pop());
+        interleaved.extend(other_family.pop());
+    }
+    interleaved
+}
+
+/// One connection attempt of a race.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Attempt {
+    pub addr: SocketAddr,
+    pub connection_type: ConnectionType,
+}
+
+/// Attempts of a race over `addrs`, in order: each address with
+/// `preferred` then the other transport.
+pub(crate) fn attempts(addrs: &[SocketAddr], preferred: ConnectionType) -> Vec<Attempt> {
+    let other = match preferred {
+        ConnectionType::Datagram => ConnectionType::Stream,
+        ConnectionType::Stream => ConnectionType::Datagram,
+    };
+    addrs
+        .iter()
+        .flat_map(|&addr| {
+            [preferred, other].map(|connection_type| Attempt {
+                addr,
+                connection_type,
+            })
+        })
+        .collect()
+}
+
+/// The transport which won the last race to each server, tried first on
+/// the next one. Keyed on the server name rather than an address, which
+/// changes with DNS and across address families. Shared by every
+/// connection of a client.
+#[derive(Clone, Debug, Default)]
+pub struct TransportPreferences(Arc<Mutex<HashMap<String, ConnectionType>>>);
+
+impl TransportPreferences {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Transport to try first with `server`, UDP unless TCP won last time
+    pub fn preferred(&self, server: &str) -> ConnectionType {
+        self.0
+            .lock()
+            .unwrap()
+            .get(server)
+            .copied()
+            .unwrap_or(ConnectionType::Datagram)
+    }
+
+    pub(crate) fn set(&self, server: &str, connection_type: ConnectionType) {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(server.to_string(), connection_type);
+    }
+}
+
+/// Shares a server's event handler between the attempts of a race. Events
+/// of every attempt are forwarded until one wins, then only the winner's.
+pub(crate) struct AttemptEventHandler<H> {
+    handler: Arc<Mutex<H>>,
+    attempt: usize,
+    winner: Arc<OnceLock<usize>>,
+}
+
+impl<H> AttemptEventHandler<H> {
+    pub fn new(handler: Arc<Mutex<H>>, attempt: usize, winner: Arc<OnceLock<usize>>) -> Self {
+        Self {
+            handler,
+            attempt,
+            winner,
+        }
+    }
+}
+
+impl<H: EventCallback> EventCallback for AttemptEventHandler<H> {
+    fn event(&mut self, event: Event) {
+        if self
+            .winner
+            .get()
+            .is_none_or(|winner| *winner == self.attempt)
+        {
+            self.handler.lock().unwrap().event(event);
+        }
+    }
+}
+
+/// Shares a server's inside packet codec factory between the attempts of
+/// a race.
+pub(crate) struct SharedPacketCodecFactory(Arc<PacketCodecFactoryType>);
+
+impl SharedPacketCodecFactory {
+    pub fn new(factory: Arc<PacketCodecFactoryType>) -> Self {
+        Self(factory)
+    }
+}
+
+impl PacketCodecFactory for SharedPacketCodecFactory {
+    fn build(&self) -> PacketCodec {
+        self.0.build()
+    }
+
+    fn get_codec_name(&self) -> String {
+        self.0.get_codec_name()
+    }
+
+    fn shutdown(&self) {
+        self.0.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn addrs(addrs: &[&str]) -> Vec<SocketAddr> {
+        addrs.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    #[test_case(&[] => Vec::<SocketAddr>::new(); "empty")]
+    #[test_case(&["192.0.2.1:443", "192.0.2.2:443"] => addrs(&["192.0.2.1:443", "192.0.2.2:443"]); "single family")]
+    #[test_case(
+        &["[2001:db8::1]:443", "[2001:db8::2]:443", "[2001:db8::3]:443", "192.0.2.1:443"]
+        => addrs(&["[2001:db8::1]:443", "192.0.2.1:443", "[2001:db8::2]:443", "[2001:db8::3]:443"]);
+        "ipv6 first"
+    )]
+    #[test_case(
+        &["192.0.2.1:443", "192.0.2.2:443", "[2001:db8::1]:443"]
+        => addrs(&["192.0.2.1:443", "[2001:db8::1]:443", "192.0.2.2:443"]);
+        "ipv4 first"
+    )]
+    fn interleave(input: &[&str]) -> Vec<SocketAddr> {
+        interleave_address_families(addrs(input))
+    }
+
+    #[test]
+    fn attempts_pair_transports_per_address() {
+        let addrs = addrs(&["[2001:db8::1]:443", "192.0.2.1:443"]);
+        let attempts: Vec<_> = attempts(&addrs, ConnectionType::Stream)
+            .into_iter()
+            .map(|a| (a.addr, a.connection_type))
+            .collect();
+        assert_eq!(
+            attempts,
+            [
+                (addrs[0], ConnectionType::Stream),
+                (addrs[0], ConnectionType::Datagram),
+                (addrs[1], ConnectionType::Stream),
+                (addrs[1], ConnectionType::Datagram),
+            ]
+        );
+    }
+
+    #[test]
+    fn preferences_default_to_udp() {
+        let preferences = TransportPreferences::new();
+        let server = "vpn.example.com:443";
+        assert_eq!(preferences.preferred(server), ConnectionType::Datagram);
+
+        preferences.set(server, ConnectionType::Stream);
+        assert_eq!(
+            preferences.clone().preferred(server),
+            ConnectionType::Stream
+        );
+    }
+
+    #[derive(Default)]
+    struct Recorder(Vec<Event>);
+
+    impl EventCallback for Recorder {
+        fn event(&mut self, event: Event) {
+            self.0.push(event);
+        }
+    }
+
+    #[test]
+    fn only_winner_events_after_race() {
+        let recorder = Arc::new(Mutex::new(Recorder::default()));
+        let winner = Arc::new(OnceLock::new());
+        let mut udp = AttemptEventHandler::new(recorder.clone(), 0, winner.clone());
+        let mut tcp = AttemptEventHandler::new(recorder.clone(), 1, winner.clone());
+
+        udp.event(Event::FirstPacketReceived);
+        tcp.event(Event::FirstPacketReceived);
+        winner.set(1).unwrap();
+        udp.event(Event::KeepaliveReply { id: None });
+        tcp.event(Event::KeepaliveReply { id: None });
+
+        let events = &recorder.lock().unwrap().0;
+        assert_eq!(events.len(), 3);
+        assert!(matches!(events[2], Event::KeepaliveReply { .. }));
+    }
+}
@@ -1,7 +1,9 @@
+pub mod netstack;
 pub mod tun;
 
 use anyhow::Result;
 use bytes::BytesMut;
+pub use netstack::{Netstack, NetstackConfig};
 use std::sync::Arc;
 pub use tun::Tun;
 
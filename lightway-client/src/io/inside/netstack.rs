@@ -0,0 +1,179 @@
+//! Userspace network stack
+//!
+//! [`Netstack`] replaces the TUN device with a userspace TCP/IP stack
+//! ([smoltcp]) using the inside IP, and serves a local SOCKS5 and/or
+//! HTTP CONNECT proxy. Each proxied connection is opened by the stack,
+//! so application traffic goes through the tunnel without root
+//! privileges, a TUN device or route changes. Domain names are resolved
+//! by the stack as well, over the tunnel DNS.
+
+mod proxy;
+mod stack;
+
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bytes::BytesMut;
+use lightway_core::{
+    IOCallbackResult, InsideIOSendCallback, InsideIOSendCallbackArg, InsideIpConfig,
+    ipv4_update_destination, ipv4_update_source,
+};
+use pnet_packet::ipv4::Ipv4Packet;
+use tokio::{
+    net::TcpListener,
+    sync::{Mutex, mpsc},
+    task::JoinHandle,
+};
+
+use crate::{ConnectionState, io::inside::InsideIORecv};
+use proxy::Protocol;
+use stack::Stack;
+
+/// Inside MTU, the same as the TUN device's
+const MTU: usize = 1350;
+
+/// Packets queued from and to the tunnel
+const PACKET_QUEUE_SIZE: usize = 1024;
+
+/// Local proxies served by [`Netstack`]
+#[derive(Clone, Debug, Default)]
+pub struct NetstackConfig {
+    /// Address of the SOCKS5 proxy, not served when `None`
+    pub socks_proxy: Option<SocketAddr>,
+    /// Address of the HTTP CONNECT proxy, not served when `None`
+    pub http_proxy: Option<SocketAddr>,
+}
+
+/// Inside IO terminating the inside traffic in a userspace network
+/// stack, see the [module documentation](self)
+pub struct Netstack {
+    ip: Ipv4Addr,
+    dns_ip: Ipv4Addr,
+    from_tunnel: mpsc::Sender<BytesMut>,
+    to_tunnel_rx: Mutex<mpsc::Receiver<BytesMut>>,
+    /// The proxy listeners, stopped on drop
+    proxies: Vec<JoinHandle<()>>,
+}
+
+impl Drop for Netstack {
+    fn drop(&mut self) {
+        for proxy in &self.proxies {
+            proxy.abort();
+        }
+    }
+}
+
+impl Netstack {
+    /// Start the stack on `ip`, resolving names with `dns_ip`, and
+    /// listen for the proxies of `config`
+    pub async fn new(config: &NetstackConfig, ip: Ipv4Addr, dns_ip: Ipv4Addr) -> Result<Self> {
+        let (from_tunnel, from_tunnel_rx) = mpsc::channel(PACKET_QUEUE_SIZE);
+        let (to_tunnel, to_tunnel_rx) = mpsc::channel(PACKET_QUEUE_SIZE);
+
+        let (stack, connector) = Stack::new(MTU, ip, dns_ip, to_tunnel);
+
+        let mut proxies = Vec::new();
+        for (addr, protocol) in [
+            (config.socks_proxy, Protocol::Socks5),
+            (config.http_proxy, Protocol::HttpConnect),
+        ] {
+            let Some(addr) = addr else {
+                continue;
+            };
+            let listener = TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("Failed to listen for the {protocol} proxy on {addr}"))?;
+            tracing::info!(%addr, "{protocol} proxy listening");
+            proxies.push(tokio::spawn(proxy::serve(
+                listener,
+                protocol,
+                connector.clone(),
+            )));
+        }
+
+        // Stops once `from_tunnel` is dropped
+        tokio::spawn(stack.run(from_tunnel_rx));
+
+        Ok(Self {
+            ip,
+            dns_ip,
+            from_tunnel,
+            to_tunnel_rx: Mutex::new(to_tunnel_rx),
+            proxies,
+        })
+    }
+
+    /// Hand a packet from the tunnel to the stack
+    fn receive(&self, mut pkt: BytesMut, ip_config: Option<InsideIpConfig>) -> usize {
+        let len = pkt.len();
+        // Update destination IP from server provided inside ip to the stack ip
+        ipv4_update_destination(pkt.as_mut(), self.ip);
+
+        // Update source IP from server DNS ip to the stack DNS ip
+        if let Some(ip_config) = ip_config {
+            let packet = Ipv4Packet::new(pkt.as_ref());
+            if let Some(packet) = packet
+                && packet.get_source() == ip_config.dns_ip
+            {
+                ipv4_update_source(pkt.as_mut(), self.dns_ip);
+            };
+        }
+
+        // Dropped like by any device when the stack lags behind
+        let _ = self.from_tunnel.try_send(pkt);
+        len
+    }
+}
+
+#[async_trait]
+impl<ExtAppState: Send + Sync> InsideIORecv<ExtAppState> for Netstack {
+    async fn recv_buf(&self, buf: &mut BytesMut) -> IOCallbackResult<usize> {
+        let Some(packet) = self.to_tunnel_rx.lock().await.recv().await else {
+            return IOCallbackResult::Err(std::io::Error::other("Network stack stopped"));
+        };
+        buf.clear();
+        buf.extend_from_slice(&packet);
+        IOCallbackResult::Ok(packet.len())
+    }
+
+    /// Api to send packet in the tunnel
+    fn try_send(&self, pkt: BytesMut, ip_config: Option<InsideIpConfig>) -> Result<usize> {
+        Ok(self.receive(pkt, ip_config))
+    }
+
+    fn mtu(&self) -> usize {
+        MTU
+    }
+
+    fn into_io_send_callback(
+        self: Arc<Self>,
+    ) -> InsideIOSendCallbackArg<ConnectionState<ExtAppState>> {
+        self
+    }
+}
+
+impl<ExtAppState: Send + Sync> InsideIOSendCallback<ConnectionState<ExtAppState>> for Netstack {
+    fn send(
+        &self,
+        buf: BytesMut,
+        state: &mut ConnectionState<ExtAppState>,
+    ) -> IOCallbackResult<usize> {
+        IOCallbackResult::Ok(self.receive(buf, state.ip_config))
+    }
+
+    fn mtu(&self) -> usize {
+        MTU
+    }
+
+    fn if_index(&self) -> std::io::Result<u32> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+
+    fn name(&self) -> std::io::Result<String> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+}
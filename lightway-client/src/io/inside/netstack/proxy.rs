@@ -0,0 +1,314 @@
+//! Local proxies
+//!
+//! SOCKS5 (RFC 1928, `CONNECT` without authentication) and HTTP
+//! `CONNECT` proxies, whose connections are opened by the [`Stack`].
+//!
+//! [`Stack`]: super::stack::Stack
+
+use std::{
+    fmt,
+    io::{Error, ErrorKind},
+    net::Ipv4Addr,
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpListener,
+};
+
+use super::stack::{Connector, Remote};
+
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_NO_AUTHENTICATION: u8 = 0x00;
+const SOCKS_NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const SOCKS_CMD_CONNECT: u8 = 0x01;
+const SOCKS_ATYP_IPV4: u8 = 0x01;
+const SOCKS_ATYP_DOMAIN: u8 = 0x03;
+const SOCKS_ATYP_IPV6: u8 = 0x04;
+
+const SOCKS_SUCCEEDED: u8 = 0x00;
+const SOCKS_GENERAL_FAILURE: u8 = 0x01;
+const SOCKS_HOST_UNREACHABLE: u8 = 0x04;
+const SOCKS_CONNECTION_REFUSED: u8 = 0x05;
+const SOCKS_TTL_EXPIRED: u8 = 0x06;
+const SOCKS_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const SOCKS_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
+
+/// Longest HTTP request head accepted
+const MAX_HTTP_HEAD_SIZE: u64 = 8 * 1024;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) enum Protocol {
+    Socks5,
+    HttpConnect,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Socks5 => f.write_str("SOCKS5"),
+            Self::HttpConnect => f.write_str("HTTP CONNECT"),
+        }
+    }
+}
+
+/// Accept the proxy connections of `listener`
+pub(super) async fn serve(listener: TcpListener, protocol: Protocol, connector: Connector) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                tracing::warn!(%protocol, ?err, "Failed to accept proxy connection");
+                continue;
+            }
+        };
+        let connector = connector.clone();
+        tokio::spawn(async move {
+            let result = match protocol {
+                Protocol::Socks5 => socks5(stream, &connector).await,
+                Protocol::HttpConnect => http_connect(BufReader::new(stream), &connector).await,
+            };
+            if let Err(err) = result {
+                tracing::debug!(%protocol, ?err, "Proxy connection failed");
+            }
+        });
+    }
+}
+
+/// Serve a SOCKS5 proxy connection
+async fn socks5<S>(mut stream: S, connector: &Connector) -> std::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let Some(remote) = socks5_request(&mut stream).await? else {
+        return Ok(());
+    };
+
+    let connection = match connector.connect(remote.clone()).await {
+        Ok(connection) => connection,
+        Err(err) => {
+            tracing::debug!(%remote, ?err, "Failed to connect");
+            return socks5_reply(&mut stream, socks5_reply_code(&err)).await;
+        }
+    };
+    socks5_reply(&mut stream, SOCKS_SUCCEEDED).await?;
+    connection.relay(stream).await;
+    Ok(())
+}
+
+/// Negotiate the method and read the request of a SOCKS5 connection.
+/// Returns the remote to connect to, `None` once an unsupported request
+/// was rejected.
+async fn socks5_request<S>(stream: &mut S) -> std::io::Result<Option<Remote>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let [version, method_count] = read_array(stream).await?;
+    if version != SOCKS_VERSION {
+        return Err(Error::new(ErrorKind::InvalidData, "Not a SOCKS5 client"));
+    }
+    let mut methods = vec![0; method_count as usize];
+    stream.read_exact(&mut methods).await?;
+    if !methods.contains(&SOCKS_NO_AUTHENTICATION) {
+        stream
+            .write_all(&[SOCKS_VERSION, SOCKS_NO_ACCEPTABLE_METHOD])
+            .await?;
+        return Ok(None);
+    }
+    stream
+        .write_all(&[SOCKS_VERSION, SOCKS_NO_AUTHENTICATION])
+        .await?;
+
+    let [version, command, _reserved, address_type] = read_array(stream).await?;
+    if version != SOCKS_VERSION {
+        return Err(Error::new(ErrorKind::InvalidData, "Not a SOCKS5 request"));
+    }
+    let host = match address_type {
+        SOCKS_ATYP_IPV4 => Ipv4Addr::from(read_array::<_, 4>(stream).await?).to_string(),
+        SOCKS_ATYP_DOMAIN => {
+            let [len] = read_array(stream).await?;
+            let mut name = vec![0; len as usize];
+            stream.read_exact(&mut name).await?;
+            String::from_utf8(name).map_err(|err| Error::new(ErrorKind::InvalidData, err))?
+        }
+        SOCKS_ATYP_IPV6 => {
+            // The tunnel is IPv4 only
+            read_array::<_, 18>(stream).await?;
+            socks5_reply(stream, SOCKS_ADDRESS_TYPE_NOT_SUPPORTED).await?;
+            return Ok(None);
+        }
+        _ => {
+            socks5_reply(stream, SOCKS_ADDRESS_TYPE_NOT_SUPPORTED).await?;
+            return Ok(None);
+        }
+    };
+    let port = u16::from_be_bytes(read_array(stream).await?);
+
+    if command != SOCKS_CMD_CONNECT {
+        socks5_reply(stream, SOCKS_COMMAND_NOT_SUPPORTED).await?;
+        return Ok(None);
+    }
+    Ok(Some(Remote::new(&host, port)))
+}
+
+async fn socks5_reply<S: AsyncWrite + Unpin>(stream: &mut S, code: u8) -> std::io::Result<()> {
+    // Bound to 0.0.0.0:0, the stack does not expose its address
+    stream
+        .write_all(&[SOCKS_VERSION, code, 0, SOCKS_ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+        .await
+}
+
+fn socks5_reply_code(err: &Error) -> u8 {
+    match err.kind() {
+        ErrorKind::ConnectionRefused => SOCKS_CONNECTION_REFUSED,
+        ErrorKind::TimedOut => SOCKS_TTL_EXPIRED,
+        ErrorKind::NotFound => SOCKS_HOST_UNREACHABLE,
+        _ => SOCKS_GENERAL_FAILURE,
+    }
+}
+
+async fn read_array<S: AsyncRead + Unpin, const N: usize>(
+    stream: &mut S,
+) -> std::io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    stream.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+/// Serve an HTTP CONNECT proxy connection
+async fn http_connect<S>(mut stream: BufReader<S>, connector: &Connector) -> std::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut head = (&mut stream).take(MAX_HTTP_HEAD_SIZE);
+    let mut request_line = String::new();
+    head.read_line(&mut request_line).await?;
+    // Skip the headers, nothing in them matters to a tunnel
+    loop {
+        let mut line = String::new();
+        if head.read_line(&mut line).await? == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Incomplete HTTP request head",
+            ));
+        }
+        if line.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    let remote = match parse_connect_request(&request_line) {
+        Ok(remote) => remote,
+        Err(err) => return stream.write_all(err.response()).await,
+    };
+
+    let connection = match connector.connect(remote.clone()).await {
+        Ok(connection) => connection,
+        Err(err) => {
+            tracing::debug!(%remote, ?err, "Failed to connect");
+            let status: &[u8] = match err.kind() {
+                ErrorKind::TimedOut => b"HTTP/1.1 504 Gateway Timeout\r\n\r\n",
+                _ => b"HTTP/1.1 502 Bad Gateway\r\n\r\n",
+            };
+            return stream.write_all(status).await;
+        }
+    };
+    stream
+        .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+        .await?;
+    // Anything the client sent past the head is still buffered
+    connection.relay(stream).await;
+    Ok(())
+}
+
+/// Why an HTTP request line was rejected
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ConnectRequestError {
+    /// Not a `CONNECT <host>:<port> HTTP/1.x` request
+    NotConnect,
+    /// The host is a bracketed IPv6 address, the tunnel is IPv4 only
+    Ipv6Host,
+}
+
+impl ConnectRequestError {
+    fn response(self) -> &'static [u8] {
+        match self {
+            Self::NotConnect => b"HTTP/1.1 405 Method Not Allowed\r\nAllow: CONNECT\r\n\r\n",
+            Self::Ipv6Host => {
+                b"HTTP/1.1 400 Bad Request\r\n\r\nIPv6 destinations are not supported\n"
+            }
+        }
+    }
+}
+
+/// Remote of an HTTP `CONNECT <host>:<port> HTTP/1.x` request line
+fn parse_connect_request(line: &str) -> Result<Remote, ConnectRequestError> {
+    use ConnectRequestError::*;
+
+    let mut parts = line.split_whitespace();
+    if parts.next() != Some("CONNECT") {
+        return Err(NotConnect);
+    }
+    let authority = parts.next().ok_or(NotConnect)?;
+    if !parts
+        .next()
+        .is_some_and(|version| version.starts_with("HTTP/1."))
+    {
+        return Err(NotConnect);
+    }
+    let (host, port) = authority.rsplit_once(':').ok_or(NotConnect)?;
+    if host.starts_with('[') {
+        return Err(Ipv6Host);
+    }
+    let port = port.parse().map_err(|_| NotConnect)?;
+    Ok(Remote::new(host, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("CONNECT example.com:443 HTTP/1.1\r\n" => Ok(Remote::Name("example.com".to_string(), 443)))]
+    #[test_case("CONNECT 1.1.1.1:853 HTTP/1.0\r\n" => Ok(Remote::Addr("1.1.1.1:853".parse().unwrap())))]
+    #[test_case("CONNECT [::1]:443 HTTP/1.1\r\n" => Err(ConnectRequestError::Ipv6Host))]
+    #[test_case("CONNECT [2606:4700::1111]:443 HTTP/1.1\r\n" => Err(ConnectRequestError::Ipv6Host))]
+    #[test_case("CONNECT example.com HTTP/1.1\r\n" => Err(ConnectRequestError::NotConnect))]
+    #[test_case("CONNECT example.com:https HTTP/1.1\r\n" => Err(ConnectRequestError::NotConnect))]
+    #[test_case("GET http://example.com/ HTTP/1.1\r\n" => Err(ConnectRequestError::NotConnect))]
+    #[test_case("CONNECT example.com:443\r\n" => Err(ConnectRequestError::NotConnect))]
+    fn connect_request(line: &str) -> Result<Remote, ConnectRequestError> {
+        parse_connect_request(line)
+    }
+
+    #[test_case(&[SOCKS_ATYP_DOMAIN, 11, b'e', b'x', b'a', b'm', b'p', b'l', b'e', b'.', b'c', b'o', b'm', 1, 187], Remote::Name("example.com".to_string(), 443))]
+    #[test_case(&[SOCKS_ATYP_IPV4, 1, 1, 1, 1, 0, 53], Remote::Addr("1.1.1.1:53".parse().unwrap()))]
+    #[tokio::test]
+    async fn socks5_connect_request(address: &[u8], expected: Remote) {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let mut request = vec![SOCKS_VERSION, 1, SOCKS_NO_AUTHENTICATION];
+        request.extend_from_slice(&[SOCKS_VERSION, SOCKS_CMD_CONNECT, 0]);
+        request.extend_from_slice(address);
+        client.write_all(&request).await.unwrap();
+
+        let remote = socks5_request(&mut server).await.unwrap();
+        assert_eq!(remote, Some(expected));
+
+        let mut method = [0; 2];
+        client.read_exact(&mut method).await.unwrap();
+        assert_eq!(method, [SOCKS_VERSION, SOCKS_NO_AUTHENTICATION]);
+    }
+
+    #[tokio::test]
+    async fn socks5_rejects_authentication() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        // Username/password only
+        client.write_all(&[SOCKS_VERSION, 1, 0x02]).await.unwrap();
+
+        assert_eq!(socks5_request(&mut server).await.unwrap(), None);
+
+        let mut method = [0; 2];
+        client.read_exact(&mut method).await.unwrap();
+        assert_eq!(method, [SOCKS_VERSION, SOCKS_NO_ACCEPTABLE_METHOD]);
+    }
+}
@@ -0,0 +1,626 @@
+//! TCP/IP stack
+//!
+//! [`Stack`] connects to the remotes asked for by the proxies through
+//! the tunnel, resolving their names with the tunnel DNS first, and
+//! relays the data of each connection to the proxy connection it was
+//! opened for.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bytes::{Buf, Bytes, BytesMut};
+use smoltcp::{
+    iface::{Config, Interface, SocketHandle, SocketSet},
+    phy::{self, Device, DeviceCapabilities, Medium},
+    socket::{dns, tcp},
+    wire::{DnsQueryType, HardwareAddress, IpAddress, IpCidr},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf},
+    sync::{Notify, mpsc, oneshot},
+};
+
+/// Receive and send buffer of each connection of the stack
+const TCP_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Most connections open at a time
+const MAX_TCP_CONNECTIONS: usize = 1024;
+
+/// Most names being resolved at a time
+const MAX_DNS_QUERIES: usize = 64;
+
+/// Connect requests queued for the stack
+const REQUEST_QUEUE_SIZE: usize = 64;
+
+/// Chunks of data queued between a connection and its proxy connection
+const APP_QUEUE_SIZE: usize = 16;
+
+/// Largest chunk of data read from a proxy connection
+const APP_READ_SIZE: usize = 16 * 1024;
+
+/// Covers resolving the name of the remote too
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connections unacknowledged for this long are aborted
+const TCP_TIMEOUT: Duration = Duration::from_secs(120);
+
+const TCP_KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+/// Longest wait between two polls of the stack
+const MAX_POLL_DELAY: Duration = Duration::from_secs(1);
+
+/// Local ports of the connections
+const EPHEMERAL_PORTS: std::ops::RangeInclusive<u16> = 49152..=65535;
+
+/// Where a proxy connection goes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) enum Remote {
+    Addr(SocketAddrV4),
+    /// Resolved with the tunnel DNS
+    Name(String, u16),
+}
+
+impl Remote {
+    /// Remote of a `host` which is either an IPv4 address or a name
+    pub(super) fn new(host: &str, port: u16) -> Self {
+        match host.parse::<Ipv4Addr>() {
+            Ok(ip) => Self::Addr(SocketAddrV4::new(ip, port)),
+            Err(_) => Self::Name(host.to_string(), port),
+        }
+    }
+}
+
+impl fmt::Display for Remote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Addr(addr) => addr.fmt(f),
+            Self::Name(name, port) => write!(f, "{name}:{port}"),
+        }
+    }
+}
+
+/// Sent by a proxy connection
+enum AppEvent {
+    Data(Bytes),
+    /// The proxy connection failed, reset the stack's
+    Failed,
+}
+
+/// Asks the stack for a connection to a remote, see [`Connector::connect`]
+struct ConnectRequest {
+    remote: Remote,
+    /// Answered once connected, or on failure
+    connected: oneshot::Sender<std::io::Result<()>>,
+    to_app: mpsc::Sender<Bytes>,
+    from_app: mpsc::Receiver<AppEvent>,
+}
+
+impl ConnectRequest {
+    fn fail(self, err: std::io::Error) {
+        let _ = self.connected.send(Err(err));
+    }
+}
+
+/// Opens connections through a [`Stack`]
+#[derive(Clone)]
+pub(super) struct Connector {
+    requests: mpsc::Sender<ConnectRequest>,
+    wake: Arc<Notify>,
+}
+
+impl Connector {
+    /// Connect to `remote` through the tunnel
+    pub(super) async fn connect(&self, remote: Remote) -> std::io::Result<StackStream> {
+        let stopped = || std::io::Error::other("Network stack stopped");
+        let (to_app, from_stack) = mpsc::channel(APP_QUEUE_SIZE);
+        let (to_stack, from_app) = mpsc::channel(APP_QUEUE_SIZE);
+        let (connected, connected_rx) = oneshot::channel();
+        self.requests
+            .send(ConnectRequest {
+                remote,
+                connected,
+                to_app,
+                from_app,
+            })
+            .await
+            .map_err(|_| stopped())?;
+        connected_rx.await.map_err(|_| stopped())??;
+
+        Ok(StackStream {
+            to_stack,
+            from_stack,
+            wake: self.wake.clone(),
+        })
+    }
+}
+
+/// Connection opened by [`Connector::connect`]
+pub(super) struct StackStream {
+    to_stack: mpsc::Sender<AppEvent>,
+    from_stack: mpsc::Receiver<Bytes>,
+    wake: Arc<Notify>,
+}
+
+impl StackStream {
+    /// Relay data between the connection and the proxy connection
+    /// `stream` until both are closed
+    pub(super) async fn relay<S: AsyncRead + AsyncWrite>(self, stream: S) {
+        let (reader, writer) = tokio::io::split(stream);
+
+        // The stack sees the proxy connection closing once `upload`
+        // dropped its sender, failures of `download` are reported
+        // unless that already happened
+        let failed = self.to_stack.downgrade();
+        let result = tokio::try_join!(
+            upload(reader, self.to_stack, &self.wake),
+            download(writer, self.from_stack, &self.wake),
+        );
+        if result.is_err()
+            && let Some(to_stack) = failed.upgrade()
+        {
+            let _ = to_stack.send(AppEvent::Failed).await;
+        }
+        self.wake.notify_one();
+    }
+}
+
+/// Read data from the proxy connection for the stack
+async fn upload<S: AsyncRead>(
+    mut reader: ReadHalf<S>,
+    to_stack: mpsc::Sender<AppEvent>,
+    wake: &Notify,
+) -> std::io::Result<()> {
+    loop {
+        let mut buf = BytesMut::with_capacity(APP_READ_SIZE);
+        match reader.read_buf(&mut buf).await {
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            Err(err) => {
+                let _ = to_stack.send(AppEvent::Failed).await;
+                return Err(err);
+            }
+        }
+        if to_stack.send(AppEvent::Data(buf.freeze())).await.is_err() {
+            return Ok(());
+        }
+        wake.notify_one();
+    }
+}
+
+/// Write the data of the stack to the proxy connection
+async fn download<S: AsyncWrite>(
+    mut writer: WriteHalf<S>,
+    mut from_stack: mpsc::Receiver<Bytes>,
+    wake: &Notify,
+) -> std::io::Result<()> {
+    while let Some(data) = from_stack.recv().await {
+        writer.write_all(&data).await?;
+        // Room for more data in the queue
+        wake.notify_one();
+    }
+    writer.shutdown().await
+}
+
+/// Device of the stack, exchanging packets with the tunnel
+struct TunnelDevice {
+    mtu: usize,
+    /// Packets from the tunnel, yet to be processed by the stack
+    received: VecDeque<BytesMut>,
+    to_tunnel: mpsc::Sender<BytesMut>,
+}
+
+struct TunnelRxToken(BytesMut);
+
+struct TunnelTxToken<'a>(&'a mpsc::Sender<BytesMut>);
+
+impl phy::RxToken for TunnelRxToken {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        f(&self.0)
+    }
+}
+
+impl phy::TxToken for TunnelTxToken<'_> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut packet = BytesMut::zeroed(len);
+        let result = f(&mut packet);
+        // Dropped like by any device when the tunnel lags behind
+        let _ = self.0.try_send(packet);
+        result
+    }
+}
+
+impl Device for TunnelDevice {
+    type RxToken<'a> = TunnelRxToken;
+    type TxToken<'a> = TunnelTxToken<'a>;
+
+    fn receive(
+        &mut self,
+        _timestamp: smoltcp::time::Instant,
+    ) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let packet = self.received.pop_front()?;
+        Some((TunnelRxToken(packet), TunnelTxToken(&self.to_tunnel)))
+    }
+
+    fn transmit(&mut self, _timestamp: smoltcp::time::Instant) -> Option<Self::TxToken<'_>> {
+        Some(TunnelTxToken(&self.to_tunnel))
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut capabilities = DeviceCapabilities::default();
+        capabilities.medium = Medium::Ip;
+        capabilities.max_transmission_unit = self.mtu;
+        capabilities
+    }
+}
+
+/// Connection of the stack for a proxy connection
+struct TcpFlow {
+    created: Instant,
+    local_port: u16,
+    /// `None` once connected
+    connected: Option<oneshot::Sender<std::io::Result<()>>>,
+    /// `None` once the remote closed its side
+    to_app: Option<mpsc::Sender<Bytes>>,
+    /// Closed once the proxy connection closed its side
+    from_app: mpsc::Receiver<AppEvent>,
+    /// Data from the proxy connection not accepted by the stack yet
+    pending: Bytes,
+    app_closed: bool,
+}
+
+impl TcpFlow {
+    /// Relay data between the connection and the proxy connection.
+    /// Returns `false` once the flow is over.
+    fn relay(&mut self, socket: &mut tcp::Socket) -> bool {
+        if let Some(connected) = self.connected.take() {
+            let result = match socket.state() {
+                tcp::State::SynSent | tcp::State::SynReceived
+                    if self.created.elapsed() < CONNECT_TIMEOUT =>
+                {
+                    self.connected = Some(connected);
+                    return true;
+                }
+                tcp::State::SynSent | tcp::State::SynReceived => {
+                    socket.abort();
+                    Err(std::io::Error::from(std::io::ErrorKind::TimedOut))
+                }
+                tcp::State::Closed => {
+                    Err(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+                }
+                _ => Ok(()),
+            };
+            let failed = result.is_err();
+            // The proxy connection went away in the meantime
+            if connected.send(result).is_err() {
+                socket.abort();
+                return false;
+            }
+            if failed {
+                return false;
+            }
+        }
+
+        // Remote to proxy connection
+        while socket.can_recv()
+            && let Some(permit) = self.to_app.as_ref().and_then(|tx| tx.try_reserve().ok())
+        {
+            match socket.recv(|data| (data.len(), Bytes::copy_from_slice(data))) {
+                Ok(data) => permit.send(data),
+                Err(_) => break,
+            }
+        }
+        if !socket.can_recv()
+            && matches!(
+                socket.state(),
+                tcp::State::CloseWait | tcp::State::LastAck | tcp::State::Closing
+            )
+        {
+            // Shuts the proxy connection down for writing
+            self.to_app = None;
+        }
+
+        // Proxy connection to remote
+        loop {
+            if self.pending.is_empty() {
+                match self.from_app.try_recv() {
+                    Ok(AppEvent::Data(data)) => self.pending = data,
+                    Ok(AppEvent::Failed) => {
+                        socket.abort();
+                        return false;
+                    }
+                    Err(mpsc::error::TryRecvError::Empty) => break,
+                    Err(mpsc::error::TryRecvError::Disconnected) => {
+                        if !self.app_closed {
+                            self.app_closed = true;
+                            socket.close();
+                        }
+                        break;
+                    }
+                }
+            }
+            if !socket.can_send() {
+                break;
+            }
+            match socket.send_slice(&self.pending) {
+                Ok(len) => self.pending.advance(len),
+                Err(_) => {
+                    socket.abort();
+                    return false;
+                }
+            }
+        }
+
+        socket.state() != tcp::State::Closed
+    }
+}
+
+/// Name of a remote being resolved
+struct Resolving {
+    query: dns::QueryHandle,
+    port: u16,
+    started: Instant,
+    request: ConnectRequest,
+}
+
+pub(super) struct Stack {
+    iface: Interface,
+    device: TunnelDevice,
+    sockets: SocketSet<'static>,
+    dns: SocketHandle,
+    resolving: Vec<Resolving>,
+    flows: HashMap<SocketHandle, TcpFlow>,
+    next_port: u16,
+    requests: mpsc::Receiver<ConnectRequest>,
+    /// Notified by the proxy connections
+    wake: Arc<Notify>,
+}
+
+impl Stack {
+    /// Stack using `ip` and resolving names with `dns_ip`, along with
+    /// the [`Connector`] to open connections with
+    pub(super) fn new(
+        mtu: usize,
+        ip: Ipv4Addr,
+        dns_ip: Ipv4Addr,
+        to_tunnel: mpsc::Sender<BytesMut>,
+    ) -> (Self, Connector) {
+        let mut device = TunnelDevice {
+            mtu,
+            received: VecDeque::new(),
+            to_tunnel,
+        };
+        let mut iface = Interface::new(
+            Config::new(HardwareAddress::Ip),
+            &mut device,
+            smoltcp::time::Instant::now(),
+        );
+        // Every address is reached through the tunnel
+        iface.update_ip_addrs(|addrs| {
+            addrs
+                .push(IpCidr::new(IpAddress::Ipv4(ip), 0))
+                .expect("Room for one address");
+        });
+
+        let mut sockets = SocketSet::new(Vec::new());
+        let queries: Vec<Option<dns::DnsQuery>> = (0..MAX_DNS_QUERIES).map(|_| None).collect();
+        let dns = sockets.add(dns::Socket::new(&[IpAddress::Ipv4(dns_ip)], queries));
+
+        let (requests_tx, requests) = mpsc::channel(REQUEST_QUEUE_SIZE);
+        let wake = Arc::new(Notify::new());
+        let connector = Connector {
+            requests: requests_tx,
+            wake: wake.clone(),
+        };
+
+        let stack = Self {
+            iface,
+            device,
+            sockets,
+            dns,
+            resolving: Vec::new(),
+            flows: HashMap::new(),
+            next_port: *EPHEMERAL_PORTS.start(),
+            requests,
+            wake,
+        };
+        (stack, connector)
+    }
+
+    /// Process the packets from the tunnel and the connect requests
+    /// until `from_tunnel` is closed
+    pub(super) async fn run(mut self, mut from_tunnel: mpsc::Receiver<BytesMut>) {
+        loop {
+            let delay = self
+                .iface
+                .poll_delay(smoltcp::time::Instant::now(), &self.sockets)
+                .map(Duration::from)
+                .unwrap_or(MAX_POLL_DELAY)
+                .min(MAX_POLL_DELAY);
+            tokio::select! {
+                packet = from_tunnel.recv() => {
+                    let Some(packet) = packet else {
+                        break;
+                    };
+                    self.device.received.push_back(packet);
+                    while let Ok(packet) = from_tunnel.try_recv() {
+                        self.device.received.push_back(packet);
+                    }
+                }
+                Some(request) = self.requests.recv() => self.connect(request),
+                _ = self.wake.notified() => {}
+                _ = tokio::time::sleep(delay) => {}
+            }
+            self.poll();
+        }
+    }
+
+    /// Start connecting for `request`, resolving its remote first if
+    /// needed
+    fn connect(&mut self, request: ConnectRequest) {
+        match &request.remote {
+            Remote::Addr(addr) => {
+                let addr = *addr;
+                self.open(addr, request);
+            }
+            Remote::Name(name, port) => {
+                let port = *port;
+                let result = self.sockets.get_mut::<dns::Socket>(self.dns).start_query(
+                    self.iface.context(),
+                    name,
+                    DnsQueryType::A,
+                );
+                match result {
+                    Ok(query) => self.resolving.push(Resolving {
+                        query,
+                        port,
+                        started: Instant::now(),
+                        request,
+                    }),
+                    Err(err) => {
+                        let err =
+                            std::io::Error::other(format!("Failed to resolve {name}: {err:?}"));
+                        request.fail(err);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Open the connection of `request` to `addr`
+    fn open(&mut self, addr: SocketAddrV4, request: ConnectRequest) {
+        if self.flows.len() >= MAX_TCP_CONNECTIONS {
+            request.fail(std::io::Error::other("Too many connections"));
+            return;
+        }
+
+        let mut socket = tcp::Socket::new(
+            tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+            tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+        );
+        socket.set_timeout(Some(TCP_TIMEOUT.into()));
+        socket.set_keep_alive(Some(TCP_KEEP_ALIVE.into()));
+        let local_port = self.local_port();
+        let remote = (IpAddress::Ipv4(*addr.ip()), addr.port());
+        if let Err(err) = socket.connect(self.iface.context(), remote, local_port) {
+            request.fail(std::io::Error::other(format!(
+                "Failed to connect to {addr}: {err:?}"
+            )));
+            return;
+        }
+
+        let handle = self.sockets.add(socket);
+        self.flows.insert(
+            handle,
+            TcpFlow {
+                created: Instant::now(),
+                local_port,
+                connected: Some(request.connected),
+                to_app: Some(request.to_app),
+                from_app: request.from_app,
+                pending: Bytes::new(),
+                app_closed: false,
+            },
+        );
+    }
+
+    /// Next ephemeral port not used by a connection
+    fn local_port(&mut self) -> u16 {
+        loop {
+            let port = self.next_port;
+            self.next_port = if port == *EPHEMERAL_PORTS.end() {
+                *EPHEMERAL_PORTS.start()
+            } else {
+                port + 1
+            };
+            // There are more ephemeral ports than connections
+            if !self.flows.values().any(|flow| flow.local_port == port) {
+                return port;
+            }
+        }
+    }
+
+    /// Process the packets from the tunnel, connect the resolved
+    /// remotes, relay data between the connections and the proxy
+    /// connections and send the resulting packets
+    fn poll(&mut self) {
+        self.iface.poll(
+            smoltcp::time::Instant::now(),
+            &mut self.device,
+            &mut self.sockets,
+        );
+
+        for resolving in std::mem::take(&mut self.resolving) {
+            let dns = self.sockets.get_mut::<dns::Socket>(self.dns);
+            match dns.get_query_result(resolving.query) {
+                Ok(addrs) => {
+                    #[allow(unreachable_patterns)]
+                    let ip = addrs.iter().find_map(|addr| match addr {
+                        IpAddress::Ipv4(ip) => Some(*ip),
+                        _ => None,
+                    });
+                    match ip {
+                        Some(ip) => {
+                            let addr = SocketAddrV4::new(ip, resolving.port);
+                            self.open(addr, resolving.request);
+                        }
+                        None => resolving.request.fail(std::io::ErrorKind::NotFound.into()),
+                    }
+                }
+                Err(dns::GetQueryResultError::Pending)
+                    if resolving.started.elapsed() < CONNECT_TIMEOUT =>
+                {
+                    self.resolving.push(resolving);
+                }
+                Err(dns::GetQueryResultError::Pending) => {
+                    dns.cancel_query(resolving.query);
+                    resolving.request.fail(std::io::ErrorKind::TimedOut.into());
+                }
+                Err(dns::GetQueryResultError::Failed) => {
+                    resolving.request.fail(std::io::ErrorKind::NotFound.into());
+                }
+            }
+        }
+
+        let mut over = Vec::new();
+        for (handle, flow) in self.flows.iter_mut() {
+            if !flow.relay(self.sockets.get_mut::<tcp::Socket>(*handle)) {
+                over.push(*handle);
+            }
+        }
+        for handle in over {
+            self.flows.remove(&handle);
+            self.sockets.remove(handle);
+        }
+
+        // Send what was relayed right away
+        self.iface.poll(
+            smoltcp::time::Instant::now(),
+            &mut self.device,
+            &mut self.sockets,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("1.1.1.1", 443 => Remote::Addr("1.1.1.1:443".parse().unwrap()))]
+    #[test_case("example.com", 80 => Remote::Name("example.com".to_string(), 80))]
+    #[test_case("::1", 80 => Remote::Name("::1".to_string(), 80))]
+    fn remote_new(host: &str, port: u16) -> Remote {
+        Remote::new(host, port)
+    }
+}
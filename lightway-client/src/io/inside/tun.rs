@@ -5,6 +5,7 @@ use std::{net::Ipv4Addr, sync::Arc};
 use anyhow::Result;
 use async_trait::async_trait;
 use bytes::BytesMut;
+use ipnet::Ipv4Net;
 use pnet_packet::ipv4::Ipv4Packet;
 
 use lightway_app_utils::{Tun as AppUtilsTun, TunConfig};
@@ -19,12 +20,19 @@ pub struct Tun {
     tun: AppUtilsTun,
     ip: Ipv4Addr,
     dns_ip: Ipv4Addr,
+    /// Subnets behind this client, whose packets keep their destination
+    site_subnets: Vec<Ipv4Net>,
 }
 
 impl Tun {
     pub async fn new(tun: &TunConfig, ip: Ipv4Addr, dns_ip: Ipv4Addr) -> Result<Self> {
         let tun = AppUtilsTun::direct(tun).await?;
-        Ok(Tun { tun, ip, dns_ip })
+        Ok(Tun {
+            tun,
+            ip,
+            dns_ip,
+            site_subnets: Vec::new(),
+        })
     }
 
     #[cfg(feature = "io-uring")]
@@ -36,7 +44,35 @@ impl Tun {
         iouring_sqpoll_idle_time: Duration,
     ) -> Result<Self> {
         let tun = AppUtilsTun::iouring(tun, iouring_ring_size, iouring_sqpoll_idle_time).await?;
-        Ok(Tun { tun, ip, dns_ip })
+        Ok(Tun {
+            tun,
+            ip,
+            dns_ip,
+            site_subnets: Vec::new(),
+        })
+    }
+
+    /// Forward packets to `site_subnets` without translating their
+    /// destination
+    pub fn with_site_subnets(mut self, site_subnets: Vec<Ipv4Net>) -> Self {
+        self.site_subnets = site_subnets;
+        self
+    }
+
+    /// Update destination IP from server provided inside ip to TUN
+    /// device ip, unless the packet is to a subnet behind this client
+    fn update_destination(&self, pkt: &mut [u8]) {
+        if let Some(packet) = Ipv4Packet::new(pkt) {
+            let destination = packet.get_destination();
+            if self
+                .site_subnets
+                .iter()
+                .any(|subnet| subnet.contains(&destination))
+            {
+                return;
+            }
+        }
+        ipv4_update_destination(pkt, self.ip);
     }
 
     pub fn if_index(&self) -> std::io::Result<u32> {
@@ -57,8 +93,7 @@ impl<ExtAppState: Send + Sync> InsideIORecv<ExtAppState> for Tun {
     /// Api to send packet in the tunnel
     fn try_send(&self, mut pkt: BytesMut, ip_config: Option<InsideIpConfig>) -> Result<usize> {
         let pkt_len = pkt.len();
-        // Update destination IP from server provided inside ip to TUN device ip
-        ipv4_update_destination(pkt.as_mut(), self.ip);
+        self.update_destination(pkt.as_mut());
 
         // Update source IP from server DNS ip to TUN DNS ip
         if let Some(ip_config) = ip_config {
@@ -95,8 +130,7 @@ impl<ExtAppState: Send + Sync> InsideIOSendCallback<ConnectionState<ExtAppState>
         mut buf: BytesMut,
         state: &mut ConnectionState<ExtAppState>,
     ) -> IOCallbackResult<usize> {
-        // Update destination IP from server provided inside ip to TUN device ip
-        ipv4_update_destination(buf.as_mut(), self.ip);
+        self.update_destination(buf.as_mut());
 
         // Update source IP from server DNS ip to TUN DNS ip
         if let Some(ip_config) = state.ip_config {
@@ -3,6 +3,8 @@ pub mod udp;
 
 pub use tcp::Tcp;
 pub use udp::Udp;
+#[cfg(desktop)]
+pub use udp::multipath::{Multipath, MultipathConfig, MultipathPolicy};
 
 use anyhow::Result;
 use async_trait::async_trait;
@@ -16,6 +16,8 @@ use tokio::net::UdpSocket;
 
 #[cfg(batch_receive)]
 mod batch_receive;
+#[cfg(desktop)]
+pub mod multipath;
 
 pub struct Udp {
     sock: Arc<tokio::net::UdpSocket>,
@@ -0,0 +1,434 @@
+//! Multipath UDP: a single connection carried over several outside
+//! links (e.g. Wi-Fi and a cellular modem), each with its own socket to
+//! the server. How packets are spread over the links is set by
+//! [`MultipathPolicy`]. Once the server grants the policy's mode, it
+//! learns about each link from the multipath mode in the packet header
+//! and, after the link answers a path challenge, adds it as a path of
+//! the session rather than floating to it.
+
+use super::Udp;
+use crate::io::outside::{OutsideIO, OutsideSocket};
+use crate::keepalive::{self, KeepaliveReplies};
+use crate::server_selection::KeepaliveProbe;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use lightway_core::{
+    ConnectionResult, IOCallbackResult, KeepaliveId, MultipathMode, OutsideIOSendCallback,
+    OutsideIOSendCallbackArg,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+/// How long to wait for the keepalive reply on a probed link
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often to check whether the connection is online before probing
+const ONLINE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Value of [`Multipath::probing`] when no link is being probed
+const NO_PROBE: usize = usize::MAX;
+
+/// How packets are spread over the links of a multipath connection
+#[derive(
+    Debug, Clone, Copy, JsonSchema, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize, Default,
+)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum MultipathPolicy {
+    /// Send on the first link, in configured order, answering probes
+    #[default]
+    ActiveBackup,
+    /// Send on the link with the lowest probed round trip time
+    LowestRtt,
+    /// Send every packet on every link, trading bandwidth for loss
+    Redundant,
+}
+
+impl MultipathPolicy {
+    /// Multipath mode announced to the server in each packet header
+    fn mode(self) -> MultipathMode {
+        match self {
+            Self::ActiveBackup | Self::LowestRtt => MultipathMode::Scheduled,
+            Self::Redundant => MultipathMode::Redundant,
+        }
+    }
+
+    /// Pick the link to send on given the probed round trip time of each
+    /// link, `None` for an unanswered probe. Stays on `current` when no
+    /// link answered.
+    fn select(self, rtts: &[Option<Duration>], current: usize) -> usize {
+        match self {
+            Self::ActiveBackup => rtts.iter().position(Option::is_some),
+            Self::LowestRtt => rtts
+                .iter()
+                .enumerate()
+                .filter_map(|(index, rtt)| rtt.map(|rtt| (rtt, index)))
+                .min()
+                .map(|(_, index)| index),
+            Self::Redundant => None,
+        }
+        .unwrap_or(current)
+    }
+}
+
+/// Multipath configuration
+#[derive(Clone, Debug, Default)]
+pub struct MultipathConfig {
+    /// Outside links, each an interface name or a local IP address
+    pub links: Vec<String>,
+    /// How packets are spread over `links`
+    pub policy: MultipathPolicy,
+}
+
+impl MultipathConfig {
+    /// Multipath is used when at least one link is configured
+    pub fn is_enabled(&self) -> bool {
+        !self.links.is_empty()
+    }
+}
+
+struct Path {
+    link: String,
+    udp: Udp,
+}
+
+/// Outside IO spreading a connection over several UDP sockets, one per
+/// link.
+pub struct Multipath {
+    paths: Vec<Path>,
+    policy: MultipathPolicy,
+    /// Link scheduled sends go to
+    active: AtomicUsize,
+    /// Link a keepalive probe is pinned to, [`NO_PROBE`] otherwise
+    probing: AtomicUsize,
+    /// Link to start the next receive from, so that no link starves
+    next_recv: AtomicUsize,
+}
+
+impl Multipath {
+    pub async fn new(
+        remote_addr: SocketAddr,
+        config: &MultipathConfig,
+        #[cfg(all(linux, not(feature = "mobile")))] fwmark: u32,
+    ) -> Result<Self> {
+        let mut paths = Vec::with_capacity(config.links.len());
+        for link in &config.links {
+            let sock = bind_link(link, remote_addr)
+                .with_context(|| format!("Failed to bind outside socket to link {link}"))?;
+            let udp = Udp::new(
+                remote_addr,
+                Some(sock),
+                #[cfg(all(linux, not(feature = "mobile")))]
+                fwmark,
+            )
+            .await?;
+            paths.push(Path {
+                link: link.clone(),
+                udp,
+            });
+        }
+        tracing::info!(links = ?config.links, policy = ?config.policy, "Using multipath outside IO");
+
+        Ok(Self {
+            paths,
+            policy: config.policy,
+            active: AtomicUsize::new(0),
+            probing: AtomicUsize::new(NO_PROBE),
+            next_recv: AtomicUsize::new(0),
+        })
+    }
+
+    /// Probe every link in turn with a keepalive and reschedule sends
+    /// according to the policy.
+    async fn probe_links(
+        self: &Arc<Self>,
+        conn: impl keepalive::Connection + Clone + 'static,
+        keepalive_replies: &KeepaliveReplies,
+    ) {
+        let mut rtts = Vec::with_capacity(self.paths.len());
+        for (index, path) in self.paths.iter().enumerate() {
+            let probe = KeepaliveProbe::new(
+                LinkConnection {
+                    conn: conn.clone(),
+                    multipath: self.clone(),
+                    index,
+                },
+                keepalive_replies.clone(),
+            );
+            let rtt = probe.rtts(1, PROBE_TIMEOUT).await.first().copied();
+            tracing::debug!(link = path.link, ?rtt, "Probed multipath link");
+            rtts.push(rtt);
+        }
+
+        let current = self.active.load(Ordering::Relaxed);
+        let active = self.policy.select(&rtts, current);
+        if active != current {
+            tracing::info!(
+                from = self.paths[current].link,
+                to = self.paths[active].link,
+                "Switching multipath link"
+            );
+            self.active.store(active, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Probe the links of `multipath` every `interval` once `is_online`,
+/// moving scheduled sends off links which stop answering.
+///
+/// Probing each link also registers it with the server: redundant copies
+/// of a packet are dropped as replays, so a link is only added as a path
+/// once a packet first arrives on it.
+pub(crate) async fn probe_links(
+    multipath: Arc<Multipath>,
+    conn: impl keepalive::Connection + Clone + 'static,
+    keepalive_replies: KeepaliveReplies,
+    is_online: impl Fn() -> bool + Send,
+    interval: Duration,
+) {
+    while !is_online() {
+        tokio::time::sleep(ONLINE_POLL_INTERVAL).await;
+    }
+
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        multipath
+            .probe_links(conn.clone(), &keepalive_replies)
+            .await;
+    }
+}
+
+/// Connection whose keepalives are sent on a single link.
+///
+/// Sends only stay pinned for the keepalive itself: the connection lock
+/// is held throughout, so no data packet is sent on the probed link.
+struct LinkConnection<C> {
+    conn: C,
+    multipath: Arc<Multipath>,
+    index: usize,
+}
+
+impl<C: keepalive::Connection> keepalive::Connection for LinkConnection<C> {
+    fn keepalive(&self) -> ConnectionResult<Option<KeepaliveId>> {
+        self.multipath.probing.store(self.index, Ordering::Relaxed);
+        let result = self.conn.keepalive();
+        self.multipath.probing.store(NO_PROBE, Ordering::Relaxed);
+        result
+    }
+}
+
+/// Create a UDP socket sending out of `link`, either an interface name or
+/// a local IP address.
+fn bind_link(link: &str, remote_addr: SocketAddr) -> std::io::Result<tokio::net::UdpSocket> {
+    let sock = socket2::Socket::new(
+        socket2::Domain::for_address(remote_addr),
+        socket2::Type::DGRAM,
+        Some(socket2::Protocol::UDP),
+    )?;
+    let local_ip = match link.parse::<IpAddr>() {
+        Ok(ip) => ip,
+        Err(_) => {
+            bind_device(&sock, link, remote_addr.is_ipv6())?;
+            if remote_addr.is_ipv6() {
+                IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+            } else {
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+            }
+        }
+    };
+    sock.bind(&SocketAddr::new(local_ip, 0).into())?;
+    sock.set_nonblocking(true)?;
+    tokio::net::UdpSocket::from_std(sock.into())
+}
+
+#[cfg(linux)]
+fn bind_device(sock: &socket2::Socket, name: &str, _ipv6: bool) -> std::io::Result<()> {
+    sock.bind_device(Some(name.as_bytes()))
+}
+
+#[cfg(macos)]
+fn bind_device(sock: &socket2::Socket, name: &str, ipv6: bool) -> std::io::Result<()> {
+    let name = std::ffi::CString::new(name).map_err(std::io::Error::other)?;
+    // SAFETY: `name` is a valid NUL terminated string
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    let index = std::num::NonZeroU32::new(index).ok_or_else(std::io::Error::last_os_error)?;
+    if ipv6 {
+        sock.bind_device_by_index_v6(Some(index))
+    } else {
+        sock.bind_device_by_index_v4(Some(index))
+    }
+}
+
+#[cfg(windows)]
+fn bind_device(_sock: &socket2::Socket, _name: &str, _ipv6: bool) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Multipath links must be local IP addresses on Windows",
+    ))
+}
+
+#[async_trait]
+impl OutsideIO for Multipath {
+    fn set_send_buffer_size(&self, size: usize) -> Result<()> {
+        self.paths
+            .iter()
+            .try_for_each(|path| path.udp.set_send_buffer_size(size))
+    }
+    fn set_recv_buffer_size(&self, size: usize) -> Result<()> {
+        self.paths
+            .iter()
+            .try_for_each(|path| path.udp.set_recv_buffer_size(size))
+    }
+
+    fn send_buffer_size(&self) -> Result<usize> {
+        self.paths[0].udp.send_buffer_size()
+    }
+    fn recv_buffer_size(&self) -> Result<usize> {
+        self.paths[0].udp.recv_buffer_size()
+    }
+
+    async fn poll(&self, interest: tokio::io::Interest) -> Result<tokio::io::Ready> {
+        let ready = self
+            .paths
+            .iter()
+            .map(|path| Box::pin(path.udp.sock.ready(interest)));
+        let (r, _, _) = futures::future::select_all(ready).await;
+        Ok(r?)
+    }
+
+    fn recv_buf(&self, buf: &mut bytes::BytesMut) -> IOCallbackResult<usize> {
+        let start = self.next_recv.fetch_add(1, Ordering::Relaxed);
+        for offset in 0..self.paths.len() {
+            let path = &self.paths[(start + offset) % self.paths.len()];
+            match OutsideIO::recv_buf(&path.udp, buf) {
+                IOCallbackResult::WouldBlock => continue,
+                other => return other,
+            }
+        }
+        IOCallbackResult::WouldBlock
+    }
+
+    fn into_io_send_callback(self: Arc<Self>) -> OutsideIOSendCallbackArg {
+        self
+    }
+
+    fn peer_addr(&self) -> SocketAddr {
+        self.paths[0].udp.peer_addr()
+    }
+
+    #[cfg(apple)]
+    fn reconnect(&self) {
+        self.paths
+            .iter()
+            .for_each(|path| OutsideIO::reconnect(&path.udp));
+    }
+
+    fn socket(&self) -> OutsideSocket {
+        OutsideIO::socket(&self.paths[0].udp)
+    }
+}
+
+impl OutsideIOSendCallback for Multipath {
+    fn send(&self, buf: &[u8]) -> IOCallbackResult<usize> {
+        // A failing link must not fail the connection: the prober moves
+        // scheduled sends off it, and the keepalive timeout catches all
+        // links being down.
+        let send = |path: &Path| match path.udp.send(buf) {
+            IOCallbackResult::Err(err) => {
+                path.udp.note_swallowed_send(&err);
+                IOCallbackResult::Ok(buf.len())
+            }
+            other => other,
+        };
+
+        let probing = self.probing.load(Ordering::Relaxed);
+        if probing != NO_PROBE {
+            return send(&self.paths[probing]);
+        }
+
+        match self.policy {
+            MultipathPolicy::Redundant => {
+                let mut result = IOCallbackResult::WouldBlock;
+                for path in &self.paths {
+                    let path_result = send(path);
+                    if !matches!(result, IOCallbackResult::Ok(_)) {
+                        result = path_result;
+                    }
+                }
+                result
+            }
+            MultipathPolicy::ActiveBackup | MultipathPolicy::LowestRtt => {
+                send(&self.paths[self.active.load(Ordering::Relaxed)])
+            }
+        }
+    }
+
+    fn send_gso(&self, _bufs: &[std::io::IoSlice<'_>], _gso_size: u16) -> IOCallbackResult<usize> {
+        IOCallbackResult::Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+
+    fn peer_addr(&self) -> SocketAddr {
+        self.paths[0].udp.peer_addr()
+    }
+
+    fn enable_pmtud_probe(&self) -> std::io::Result<()> {
+        self.paths
+            .iter()
+            .try_for_each(|path| path.udp.enable_pmtud_probe())
+    }
+
+    fn disable_pmtud_probe(&self) -> std::io::Result<()> {
+        self.paths
+            .iter()
+            .try_for_each(|path| path.udp.disable_pmtud_probe())
+    }
+
+    fn multipath_mode(&self) -> MultipathMode {
+        self.policy.mode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn rtts(ms: &[Option<u64>]) -> Vec<Option<Duration>> {
+        ms.iter().map(|ms| ms.map(Duration::from_millis)).collect()
+    }
+
+    #[test_case(MultipathPolicy::ActiveBackup => MultipathMode::Scheduled)]
+    #[test_case(MultipathPolicy::LowestRtt => MultipathMode::Scheduled)]
+    #[test_case(MultipathPolicy::Redundant => MultipathMode::Redundant)]
+    fn policy_mode(policy: MultipathPolicy) -> MultipathMode {
+        policy.mode()
+    }
+
+    #[test_case(MultipathPolicy::ActiveBackup, &[Some(80), Some(20)], 1 => 0; "active backup prefers first")]
+    #[test_case(MultipathPolicy::ActiveBackup, &[None, Some(20), Some(10)], 0 => 1; "active backup fails over")]
+    #[test_case(MultipathPolicy::LowestRtt, &[Some(80), Some(20), Some(50)], 0 => 1; "lowest rtt")]
+    #[test_case(MultipathPolicy::LowestRtt, &[Some(80), None], 1 => 0; "lowest rtt skips unanswered")]
+    #[test_case(MultipathPolicy::LowestRtt, &[None, None], 1 => 1; "none answered keeps current")]
+    #[test_case(MultipathPolicy::Redundant, &[Some(80), Some(20)], 0 => 0; "redundant keeps current")]
+    fn policy_select(policy: MultipathPolicy, ms: &[Option<u64>], current: usize) -> usize {
+        policy.select(&rtts(ms), current)
+    }
+
+    #[tokio::test]
+    async fn binds_to_local_address() {
+        let sock = bind_link("127.0.0.1", "127.0.0.1:27690".parse().unwrap()).unwrap();
+        assert_eq!(
+            sock.local_addr().unwrap().ip(),
+            IpAddr::V4(Ipv4Addr::LOCALHOST)
+        );
+    }
+}
@@ -4,29 +4,107 @@ use std::{
     sync::{Arc, Mutex, Weak},
     time::Duration,
 };
-use tokio::{sync::mpsc, task::JoinHandle};
+use tokio::{
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+};
 use tokio_util::sync::{CancellationToken, DropGuard};
 
 use crate::ConnectionState;
+use lightway_core::{KeepaliveId, KeepaliveStats};
+
+/// Replies buffered for each [`KeepaliveReplies`] probe
+const KEEPALIVE_REPLIES_CAPACITY: usize = 16;
+
+pub trait Connection: Send + Sync {
+    /// Send a keepalive, returning its id, `None` when nothing was sent
+    fn keepalive(&self) -> lightway_core::ConnectionResult<Option<KeepaliveId>>;
 
-pub trait Connection: Send {
-    fn keepalive(&self) -> lightway_core::ConnectionResult<()>;
+    /// Round trip statistics of the keepalives sent, `None` when unknown
+    fn keepalive_stats(&self) -> Option<KeepaliveStats> {
+        None
+    }
 }
 
 impl<T: Send + Sync> Connection for Weak<Mutex<lightway_core::Connection<ConnectionState<T>>>> {
-    fn keepalive(&self) -> lightway_core::ConnectionResult<()> {
+    fn keepalive(&self) -> lightway_core::ConnectionResult<Option<KeepaliveId>> {
         let Some(conn) = self.upgrade() else {
-            return Ok(());
+            return Ok(None);
         };
         let mut conn = conn.lock().unwrap();
         conn.keepalive()
     }
+
+    fn keepalive_stats(&self) -> Option<KeepaliveStats> {
+        let conn = self.upgrade()?;
+        let conn = conn.lock().unwrap();
+        Some(conn.keepalive_stats())
+    }
+}
+
+/// The keepalive replies a connection receives, so that probes wait for
+/// the reply to their own keepalive rather than to any keepalive.
+#[derive(Clone)]
+pub struct KeepaliveReplies(broadcast::Sender<Option<KeepaliveId>>);
+
+impl Default for KeepaliveReplies {
+    fn default() -> Self {
+        Self(broadcast::channel(KEEPALIVE_REPLIES_CAPACITY).0)
+    }
+}
+
+impl KeepaliveReplies {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A [`lightway_core::Event::KeepaliveReply`] was received
+    pub fn received(&self, id: Option<KeepaliveId>) {
+        // No probe may be waiting
+        let _ = self.0.send(id);
+    }
+
+    /// Send a keepalive on `conn`, returning whether it was answered
+    /// within `timeout`. Replies without an id, from peers which do not
+    /// echo keepalive stamps, answer any keepalive.
+    pub async fn probe(
+        &self,
+        conn: &(impl Connection + ?Sized),
+        timeout: Duration,
+    ) -> lightway_core::ConnectionResult<bool> {
+        // Subscribes before sending, not to miss the reply
+        let mut replies = self.0.subscribe();
+        let Some(id) = conn.keepalive()? else {
+            return Ok(false);
+        };
+        let reply = async {
+            loop {
+                match replies.recv().await {
+                    Ok(None) => return true,
+                    Ok(Some(reply)) if reply == id => return true,
+                    Ok(Some(_)) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => return false,
+                }
+            }
+        };
+        Ok(tokio::time::timeout(timeout, reply).await.unwrap_or(false))
+    }
 }
 
 pub trait SleepManager: Send {
     fn sleep_for_interval(&self) -> impl std::future::Future<Output = ()> + std::marker::Send;
     fn sleep_for_timeout(&self) -> impl std::future::Future<Output = ()> + std::marker::Send;
     fn continuous(&self) -> bool;
+
+    /// A keepalive was sent
+    fn keepalive_sent(&self) {}
+
+    /// A keepalive reply was received, with the connection's keepalive
+    /// statistics if known
+    fn reply_received(&self, _stats: Option<KeepaliveStats>) {}
+
+    /// Traffic other than keepalives was seen between keepalives
+    fn traffic(&self) {}
 }
 
 #[derive(Clone)]
@@ -51,6 +129,125 @@ impl SleepManager for Config {
     }
 }
 
+/// Bounds of [`Adaptive`] keepalives
+#[derive(Clone, Copy, Debug)]
+pub struct AdaptiveConfig {
+    /// Least timeout derived from round trip times
+    pub min_timeout: Duration,
+    /// Most timeout derived from round trip times
+    pub max_timeout: Duration,
+    /// Most the interval backs off to on idle links
+    pub max_interval: Duration,
+}
+
+/// Keepalive timings adapting to the link.
+///
+/// The timeout is derived from the measured round trip times alone: one
+/// TCP style retransmission timeout for the keepalive and each of its
+/// [`ADAPTIVE_RETRIES`] resends, within `min_timeout` and `max_timeout`.
+/// Resends of an unanswered keepalive are spread over the timeout, at
+/// most the configured interval apart. In continuous mode the interval
+/// doubles with every reply received on an idle link, up to
+/// `max_interval`, saving battery on mobile links.
+pub struct Adaptive {
+    config: Config,
+    bounds: AdaptiveConfig,
+    state: Mutex<AdaptiveState>,
+}
+
+/// Resends of an unanswered keepalive the adaptive timeout leaves room for
+pub const ADAPTIVE_RETRIES: u32 = 3;
+
+struct AdaptiveState {
+    /// Interval between keepalives on an idle link
+    idle_interval: Duration,
+    timeout: Duration,
+    /// Whether a reply is pending
+    pending: bool,
+    /// Whether traffic was seen since the last reply
+    traffic: bool,
+}
+
+impl Adaptive {
+    pub fn new(config: Config, bounds: AdaptiveConfig) -> Self {
+        let state = AdaptiveState {
+            idle_interval: config.interval,
+            timeout: config.timeout,
+            pending: false,
+            traffic: false,
+        };
+        Self {
+            config,
+            bounds,
+            state: Mutex::new(state),
+        }
+    }
+
+    fn interval(&self) -> Duration {
+        let state = self.state.lock().unwrap();
+        if state.pending {
+            (state.timeout / (ADAPTIVE_RETRIES + 1)).min(self.config.interval)
+        } else {
+            state.idle_interval
+        }
+    }
+
+    fn timeout(&self) -> Duration {
+        self.state.lock().unwrap().timeout
+    }
+}
+
+impl SleepManager for Adaptive {
+    async fn sleep_for_interval(&self) {
+        tokio::time::sleep(self.interval()).await
+    }
+
+    async fn sleep_for_timeout(&self) {
+        tokio::time::sleep(self.timeout()).await
+    }
+
+    fn continuous(&self) -> bool {
+        self.config.continuous
+    }
+
+    fn keepalive_sent(&self) {
+        self.state.lock().unwrap().pending = true;
+    }
+
+    fn reply_received(&self, stats: Option<KeepaliveStats>) {
+        let mut state = self.state.lock().unwrap();
+        state.pending = false;
+
+        if let Some(rto) = stats.and_then(|stats| stats.rto()) {
+            let timeout = rto * (ADAPTIVE_RETRIES + 1);
+            state.timeout = timeout.clamp(
+                self.bounds.min_timeout.min(self.bounds.max_timeout),
+                self.bounds.max_timeout,
+            );
+        }
+
+        state.idle_interval = if std::mem::take(&mut state.traffic) {
+            self.config.interval
+        } else {
+            (state.idle_interval * 2)
+                .min(self.bounds.max_interval)
+                .max(self.config.interval)
+        };
+        tracing::debug!(
+            interval = ?state.idle_interval,
+            timeout = ?state.timeout,
+            ?stats,
+            "Adapted keepalives"
+        );
+    }
+
+    fn traffic(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.traffic = true;
+        state.idle_interval = self.config.interval;
+    }
+}
+
 #[derive(Debug)]
 pub enum Message {
     Online,
@@ -59,6 +256,7 @@ pub enum Message {
     NetworkChange,
     TracerDeltaExceeded,
     Suspend,
+    IdleInterval(Option<Duration>),
 }
 
 pub enum KeepaliveResult {
@@ -125,6 +323,14 @@ impl Keepalive {
     pub async fn suspend(&self) {
         let _ = self.tx.send(Message::Suspend).await;
     }
+
+    /// Signal the interval between keepalives on an idle link, in place
+    /// of the configured one, e.g. following the NAT binding lifetime.
+    /// Resends of unanswered keepalives keep the configured interval.
+    /// `None` reverts to the configured interval.
+    pub async fn set_idle_interval(&self, interval: Option<Duration>) {
+        let _ = self.tx.send(Message::IdleInterval(interval)).await;
+    }
 }
 
 async fn keepalive<CONFIG: SleepManager, CONNECTION: Connection>(
@@ -147,6 +353,7 @@ async fn keepalive<CONFIG: SleepManager, CONNECTION: Connection>(
     }
 
     let mut state = State::Inactive;
+    let mut idle_interval = None;
 
     // Unlike the interval timeout this should not be reset if the
     // select picks a different case.
@@ -168,6 +375,10 @@ async fn keepalive<CONFIG: SleepManager, CONNECTION: Connection>(
                         }
                     },
                     Message::OutsideActivity => {
+                        // A reply pending may be this activity itself
+                        if matches!(state, State::Waiting) {
+                            config.traffic();
+                        }
                         // The interval timer is restarted on the next
                         // iteration of the loop. IOW just by taking
                         // this branch of the select we have achieved
@@ -177,6 +388,7 @@ async fn keepalive<CONFIG: SleepManager, CONNECTION: Connection>(
                         continue
                     },
                     Message::ReplyReceived => {
+                        config.reply_received(conn.keepalive_stats());
                         state = if config.continuous() {
                             State::Waiting
                         } else {
@@ -208,6 +420,10 @@ async fn keepalive<CONFIG: SleepManager, CONNECTION: Connection>(
                         state = State::Suspended;
                         timeout.as_mut().set(None.into())
                     },
+                    Message::IdleInterval(interval) => {
+                        tracing::debug!(?interval, "Keepalive idle interval");
+                        idle_interval = interval;
+                    },
                 }
             }
 
@@ -215,6 +431,7 @@ async fn keepalive<CONFIG: SleepManager, CONNECTION: Connection>(
                 if let Err(e) = conn.keepalive() {
                     tracing::error!("Send Keepalive failed: {e:?}");
                 }
+                config.keepalive_sent();
                 state = State::Pending;
                 if timeout.is_terminated() {
                     let fut = config.sleep_for_timeout().fuse();
@@ -222,10 +439,11 @@ async fn keepalive<CONFIG: SleepManager, CONNECTION: Connection>(
                 }
             }
 
-            _ = config.sleep_for_interval(), if matches!(state, State::Pending | State::Waiting) => {
+            _ = sleep_for_interval(&config, idle_interval.filter(|_| matches!(state, State::Waiting))), if matches!(state, State::Pending | State::Waiting) => {
                 if let Err(e) = conn.keepalive() {
                     tracing::error!("Send Keepalive failed: {e:?}");
                 }
+                config.keepalive_sent();
                 state = State::Pending;
                 if timeout.is_terminated() {
                     let fut = config.sleep_for_timeout().fuse();
@@ -244,6 +462,14 @@ async fn keepalive<CONFIG: SleepManager, CONNECTION: Connection>(
     }
 }
 
+/// Sleep for `idle_interval` if set, for the interval of `config` otherwise
+async fn sleep_for_interval(config: &impl SleepManager, idle_interval: Option<Duration>) {
+    match idle_interval {
+        Some(interval) => tokio::time::sleep(interval).await,
+        None => config.sleep_for_interval().await,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -271,9 +497,9 @@ mod tests {
     }
 
     impl Connection for MockConnection {
-        fn keepalive(&self) -> lightway_core::ConnectionResult<()> {
-            self.keepalive_count.fetch_add(1, Ordering::SeqCst);
-            Ok(())
+        fn keepalive(&self) -> lightway_core::ConnectionResult<Option<KeepaliveId>> {
+            let count = self.keepalive_count.fetch_add(1, Ordering::SeqCst);
+            Ok(Some(KeepaliveId(count as u32)))
         }
     }
 
@@ -436,6 +662,31 @@ mod tests {
         assert!(matches!(result, KeepaliveResult::Cancelled));
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn idle_interval_replaces_configured_one() {
+        let (sleep_manager, connection) = KeepaliveTestBuilder::new().build();
+
+        let (keepalive, task) = Keepalive::new(sleep_manager.clone(), connection.clone());
+        keepalive
+            .set_idle_interval(Some(Duration::from_secs(30)))
+            .await;
+        keepalive.online().await;
+
+        sleep(Duration::from_secs(29)).await;
+        assert_eq!(connection.keepalive_count(), 0);
+        sleep(Duration::from_millis(1010)).await;
+        assert_eq!(connection.keepalive_count(), 1);
+
+        // Unanswered, resent at the configured interval
+        sleep_manager.trigger_interval();
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(connection.keepalive_count(), 2);
+
+        drop(keepalive);
+        let result = task.await.unwrap().unwrap();
+        assert!(matches!(result, KeepaliveResult::Cancelled));
+    }
+
     #[test_case(true; "continuous")]
     #[test_case(false; "non-continuous")]
     #[tokio::test]
@@ -655,4 +906,89 @@ mod tests {
         let result = task.await.unwrap().unwrap();
         assert!(matches!(result, KeepaliveResult::Cancelled));
     }
+
+    fn adaptive() -> Adaptive {
+        let config = Config {
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(60),
+            continuous: true,
+            tracer_trigger_timeout: None,
+        };
+        let bounds = AdaptiveConfig {
+            min_timeout: Duration::from_secs(15),
+            max_timeout: Duration::from_secs(60),
+            max_interval: Duration::from_secs(60),
+        };
+        Adaptive::new(config, bounds)
+    }
+
+    fn stats(srtt_ms: u64, rttvar_ms: u64) -> KeepaliveStats {
+        KeepaliveStats {
+            srtt: Some(Duration::from_millis(srtt_ms)),
+            rttvar: Duration::from_millis(rttvar_ms),
+            ..Default::default()
+        }
+    }
+
+    #[test_case(None => Duration::from_secs(60); "no stats keeps timeout")]
+    #[test_case(Some(stats(100, 50)) => Duration::from_secs(15); "raised to min_timeout")]
+    #[test_case(Some(stats(2_000, 1_000)) => Duration::from_secs(24); "rto per attempt")]
+    #[test_case(Some(stats(10_000, 5_000)) => Duration::from_secs(60); "capped by max_timeout")]
+    fn adaptive_timeout(stats: Option<KeepaliveStats>) -> Duration {
+        let adaptive = adaptive();
+        adaptive.keepalive_sent();
+        adaptive.reply_received(stats);
+        adaptive.timeout()
+    }
+
+    #[test]
+    fn adaptive_timeout_has_minimum() {
+        let mut adaptive = adaptive();
+        adaptive.bounds.min_timeout = Duration::from_secs(45);
+        adaptive.reply_received(Some(stats(100, 50)));
+        assert_eq!(adaptive.timeout(), Duration::from_secs(45));
+    }
+
+    #[test]
+    fn adaptive_interval_backs_off_when_idle() {
+        let adaptive = adaptive();
+        let mut intervals = vec![adaptive.interval()];
+        for _ in 0..4 {
+            adaptive.keepalive_sent();
+            adaptive.reply_received(None);
+            intervals.push(adaptive.interval());
+        }
+        assert_eq!(
+            intervals,
+            [10, 20, 40, 60, 60].map(Duration::from_secs).to_vec()
+        );
+    }
+
+    #[test]
+    fn adaptive_interval_resets_on_traffic() {
+        let adaptive = adaptive();
+        adaptive.reply_received(None);
+        adaptive.reply_received(None);
+        assert_eq!(adaptive.interval(), Duration::from_secs(40));
+
+        adaptive.traffic();
+        assert_eq!(adaptive.interval(), Duration::from_secs(10));
+        // Traffic since the last reply, no back off
+        adaptive.reply_received(None);
+        assert_eq!(adaptive.interval(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn adaptive_resends_fit_in_timeout() {
+        let adaptive = adaptive();
+        adaptive.reply_received(None);
+        assert_eq!(adaptive.interval(), Duration::from_secs(20));
+        adaptive.keepalive_sent();
+        // At most the configured interval apart
+        assert_eq!(adaptive.interval(), Duration::from_secs(10));
+
+        adaptive.reply_received(Some(stats(2_000, 1_000)));
+        adaptive.keepalive_sent();
+        assert_eq!(adaptive.interval(), Duration::from_secs(6));
+    }
 }
@@ -2,11 +2,19 @@ pub mod config;
 mod debug;
 #[cfg(desktop)]
 pub mod dns_manager;
+pub mod dns_proxy;
+pub mod happy_eyeballs;
 pub mod io;
 pub mod keepalive;
+#[cfg(desktop)]
+pub mod nat_keepalive;
 pub mod platform;
 #[cfg(desktop)]
+pub mod reconnect;
+#[cfg(desktop)]
 pub mod route_manager;
+pub mod server_selection;
+pub mod session_ticket;
 
 #[cfg(feature = "mobile")]
 pub mod mobile;
@@ -20,6 +28,7 @@ use bytesize::ByteSize;
 use futures::{FutureExt, stream::FuturesUnordered};
 pub use io::inside::{InsideIO, InsideIORecv};
 use io::outside::OutsideIO;
+use ipnet::Ipv4Net;
 use keepalive::Keepalive;
 #[cfg(desktop)]
 use lightway_app_utils::NetworkChangeMonitor;
@@ -27,7 +36,11 @@ use lightway_app_utils::NetworkChangeMonitor;
 use lightway_app_utils::args::KeyShare;
 use lightway_app_utils::{
     ConnectionTicker, ConnectionTickerState, DplpmtudTimer, EventStream, EventStreamCallback,
-    PacketCodecFactoryType, TunConfig, args::Cipher, connection_ticker_cb,
+    PacketCodecFactoryType, TunConfig,
+    args::Cipher,
+    connection_ticker_cb,
+    obfuscation::{Obfuscation, ObfuscationKey, WebSocket},
+    split_dns::{ServerConfigPayload, SplitDnsConfig},
 };
 use lightway_core::{
     BuilderPredicates, ClientContextBuilder, ClientIpConfig, Connection, ConnectionError,
@@ -40,9 +53,21 @@ use tokio::sync::mpsc::UnboundedReceiver;
 use crate::debug::WiresharkKeyLogger;
 #[cfg(desktop)]
 use crate::dns_manager::{DnsConfigMode, DnsManager, DnsManagerError, DnsSetup};
-use crate::keepalive::Config as KeepaliveConfig;
+use crate::dns_proxy::{Blocklist, DnsProxy, DnsProxyConfig, DnsProxyInsideIO, QueryAction};
+use crate::happy_eyeballs::{Attempt, AttemptEventHandler, TransportPreferences};
+#[cfg(desktop)]
+use crate::io::outside::MultipathConfig;
+use crate::keepalive::{Config as KeepaliveConfig, KeepaliveReplies};
+#[cfg(linux)]
+use crate::platform::linux::kill_switch::{AllowedServer, KillSwitch, KillSwitchMode};
+#[cfg(desktop)]
+use crate::reconnect::{Backoff, ReconnectConfig, ReconnectEvent};
 #[cfg(desktop)]
-use crate::route_manager::{RouteManager, RouteMode, RouteUpdater};
+use crate::route_manager::{RouteManager, RouteMode, RouteUpdater, ServerBypassRoutes};
+use crate::server_selection::{
+    Candidate, ConnectionMeasurement, KeepaliveProbe, ServerSelection, ServerSelectionConfig,
+};
+use crate::session_ticket::{SessionTicketCache, SessionTicketSlot};
 #[cfg(batch_receive)]
 use lightway_core::MAX_IO_BATCH_SIZE;
 pub use lightway_core::{
@@ -78,6 +103,13 @@ use tracing::info;
 pub enum ClientConnectionMode {
     Stream(Option<TcpStream>),
     Datagram(Option<UdpSocket>),
+    /// Race UDP and TCP over `addrs`, the addresses of the server
+    /// `name` (e.g. `vpn.example.com:443`), see [`happy_eyeballs`].
+    /// Only over `server` when `addrs` is empty
+    Auto {
+        name: String,
+        addrs: Vec<SocketAddr>,
+    },
 }
 
 impl std::fmt::Debug for ClientConnectionMode {
@@ -85,6 +117,11 @@ impl std::fmt::Debug for ClientConnectionMode {
         match self {
             Self::Stream(_) => f.debug_tuple("Stream").finish(),
             Self::Datagram(_) => f.debug_tuple("Datagram").finish(),
+            Self::Auto { name, addrs } => f
+                .debug_struct("Auto")
+                .field("name", name)
+                .field("addrs", addrs)
+                .finish(),
         }
     }
 }
@@ -145,6 +182,11 @@ pub struct BestConnectionInfo {
     pub connection: ConnectionInfo,
     /// Inside IP configuration assigned by the server to the selected connection
     pub ip_config: InsideIpConfig,
+    /// Strategy the connection was selected with
+    pub strategy: ServerSelection,
+    /// Measurements of the connections online when selecting, in the
+    /// order they came online
+    pub measurements: Vec<ConnectionMeasurement>,
 }
 
 #[derive(educe::Educe)]
@@ -170,6 +212,20 @@ pub struct ClientConfig<ExtAppState: Send + Sync> {
     /// DNS IP to use in Tun device
     pub tun_dns_ip: Ipv4Addr,
 
+    /// Subnets behind this client, whose traffic is forwarded without
+    /// translating its addresses
+    pub site_subnets: Vec<Ipv4Net>,
+
+    /// Built-in DNS proxy, answering all DNS queries entering the
+    /// inside path. Disabled when `None`
+    pub dns_proxy: Option<DnsProxyConfig>,
+
+    /// Serve local proxies over a userspace network stack instead of
+    /// creating a Tun device, see [`io::inside::Netstack`]. Not used
+    /// when `inside_io` is supplied
+    #[cfg(desktop)]
+    pub netstack: Option<io::inside::NetstackConfig>,
+
     /// Key share group for post-quantum key exchange
     #[cfg(feature = "postquantum")]
     pub keyshare: KeyShare,
@@ -188,9 +244,35 @@ pub struct ClientConfig<ExtAppState: Send + Sync> {
     /// of only during network change events
     pub continuous_keepalive: bool,
 
+    /// Adapt keepalives to the link within these bounds, see
+    /// [`keepalive::Adaptive`]. Fixed timings when `None`
+    pub adaptive_keepalive: Option<keepalive::AdaptiveConfig>,
+
+    /// Probe and refresh the NAT binding of UDP connections, see
+    /// [`nat_keepalive`]. Disabled when `None`
+    #[cfg(desktop)]
+    pub nat_keepalive: Option<nat_keepalive::Config>,
+
+    /// NAT binding lifetimes learned on each network
+    #[cfg(desktop)]
+    #[educe(Debug(ignore))]
+    pub nat_lifetimes: nat_keepalive::NatLifetimes,
+
     /// How long to wait before selecting the preferred connection
     pub preferred_connection_wait_interval: Duration,
 
+    /// How to select the best connection
+    pub server_selection: ServerSelectionConfig,
+
+    /// Delay between starting two connection attempts to an `auto` mode
+    /// server
+    pub connection_attempt_delay: Duration,
+
+    /// Transport of the last connection to each `auto` mode server, tried
+    /// first when connecting again
+    #[educe(Debug(ignore))]
+    pub transport_preferences: TransportPreferences,
+
     /// Socket send buffer size
     pub sndbuf: ByteSize,
     /// Socket receive buffer size
@@ -200,6 +282,10 @@ pub struct ClientConfig<ExtAppState: Send + Sync> {
     #[cfg(batch_receive)]
     pub enable_batch_receive: bool,
 
+    /// Outside links to spread UDP connections over
+    #[cfg(desktop)]
+    pub multipath: MultipathConfig,
+
     /// Route Mode
     #[cfg(desktop)]
     pub route_mode: RouteMode,
@@ -208,6 +294,10 @@ pub struct ClientConfig<ExtAppState: Send + Sync> {
     #[cfg(linux)]
     pub fwmark: u32,
 
+    /// Kill switch mode (Linux only).
+    #[cfg(linux)]
+    pub kill_switch_mode: KillSwitchMode,
+
     /// DNS configuration mode
     #[cfg(desktop)]
     pub dns_config_mode: DnsConfigMode,
@@ -215,6 +305,10 @@ pub struct ClientConfig<ExtAppState: Send + Sync> {
     /// Enable Expresslane for Udp connections
     pub enable_expresslane: bool,
 
+    /// Move UDP connections to a freshly bound outside socket on network
+    /// change, keeping the connection
+    pub udp_migration: bool,
+
     /// Connect the outside UDP socket to the server so sends skip the
     /// per-packet route lookup (Apple platforms, Datagram only). The socket
     /// is re-connected when the network changes.
@@ -239,6 +333,10 @@ pub struct ClientConfig<ExtAppState: Send + Sync> {
     /// Base MTU for PMTU discovery
     pub pmtud_base_mtu: Option<u16>,
 
+    /// Obfuscation of the outside traffic, the server must match. An
+    /// empty WebSocket `host` is replaced by the server address.
+    pub obfuscation: Obfuscation,
+
     /// Enable IO-uring interface for Tunnel
     #[cfg(feature = "io-uring")]
     pub enable_tun_iouring: bool,
@@ -279,6 +377,21 @@ pub struct ClientConfig<ExtAppState: Send + Sync> {
     #[educe(Debug(ignore))]
     pub best_connection_selected_signal: Option<oneshot::Sender<BestConnectionInfo>>,
 
+    /// Retry when an established connection ends, see
+    /// [`client_with_reconnect`]. Disabled when `None`
+    #[cfg(desktop)]
+    pub reconnect: Option<ReconnectConfig>,
+
+    /// Signal for Lightway to report the progress of reconnecting
+    #[cfg(desktop)]
+    #[educe(Debug(ignore))]
+    pub reconnect_signal: Option<mpsc::UnboundedSender<ReconnectEvent>>,
+
+    /// Session tickets to resume connections with, shared with every
+    /// connection made with this config. Disabled when `None`
+    #[educe(Debug(ignore))]
+    pub session_tickets: Option<SessionTicketCache>,
+
     /// Enable TLS debugging
     #[cfg(feature = "debug")]
     pub tls_debug: bool,
@@ -317,6 +430,27 @@ impl<ExtAppState: Send + Sync> ClientConfig<ExtAppState> {
             tun_config.device_guid(parsed.as_u128());
         }
 
+        let dns_proxy = if config.dns_proxy {
+            Some(DnsProxyConfig {
+                upstream: config.dns_proxy_upstream.clone(),
+                cache_size: std::num::NonZeroUsize::new(config.dns_proxy_cache_size),
+                blocklist: match &config.dns_proxy_blocklist {
+                    Some(path) => Blocklist::load(path)?,
+                    None => Blocklist::default(),
+                },
+            })
+        } else {
+            None
+        };
+
+        #[cfg(desktop)]
+        let netstack = (config.socks_proxy.is_some() || config.http_proxy.is_some()).then(|| {
+            io::inside::NetstackConfig {
+                socks_proxy: config.socks_proxy,
+                http_proxy: config.http_proxy,
+            }
+        });
+
         // TODO: Fix in future PR
         tun_config
             .mtu(1350)
@@ -331,9 +465,14 @@ impl<ExtAppState: Send + Sync> ClientConfig<ExtAppState> {
             tun_local_ip: config.tun_local_ip,
             tun_peer_ip: config.tun_peer_ip,
             tun_dns_ip: config.tun_dns_ip,
+            site_subnets: config.site_subnets.clone(),
+            dns_proxy,
+            #[cfg(desktop)]
+            netstack,
             #[cfg(feature = "postquantum")]
             keyshare: config.keyshare,
             enable_expresslane: config.enable_expresslane,
+            udp_migration: config.udp_migration,
             #[cfg(apple)]
             enable_connected_udp: config.enable_connected_udp,
             expresslane_keys_rotation_interval: config.expresslane_keys_rotation_interval.into(),
@@ -342,20 +481,65 @@ impl<ExtAppState: Send + Sync> ClientConfig<ExtAppState> {
             keepalive_interval: config.keepalive_interval.into(),
             keepalive_timeout: config.keepalive_timeout.into(),
             continuous_keepalive: config.keepalive_continuous,
+            adaptive_keepalive: config
+                .keepalive_adaptive
+                .then(|| keepalive::AdaptiveConfig {
+                    min_timeout: config.keepalive_min_timeout.into(),
+                    max_timeout: config.keepalive_timeout.into(),
+                    max_interval: config.keepalive_max_interval.into(),
+                }),
+            #[cfg(desktop)]
+            nat_keepalive: config.keepalive_nat_probe.then(|| nat_keepalive::Config {
+                min_interval: config.keepalive_nat_min_interval.into(),
+                max_interval: config.keepalive_nat_max_interval.into(),
+            }),
+            #[cfg(desktop)]
+            nat_lifetimes: nat_keepalive::NatLifetimes::new(),
             tracer_packet_timeout: config.tracer_packet_timeout.into(),
             preferred_connection_wait_interval: config.preferred_connection_wait_interval.into(),
+            #[cfg(desktop)]
+            server_selection: ServerSelectionConfig {
+                strategy: config.server_selection,
+                probes: config.server_selection_probes,
+                priority_weight: config.server_selection_priority_weight.into(),
+                ..Default::default()
+            },
+            #[cfg(not(desktop))]
+            server_selection: ServerSelectionConfig::default(),
+            connection_attempt_delay: config.connection_attempt_delay.into(),
+            transport_preferences: TransportPreferences::new(),
             sndbuf: config.sndbuf,
             rcvbuf: config.rcvbuf,
             #[cfg(batch_receive)]
             enable_batch_receive: config.enable_batch_receive,
             #[cfg(desktop)]
+            multipath: MultipathConfig {
+                links: config.multipath_links.clone(),
+                policy: config.multipath_policy,
+            },
+            #[cfg(desktop)]
             route_mode: config.route_mode,
             #[cfg(linux)]
             fwmark: config.fwmark,
+            #[cfg(linux)]
+            kill_switch_mode: config.kill_switch,
             #[cfg(desktop)]
             dns_config_mode: config.dns_config_mode,
             enable_pmtud: config.enable_pmtud,
             pmtud_base_mtu: config.pmtud_base_mtu,
+            obfuscation: Obfuscation {
+                key: config
+                    .obfuscation_key
+                    .as_deref()
+                    .map(ObfuscationKey::read)
+                    .transpose()?,
+                max_padding: config.obfuscation_padding,
+                max_jitter: config.obfuscation_jitter.into(),
+                websocket: config.websocket.then(|| WebSocket::Client {
+                    host: config.websocket_host.clone().unwrap_or_default(),
+                    path: config.websocket_path.clone(),
+                }),
+            },
             #[cfg(feature = "io-uring")]
             enable_tun_iouring: config.enable_tun_iouring,
             #[cfg(feature = "io-uring")]
@@ -367,6 +551,15 @@ impl<ExtAppState: Send + Sync> ClientConfig<ExtAppState> {
             config_reload_signal,
             network_change_signal: None,
             best_connection_selected_signal: None,
+            #[cfg(desktop)]
+            reconnect: config.reconnect.then(|| ReconnectConfig {
+                initial_delay: config.reconnect_initial_delay.into(),
+                max_delay: config.reconnect_max_delay.into(),
+                max_attempts: std::num::NonZeroU32::new(config.reconnect_max_attempts),
+            }),
+            #[cfg(desktop)]
+            reconnect_signal: None,
+            session_tickets: config.session_resumption.then(SessionTicketCache::new),
             #[cfg(feature = "debug")]
             tls_debug: config.tls_debug,
             #[cfg(feature = "debug")]
@@ -416,27 +609,42 @@ pub struct ClientConnectionConfig<EventHandler: 'static + Send + EventCallback>
 
 impl<EventHandler: 'static + Send + EventCallback> ClientConnectionConfig<EventHandler> {
     pub async fn try_from_event_handler_and_connection_config(
+        event_handler: Option<EventHandler>,
+        config: config::ConnectionConfig,
+    ) -> Result<ClientConnectionConfig<EventHandler>> {
+        let server_addrs = resolve_server_addrs(&config.server).await?;
+        Self::try_from_event_handler_and_resolved_config(event_handler, config, server_addrs)
+    }
+
+    /// Like [`Self::try_from_event_handler_and_connection_config`], with
+    /// `config.server` already resolved to `server_addrs` (see
+    /// [`resolve_server_addrs`]), e.g. to build the configs of reconnect
+    /// attempts while DNS points at the tunnel. Only `auto` mode uses more
+    /// than the first address.
+    pub fn try_from_event_handler_and_resolved_config(
         event_handler: Option<EventHandler>,
         mut config: config::ConnectionConfig,
+        server_addrs: Vec<SocketAddr>,
     ) -> Result<ClientConnectionConfig<EventHandler>> {
         let auth = config.take_auth()?;
-        tracing::info!("Resolving server address: {}", &config.server);
-
-        let server_addr: SocketAddr = tokio::net::lookup_host(config.server)
-            .await?
-            .next()
+        let server = *server_addrs
+            .first()
             .ok_or_else(|| anyhow!("No addresses resolved"))?;
 
         let mode = match config.mode {
             lightway_app_utils::args::ConnectionType::Tcp => ClientConnectionMode::Stream(None),
             lightway_app_utils::args::ConnectionType::Udp => ClientConnectionMode::Datagram(None),
+            lightway_app_utils::args::ConnectionType::Auto => ClientConnectionMode::Auto {
+                name: config.server.clone(),
+                addrs: server_addrs,
+            },
         };
 
         Ok(ClientConnectionConfig {
             mode,
             cipher: config.cipher,
             server_dn: config.server_dn,
-            server: server_addr,
+            server,
             auth,
             cert_content: config.ca_cert.ok_or(anyhow!(
                 "ca_cert missing; ensure Config::take_servers() was called first"
@@ -447,6 +655,46 @@ impl<EventHandler: 'static + Send + EventCallback> ClientConnectionConfig<EventH
             event_handler,
         })
     }
+
+    /// Addresses and transports connections made with this config may use
+    pub fn endpoints(&self) -> Vec<(SocketAddr, ConnectionType)> {
+        match &self.mode {
+            ClientConnectionMode::Stream(_) => vec![(self.server, ConnectionType::Stream)],
+            ClientConnectionMode::Datagram(_) => vec![(self.server, ConnectionType::Datagram)],
+            ClientConnectionMode::Auto { addrs, .. } => {
+                let addrs = if addrs.is_empty() {
+                    std::slice::from_ref(&self.server)
+                } else {
+                    addrs
+                };
+                happy_eyeballs::attempts(addrs, ConnectionType::Datagram)
+                    .into_iter()
+                    .map(|attempt| (attempt.addr, attempt.connection_type))
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Resolve a `<hostname>:<port>` server address.
+pub async fn resolve_server(server: &str) -> Result<SocketAddr> {
+    tracing::info!("Resolving server address: {}", server);
+    tokio::net::lookup_host(server)
+        .await?
+        .next()
+        .ok_or_else(|| anyhow!("No addresses resolved"))
+}
+
+/// Resolve all addresses of a `<hostname>:<port>` server, A and AAAA
+/// records, alternating address families. The first address is the one
+/// [`resolve_server`] returns.
+pub async fn resolve_server_addrs(server: &str) -> Result<Vec<SocketAddr>> {
+    tracing::info!("Resolving server addresses: {}", server);
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host(server).await?.collect();
+    if addrs.is_empty() {
+        return Err(anyhow!("No addresses resolved"));
+    }
+    Ok(happy_eyeballs::interleave_address_families(addrs))
 }
 
 #[derive(educe::Educe)]
@@ -519,6 +767,10 @@ pub struct ConnectionState<ExtAppState: Send + Sync = ()> {
     pub ticker: ConnectionTicker,
     /// InsideIpConfig received from server
     pub ip_config: Option<InsideIpConfig>,
+    /// Split DNS configuration pushed by the server
+    pub split_dns: Option<SplitDnsConfig>,
+    /// Subnets behind this client, see [`ClientConfig::site_subnets`]
+    pub site_subnets: Vec<Ipv4Net>,
     /// Other extended state
     pub extended: ExtAppState,
 }
@@ -532,9 +784,11 @@ impl<ExtAppState: Send + Sync> ConnectionTickerState for ConnectionState<ExtAppS
 async fn handle_events<A: 'static + Send + EventCallback, ExtAppState: Send + Sync>(
     mut stream: EventStream,
     keepalive: Keepalive,
+    keepalive_replies: KeepaliveReplies,
     weak: Weak<Mutex<Connection<ConnectionState<ExtAppState>>>>,
     enable_encoding_when_online: bool,
     mut event_handler: Option<A>,
+    session_ticket: Option<SessionTicketSlot>,
     connected_signal: oneshot::Sender<()>,
     disconnected_signal: oneshot::Sender<()>,
 ) {
@@ -552,6 +806,10 @@ async fn handle_events<A: 'static + Send + EventCallback, ExtAppState: Send + Sy
                         break; // Connection disconnected.
                     };
 
+                    if conn.lock().unwrap().is_session_resumed() {
+                        info!("TLS session resumed");
+                    }
+
                     if enable_encoding_when_online
                         && let Err(e) = conn.lock().unwrap().set_encoding(true)
                     {
@@ -563,7 +821,10 @@ async fn handle_events<A: 'static + Send + EventCallback, ExtAppState: Send + Sy
                     let _ = disconnected_tx.send(());
                 }
             }
-            Event::KeepaliveReply => keepalive.reply_received().await,
+            Event::KeepaliveReply { id } => {
+                keepalive_replies.received(*id);
+                keepalive.reply_received().await
+            }
             Event::FirstPacketReceived => {
                 info!("First outside packet received");
             }
@@ -573,6 +834,23 @@ async fn handle_events<A: 'static + Send + EventCallback, ExtAppState: Send + Sy
             Event::EncodingStateChanged { enabled } => {
                 info!("Encoding state changed to {enabled}");
             }
+            Event::ServerConfig(data) => {
+                let Some(conn) = weak.upgrade() else {
+                    break; // Connection disconnected.
+                };
+                match ServerConfigPayload::from_bytes(data) {
+                    Ok(payload) => {
+                        info!(split_dns = ?payload.split_dns, "Server config received");
+                        conn.lock().unwrap().app_state_mut().split_dns = payload.split_dns;
+                    }
+                    Err(e) => tracing::warn!("Ignoring invalid server config: {e}"),
+                }
+            }
+            Event::SessionTicket(ticket) => {
+                if let Some(slot) = &session_ticket {
+                    slot.store(ticket.clone());
+                }
+            }
 
             // Server only events
             Event::SessionIdRotationStarted { .. }
@@ -648,6 +926,66 @@ pub async fn outside_io_task<ExtAppState: Send + Sync>(
     }
 }
 
+/// Runs [`outside_io_task`] on a UDP connection, moving the connection to
+/// a newly bound socket on each `migrate` request.
+///
+/// The connection and its DTLS state are kept: the keepalive sent right
+/// away on the new socket lets the server find the session by its ID and
+/// float it to the new address, after which the server rotates the
+/// session ID (see `docs/udp_session_id.md`). A network handover thus
+/// costs a round trip rather than a handshake.
+///
+/// `current` is updated along with the connection, for the users of the
+/// socket outside the connection.
+async fn migrating_outside_io_task<ExtAppState: Send + Sync>(
+    conn: Arc<Mutex<Connection<ConnectionState<ExtAppState>>>>,
+    mtu: usize,
+    current: SharedOutsideIO,
+    keepalive: Keepalive,
+    options: UdpSocketOptions,
+    obfuscation: Obfuscation,
+    mut migrate: mpsc::Receiver<()>,
+) -> Result<()> {
+    let mut outside_io = current.read().unwrap().clone();
+    loop {
+        tokio::select! {
+            result = outside_io_task(
+                conn.clone(),
+                mtu,
+                ConnectionType::Datagram,
+                outside_io.clone(),
+                keepalive.clone(),
+                None,
+            ) => return result,
+            Some(()) = migrate.recv() => {
+                match options.bind(outside_io.peer_addr(), None).await {
+                    Ok(sock) => {
+                        let sock: Arc<dyn io::outside::OutsideIO> = Arc::new(sock);
+                        // Swap both under the connection lock, so no one
+                        // sees the connection on one socket and `current`
+                        // on the other
+                        let mut conn = conn.lock().unwrap();
+                        conn.set_outside_io(obfuscation.wrap_outside_io(
+                            ConnectionType::Datagram,
+                            sock.clone().into_io_send_callback(),
+                        ));
+                        *current.write().unwrap() = sock.clone();
+                        drop(conn);
+                        outside_io = sock;
+                        info!("Moved connection to a new outside UDP socket");
+                    }
+                    Err(e) => {
+                        tracing::warn!("Failed to bind a new outside UDP socket, keeping the current one: {e:?}");
+                    }
+                }
+                // Replies are read once the new socket is polled, on the
+                // next iteration.
+                keepalive.network_changed().await;
+            }
+        }
+    }
+}
+
 const DEFAULT_TRACER_TRIGGER_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Tracks the tracer trigger for the inside IO loops: fires
@@ -689,15 +1027,17 @@ impl TracerTrigger {
     }
 }
 
-/// Shared body of the inside IO loops: rewrite the source/DNS
-/// addresses, dispatch the packet into the connection and map the
-/// recoverable errors. Returns `Ok(Some(last_outside_data_received))`
-/// when the packet entered the pipeline, `Ok(None)` when it was
-/// dropped and the loop should just continue.
+/// Shared body of the inside IO loops: let the DNS proxy answer DNS
+/// queries, rewrite the source/DNS addresses, dispatch the packet into
+/// the connection and map the recoverable errors. Returns
+/// `Ok(Some(last_outside_data_received))` when the packet entered the
+/// pipeline, `Ok(None)` when it was dropped and the loop should just
+/// continue.
 fn process_inside_packet<ExtAppState: Send + Sync>(
     conn: &Mutex<Connection<ConnectionState<ExtAppState>>>,
     inside_io: &dyn io::inside::InsideIORecv<ExtAppState>,
     tun_dns_ip: Ipv4Addr,
+    dns_proxy: Option<&Arc<DnsProxy>>,
     buf: &mut BytesMut,
     dispatch: impl FnOnce(
         &mut Connection<ConnectionState<ExtAppState>>,
@@ -709,7 +1049,22 @@ fn process_inside_packet<ExtAppState: Send + Sync>(
     // Update source IP address to server assigned IP address
     let ip_config = conn.app_state().ip_config;
     if let Some(ip_config) = &ip_config {
-        ipv4_update_source(buf.as_mut(), ip_config.client_ip);
+        let split_dns = conn.app_state().split_dns.as_ref();
+        if let Some(dns_proxy) = dns_proxy
+            && dns_proxy.handle_query(buf, ip_config.dns_ip, split_dns) == QueryAction::Consumed
+        {
+            return Ok(None);
+        }
+
+        // Packets from the subnets behind this client keep their source
+        let site_subnets = &conn.app_state().site_subnets;
+        let from_site = Ipv4Packet::new(buf.as_ref()).is_some_and(|packet| {
+            let source = packet.get_source();
+            site_subnets.iter().any(|subnet| subnet.contains(&source))
+        });
+        if !from_site {
+            ipv4_update_source(buf.as_mut(), ip_config.client_ip);
+        }
 
         // Update TUN device DNS IP address to server provided DNS address
         let packet = Ipv4Packet::new(buf.as_ref());
@@ -743,6 +1098,7 @@ pub async fn inside_io_task<ExtAppState: Send + Sync>(
     conn: Arc<Mutex<Connection<ConnectionState<ExtAppState>>>>,
     inside_io: Arc<dyn io::inside::InsideIORecv<ExtAppState>>,
     tun_dns_ip: Ipv4Addr,
+    dns_proxy: Option<Arc<DnsProxy>>,
     keepalive: Keepalive,
     keepalive_config: KeepaliveConfig,
     inside_pkt_codec_stall_timeout: Duration,
@@ -769,6 +1125,7 @@ pub async fn inside_io_task<ExtAppState: Send + Sync>(
             &conn,
             inside_io.as_ref(),
             tun_dns_ip,
+            dns_proxy.as_ref(),
             &mut buf,
             |conn, buf| {
                 conn.inside_data_received(buf)?;
@@ -796,10 +1153,14 @@ pub async fn inside_io_task<ExtAppState: Send + Sync>(
     }
 }
 
+/// Reacts to network changes: a UDP connection migrates to a new outside
+/// socket when `migrate` is set, or probes the current one with
+/// keepalives otherwise. A TCP connection is shut down.
 async fn handle_network_change<ExtAppState: Send + Sync>(
     keepalive: Keepalive,
     mut network_change_signal: mpsc::Receiver<()>,
     weak: Weak<Mutex<lightway_core::Connection<ConnectionState<ExtAppState>>>>,
+    migrate: Option<mpsc::Sender<()>>,
 ) -> ClientResult {
     while (network_change_signal.recv().await).is_some() {
         let Some(conn) = weak.upgrade() else {
@@ -808,6 +1169,12 @@ async fn handle_network_change<ExtAppState: Send + Sync>(
         let conn_type = conn.lock().unwrap().connection_type();
         match conn_type {
             ConnectionType::Datagram => {
+                if let Some(migrate) = &migrate
+                    && migrate.send(()).await.is_ok()
+                {
+                    info!("migrating outside socket due to network change ..");
+                    continue;
+                }
                 info!("sending keepalives due to network change ..");
                 keepalive.network_changed().await;
             }
@@ -835,7 +1202,7 @@ async fn network_event_coordinator(
     mut transition_rx: Option<watch::Receiver<()>>,
     nudge_on_route_event: bool,
     #[cfg(apple)] nudge_on_route_update: bool,
-    #[cfg(apple)] outside_io: Weak<dyn OutsideIO>,
+    #[cfg(apple)] outside_io: Weak<std::sync::RwLock<Arc<dyn OutsideIO>>>,
     network_change_signal: mpsc::Sender<()>,
 ) {
     tracing::info!("Reacting to network change events...");
@@ -890,7 +1257,7 @@ async fn network_event_coordinator(
         // time; re-resolve it now that the routing table is up to date.
         #[cfg(apple)]
         if let Some(io) = outside_io.upgrade() {
-            io.reconnect();
+            io.read().unwrap().reconnect();
         }
 
         if nudge && let Err(e) = network_change_signal.send(()).await {
@@ -1005,14 +1372,21 @@ async fn config_reload_task(
     tracing::info!("config reload task has finished");
 }
 
+/// Outside IO of a connection, replaced when a UDP connection migrates to
+/// a new socket
+type SharedOutsideIO = Arc<std::sync::RwLock<Arc<dyn io::outside::OutsideIO>>>;
+
 /// Represents a connection to a server. When dropped, the route table will be removed.
 pub struct ClientConnection<T: Send + Sync> {
     task: JoinHandle<anyhow::Result<ClientResult>>,
     conn: Arc<Mutex<Connection<ConnectionState<T>>>>,
     inside_io: Arc<dyn io::inside::InsideIO<T>>,
+    dns_proxy: Option<Arc<DnsProxy>>,
     #[cfg(desktop)]
-    outside_io: Arc<dyn io::outside::OutsideIO>,
+    outside_io: SharedOutsideIO,
     connected_signal: Option<oneshot::Receiver<()>>,
+    handshake_started: Instant,
+    keepalive_replies: KeepaliveReplies,
     stop_signal: Option<oneshot::Sender<()>>,
     network_change_signal: mpsc::Sender<()>,
     encoding_request_signal: mpsc::Sender<bool>,
@@ -1026,9 +1400,10 @@ impl<ExtAppState: Send + Sync> ClientConnection<ExtAppState> {
     /// Returns details about the established outside connection.
     #[cfg(desktop)]
     pub fn outside_connection_info(&self) -> ConnectionInfo {
+        let outside_io = self.outside_io.read().unwrap();
         ConnectionInfo {
-            socket: self.outside_io.socket(),
-            peer_addr: self.outside_io.peer_addr(),
+            socket: outside_io.socket(),
+            peer_addr: outside_io.peer_addr(),
         }
     }
 
@@ -1038,7 +1413,8 @@ impl<ExtAppState: Send + Sync> ClientConnection<ExtAppState> {
     /// `route_rx` events are unclassified (an embedder-supplied signal) so
     /// each one also nudges the connection's network-change handler;
     /// `nudge_on_route_update` (Apple) nudges when the refresh actually
-    /// replaced the server route.
+    /// replaced the server route. `fwmark` (Linux) is the outside socket's
+    /// mark, used by [`RouteMode::Policy`].
     #[cfg(desktop)]
     #[allow(clippy::too_many_arguments)]
     pub async fn initialize_routes(
@@ -1050,8 +1426,9 @@ impl<ExtAppState: Send + Sync> ClientConnection<ExtAppState> {
         transition_rx: Option<watch::Receiver<()>>,
         nudge_on_route_event: bool,
         #[cfg(apple)] nudge_on_route_update: bool,
+        #[cfg(linux)] fwmark: u32,
     ) -> Result<()> {
-        let server_ip = self.outside_io.peer_addr().ip();
+        let server_ip = self.outside_io.read().unwrap().peer_addr().ip();
         let tun_index = self.inside_io.if_index()?;
 
         tracing::trace!(
@@ -1062,8 +1439,11 @@ impl<ExtAppState: Send + Sync> ClientConnection<ExtAppState> {
             tun_peer_ip,
             tun_dns_ip
         );
-        let mut route_manager =
+        let route_manager =
             RouteManager::new(route_mode, server_ip, tun_index, tun_peer_ip, tun_dns_ip)?;
+        #[cfg(linux)]
+        let route_manager = route_manager.with_fwmark(fwmark);
+        let mut route_manager = route_manager;
         let route_updater = route_manager.start().await?;
 
         // A weak ref keeps the coordinator task from extending the outside
@@ -1085,28 +1465,47 @@ impl<ExtAppState: Send + Sync> ClientConnection<ExtAppState> {
         Ok(())
     }
 
+    /// Configure system DNS, using the split DNS configuration pushed
+    /// by the server if there is one and the DNS backend supports it.
     #[cfg(desktop)]
     pub fn set_dns(
         &mut self,
         dns_config_mode: DnsConfigMode,
         tun_dns_ip: IpAddr,
     ) -> Result<(), DnsManagerError> {
-        if dns_config_mode == DnsConfigMode::Default {
+        if dns_config_mode != DnsConfigMode::NoExec {
             let tun_index = self
                 .inside_io
                 .if_index()
                 .map_err(|e| DnsManagerError::FailedToSetDnsConfig(e.to_string()))?;
-            let mut dns_manager = DnsManager::new(tun_index);
-            dns_manager.set_dns(tun_dns_ip)?;
+            let mut dns_manager = DnsManager::with_mode(tun_index, dns_config_mode);
+            let split_dns = self.conn.lock().unwrap().app_state().split_dns.clone();
+            let result = match &split_dns {
+                Some(split_dns) => dns_manager.set_split_dns(split_dns),
+                None => Err(DnsManagerError::SplitDnsUnsupported),
+            };
+            match result {
+                Ok(()) => info!(?dns_config_mode, ?split_dns, "Split DNS configured"),
+                Err(DnsManagerError::SplitDnsUnsupported) => {
+                    if split_dns.is_some() {
+                        tracing::warn!("DNS backend does not support split DNS, using tunnel DNS");
+                    }
+                    dns_manager.set_dns(tun_dns_ip)?;
+                    info!(?dns_config_mode, %tun_dns_ip, "DNS configured");
+                }
+                Err(e) => return Err(e),
+            }
             self.dns_manager = Some(dns_manager);
-            info!(?dns_config_mode, %tun_dns_ip, "DNS configured");
         }
         Ok(())
     }
 
     pub fn set_connection_inside_io(&self) {
-        let inside_io: InsideIOSendCallbackArg<ConnectionState<ExtAppState>> =
+        let mut inside_io: InsideIOSendCallbackArg<ConnectionState<ExtAppState>> =
             self.inside_io.clone().into_io_send_callback();
+        if let Some(dns_proxy) = &self.dns_proxy {
+            inside_io = Arc::new(DnsProxyInsideIO::new(inside_io, dns_proxy.clone()));
+        }
         self.conn.lock().unwrap().inside_io(inside_io);
     }
 }
@@ -1118,6 +1517,7 @@ impl<ExtAppState: Send + Sync> ClientConnection<ExtAppState> {
         config,
         server_config,
         inside_io,
+        dns_proxy,
     )
 )]
 pub async fn connect<
@@ -1127,6 +1527,227 @@ pub async fn connect<
     config: &ClientConfig<ExtAppState>,
     server_config: ClientConnectionConfig<EventHandler>,
     inside_io: Arc<dyn io::inside::InsideIO<ExtAppState>>,
+    dns_proxy: Option<Arc<DnsProxy>>,
+) -> Result<ClientConnection<ExtAppState>> {
+    if matches!(server_config.mode, ClientConnectionMode::Auto { .. }) {
+        connect_auto(config, server_config, inside_io, dns_proxy).await
+    } else {
+        connect_transport(config, server_config, inside_io, dns_proxy).await
+    }
+}
+
+/// Races connection attempts to an `auto` mode server over UDP and TCP
+/// and over its addresses, see [`happy_eyeballs`]. Attempts start
+/// `config.connection_attempt_delay` apart, or right away when the
+/// previous one failed. Returns the first connection online, stopping
+/// the others.
+async fn connect_auto<
+    EventHandler: 'static + Send + EventCallback,
+    ExtAppState: 'static + Default + Send + Sync,
+>(
+    config: &ClientConfig<ExtAppState>,
+    server_config: ClientConnectionConfig<EventHandler>,
+    inside_io: Arc<dyn io::inside::InsideIO<ExtAppState>>,
+    dns_proxy: Option<Arc<DnsProxy>>,
+) -> Result<ClientConnection<ExtAppState>> {
+    let ClientConnectionConfig {
+        mode,
+        cipher,
+        server,
+        server_dn,
+        auth,
+        cert_content,
+        inside_pkt_codec,
+        inside_plugins,
+        outside_plugins,
+        event_handler,
+    } = server_config;
+
+    let ClientConnectionMode::Auto { name, addrs } = mode else {
+        return Err(anyhow!("Only auto mode connections are raced"));
+    };
+    let addrs = if addrs.is_empty() {
+        vec![server]
+    } else {
+        addrs
+    };
+    let attempts = happy_eyeballs::attempts(&addrs, config.transport_preferences.preferred(&name));
+
+    let event_handler = event_handler.map(|h| Arc::new(Mutex::new(h)));
+    let inside_pkt_codec = inside_pkt_codec.map(Arc::new);
+    let winner = Arc::new(std::sync::OnceLock::new());
+    let attempt_config = |index: usize, attempt: Attempt| ClientConnectionConfig {
+        mode: match attempt.connection_type {
+            ConnectionType::Stream => ClientConnectionMode::Stream(None),
+            ConnectionType::Datagram => ClientConnectionMode::Datagram(None),
+        },
+        cipher,
+        server_dn: server_dn.clone(),
+        server: attempt.addr,
+        auth: auth.clone(),
+        cert_content: cert_content.clone(),
+        inside_pkt_codec: inside_pkt_codec.clone().map(|codec| {
+            Box::new(happy_eyeballs::SharedPacketCodecFactory::new(codec)) as PacketCodecFactoryType
+        }),
+        inside_plugins: inside_plugins.clone(),
+        outside_plugins: outside_plugins.clone(),
+        event_handler: event_handler
+            .clone()
+            .map(|h| AttemptEventHandler::new(h, index, winner.clone())),
+    };
+
+    let mut pending = attempts.into_iter().enumerate().peekable();
+    let mut racing = FuturesUnordered::new();
+    let mut last_error = None;
+    let next_attempt = tokio::time::sleep(Duration::ZERO);
+    tokio::pin!(next_attempt);
+
+    loop {
+        tokio::select! {
+            _ = &mut next_attempt, if pending.peek().is_some() => {
+                let (index, attempt) = pending.next().unwrap();
+                tracing::debug!(?attempt, "Starting connection attempt");
+                racing.push(
+                    connect_attempt(
+                        config,
+                        attempt_config(index, attempt),
+                        inside_io.clone(),
+                        dns_proxy.clone(),
+                    )
+                    .map(move |result| (index, attempt, result)),
+                );
+                next_attempt
+                    .as_mut()
+                    .reset(tokio::time::Instant::now() + config.connection_attempt_delay);
+            }
+            Some((index, attempt, result)) = racing.next() => match result {
+                Ok(connection) => {
+                    info!(?attempt, "Connection attempt won");
+                    let _ = winner.set(index);
+                    config
+                        .transport_preferences
+                        .set(&name, attempt.connection_type);
+                    return Ok(connection);
+                }
+                Err(e) => {
+                    tracing::debug!(?attempt, "Connection attempt failed: {e:?}");
+                    last_error = Some(e);
+                    next_attempt.as_mut().reset(tokio::time::Instant::now());
+                }
+            },
+            else => {
+                return Err(last_error
+                    .unwrap_or_else(|| anyhow!("No connection attempts"))
+                    .context("All connection attempts failed"));
+            }
+        }
+    }
+}
+
+/// One attempt of [`connect_auto`], completing once the connection is
+/// online.
+async fn connect_attempt<
+    EventHandler: 'static + Send + EventCallback,
+    ExtAppState: 'static + Default + Send + Sync,
+>(
+    config: &ClientConfig<ExtAppState>,
+    server_config: ClientConnectionConfig<EventHandler>,
+    inside_io: Arc<dyn io::inside::InsideIO<ExtAppState>>,
+    dns_proxy: Option<Arc<DnsProxy>>,
+) -> Result<ClientConnection<ExtAppState>> {
+    // Dropping the connection stops it
+    let mut connection = connect_transport(config, server_config, inside_io, dns_proxy).await?;
+    connection
+        .connected_signal
+        .as_mut()
+        .expect("connected_signal is set by connect_transport")
+        .await
+        .map_err(|_| anyhow!("Connection ended before coming online"))?;
+
+    // Already online for whoever selects the connection
+    let (connected_tx, connected_rx) = oneshot::channel();
+    let _ = connected_tx.send(());
+    connection.connected_signal = Some(connected_rx);
+    Ok(connection)
+}
+
+/// Settings of the outside UDP socket, kept to bind a new one when the
+/// connection migrates on network change.
+#[derive(Clone, Copy)]
+struct UdpSocketOptions {
+    #[cfg(all(linux, not(feature = "mobile")))]
+    fwmark: u32,
+    #[cfg(batch_receive)]
+    enable_batch_receive: bool,
+    sndbuf: ByteSize,
+    rcvbuf: ByteSize,
+    #[cfg(apple)]
+    enable_connected_udp: bool,
+}
+
+impl UdpSocketOptions {
+    fn new<ExtAppState: Send + Sync>(config: &ClientConfig<ExtAppState>) -> Self {
+        Self {
+            #[cfg(all(linux, not(feature = "mobile")))]
+            fwmark: config.fwmark,
+            #[cfg(batch_receive)]
+            enable_batch_receive: config.enable_batch_receive,
+            sndbuf: config.sndbuf,
+            rcvbuf: config.rcvbuf,
+            #[cfg(apple)]
+            enable_connected_udp: config.enable_connected_udp,
+        }
+    }
+
+    /// Set up `sock`, or a newly bound socket when `None`, to send to
+    /// `server`.
+    async fn bind(self, server: SocketAddr, sock: Option<UdpSocket>) -> Result<io::outside::Udp> {
+        #[cfg_attr(not(batch_receive), allow(unused_mut))]
+        let mut sock = io::outside::Udp::new(
+            server,
+            sock,
+            #[cfg(all(linux, not(feature = "mobile")))]
+            self.fwmark,
+        )
+        .await
+        .inspect_err(|e| tracing::error!("Failed to create outside IO UDP socket: {e}"))
+        .context("Outside IO UDP")?;
+
+        #[cfg(batch_receive)]
+        if self.enable_batch_receive {
+            sock.enable_batch_receive();
+        }
+
+        sock.set_send_buffer_size(self.sndbuf.as_u64().try_into()?)?;
+        sock.set_recv_buffer_size(self.rcvbuf.as_u64().try_into()?)?;
+
+        // On Apple platforms a connected UDP socket lets `send` skip
+        // the per-packet route lookup, improving throughput. Safe
+        // because a network change re-connects the socket: via the
+        // network-event coordinator on desktop (see
+        // `initialize_routes`), via the network-change signal
+        // forwarder elsewhere.
+        #[cfg(apple)]
+        if self.enable_connected_udp
+            && let Err(e) = sock.enable_connected_send()
+        {
+            tracing::warn!("Failed to connect outside UDP socket, using send_to: {e}");
+        }
+
+        Ok(sock)
+    }
+}
+
+/// Connects over the transport of `server_config.mode`, which must not be
+/// [`ClientConnectionMode::Auto`].
+async fn connect_transport<
+    EventHandler: 'static + Send + EventCallback,
+    ExtAppState: 'static + Default + Send + Sync,
+>(
+    config: &ClientConfig<ExtAppState>,
+    server_config: ClientConnectionConfig<EventHandler>,
+    inside_io: Arc<dyn io::inside::InsideIO<ExtAppState>>,
+    dns_proxy: Option<Arc<DnsProxy>>,
 ) -> Result<ClientConnection<ExtAppState>> {
     let mut join_set = JoinSet::new();
     let ClientConnectionConfig {
@@ -1142,41 +1763,45 @@ pub async fn connect<
         event_handler,
     } = server_config;
 
+    #[cfg(desktop)]
+    let mut multipath = None;
+    let mut udp_migration = None;
+    #[cfg(desktop)]
+    let mut nat_probe = None;
     let (connection_type, outside_io): (ConnectionType, Arc<dyn io::outside::OutsideIO>) =
         match mode {
-            ClientConnectionMode::Datagram(maybe_sock) => {
-                #[cfg_attr(not(batch_receive), allow(unused_mut))]
-                let mut sock = io::outside::Udp::new(
+            #[cfg(desktop)]
+            ClientConnectionMode::Datagram(_) if config.multipath.is_enabled() => {
+                let paths = io::outside::Multipath::new(
                     server,
-                    maybe_sock,
+                    &config.multipath,
                     #[cfg(all(linux, not(feature = "mobile")))]
                     config.fwmark,
                 )
                 .await
-                .inspect_err(|e| tracing::error!("Failed to create outside IO UDP socket: {e}"))
-                .context("Outside IO UDP")?;
+                .inspect_err(|e| tracing::error!("Failed to create multipath outside IO: {e}"))
+                .context("Outside IO multipath")?;
 
-                #[cfg(batch_receive)]
-                if config.enable_batch_receive {
-                    sock.enable_batch_receive();
-                }
+                paths.set_send_buffer_size(config.sndbuf.as_u64().try_into()?)?;
+                paths.set_recv_buffer_size(config.rcvbuf.as_u64().try_into()?)?;
 
-                sock.set_send_buffer_size(config.sndbuf.as_u64().try_into()?)?;
-                sock.set_recv_buffer_size(config.rcvbuf.as_u64().try_into()?)?;
-
-                // On Apple platforms a connected UDP socket lets `send` skip
-                // the per-packet route lookup, improving throughput. Safe
-                // because a network change re-connects the socket: via the
-                // network-event coordinator on desktop (see
-                // `initialize_routes`), via the network-change signal
-                // forwarder elsewhere.
-                #[cfg(apple)]
-                if config.enable_connected_udp
-                    && let Err(e) = sock.enable_connected_send()
+                let paths = Arc::new(paths);
+                multipath = Some(paths.clone());
+                (ConnectionType::Datagram, paths)
+            }
+            ClientConnectionMode::Datagram(maybe_sock) => {
+                // A socket supplied by the embedder may have been set up in
+                // ways a rebound one would miss, keep it.
+                if config.udp_migration && maybe_sock.is_none() {
+                    udp_migration = Some(UdpSocketOptions::new(config));
+                }
+                #[cfg(desktop)]
                 {
-                    tracing::warn!("Failed to connect outside UDP socket, using send_to: {e}");
+                    nat_probe = config.nat_keepalive;
                 }
-
+                let sock = UdpSocketOptions::new(config)
+                    .bind(server, maybe_sock)
+                    .await?;
                 (ConnectionType::Datagram, Arc::new(sock))
             }
             ClientConnectionMode::Stream(maybe_sock) => {
@@ -1202,14 +1827,40 @@ pub async fn connect<
                 }
                 (ConnectionType::Stream, Arc::new(sock))
             }
+            ClientConnectionMode::Auto { .. } => {
+                return Err(anyhow!("Auto mode connections are raced by connect()"));
+            }
         };
 
+    let session_ticket = config
+        .session_tickets
+        .as_ref()
+        .map(|tickets| tickets.slot(server, connection_type, server_dn.as_deref()));
+
+    let mut obfuscation = config.obfuscation.clone();
+    if let Some(WebSocket::Client { host, .. }) = &mut obfuscation.websocket
+        && host.is_empty()
+    {
+        *host = server_dn.clone().unwrap_or_else(|| server.to_string());
+    }
+    let mut outside_plugins = outside_plugins;
+    obfuscation.add_outside_plugins(connection_type, &mut outside_plugins);
+    // Room for what obfuscation adds to each datagram
+    let outside_mtu = match connection_type {
+        ConnectionType::Datagram => config
+            .outside_mtu
+            .saturating_sub(obfuscation.datagram_overhead()),
+        ConnectionType::Stream => config.outside_mtu,
+    };
+
     let (event_cb, event_stream) = EventStreamCallback::new();
 
     let (ticker, ticker_task) = ConnectionTicker::new();
     let state = ConnectionState {
         ticker,
         ip_config: None,
+        split_dns: None,
+        site_subnets: config.site_subnets.clone(),
         extended: Default::default(),
     };
     let (pmtud_timer, pmtud_timer_task) = DplpmtudTimer::new();
@@ -1268,16 +1919,22 @@ pub async fn connect<
     let conn_builder = ctx_builder
         .build()
         .start_connect(
-            outside_io.clone().into_io_send_callback(),
-            config.outside_mtu,
+            obfuscation
+                .wrap_outside_io(connection_type, outside_io.clone().into_io_send_callback()),
+            outside_mtu,
         )?
         .with_auth(auth)
+        .with_site_subnets(config.site_subnets.clone())
         .with_event_cb(Box::new(event_cb))
         .with_inside_pkt_codec(inside_io_codec)
         .when_some(config.pmtud_base_mtu, |b, mtu| b.with_pmtud_base_mtu(mtu))
         .when_some(server_dn, |b, sdn| {
             b.with_server_domain_name_validation(&sdn)
         })
+        .try_when_some(
+            session_ticket.as_ref().and_then(SessionTicketSlot::take),
+            |b, ticket| b.with_session_ticket(&ticket),
+        )?
         .when(connection_type.is_datagram() && config.enable_pmtud, |b| {
             b.with_pmtud_timer(pmtud_timer)
         });
@@ -1285,6 +1942,7 @@ pub async fn connect<
     #[cfg(feature = "postquantum")]
     let conn_builder = conn_builder.with_pq_crypto(config.keyshare.into());
 
+    let handshake_started = Instant::now();
     let conn = Arc::new(Mutex::new(conn_builder.connect(state)?));
 
     let keepalive_config = keepalive::Config {
@@ -1293,21 +1951,29 @@ pub async fn connect<
         continuous: config.continuous_keepalive,
         tracer_trigger_timeout: Some(config.tracer_packet_timeout),
     };
-    let (keepalive, keepalive_task) =
-        Keepalive::new(keepalive_config.clone(), Arc::downgrade(&conn));
+    let (keepalive, keepalive_task) = match config.adaptive_keepalive {
+        Some(bounds) => Keepalive::new(
+            keepalive::Adaptive::new(keepalive_config.clone(), bounds),
+            Arc::downgrade(&conn),
+        ),
+        None => Keepalive::new(keepalive_config.clone(), Arc::downgrade(&conn)),
+    };
 
     let (connected_tx, connected_rx) = oneshot::channel();
     let (disconnected_tx, disconnected_rx) = oneshot::channel();
+    let keepalive_replies = KeepaliveReplies::new();
 
     join_set.spawn(handle_events(
         event_stream,
         keepalive.clone(),
+        keepalive_replies.clone(),
         Arc::downgrade(&conn),
         config
             .inside_pkt_codec_config
             .as_ref()
             .is_some_and(|x| x.enable_inside_pkt_encoding),
         event_handler,
+        session_ticket,
         connected_tx,
         disconnected_tx,
     ));
@@ -1315,19 +1981,73 @@ pub async fn connect<
     let mut ticker_task = ticker_task.spawn(Arc::downgrade(&conn));
     pmtud_timer_task.spawn(Arc::downgrade(&conn), &mut join_set);
 
-    let mut outside_io_loop: JoinHandle<anyhow::Result<()>> = tokio::spawn(outside_io_task(
-        conn.clone(),
-        config.outside_mtu,
-        connection_type,
-        outside_io.clone(),
-        keepalive.clone(),
-        None,
-    ));
+    #[cfg(desktop)]
+    if let Some(multipath) = multipath {
+        let weak = Arc::downgrade(&conn);
+        join_set.spawn(io::outside::udp::multipath::probe_links(
+            multipath,
+            Arc::downgrade(&conn),
+            keepalive_replies.clone(),
+            move || {
+                weak.upgrade()
+                    .is_some_and(|conn| matches!(conn.lock().unwrap().state(), State::Online))
+            },
+            config.keepalive_interval,
+        ));
+    }
+
+    #[cfg(desktop)]
+    if let Some(nat_config) = nat_probe {
+        let weak = Arc::downgrade(&conn);
+        join_set.spawn(nat_keepalive::nat_keepalive(
+            Arc::downgrade(&conn),
+            keepalive.clone(),
+            keepalive_replies.clone(),
+            move || {
+                weak.upgrade()
+                    .is_some_and(|conn| matches!(conn.lock().unwrap().state(), State::Online))
+            },
+            server.ip(),
+            nat_config,
+            config.nat_lifetimes.clone(),
+        ));
+    }
+
+    let shared_outside_io: SharedOutsideIO = Arc::new(std::sync::RwLock::new(outside_io.clone()));
+
+    let (migrate_tx, mut outside_io_loop): (_, JoinHandle<anyhow::Result<()>>) = match udp_migration
+    {
+        Some(options) => {
+            let (migrate_tx, migrate_rx) = mpsc::channel(1);
+            let task = tokio::spawn(migrating_outside_io_task(
+                conn.clone(),
+                config.outside_mtu,
+                shared_outside_io.clone(),
+                keepalive.clone(),
+                options,
+                obfuscation,
+                migrate_rx,
+            ));
+            (Some(migrate_tx), task)
+        }
+        None => {
+            let task = tokio::spawn(outside_io_task(
+                conn.clone(),
+                config.outside_mtu,
+                connection_type,
+                outside_io.clone(),
+                keepalive.clone(),
+                None,
+            ));
+            (None, task)
+        }
+    };
 
     let mut inside_io_loop: JoinHandle<anyhow::Result<()>> = tokio::spawn(inside_io_task(
         conn.clone(),
         inside_io.clone(),
         config.tun_dns_ip,
+        dns_proxy.clone(),
         keepalive.clone(),
         keepalive_config,
         config.inside_pkt_codec_stall_timeout,
@@ -1338,6 +2058,7 @@ pub async fn connect<
         keepalive,
         network_change_rx,
         Arc::downgrade(&conn),
+        migrate_tx,
     ));
 
     let mut encoded_pkt_send_task: JoinHandle<anyhow::Result<()>> = tokio::spawn(
@@ -1417,9 +2138,12 @@ pub async fn connect<
         task,
         conn,
         inside_io,
+        dns_proxy,
         #[cfg(desktop)]
-        outside_io,
+        outside_io: shared_outside_io,
         connected_signal: Some(connected_rx),
+        handshake_started,
+        keepalive_replies,
         stop_signal: Some(stop_tx),
         network_change_signal: network_change_tx,
         encoding_request_signal: encoding_request_tx,
@@ -1430,24 +2154,39 @@ pub async fn connect<
     })
 }
 
-/// Returns the index of the best connection.
+impl<ExtAppState: Send + Sync + 'static> Candidate for ClientConnection<ExtAppState> {
+    fn handshake_started(&self) -> Option<Instant> {
+        Some(self.handshake_started)
+    }
+
+    fn keepalive_probe(&self) -> KeepaliveProbe {
+        KeepaliveProbe::new(Arc::downgrade(&self.conn), self.keepalive_replies.clone())
+    }
+}
+
+/// Returns the index of the best connection, along with the
+/// measurements of the connections online at that point.
 ///
-/// Receives `(index, connected_signal)` pairs from `connection_setup_rx` as connections
-/// are set up, rather than requiring all connections to be ready upfront.
+/// Receives `(index, connected_signal, handshake_started)` from `connection_setup_rx`
+/// as connections are set up, rather than requiring all connections to be ready upfront.
 /// The channel closing signals that no more connections will arrive.
 ///
 /// If `preferred_connection_wait_interval` is non-zero it will wait that
-/// duration before returning the highest priority connection (lowest index).
-/// If there is only one connection, or the preferred connection (index 0)
-/// is the first to connect, it will not wait.
+/// duration before returning the best connection according to `selection`.
+/// If there is only one connection it will not wait. With
+/// [`ServerSelection::Priority`] it will not wait either once the
+/// preferred connection (index 0) is online, with the other strategies
+/// once every connection is online or failed.
 async fn find_best_connection(
-    mut connection_setup_rx: mpsc::Receiver<(usize, oneshot::Receiver<()>)>,
+    mut connection_setup_rx: mpsc::Receiver<(usize, oneshot::Receiver<()>, Instant)>,
     preferred_connection_wait_interval: Duration,
-) -> Result<usize> {
+    selection: ServerSelectionConfig,
+) -> Result<(usize, Vec<ConnectionMeasurement>)> {
     let mut wait_timer_task = tokio::spawn(tokio::time::sleep(preferred_connection_wait_interval));
 
     let mut connected_stream = StreamMap::new();
-    let mut best_connection_index: Option<usize> = None;
+    let mut handshake_starts = std::collections::HashMap::new();
+    let mut online: Vec<ConnectionMeasurement> = Vec::new();
     let mut channel_open = true;
 
     loop {
@@ -1455,16 +2194,17 @@ async fn find_best_connection(
             biased;
             // Highest priority to make sure we add connections to the stream as soon as they are ready
             item = connection_setup_rx.recv(), if channel_open => {
-                if let Some((index, signal)) = item {
+                if let Some((index, signal, handshake_started)) = item {
                     connected_stream.insert(index, signal.into_stream());
+                    handshake_starts.insert(index, handshake_started);
                 } else {
                     channel_open = false;
                 }
             }
             _ = &mut wait_timer_task, if !wait_timer_task.is_finished() => {
-                if let Some(index) = best_connection_index {
+                if let Some(index) = selection.best(&online) {
                     tracing::debug!("Preferred connection wait finished, using best connection so far: {index}");
-                    return Ok(index);
+                    return Ok((index, online));
                 }
                 tracing::debug!("Preferred connection wait finished, but no connection so far. Waiting for next connection.");
             }
@@ -1475,19 +2215,25 @@ async fn find_best_connection(
                 }
 
                 tracing::debug!("Connection {index} is online");
+                let handshake_rtt = handshake_starts[&index].elapsed();
+                online.push(ConnectionMeasurement::new(index, handshake_rtt));
 
                 if wait_timer_task.is_finished() {
                     tracing::debug!("Preferred connection wait finished, using only connection so far: {index}");
-                    return Ok(index);
+                    return Ok((index, online));
                 }
 
                 // We don't defer connection if it's the preferred connection
-                if index == 0 {
+                if index == 0 && !selection.compares_latency() {
                     tracing::debug!("Preferred connection is online, using it.");
-                    return Ok(index);
+                    return Ok((index, online));
                 }
-
-                best_connection_index = Some(best_connection_index.map_or(index, |i| i.min(index)));
+            }
+            // No more connections to compare with
+            _ = std::future::ready(()), if !channel_open && connected_stream.is_empty() && !online.is_empty() && selection.compares_latency() => {
+                let index = selection.best(&online).expect("online is not empty");
+                tracing::debug!("All connections settled, using best connection: {index}");
+                return Ok((index, online));
             }
             else => return Err(anyhow!("All connections disconnected")),
         }
@@ -1496,16 +2242,19 @@ async fn find_best_connection(
 
 /// Runs connection futures concurrently, feeds their connected signals to
 /// [`find_best_connection`], and returns the best connection index along with
-/// all successful connections.
+/// the measurements it was selected by and all successful connections.
 ///
 /// Each connect future must yield `(index, Result<(connected_signal, connection)>)`.
 /// The `connected_signal` is forwarded to the selection logic; the `connection` is
-/// stored and returned alongside the winning index.
+/// stored and returned alongside the winning index. Connections online are
+/// probed with keepalives before selecting if `selection` asks for it.
 async fn select_best_from_futures<C, Fut>(
     mut connect_futs: FuturesUnordered<Fut>,
     preferred_connection_wait_interval: Duration,
-) -> Result<(usize, Vec<(usize, C)>)>
+    selection: &ServerSelectionConfig,
+) -> Result<(usize, Vec<ConnectionMeasurement>, Vec<(usize, C)>)>
 where
+    C: Candidate,
     Fut: Future<Output = (usize, Result<(oneshot::Receiver<()>, C)>)>,
 {
     if connect_futs.is_empty() {
@@ -1522,6 +2271,7 @@ where
     let mut find_best = std::pin::pin!(find_best_connection(
         connection_setup_rx,
         preferred_connection_wait_interval,
+        selection.clone(),
     ));
 
     loop {
@@ -1532,7 +2282,8 @@ where
             Some((orig_idx, result)) = connect_futs.next(), if !setup_complete => {
                 match result {
                     Ok((signal, conn)) => {
-                        let _ = connection_setup_tx.as_ref().unwrap().send((orig_idx, signal)).await.inspect_err(|e| tracing::warn!("Failed to send connection signal: {e}"));
+                        let handshake_started = conn.handshake_started().unwrap_or_else(Instant::now);
+                        let _ = connection_setup_tx.as_ref().unwrap().send((orig_idx, signal, handshake_started)).await.inspect_err(|e| tracing::warn!("Failed to send connection signal: {e}"));
                         connections.push((orig_idx, conn));
                     }
                     Err(e) => {
@@ -1548,8 +2299,17 @@ where
                 }
             }
 
-            index = &mut find_best => {
-                return Ok((index?, connections));
+            result = &mut find_best => {
+                let (mut index, mut online) = result?;
+                if selection.probes_keepalive() && online.len() > 1 {
+                    let probes = online.iter_mut().filter_map(|measurement| {
+                        let (_, conn) = connections.iter().find(|(i, _)| *i == measurement.index)?;
+                        Some(conn.keepalive_probe().measure(selection, measurement))
+                    });
+                    futures::future::join_all(probes).await;
+                    index = selection.best(&online).unwrap_or(index);
+                }
+                return Ok((index, online, connections));
             }
         }
     }
@@ -1584,47 +2344,226 @@ fn validate_client_config<
     Ok(())
 }
 
+/// Routes and DNS configured for a connection. Kept in place after the
+/// connection ends while reconnecting, so that traffic keeps going to the
+/// tunnel device rather than leaking. Dropping it releases them too, but
+/// without waiting for the routes to be removed.
+#[cfg(desktop)]
+struct ConnectionSetup {
+    server_ip: IpAddr,
+    route_manager: Option<RouteManager>,
+    dns_manager: Option<DnsManager>,
+    network_change_monitor: Option<NetworkChangeMonitor>,
+}
+
+#[cfg(desktop)]
+impl ConnectionSetup {
+    async fn release(mut self) {
+        if let Some(mut route_manager) = self.route_manager.take() {
+            let _ = route_manager.stop().await;
+        }
+
+        // Dropping the monitor aborts its background task, and the DNS
+        // manager resets DNS.
+    }
+
+    /// Release once the next connection's setup is in place. Its DNS
+    /// configuration replaced this one's, so must not be reset, and routes
+    /// both share stay until their last owner releases them.
+    async fn hand_over(mut self) {
+        if let Some(dns_manager) = self.dns_manager.as_mut() {
+            dns_manager.hand_over();
+        }
+        self.release().await;
+    }
+}
+
+/// Forwards encoding requests to the current connection, which changes
+/// on every reconnect.
+#[cfg(desktop)]
+async fn forward_encoding_requests(
+    mut requests: mpsc::Receiver<bool>,
+    mut current: watch::Receiver<Option<mpsc::Sender<bool>>>,
+) {
+    while let Some(enabled) = requests.recv().await {
+        let target = match current.wait_for(Option::is_some).await {
+            Ok(target) => target.clone().unwrap(),
+            Err(_) => break,
+        };
+        if let Err(e) = target.send(enabled).await {
+            tracing::error!("Failed to send encoding_request_signal: {e}");
+        }
+    }
+}
+
+/// Connects to all `conn_confs` concurrently and returns the best
+/// connection, see [`client`], stopping the others.
+#[cfg(desktop)]
+async fn select_best_connection<
+    EventHandler: 'static + Send + EventCallback,
+    ExtAppState: 'static + Default + Send + Sync,
+>(
+    config: &ClientConfig<ExtAppState>,
+    conn_confs: Vec<ClientConnectionConfig<EventHandler>>,
+    inside_io: &Arc<dyn io::inside::InsideIO<ExtAppState>>,
+    dns_proxy: &Option<Arc<DnsProxy>>,
+) -> Result<(
+    usize,
+    Vec<ConnectionMeasurement>,
+    ClientConnection<ExtAppState>,
+)> {
+    let connect_futs: FuturesUnordered<_> = conn_confs
+        .into_iter()
+        .enumerate()
+        .map(|(index, server_config)| {
+            let inside_io = inside_io.clone();
+            let dns_proxy = dns_proxy.clone();
+            async move {
+                let result = connect(config, server_config, inside_io, dns_proxy)
+                    .await
+                    .map(|mut conn| (conn.connected_signal.take().unwrap(), conn));
+                (index, result)
+            }
+        })
+        .collect();
+
+    let (best_connection_index, measurements, mut connections) = select_best_from_futures(
+        connect_futs,
+        config.preferred_connection_wait_interval,
+        &config.server_selection,
+    )
+    .await?;
+
+    tracing::info!(
+        message = "Best connection selected",
+        connection_id = best_connection_index,
+        strategy = ?config.server_selection.strategy,
+        ?measurements,
+    );
+    let pos = connections
+        .iter()
+        .position(|(idx, _)| *idx == best_connection_index)
+        .unwrap();
+    let (_, connection) = connections.swap_remove(pos);
+
+    for (_, conn) in connections.iter_mut() {
+        let _ = conn.stop_signal.take().unwrap().send(());
+    }
+
+    Ok((best_connection_index, measurements, connection))
+}
+
 /// Launches connections concurrently and waits for the first one to complete.
 /// If `config.preferred_connection_wait_interval` is set, it will wait that
 /// duration after the first connection completes before returning the highest
-/// priority connection (in the specified array order).
+/// priority connection (in the specified array order), or the one with the
+/// lowest latency as per `config.server_selection`.
 ///
 /// stop_signal sends a signal if the program received INT/TERM signals
+///
+/// Returns once the connection ends. Reconnecting needs the connection
+/// configs of every attempt, so `config.reconnect` is an error here: use
+/// [`client_with_reconnect`] instead.
 #[cfg(desktop)]
 pub async fn client<
     EventHandler: 'static + Send + EventCallback,
     ExtAppState: 'static + Default + Send + Sync,
+>(
+    config: ClientConfig<ExtAppState>,
+    stop_signal: oneshot::Receiver<()>,
+    conn_confs: Vec<ClientConnectionConfig<EventHandler>>,
+) -> Result<ClientResult> {
+    if config.reconnect.is_some() {
+        return Err(anyhow!(
+            "Reconnecting requires client_with_reconnect, which builds the connection configs of every attempt"
+        ));
+    }
+    let mut conn_confs = Some(conn_confs);
+    client_with_reconnect(config, stop_signal, move || {
+        conn_confs.take().unwrap_or_default()
+    })
+    .await
+}
+
+/// Like [`client`], but once an established connection ends other than by
+/// `stop_signal`, reconnects with `config.reconnect`'s backoff if set,
+/// re-running server selection. The tunnel device, routes, DNS and kill
+/// switch stay in place in the meantime. Progress is reported through
+/// `config.reconnect_signal`.
+///
+/// `conn_confs` builds the connection configs of every attempt, in
+/// priority order. It should reuse the server addresses resolved for the
+/// first one (see [`ClientConnectionConfig::try_from_event_handler_and_resolved_config`]),
+/// as DNS points at the tunnel while reconnecting.
+///
+/// Failing to connect in the first place, or `max_attempts` times in a
+/// row, returns the last error. Routes and DNS are removed however the
+/// client stops, the kill switch only on a user disconnect.
+#[cfg(desktop)]
+pub async fn client_with_reconnect<
+    EventHandler: 'static + Send + EventCallback,
+    ExtAppState: 'static + Default + Send + Sync,
 >(
     mut config: ClientConfig<ExtAppState>,
     mut stop_signal: oneshot::Receiver<()>,
-    conn_confs: Vec<ClientConnectionConfig<EventHandler>>,
+    mut conn_confs: impl FnMut() -> Vec<ClientConnectionConfig<EventHandler>>,
 ) -> Result<ClientResult> {
+    let attempt_confs = conn_confs();
     tracing::info!(
         "Client starting with config:\n{:#?}, connections:\n{:#?}",
         &config,
-        &conn_confs
+        &attempt_confs
     );
 
-    validate_client_config(&config, &conn_confs)?;
-
-    let inside_io = match &config.inside_io {
-        Some(io) => Arc::clone(io),
-        #[cfg(feature = "io-uring")]
-        None if config.enable_tun_iouring => Arc::new(
-            io::inside::Tun::new_with_iouring(
-                &config.tun_config,
-                config.tun_local_ip,
-                config.tun_dns_ip,
-                config.iouring_entry_count,
-                config.iouring_sqpoll_idle_time,
-            )
-            .await?,
-        ),
-        None => Arc::new(
-            io::inside::Tun::new(&config.tun_config, config.tun_local_ip, config.tun_dns_ip)
-                .await?,
-        ),
-    };
+    validate_client_config(&config, &attempt_confs)?;
+
+    let mut session = ClientSession::new(&mut config).await?;
+    let result = session
+        .run(&mut config, &mut stop_signal, conn_confs, attempt_confs)
+        .await;
+    session.close().await;
+
+    // Any other exit is a dropped tunnel: keep blocking until the next
+    // connection replaces the rules.
+    #[cfg(linux)]
+    if matches!(result, Ok(ClientResult::UserDisconnect)) {
+        let kill_switch = session.kill_switch;
+        tokio::task::spawn_blocking(move || kill_switch.remove()).await??;
+    }
+
+    result
+}
+
+/// Creates the tunnel device, or the userspace network stack if
+/// configured instead.
+#[cfg(desktop)]
+async fn create_inside_io<ExtAppState: 'static + Default + Send + Sync>(
+    config: &ClientConfig<ExtAppState>,
+) -> Result<Arc<dyn io::inside::InsideIO<ExtAppState>>> {
+    let inside_io: Arc<dyn io::inside::InsideIO<ExtAppState>> =
+        match (&config.inside_io, &config.netstack) {
+            (Some(io), _) => Arc::clone(io),
+            (None, Some(netstack)) => Arc::new(
+                io::inside::Netstack::new(netstack, config.tun_local_ip, config.tun_dns_ip).await?,
+            ),
+            #[cfg(feature = "io-uring")]
+            (None, None) if config.enable_tun_iouring => Arc::new(
+                io::inside::Tun::new_with_iouring(
+                    &config.tun_config,
+                    config.tun_local_ip,
+                    config.tun_dns_ip,
+                    config.iouring_entry_count,
+                    config.iouring_sqpoll_idle_time,
+                )
+                .await?
+                .with_site_subnets(config.site_subnets.clone()),
+            ),
+            (None, None) => Arc::new(
+                io::inside::Tun::new(&config.tun_config, config.tun_local_ip, config.tun_dns_ip)
+                    .await?
+                    .with_site_subnets(config.site_subnets.clone()),
+            ),
+        };
     if let Ok(device_name) = inside_io.name() {
         tracing::info!(
             message = "Interface Details",
@@ -1635,128 +2574,342 @@ pub async fn client<
             peer_ip = %config.tun_peer_ip,
         );
     }
+    Ok(inside_io)
+}
 
-    let preferred_connection_wait_interval = config.preferred_connection_wait_interval;
-
-    let (best_connection_index, mut connections) = {
-        let connect_futs: FuturesUnordered<_> = conn_confs
-            .into_iter()
-            .enumerate()
-            .map(|(index, server_config)| {
-                let config = &config;
-                let inside_io = inside_io.clone();
-                async move {
-                    let result = connect(config, server_config, inside_io)
-                        .await
-                        .map(|mut conn| (conn.connected_signal.take().unwrap(), conn));
-                    (index, result)
+/// The embedder's encoding request and reload signals outlive a single
+/// connection, so they feed the current one, as set in the returned
+/// sender.
+#[cfg(desktop)]
+fn forward_connection_signals<ExtAppState: Send + Sync>(
+    config: &mut ClientConfig<ExtAppState>,
+) -> watch::Sender<Option<mpsc::Sender<bool>>> {
+    let (encoding_request_tx, encoding_request_rx) = mpsc::channel(1);
+    let (current_encoding_request, current_encoding_request_rx) = watch::channel(None);
+    tokio::spawn(forward_encoding_requests(
+        encoding_request_rx,
+        current_encoding_request_rx,
+    ));
+
+    if let Some(inside_pkt_codec_config) = config.inside_pkt_codec_config.as_mut() {
+        // Later connections only need `enable_inside_pkt_encoding`
+        let (_, closed) = mpsc::channel(1);
+        let mut encoding_request_signal =
+            std::mem::replace(&mut inside_pkt_codec_config.encoding_request_signal, closed);
+        let encoding_request_tx = encoding_request_tx.clone();
+        tokio::spawn(async move {
+            while let Some(enabled) = encoding_request_signal.recv().await {
+                if encoding_request_tx.send(enabled).await.is_err() {
+                    break;
                 }
-            })
-            .collect();
+            }
+        });
+    }
 
-        tokio::select! {
-            result = select_best_from_futures(
-                connect_futs,
-                preferred_connection_wait_interval,
-            ) => result?,
+    if let Some(reload_signal) = config.config_reload_signal.take() {
+        tokio::spawn(config_reload_task(reload_signal, encoding_request_tx));
+    }
 
-            _ = &mut stop_signal => {
-                return Ok(ClientResult::UserDisconnect);
-            }
-        }
+    current_encoding_request
+}
+
+/// Sends the first selected connection to `best_connection_selected_signal`.
+#[cfg(desktop)]
+fn report_best_connection<ExtAppState: Send + Sync>(
+    config: &mut ClientConfig<ExtAppState>,
+    index: usize,
+    measurements: Vec<ConnectionMeasurement>,
+    connection: &ClientConnection<ExtAppState>,
+) {
+    let Some(signal) = config.best_connection_selected_signal.take() else {
+        return;
     };
-    // connect_futs dropped here — releases &config borrow
+    let ip_config = connection
+        .conn
+        .lock()
+        .unwrap()
+        .app_state()
+        .ip_config
+        .expect("selected connection is Online, so ip_config is set");
+    if signal
+        .send(BestConnectionInfo {
+            index,
+            connection: connection.outside_connection_info(),
+            ip_config,
+            strategy: config.server_selection.strategy,
+            measurements,
+        })
+        .is_err()
+    {
+        tracing::error!("Failed to send best_connection_selected_signal");
+    }
+}
 
-    tracing::info!(
-        message = "Best connection selected",
-        connection_id = best_connection_index,
-    );
-    let pos = connections
-        .iter()
-        .position(|(idx, _)| *idx == best_connection_index)
-        .unwrap();
-    let (_, mut connection) = connections.swap_remove(pos);
+/// What outlives a single connection of [`client_with_reconnect`]. Must
+/// be closed with [`Self::close`] once done.
+#[cfg(desktop)]
+struct ClientSession<ExtAppState: Send + Sync> {
+    inside_io: Arc<dyn io::inside::InsideIO<ExtAppState>>,
+    dns_proxy: Option<Arc<DnsProxy>>,
+    // Without a device, there are no routes or DNS to configure
+    uses_netstack: bool,
+    current_encoding_request: watch::Sender<Option<mpsc::Sender<bool>>>,
+    // Set up for the last connection, while it is running or being replaced
+    setup: Option<ConnectionSetup>,
+    #[cfg(linux)]
+    kill_switch: KillSwitch,
+    // Split DNS resolvers pushed for the last connection, reachable
+    // through the kill switch for as long as its DNS setup is in place
+    #[cfg(linux)]
+    split_dns_servers: Vec<IpAddr>,
+}
 
-    if let Some(signal) = config.best_connection_selected_signal.take() {
-        let ip_config = connection
-            .conn
-            .lock()
-            .unwrap()
-            .app_state()
-            .ip_config
-            .expect("selected connection is Online, so ip_config is set");
-        if signal
-            .send(BestConnectionInfo {
-                index: best_connection_index,
-                connection: connection.outside_connection_info(),
-                ip_config,
-            })
-            .is_err()
-        {
-            tracing::error!("Failed to send best_connection_selected_signal");
-        }
+#[cfg(desktop)]
+impl<ExtAppState: 'static + Default + Send + Sync> ClientSession<ExtAppState> {
+    async fn new(config: &mut ClientConfig<ExtAppState>) -> Result<Self> {
+        let uses_netstack = config.inside_io.is_none() && config.netstack.is_some();
+        let inside_io = create_inside_io(config).await?;
+
+        // Resolves a DoT/DoH upstream, so must happen before DNS is pointed
+        // at the tunnel. The reply task ends with the last reference to the
+        // proxy.
+        let dns_proxy = match config.dns_proxy.take() {
+            Some(dns_proxy_config) => {
+                let (dns_proxy, replies) = DnsProxy::new(dns_proxy_config).await?;
+                tokio::spawn(dns_proxy::reply_task(replies, inside_io.clone()));
+                Some(dns_proxy)
+            }
+            None => None,
+        };
+
+        // Installed before connecting, and every attempt allows all of its
+        // candidate servers, so a rule set left behind by a previous run or
+        // connection cannot block the new attempt.
+        #[cfg(linux)]
+        let kill_switch = {
+            let tun_name = match config.kill_switch_mode {
+                KillSwitchMode::Disabled => String::new(),
+                _ => inside_io.name()?,
+            };
+            KillSwitch::new(config.kill_switch_mode, tun_name, config.tun_dns_ip.into())
+        };
+
+        Ok(Self {
+            inside_io,
+            dns_proxy,
+            uses_netstack,
+            current_encoding_request: forward_connection_signals(config),
+            setup: None,
+            #[cfg(linux)]
+            kill_switch,
+            #[cfg(linux)]
+            split_dns_servers: Vec::new(),
+        })
     }
 
-    for (_, conn) in connections.iter_mut() {
-        let _ = conn.stop_signal.take().unwrap().send(());
+    /// Releases the last connection's routes and DNS. The kill switch is
+    /// left to the caller, as it outlives a dropped tunnel.
+    async fn close(&mut self) {
+        if let Some(setup) = self.setup.take() {
+            setup.release().await;
+        }
     }
 
-    // On desktop the network-event coordinator (see `initialize_routes`
-    // below) delivers the embedder signal's nudge after the route and socket
-    // refresh instead, so this forwarder only serves non-desktop builds.
-    #[cfg(not(desktop))]
-    if let Some(mut network_change_signal) = config.network_change_signal.clone() {
-        let connection_network_change_signal = connection.network_change_signal.clone();
-        #[cfg(apple)]
-        let outside_io = Arc::downgrade(&connection.outside_io);
-        tokio::spawn(async move {
-            while network_change_signal.changed().await.is_ok() {
-                // Re-pin the connected outside socket first so the keepalive
-                // probe rides the new path.
-                #[cfg(apple)]
-                if let Some(io) = outside_io.upgrade() {
-                    io.reconnect();
-                }
-                if let Err(e) = connection_network_change_signal.send(()).await {
-                    tracing::error!("Failed to send network_change_signal: {e}");
-                }
+    /// Connects, and reconnects as configured, until the client stops.
+    async fn run<EventHandler: 'static + Send + EventCallback>(
+        &mut self,
+        config: &mut ClientConfig<ExtAppState>,
+        stop_signal: &mut oneshot::Receiver<()>,
+        mut conn_confs: impl FnMut() -> Vec<ClientConnectionConfig<EventHandler>>,
+        mut attempt_confs: Vec<ClientConnectionConfig<EventHandler>>,
+    ) -> Result<ClientResult> {
+        let mut backoff = config.reconnect.take().map(Backoff::new);
+        let reconnect_signal = config.reconnect_signal.take();
+        let report = |event: ReconnectEvent| {
+            info!(?event, "Reconnect progress");
+            if let Some(signal) = &reconnect_signal {
+                let _ = signal.send(event);
             }
-        });
-    }
+        };
 
-    if let Some(mut inside_pkt_codec_config) = config.inside_pkt_codec_config.take() {
-        let connection_encoding_request_signal = connection.encoding_request_signal.clone();
-        tokio::spawn(async move {
-            while let Some(enabled) = inside_pkt_codec_config.encoding_request_signal.recv().await {
-                if let Err(e) = connection_encoding_request_signal.send(enabled).await {
-                    tracing::error!("Failed to send encoding_request_signal: {e}");
+        loop {
+            #[cfg(linux)]
+            {
+                let servers: Vec<AllowedServer> = attempt_confs
+                    .iter()
+                    .flat_map(|c| c.endpoints())
+                    .map(|(addr, connection_type)| AllowedServer {
+                        addr,
+                        connection_type,
+                    })
+                    .collect();
+                self.kill_switch
+                    .allow_servers(&servers, &self.split_dns_servers)?;
+            }
+
+            let bypass_routes = self.bypass_routes(config.route_mode, &attempt_confs);
+
+            let selected = tokio::select! {
+                result = select_best_connection(
+                    config,
+                    attempt_confs,
+                    &self.inside_io,
+                    &self.dns_proxy,
+                ) => result,
+
+                _ = &mut *stop_signal => return Ok(ClientResult::UserDisconnect),
+            };
+
+            let result = match selected {
+                Err(e) => Err(e),
+                Ok((best_connection_index, measurements, connection)) => {
+                    report_best_connection(
+                        config,
+                        best_connection_index,
+                        measurements,
+                        &connection,
+                    );
+
+                    if let Some(backoff) = backoff.as_mut()
+                        && self.setup.is_some()
+                    {
+                        report(ReconnectEvent::Reconnected {
+                            attempt: backoff.attempts(),
+                        });
+                        backoff.reset();
+                    }
+
+                    let (result, stopped) = self
+                        .run_connection(config, stop_signal, connection, bypass_routes)
+                        .await?;
+                    if stopped || matches!(result, Ok(ClientResult::UserDisconnect)) {
+                        return result;
+                    }
+                    result
                 }
+            };
+
+            // Only an established connection is re-established
+            let (Some(backoff), Some(_)) = (backoff.as_mut(), self.setup.as_ref()) else {
+                return result;
+            };
+
+            let Some(delay) = backoff.next_delay() else {
+                report(ReconnectEvent::GaveUp {
+                    attempts: backoff.attempts(),
+                });
+                return result;
+            };
+            report(ReconnectEvent::Reconnecting {
+                attempt: backoff.attempts(),
+                delay,
+                reason: match &result {
+                    Ok(result) => format!("{result:?}"),
+                    Err(e) => format!("{e:#}"),
+                },
+            });
+
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = &mut *stop_signal => return Ok(ClientResult::UserDisconnect),
             }
-        });
+
+            attempt_confs = conn_confs();
+        }
     }
 
-    if let Some(reload_signal) = config.config_reload_signal.take() {
-        let encoding_request = connection.encoding_request_signal.clone();
-        tokio::spawn(config_reload_task(reload_signal, encoding_request));
+    /// The previous connection's tunnel routes capture all traffic,
+    /// including to the other candidate servers.
+    fn bypass_routes<EventHandler: 'static + Send + EventCallback>(
+        &self,
+        route_mode: RouteMode,
+        attempt_confs: &[ClientConnectionConfig<EventHandler>],
+    ) -> Option<ServerBypassRoutes> {
+        let setup = self.setup.as_ref()?;
+        let servers: std::collections::HashSet<IpAddr> = attempt_confs
+            .iter()
+            .flat_map(|c| c.endpoints())
+            .map(|(addr, _)| addr.ip())
+            .filter(|ip| *ip != setup.server_ip)
+            .collect();
+        ServerBypassRoutes::install(route_mode, servers)
+            .inspect_err(|e| tracing::warn!("Failed to route servers around the tunnel: {e}"))
+            .ok()
     }
 
-    let connection_stop_signal = connection.stop_signal.take().unwrap();
-    tokio::spawn(async move {
-        let _ = stop_signal.await;
-        if let Err(()) = connection_stop_signal.send(()) {
-            tracing::error!("Failed to send stop signal");
+    /// Runs `connection` until it ends, returning its result and whether
+    /// `stop_signal` stopped it.
+    ///
+    /// Its routes and DNS are set up over the previous connection's, which
+    /// are only released afterwards, so that traffic never goes around the
+    /// tunnel in between.
+    async fn run_connection(
+        &mut self,
+        config: &ClientConfig<ExtAppState>,
+        stop_signal: &mut oneshot::Receiver<()>,
+        mut connection: ClientConnection<ExtAppState>,
+        bypass_routes: Option<ServerBypassRoutes>,
+    ) -> Result<(Result<ClientResult>, bool)> {
+        self.current_encoding_request
+            .send_replace(Some(connection.encoding_request_signal.clone()));
+
+        connection.set_connection_inside_io();
+
+        let setup = self.setup_connection(config, &mut connection).await?;
+
+        // Narrow the kill switch down to the selected server only, and the
+        // split DNS resolvers it pushed.
+        #[cfg(linux)]
+        {
+            let connection_type = {
+                let conn = connection.conn.lock().unwrap();
+                self.split_dns_servers = conn
+                    .app_state()
+                    .split_dns
+                    .as_ref()
+                    .map(SplitDnsConfig::servers)
+                    .unwrap_or_default();
+                conn.connection_type()
+            };
+            self.kill_switch.allow_servers(
+                &[AllowedServer {
+                    addr: connection.outside_connection_info().peer_addr,
+                    connection_type,
+                }],
+                &self.split_dns_servers,
+            )?;
         }
-    });
 
-    connection.set_connection_inside_io();
+        // Routes shared with the new setup, such as a bypass route to the
+        // new server, stay in place.
+        drop(bypass_routes);
+        if let Some(previous) = self.setup.replace(setup) {
+            previous.hand_over().await;
+        }
 
-    #[cfg(desktop)]
-    let mut network_change_monitor: Option<NetworkChangeMonitor> = None;
-    #[cfg(desktop)]
-    {
+        let connection_stop_signal = connection.stop_signal.take().unwrap();
+        Ok(tokio::select! {
+            result = &mut connection.task => (result?, false),
+            _ = &mut *stop_signal => {
+                if let Err(()) = connection_stop_signal.send(()) {
+                    tracing::error!("Failed to send stop signal");
+                }
+                ((&mut connection.task).await?, true)
+            }
+        })
+    }
+
+    /// Starts the network change monitor, then configures routes and DNS
+    /// for `connection`.
+    async fn setup_connection(
+        &self,
+        config: &ClientConfig<ExtAppState>,
+        connection: &mut ClientConnection<ExtAppState>,
+    ) -> Result<ConnectionSetup> {
         // Wake sources for the network-event coordinator. An embedder-supplied
         // signal is unclassified, so every event serves as both the route hint
         // and a transition; the internal monitor distinguishes the two.
+        let mut network_change_monitor: Option<NetworkChangeMonitor> = None;
         let (route_rx, transition_rx, nudge_on_route_event) = match config.network_change_signal {
             Some(ref rx) => (rx.clone(), None, true),
             None => {
@@ -1787,35 +2940,31 @@ pub async fn client<
                 ConnectionType::Datagram
             );
 
-        connection
-            .initialize_routes(
-                config.route_mode,
-                config.tun_peer_ip.into(),
-                config.tun_dns_ip.into(),
-                route_rx,
-                transition_rx,
-                nudge_on_route_event,
-                #[cfg(apple)]
-                nudge_on_route_update,
-            )
-            .await?;
-    }
-
-    #[cfg(desktop)]
-    connection.set_dns(config.dns_config_mode, config.tun_dns_ip.into())?;
-
-    let result = connection.task.await?;
+        if !self.uses_netstack {
+            connection
+                .initialize_routes(
+                    config.route_mode,
+                    config.tun_peer_ip.into(),
+                    config.tun_dns_ip.into(),
+                    route_rx,
+                    transition_rx,
+                    nudge_on_route_event,
+                    #[cfg(apple)]
+                    nudge_on_route_update,
+                    #[cfg(linux)]
+                    config.fwmark,
+                )
+                .await?;
+            connection.set_dns(config.dns_config_mode, config.tun_dns_ip.into())?;
+        }
 
-    #[cfg(desktop)]
-    if let Some(mut route_manager) = connection.route_manager {
-        let _ = route_manager.stop().await;
+        Ok(ConnectionSetup {
+            server_ip: connection.outside_connection_info().peer_addr.ip(),
+            route_manager: connection.route_manager.take(),
+            dns_manager: connection.dns_manager.take(),
+            network_change_monitor,
+        })
     }
-
-    // Dropping the monitor aborts its background task.
-    #[cfg(desktop)]
-    drop(network_change_monitor);
-
-    result
 }
 
 #[cfg(test)]
@@ -1855,13 +3004,16 @@ mod tests {
             .unzip();
 
         for (i, rx) in connected_rxs.into_iter().enumerate() {
-            connection_setup_tx.try_send((i, rx)).unwrap();
+            connection_setup_tx
+                .try_send((i, rx, Instant::now()))
+                .unwrap();
         }
         drop(connection_setup_tx);
 
         let task = tokio::spawn(find_best_connection(
             connection_setup_rx,
             Duration::from_millis(200),
+            ServerSelectionConfig::default(),
         ));
 
         tokio::spawn(async move {
@@ -1881,7 +3033,7 @@ mod tests {
 
         tokio::select! {
             index = task => {
-                index.unwrap().ok()
+                index.unwrap().ok().map(|(index, _)| index)
             }
             _ = tokio::time::sleep(wait_duration) => None
         }
@@ -1893,13 +3045,18 @@ mod tests {
         let (_, rx0) = tokio::sync::oneshot::channel::<()>();
         let (tx1, rx1) = tokio::sync::oneshot::channel::<()>();
 
-        connection_setup_tx.try_send((0, rx0)).unwrap();
-        connection_setup_tx.try_send((1, rx1)).unwrap();
+        connection_setup_tx
+            .try_send((0, rx0, Instant::now()))
+            .unwrap();
+        connection_setup_tx
+            .try_send((1, rx1, Instant::now()))
+            .unwrap();
         drop(connection_setup_tx);
 
         let task = tokio::spawn(find_best_connection(
             connection_setup_rx,
             Duration::from_millis(200),
+            ServerSelectionConfig::default(),
         ));
 
         tokio::spawn(async move {
@@ -1910,7 +3067,7 @@ mod tests {
 
         let best_connection_index = tokio::select! {
             index = task => {
-                Some(index.unwrap().unwrap())
+                Some(index.unwrap().unwrap().0)
             }
             _ = tokio::time::sleep(Duration::from_millis(400)) => None
         };
@@ -1918,12 +3075,70 @@ mod tests {
         assert_eq!(best_connection_index, Some(1));
     }
 
+    #[tokio::test]
+    async fn test_find_best_connection_by_handshake_rtt() {
+        let (connection_setup_tx, connection_setup_rx) = mpsc::channel(3);
+        let (tx0, rx0) = oneshot::channel::<()>();
+        let (tx1, rx1) = oneshot::channel::<()>();
+        let (tx2, rx2) = oneshot::channel::<()>();
+
+        connection_setup_tx
+            .try_send((0, rx0, Instant::now()))
+            .unwrap();
+        connection_setup_tx
+            .try_send((1, rx1, Instant::now()))
+            .unwrap();
+        connection_setup_tx
+            .try_send((2, rx2, Instant::now()))
+            .unwrap();
+        drop(connection_setup_tx);
+
+        let task = tokio::spawn(find_best_connection(
+            connection_setup_rx,
+            Duration::from_millis(500),
+            ServerSelectionConfig {
+                strategy: ServerSelection::HandshakeRtt,
+                ..Default::default()
+            },
+        ));
+
+        tokio::spawn(async move {
+            for tx in [tx2, tx0] {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                let _ = tx.send(());
+            }
+            // Connection 1 fails
+            drop(tx1);
+        });
+
+        // Returns once all connections settled, without waiting for the interval
+        let (index, measurements) = tokio::select! {
+            result = task => result.unwrap().unwrap(),
+            _ = tokio::time::sleep(Duration::from_millis(300)) => panic!("Waited for the interval"),
+        };
+
+        assert_eq!(index, 2);
+        let indices: Vec<usize> = measurements.iter().map(|m| m.index).collect();
+        assert_eq!(indices, [2, 0]);
+        assert!(measurements[0].handshake_rtt < measurements[1].handshake_rtt);
+    }
+
     // select_best_from_futures tests
 
     // Helper type alias for boxed connect futures used in select_best_from_futures tests
     type BoxedConnectFut =
         std::pin::Pin<Box<dyn Future<Output = (usize, Result<(oneshot::Receiver<()>, ())>)>>>;
 
+    // A connection already gone, never answering keepalives
+    impl Candidate for () {
+        fn keepalive_probe(&self) -> KeepaliveProbe {
+            KeepaliveProbe::new(
+                Weak::<Mutex<Connection<ConnectionState<()>>>>::new(),
+                KeepaliveReplies::default(),
+            )
+        }
+    }
+
     #[tokio::test]
     async fn test_select_best_one_connect_fails_other_succeeds() {
         let (tx1, rx1) = oneshot::channel::<()>();
@@ -1943,9 +3158,10 @@ mod tests {
             let _ = tx1.send(());
         });
 
-        let (best_index, connections) = select_best_from_futures(futs, Duration::ZERO)
-            .await
-            .unwrap();
+        let (best_index, _, connections) =
+            select_best_from_futures(futs, Duration::ZERO, &ServerSelectionConfig::default())
+                .await
+                .unwrap();
 
         assert_eq!(best_index, 1);
         assert_eq!(connections.len(), 1);
@@ -1964,7 +3180,8 @@ mod tests {
             }));
         }
 
-        let result = select_best_from_futures(futs, Duration::ZERO).await;
+        let result =
+            select_best_from_futures(futs, Duration::ZERO, &ServerSelectionConfig::default()).await;
         assert!(result.is_err());
         assert_eq!(result.unwrap_err().to_string(), expected_error);
     }
@@ -1987,9 +3204,13 @@ mod tests {
             let _ = tx0.send(());
         });
 
-        let (best_index, connections) = select_best_from_futures(futs, Duration::from_millis(200))
-            .await
-            .unwrap();
+        let (best_index, _, connections) = select_best_from_futures(
+            futs,
+            Duration::from_millis(200),
+            &ServerSelectionConfig::default(),
+        )
+        .await
+        .unwrap();
 
         // Server 0 is preferred (lowest index) and connected within wait interval
         assert_eq!(best_index, 0);
@@ -2022,9 +3243,13 @@ mod tests {
         // tx1 intentionally dropped (connection 1 failed to connect)
         drop(tx1);
 
-        let (best_index, connections) = select_best_from_futures(futs, Duration::from_millis(200))
-            .await
-            .unwrap();
+        let (best_index, _, connections) = select_best_from_futures(
+            futs,
+            Duration::from_millis(200),
+            &ServerSelectionConfig::default(),
+        )
+        .await
+        .unwrap();
 
         // Server 0 is preferred and connected within wait
         assert_eq!(best_index, 0);
@@ -2064,9 +3289,13 @@ mod tests {
             let _ = tx0.send(());
         });
 
-        let (best_index, connections) = select_best_from_futures(futs, Duration::from_millis(200))
-            .await
-            .unwrap();
+        let (best_index, _, connections) = select_best_from_futures(
+            futs,
+            Duration::from_millis(200),
+            &ServerSelectionConfig::default(),
+        )
+        .await
+        .unwrap();
 
         assert_eq!(best_index, 0);
         assert_eq!(connections.len(), 3);
@@ -2116,9 +3345,13 @@ mod tests {
             }
         });
 
-        let (best_index, connections) = select_best_from_futures(futs, Duration::from_millis(50))
-            .await
-            .unwrap();
+        let (best_index, _, connections) = select_best_from_futures(
+            futs,
+            Duration::from_millis(50),
+            &ServerSelectionConfig::default(),
+        )
+        .await
+        .unwrap();
 
         assert_eq!(best_index, expected_best);
         assert_eq!(connections.len(), max_idx + 1);
@@ -2137,7 +3370,12 @@ mod tests {
         futs.push(Box::pin(async move { (0usize, Ok((rx0, ()))) }) as BoxedConnectFut);
         futs.push(Box::pin(async move { (1usize, Ok((rx1, ()))) }));
 
-        let result = select_best_from_futures(futs, Duration::from_millis(50)).await;
+        let result = select_best_from_futures(
+            futs,
+            Duration::from_millis(50),
+            &ServerSelectionConfig::default(),
+        )
+        .await;
         assert!(result.is_err());
         assert_eq!(
             result.unwrap_err().to_string(),
@@ -2145,6 +3383,39 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_select_best_unanswered_probes_fall_back_to_handshake_rtt() {
+        let (tx0, rx0) = oneshot::channel::<()>();
+        let (tx1, rx1) = oneshot::channel::<()>();
+
+        let futs = FuturesUnordered::new();
+        futs.push(Box::pin(async move { (0usize, Ok((rx0, ()))) }) as BoxedConnectFut);
+        futs.push(Box::pin(async move { (1usize, Ok((rx1, ()))) }));
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let _ = tx1.send(());
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let _ = tx0.send(());
+        });
+
+        let selection = ServerSelectionConfig {
+            strategy: ServerSelection::KeepaliveRtt,
+            probes: 2,
+            probe_timeout: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let (best_index, measurements, connections) =
+            select_best_from_futures(futs, Duration::from_millis(200), &selection)
+                .await
+                .unwrap();
+
+        assert_eq!(best_index, 1);
+        assert_eq!(measurements.len(), 2);
+        assert!(measurements.iter().all(|m| m.keepalive_rtt.is_none()));
+        assert_eq!(connections.len(), 2);
+    }
+
     #[test_case(Some(true),  Some(true)  => None       ; "unchanged")]
     #[test_case(Some(false), Some(true)  => Some(true) ; "changed")]
     #[test_case(None,        Some(true)  => Some(true) ; "none to some")]
@@ -158,11 +158,13 @@ async fn main() -> Result<()> {
         config,
     )?;
 
-    let conn_confs = join_all(servers.into_iter().map(|c| {
-        ClientConnectionConfig::try_from_event_handler_and_connection_config(Some(EventHandler), c)
+    let servers = join_all(servers.into_iter().map(|c| async move {
+        resolve_server_addrs(&c.server)
+            .await
+            .map(|addrs| (c, addrs))
     }));
-    let conn_confs = tokio::select! {
-        results = conn_confs => {
+    let servers = tokio::select! {
+        results = servers => {
             results.into_iter()
                 .flat_map(|result| result.map_err(|e| tracing::error!("{e}")))
                 .collect::<Vec<_>>()
@@ -176,7 +178,24 @@ async fn main() -> Result<()> {
         }
     };
 
-    client(client_config, ctrlc_rx, conn_confs)
+    // Built again for every reconnect attempt, from the addresses resolved
+    // above as DNS then points at the tunnel
+    let conn_confs = move || {
+        servers
+            .iter()
+            .cloned()
+            .flat_map(|(c, addrs)| {
+                ClientConnectionConfig::try_from_event_handler_and_resolved_config(
+                    Some(EventHandler),
+                    c,
+                    addrs,
+                )
+                .map_err(|e| tracing::error!("{e}"))
+            })
+            .collect()
+    };
+
+    client_with_reconnect(client_config, ctrlc_rx, conn_confs)
         .await
         .map(|_| ())
 }
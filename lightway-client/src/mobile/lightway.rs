@@ -392,6 +392,7 @@ pub(crate) async fn async_lightway_start(
         conn.clone(),
         inside_io,
         config.tun_dns_ip,
+        None,
         keepalive,
         keepalive_config,
         Duration::ZERO,
@@ -610,6 +611,8 @@ async fn lightway_client_connect(
     let state: ConnectionState<TunnelState> = ConnectionState {
         ticker,
         ip_config: None,
+        split_dns: None,
+        site_subnets: Vec::new(),
         extended: None,
     };
     let (pmtud_timer, pmtud_timer_task) = DplpmtudTimer::new();
@@ -802,7 +805,7 @@ async fn handle_events<A: 'static + Send + EventCallback>(
                     };
                 }
             }
-            Event::KeepaliveReply => {
+            Event::KeepaliveReply { .. } => {
                 notify_keepalive_reply.notify_waiters();
                 keepalive.reply_received().await
             }
@@ -816,6 +819,10 @@ async fn handle_events<A: 'static + Send + EventCallback>(
                 continue;
             }
             Event::FirstPacketReceived | Event::EncodingStateChanged { .. } => (), // will be handled by handle_global_events
+            // Split DNS is left to the platform VPN APIs on mobile
+            Event::ServerConfig(_) => (),
+            // Mobile apps reconnect with a new connection from scratch
+            Event::SessionTicket(_) => (),
 
             // Server-only events
             Event::SessionIdRotationAcknowledged { .. }
@@ -0,0 +1,548 @@
+//! NAT binding lifetime probing
+//!
+//! A NAT drops the UDP binding of a connection once it stays idle for
+//! longer than the NAT's timeout, after which the server can no longer
+//! reach the client until the client sends again. The lifetime of the
+//! binding is probed by sending keepalives after growing idle times:
+//! the server answers a packet arriving from a new binding to the
+//! address it knew before floating the connection to the new one (see
+//! `docs/udp_session_id.md`), so when the binding expired the reply to
+//! the first keepalive is lost while the one to a keepalive sent right
+//! after arrives.
+//!
+//! Idleness is measured from the inside packets sent, the regular
+//! keepalives being held back while probing so that they do not refresh
+//! the binding being probed.
+//!
+//! Once the lifetime is known the keepalives on an idle connection are
+//! sent a little ahead of it, whatever the configured interval. A NoOp
+//! frame, the smallest there is and not answered, refreshes the binding
+//! whenever nothing else was sent for a little less than the lifetime,
+//! e.g. when keepalives are not continuous. Lifetimes are learned once
+//! per network, see [`NetworkChangeMonitor::current_network`].
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::IpAddr,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+use lightway_app_utils::{NetworkChangeMonitor, NetworkId};
+
+use crate::{
+    ConnectionState,
+    keepalive::{self, Keepalive, KeepaliveReplies},
+};
+
+/// Fraction of the binding lifetime after which the binding is refreshed
+const REFRESH_MARGIN: f64 = 0.9;
+
+/// Fraction of the binding lifetime keepalives are sent after on an idle
+/// connection, ahead of the refresh so that only one of them is sent
+const KEEPALIVE_MARGIN: f64 = 0.8;
+
+/// Factor the idle time grows by between probes
+const PROBE_GROWTH: f64 = 1.5;
+
+/// How long to wait for the reply to a probe
+const REPLY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Poll interval while waiting for the connection to come online
+const ONLINE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Bounds of NAT binding lifetime probing
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    /// Idle time probing starts at. Bindings expiring sooner are taken
+    /// to last this long
+    pub min_interval: Duration,
+    /// Idle time probing stops at. Bindings lasting longer are taken to
+    /// last this long
+    pub max_interval: Duration,
+}
+
+/// NAT binding lifetimes learned on each network. Shared by every
+/// connection of a client.
+#[derive(Clone, Debug, Default)]
+pub struct NatLifetimes(Arc<Mutex<HashMap<NetworkId, Duration>>>);
+
+impl NatLifetimes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lifetime of NAT bindings on `network`, if learned
+    pub fn get(&self, network: &NetworkId) -> Option<Duration> {
+        self.0.lock().unwrap().get(network).copied()
+    }
+
+    pub(crate) fn set(&self, network: NetworkId, lifetime: Duration) {
+        self.0.lock().unwrap().insert(network, lifetime);
+    }
+}
+
+/// Connection whose NAT binding is probed and refreshed.
+pub(crate) trait Connection: keepalive::Connection {
+    /// Last time anything was sent, `None` once the connection is gone
+    fn last_sent(&self) -> Option<Instant>;
+
+    /// Last time an inside packet was sent, `None` once the connection
+    /// is gone
+    fn last_data_sent(&self) -> Option<Instant>;
+
+    /// Send a frame keeping the binding alive without asking for a reply
+    fn nat_keepalive(&self) -> lightway_core::ConnectionResult<()>;
+
+    /// The network `peer` is reached over, `None` if unknown
+    fn current_network(&self, peer: IpAddr) -> impl Future<Output = Option<NetworkId>> + Send {
+        current_network(peer)
+    }
+}
+
+impl<T: Send + Sync> Connection for Weak<Mutex<lightway_core::Connection<ConnectionState<T>>>> {
+    fn last_sent(&self) -> Option<Instant> {
+        let conn = self.upgrade()?;
+        let conn = conn.lock().unwrap();
+        Some(conn.activity().last_outside_data_sent)
+    }
+
+    fn last_data_sent(&self) -> Option<Instant> {
+        let conn = self.upgrade()?;
+        let conn = conn.lock().unwrap();
+        Some(conn.activity().last_data_sent)
+    }
+
+    fn nat_keepalive(&self) -> lightway_core::ConnectionResult<()> {
+        let Some(conn) = self.upgrade() else {
+            return Ok(());
+        };
+        let mut conn = conn.lock().unwrap();
+        conn.nat_keepalive()
+    }
+}
+
+/// Outcome of a probe after an idle time
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Outcome {
+    /// The keepalive was answered, the binding is alive
+    Answered,
+    /// Only a second keepalive was answered, the binding expired
+    Expired,
+    /// Neither keepalive was answered, nothing is known
+    Unanswered,
+}
+
+/// Searches for the binding lifetime, growing the idle time until the
+/// binding expires.
+struct Prober {
+    config: Config,
+    /// Idle time to probe after next
+    idle: Duration,
+    /// Longest idle time the binding was seen to survive
+    survived: Option<Duration>,
+}
+
+impl Prober {
+    fn new(config: Config) -> Self {
+        Self {
+            config,
+            idle: config.min_interval,
+            survived: None,
+        }
+    }
+
+    /// Account for the `outcome` of probing after [`Self::idle`],
+    /// returning the binding lifetime once known.
+    fn outcome(&mut self, outcome: Outcome) -> Option<Duration> {
+        match outcome {
+            Outcome::Answered if self.idle >= self.config.max_interval => {
+                Some(self.config.max_interval)
+            }
+            Outcome::Answered => {
+                self.survived = Some(self.idle);
+                self.idle = self
+                    .idle
+                    .mul_f64(PROBE_GROWTH)
+                    .min(self.config.max_interval);
+                None
+            }
+            Outcome::Expired => Some(self.survived.unwrap_or(self.config.min_interval)),
+            Outcome::Unanswered => None,
+        }
+    }
+}
+
+/// Idle time after which a binding of `lifetime` is refreshed
+fn refresh_interval(lifetime: Duration) -> Duration {
+    lifetime.mul_f64(REFRESH_MARGIN)
+}
+
+/// Interval of keepalives on an idle connection whose binding lasts
+/// `lifetime`
+fn keepalive_interval(lifetime: Duration) -> Duration {
+    lifetime.mul_f64(KEEPALIVE_MARGIN)
+}
+
+/// Interval of keepalives on an idle connection while probing, beyond
+/// the longest probe so that none refreshes the binding
+fn probing_keepalive_interval(config: Config) -> Duration {
+    config.max_interval + 2 * REPLY_TIMEOUT
+}
+
+/// Keep the NAT binding of `conn` to `peer` alive once `is_online`,
+/// probing its lifetime on networks not seen before. The interval of
+/// `keepalive` on an idle connection follows the lifetime.
+pub(crate) async fn nat_keepalive(
+    conn: impl Connection,
+    keepalive: Keepalive,
+    keepalive_replies: KeepaliveReplies,
+    is_online: impl Fn() -> bool + Send,
+    peer: IpAddr,
+    config: Config,
+    lifetimes: NatLifetimes,
+) {
+    while !is_online() {
+        tokio::time::sleep(ONLINE_POLL_INTERVAL).await;
+    }
+
+    loop {
+        let network = conn.current_network(peer).await;
+        let lifetime = match network.and_then(|network| lifetimes.get(&network)) {
+            Some(lifetime) => lifetime,
+            None => {
+                tracing::info!(?network, "Probing NAT binding lifetime");
+                keepalive
+                    .set_idle_interval(Some(probing_keepalive_interval(config)))
+                    .await;
+                let lifetime = match probe(&conn, &keepalive_replies, peer, network, config).await {
+                    Probed::Lifetime(lifetime) => lifetime,
+                    Probed::NetworkChanged => continue,
+                    Probed::Closed => return,
+                };
+                tracing::info!(?network, ?lifetime, "Learned NAT binding lifetime");
+                if let Some(network) = network {
+                    lifetimes.set(network, lifetime);
+                }
+                lifetime
+            }
+        };
+
+        keepalive
+            .set_idle_interval(Some(keepalive_interval(lifetime)))
+            .await;
+
+        loop {
+            if !wait_idle(|| conn.last_sent(), refresh_interval(lifetime)).await {
+                return;
+            }
+            if conn.current_network(peer).await != network {
+                break;
+            }
+            if let Err(e) = conn.nat_keepalive() {
+                tracing::error!("Send NAT keepalive failed: {e:?}");
+            }
+        }
+    }
+}
+
+/// Result of probing the binding lifetime
+enum Probed {
+    Lifetime(Duration),
+    /// The network changed while probing
+    NetworkChanged,
+    /// The connection is gone
+    Closed,
+}
+
+/// Probe the binding lifetime on `network`.
+async fn probe(
+    conn: &impl Connection,
+    keepalive_replies: &KeepaliveReplies,
+    peer: IpAddr,
+    network: Option<NetworkId>,
+    config: Config,
+) -> Probed {
+    let mut prober = Prober::new(config);
+    // The probes refresh the binding as well
+    let mut last_probed: Option<Instant> = None;
+    loop {
+        let last_sent = || {
+            let last_data_sent = conn.last_data_sent()?;
+            Some(last_probed.map_or(last_data_sent, |probed| probed.max(last_data_sent)))
+        };
+        if !wait_idle(last_sent, prober.idle).await {
+            return Probed::Closed;
+        }
+        if conn.current_network(peer).await != network {
+            return Probed::NetworkChanged;
+        }
+
+        let outcome = if answered(conn, keepalive_replies, REPLY_TIMEOUT).await {
+            Outcome::Answered
+        } else if answered(conn, keepalive_replies, REPLY_TIMEOUT).await {
+            Outcome::Expired
+        } else {
+            Outcome::Unanswered
+        };
+        last_probed = Some(now());
+        tracing::debug!(idle = ?prober.idle, ?outcome, "Probed NAT binding");
+
+        if let Some(lifetime) = prober.outcome(outcome) {
+            return Probed::Lifetime(lifetime);
+        }
+    }
+}
+
+/// Wait until nothing was sent for `idle`, as of `last_sent`. Returns
+/// `false` once the connection is gone, when `last_sent` is `None`.
+async fn wait_idle(last_sent: impl Fn() -> Option<Instant>, idle: Duration) -> bool {
+    loop {
+        let Some(last_sent) = last_sent() else {
+            return false;
+        };
+        let idle_for = now().saturating_duration_since(last_sent);
+        if idle_for >= idle {
+            return true;
+        }
+        tokio::time::sleep(idle - idle_for).await;
+    }
+}
+
+/// The time now as told by tokio, whose clock tests pause
+fn now() -> Instant {
+    tokio::time::Instant::now().into_std()
+}
+
+/// Send a keepalive, returning whether it was answered within `timeout`.
+async fn answered(
+    conn: &impl Connection,
+    keepalive_replies: &KeepaliveReplies,
+    timeout: Duration,
+) -> bool {
+    keepalive_replies
+        .probe(conn, timeout)
+        .await
+        .unwrap_or_else(|e| {
+            tracing::debug!("NAT binding probe failed: {e}");
+            false
+        })
+}
+
+async fn current_network(peer: IpAddr) -> Option<NetworkId> {
+    tokio::task::spawn_blocking(move || NetworkChangeMonitor::current_network(peer))
+        .await
+        .ok()?
+        .inspect_err(|e| tracing::debug!("Failed to identify the network: {e}"))
+        .ok()?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lightway_core::KeepaliveId;
+    use test_case::test_case;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    fn prober() -> Prober {
+        Prober::new(Config {
+            min_interval: secs(20),
+            max_interval: secs(100),
+        })
+    }
+
+    #[test_case(&[Outcome::Expired] => Some(secs(20)); "expired at first")]
+    #[test_case(&[Outcome::Answered, Outcome::Expired] => Some(secs(20)); "expired at second")]
+    #[test_case(&[Outcome::Answered, Outcome::Answered, Outcome::Expired] => Some(secs(30)); "expired at third")]
+    #[test_case(&[Outcome::Answered, Outcome::Unanswered, Outcome::Expired] => Some(secs(20)); "unanswered retries")]
+    #[test_case(&[Outcome::Answered; 5] => Some(secs(100)); "outlives max")]
+    #[test_case(&[Outcome::Answered; 4] => None; "still probing")]
+    fn lifetime(outcomes: &[Outcome]) -> Option<Duration> {
+        let mut prober = prober();
+        let mut lifetime = None;
+        for outcome in outcomes {
+            assert_eq!(lifetime, None, "probed after the lifetime was known");
+            lifetime = prober.outcome(*outcome);
+        }
+        lifetime
+    }
+
+    #[test]
+    fn idle_grows_up_to_max() {
+        let mut prober = prober();
+        let mut idles = vec![prober.idle];
+        while prober.outcome(Outcome::Answered).is_none() {
+            idles.push(prober.idle);
+        }
+        assert_eq!(
+            idles,
+            [
+                secs(20),
+                secs(30),
+                secs(45),
+                Duration::from_millis(67_500),
+                secs(100)
+            ]
+        );
+    }
+
+    #[test]
+    fn refreshed_before_expiry() {
+        assert_eq!(refresh_interval(secs(30)), secs(27));
+        assert!(keepalive_interval(secs(30)) < refresh_interval(secs(30)));
+    }
+
+    /// Lifetime of the binding of the simulated NAT
+    const NAT_LIFETIME: Duration = Duration::from_secs(50);
+
+    /// Round trip time to the server
+    const RTT: Duration = Duration::from_millis(50);
+
+    const NETWORK: NetworkId = NetworkId {
+        gateway: IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, 1)),
+        if_index: Some(2),
+    };
+
+    /// Idle connection behind a NAT whose binding expires after
+    /// [`NAT_LIFETIME`], losing the reply to the packet sent after.
+    #[derive(Clone)]
+    struct NatConnection {
+        state: Arc<Mutex<NatState>>,
+        replies: tokio::sync::mpsc::UnboundedSender<KeepaliveId>,
+    }
+
+    struct NatState {
+        online: Instant,
+        last_sent: Instant,
+        /// Bindings expired
+        expired: usize,
+        keepalives: u32,
+        nat_keepalives: usize,
+    }
+
+    impl NatConnection {
+        /// Send a packet through the NAT, returning whether its binding
+        /// was still alive
+        fn send(&self) -> bool {
+            let mut state = self.state.lock().unwrap();
+            let now = now();
+            let alive = now.duration_since(state.last_sent) < NAT_LIFETIME;
+            if !alive {
+                state.expired += 1;
+            }
+            state.last_sent = now;
+            alive
+        }
+    }
+
+    impl keepalive::Connection for NatConnection {
+        fn keepalive(&self) -> lightway_core::ConnectionResult<Option<KeepaliveId>> {
+            let id = {
+                let mut state = self.state.lock().unwrap();
+                state.keepalives += 1;
+                KeepaliveId(state.keepalives)
+            };
+            if self.send() {
+                let replies = self.replies.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(RTT).await;
+                    let _ = replies.send(id);
+                });
+            }
+            Ok(Some(id))
+        }
+    }
+
+    impl Connection for NatConnection {
+        fn last_sent(&self) -> Option<Instant> {
+            Some(self.state.lock().unwrap().last_sent)
+        }
+
+        fn last_data_sent(&self) -> Option<Instant> {
+            Some(self.state.lock().unwrap().online)
+        }
+
+        fn nat_keepalive(&self) -> lightway_core::ConnectionResult<()> {
+            self.send();
+            self.state.lock().unwrap().nat_keepalives += 1;
+            Ok(())
+        }
+
+        async fn current_network(&self, _peer: IpAddr) -> Option<NetworkId> {
+            Some(NETWORK)
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn learns_lifetime_with_default_keepalives() {
+        let defaults = crate::config::Config::default();
+        let keepalive_config = keepalive::Config {
+            interval: defaults.keepalive_interval.into(),
+            timeout: defaults.keepalive_timeout.into(),
+            continuous: defaults.keepalive_continuous,
+            tracer_trigger_timeout: None,
+        };
+        let config = Config {
+            min_interval: defaults.keepalive_nat_min_interval.into(),
+            max_interval: defaults.keepalive_nat_max_interval.into(),
+        };
+
+        let (replies, mut replies_rx) = tokio::sync::mpsc::unbounded_channel();
+        let conn = NatConnection {
+            state: Arc::new(Mutex::new(NatState {
+                online: now(),
+                last_sent: now(),
+                expired: 0,
+                keepalives: 0,
+                nat_keepalives: 0,
+            })),
+            replies,
+        };
+        let (keepalive, keepalive_task) = Keepalive::new(keepalive_config, conn.clone());
+        let keepalive_replies = KeepaliveReplies::new();
+        let lifetimes = NatLifetimes::new();
+
+        let forward_replies = {
+            let keepalive = keepalive.clone();
+            let keepalive_replies = keepalive_replies.clone();
+            tokio::spawn(async move {
+                while let Some(id) = replies_rx.recv().await {
+                    keepalive_replies.received(Some(id));
+                    keepalive.reply_received().await;
+                }
+            })
+        };
+        keepalive.online().await;
+        let nat_keepalive = tokio::spawn(nat_keepalive(
+            conn.clone(),
+            keepalive.clone(),
+            keepalive_replies,
+            || true,
+            IpAddr::V4(std::net::Ipv4Addr::LOCALHOST),
+            config,
+            lifetimes.clone(),
+        ));
+
+        tokio::time::sleep(Duration::from_secs(20 * 60)).await;
+
+        // Idle for 45s survived, 67.5s did not
+        assert_eq!(lifetimes.get(&NETWORK), Some(secs(45)));
+        let state = conn.state.lock().unwrap();
+        // Only the probe expired the binding, keepalives refresh it since
+        assert_eq!(state.expired, 1);
+        assert_eq!(state.nat_keepalives, 0);
+        drop(state);
+
+        nat_keepalive.abort();
+        forward_replies.abort();
+        drop(keepalive);
+        assert!(matches!(
+            keepalive_task.await.unwrap().unwrap(),
+            keepalive::KeepaliveResult::Cancelled
+        ));
+    }
+}
@@ -1 +1,19 @@
 pub mod dns_manager;
+pub mod kill_switch;
+pub mod policy_routing;
+
+use std::fs;
+
+/// Resolve the network interface name from its kernel index via `/sys/class/net`.
+pub(crate) fn ifname_from_index(ifindex: u32) -> Option<String> {
+    let net_dir = fs::read_dir("/sys/class/net").ok()?;
+    for entry in net_dir.flatten() {
+        let index_path = entry.path().join("ifindex");
+        if let Ok(content) = fs::read_to_string(&index_path)
+            && content.trim().parse::<u32>().ok() == Some(ifindex)
+        {
+            return Some(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    None
+}
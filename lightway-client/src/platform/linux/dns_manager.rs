@@ -1,15 +1,25 @@
 mod direct_file;
+mod network_manager;
 mod resolvconf;
 mod resolvectl;
+mod resolved_dbus;
 
-use std::fs;
 use std::net::IpAddr;
 
-use crate::dns_manager::{DnsManagerError, DnsSetup};
+use lightway_app_utils::split_dns::SplitDnsConfig;
+use zbus::blocking::Connection;
+
+use crate::dns_manager::{DnsConfigMode, DnsManagerError, DnsSetup};
+
+use super::ifname_from_index;
 
 /// Trait implemented by each DNS backend.
 pub(super) trait DnsBackend {
     fn set(&self, dns_server: IpAddr) -> Result<(), DnsManagerError>;
+    /// Only backends with per-link routing domains can support split DNS.
+    fn set_split(&self, _config: &SplitDnsConfig) -> Result<(), DnsManagerError> {
+        Err(DnsManagerError::SplitDnsUnsupported)
+    }
     fn reset(&self) -> Result<(), DnsManagerError>;
 }
 
@@ -20,8 +30,12 @@ pub struct DnsManager {
 
 impl DnsManager {
     pub fn new(ifindex: u32) -> Self {
+        Self::with_mode(ifindex, DnsConfigMode::Default)
+    }
+
+    pub fn with_mode(ifindex: u32, mode: DnsConfigMode) -> Self {
         Self {
-            backend: detect_backend(ifindex),
+            backend: select_backend(ifindex, mode),
             setup: false,
         }
     }
@@ -33,20 +47,6 @@ fn binary_in_path(name: &str) -> bool {
         .unwrap_or(false)
 }
 
-/// Resolve the network interface name from its kernel index via `/sys/class/net`.
-fn ifname_from_index(ifindex: u32) -> Option<String> {
-    let net_dir = fs::read_dir("/sys/class/net").ok()?;
-    for entry in net_dir.flatten() {
-        let index_path = entry.path().join("ifindex");
-        if let Ok(content) = fs::read_to_string(&index_path)
-            && content.trim().parse::<u32>().ok() == Some(ifindex)
-        {
-            return Some(entry.file_name().to_string_lossy().into_owned());
-        }
-    }
-    None
-}
-
 /// Returns `true` when systemd-resolved is actively running and wired up as the
 /// system resolver.
 ///
@@ -90,12 +90,66 @@ fn resolvconf_running() -> bool {
     std::path::Path::new("/run/resolvconf/resolv.conf").exists()
 }
 
+/// Connect to the system bus, returning `None` if it is unavailable.
+fn system_bus() -> Option<Connection> {
+    Connection::system()
+        .inspect_err(|e| tracing::debug!("System D-Bus unavailable: {e}"))
+        .ok()
+}
+
+/// Returns `true` when `name` is currently owned on the bus.
+fn bus_name_has_owner(conn: &Connection, name: &str) -> bool {
+    zbus::blocking::fdo::DBusProxy::new(conn)
+        .and_then(|proxy| proxy.name_has_owner(name.try_into()?))
+        .unwrap_or(false)
+}
+
+fn select_backend(ifindex: u32, mode: DnsConfigMode) -> Box<dyn DnsBackend> {
+    let forced: Option<Box<dyn DnsBackend>> = match mode {
+        DnsConfigMode::Resolved => system_bus()
+            .filter(|conn| bus_name_has_owner(conn, resolved_dbus::RESOLVED_BUS_NAME))
+            .map(|conn| Box::new(resolved_dbus::ResolvedDbus::new(conn, ifindex)) as _),
+        DnsConfigMode::NetworkManager => ifname_from_index(ifindex).and_then(|iface_name| {
+            system_bus()
+                .filter(|conn| {
+                    bus_name_has_owner(conn, network_manager::NETWORK_MANAGER_BUS_NAME)
+                        && network_manager::NetworkManager::manages(conn, &iface_name)
+                })
+                .map(|conn| Box::new(network_manager::NetworkManager::new(conn, iface_name)) as _)
+        }),
+        _ => return detect_backend(ifindex),
+    };
+    forced.unwrap_or_else(|| {
+        tracing::warn!(
+            ?mode,
+            "DNS backend unavailable, falling back to auto-detection"
+        );
+        detect_backend(ifindex)
+    })
+}
+
 fn detect_backend(ifindex: u32) -> Box<dyn DnsBackend> {
     if let Some(iface_name) = ifname_from_index(ifindex) {
+        let bus = system_bus();
+        if systemd_resolved_running()
+            && let Some(conn) = bus
+                .as_ref()
+                .filter(|conn| bus_name_has_owner(conn, resolved_dbus::RESOLVED_BUS_NAME))
+        {
+            tracing::debug!("Using systemd-resolved D-Bus DNS backend for interface {iface_name}");
+            return Box::new(resolved_dbus::ResolvedDbus::new(conn.clone(), ifindex));
+        }
         if binary_in_path("resolvectl") && systemd_resolved_running() {
             tracing::debug!("Using resolvectl DNS backend for interface {iface_name}");
             return Box::new(resolvectl::Resolvectl { iface_name });
         }
+        if let Some(conn) = bus.filter(|conn| {
+            bus_name_has_owner(conn, network_manager::NETWORK_MANAGER_BUS_NAME)
+                && network_manager::NetworkManager::manages(conn, &iface_name)
+        }) {
+            tracing::debug!("Using NetworkManager DNS backend for interface {iface_name}");
+            return Box::new(network_manager::NetworkManager::new(conn, iface_name));
+        }
         if binary_in_path("resolvconf") && resolvconf_running() {
             tracing::debug!("Using resolvconf DNS backend for interface {iface_name}");
             return Box::new(resolvconf::Resolvconf::new(iface_name));
@@ -121,6 +175,15 @@ impl DnsSetup for DnsManager {
         Ok(())
     }
 
+    fn set_split_dns(&mut self, config: &SplitDnsConfig) -> Result<(), DnsManagerError> {
+        if self.setup {
+            return Err(DnsManagerError::DnsAlreadyConfigured);
+        }
+        self.backend.set_split(config)?;
+        self.setup = true;
+        Ok(())
+    }
+
     fn reset_dns(&mut self) -> Result<(), DnsManagerError> {
         if self.setup {
             self.backend.reset()?;
@@ -128,6 +191,10 @@ impl DnsSetup for DnsManager {
         self.setup = false;
         Ok(())
     }
+
+    fn hand_over(&mut self) {
+        self.setup = false;
+    }
 }
 
 impl Drop for DnsManager {
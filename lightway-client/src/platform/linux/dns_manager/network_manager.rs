@@ -0,0 +1,444 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use lightway_app_utils::split_dns::SplitDnsConfig;
+use zbus::blocking::Connection;
+use zbus::proxy::CacheProperties;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue, Value};
+
+use crate::dns_manager::DnsManagerError;
+
+use super::DnsBackend;
+
+pub(super) const NETWORK_MANAGER_BUS_NAME: &str = "org.freedesktop.NetworkManager";
+
+/// Lower values win; negative ones exclude every other connection with a
+/// higher priority, so the tunnel's resolver is used exclusively.
+const DNS_PRIORITY: i32 = -i32::MAX;
+
+/// NetworkManager's default for VPN connections. Split DNS only claims
+/// its routing domains, so other connections must stay in use.
+const SPLIT_DNS_PRIORITY: i32 = 50;
+
+/// `NM_DEVICE_STATE_ACTIVATED`: the device has an active connection.
+const DEVICE_STATE_ACTIVATED: u32 = 100;
+
+/// Connection settings as exchanged with NetworkManager (`a{sa{sv}}`).
+type Settings = HashMap<String, HashMap<String, OwnedValue>>;
+
+/// Settings applied before the first reapply, per interface. Shared by
+/// every manager of an interface: when a reconnect configures DNS before
+/// the previous connection hands it over, the new manager would otherwise
+/// record the tunnel's own settings as the ones to restore.
+static ORIGINALS: Mutex<Vec<(String, Settings)>> = Mutex::new(Vec::new());
+
+#[zbus::proxy(
+    interface = "org.freedesktop.NetworkManager",
+    default_service = "org.freedesktop.NetworkManager",
+    default_path = "/org/freedesktop/NetworkManager"
+)]
+trait NetworkManager {
+    fn get_device_by_ip_iface(&self, iface: &str) -> zbus::Result<OwnedObjectPath>;
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.NetworkManager.Device",
+    default_service = "org.freedesktop.NetworkManager"
+)]
+trait Device {
+    fn get_applied_connection(&self, flags: u32) -> zbus::Result<(Settings, u64)>;
+
+    fn reapply(&self, connection: &Settings, version_id: u64, flags: u32) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn managed(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn state(&self) -> zbus::Result<u32>;
+}
+
+/// NetworkManager backend, configured over D-Bus.
+///
+/// Reapplies the TUN device's active connection with the tunnel resolver,
+/// a `~.` search domain and an exclusive DNS priority. The original applied
+/// settings are restored on reset.
+pub(super) struct NetworkManager {
+    conn: Connection,
+    iface_name: String,
+}
+
+impl NetworkManager {
+    pub(super) fn new(conn: Connection, iface_name: String) -> Self {
+        Self { conn, iface_name }
+    }
+
+    /// Whether NetworkManager manages `iface_name` and has activated a
+    /// connection on it, which can be reapplied. A freshly created TUN
+    /// device is usually unmanaged or externally connected instead.
+    pub(super) fn manages(conn: &Connection, iface_name: &str) -> bool {
+        device_by_iface(conn, iface_name)
+            .and_then(|device| Ok(device.managed()? && device.state()? == DEVICE_STATE_ACTIVATED))
+            .unwrap_or(false)
+    }
+
+    fn device(&self) -> zbus::Result<DeviceProxyBlocking<'_>> {
+        device_by_iface(&self.conn, &self.iface_name)
+    }
+
+    fn reapply_with(
+        &self,
+        servers: &[IpAddr],
+        search: &[String],
+        priority: i32,
+    ) -> Result<(), DnsManagerError> {
+        let set_err = |e: zbus::Error| DnsManagerError::FailedToSetDnsConfig(e.to_string());
+        let device = self.device().map_err(set_err)?;
+        let (applied, version_id) = device.get_applied_connection(0).map_err(set_err)?;
+
+        let mut settings = applied.clone();
+        apply_dns(&mut settings, servers, search, priority).map_err(set_err)?;
+        device.reapply(&settings, version_id, 0).map_err(set_err)?;
+
+        let mut originals = ORIGINALS.lock().unwrap();
+        if !originals.iter().any(|(iface, _)| *iface == self.iface_name) {
+            originals.push((self.iface_name.clone(), applied));
+        }
+        Ok(())
+    }
+}
+
+impl DnsBackend for NetworkManager {
+    fn set(&self, dns_server: IpAddr) -> Result<(), DnsManagerError> {
+        // ~. routes all DNS queries through this interface
+        self.reapply_with(&[dns_server], &["~.".to_string()], DNS_PRIORITY)
+    }
+
+    fn set_split(&self, config: &SplitDnsConfig) -> Result<(), DnsManagerError> {
+        let search: Vec<String> = config
+            .routing_domains()
+            .map(|domain| format!("~{domain}"))
+            .chain(config.search_domain_names().map(str::to_string))
+            .collect();
+        self.reapply_with(&config.servers(), &search, SPLIT_DNS_PRIORITY)
+    }
+
+    fn reset(&self) -> Result<(), DnsManagerError> {
+        let original = {
+            let mut originals = ORIGINALS.lock().unwrap();
+            let Some(pos) = originals
+                .iter()
+                .position(|(iface, _)| *iface == self.iface_name)
+            else {
+                return Ok(());
+            };
+            originals.swap_remove(pos).1
+        };
+        let restore_err = |e: zbus::Error| DnsManagerError::FailedToRestoreDnsConfig(e.to_string());
+        let device = self.device().map_err(restore_err)?;
+        let (_, version_id) = device.get_applied_connection(0).map_err(restore_err)?;
+        device
+            .reapply(&original, version_id, 0)
+            .map_err(restore_err)
+    }
+}
+
+fn device_by_iface<'a>(
+    conn: &'a Connection,
+    iface_name: &str,
+) -> zbus::Result<DeviceProxyBlocking<'a>> {
+    let path = NetworkManagerProxyBlocking::new(conn)?.get_device_by_ip_iface(iface_name)?;
+    // Properties are read once, no need to track their changes
+    DeviceProxyBlocking::builder(conn)
+        .path(path)?
+        .cache_properties(CacheProperties::No)
+        .build()
+}
+
+/// Point the connection's DNS at `servers`, for the domains in `search`.
+///
+/// Settings are written to the `ipv4` and/or `ipv6` group, depending on
+/// the address families in `servers`.
+fn apply_dns(
+    settings: &mut Settings,
+    servers: &[IpAddr],
+    search: &[String],
+    priority: i32,
+) -> zbus::Result<()> {
+    // NetworkManager expects IPv4 addresses in network byte order
+    let v4: Vec<u32> = servers
+        .iter()
+        .filter_map(|ip| match ip {
+            IpAddr::V4(ip) => Some(u32::from_ne_bytes(ip.octets())),
+            IpAddr::V6(_) => None,
+        })
+        .collect();
+    let v6: Vec<Vec<u8>> = servers
+        .iter()
+        .filter_map(|ip| match ip {
+            IpAddr::V4(_) => None,
+            IpAddr::V6(ip) => Some(ip.octets().to_vec()),
+        })
+        .collect();
+
+    if !v4.is_empty() {
+        let group = settings.entry("ipv4".to_string()).or_default();
+        apply_dns_group(group, Value::from(v4), search, priority)?;
+    }
+    if !v6.is_empty() {
+        let group = settings.entry("ipv6".to_string()).or_default();
+        apply_dns_group(group, Value::from(v6), search, priority)?;
+    }
+    Ok(())
+}
+
+fn apply_dns_group(
+    group: &mut HashMap<String, OwnedValue>,
+    dns: Value<'_>,
+    search: &[String],
+    priority: i32,
+) -> zbus::Result<()> {
+    group.insert("dns".to_string(), dns.try_into()?);
+    group.insert(
+        "dns-search".to_string(),
+        Value::from(search.to_vec()).try_into()?,
+    );
+    group.insert("dns-priority".to_string(), priority.into());
+    group.insert("ignore-auto-dns".to_string(), true.into());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use std::os::unix::net::UnixStream;
+    use std::sync::Arc;
+
+    /// Not shared with other tests, [`ORIGINALS`] is global
+    const MOCK_IFACE: &str = "lw-nm-mock0";
+    const MOCK_DEVICE_PATH: &str = "/org/freedesktop/NetworkManager/Devices/7";
+    /// Known to NetworkManager, but not managed by it
+    const UNMANAGED_IFACE: &str = "lw-nm-mock1";
+    const UNMANAGED_DEVICE_PATH: &str = "/org/freedesktop/NetworkManager/Devices/8";
+    /// `NM_DEVICE_STATE_UNMANAGED`
+    const DEVICE_STATE_UNMANAGED: u32 = 10;
+
+    /// Applied connection of the mock device
+    struct Applied {
+        settings: Settings,
+        version_id: u64,
+    }
+
+    struct MockNetworkManager;
+
+    #[zbus::interface(name = "org.freedesktop.NetworkManager")]
+    impl MockNetworkManager {
+        fn get_device_by_ip_iface(&self, iface: &str) -> zbus::fdo::Result<OwnedObjectPath> {
+            let path = match iface {
+                MOCK_IFACE => MOCK_DEVICE_PATH,
+                UNMANAGED_IFACE => UNMANAGED_DEVICE_PATH,
+                _ => {
+                    return Err(zbus::fdo::Error::UnknownObject(format!(
+                        "No device found for interface {iface}"
+                    )));
+                }
+            };
+            Ok(OwnedObjectPath::try_from(path).unwrap())
+        }
+    }
+
+    struct MockDevice {
+        applied: Arc<Mutex<Applied>>,
+        managed: bool,
+        state: u32,
+    }
+
+    #[zbus::interface(name = "org.freedesktop.NetworkManager.Device")]
+    impl MockDevice {
+        fn get_applied_connection(&self, _flags: u32) -> (Settings, u64) {
+            let applied = self.applied.lock().unwrap();
+            (applied.settings.clone(), applied.version_id)
+        }
+
+        fn reapply(
+            &self,
+            connection: Settings,
+            version_id: u64,
+            _flags: u32,
+        ) -> zbus::fdo::Result<()> {
+            let mut applied = self.applied.lock().unwrap();
+            if version_id != applied.version_id {
+                return Err(zbus::fdo::Error::Failed("Version id mismatch".to_string()));
+            }
+            applied.settings = connection;
+            applied.version_id += 1;
+            Ok(())
+        }
+
+        #[zbus(property)]
+        fn managed(&self) -> bool {
+            self.managed
+        }
+
+        #[zbus(property)]
+        fn state(&self) -> u32 {
+            self.state
+        }
+    }
+
+    /// NetworkManager managing [`MOCK_IFACE`], and aware of the unmanaged
+    /// [`UNMANAGED_IFACE`], served in process over a peer-to-peer
+    /// connection. Returns the client end, the server end to keep alive,
+    /// and the applied connection of the managed device.
+    fn mock_network_manager(settings: Settings) -> (Connection, Connection, Arc<Mutex<Applied>>) {
+        let applied = Arc::new(Mutex::new(Applied {
+            settings,
+            version_id: 1,
+        }));
+        let (client, server) = UnixStream::pair().unwrap();
+        let server = {
+            let applied = applied.clone();
+            // Both ends block until authenticated
+            std::thread::spawn(move || {
+                zbus::blocking::connection::Builder::unix_stream(server)
+                    .server(zbus::Guid::generate())
+                    .unwrap()
+                    .p2p()
+                    .serve_at("/org/freedesktop/NetworkManager", MockNetworkManager)
+                    .unwrap()
+                    .serve_at(
+                        MOCK_DEVICE_PATH,
+                        MockDevice {
+                            applied,
+                            managed: true,
+                            state: DEVICE_STATE_ACTIVATED,
+                        },
+                    )
+                    .unwrap()
+                    .serve_at(
+                        UNMANAGED_DEVICE_PATH,
+                        MockDevice {
+                            applied: Arc::new(Mutex::new(Applied {
+                                settings: Settings::new(),
+                                version_id: 1,
+                            })),
+                            managed: false,
+                            state: DEVICE_STATE_UNMANAGED,
+                        },
+                    )
+                    .unwrap()
+                    .build()
+                    .unwrap()
+            })
+        };
+        let client = zbus::blocking::connection::Builder::unix_stream(client)
+            .p2p()
+            .build()
+            .unwrap();
+        (client, server.join().unwrap(), applied)
+    }
+
+    fn setting<T>(settings: &Settings, group: &str, key: &str) -> T
+    where
+        T: TryFrom<OwnedValue>,
+        T::Error: std::fmt::Debug,
+    {
+        T::try_from(settings[group][key].clone()).unwrap()
+    }
+
+    #[test]
+    fn apply_dns_ipv4() {
+        let mut settings = Settings::new();
+        let servers = [IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1))];
+        apply_dns(&mut settings, &servers, &["~.".to_string()], DNS_PRIORITY).unwrap();
+
+        let dns: Vec<u32> = setting(&settings, "ipv4", "dns");
+        assert_eq!(dns, [u32::from_ne_bytes([100, 64, 0, 1])]);
+        let search: Vec<String> = setting(&settings, "ipv4", "dns-search");
+        assert_eq!(search, ["~."]);
+        let priority: i32 = setting(&settings, "ipv4", "dns-priority");
+        assert_eq!(priority, DNS_PRIORITY);
+        let ignore_auto: bool = setting(&settings, "ipv4", "ignore-auto-dns");
+        assert!(ignore_auto);
+        assert!(!settings.contains_key("ipv6"));
+    }
+
+    #[test]
+    fn apply_dns_ipv6() {
+        let mut settings = Settings::new();
+        let servers = [IpAddr::V6(Ipv6Addr::LOCALHOST)];
+        apply_dns(&mut settings, &servers, &["~.".to_string()], DNS_PRIORITY).unwrap();
+
+        let dns: Vec<Vec<u8>> = setting(&settings, "ipv6", "dns");
+        assert_eq!(dns, [Ipv6Addr::LOCALHOST.octets().to_vec()]);
+        assert!(!settings.contains_key("ipv4"));
+    }
+
+    #[test]
+    fn apply_dns_keeps_other_settings() {
+        let mut settings = Settings::new();
+        settings
+            .entry("connection".to_string())
+            .or_default()
+            .insert(
+                "id".to_string(),
+                Value::from("lightway").try_into().unwrap(),
+            );
+        let servers = [IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1))];
+        apply_dns(&mut settings, &servers, &["~.".to_string()], DNS_PRIORITY).unwrap();
+
+        let id: String = setting(&settings, "connection", "id");
+        assert_eq!(id, "lightway");
+    }
+
+    #[test]
+    fn apply_dns_mixed_families() {
+        let mut settings = Settings::new();
+        let servers = [
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 53)),
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ];
+        let search = ["~corp.example".to_string(), "corp.example".to_string()];
+        apply_dns(&mut settings, &servers, &search, SPLIT_DNS_PRIORITY).unwrap();
+
+        for group in ["ipv4", "ipv6"] {
+            let dns_search: Vec<String> = setting(&settings, group, "dns-search");
+            assert_eq!(dns_search, search);
+            let priority: i32 = setting(&settings, group, "dns-priority");
+            assert_eq!(priority, SPLIT_DNS_PRIORITY);
+        }
+    }
+
+    #[test]
+    fn set_and_reset_over_dbus() {
+        let mut original = Settings::new();
+        original
+            .entry("ipv4".to_string())
+            .or_default()
+            .insert("dns-priority".to_string(), OwnedValue::from(100i32));
+        let (conn, _server, applied) = mock_network_manager(original);
+        assert!(NetworkManager::manages(&conn, MOCK_IFACE));
+        assert!(!NetworkManager::manages(&conn, UNMANAGED_IFACE));
+        assert!(!NetworkManager::manages(&conn, "eth0"));
+
+        let nm = NetworkManager::new(conn, MOCK_IFACE.to_string());
+        nm.set(IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1))).unwrap();
+        {
+            let applied = applied.lock().unwrap();
+            let dns: Vec<u32> = setting(&applied.settings, "ipv4", "dns");
+            assert_eq!(dns, [u32::from_ne_bytes([100, 64, 0, 1])]);
+            let search: Vec<String> = setting(&applied.settings, "ipv4", "dns-search");
+            assert_eq!(search, ["~."]);
+            let priority: i32 = setting(&applied.settings, "ipv4", "dns-priority");
+            assert_eq!(priority, DNS_PRIORITY);
+        }
+
+        nm.reset().unwrap();
+        let applied = applied.lock().unwrap();
+        let priority: i32 = setting(&applied.settings, "ipv4", "dns-priority");
+        assert_eq!(priority, 100);
+        assert!(!applied.settings["ipv4"].contains_key("dns"));
+        assert_eq!(applied.version_id, 3);
+    }
+}
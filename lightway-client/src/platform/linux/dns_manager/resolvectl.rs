@@ -1,6 +1,8 @@
 use std::net::IpAddr;
 use std::process::Command;
 
+use lightway_app_utils::split_dns::SplitDnsConfig;
+
 use crate::dns_manager::DnsManagerError;
 
 use super::DnsBackend;
@@ -23,6 +25,27 @@ impl DnsBackend for Resolvectl {
         )
     }
 
+    fn set_split(&self, config: &SplitDnsConfig) -> Result<(), DnsManagerError> {
+        let servers: Vec<String> = config.servers().iter().map(ToString::to_string).collect();
+        run(
+            Command::new("resolvectl")
+                .args(["dns", &self.iface_name])
+                .args(&servers),
+            "resolvectl dns",
+        )?;
+        run(
+            Command::new("resolvectl")
+                .args(["domain", &self.iface_name])
+                .args(split_domains(config)),
+            "resolvectl domain",
+        )?;
+        // Queries outside the routing domains keep using other links
+        run(
+            Command::new("resolvectl").args(["default-route", &self.iface_name, "false"]),
+            "resolvectl default-route",
+        )
+    }
+
     fn reset(&self) -> Result<(), DnsManagerError> {
         let status = Command::new("resolvectl")
             .args(["revert", &self.iface_name])
@@ -37,6 +60,16 @@ impl DnsBackend for Resolvectl {
     }
 }
 
+/// `~`-prefixed routing domains followed by plain search domains, as
+/// understood by `resolvectl domain`.
+fn split_domains(config: &SplitDnsConfig) -> Vec<String> {
+    config
+        .routing_domains()
+        .map(|domain| format!("~{domain}"))
+        .chain(config.search_domain_names().map(str::to_string))
+        .collect()
+}
+
 fn run(cmd: &mut Command, label: &str) -> Result<(), DnsManagerError> {
     let status = cmd
         .status()
@@ -48,3 +81,21 @@ fn run(cmd: &mut Command, label: &str) -> Result<(), DnsManagerError> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lightway_app_utils::split_dns::DomainResolver;
+
+    #[test]
+    fn split_domains_routing_then_search() {
+        let config = SplitDnsConfig {
+            resolvers: vec![DomainResolver {
+                domain: "corp.example.".to_string(),
+                servers: vec!["10.0.0.53".parse().unwrap()],
+            }],
+            search_domains: vec!["corp.example".to_string()],
+        };
+        assert_eq!(split_domains(&config), ["~corp.example", "corp.example"]);
+    }
+}
@@ -0,0 +1,261 @@
+use std::net::IpAddr;
+
+use lightway_app_utils::split_dns::SplitDnsConfig;
+use zbus::blocking::Connection;
+
+use crate::dns_manager::DnsManagerError;
+
+use super::DnsBackend;
+
+pub(super) const RESOLVED_BUS_NAME: &str = "org.freedesktop.resolve1";
+
+#[zbus::proxy(
+    interface = "org.freedesktop.resolve1.Manager",
+    default_service = "org.freedesktop.resolve1",
+    default_path = "/org/freedesktop/resolve1"
+)]
+trait Manager {
+    #[zbus(name = "SetLinkDNS")]
+    fn set_link_dns(&self, ifindex: i32, addresses: &[(i32, Vec<u8>)]) -> zbus::Result<()>;
+
+    fn set_link_domains(&self, ifindex: i32, domains: &[(&str, bool)]) -> zbus::Result<()>;
+
+    fn set_link_default_route(&self, ifindex: i32, enable: bool) -> zbus::Result<()>;
+
+    fn revert_link(&self, ifindex: i32) -> zbus::Result<()>;
+}
+
+/// systemd-resolved backend, configured over D-Bus (`org.freedesktop.resolve1`).
+///
+/// Equivalent to the `resolvectl` backend without spawning a process per
+/// call: per-link DNS plus a `~.` routing domain on the TUN device.
+pub(super) struct ResolvedDbus {
+    conn: Connection,
+    ifindex: i32,
+}
+
+impl ResolvedDbus {
+    pub(super) fn new(conn: Connection, ifindex: u32) -> Self {
+        Self {
+            conn,
+            ifindex: ifindex as i32,
+        }
+    }
+
+    fn proxy(&self) -> zbus::Result<ManagerProxyBlocking<'_>> {
+        ManagerProxyBlocking::new(&self.conn)
+    }
+}
+
+impl DnsBackend for ResolvedDbus {
+    fn set(&self, dns_server: IpAddr) -> Result<(), DnsManagerError> {
+        let set_err = |e: zbus::Error| DnsManagerError::FailedToSetDnsConfig(e.to_string());
+        let proxy = self.proxy().map_err(set_err)?;
+
+        proxy
+            .set_link_dns(self.ifindex, &[link_address(dns_server)])
+            .map_err(set_err)?;
+        // ~. routes all DNS queries through this interface
+        proxy
+            .set_link_domains(self.ifindex, &[(".", true)])
+            .map_err(set_err)?;
+        proxy
+            .set_link_default_route(self.ifindex, true)
+            .map_err(set_err)
+    }
+
+    fn set_split(&self, config: &SplitDnsConfig) -> Result<(), DnsManagerError> {
+        let set_err = |e: zbus::Error| DnsManagerError::FailedToSetDnsConfig(e.to_string());
+        let proxy = self.proxy().map_err(set_err)?;
+
+        let addresses: Vec<_> = config.servers().into_iter().map(link_address).collect();
+        proxy
+            .set_link_dns(self.ifindex, &addresses)
+            .map_err(set_err)?;
+        let domains: Vec<(&str, bool)> = config
+            .routing_domains()
+            .map(|domain| (domain, true))
+            .chain(config.search_domain_names().map(|domain| (domain, false)))
+            .collect();
+        proxy
+            .set_link_domains(self.ifindex, &domains)
+            .map_err(set_err)?;
+        // Queries outside the routing domains keep using other links
+        proxy
+            .set_link_default_route(self.ifindex, false)
+            .map_err(set_err)
+    }
+
+    fn reset(&self) -> Result<(), DnsManagerError> {
+        self.proxy()
+            .and_then(|proxy| proxy.revert_link(self.ifindex))
+            .map_err(|e| DnsManagerError::FailedToRestoreDnsConfig(e.to_string()))
+    }
+}
+
+/// Address family and raw bytes, as expected by `SetLinkDNS`.
+fn link_address(ip: IpAddr) -> (i32, Vec<u8>) {
+    match ip {
+        IpAddr::V4(ip) => (libc::AF_INET, ip.octets().to_vec()),
+        IpAddr::V6(ip) => (libc::AF_INET6, ip.octets().to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use std::sync::{Arc, Mutex};
+
+    /// Private session bus, killed on drop.
+    struct TestBus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl TestBus {
+        fn spawn() -> Self {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .expect("dbus-daemon must be installed");
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            Self {
+                daemon,
+                address: address.trim().to_string(),
+            }
+        }
+
+        fn connect(&self) -> Connection {
+            zbus::blocking::connection::Builder::address(self.address.as_str())
+                .unwrap()
+                .build()
+                .unwrap()
+        }
+    }
+
+    impl Drop for TestBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    #[derive(Default, Clone)]
+    struct MockResolved {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[zbus::interface(name = "org.freedesktop.resolve1.Manager")]
+    impl MockResolved {
+        #[zbus(name = "SetLinkDNS")]
+        fn set_link_dns(&self, ifindex: i32, addresses: Vec<(i32, Vec<u8>)>) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("dns {ifindex} {addresses:?}"));
+        }
+
+        fn set_link_domains(&self, ifindex: i32, domains: Vec<(String, bool)>) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("domains {ifindex} {domains:?}"));
+        }
+
+        fn set_link_default_route(&self, ifindex: i32, enable: bool) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("default-route {ifindex} {enable}"));
+        }
+
+        fn revert_link(&self, ifindex: i32) {
+            self.calls.lock().unwrap().push(format!("revert {ifindex}"));
+        }
+    }
+
+    #[test]
+    #[ignore = "Requires dbus-daemon"]
+    fn set_and_reset_via_mock_resolved() {
+        let bus = TestBus::spawn();
+        let mock = MockResolved::default();
+        let _service = zbus::blocking::connection::Builder::address(bus.address.as_str())
+            .unwrap()
+            .name(RESOLVED_BUS_NAME)
+            .unwrap()
+            .serve_at("/org/freedesktop/resolve1", mock.clone())
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let backend = ResolvedDbus::new(bus.connect(), 7);
+        backend
+            .set(IpAddr::V4(std::net::Ipv4Addr::new(100, 64, 0, 1)))
+            .unwrap();
+        backend.reset().unwrap();
+
+        assert_eq!(
+            *mock.calls.lock().unwrap(),
+            [
+                format!("dns 7 [({}, [100, 64, 0, 1])]", libc::AF_INET),
+                "domains 7 [(\".\", true)]".to_string(),
+                "default-route 7 true".to_string(),
+                "revert 7".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    #[ignore = "Requires dbus-daemon"]
+    fn set_split_via_mock_resolved() {
+        use lightway_app_utils::split_dns::DomainResolver;
+
+        let bus = TestBus::spawn();
+        let mock = MockResolved::default();
+        let _service = zbus::blocking::connection::Builder::address(bus.address.as_str())
+            .unwrap()
+            .name(RESOLVED_BUS_NAME)
+            .unwrap()
+            .serve_at("/org/freedesktop/resolve1", mock.clone())
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let backend = ResolvedDbus::new(bus.connect(), 7);
+        backend
+            .set_split(&SplitDnsConfig {
+                resolvers: vec![DomainResolver {
+                    domain: "corp.example".to_string(),
+                    servers: vec![IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 53))],
+                }],
+                search_domains: vec!["corp.example".to_string()],
+            })
+            .unwrap();
+
+        assert_eq!(
+            *mock.calls.lock().unwrap(),
+            [
+                format!("dns 7 [({}, [10, 0, 0, 53])]", libc::AF_INET),
+                "domains 7 [(\"corp.example\", true), (\"corp.example\", false)]".to_string(),
+                "default-route 7 false".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    #[ignore = "Requires dbus-daemon"]
+    fn set_fails_without_service() {
+        let bus = TestBus::spawn();
+        let backend = ResolvedDbus::new(bus.connect(), 7);
+        assert!(matches!(
+            backend.set(IpAddr::V4(std::net::Ipv4Addr::new(100, 64, 0, 1))),
+            Err(DnsManagerError::FailedToSetDnsConfig(_))
+        ));
+    }
+}
@@ -0,0 +1,314 @@
+//! nftables based kill switch.
+//!
+//! While enabled, every outbound packet is dropped unless it leaves via
+//! the loopback or TUN device, or is addressed to one of the allowed
+//! Lightway servers. DNS is only permitted towards `tun_dns_ip` and the
+//! split DNS resolvers pushed by the server, so the OS cannot fall back to
+//! the physical network's resolver.
+//!
+//! The rules live in their own `inet` table and are deliberately not
+//! removed on drop: they must survive reconnects, network changes and
+//! even the client exiting on error. Only [`KillSwitch::remove`] takes
+//! them down, which the client calls on a deliberate user disconnect.
+
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::net::{IpAddr, SocketAddr};
+use std::process::{Command, Stdio};
+
+use lightway_core::ConnectionType;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Name of the nftables table owned by the kill switch.
+const TABLE_NAME: &str = "lightway_killswitch";
+
+// LAN networks reachable in KillSwitchMode::Lan
+const LAN_NETWORKS_V4: [&str; 5] = [
+    "10.0.0.0/8",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "169.254.0.0/16",
+    "224.0.0.0/24",
+];
+const LAN_NETWORKS_V6: [&str; 3] = ["fe80::/10", "fc00::/7", "ff02::/16"];
+
+#[derive(
+    Debug, PartialEq, Eq, Copy, Clone, clap::ValueEnum, JsonSchema, Serialize, Deserialize, Default,
+)]
+#[serde(rename_all = "lowercase")]
+#[value(rename_all = "lowercase")]
+pub enum KillSwitchMode {
+    #[default]
+    Disabled,
+    Enabled,
+    Lan,
+}
+
+#[derive(Error, Debug)]
+pub enum KillSwitchError {
+    #[error("Failed to run nft: {0}")]
+    NftExec(std::io::Error),
+    #[error("nft exited with {0}")]
+    NftFailed(std::process::ExitStatus),
+}
+
+/// A Lightway server the kill switch lets traffic through to.
+#[derive(Debug, Clone, Copy)]
+pub struct AllowedServer {
+    pub addr: SocketAddr,
+    pub connection_type: ConnectionType,
+}
+
+pub struct KillSwitch {
+    mode: KillSwitchMode,
+    tun_name: String,
+    tun_dns_ip: IpAddr,
+}
+
+impl KillSwitch {
+    pub fn new(mode: KillSwitchMode, tun_name: String, tun_dns_ip: IpAddr) -> Self {
+        Self {
+            mode,
+            tun_name,
+            tun_dns_ip,
+        }
+    }
+
+    /// Atomically install (or replace) the ruleset so that only `servers`
+    /// are reachable outside the tunnel, along with DNS to `dns_servers`,
+    /// the split DNS resolvers in use.
+    pub fn allow_servers(
+        &self,
+        servers: &[AllowedServer],
+        dns_servers: &[IpAddr],
+    ) -> Result<(), KillSwitchError> {
+        if self.mode == KillSwitchMode::Disabled {
+            return Ok(());
+        }
+        nft(&self.ruleset(servers, dns_servers))?;
+        tracing::info!(mode = ?self.mode, ?servers, ?dns_servers, "Kill switch rules installed");
+        Ok(())
+    }
+
+    /// Remove the kill switch table, restoring unrestricted access.
+    pub fn remove(&self) -> Result<(), KillSwitchError> {
+        if self.mode == KillSwitchMode::Disabled {
+            return Ok(());
+        }
+        nft(&flush_table())?;
+        tracing::info!("Kill switch rules removed");
+        Ok(())
+    }
+
+    fn ruleset(&self, servers: &[AllowedServer], dns_servers: &[IpAddr]) -> String {
+        // Declaring then deleting the table makes the batch work whether or
+        // not a previous run left it behind, and nft applies it atomically.
+        let mut rules = flush_table();
+        let _ = writeln!(rules, "table inet {TABLE_NAME} {{");
+        let _ = writeln!(rules, "  chain output {{");
+        let _ = writeln!(
+            rules,
+            "    type filter hook output priority 0; policy drop;"
+        );
+        let _ = writeln!(rules, "    oifname \"lo\" accept");
+        for server in servers {
+            let family = match server.addr.ip() {
+                IpAddr::V4(_) => "ip",
+                IpAddr::V6(_) => "ip6",
+            };
+            let proto = if server.connection_type.is_datagram() {
+                "udp"
+            } else {
+                "tcp"
+            };
+            let _ = writeln!(
+                rules,
+                "    {family} daddr {} {proto} dport {} accept",
+                server.addr.ip(),
+                server.addr.port()
+            );
+        }
+        // Split DNS resolvers may be reached outside the tunnel, e.g. on
+        // the LAN, so they are accepted ahead of the DNS drop rules
+        for dns_server in dns_servers {
+            let family = match dns_server {
+                IpAddr::V4(_) => "ip",
+                IpAddr::V6(_) => "ip6",
+            };
+            for proto in ["udp", "tcp"] {
+                let _ = writeln!(
+                    rules,
+                    "    {family} daddr {dns_server} {proto} dport 53 accept"
+                );
+            }
+        }
+        let (dns_family, dns_nfproto, other_nfproto) = match self.tun_dns_ip {
+            IpAddr::V4(_) => ("ip", "ipv4", "ipv6"),
+            IpAddr::V6(_) => ("ip6", "ipv6", "ipv4"),
+        };
+        // Resolvers reached through the tunnel (e.g. split DNS) don't leak
+        let tun = &self.tun_name;
+        for proto in ["udp", "tcp"] {
+            let _ = writeln!(
+                rules,
+                "    oifname != \"{tun}\" meta nfproto {dns_nfproto} {proto} dport 53 {dns_family} daddr != {} drop",
+                self.tun_dns_ip
+            );
+            let _ = writeln!(
+                rules,
+                "    oifname != \"{tun}\" meta nfproto {other_nfproto} {proto} dport 53 drop"
+            );
+        }
+        let _ = writeln!(rules, "    oifname \"{}\" accept", self.tun_name);
+        if self.mode == KillSwitchMode::Lan {
+            // DHCP and neighbour discovery keep the physical link usable
+            let _ = writeln!(rules, "    udp sport 68 udp dport 67 accept");
+            let _ = writeln!(
+                rules,
+                "    icmpv6 type {{ nd-router-solicit, nd-neighbor-solicit, nd-neighbor-advert }} accept"
+            );
+            let _ = writeln!(
+                rules,
+                "    ip daddr {{ {} }} accept",
+                LAN_NETWORKS_V4.join(", ")
+            );
+            let _ = writeln!(
+                rules,
+                "    ip6 daddr {{ {} }} accept",
+                LAN_NETWORKS_V6.join(", ")
+            );
+        }
+        let _ = writeln!(rules, "  }}");
+        let _ = writeln!(rules, "}}");
+        rules
+    }
+}
+
+/// Ruleset fragment which removes the kill switch table, if present.
+fn flush_table() -> String {
+    format!("table inet {TABLE_NAME}\ndelete table inet {TABLE_NAME}\n")
+}
+
+fn nft(ruleset: &str) -> Result<(), KillSwitchError> {
+    let mut child = Command::new("nft")
+        .args(["-f", "-"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(KillSwitchError::NftExec)?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(ruleset.as_bytes())
+            .map_err(KillSwitchError::NftExec)?;
+    }
+
+    let status = child.wait().map_err(KillSwitchError::NftExec)?;
+    if !status.success() {
+        return Err(KillSwitchError::NftFailed(status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use test_case::test_case;
+
+    const TUN_DNS_IP: IpAddr = IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1));
+
+    fn server(addr: &str, connection_type: ConnectionType) -> AllowedServer {
+        AllowedServer {
+            addr: addr.parse().unwrap(),
+            connection_type,
+        }
+    }
+
+    #[test]
+    fn ruleset_allows_tun_loopback_and_servers() {
+        let kill_switch =
+            KillSwitch::new(KillSwitchMode::Enabled, "lightway".to_string(), TUN_DNS_IP);
+        let rules = kill_switch.ruleset(
+            &[
+                server("1.2.3.4:27690", ConnectionType::Datagram),
+                server("[2001:db8::1]:443", ConnectionType::Stream),
+            ],
+            &[],
+        );
+
+        assert!(rules.contains("policy drop;"));
+        assert!(rules.contains("oifname \"lo\" accept"));
+        assert!(rules.contains("oifname \"lightway\" accept"));
+        assert!(rules.contains("ip daddr 1.2.3.4 udp dport 27690 accept"));
+        assert!(rules.contains("ip6 daddr 2001:db8::1 tcp dport 443 accept"));
+        assert!(rules.contains("udp dport 53 ip daddr != 100.64.0.1 drop"));
+        assert!(rules.contains("tcp dport 53 ip daddr != 100.64.0.1 drop"));
+        assert!(rules.contains("oifname != \"lightway\" meta nfproto ipv6 udp dport 53 drop"));
+        assert!(!rules.contains("10.0.0.0/8"));
+    }
+
+    #[test]
+    fn ruleset_blocks_dns_before_tun_accept() {
+        let kill_switch =
+            KillSwitch::new(KillSwitchMode::Enabled, "lightway".to_string(), TUN_DNS_IP);
+        let rules = kill_switch.ruleset(&[], &[]);
+
+        let dns_drop = rules.find("udp dport 53").unwrap();
+        let tun_accept = rules.find("oifname \"lightway\"").unwrap();
+        assert!(dns_drop < tun_accept);
+    }
+
+    #[test]
+    fn ruleset_allows_split_dns_servers() {
+        let kill_switch =
+            KillSwitch::new(KillSwitchMode::Enabled, "lightway".to_string(), TUN_DNS_IP);
+        let rules = kill_switch.ruleset(
+            &[],
+            &[
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 53)),
+                IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 0x53)),
+            ],
+        );
+
+        let split_dns_accept = rules
+            .find("ip daddr 10.0.0.53 udp dport 53 accept")
+            .unwrap();
+        assert!(rules.contains("ip daddr 10.0.0.53 tcp dport 53 accept"));
+        assert!(rules.contains("ip6 daddr fd00::53 udp dport 53 accept"));
+        assert!(rules.contains("ip6 daddr fd00::53 tcp dport 53 accept"));
+        let dns_drop = rules.find("dport 53 ip daddr != 100.64.0.1 drop").unwrap();
+        assert!(split_dns_accept < dns_drop);
+    }
+
+    #[test_case(KillSwitchMode::Enabled => false)]
+    #[test_case(KillSwitchMode::Lan => true)]
+    fn ruleset_lan_exceptions(mode: KillSwitchMode) -> bool {
+        let kill_switch = KillSwitch::new(mode, "lightway".to_string(), TUN_DNS_IP);
+        let rules = kill_switch.ruleset(&[], &[]);
+        LAN_NETWORKS_V4
+            .iter()
+            .chain(LAN_NETWORKS_V6.iter())
+            .all(|net| rules.contains(net))
+    }
+
+    #[test]
+    fn ruleset_replaces_existing_table() {
+        let kill_switch =
+            KillSwitch::new(KillSwitchMode::Enabled, "lightway".to_string(), TUN_DNS_IP);
+        let rules = kill_switch.ruleset(&[], &[]);
+        assert!(rules.starts_with(&flush_table()));
+    }
+
+    #[test]
+    fn disabled_mode_is_noop() {
+        let kill_switch = KillSwitch::new(
+            KillSwitchMode::Disabled,
+            "lightway".to_string(),
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+        );
+        assert!(kill_switch.allow_servers(&[], &[]).is_ok());
+        assert!(kill_switch.remove().is_ok());
+    }
+}
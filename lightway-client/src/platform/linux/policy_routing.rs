@@ -0,0 +1,233 @@
+//! WireGuard style policy based routing, configured via the `ip` CLI.
+//!
+//! Instead of overriding the default route, a dedicated routing table
+//! holds a default route via the TUN device and a `not fwmark` rule sends
+//! every unmarked packet to it. The outside socket carries the fwmark, so
+//! the tunnel's own packets keep using the main table and the server route
+//! never has to follow default gateway changes.
+//!
+//! The tunnel is IPv4 only: unmarked IPv6 packets go to the same table,
+//! whose IPv6 default route is a blackhole, so that they do not leak
+//! outside the tunnel.
+
+use std::process::Command;
+use std::sync::Mutex;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum PolicyRoutingError {
+    #[error("Policy routing requires a non-zero fwmark")]
+    MissingFwmark,
+    #[error("TUN interface name not found for index {0}")]
+    InterfaceNotFound(u32),
+    #[error("Failed to run ip: {0}")]
+    IpExec(std::io::Error),
+    #[error("`ip {0}` exited with {1}")]
+    IpFailed(String, std::process::ExitStatus),
+}
+
+/// Tables installed by this process and how many [`PolicyRouting`]s hold
+/// each. Reconnecting installs the new connection's before releasing the
+/// previous one's, which are the same rules.
+static INSTALLED_TABLES: Mutex<Vec<(u32, usize)>> = Mutex::new(Vec::new());
+
+pub struct PolicyRouting {
+    /// Routing table id, which is also the fwmark of the outside socket.
+    table: u32,
+    tun_name: String,
+    /// Whether the host has IPv6, which then has to be blackholed
+    ipv6: bool,
+    installed: bool,
+}
+
+impl PolicyRouting {
+    pub fn new(fwmark: u32, tun_index: u32) -> Result<Self, PolicyRoutingError> {
+        if fwmark == 0 {
+            return Err(PolicyRoutingError::MissingFwmark);
+        }
+        let tun_name = super::ifname_from_index(tun_index)
+            .ok_or(PolicyRoutingError::InterfaceNotFound(tun_index))?;
+        Ok(Self {
+            table: fwmark,
+            tun_name,
+            ipv6: ipv6_enabled(),
+            installed: false,
+        })
+    }
+
+    pub fn install(&mut self) -> Result<(), PolicyRoutingError> {
+        let mut tables = INSTALLED_TABLES.lock().unwrap();
+        if let Some((_, owners)) = tables.iter_mut().find(|(table, _)| *table == self.table) {
+            *owners += 1;
+            self.installed = true;
+            return Ok(());
+        }
+        for args in self.install_commands() {
+            if let Err(e) = ip(&args) {
+                // Not registered yet, removed right away
+                drop(tables);
+                self.cleanup();
+                return Err(e);
+            }
+            // Anything added so far has to be cleaned up from here on
+            self.installed = true;
+        }
+        tables.push((self.table, 1));
+        tracing::info!(table = self.table, tun = %self.tun_name, "Policy routing installed");
+        Ok(())
+    }
+
+    /// Remove the rules and flush the table, best effort. Left in place
+    /// while another [`PolicyRouting`] still holds them.
+    pub fn cleanup(&mut self) {
+        if !self.installed {
+            return;
+        }
+        let mut tables = INSTALLED_TABLES.lock().unwrap();
+        if let Some(pos) = tables.iter().position(|(table, _)| *table == self.table) {
+            tables[pos].1 -= 1;
+            if tables[pos].1 > 0 {
+                self.installed = false;
+                return;
+            }
+            tables.swap_remove(pos);
+        }
+        for args in self.cleanup_commands() {
+            if let Err(e) = ip(&args) {
+                tracing::warn!("Failed to clean up policy routing: {e}");
+            }
+        }
+        self.installed = false;
+    }
+
+    fn install_commands(&self) -> Vec<String> {
+        let (table, tun) = (self.table, &self.tun_name);
+        let mut commands = vec![
+            format!("-4 route replace default dev {tun} table {table}"),
+            // More specific routes in the main table (LAN, link local) still
+            // win, mirroring the 0.0.0.0/1 + 128.0.0.0/1 split routes.
+            "-4 rule add table main suppress_prefixlength 0".to_string(),
+            format!("-4 rule add not fwmark {table} table {table}"),
+        ];
+        if self.ipv6 {
+            commands.extend([
+                format!("-6 route replace blackhole default table {table}"),
+                "-6 rule add table main suppress_prefixlength 0".to_string(),
+                format!("-6 rule add not fwmark {table} table {table}"),
+            ]);
+        }
+        commands
+    }
+
+    fn cleanup_commands(&self) -> Vec<String> {
+        let table = self.table;
+        let mut commands = Vec::new();
+        if self.ipv6 {
+            commands.extend([
+                format!("-6 rule del not fwmark {table} table {table}"),
+                "-6 rule del table main suppress_prefixlength 0".to_string(),
+                format!("-6 route flush table {table}"),
+            ]);
+        }
+        commands.extend([
+            format!("-4 rule del not fwmark {table} table {table}"),
+            "-4 rule del table main suppress_prefixlength 0".to_string(),
+            format!("-4 route flush table {table}"),
+        ]);
+        commands
+    }
+}
+
+impl Drop for PolicyRouting {
+    fn drop(&mut self) {
+        self.cleanup();
+    }
+}
+
+/// Whether the kernel has IPv6 enabled, `if_inet6` is missing otherwise
+fn ipv6_enabled() -> bool {
+    std::path::Path::new("/proc/net/if_inet6").exists()
+}
+
+fn ip(args: &str) -> Result<(), PolicyRoutingError> {
+    let status = Command::new("ip")
+        .args(args.split_whitespace())
+        .status()
+        .map_err(PolicyRoutingError::IpExec)?;
+    if !status.success() {
+        return Err(PolicyRoutingError::IpFailed(args.to_string(), status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy_routing(ipv6: bool) -> PolicyRouting {
+        PolicyRouting {
+            table: 51820,
+            tun_name: "lightway".to_string(),
+            ipv6,
+            installed: false,
+        }
+    }
+
+    #[test]
+    fn new_requires_fwmark() {
+        assert!(matches!(
+            PolicyRouting::new(0, 1),
+            Err(PolicyRoutingError::MissingFwmark)
+        ));
+    }
+
+    #[test]
+    fn install_commands() {
+        assert_eq!(
+            policy_routing(true).install_commands(),
+            [
+                "-4 route replace default dev lightway table 51820",
+                "-4 rule add table main suppress_prefixlength 0",
+                "-4 rule add not fwmark 51820 table 51820",
+                "-6 route replace blackhole default table 51820",
+                "-6 rule add table main suppress_prefixlength 0",
+                "-6 rule add not fwmark 51820 table 51820",
+            ]
+        );
+    }
+
+    #[test]
+    fn install_commands_without_ipv6() {
+        assert_eq!(
+            policy_routing(false).install_commands(),
+            [
+                "-4 route replace default dev lightway table 51820",
+                "-4 rule add table main suppress_prefixlength 0",
+                "-4 rule add not fwmark 51820 table 51820",
+            ]
+        );
+    }
+
+    #[test]
+    fn cleanup_commands_reverse_install() {
+        assert_eq!(
+            policy_routing(true).cleanup_commands(),
+            [
+                "-6 rule del not fwmark 51820 table 51820",
+                "-6 rule del table main suppress_prefixlength 0",
+                "-6 route flush table 51820",
+                "-4 rule del not fwmark 51820 table 51820",
+                "-4 rule del table main suppress_prefixlength 0",
+                "-4 route flush table 51820",
+            ]
+        );
+    }
+
+    #[test]
+    fn cleanup_without_install_is_noop() {
+        let mut policy_routing = policy_routing(true);
+        policy_routing.cleanup();
+        assert!(!policy_routing.installed);
+    }
+}
@@ -192,6 +192,10 @@ impl DnsSetup for DnsManager {
         }
         Ok(())
     }
+
+    fn hand_over(&mut self) {
+        self.service_id = None;
+    }
 }
 
 #[cfg(test)]
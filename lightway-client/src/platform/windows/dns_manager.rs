@@ -67,11 +67,18 @@ impl DnsSetup for DnsManager {
         Ok(())
     }
     fn reset_dns(&mut self) -> Result<(), DnsManagerError> {
+        if !self.setup {
+            return Ok(());
+        }
         Self::remove_nrpt_rule()?;
         Self::flush_dns_cache();
         self.setup = false;
         Ok(())
     }
+
+    fn hand_over(&mut self) {
+        self.setup = false;
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,143 @@
+//! Reconnect supervisor support: backoff between attempts and the events
+//! reporting its progress, see [`crate::client_with_reconnect`].
+
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+use rand::RngExt;
+
+/// How to retry once an established connection ends.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReconnectConfig {
+    /// Delay before the first attempt, doubled after every failed one
+    pub initial_delay: Duration,
+
+    /// Upper bound for the delay between attempts
+    pub max_delay: Duration,
+
+    /// Give up after this many consecutive failed attempts, retry
+    /// forever when `None`
+    pub max_attempts: Option<NonZeroU32>,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_attempts: None,
+        }
+    }
+}
+
+/// Progress of the reconnect supervisor, sent via `reconnect_signal`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReconnectEvent {
+    /// The connection, or the previous attempt, ended with `reason`.
+    /// Attempt number `attempt` (starting at 1) starts after `delay`.
+    Reconnecting {
+        attempt: u32,
+        delay: Duration,
+        reason: String,
+    },
+    /// Attempt number `attempt` established a new connection
+    Reconnected { attempt: u32 },
+    /// `attempts` consecutive attempts failed, the client stops
+    GaveUp { attempts: u32 },
+}
+
+/// Jittered exponential backoff.
+#[derive(Debug)]
+pub(crate) struct Backoff {
+    config: ReconnectConfig,
+    attempts: u32,
+}
+
+impl Backoff {
+    pub fn new(config: ReconnectConfig) -> Self {
+        Self {
+            config,
+            attempts: 0,
+        }
+    }
+
+    /// Attempts made since the last [`Self::reset`].
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Delay before the next attempt, `None` once `max_attempts` have
+    /// been made.
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if let Some(max_attempts) = self.config.max_attempts
+            && self.attempts >= max_attempts.get()
+        {
+            return None;
+        }
+        let delay = self
+            .config
+            .initial_delay
+            .saturating_mul(2u32.saturating_pow(self.attempts))
+            .min(self.config.max_delay);
+        self.attempts += 1;
+        // Somewhere in the upper half, so that clients dropped by the
+        // same server do not all come back at once
+        Some(delay.mul_f64(rand::rng().random_range(0.5..=1.0)))
+    }
+
+    /// Start over from `initial_delay`, after a successful attempt.
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn backoff(max_attempts: Option<u32>) -> Backoff {
+        Backoff::new(ReconnectConfig {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(10),
+            max_attempts: max_attempts.and_then(NonZeroU32::new),
+        })
+    }
+
+    #[test_case(0 => Duration::from_secs(1); "first")]
+    #[test_case(1 => Duration::from_secs(2); "second")]
+    #[test_case(3 => Duration::from_secs(8); "fourth")]
+    #[test_case(4 => Duration::from_secs(10); "capped")]
+    #[test_case(40 => Duration::from_secs(10); "no overflow")]
+    fn delay_is_jittered_below_exponential(previous_attempts: u32) -> Duration {
+        let mut backoff = backoff(None);
+        for _ in 0..previous_attempts {
+            backoff.next_delay();
+        }
+        let delay = backoff.next_delay().unwrap();
+        let upper = std::cmp::min(
+            Duration::from_secs(1).saturating_mul(2u32.saturating_pow(previous_attempts)),
+            Duration::from_secs(10),
+        );
+        assert!(delay >= upper / 2 && delay <= upper, "{delay:?}");
+        upper
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let mut backoff = backoff(Some(2));
+        assert!(backoff.next_delay().is_some());
+        assert!(backoff.next_delay().is_some());
+        assert_eq!(backoff.next_delay(), None);
+        assert_eq!(backoff.attempts(), 2);
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut backoff = backoff(Some(1));
+        backoff.next_delay();
+        backoff.reset();
+        assert_eq!(backoff.attempts(), 0);
+        assert!(backoff.next_delay().unwrap() <= Duration::from_secs(1));
+    }
+}
@@ -3,6 +3,7 @@ use route_manager::{AsyncRouteManager, Route, RouteManager as SyncRouteManager};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Mutex;
 use thiserror::Error;
 use tokio::task::JoinHandle;
 use tracing::{trace, warn};
@@ -10,6 +11,8 @@ use tracing::{trace, warn};
 #[cfg(windows)]
 use windows_sys::Win32::Foundation::ERROR_OBJECT_ALREADY_EXISTS;
 
+#[cfg(linux)]
+use crate::platform::linux::policy_routing::{PolicyRouting, PolicyRoutingError};
 #[cfg(windows)]
 use crate::platform::windows::utils;
 
@@ -66,6 +69,9 @@ pub enum RouteMode {
     Default,
     Lan,
     NoExec,
+    /// Dedicated routing table plus a `not fwmark` rule (Linux only)
+    #[cfg(linux)]
+    Policy,
 }
 
 #[derive(Error, Debug)]
@@ -90,6 +96,9 @@ pub enum RoutingTableError {
     RoutingManagerError(std::io::Error),
     #[error("Server route already exists, try modifying it instead")]
     ServerRouteAlreadyExists,
+    #[cfg(linux)]
+    #[error("Policy routing error {0}")]
+    PolicyRoutingError(#[from] PolicyRoutingError),
 }
 
 /// Returns the host prefix length for an IP address
@@ -110,6 +119,53 @@ fn same_ip_family(ip1: &IpAddr, ip2: &IpAddr) -> bool {
     )
 }
 
+fn is_route_exists_error(error: &std::io::Error) -> bool {
+    match error.raw_os_error() {
+        #[cfg(any(target_os = "linux", target_os = "macos",))]
+        Some(libc::EEXIST) => true,
+        #[cfg(windows)]
+        Some(code) => code == ERROR_OBJECT_ALREADY_EXISTS as i32,
+        _ => false,
+    }
+}
+
+/// Routes installed by this process and how many owners hold each.
+/// Reconnecting installs the routes of the new connection before the
+/// previous connection's are released, and both share some (e.g. the
+/// tunnel routes), so a route is only deleted by its last owner.
+static ROUTE_OWNERS: Mutex<Vec<(Route, usize)>> = Mutex::new(Vec::new());
+
+/// Register an owner of `route`. Returns whether it is the first one,
+/// which is to install the route.
+fn acquire_route(route: &Route) -> bool {
+    let mut owners = ROUTE_OWNERS.lock().unwrap();
+    match owners.iter_mut().find(|(owned, _)| owned == route) {
+        Some((_, count)) => {
+            *count += 1;
+            false
+        }
+        None => {
+            owners.push((route.clone(), 1));
+            true
+        }
+    }
+}
+
+/// Unregister an owner of `route`. Returns whether it was the last one,
+/// which is to delete the route.
+fn release_route(route: &Route) -> bool {
+    let mut owners = ROUTE_OWNERS.lock().unwrap();
+    let Some(pos) = owners.iter().position(|(owned, _)| owned == route) else {
+        return true;
+    };
+    owners[pos].1 -= 1;
+    if owners[pos].1 > 0 {
+        return false;
+    }
+    owners.swap_remove(pos);
+    true
+}
+
 pub struct RouteManager {
     inner: Option<RouteManagerInner>,
     task: Option<JoinHandle<()>>,
@@ -126,6 +182,10 @@ struct RouteManagerInner {
     vpn_routes: Vec<Route>,
     lan_routes: Vec<Route>,
     server_route: Option<Route>,
+    #[cfg(linux)]
+    fwmark: u32,
+    #[cfg(linux)]
+    policy_routing: Option<PolicyRouting>,
 }
 
 impl RouteManager {
@@ -146,6 +206,16 @@ impl RouteManager {
         Ok(Self { inner, task: None })
     }
 
+    /// Firewall mark carried by the outside socket. Required by
+    /// [`RouteMode::Policy`], where it doubles as the routing table id.
+    #[cfg(linux)]
+    pub fn with_fwmark(mut self, fwmark: u32) -> Self {
+        if let Some(inner) = self.inner.as_mut() {
+            inner.fwmark = fwmark;
+        }
+        self
+    }
+
     /// Install the routes required to use the tunnel (NoExec installs
     /// nothing) and hand back the per-event updater. The task that takes
     /// ownership of the updater should be registered with [`Self::set_task`]
@@ -185,15 +255,122 @@ pub struct RouteUpdater {
 
 impl RouteUpdater {
     /// Refresh the server route if the default route changed (a no-op in
-    /// NoExec mode). Returns whether the server route was actually replaced.
+    /// NoExec and Policy modes). Returns whether the server route was
+    /// actually replaced.
     pub async fn check_and_update_server_route(&mut self) -> Result<bool, RoutingTableError> {
         if self.inner.routing_mode == RouteMode::NoExec {
             return Ok(false);
         }
+        // The fwmark'ed outside socket always follows the main table
+        #[cfg(linux)]
+        if self.inner.routing_mode == RouteMode::Policy {
+            return Ok(false);
+        }
         self.inner.check_and_update_server_route().await
     }
 }
 
+// Without a `stop()`, e.g. on an early error return, still let the task
+// go so that its updater removes the routes.
+impl Drop for RouteManager {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+/// Host routes sending servers via the best default route while the tunnel
+/// routes of an ended connection are still in place, so that they can be
+/// reached while reconnecting. Dropping it removes the routes.
+pub struct ServerBypassRoutes {
+    route_manager: SyncRouteManager,
+    routes: Vec<Route>,
+}
+
+impl ServerBypassRoutes {
+    /// Route each of `servers` (NoExec and Policy modes install nothing,
+    /// the latter's fwmark'ed sockets bypass the tunnel anyway).
+    pub fn install(
+        routing_mode: RouteMode,
+        servers: impl IntoIterator<Item = IpAddr>,
+    ) -> Result<Self, RoutingTableError> {
+        let mut bypass = Self {
+            route_manager: SyncRouteManager::new()
+                .map_err(RoutingTableError::RoutingManagerError)?,
+            routes: Vec::new(),
+        };
+        #[cfg(linux)]
+        if routing_mode == RouteMode::Policy {
+            return Ok(bypass);
+        }
+        if routing_mode == RouteMode::NoExec {
+            return Ok(bypass);
+        }
+
+        let default_routes = bypass
+            .route_manager
+            .list()
+            .map_err(RoutingTableError::DefaultInterfaceNotFound)?
+            .into_iter()
+            .filter(|route| route.prefix() == 0)
+            .collect::<Vec<_>>();
+        for server_ip in servers {
+            let default_route = default_routes
+                .iter()
+                .filter(|route| same_ip_family(&route.destination(), &server_ip))
+                .min_by_key(|route| RouteManagerInner::get_route_metric(route))
+                .ok_or(RoutingTableError::DefaultRouteNotFound)?;
+            let mut route = Route::new(server_ip, host_prefix_len(&server_ip));
+            if let Some(if_index) = default_route.if_index() {
+                route = route.with_if_index(if_index);
+            }
+            if let Some(gateway) = default_route.gateway() {
+                route = route.with_gateway(gateway);
+            }
+            #[cfg(windows)]
+            let route = route.with_metric(0);
+
+            if !acquire_route(&route) {
+                // Installed by a connection, shared until both are done
+                bypass.routes.push(route);
+                continue;
+            }
+            match bypass.route_manager.add(&route) {
+                Ok(()) => {
+                    tracing::info!("Added bypass {route}");
+                    bypass.routes.push(route);
+                }
+                Err(e) => {
+                    release_route(&route);
+                    match e {
+                        // Not ours to remove
+                        e if is_route_exists_error(&e) => {}
+                        e if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                            return Err(RoutingTableError::InsufficientPermissions);
+                        }
+                        e => return Err(RoutingTableError::AddRouteError(e)),
+                    }
+                }
+            }
+        }
+        Ok(bypass)
+    }
+}
+
+impl Drop for ServerBypassRoutes {
+    fn drop(&mut self) {
+        for route in &self.routes {
+            if !release_route(route) {
+                continue;
+            }
+            if let Err(e) = self.route_manager.delete(route) {
+                warn!("Failed to delete bypass route: {}, error: {}", route, e);
+            }
+        }
+    }
+}
+
 impl RouteManagerInner {
     fn new(
         routing_mode: RouteMode,
@@ -217,6 +394,10 @@ impl RouteManagerInner {
             vpn_routes: Vec::with_capacity(TUNNEL_ROUTES.len() + 1),
             lan_routes: Vec::with_capacity(LAN_NETWORKS.len()),
             server_route: None,
+            #[cfg(linux)]
+            fwmark: 0,
+            #[cfg(linux)]
+            policy_routing: None,
         })
     }
 
@@ -349,6 +530,10 @@ impl RouteManagerInner {
 
     /// Adds Route
     async fn add_route(&mut self, route: &Route) -> Result<(), RoutingTableError> {
+        if !acquire_route(route) {
+            tracing::debug!("Sharing {route} with the previous connection");
+            return Ok(());
+        }
         match self.route_manager_async.add(route).await {
             Ok(()) => {
                 tracing::info!("Added {route}");
@@ -359,23 +544,28 @@ impl RouteManagerInner {
                     // Ignore error if route already exists and
                     // keep the existing route
                     Ok(())
-                } else if e.kind() == std::io::ErrorKind::PermissionDenied {
-                    Err(RoutingTableError::InsufficientPermissions)
                 } else {
-                    Err(RoutingTableError::AddRouteError(e))
+                    release_route(route);
+                    if e.kind() == std::io::ErrorKind::PermissionDenied {
+                        Err(RoutingTableError::InsufficientPermissions)
+                    } else {
+                        Err(RoutingTableError::AddRouteError(e))
+                    }
                 }
             }
         }
     }
 
-    fn is_route_exists_error(&self, error: &std::io::Error) -> bool {
-        match error.raw_os_error() {
-            #[cfg(any(target_os = "linux", target_os = "macos",))]
-            Some(libc::EEXIST) => true,
-            #[cfg(windows)]
-            Some(code) => code == ERROR_OBJECT_ALREADY_EXISTS as i32,
-            _ => false,
+    /// Delete `route` unless another owner still holds it
+    fn delete_route_sync(&self, route: &Route) -> std::io::Result<()> {
+        if !release_route(route) {
+            return Ok(());
         }
+        self.route_manager.delete(route)
+    }
+
+    fn is_route_exists_error(&self, error: &std::io::Error) -> bool {
+        is_route_exists_error(error)
     }
 
     /// Adds Routes and stores it
@@ -404,8 +594,13 @@ impl RouteManagerInner {
 
     /// Clean up for program unwind
     fn cleanup_sync(&mut self) {
+        #[cfg(linux)]
+        if let Some(mut policy_routing) = self.policy_routing.take() {
+            policy_routing.cleanup();
+        }
+
         for route in &self.vpn_routes {
-            if let Err(e) = self.route_manager.delete(route) {
+            if let Err(e) = self.delete_route_sync(route) {
                 warn!(
                     "Failed to delete VPN route during drop: {}, error: {}",
                     route, e
@@ -414,7 +609,7 @@ impl RouteManagerInner {
         }
 
         for route in &self.lan_routes {
-            if let Err(e) = self.route_manager.delete(route) {
+            if let Err(e) = self.delete_route_sync(route) {
                 warn!(
                     "Failed to delete LAN route during drop: {}, error: {}",
                     route, e
@@ -423,7 +618,7 @@ impl RouteManagerInner {
         }
 
         if let Some(route) = &self.server_route
-            && let Err(e) = self.route_manager.delete(route)
+            && let Err(e) = self.delete_route_sync(route)
         {
             warn!(
                 "Failed to delete server route during drop: {}, error: {}",
@@ -438,6 +633,14 @@ impl RouteManagerInner {
             return Ok(());
         }
 
+        #[cfg(linux)]
+        if self.routing_mode == RouteMode::Policy {
+            let mut policy_routing = PolicyRouting::new(self.fwmark, self.tun_index)?;
+            policy_routing.install()?;
+            self.policy_routing = Some(policy_routing);
+            return Ok(());
+        }
+
         let server_ip = self.server_ip;
 
         // Setting up VPN Server Routes
@@ -523,7 +726,9 @@ impl RouteManagerInner {
                 // Update server route with new gateway/interface
                 if let Some(old_route) = self.server_route.take() {
                     // Remove old route
-                    let _ = self.route_manager_async.delete(&old_route).await;
+                    if release_route(&old_route) {
+                        let _ = self.route_manager_async.delete(&old_route).await;
+                    }
                 }
 
                 // Add new route with current gateway and interface
@@ -988,6 +1193,19 @@ mod tests {
         assert!(stop_again_result.is_ok());
     }
 
+    #[test]
+    fn route_deleted_by_last_owner() {
+        let route = Route::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 123)), 32);
+
+        assert!(acquire_route(&route));
+        assert!(!acquire_route(&route));
+        assert!(!release_route(&route));
+        assert!(release_route(&route));
+
+        // Installed by someone else
+        assert!(release_route(&route));
+    }
+
     #[tokio::test]
     async fn test_stop_aborts_task_and_awaits_updater_drop() {
         struct DropFlag(std::sync::Arc<std::sync::atomic::AtomicBool>);
@@ -1220,4 +1438,33 @@ mod tests {
             "IPv6 route should maintain /128 prefix"
         );
     }
+
+    #[tokio::test]
+    #[ignore = "May falsely fail during development due to local route settings"]
+    #[serial_test::serial(route_manager)]
+    async fn test_privileged_server_bypass_routes() {
+        let (_restorer, _tun_device, mut inner) =
+            create_test_setup(RouteMode::Default, EXTERNAL_IP_V4)
+                .await
+                .unwrap();
+        inner.install_routes().await.unwrap();
+        let gateway = inner.server_route.as_ref().unwrap().gateway();
+
+        // The tunnel routes are in place, yet the bypass goes around them
+        let bypass =
+            ServerBypassRoutes::install(RouteMode::Default, [ROUTE_TEST_IP1, EXTERNAL_IP_V4])
+                .unwrap();
+        let route = inner.find_route(&ROUTE_TEST_IP1).unwrap();
+        assert_eq!(route.gateway(), gateway);
+        // The server route was there already and is left alone
+        assert_eq!(bypass.routes.len(), 1);
+
+        drop(bypass);
+        let route = inner.find_route(&ROUTE_TEST_IP1).unwrap();
+        assert_eq!(route.if_index(), Some(inner.tun_index));
+        assert_eq!(
+            inner.find_route(&EXTERNAL_IP_V4).unwrap().gateway(),
+            gateway
+        );
+    }
 }
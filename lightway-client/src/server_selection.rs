@@ -0,0 +1,360 @@
+//! How parallel connect picks the connection to keep among those that
+//! came online, see [`crate::client`].
+
+use std::time::{Duration, Instant};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::keepalive::{self, KeepaliveReplies};
+
+/// Default number of keepalives sent to each connection when probing
+pub const DEFAULT_SERVER_SELECTION_PROBES: usize = 3;
+
+/// Default latency added per position in the server list by
+/// [`ServerSelection::Weighted`]
+pub const DEFAULT_SERVER_SELECTION_PRIORITY_WEIGHT: Duration = Duration::from_millis(20);
+
+/// Default time to wait for the reply to a keepalive probe
+pub const DEFAULT_SERVER_SELECTION_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Server selection strategy
+#[derive(
+    Debug, Clone, Copy, JsonSchema, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize, Default,
+)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum ServerSelection {
+    /// Earliest connection in the server list
+    #[default]
+    Priority,
+    /// Lowest handshake round trip time
+    HandshakeRtt,
+    /// Lowest median round trip time of keepalive probes
+    KeepaliveRtt,
+    /// Lowest latency plus a penalty per position in the server list
+    Weighted,
+}
+
+/// Server selection configuration
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerSelectionConfig {
+    /// Strategy ranking the connections
+    pub strategy: ServerSelection,
+
+    /// Keepalives sent to each connection by `KeepaliveRtt` and
+    /// `Weighted`. `Weighted` uses the handshake round trip time when 0
+    pub probes: usize,
+
+    /// Time to wait for the reply to a keepalive probe
+    pub probe_timeout: Duration,
+
+    /// Latency `Weighted` adds per position in the server list
+    pub priority_weight: Duration,
+}
+
+impl Default for ServerSelectionConfig {
+    fn default() -> Self {
+        Self {
+            strategy: ServerSelection::default(),
+            probes: DEFAULT_SERVER_SELECTION_PROBES,
+            probe_timeout: DEFAULT_SERVER_SELECTION_PROBE_TIMEOUT,
+            priority_weight: DEFAULT_SERVER_SELECTION_PRIORITY_WEIGHT,
+        }
+    }
+}
+
+/// What server selection measured of a connection that came online.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectionMeasurement {
+    /// Index of the connection in the server list
+    pub index: usize,
+
+    /// Time from the connection starting its handshake to it coming
+    /// online, i.e. the round trips of the handshake
+    pub handshake_rtt: Duration,
+
+    /// Median round trip time of the answered keepalive probes. `None`
+    /// when not probed or no probe was answered
+    pub keepalive_rtt: Option<Duration>,
+
+    /// Number of keepalive probes answered
+    pub probes_answered: usize,
+}
+
+impl ConnectionMeasurement {
+    pub(crate) fn new(index: usize, handshake_rtt: Duration) -> Self {
+        Self {
+            index,
+            handshake_rtt,
+            keepalive_rtt: None,
+            probes_answered: 0,
+        }
+    }
+
+    fn record_probes(&mut self, mut rtts: Vec<Duration>) {
+        rtts.sort();
+        self.probes_answered = rtts.len();
+        self.keepalive_rtt = rtts.get(rtts.len() / 2).copied();
+    }
+}
+
+impl ServerSelectionConfig {
+    /// Whether to wait for every connection (bounded by the preferred
+    /// connection wait interval) rather than stopping at the preferred one
+    pub(crate) fn compares_latency(&self) -> bool {
+        self.strategy != ServerSelection::Priority
+    }
+
+    /// Whether the connections online are probed with keepalives
+    pub(crate) fn probes_keepalive(&self) -> bool {
+        self.probes > 0
+            && matches!(
+                self.strategy,
+                ServerSelection::KeepaliveRtt | ServerSelection::Weighted
+            )
+    }
+
+    /// Index of the best connection in `online`
+    pub(crate) fn best(&self, online: &[ConnectionMeasurement]) -> Option<usize> {
+        online.iter().min_by_key(|m| self.rank(m)).map(|m| m.index)
+    }
+
+    /// Sort key of a connection, lowest is best. Ties go to the earliest
+    /// connection in the server list.
+    fn rank(&self, m: &ConnectionMeasurement) -> (bool, Duration, usize) {
+        match self.strategy {
+            ServerSelection::Priority => (false, Duration::ZERO, m.index),
+            ServerSelection::HandshakeRtt => (false, m.handshake_rtt, m.index),
+            // Unanswered ones last, by handshake as before probing
+            ServerSelection::KeepaliveRtt => match m.keepalive_rtt {
+                Some(rtt) => (false, rtt, m.index),
+                None => (self.probes_keepalive(), m.handshake_rtt, m.index),
+            },
+            ServerSelection::Weighted => {
+                let latency = m.keepalive_rtt.unwrap_or(m.handshake_rtt);
+                let penalty = self
+                    .priority_weight
+                    .saturating_mul(m.index.try_into().unwrap_or(u32::MAX));
+                (false, latency.saturating_add(penalty), m.index)
+            }
+        }
+    }
+}
+
+/// A connection server selection chooses from.
+pub(crate) trait Candidate {
+    /// When the connection started its handshake, when set up if `None`
+    fn handshake_started(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Probe to measure the connection's keepalive round trip time
+    fn keepalive_probe(&self) -> KeepaliveProbe;
+}
+
+/// Measures the round trip time of keepalives sent on a connection.
+pub(crate) struct KeepaliveProbe {
+    conn: Box<dyn keepalive::Connection>,
+    keepalive_replies: KeepaliveReplies,
+}
+
+impl KeepaliveProbe {
+    /// `keepalive_replies` must receive the keepalive replies of the
+    /// connection.
+    pub fn new(
+        conn: impl keepalive::Connection + 'static,
+        keepalive_replies: KeepaliveReplies,
+    ) -> Self {
+        Self {
+            conn: Box::new(conn),
+            keepalive_replies,
+        }
+    }
+
+    /// Send `config.probes` keepalives one after the other and record the
+    /// round trip times of those answered within `config.probe_timeout`.
+    pub async fn measure(
+        self,
+        config: &ServerSelectionConfig,
+        measurement: &mut ConnectionMeasurement,
+    ) {
+        let rtts = self.rtts(config.probes, config.probe_timeout).await;
+        measurement.record_probes(rtts);
+    }
+
+    /// Send `probes` keepalives one after the other, returning the round
+    /// trip times of those answered within `timeout`.
+    pub async fn rtts(self, probes: usize, timeout: Duration) -> Vec<Duration> {
+        let mut rtts = Vec::with_capacity(probes);
+        for _ in 0..probes {
+            let start = Instant::now();
+            match self.keepalive_replies.probe(&*self.conn, timeout).await {
+                Ok(true) => rtts.push(start.elapsed()),
+                Ok(false) => {}
+                Err(e) => {
+                    tracing::debug!("Keepalive probe failed: {e}");
+                    break;
+                }
+            }
+        }
+        rtts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lightway_core::KeepaliveId;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use test_case::test_case;
+
+    fn measurement(
+        index: usize,
+        handshake_ms: u64,
+        keepalive_ms: Option<u64>,
+    ) -> ConnectionMeasurement {
+        ConnectionMeasurement {
+            index,
+            handshake_rtt: Duration::from_millis(handshake_ms),
+            keepalive_rtt: keepalive_ms.map(Duration::from_millis),
+            probes_answered: keepalive_ms.map_or(0, |_| 1),
+        }
+    }
+
+    fn config(strategy: ServerSelection) -> ServerSelectionConfig {
+        ServerSelectionConfig {
+            strategy,
+            ..Default::default()
+        }
+    }
+
+    #[test_case(ServerSelection::Priority => Some(0))]
+    #[test_case(ServerSelection::HandshakeRtt => Some(2))]
+    #[test_case(ServerSelection::KeepaliveRtt => Some(1))]
+    // 0: 100ms, 1: 40ms + 20ms, 2: 90ms + 40ms
+    #[test_case(ServerSelection::Weighted => Some(1))]
+    fn best_by_strategy(strategy: ServerSelection) -> Option<usize> {
+        let online = [
+            measurement(2, 30, Some(90)),
+            measurement(1, 50, Some(40)),
+            measurement(0, 80, Some(100)),
+        ];
+        config(strategy).best(&online)
+    }
+
+    #[test]
+    fn keepalive_rtt_ranks_unanswered_last() {
+        let online = [measurement(0, 10, None), measurement(1, 50, Some(400))];
+        assert_eq!(config(ServerSelection::KeepaliveRtt).best(&online), Some(1));
+
+        // Not probed yet: by handshake
+        let config = ServerSelectionConfig {
+            probes: 0,
+            ..config(ServerSelection::KeepaliveRtt)
+        };
+        assert_eq!(config.best(&online), Some(0));
+    }
+
+    #[test]
+    fn weighted_prefers_priority_on_similar_latency() {
+        let online = [measurement(1, 90, None), measurement(0, 100, None)];
+        let config = ServerSelectionConfig {
+            probes: 0,
+            ..config(ServerSelection::Weighted)
+        };
+        assert_eq!(config.best(&online), Some(0));
+    }
+
+    #[test]
+    fn best_of_none() {
+        assert_eq!(config(ServerSelection::Priority).best(&[]), None);
+    }
+
+    #[test]
+    fn median_of_answered_probes() {
+        let mut m = measurement(0, 10, None);
+        m.record_probes([30, 10, 20].map(Duration::from_millis).to_vec());
+        assert_eq!(m.keepalive_rtt, Some(Duration::from_millis(20)));
+        assert_eq!(m.probes_answered, 3);
+
+        m.record_probes(vec![]);
+        assert_eq!(m.keepalive_rtt, None);
+    }
+
+    /// Answers keepalives after `rtt`, or never, and right away replies
+    /// to an older keepalive when `stale_reply`
+    struct MockConnection {
+        rtt: Option<Duration>,
+        stale_reply: bool,
+        sent: AtomicU32,
+        keepalive_replies: KeepaliveReplies,
+    }
+
+    impl keepalive::Connection for MockConnection {
+        fn keepalive(&self) -> lightway_core::ConnectionResult<Option<KeepaliveId>> {
+            let id = KeepaliveId(self.sent.fetch_add(1, Ordering::Relaxed) + 100);
+            if self.stale_reply {
+                self.keepalive_replies
+                    .received(Some(KeepaliveId(id.0 - 100)));
+            }
+            if let Some(rtt) = self.rtt {
+                let replies = self.keepalive_replies.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(rtt).await;
+                    replies.received(Some(id));
+                });
+            }
+            Ok(Some(id))
+        }
+    }
+
+    fn probe(rtt: Option<Duration>, stale_reply: bool) -> KeepaliveProbe {
+        let keepalive_replies = KeepaliveReplies::new();
+        KeepaliveProbe::new(
+            MockConnection {
+                rtt,
+                stale_reply,
+                sent: AtomicU32::new(0),
+                keepalive_replies: keepalive_replies.clone(),
+            },
+            keepalive_replies,
+        )
+    }
+
+    #[tokio::test]
+    async fn probe_measures_rtt() {
+        let config = ServerSelectionConfig {
+            strategy: ServerSelection::KeepaliveRtt,
+            probes: 2,
+            probe_timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let mut m = measurement(0, 10, None);
+        probe(Some(Duration::from_millis(20)), true)
+            .measure(&config, &mut m)
+            .await;
+        assert_eq!(m.probes_answered, 2);
+        let rtt = m.keepalive_rtt.unwrap();
+        assert!(rtt >= Duration::from_millis(20) && rtt < Duration::from_millis(200));
+
+        let mut m = measurement(0, 10, None);
+        probe(None, false).measure(&config, &mut m).await;
+        assert_eq!(m.probes_answered, 0);
+        assert_eq!(m.keepalive_rtt, None);
+    }
+
+    #[tokio::test]
+    async fn probe_ignores_other_replies() {
+        let config = ServerSelectionConfig {
+            strategy: ServerSelection::KeepaliveRtt,
+            probes: 2,
+            probe_timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let mut m = measurement(0, 10, None);
+        probe(None, true).measure(&config, &mut m).await;
+        assert_eq!(m.probes_answered, 0);
+        assert_eq!(m.keepalive_rtt, None);
+    }
+}
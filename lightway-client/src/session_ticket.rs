@@ -0,0 +1,105 @@
+//! TLS session tickets kept between connections, so that reconnecting to
+//! a server resumes the previous session instead of a full handshake.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use lightway_core::ConnectionType;
+
+/// Which connections may resume a ticket: a ticket only resumes with
+/// the server, transport and domain name validation it was issued for.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct TicketId {
+    server: SocketAddr,
+    datagram: bool,
+    server_dn: Option<String>,
+}
+
+/// In-memory store of the latest session ticket per server, shared by
+/// every connection of a client. Tickets are never written to disk.
+#[derive(Clone, Debug, Default)]
+pub struct SessionTicketCache(Arc<Mutex<HashMap<TicketId, Bytes>>>);
+
+impl SessionTicketCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn slot(
+        &self,
+        server: SocketAddr,
+        connection_type: ConnectionType,
+        server_dn: Option<&str>,
+    ) -> SessionTicketSlot {
+        SessionTicketSlot {
+            cache: self.clone(),
+            id: TicketId {
+                server,
+                datagram: connection_type.is_datagram(),
+                server_dn: server_dn.map(str::to_string),
+            },
+        }
+    }
+}
+
+/// The ticket of one connection in a [`SessionTicketCache`].
+#[derive(Debug)]
+pub(crate) struct SessionTicketSlot {
+    cache: SessionTicketCache,
+    id: TicketId,
+}
+
+impl SessionTicketSlot {
+    /// Take the ticket to offer: each ticket is offered once, so that
+    /// connections cannot be linked through it. The resumed connection
+    /// receives fresh ones.
+    pub fn take(&self) -> Option<Bytes> {
+        self.cache.0.lock().unwrap().remove(&self.id)
+    }
+
+    pub fn store(&self, ticket: Bytes) {
+        self.cache.0.lock().unwrap().insert(self.id.clone(), ticket);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER: SocketAddr = SocketAddr::new(
+        std::net::IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1)),
+        443,
+    );
+
+    #[test]
+    fn ticket_is_taken_once() {
+        let cache = SessionTicketCache::new();
+        let slot = cache.slot(SERVER, ConnectionType::Stream, Some("example.com"));
+        slot.store(Bytes::from_static(b"old"));
+        slot.store(Bytes::from_static(b"new"));
+
+        let slot = cache.slot(SERVER, ConnectionType::Stream, Some("example.com"));
+        assert_eq!(slot.take(), Some(Bytes::from_static(b"new")));
+        assert_eq!(slot.take(), None);
+    }
+
+    #[test]
+    fn ticket_is_bound_to_its_connection() {
+        let cache = SessionTicketCache::new();
+        cache
+            .slot(SERVER, ConnectionType::Stream, Some("example.com"))
+            .store(Bytes::from_static(b"ticket"));
+
+        let other_server = SocketAddr::new(SERVER.ip(), 8443);
+        for slot in [
+            cache.slot(other_server, ConnectionType::Stream, Some("example.com")),
+            cache.slot(SERVER, ConnectionType::Datagram, Some("example.com")),
+            cache.slot(SERVER, ConnectionType::Stream, Some("example.net")),
+            cache.slot(SERVER, ConnectionType::Stream, None),
+        ] {
+            assert_eq!(slot.take(), None);
+        }
+    }
+}
@@ -0,0 +1,351 @@
+//! Per connection access control
+//!
+//! An [`Acl`] granted by the [`ServerAuthHandle`](crate::ServerAuthHandle)
+//! restricts the flows a client can open through the server. The server
+//! compiles it into an inside [`Plugin`] of the client's connection.
+
+use std::{net::Ipv4Addr, ops::RangeInclusive};
+
+use bytes::BytesMut;
+use ipnet::Ipv4Net;
+use pnet_packet::{
+    Packet,
+    icmp::{IcmpCode, IcmpPacket, IcmpTypes, MutableIcmpPacket},
+    ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
+    ipv4::{self, Ipv4Packet, MutableIpv4Packet},
+};
+
+use crate::{Plugin, PluginResult, metrics};
+
+/// ICMP destination unreachable code "Communication Administratively
+/// Prohibited" (RFC 1812)
+const ICMP_CODE_ADMIN_PROHIBITED: IcmpCode = IcmpCode(13);
+
+/// Length of the ICMP header preceding the quoted packet
+const ICMP_HEADER_LEN: usize = 8;
+
+/// Bytes of the original payload quoted in ICMP errors (RFC 792)
+const ICMP_QUOTED_PAYLOAD_LEN: usize = 8;
+
+/// Flows a client can open through the server
+///
+/// A flow is allowed if it matches no `deny` rule and, unless `allow`
+/// is empty, at least one `allow` rule. Rules are matched against the
+/// remote end of the flow only, so replies to allowed flows get through,
+/// but so does any packet from a remote the client can reach, whichever
+/// port of the client it goes to: there is no connection tracking.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Acl {
+    /// Flows the client can open. Empty allows all flows not denied.
+    pub allow: Vec<AclRule>,
+    /// Flows the client cannot open, taking precedence over `allow`
+    pub deny: Vec<AclRule>,
+}
+
+/// Transport protocol an [`AclRule`] applies to
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AclProtocol {
+    /// TCP
+    Tcp,
+    /// UDP
+    Udp,
+    /// ICMP
+    Icmp,
+}
+
+impl AclProtocol {
+    fn next_header(&self) -> IpNextHeaderProtocol {
+        match self {
+            AclProtocol::Tcp => IpNextHeaderProtocols::Tcp,
+            AclProtocol::Udp => IpNextHeaderProtocols::Udp,
+            AclProtocol::Icmp => IpNextHeaderProtocols::Icmp,
+        }
+    }
+}
+
+/// Remote endpoints matched by an [`Acl`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AclRule {
+    network: Ipv4Net,
+    protocol: Option<AclProtocol>,
+    ports: Option<RangeInclusive<u16>>,
+}
+
+impl AclRule {
+    /// Match any protocol and port of the hosts in `network`
+    pub fn new(network: Ipv4Net) -> Self {
+        Self {
+            network,
+            protocol: None,
+            ports: None,
+        }
+    }
+
+    /// Only match `protocol`
+    pub fn with_protocol(self, protocol: AclProtocol) -> Self {
+        Self {
+            protocol: Some(protocol),
+            ..self
+        }
+    }
+
+    /// Only match TCP and UDP ports within `ports`
+    pub fn with_ports(self, ports: RangeInclusive<u16>) -> Self {
+        Self {
+            ports: Some(ports),
+            ..self
+        }
+    }
+}
+
+/// [`AclRule`] reduced to integer comparisons
+struct CompiledRule {
+    network: u32,
+    netmask: u32,
+    protocol: Option<IpNextHeaderProtocol>,
+    ports: Option<RangeInclusive<u16>>,
+}
+
+impl From<&AclRule> for CompiledRule {
+    fn from(rule: &AclRule) -> Self {
+        Self {
+            network: rule.network.network().to_bits(),
+            netmask: rule.network.netmask().to_bits(),
+            protocol: rule.protocol.as_ref().map(AclProtocol::next_header),
+            ports: rule.ports.clone(),
+        }
+    }
+}
+
+impl CompiledRule {
+    fn matches(&self, flow: &Flow) -> bool {
+        if flow.remote & self.netmask != self.network {
+            return false;
+        }
+        if self
+            .protocol
+            .is_some_and(|protocol| protocol != flow.protocol)
+        {
+            return false;
+        }
+        match &self.ports {
+            None => true,
+            Some(ports) => flow.remote_port.is_some_and(|port| ports.contains(&port)),
+        }
+    }
+}
+
+/// Remote end of the flow a packet belongs to
+struct Flow {
+    remote: u32,
+    protocol: IpNextHeaderProtocol,
+    /// `None` unless a TCP or UDP header is available
+    remote_port: Option<u16>,
+}
+
+impl Flow {
+    /// `outbound` packets are sent by the client, others to it
+    fn new(packet: &Ipv4Packet, outbound: bool) -> Self {
+        let (remote, port_offset) = if outbound {
+            (packet.get_destination(), 2)
+        } else {
+            (packet.get_source(), 0)
+        };
+        let protocol = packet.get_next_level_protocol();
+        let remote_port = match protocol {
+            IpNextHeaderProtocols::Tcp | IpNextHeaderProtocols::Udp
+                if packet.get_fragment_offset() == 0 =>
+            {
+                packet
+                    .payload()
+                    .get(port_offset..port_offset + 2)
+                    .map(|port| u16::from_be_bytes([port[0], port[1]]))
+            }
+            _ => None,
+        };
+
+        Self {
+            remote: remote.to_bits(),
+            protocol,
+            remote_port,
+        }
+    }
+}
+
+/// [`Acl`] compiled into an inside [`Plugin`] of a server connection
+///
+/// Packets from the client to a remote it cannot reach are dropped
+/// with an ICMP "Communication Administratively Prohibited" reply
+/// from `server_ip`, packets from such a remote are dropped silently.
+pub(crate) struct AclPlugin {
+    allow: Vec<CompiledRule>,
+    deny: Vec<CompiledRule>,
+    server_ip: Ipv4Addr,
+}
+
+impl AclPlugin {
+    pub(crate) fn new(acl: &Acl, server_ip: Ipv4Addr) -> Self {
+        Self {
+            allow: acl.allow.iter().map(CompiledRule::from).collect(),
+            deny: acl.deny.iter().map(CompiledRule::from).collect(),
+            server_ip,
+        }
+    }
+
+    fn allows(&self, flow: &Flow) -> bool {
+        if self.deny.iter().any(|rule| rule.matches(flow)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|rule| rule.matches(flow))
+    }
+
+    /// ICMP error for `packet`, unless it must not be replied to
+    fn unreachable_reply(&self, packet: &Ipv4Packet) -> Option<BytesMut> {
+        // Never reply to non initial fragments or ICMP errors (RFC 1122)
+        if packet.get_fragment_offset() != 0 {
+            return None;
+        }
+        if packet.get_next_level_protocol() == IpNextHeaderProtocols::Icmp
+            && IcmpPacket::new(packet.payload())
+                .is_none_or(|icmp| icmp.get_icmp_type() != IcmpTypes::EchoRequest)
+        {
+            return None;
+        }
+
+        let header_len = packet.get_header_length() as usize * 4;
+        let quoted_len = (header_len + ICMP_QUOTED_PAYLOAD_LEN).min(packet.packet().len());
+        let icmp_offset = Ipv4Packet::minimum_packet_size();
+        let total_len = icmp_offset + ICMP_HEADER_LEN + quoted_len;
+
+        let mut buf = BytesMut::zeroed(total_len);
+        buf[icmp_offset + ICMP_HEADER_LEN..].copy_from_slice(&packet.packet()[..quoted_len]);
+        {
+            let mut icmp = MutableIcmpPacket::new(&mut buf[icmp_offset..])?;
+            icmp.set_icmp_type(IcmpTypes::DestinationUnreachable);
+            icmp.set_icmp_code(ICMP_CODE_ADMIN_PROHIBITED);
+            let checksum = pnet_packet::icmp::checksum(&icmp.to_immutable());
+            icmp.set_checksum(checksum);
+        }
+        let mut ip = MutableIpv4Packet::new(&mut buf)?;
+        ip.set_version(4);
+        ip.set_header_length(5);
+        ip.set_total_length(total_len as u16);
+        ip.set_ttl(64);
+        ip.set_next_level_protocol(IpNextHeaderProtocols::Icmp);
+        ip.set_source(self.server_ip);
+        ip.set_destination(packet.get_source());
+        let checksum = ipv4::checksum(&ip.to_immutable());
+        ip.set_checksum(checksum);
+
+        Some(buf)
+    }
+}
+
+impl Plugin for AclPlugin {
+    fn ingress(&self, data: &mut BytesMut) -> PluginResult {
+        let Some(packet) = Ipv4Packet::new(data) else {
+            return PluginResult::Drop;
+        };
+        if self.allows(&Flow::new(&packet, false)) {
+            return PluginResult::Accept;
+        }
+        metrics::acl_rejected_inbound();
+        PluginResult::Drop
+    }
+
+    fn egress(&self, data: &mut BytesMut) -> PluginResult {
+        let Some(packet) = Ipv4Packet::new(data) else {
+            return PluginResult::Drop;
+        };
+        if self.allows(&Flow::new(&packet, true)) {
+            return PluginResult::Accept;
+        }
+        metrics::acl_rejected_outbound();
+        match self.unreachable_reply(&packet) {
+            Some(reply) => PluginResult::DropWithReply(reply),
+            None => PluginResult::Drop,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet_packet::udp::MutableUdpPacket;
+    use test_case::test_case;
+
+    const CLIENT_IP: Ipv4Addr = Ipv4Addr::new(10, 125, 0, 2);
+    const SERVER_IP: Ipv4Addr = Ipv4Addr::new(10, 125, 0, 1);
+
+    fn udp_packet(source: Ipv4Addr, destination: Ipv4Addr, port: u16) -> BytesMut {
+        let mut buf = BytesMut::zeroed(28);
+        let mut ip = MutableIpv4Packet::new(&mut buf).unwrap();
+        ip.set_version(4);
+        ip.set_header_length(5);
+        ip.set_total_length(28);
+        ip.set_ttl(64);
+        ip.set_next_level_protocol(IpNextHeaderProtocols::Udp);
+        ip.set_source(source);
+        ip.set_destination(destination);
+        let mut udp = MutableUdpPacket::new(&mut buf[20..]).unwrap();
+        udp.set_source(port);
+        udp.set_destination(port);
+        udp.set_length(8);
+        buf
+    }
+
+    fn acl() -> Acl {
+        Acl {
+            allow: vec![
+                AclRule::new("192.168.0.0/16".parse().unwrap()),
+                AclRule::new("0.0.0.0/0".parse().unwrap())
+                    .with_protocol(AclProtocol::Udp)
+                    .with_ports(53..=53),
+            ],
+            deny: vec![AclRule::new("192.168.10.0/24".parse().unwrap())],
+        }
+    }
+
+    #[test_case("192.168.1.1", 80 => true; "allowed network")]
+    #[test_case("192.168.10.1", 80 => false; "denied within allowed network")]
+    #[test_case("8.8.8.8", 53 => true; "allowed port")]
+    #[test_case("8.8.8.8", 80 => false; "not allowed")]
+    fn acl_matches(remote: &str, port: u16) -> bool {
+        let plugin = AclPlugin::new(&acl(), SERVER_IP);
+        let remote = remote.parse().unwrap();
+
+        let mut outbound = udp_packet(CLIENT_IP, remote, port);
+        let mut inbound = udp_packet(remote, CLIENT_IP, port);
+        let outbound_allowed = matches!(plugin.egress(&mut outbound), PluginResult::Accept);
+        let inbound_allowed = matches!(plugin.ingress(&mut inbound), PluginResult::Accept);
+        assert_eq!(outbound_allowed, inbound_allowed);
+        outbound_allowed
+    }
+
+    #[test]
+    fn empty_acl_allows_all() {
+        let plugin = AclPlugin::new(&Acl::default(), SERVER_IP);
+        let mut packet = udp_packet(CLIENT_IP, "8.8.8.8".parse().unwrap(), 80);
+        assert!(matches!(plugin.egress(&mut packet), PluginResult::Accept));
+    }
+
+    #[test]
+    fn rejected_flow_gets_unreachable_reply() {
+        let plugin = AclPlugin::new(&acl(), SERVER_IP);
+        let mut packet = udp_packet(CLIENT_IP, "8.8.8.8".parse().unwrap(), 80);
+
+        let PluginResult::DropWithReply(reply) = plugin.egress(&mut packet) else {
+            panic!("Expected a reply");
+        };
+
+        let ip = Ipv4Packet::new(&reply).unwrap();
+        assert_eq!(ip.get_source(), SERVER_IP);
+        assert_eq!(ip.get_destination(), CLIENT_IP);
+        assert_eq!(ip.get_checksum(), ipv4::checksum(&ip));
+        let icmp = IcmpPacket::new(ip.payload()).unwrap();
+        assert_eq!(icmp.get_icmp_type(), IcmpTypes::DestinationUnreachable);
+        assert_eq!(icmp.get_icmp_code(), ICMP_CODE_ADMIN_PROHIBITED);
+        assert_eq!(icmp.get_checksum(), pnet_packet::icmp::checksum(&icmp));
+        assert_eq!(&icmp.payload()[4..], &packet[..]);
+    }
+}
@@ -4,11 +4,13 @@ mod event;
 pub(crate) mod expresslane;
 mod fragment_map;
 mod io_adapter;
+mod keepalive_stats;
 mod key_update;
 
 use crate::tls::{ErrorKind, IOCallbackResult, ProtocolVersion};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use dplpmtud::BASE_PLPMTU;
+use ipnet::Ipv4Net;
 use rand::distr::{Distribution, StandardUniform};
 use std::borrow::Cow;
 use std::collections::VecDeque;
@@ -26,6 +28,7 @@ use crate::context::ExpresslaneTickData;
 use crate::{
     ConnectionType, IPV4_HEADER_SIZE, InsideIOSendCallbackArg, PluginResult, SessionId,
     TCP_HEADER_SIZE, Version,
+    acl::AclPlugin,
     context::{ScheduleTickCb, ServerAuthArg, ServerAuthHandle, ServerAuthResult},
     encoding_request_states::EncodingRequestStates,
     metrics,
@@ -35,8 +38,8 @@ use crate::{
     wire::{self, AuthMethod},
 };
 use crate::{
-    ExpresslaneCbData, Header, LightwayFeature, OutsideIOSendCallbackArg, TickType,
-    dtls_required_outside_mtu, max_dtls_mtu,
+    ExpresslaneCbData, Header, LightwayFeature, MultipathMode, OutsideIOSendCallbackArg, PeerPath,
+    TickType, dtls_required_outside_mtu, max_dtls_mtu,
 };
 
 use crate::context::ip_pool::{ClientIpConfigArg, ServerIpPoolArg};
@@ -50,6 +53,8 @@ pub use builders::{ClientConnectionBuilder, ConnectionBuilderError, ServerConnec
 pub use event::Event;
 use fragment_map::{FragmentMap, FragmentMapResult};
 pub(crate) use io_adapter::TlsIOAdapter;
+use keepalive_stats::KeepaliveEstimator;
+pub use keepalive_stats::{KeepaliveId, KeepaliveStats};
 
 /// D/TLS is a UDP based protocol and requires the application
 /// (rather than the OS as with TCP) to keep track of the need to do
@@ -461,6 +466,8 @@ enum ConnectionMode<AppState> {
     Client {
         /// Authentication info to use
         auth_method: AuthMethod,
+        /// Subnets behind the client, advertised when authenticating
+        site_subnets: Vec<Ipv4Net>,
         /// Callback to notify about inside ip config
         ip_config_cb: ClientIpConfigArg<AppState>,
     },
@@ -473,6 +480,8 @@ enum ConnectionMode<AppState> {
         key_update: key_update::State,
         /// `Some(_)` iff a session ID rotation is in progress.
         pending_session_id: Option<SessionId>,
+        /// Pushed to the client ahead of the auth success reply.
+        server_config: Option<Bytes>,
     },
 }
 
@@ -490,6 +499,14 @@ pub struct ConnectionActivity {
     /// Unlike `last_outside_data_received`, control frames (ping/pong) never
     /// touch this, so it reflects real data-plane delivery.
     pub last_data_delivered_to_inside: Instant,
+
+    /// Last time a frame was sent to the peer.
+    pub last_outside_data_sent: Instant,
+
+    /// Last time an inside packet was sent to the peer. Unlike
+    /// `last_outside_data_sent`, control frames (ping/pong) never touch
+    /// this.
+    pub last_data_sent: Instant,
 }
 
 /// The result of an operation on a [`Connection`].
@@ -599,6 +616,18 @@ pub struct Connection<AppState: Send = ()> {
 
     // Expresslane state, config exchange, health monitoring, wire crypto, and callbacks
     expresslane: expresslane::Expresslane<AppState>,
+
+    // Round trip statistics of keepalives sent by this side
+    keepalive_estimator: KeepaliveEstimator,
+
+    // Whether the server will grant multipath requests
+    can_use_multipath: bool,
+
+    // Multipath mode granted by the server, see `wire::MultipathConfig`
+    multipath_mode: MultipathMode,
+
+    // Client: a multipath request awaits the server's reply
+    multipath_requested: bool,
 }
 
 /// Information about the new session being established with a new
@@ -662,6 +691,8 @@ impl<AppState: Send> Connection<AppState> {
                 last_data_traffic_from_peer: now,
                 last_outside_data_received: now,
                 last_data_delivered_to_inside: now,
+                last_outside_data_sent: now,
+                last_data_sent: now,
             },
             tls_tick_interval: None,
             tls_pending_queue: VecDeque::new(),
@@ -691,6 +722,10 @@ impl<AppState: Send> Connection<AppState> {
                 args.expresslane_metrics,
                 args.expresslane_keys_rotation_interval,
             ),
+            keepalive_estimator: KeepaliveEstimator::new(now),
+            can_use_multipath: false,
+            multipath_mode: MultipathMode::Off,
+            multipath_requested: false,
         };
 
         // This will very likely fail since negotiation always needs
@@ -770,9 +805,13 @@ impl<AppState: Send> Connection<AppState> {
         }
 
         if matches!(new_state, State::LinkUp)
-            && let ConnectionMode::Client { auth_method, .. } = &self.mode
+            && let ConnectionMode::Client {
+                auth_method,
+                site_subnets,
+                ..
+            } = &self.mode
         {
-            self.authenticate(auth_method.clone())?;
+            self.authenticate(auth_method.clone(), site_subnets.clone())?;
         };
         Ok(())
     }
@@ -875,6 +914,59 @@ impl<AppState: Send> Connection<AppState> {
         old
     }
 
+    /// Multipath mode granted to the client, [`MultipathMode::Off`]
+    /// until then
+    pub fn multipath_mode(&self) -> MultipathMode {
+        self.multipath_mode
+    }
+
+    /// Use the peer's `addr`, which a packet was just received from, as
+    /// a path of the multipath session, see
+    /// [`crate::OutsideIOSendCallback::use_peer_path`]. A new address is
+    /// sent a [`wire::PathChallenge`] and only added once the peer
+    /// answers it, so that a packet copied to another address cannot
+    /// divert the session's traffic. Returns whether `addr` was added.
+    pub fn use_peer_path(&mut self, addr: SocketAddr) -> ConnectionResult<bool> {
+        if self.multipath_mode == MultipathMode::Off {
+            return Ok(false);
+        }
+
+        match self
+            .session
+            .io_cb()
+            .io
+            .use_peer_path(addr, self.multipath_mode)
+        {
+            PeerPath::Known => Ok(false),
+            PeerPath::Added => Ok(true),
+            PeerPath::Unvalidated => {
+                let token = StandardUniform.sample(&mut *self.rng.lock().unwrap());
+                if !self.session.io_cb().io.challenge_peer_path(addr, token) {
+                    return Ok(false);
+                }
+
+                debug!(session = ?self.session_id, ?addr, "Challenging multipath path");
+                let msg = wire::Frame::PathChallenge(wire::PathChallenge { token });
+
+                self.session.io_cb().io.pin_peer_path(Some(addr));
+                let res = self.send_frame_or_queue(msg);
+                self.session.io_cb().io.pin_peer_path(None);
+                res.map(|_| false)
+            }
+        }
+    }
+
+    /// The peer's paths of a multipath session, besides [`Self::peer_addr`]
+    pub fn peer_paths(&self) -> Vec<SocketAddr> {
+        self.session.io_cb().io.peer_paths()
+    }
+
+    /// Remove the paths of a multipath session nothing was received from
+    /// for `max_idle`, returning their addresses
+    pub fn expire_peer_paths(&self, max_idle: Duration) -> Vec<SocketAddr> {
+        self.session.io_cb().io.expire_peer_paths(max_idle)
+    }
+
     /// Get the negotiated cipher, only valid after [`State::LinkUp`]
     /// has been reached.
     pub fn current_cipher(&mut self) -> Option<String> {
@@ -887,6 +979,12 @@ impl<AppState: Send> Connection<AppState> {
         self.session.get_current_curve_name()
     }
 
+    /// Whether the TLS session was resumed from a session ticket, only
+    /// valid after [`State::LinkUp`] has been reached.
+    pub fn is_session_resumed(&self) -> bool {
+        crate::tls::session_resumed(&self.session)
+    }
+
     fn update_tick_interval(&mut self) {
         // Only Datagram (DTLS) connections need ticks
         if !self.connection_type.is_datagram() {
@@ -962,8 +1060,14 @@ impl<AppState: Send> Connection<AppState> {
 
         match self.state {
             State::Authenticating => {
-                if let ConnectionMode::Client { auth_method, .. } = &self.mode {
-                    self.authenticate(auth_method.clone())?; // Resend authentication request
+                if let ConnectionMode::Client {
+                    auth_method,
+                    site_subnets,
+                    ..
+                } = &self.mode
+                {
+                    // Resend authentication request
+                    self.authenticate(auth_method.clone(), site_subnets.clone())?;
                 } else {
                     // Server should never be authenticating.
                     return Err(ConnectionError::InvalidMode);
@@ -1260,6 +1364,8 @@ impl<AppState: Send> Connection<AppState> {
     ) -> ConnectionResult<()> {
         use crate::gso;
 
+        self.activity.last_data_sent = Instant::now();
+
         // Parse the protocol header length from the packet itself. We
         // can't trust `hdr.hdr_len` — Linux's TUN puts `skb_headlen`
         // (~MTU for multi-segment aggregates) there, not the protocol
@@ -1412,6 +1518,7 @@ impl<AppState: Send> Connection<AppState> {
         if !matches!(self.state, State::Online) {
             return Err(ConnectionError::InvalidState);
         }
+        self.activity.last_data_sent = Instant::now();
 
         if let Some(pmtu) = &self.pmtud
             && let Some((data_mps, frag_mps)) = pmtu.maximum_packet_sizes()
@@ -1492,14 +1599,22 @@ impl<AppState: Send> Connection<AppState> {
         Ok(())
     }
 
-    /// Send a keepalive packet to the peer.
-    pub fn keepalive(&mut self) -> ConnectionResult<()> {
+    /// Send a keepalive packet to the peer. Returns the id its reply
+    /// will carry, `None` when not online and nothing was sent.
+    pub fn keepalive(&mut self) -> ConnectionResult<Option<KeepaliveId>> {
         if !matches!(self.state, State::Online) {
-            return Ok(());
+            return Ok(None);
         };
 
+        // The request or its reply may have been lost
+        if self.multipath_requested {
+            self.request_multipath()?;
+        }
+
         // Calculate expresslane metrics if expresslane is ready
         let payload = self.encode_expresslane_metrics_payload();
+        let stamp = self.keepalive_estimator.stamp(Instant::now());
+        let payload = stamp.append_to(payload);
 
         debug!(session = ?self.session_id, payload_len = payload.len(), "Sending ping");
 
@@ -1510,7 +1625,28 @@ impl<AppState: Send> Connection<AppState> {
 
         let msg = wire::Frame::Ping(ping);
 
-        self.send_frame_or_queue(msg)
+        self.send_frame_or_queue(msg)?;
+        Ok(Some(KeepaliveId(stamp.sequence)))
+    }
+
+    /// Send a [`wire::Frame::NoOp`], the smallest frame there is, to keep
+    /// NAT bindings on the path to the peer alive. Unlike
+    /// [`Connection::keepalive`] the peer does not reply.
+    pub fn nat_keepalive(&mut self) -> ConnectionResult<()> {
+        if !matches!(self.state, State::Online) {
+            return Ok(());
+        };
+
+        debug!(session = ?self.session_id, "Sending NAT keepalive");
+
+        self.send_frame_or_queue(wire::Frame::NoOp)
+    }
+
+    /// Round trip time, jitter and loss measured from the keepalives sent
+    /// with [`Connection::keepalive`]. Only replies from peers echoing the
+    /// keepalive stamp are measured.
+    pub fn keepalive_stats(&self) -> KeepaliveStats {
+        self.keepalive_estimator.stats()
     }
 
     /// Disconnect this connection
@@ -1564,6 +1700,7 @@ impl<AppState: Send> Connection<AppState> {
     /// buffer); further frames are dropped — inner TCP retransmits the
     /// payloads anyway.
     fn send_frame_or_queue(&mut self, frame: wire::Frame) -> ConnectionResult<()> {
+        self.activity.last_outside_data_sent = Instant::now();
         let queue_limit = match self.connection_type {
             ConnectionType::Stream => match &frame {
                 wire::Frame::Data(d) if ipv4_is_tcp(d.data.as_ref()) => 1,
@@ -1721,11 +1858,18 @@ impl<AppState: Send> Connection<AppState> {
         }
     }
 
-    fn authenticate(&mut self, auth_method: AuthMethod) -> ConnectionResult<()> {
+    fn authenticate(
+        &mut self,
+        auth_method: AuthMethod,
+        site_subnets: Vec<Ipv4Net>,
+    ) -> ConnectionResult<()> {
         assert!(matches!(self.state, State::LinkUp | State::Authenticating));
         self.set_state(State::Authenticating)?;
 
-        let msg = wire::Frame::AuthRequest(wire::AuthRequest { auth_method });
+        let msg = wire::Frame::AuthRequest(wire::AuthRequest {
+            auth_method,
+            site_subnets,
+        });
         self.send_frame_or_queue(msg)
     }
 
@@ -1876,13 +2020,22 @@ impl<AppState: Send> Connection<AppState> {
                 wire::Frame::AuthSuccessWithConfigV4(cfg) => self.handle_auth_response(cfg)?,
                 wire::Frame::AuthFailure(_) => return Err(ConnectionError::Unauthorized),
                 wire::Frame::Goodbye => return Err(ConnectionError::Goodbye),
-                wire::Frame::ServerConfig(_) => warn!("Ignoring ServerConfig"),
+                wire::Frame::ServerConfig(sc) => self.handle_server_config(sc)?,
                 wire::Frame::EncodingRequest(er) => self.process_encoding_request_pkt(er)?,
                 wire::Frame::EncodingResponse(er) => self.process_encoding_response_pkt(er)?,
                 wire::Frame::ExpresslaneConfig(config) => self.handle_expresslane_config(config)?,
+                wire::Frame::MultipathConfig(mc) => self.handle_multipath_config(mc)?,
+                wire::Frame::PathChallenge(challenge) => self.handle_path_challenge(challenge)?,
+                wire::Frame::PathResponse(response) => self.handle_path_response(response)?,
             };
         }
 
+        if matches!(self.mode, ConnectionMode::Client { .. })
+            && let Some(ticket) = crate::tls::take_session_ticket(&mut self.session)
+        {
+            self.event(Event::SessionTicket(ticket.into()));
+        }
+
         self.maybe_update_tls_keys()?;
         if let ConnectionMode::Server { key_update, .. } = &mut self.mode {
             let pending = self.session.is_update_keys_pending();
@@ -1907,9 +2060,14 @@ impl<AppState: Send> Connection<AppState> {
             "Received ping"
         );
 
-        // Encode absolute expresslane counters for keepalive pongs if expresslane is ready
+        // Encode absolute expresslane counters for keepalive pongs if expresslane is ready,
+        // echoing the keepalive stamp of the ping
         let payload = if ping.id == wire::Ping::KEEPALIVE_ID {
-            self.encode_expresslane_metrics_payload()
+            let payload = self.encode_expresslane_metrics_payload();
+            match wire::KeepaliveStamp::split_from(ping.payload) {
+                (_, Some(stamp)) => stamp.append_to(payload),
+                (_, None) => payload,
+            }
         } else {
             Default::default()
         };
@@ -1938,8 +2096,14 @@ impl<AppState: Send> Connection<AppState> {
         );
 
         if pong.id == wire::Ping::KEEPALIVE_ID {
-            self.event(Event::KeepaliveReply);
-            self.check_expresslane_health(&pong.payload)?;
+            let (payload, stamp) = wire::KeepaliveStamp::split_from(pong.payload.clone());
+            if let Some(stamp) = stamp {
+                self.keepalive_estimator.reply(stamp, Instant::now());
+            }
+            self.event(Event::KeepaliveReply {
+                id: stamp.map(|stamp| KeepaliveId(stamp.sequence)),
+            });
+            self.check_expresslane_health(&payload)?;
         }
 
         if let Some(ref mut pmtud) = self.pmtud {
@@ -2428,7 +2592,9 @@ impl<AppState: Send> Connection<AppState> {
                 is_encoded,
             )
             .map_err(expresslane_encrypt_error)?;
-        self.activity.last_data_traffic_from_peer = Instant::now();
+        let now = Instant::now();
+        self.activity.last_data_traffic_from_peer = now;
+        self.activity.last_outside_data_sent = now;
 
         // `udp_send` coalesces into the per-connection `GsoBuffer`
         // when a batch has been opened by an upstream `gso_buf.open()`
@@ -2449,11 +2615,13 @@ impl<AppState: Send> Connection<AppState> {
             auth_handle,
             ip_pool,
             key_update,
+            server_config,
             ..
         } = &mut self.mode
         else {
             return Err(ConnectionError::InvalidMode);
         };
+        let server_config = server_config.clone();
 
         // Normally we would expect to be in `State::LinkUp` when
         // authenticating. However with aggressive connection mode we
@@ -2465,17 +2633,16 @@ impl<AppState: Send> Connection<AppState> {
             return Err(ConnectionError::InvalidState);
         }
 
-        let Some(ip_config) = ip_pool.alloc(&mut self.app_state) else {
-            self.send_auth_failure();
-            return Err(ConnectionError::NoAvailableClientIp);
-        };
-
         let Some(inside_io) = self.inside_io.as_ref() else {
             self.send_auth_failure();
             return Err(ConnectionError::InvalidInsideIo);
         };
 
-        match auth.authorize(&auth_request.auth_method, &mut self.app_state) {
+        match auth.authorize_with_site_subnets(
+            &auth_request.auth_method,
+            &auth_request.site_subnets,
+            &mut self.app_state,
+        ) {
             ServerAuthResult::Granted {
                 tunnel_protocol_version,
                 handle,
@@ -2484,6 +2651,14 @@ impl<AppState: Send> Connection<AppState> {
                     "Setting tunnel protocol version : {:?}",
                     tunnel_protocol_version
                 );
+
+                // Allocated once authorized, so that the pool may
+                // take the client's identity into account.
+                let Some(ip_config) = ip_pool.alloc(&mut self.app_state) else {
+                    self.send_auth_failure();
+                    return Err(ConnectionError::NoAvailableClientIp);
+                };
+
                 key_update.online();
 
                 let msg = wire::Frame::AuthSuccessWithConfigV4(wire::AuthSuccessWithConfigV4 {
@@ -2495,12 +2670,28 @@ impl<AppState: Send> Connection<AppState> {
                 });
 
                 if let Some(ref handle) = handle {
+                    let features = handle.features();
                     self.can_use_inside_pkt_encoding =
-                        handle.features().contains(&LightwayFeature::InsidePktCodec);
+                        features.contains(&LightwayFeature::InsidePktCodec);
+                    self.can_use_multipath = features.contains(&LightwayFeature::Multipath);
+
+                    // Installed once, auth requests are repeated while
+                    // online in aggressive mode.
+                    if let Some(acl) = handle.acl()
+                        && !matches!(self.state, State::Online)
+                    {
+                        self.inside_plugins
+                            .push(Box::new(AclPlugin::new(&acl, ip_config.server_ip)));
+                    }
                 }
 
                 *auth_handle = handle;
 
+                if let Some(data) = server_config {
+                    self.send_frame_or_queue(wire::Frame::ServerConfig(wire::ServerConfig {
+                        data,
+                    }))?;
+                }
                 self.send_frame_or_queue(msg)?;
 
                 if let Some(v) = tunnel_protocol_version {
@@ -2517,6 +2708,16 @@ impl<AppState: Send> Connection<AppState> {
         }
     }
 
+    fn handle_server_config(&mut self, sc: wire::ServerConfig) -> ConnectionResult<()> {
+        if !matches!(self.mode, ConnectionMode::Client { .. }) {
+            warn!("Ignoring ServerConfig");
+            return Ok(());
+        }
+        debug!(len = sc.data.len(), "Received server config");
+        self.event(Event::ServerConfig(sc.data));
+        Ok(())
+    }
+
     fn handle_auth_response(&mut self, cfg: AuthSuccessWithConfigV4) -> ConnectionResult<()> {
         info!(config = ?cfg, "Authentication succeeded");
 
@@ -2547,6 +2748,89 @@ impl<AppState: Send> Connection<AppState> {
         // Set connection state to Online
         self.set_state(State::Online)?;
 
+        self.request_multipath()?;
+
+        Ok(())
+    }
+
+    /// Ask the server for the multipath mode of the outside IO, if any
+    /// (Client only)
+    fn request_multipath(&mut self) -> ConnectionResult<()> {
+        let mode = self.session.io_cb().io.multipath_mode();
+        if mode == MultipathMode::Off || !self.connection_type.is_datagram() {
+            return Ok(());
+        }
+
+        debug!(?mode, "Requesting multipath");
+        self.multipath_requested = true;
+        self.send_frame_or_queue(wire::Frame::MultipathConfig(wire::MultipathConfig { mode }))
+    }
+
+    fn handle_multipath_config(&mut self, mc: wire::MultipathConfig) -> ConnectionResult<()> {
+        if !matches!(self.state, State::Online) {
+            warn!("Received MultipathConfig before state is Online");
+            return Ok(());
+        }
+
+        if !self.connection_type.is_datagram() {
+            warn!("Received MultipathConfig in TCP mode");
+            return Ok(());
+        }
+
+        match self.mode {
+            ConnectionMode::Server { .. } => {
+                let mode = if self.can_use_multipath {
+                    mc.mode
+                } else {
+                    warn!(
+                        requested = ?mc.mode,
+                        "Received MultipathConfig while connection has no authorization to use multipath"
+                    );
+                    MultipathMode::Off
+                };
+                self.multipath_mode = mode;
+                self.send_frame_or_queue(wire::Frame::MultipathConfig(wire::MultipathConfig {
+                    mode,
+                }))
+            }
+            ConnectionMode::Client { .. } => {
+                if !self.multipath_requested {
+                    return Ok(());
+                }
+                self.multipath_requested = false;
+
+                // Anything but the requested mode is a refusal
+                let requested = self.session.io_cb().io.multipath_mode();
+                self.multipath_mode = if mc.mode == requested {
+                    requested
+                } else {
+                    MultipathMode::Off
+                };
+                info!(mode = ?self.multipath_mode, "Multipath negotiated");
+                self.session.io_cb_mut().set_multipath(self.multipath_mode);
+                Ok(())
+            }
+        }
+    }
+
+    fn handle_path_challenge(&mut self, challenge: wire::PathChallenge) -> ConnectionResult<()> {
+        if !matches!(self.mode, ConnectionMode::Client { .. })
+            || self.multipath_mode == MultipathMode::Off
+        {
+            warn!("Ignoring PathChallenge");
+            return Ok(());
+        }
+
+        self.send_frame_or_queue(wire::Frame::PathResponse(challenge))
+    }
+
+    fn handle_path_response(&mut self, response: wire::PathChallenge) -> ConnectionResult<()> {
+        if !matches!(self.mode, ConnectionMode::Server { .. }) {
+            warn!("Ignoring PathResponse");
+            return Ok(());
+        }
+
+        self.session.io_cb().io.peer_path_validated(response.token);
         Ok(())
     }
 
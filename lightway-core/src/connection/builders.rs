@@ -1,6 +1,7 @@
 use std::{num::NonZeroU16, sync::Arc};
 
 use bytes::{Bytes, BytesMut};
+use ipnet::Ipv4Net;
 use rand::distr::{Distribution, StandardUniform};
 use thiserror::Error;
 
@@ -8,8 +9,8 @@ use thiserror::Error;
 use crate::KeyShare;
 use crate::{
     AuthMethod, BuilderPredicates, ClientContext, Connection, ConnectionType, MAX_OUTSIDE_MTU,
-    MIN_OUTSIDE_MTU, OutsideIOSendCallbackArg, PacketDecoderType, PacketEncoderType, ServerContext,
-    ServerIpPoolArg, Version,
+    MAX_SITE_SUBNETS, MIN_OUTSIDE_MTU, OutsideIOSendCallbackArg, PacketDecoderType,
+    PacketEncoderType, ServerContext, ServerIpPoolArg, Version,
     connection::{EventCallbackArg, dplpmtud, fragment_map::FragmentMap, key_update},
     context::ServerAuthArg,
     max_dtls_outside_mtu,
@@ -26,6 +27,9 @@ pub enum ConnectionBuilderError {
     /// No auth method was provided for client connection.
     #[error("An Auth method is required")]
     AuthRequired,
+    /// More site subnets than fit in an auth request
+    #[error("At most {MAX_SITE_SUBNETS} site subnets are supported")]
+    TooManySiteSubnets,
     /// Protocol version
     #[error("Connection uses unsupported protocol version {0}")]
     UnsupportedProtocolVersion(Version),
@@ -41,6 +45,9 @@ pub enum ConnectionBuilderError {
     /// Failed to connect the stream
     #[error("Connection Error: {0}")]
     FailedConnect(#[from] ConnectionError),
+    /// The TLS library does not support session tickets
+    #[error("Session tickets are not supported by this TLS library")]
+    SessionTicketsUnsupported,
 }
 
 type ConnectionBuilderResult<T> = Result<T, ConnectionBuilderError>;
@@ -52,6 +59,7 @@ pub struct ClientConnectionBuilder<AppState> {
     outside_mtu: usize,
     pmtud_base_mtu: Option<u16>,
     auth_method: Option<AuthMethod>,
+    site_subnets: Vec<Ipv4Net>,
     session_config: crate::tls::SessionConfig<super::TlsIOAdapter>,
     event_cb: Option<EventCallbackArg>,
     max_fragment_map_entries: NonZeroU16,
@@ -84,6 +92,7 @@ impl<AppState: Send + 'static> ClientConnectionBuilder<AppState> {
             send_buf: super::io_adapter::SendBuffer::new(outside_mtu),
             io: outside_io,
             session_id: SessionId::EMPTY,
+            multipath: Default::default(),
             outside_plugins: outside_plugins.clone(),
             #[cfg(target_os = "linux")]
             gso_buf: super::io_adapter::GsoBuffer::default(),
@@ -108,6 +117,7 @@ impl<AppState: Send + 'static> ClientConnectionBuilder<AppState> {
             outside_mtu,
             session_config,
             auth_method: None,
+            site_subnets: Vec::new(),
             event_cb: None,
             max_fragment_map_entries: FragmentMap::DEFAULT_MAX_ENTRIES,
             pmtud_timer: None,
@@ -163,6 +173,16 @@ impl<AppState: Send + 'static> ClientConnectionBuilder<AppState> {
         self.with_auth(auth_method)
     }
 
+    /// Advertise `site_subnets` behind the client when authenticating,
+    /// at most [`MAX_SITE_SUBNETS`]. The server only routes
+    /// those its auth backend allows.
+    pub fn with_site_subnets(self, site_subnets: Vec<Ipv4Net>) -> Self {
+        Self {
+            site_subnets,
+            ..self
+        }
+    }
+
     /// Sets the callback to notify events
     pub fn with_event_cb(self, event_cb: EventCallbackArg) -> Self {
         Self {
@@ -198,6 +218,29 @@ impl<AppState: Send + 'static> ClientConnectionBuilder<AppState> {
         }
     }
 
+    /// Resume the TLS session `ticket` was issued for, see
+    /// [`crate::Event::SessionTicket`]. Falls back to a full handshake
+    /// when the server does not accept it.
+    ///
+    /// Only offer a ticket to the server (and domain name) it came
+    /// from: a resumed session skips certificate verification. Fails
+    /// with wolfSSL, which does not support session tickets, see
+    /// [`crate::tls::SESSION_TICKETS_SUPPORTED`].
+    pub fn with_session_ticket(self, ticket: &[u8]) -> ConnectionBuilderResult<Self> {
+        #[cfg(boringssl)]
+        {
+            Ok(Self {
+                session_config: self.session_config.with_session_ticket(ticket),
+                ..self
+            })
+        }
+        #[cfg(wolfssl)]
+        {
+            let _ = ticket;
+            Err(ConnectionBuilderError::SessionTicketsUnsupported)
+        }
+    }
+
     /// Sets the maximum number of in-progress fragmented packets to support.
     pub fn with_fragment_map_entries(self, max_fragment_map_entries: NonZeroU16) -> Self {
         Self {
@@ -239,6 +282,9 @@ impl<AppState: Send + 'static> ClientConnectionBuilder<AppState> {
         let auth_method = self
             .auth_method
             .ok_or(ConnectionBuilderError::AuthRequired)?;
+        if self.site_subnets.len() > MAX_SITE_SUBNETS {
+            return Err(ConnectionBuilderError::TooManySiteSubnets);
+        }
 
         let session = self.ctx.tls_ctx.new_session(self.session_config)?;
 
@@ -255,6 +301,7 @@ impl<AppState: Send + 'static> ClientConnectionBuilder<AppState> {
             session_id: SessionId::EMPTY,
             mode: ConnectionMode::Client {
                 auth_method,
+                site_subnets: self.site_subnets,
                 ip_config_cb: self.ctx.ip_config,
             },
             rng: self.ctx.rng.clone(),
@@ -320,6 +367,7 @@ impl<'a, AppState: Send + 'static> ServerConnectionBuilder<'a, AppState> {
             send_buf: super::io_adapter::SendBuffer::new(outside_mtu),
             io: outside_io,
             session_id,
+            multipath: Default::default(),
             outside_plugins: outside_plugins.clone(),
             #[cfg(target_os = "linux")]
             gso_buf: super::io_adapter::GsoBuffer::default(),
@@ -396,6 +444,7 @@ impl<'a, AppState: Send + 'static> ServerConnectionBuilder<'a, AppState> {
                 ip_pool: self.ip_pool,
                 key_update: key_update::State::new(self.ctx.key_update_interval),
                 pending_session_id: None,
+                server_config: self.ctx.server_config.clone(),
             },
             rng: self.ctx.rng.clone(),
             outside_mtu: MAX_OUTSIDE_MTU,
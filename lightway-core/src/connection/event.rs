@@ -1,4 +1,6 @@
-use crate::connection::ExpresslaneState;
+use bytes::Bytes;
+
+use crate::connection::{ExpresslaneState, KeepaliveId};
 use crate::{SessionId, State};
 
 /// A lightway event
@@ -7,7 +9,11 @@ pub enum Event {
     /// The connection state has changed
     StateChanged(State),
     /// A reply was received after a [`crate::Connection::keepalive()`]
-    KeepaliveReply,
+    KeepaliveReply {
+        /// The keepalive answered, `None` when the peer does not echo
+        /// keepalive stamps
+        id: Option<KeepaliveId>,
+    },
     /// A new session id has been generated and will be used in
     /// outgoing packets. The old session id is still active until
     /// the peer acknowledges the new one.
@@ -52,4 +58,21 @@ pub enum Event {
     },
     /// Expresslane state changed
     ExpresslaneStateChanged(ExpresslaneState),
+    /// Configuration data pushed by the server (see
+    /// [`crate::ServerContextBuilder::with_server_config`]).
+    ///
+    /// Sent ahead of the authentication success reply, so it is
+    /// delivered before [`Event::StateChanged`] to [`State::Online`].
+    ///
+    /// Client connections only
+    ServerConfig(Bytes),
+    /// The server sent a TLS session ticket. Pass the latest one to
+    /// [`crate::ClientConnectionBuilder::with_session_ticket`] when
+    /// reconnecting to the same server to resume the session.
+    ///
+    /// The ticket carries the session's resumption secret, keep it
+    /// as private as a key.
+    ///
+    /// Client connections only
+    SessionTicket(Bytes),
 }
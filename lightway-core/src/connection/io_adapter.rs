@@ -199,6 +199,10 @@ pub(crate) struct TlsIOAdapter {
 
     pub(crate) session_id: wire::SessionId,
 
+    /// Multipath mode put in the header of each packet sent, once
+    /// granted by the peer
+    pub(crate) multipath: wire::MultipathMode,
+
     /// Plugins to act while egressing outside packet
     pub(crate) outside_plugins: Arc<PluginList>,
 }
@@ -215,6 +219,10 @@ impl TlsIOAdapter {
         self.session_id = session_id;
     }
 
+    pub(crate) fn set_multipath(&mut self, multipath: wire::MultipathMode) {
+        self.multipath = multipath;
+    }
+
     /// Force enable the IPv4 DF bit is set for all packets.
     pub(crate) fn enable_pmtud_probe(&self) {
         match self.io.enable_pmtud_probe() {
@@ -260,6 +268,7 @@ impl TlsIOAdapter {
             version: self.protocol_version,
             aggressive_mode: false,
             expresslane_data,
+            multipath: self.multipath,
             session: self.session_id,
         };
 
@@ -356,6 +365,7 @@ impl TlsIOAdapter {
             version: self.protocol_version,
             aggressive_mode: false,
             expresslane_data,
+            multipath: self.multipath,
             session: self.session_id,
         };
         let mut hdr_buf = BytesMut::with_capacity(wire::Header::WIRE_SIZE);
@@ -608,6 +618,7 @@ mod tests {
             send_buf: SendBuffer::new(MAX_OUTSIDE_MTU),
             io,
             session_id: SessionId::from_const(*b"\xde\xad\xbe\xef\xde\xad\xbe\xef"),
+            multipath: Default::default(),
             outside_plugins: outside_plugins.into(),
             #[cfg(target_os = "linux")]
             gso_buf: GsoBuffer::default(),
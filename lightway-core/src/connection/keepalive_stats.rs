@@ -0,0 +1,231 @@
+//! Round trip time, jitter and loss measured from keepalives.
+//!
+//! Each keepalive [`wire::Ping`] carries a [`wire::KeepaliveStamp`]
+//! which the peer echoes in its [`wire::Pong`]. RTT smoothing follows
+//! [RFC 6298](https://www.rfc-editor.org/rfc/rfc6298).
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::wire;
+
+/// Number of most recent keepalives loss is measured over
+const LOSS_WINDOW: usize = 32;
+
+/// Clock granularity, the least RTT variation accounted for in
+/// [`KeepaliveStats::rto`]
+const CLOCK_GRANULARITY: Duration = Duration::from_millis(1);
+
+/// Keepalive statistics of a connection, see
+/// [`crate::Connection::keepalive_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct KeepaliveStats {
+    /// Smoothed round trip time, `None` until the first reply
+    pub srtt: Option<Duration>,
+    /// Round trip time variation, an estimate of the jitter
+    pub rttvar: Duration,
+    /// Fraction of the recent keepalives which went unanswered
+    pub loss: f64,
+    /// Keepalives sent
+    pub sent: u64,
+    /// Replies received
+    pub received: u64,
+}
+
+impl KeepaliveStats {
+    /// Retransmission timeout in the manner of TCP, `srtt + 4 * rttvar`.
+    /// `None` until the first reply.
+    pub fn rto(&self) -> Option<Duration> {
+        self.srtt
+            .map(|srtt| srtt + (self.rttvar * 4).max(CLOCK_GRANULARITY))
+    }
+}
+
+/// Identifies a keepalive sent by [`crate::Connection::keepalive`], and
+/// the reply to it in [`crate::Event::KeepaliveReply`]. This is the
+/// sequence number of its [`wire::KeepaliveStamp`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct KeepaliveId(pub u32);
+
+/// Stamps outgoing keepalives and updates [`KeepaliveStats`] from the
+/// echoed stamps.
+pub(crate) struct KeepaliveEstimator {
+    /// Stamp timestamps are milliseconds since this
+    epoch: Instant,
+    next_sequence: u32,
+    /// Sequence numbers of the most recent keepalives, and whether they
+    /// were answered
+    window: VecDeque<(u32, bool)>,
+    stats: KeepaliveStats,
+}
+
+impl KeepaliveEstimator {
+    pub(crate) fn new(epoch: Instant) -> Self {
+        Self {
+            epoch,
+            next_sequence: 0,
+            window: VecDeque::with_capacity(LOSS_WINDOW),
+            stats: KeepaliveStats::default(),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> KeepaliveStats {
+        self.stats
+    }
+
+    /// Stamp for a keepalive sent at `now`.
+    pub(crate) fn stamp(&mut self, now: Instant) -> wire::KeepaliveStamp {
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+
+        if self.window.len() == LOSS_WINDOW {
+            self.window.pop_front();
+        }
+        self.window.push_back((sequence, false));
+        self.stats.sent += 1;
+
+        wire::KeepaliveStamp {
+            sequence,
+            timestamp: self.millis(now),
+        }
+    }
+
+    /// Account for the reply to the keepalive `stamp`, received at `now`.
+    /// Replies to keepalives not in the window, or already answered, are
+    /// ignored.
+    pub(crate) fn reply(&mut self, stamp: wire::KeepaliveStamp, now: Instant) {
+        let Some(index) = self
+            .window
+            .iter()
+            .position(|(sequence, answered)| *sequence == stamp.sequence && !answered)
+        else {
+            return;
+        };
+        self.window[index].1 = true;
+        self.stats.received += 1;
+
+        let rtt = Duration::from_millis(self.millis(now).wrapping_sub(stamp.timestamp).into());
+        match self.stats.srtt {
+            None => {
+                self.stats.srtt = Some(rtt);
+                self.stats.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                self.stats.rttvar = (self.stats.rttvar * 3 + srtt.abs_diff(rtt)) / 4;
+                self.stats.srtt = Some((srtt * 7 + rtt) / 8);
+            }
+        }
+
+        // Keepalives sent before an answered one are lost if unanswered,
+        // later ones may still be in flight
+        let settled = &self.window.make_contiguous()[..=index];
+        let lost = settled.iter().filter(|(_, answered)| !answered).count();
+        self.stats.loss = lost as f64 / settled.len() as f64;
+    }
+
+    fn millis(&self, now: Instant) -> u32 {
+        // Wraps after 49 days, as does the difference taken of it
+        now.duration_since(self.epoch).as_millis() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn no_reply_no_rtt() {
+        let now = Instant::now();
+        let mut estimator = KeepaliveEstimator::new(now);
+        estimator.stamp(now);
+        assert_eq!(estimator.stats().srtt, None);
+        assert_eq!(estimator.stats().rto(), None);
+        assert_eq!(estimator.stats().sent, 1);
+    }
+
+    #[test]
+    fn first_reply_sets_rtt() {
+        let now = Instant::now();
+        let mut estimator = KeepaliveEstimator::new(now);
+        let stamp = estimator.stamp(now);
+        estimator.reply(stamp, now + ms(100));
+
+        let stats = estimator.stats();
+        assert_eq!(stats.srtt, Some(ms(100)));
+        assert_eq!(stats.rttvar, ms(50));
+        assert_eq!(stats.rto(), Some(ms(300)));
+        assert_eq!(stats.received, 1);
+    }
+
+    #[test]
+    fn later_replies_are_smoothed() {
+        let now = Instant::now();
+        let mut estimator = KeepaliveEstimator::new(now);
+        let stamp = estimator.stamp(now);
+        estimator.reply(stamp, now + ms(100));
+        let stamp = estimator.stamp(now + ms(1000));
+        estimator.reply(stamp, now + ms(1180));
+
+        let stats = estimator.stats();
+        // 7/8 * 100 + 1/8 * 180
+        assert_eq!(stats.srtt, Some(ms(110)));
+        // 3/4 * 50 + 1/4 * 80
+        assert_eq!(stats.rttvar, Duration::from_micros(57_500));
+    }
+
+    #[test]
+    fn duplicate_and_unknown_replies_are_ignored() {
+        let now = Instant::now();
+        let mut estimator = KeepaliveEstimator::new(now);
+        let stamp = estimator.stamp(now);
+        estimator.reply(stamp, now + ms(100));
+        estimator.reply(stamp, now + ms(500));
+        estimator.reply(
+            wire::KeepaliveStamp {
+                sequence: 7,
+                timestamp: 0,
+            },
+            now + ms(500),
+        );
+
+        let stats = estimator.stats();
+        assert_eq!(stats.srtt, Some(ms(100)));
+        assert_eq!(stats.received, 1);
+    }
+
+    #[test_case(&[true, true, true, true] => 0.0; "none lost")]
+    #[test_case(&[false, true, false, true] => 0.5; "half lost")]
+    #[test_case(&[false, false, false, true] => 0.75; "most lost")]
+    #[test_case(&[true, false, false, false] => 0.0; "in flight not lost")]
+    fn loss(answered: &[bool]) -> f64 {
+        let now = Instant::now();
+        let mut estimator = KeepaliveEstimator::new(now);
+        let stamps: Vec<_> = answered.iter().map(|_| estimator.stamp(now)).collect();
+        for (stamp, answered) in stamps.into_iter().zip(answered) {
+            if *answered {
+                estimator.reply(stamp, now + ms(10));
+            }
+        }
+        estimator.stats().loss
+    }
+
+    #[test]
+    fn loss_window_slides() {
+        let now = Instant::now();
+        let mut estimator = KeepaliveEstimator::new(now);
+        // Lost keepalives fall out of the window
+        for _ in 0..LOSS_WINDOW {
+            estimator.stamp(now);
+        }
+        for _ in 0..LOSS_WINDOW {
+            let stamp = estimator.stamp(now);
+            estimator.reply(stamp, now + ms(10));
+        }
+        assert_eq!(estimator.stats().loss, 0.0);
+    }
+}
@@ -1,6 +1,8 @@
 pub mod ip_pool;
 mod server_auth;
+mod session_ticket;
 
+use bytes::Bytes;
 use std::sync::{Arc, Mutex};
 use thiserror::Error;
 
@@ -18,6 +20,7 @@ use crate::{
     wire::{self, ExpresslaneConfig},
 };
 pub use server_auth::{ServerAuth, ServerAuthArg, ServerAuthHandle, ServerAuthResult};
+pub use session_ticket::SessionTicketKey;
 
 /// An error while building a [`ClientContext`] via [`ClientContextBuilder`]
 /// or a [`ServerContext`] via [`ServerContextBuilder`].
@@ -47,7 +50,7 @@ pub enum ContextBuilderError {
 type ContextBuilderResult<T> = Result<T, ContextBuilderError>;
 
 /// The type of connection used by a Lightway context
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ConnectionType {
     /// Stream mode (i.e. TCP)
     Stream,
@@ -179,6 +182,11 @@ impl<AppState> ClientContextBuilder<AppState> {
             .with_root_certificate(root_ca)?
             .with_cipher_list(Cipher::default().as_cipher_list(connection_type))?;
 
+        // Collect the session tickets servers send, see
+        // `Event::SessionTicket`. wolfSSL builds never receive any.
+        #[cfg(boringssl)]
+        let tls_ctx = tls_ctx.with_session_resumption()?;
+
         Ok(Self {
             tls_ctx,
             connection_type,
@@ -334,6 +342,14 @@ pub enum ContextError {
     /// Packet parsing error occurred
     #[error("Packet Error: {0}")]
     PacketError(#[from] OutsidePacketError),
+
+    /// No session ticket key was configured
+    #[error("Session tickets are not enabled")]
+    SessionTicketsDisabled,
+
+    /// A TLS error occurred
+    #[error("TLS Error: {0}")]
+    Tls(#[from] crate::tls::Error),
 }
 
 /// The core Lightway Server-side context.
@@ -354,6 +370,8 @@ pub struct ServerContext<AppState = ()> {
     pub(crate) expresslane_cb: Option<ExpresslaneCbType<AppState>>,
     pub(crate) expresslane_metrics: Option<ExpresslaneMetricsType>,
     pub(crate) expresslane_keys_rotation_interval: std::time::Duration,
+    pub(crate) server_config: Option<Bytes>,
+    pub(crate) session_tickets: bool,
 }
 
 impl<AppState: Send + 'static> ServerContext<AppState> {
@@ -367,6 +385,25 @@ impl<AppState: Send + 'static> ServerContext<AppState> {
         self.supported_protocol_versions.maximum() == v
     }
 
+    /// Issue new session tickets under `key`. Tickets issued under the
+    /// key it replaces still resume until the next rotation, so rotate
+    /// at most once per ticket lifetime (2 days).
+    ///
+    /// Returns false when `key` is already in use. Fails unless the
+    /// context was built [`ServerContextBuilder::with_session_ticket_key`].
+    pub fn rotate_session_ticket_key(&self, key: &SessionTicketKey) -> Result<bool, ContextError> {
+        if !self.session_tickets {
+            return Err(ContextError::SessionTicketsDisabled);
+        }
+        #[cfg(boringssl)]
+        return Ok(self.tls_ctx.rotate_session_ticket_key(key.as_bytes())?);
+        #[cfg(wolfssl)]
+        {
+            let _ = key;
+            Err(ContextError::SessionTicketsDisabled)
+        }
+    }
+
     /// Parse raw `OutsidePacket::Wire` to `TcpFrame` or `UdpFrame`
     ///
     /// Usage:
@@ -412,6 +449,8 @@ pub struct ServerContextBuilder<AppState> {
     expresslane_cb: Option<ExpresslaneCbType<AppState>>,
     expresslane_metrics: Option<ExpresslaneMetricsType>,
     expresslane_keys_rotation_interval: std::time::Duration,
+    server_config: Option<Bytes>,
+    session_tickets: bool,
 }
 
 /// server curves when PQC is not enabled, in decreasing order of preference.
@@ -477,6 +516,8 @@ impl<AppState> ServerContextBuilder<AppState> {
             expresslane_cb: None,
             expresslane_metrics: None,
             expresslane_keys_rotation_interval: DEFAULT_EXPRESSLANE_KEYS_ROTATION_INTERVAL,
+            server_config: None,
+            session_tickets: false,
         })
     }
 
@@ -555,6 +596,52 @@ impl<AppState> ServerContextBuilder<AppState> {
         }
     }
 
+    /// Sets configuration data pushed to every client in a
+    /// `ServerConfig` frame, sent just before the authentication
+    /// success reply. The payload is opaque to Lightway, clients
+    /// receive it as [`crate::Event::ServerConfig`].
+    pub fn with_server_config(self, data: Bytes) -> ContextBuilderResult<Self> {
+        if data.len() > wire::ServerConfig::MAX_SERVER_CONFIG_BYTES {
+            return Err(ContextBuilderError::InvalidParameter(format!(
+                "Server config of {} bytes exceeds maximum of {}",
+                data.len(),
+                wire::ServerConfig::MAX_SERVER_CONFIG_BYTES
+            )));
+        }
+        Ok(Self {
+            server_config: Some(data),
+            ..self
+        })
+    }
+
+    /// Issue TLS session tickets encrypted under `key`, letting clients
+    /// resume with an abbreviated handshake (see
+    /// [`crate::ClientConnectionBuilder::with_session_ticket`]). Servers
+    /// sharing the key accept each other's tickets, rotate it with
+    /// [`ServerContext::rotate_session_ticket_key`]. Early data (0-RTT)
+    /// is never accepted.
+    ///
+    /// Without a key no tickets are issued. Only supported with
+    /// BoringSSL: wolfSSL builds fail here rather than silently not
+    /// resuming.
+    pub fn with_session_ticket_key(self, key: &SessionTicketKey) -> ContextBuilderResult<Self> {
+        #[cfg(boringssl)]
+        {
+            Ok(Self {
+                tls_ctx: self.tls_ctx.with_session_ticket_key(key.as_bytes())?,
+                session_tickets: true,
+                ..self
+            })
+        }
+        #[cfg(wolfssl)]
+        {
+            let _ = key;
+            Err(ContextBuilderError::InvalidParameter(
+                "Session tickets are not supported with wolfSSL".to_string(),
+            ))
+        }
+    }
+
     /// Enable Post Quantum Crypto
     #[cfg(feature = "postquantum")]
     pub fn enable_pq_crypto(self) -> ContextBuilderResult<Self> {
@@ -587,6 +674,8 @@ impl<AppState> ServerContextBuilder<AppState> {
             expresslane_cb: self.expresslane_cb,
             expresslane_metrics: self.expresslane_metrics,
             expresslane_keys_rotation_interval: self.expresslane_keys_rotation_interval,
+            server_config: self.server_config,
+            session_tickets: self.session_tickets,
         })
     }
 }
@@ -25,10 +25,20 @@ impl TryFrom<AuthSuccessWithConfigV4> for InsideIpConfig {
     }
 }
 
+/// Inside IP requested for a client by its
+/// [`ServerAuthHandle`](crate::ServerAuthHandle)
+#[derive(Clone, Debug, PartialEq)]
+pub enum InsideIpRequest {
+    /// This exact IP
+    Ip(Ipv4Addr),
+    /// Any IP of the pool with this name
+    Pool(String),
+}
+
 /// Server Ip pool. Servers should have a pool of IPs to support
 /// multiple clients.
 pub trait ServerIpPool<AppState: Send = ()> {
-    /// Allocate IP from free pool
+    /// Allocate IP from free pool, once the client has been authorized
     ///
     /// If the pool is exhausted, this method can return None.
     /// And Lightway core will disconnect the new client
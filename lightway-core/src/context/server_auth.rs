@@ -1,9 +1,10 @@
 use std::{collections::HashSet, sync::Arc};
 
 use bytes::Bytes;
+use ipnet::Ipv4Net;
 use tracing::info;
 
-use crate::{LightwayFeature, Version, wire};
+use crate::{Acl, InsideIpRequest, LightwayFeature, Version, wire};
 
 /// A handle onto a successful auth result.
 pub trait ServerAuthHandle: std::fmt::Debug {
@@ -12,6 +13,27 @@ pub trait ServerAuthHandle: std::fmt::Debug {
     fn expired(&self) -> bool;
     /// All features available to this connection.
     fn features(&self) -> HashSet<LightwayFeature>;
+    /// Stable identity of the authenticated client, e.g. a user name
+    /// or token subject, for the server to remember it by across
+    /// connections.
+    fn identity(&self) -> Option<&str> {
+        None
+    }
+    /// Inside IP to assign to the client instead of any IP of the
+    /// server's pool. The client is rejected if it cannot be assigned.
+    fn inside_ip(&self) -> Option<InsideIpRequest> {
+        None
+    }
+    /// Flows the client can open. All flows are allowed if `None`.
+    fn acl(&self) -> Option<Acl> {
+        None
+    }
+    /// Subnets the client may have behind it, e.g. a branch office
+    /// network. Of the subnets the client advertises, those within one
+    /// of these are routed to it in addition to its inside IP.
+    fn subnets(&self) -> Vec<Ipv4Net> {
+        Vec::new()
+    }
 }
 
 /// Result of [`ServerAuth`] `authorize_*` methods.
@@ -49,6 +71,19 @@ pub trait ServerAuth<T> {
         }
     }
 
+    /// Authorize the connection based on `method`, for a client
+    /// advertising `site_subnets` behind it. Servers routing subnets to
+    /// clients override this to only route the advertised subnets the
+    /// client is allowed, by default the subnets are ignored.
+    fn authorize_with_site_subnets(
+        &self,
+        method: &wire::AuthMethod,
+        _site_subnets: &[Ipv4Net],
+        app_state: &mut T,
+    ) -> ServerAuthResult {
+        self.authorize(method, app_state)
+    }
+
     /// Authorize the given `user` with `password`.
     fn authorize_user_password(
         &self,
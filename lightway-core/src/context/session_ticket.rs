@@ -0,0 +1,103 @@
+use std::str::FromStr;
+
+use rand::RngExt;
+
+use super::ContextBuilderError;
+
+/// The key a server encrypts session tickets with, see
+/// [`crate::ServerContextBuilder::with_session_ticket_key`].
+///
+/// Servers using the same key resume each other's sessions.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SessionTicketKey([u8; Self::LEN]);
+
+#[cfg(boringssl)]
+const _: () = assert!(SessionTicketKey::LEN == crate::tls::SESSION_TICKET_KEY_LEN);
+
+impl SessionTicketKey {
+    /// Length of a key in bytes
+    pub const LEN: usize = 48;
+
+    /// Key from `LEN` bytes of key material
+    pub fn new(key: &[u8]) -> Result<Self, ContextBuilderError> {
+        let key = key.try_into().map_err(|_| {
+            ContextBuilderError::InvalidParameter(format!(
+                "Session ticket key must be {} bytes, got {}",
+                Self::LEN,
+                key.len()
+            ))
+        })?;
+        Ok(Self(key))
+    }
+
+    /// Random key, for a server not sharing its tickets
+    pub fn generate() -> Self {
+        let mut key = [0; Self::LEN];
+        rand::rng().fill(&mut key);
+        Self(key)
+    }
+
+    /// The key material
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Parses `LEN` bytes of hex, e.g. the output of `openssl rand -hex 48`.
+impl FromStr for SessionTicketKey {
+    type Err = ContextBuilderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let invalid = || {
+            ContextBuilderError::InvalidParameter(format!(
+                "Session ticket key must be {} hex digits",
+                Self::LEN * 2
+            ))
+        };
+        if !s.is_ascii() || s.len() != Self::LEN * 2 {
+            return Err(invalid());
+        }
+        let key = (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::new(&key)
+    }
+}
+
+impl std::fmt::Debug for SessionTicketKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SessionTicketKey(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test]
+    fn from_hex() {
+        let key: SessionTicketKey = format!("{}\n", "0a".repeat(48)).parse().unwrap();
+        assert_eq!(key.as_bytes(), &[10; 48]);
+    }
+
+    #[test_case(""; "empty")]
+    #[test_case(&"0a".repeat(47); "short")]
+    #[test_case(&"0a".repeat(49); "long")]
+    #[test_case(&"zz".repeat(48); "not hex")]
+    #[test_case(&"é".repeat(48); "not ascii")]
+    fn invalid_hex(s: &str) {
+        assert!(matches!(
+            s.parse::<SessionTicketKey>(),
+            Err(ContextBuilderError::InvalidParameter(_))
+        ));
+    }
+
+    #[test]
+    fn debug_hides_key() {
+        let key = SessionTicketKey::generate();
+        assert_eq!(format!("{key:?}"), "SessionTicketKey(..)");
+    }
+}
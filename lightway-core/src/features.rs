@@ -3,4 +3,7 @@
 pub enum LightwayFeature {
     /// Whether the server will accept EncodingRequests.
     InsidePktCodec,
+    /// Whether the server grants multipath requests, see
+    /// [`crate::MultipathMode`].
+    Multipath,
 }
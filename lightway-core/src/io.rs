@@ -1,6 +1,6 @@
-use crate::tls::IOCallbackResult;
+use crate::{MultipathMode, tls::IOCallbackResult};
 use bytes::BytesMut;
-use std::{io::IoSlice, net::SocketAddr, sync::Arc};
+use std::{io::IoSlice, net::SocketAddr, sync::Arc, time::Duration};
 
 /// Maximum number of packets handled in a single batched IO call —
 /// covers both inbound (recvmmsg-style) reads and outbound
@@ -30,6 +30,18 @@ pub trait InsideIOSendCallback<AppState> {
 /// Convenience type to use as function arguments
 pub type InsideIOSendCallbackArg<AppState> = Arc<dyn InsideIOSendCallback<AppState> + Send + Sync>;
 
+/// What a packet of a multipath session received from an address of
+/// the peer makes of it, see [`OutsideIOSendCallback::use_peer_path`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PeerPath {
+    /// The first address or a path already in use
+    Known,
+    /// An address which answered its challenge, now added as a path
+    Added,
+    /// An address not yet proven to be the peer's, to be challenged
+    Unvalidated,
+}
+
 /// Application provided callback used to send outside data.
 pub trait OutsideIOSendCallback {
     /// Called when Lightway wishes to send some outside data
@@ -51,6 +63,49 @@ pub trait OutsideIOSendCallback {
         self.peer_addr()
     }
 
+    /// How the packets sent are spread over several paths to the peer
+    /// (UDP only), signalled to the peer in every packet header.
+    fn multipath_mode(&self) -> MultipathMode {
+        MultipathMode::Off
+    }
+
+    /// A packet of a `mode` multipath session was received from the
+    /// peer's `addr` (UDP only). A known or newly added `addr` becomes
+    /// the path [`MultipathMode::Scheduled`] sends go to. Other
+    /// addresses are only added once they answered the challenge of
+    /// [`Self::challenge_peer_path`].
+    fn use_peer_path(&self, _addr: SocketAddr, _mode: MultipathMode) -> PeerPath {
+        // Default is to ignore if not supported.
+        PeerPath::Known
+    }
+
+    /// Expect `token` back from the peer to add `addr` as a path.
+    /// Returns whether to send the challenge, which is not the case
+    /// when `addr` was challenged recently or too many addresses are.
+    fn challenge_peer_path(&self, _addr: SocketAddr, _token: u64) -> bool {
+        false
+    }
+
+    /// The peer answered the challenge with `token`, see
+    /// [`Self::challenge_peer_path`]
+    fn peer_path_validated(&self, _token: u64) {}
+
+    /// Send only to the peer's `addr` until called again with `None`,
+    /// whatever the multipath mode.
+    fn pin_peer_path(&self, _addr: Option<SocketAddr>) {}
+
+    /// The peer's paths added by [`Self::use_peer_path`], besides
+    /// [`Self::peer_addr`]
+    fn peer_paths(&self) -> Vec<SocketAddr> {
+        Vec::new()
+    }
+
+    /// Remove the paths nothing was received from for `max_idle`,
+    /// returning their addresses
+    fn expire_peer_paths(&self, _max_idle: Duration) -> Vec<SocketAddr> {
+        Vec::new()
+    }
+
     /// Force enable the IPv4 DF bit is set for all packets (UDP only).
     fn enable_pmtud_probe(&self) -> std::io::Result<()> {
         Err(std::io::Error::other("pmtud probe not supported"))
@@ -2,6 +2,7 @@
 
 #![warn(missing_docs)]
 
+mod acl;
 mod borrowed_bytesmut;
 mod builder_predicates;
 mod cipher;
@@ -27,25 +28,30 @@ mod wire;
 pub use tls::{IOCallbackResult, ProtocolVersion, RootCertificate, Secret};
 
 // Reexport our own types
+pub use acl::{Acl, AclProtocol, AclRule};
 pub use builder_predicates::BuilderPredicates;
 pub use cipher::Cipher;
 pub use connection::{
     ClientConnectionBuilder, Connection, ConnectionActivity, ConnectionBuilderError,
     ConnectionError, ConnectionResult, Event, EventCallback, EventCallbackArg, ExpresslaneState,
-    ServerConnectionBuilder, State, dplpmtud::Timer as DplpmtudTimer, expresslane::*,
+    KeepaliveId, KeepaliveStats, ServerConnectionBuilder, State, dplpmtud::Timer as DplpmtudTimer,
+    expresslane::*,
 };
 pub use context::{
     ClientContext, ClientContextBuilder, ConnectionType, ContextError, ExpresslaneTickData,
     ScheduleTickCb, ServerAuth, ServerAuthArg, ServerAuthHandle, ServerAuthResult, ServerContext,
-    ServerContextBuilder, TickType,
-    ip_pool::{ClientIpConfig, ClientIpConfigArg, InsideIpConfig, ServerIpPool, ServerIpPoolArg},
+    ServerContextBuilder, SessionTicketKey, TickType,
+    ip_pool::{
+        ClientIpConfig, ClientIpConfigArg, InsideIpConfig, InsideIpRequest, ServerIpPool,
+        ServerIpPoolArg,
+    },
 };
 pub use features::LightwayFeature;
 #[cfg(any(target_os = "linux", test))]
 pub use gso::VirtioNetHdr;
 pub use io::{
     InsideIOSendCallback, InsideIOSendCallbackArg, MAX_IO_BATCH_SIZE, OutsideIOSendCallback,
-    OutsideIOSendCallbackArg,
+    OutsideIOSendCallbackArg, PeerPath,
 };
 #[cfg(feature = "postquantum")]
 pub use keyshare::KeyShare;
@@ -66,7 +72,8 @@ pub use utils::{
 };
 pub use version::Version;
 pub use wire::{
-    AuthMethod, ExpresslaneError, ExpresslaneKey, ExpresslaneVersion, Header, SessionId,
+    AuthMethod, ExpresslaneError, ExpresslaneKey, ExpresslaneVersion, Header, MAX_SITE_SUBNETS,
+    MultipathMode, SessionId,
 };
 
 /// Default MTU size for a packet on the outside path (on the wire)
@@ -20,6 +20,11 @@ static METRIC_RECEIVED_ENCODING_REQ_WITH_TCP: LazyLock<Counter> =
 static METRIC_RECEIVED_RECONDING_RES_AS_SERVER: LazyLock<Counter> =
     LazyLock::new(|| counter!("received_encoding_res_as_server"));
 
+static METRIC_ACL_REJECTED_OUTBOUND: LazyLock<Counter> =
+    LazyLock::new(|| counter!("acl_rejected_outbound"));
+static METRIC_ACL_REJECTED_INBOUND: LazyLock<Counter> =
+    LazyLock::new(|| counter!("acl_rejected_inbound"));
+
 static TLS_PROTOCOL_VERSION_LABEL: &str = "tls_protocol_version";
 
 static METRIC_EXPRESSLANE_ENCRYPT_NO_KEY: LazyLock<Counter> =
@@ -94,6 +99,18 @@ pub(crate) fn received_encoding_res_as_server() {
     METRIC_RECEIVED_RECONDING_RES_AS_SERVER.increment(1);
 }
 
+/// Server dropped a packet from a client to a remote its
+/// [`crate::Acl`] does not allow
+pub(crate) fn acl_rejected_outbound() {
+    METRIC_ACL_REJECTED_OUTBOUND.increment(1);
+}
+
+/// Server dropped a packet to a client from a remote its
+/// [`crate::Acl`] does not allow
+pub(crate) fn acl_rejected_inbound() {
+    METRIC_ACL_REJECTED_INBOUND.increment(1);
+}
+
 /// Server try to send an expresslane packet, but no valid expresslane key
 /// to encrypt
 pub(crate) fn expresslane_encrypt_no_key() {
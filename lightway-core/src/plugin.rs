@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use bytes::BytesMut;
 use delegate::delegate;
 use thiserror::Error;
@@ -44,7 +46,8 @@ pub enum PluginResult {
     Drop,
     /// [`Plugin`] dropped the packet and returned a reply packet to send back
     /// This is useful only for Inside IO plugins. Outside plugins cannot
-    /// drop a packet with reply
+    /// drop a packet with reply. The reply is returned to the
+    /// application as [`crate::ConnectionError::PluginDropWithReply`].
     #[error("Plugin drops the packet with reply packet")]
     DropWithReply(BytesMut),
     /// Internal [`Plugin`] error
@@ -58,6 +61,12 @@ impl PluginList {
         self.plugins.is_empty()
     }
 
+    /// Add a plugin, which sees ingress packets last and egress
+    /// packets first
+    pub(crate) fn push(&mut self, plugin: PluginType) {
+        self.plugins.push(plugin);
+    }
+
     pub(crate) fn do_ingress(&self, data: &mut BytesMut) -> PluginResult {
         for plugin in self.plugins.iter() {
             let res = plugin.ingress(data);
@@ -104,8 +113,11 @@ pub trait PluginFactory {
 }
 
 /// Stores the list of `PluginFactory`
-#[derive(Default)]
-pub struct PluginFactoryList(Vec<PluginFactoryType>);
+///
+/// Clones share the factories, e.g. to build plugins for several
+/// connections racing each other.
+#[derive(Clone, Default)]
+pub struct PluginFactoryList(Vec<Arc<dyn PluginFactory + Sync + Send>>);
 
 impl PluginFactoryList {
     /// Create new `PluginFactoryList`
@@ -125,7 +137,16 @@ impl PluginFactoryList {
 
     /// Add [`PluginFactory`] to the [`PluginFactoryList`]
     pub fn add(&mut self, factory: PluginFactoryType) {
-        self.0.push(factory);
+        self.0.push(factory.into());
+    }
+
+    /// Insert [`PluginFactory`] at `index` of the [`PluginFactoryList`].
+    /// Plugins earlier in the list see ingress packets first and egress
+    /// packets last.
+    ///
+    /// Panics if `index > len`.
+    pub fn insert(&mut self, index: usize, factory: PluginFactoryType) {
+        self.0.insert(index, factory.into());
     }
 
     // Build a PluginList
@@ -313,4 +334,28 @@ mod tests {
         }
         plugin_list.do_ingress(&mut data)
     }
+
+    struct PadPluginFactory(&'static [u8]);
+
+    impl PluginFactory for PadPluginFactory {
+        fn build(&self) -> Result<PluginType, PluginFactoryError> {
+            Ok(Box::new(PadPlugin::new(self.0.to_vec())))
+        }
+    }
+
+    #[test]
+    fn test_plugin_factory_insert() {
+        let mut factories = PluginFactoryList::new();
+        factories.add(Box::new(PadPluginFactory(b"plugin2")));
+        factories.insert(0, Box::new(PadPluginFactory(b"plugin1")));
+        let plugin_list = factories.build().unwrap();
+
+        let mut data = BytesMut::new();
+        data.put_u32(32);
+        plugin_list.do_egress(&mut data);
+
+        // The first plugin pads last
+        assert_eq!(&data[..7], b"plugin1");
+        assert_eq!(&data[7..14], b"plugin2");
+    }
 }
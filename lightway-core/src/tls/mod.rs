@@ -15,6 +15,12 @@ pub use wolfssl::*;
 #[cfg(boringssl)]
 pub use boringssl::*;
 
+/// Whether the TLS library supports session tickets, see
+/// [`crate::ClientConnectionBuilder::with_session_ticket`]. The `wolfssl`
+/// crate hides the `WOLFSSL_CTX`/`WOLFSSL` handles the wolfSSL ticket
+/// calls take, so only BoringSSL does.
+pub const SESSION_TICKETS_SUPPORTED: bool = cfg!(boringssl);
+
 /// Get version string for the TLS library that we're using
 pub fn get_version_string() -> String {
     #[cfg(wolfssl)]
@@ -22,3 +28,32 @@ pub fn get_version_string() -> String {
     #[cfg(boringssl)]
     return format!("BoringSSL - {}", boringssl::get_version_string());
 }
+
+/// Take the latest session ticket the server sent to a client session.
+/// Always `None` with wolfSSL, which does not support session tickets.
+pub(crate) fn take_session_ticket<IOCB: IOCallbacks>(
+    session: &mut Session<IOCB>,
+) -> Option<Vec<u8>> {
+    #[cfg(wolfssl)]
+    {
+        let _ = session;
+        None
+    }
+    #[cfg(boringssl)]
+    {
+        session.take_session_ticket()
+    }
+}
+
+/// Whether the handshake resumed a session from a ticket
+pub(crate) fn session_resumed<IOCB: IOCallbacks>(session: &Session<IOCB>) -> bool {
+    #[cfg(wolfssl)]
+    {
+        let _ = session;
+        false
+    }
+    #[cfg(boringssl)]
+    {
+        session.session_reused()
+    }
+}
@@ -8,6 +8,11 @@
 //! [`Frame::AuthRequest`]. The server will reply with either
 //! [`Frame::AuthSuccessWithConfigV4`] or [`Frame::AuthFailure`].
 //!
+//! A client acting as a site-to-site gateway lists the subnets behind
+//! it in the request, which is then sent as a
+//! [`FrameKind::SiteAuthRequest`]. Servers not knowing this frame kind
+//! cannot authenticate such a client.
+//!
 //! ## Communication
 //!
 //! Once authenticated, the client and the server communicate by
@@ -37,6 +42,15 @@
 //! [`Frame::NoOp`] and [`Frame::Pong`] are ignored by the recipient.
 //!
 //! [`Frame::Ping`] will result in a [`Frame::Pong`] in response.
+//!
+//! ## Multipath
+//!
+//! Once online, a UDP client wanting to use several paths sends a
+//! [`Frame::MultipathConfig`] with its [`MultipathMode`], which the
+//! server answers with the mode granted. The server then sends a
+//! [`Frame::PathChallenge`] to each new address the session is received
+//! from, and only uses the address as a path once the client answers with
+//! a [`Frame::PathResponse`].
 
 use crate::borrowed_bytesmut::BorrowedBytesMut;
 use bytes::{Buf, BufMut, BytesMut};
@@ -54,11 +68,13 @@ mod encoding_request;
 mod encoding_response;
 mod expresslane_config;
 mod expresslane_data;
+mod multipath_config;
+mod path_challenge;
 mod ping;
 mod pong;
 mod server_config;
 
-pub use auth_request::AuthMethod;
+pub use auth_request::{AuthMethod, MAX_SITE_SUBNETS};
 
 pub(crate) use auth_failure::AuthFailure;
 pub(crate) use auth_request::AuthRequest;
@@ -72,7 +88,9 @@ pub(crate) use expresslane_data::ExpresslaneData;
 pub use expresslane_data::{
     EXPRESSLANE_KEY_SIZE, ExpresslaneError, ExpresslaneKey, ExpresslaneVersion,
 };
-pub(crate) use ping::Ping;
+pub(crate) use multipath_config::MultipathConfig;
+pub(crate) use path_challenge::PathChallenge;
+pub(crate) use ping::{KeepaliveStamp, Ping};
 pub(crate) use pong::Pong;
 pub(crate) use server_config::ServerConfig;
 
@@ -99,6 +117,9 @@ pub enum FromWireError {
     /// A field which was larger than allowed was found
     #[error("Field too large")]
     FieldTooLarge,
+    /// A subnet with a prefix length over 32 was found
+    #[error("Invalid subnet")]
+    InvalidSubnet,
     /// Wire contains an invalid protocol version
     #[error("Invalid protocol version {0}.{1}")]
     InvalidProtocolVersion(u8, u8),
@@ -157,6 +178,13 @@ impl SessionId {
     }
 }
 
+impl From<[u8; 8]> for SessionId {
+    /// Rebuild a `SessionId` from the bytes of [`SessionId::as_bytes`]
+    fn from(value: [u8; 8]) -> Self {
+        Self(value)
+    }
+}
+
 impl Distribution<SessionId> for StandardUniform {
     fn sample<R: rand_core::Rng + ?Sized>(&self, rng: &mut R) -> SessionId {
         loop {
@@ -190,7 +218,7 @@ impl std::fmt::Debug for SessionId {
 /// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
 /// |   ASCII 'H'   |   ASCII 'e'   | major_version | minor_version |
 /// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
-/// |   aggressive  | express data  |          RESERVED             |
+/// |   aggressive  | express data  |   multipath   |   RESERVED    |
 /// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
 /// |                            Session                            |
 /// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
@@ -205,6 +233,8 @@ pub struct Header {
     pub aggressive_mode: bool,
     /// Expresslane data
     pub expresslane_data: bool,
+    /// How the sender uses its paths to the peer
+    pub multipath: MultipathMode,
     /// Session identifier (opaque cookie)
     pub session: SessionId,
 }
@@ -231,7 +261,9 @@ impl Header {
 
         let aggressive_mode = buf.get_u8() != 0;
         let expresslane_data = buf.get_u8() != 0;
-        buf.advance(2); // RESERVED
+        let multipath = MultipathMode::try_from(buf.get_u8())
+            .map_err(|_| FromWireError::InvalidEnumEncoding)?;
+        buf.advance(1); // RESERVED
 
         let mut session = SessionId::EMPTY;
         buf.copy_to_slice(session.as_mut_slice());
@@ -244,6 +276,7 @@ impl Header {
             version,
             aggressive_mode,
             expresslane_data,
+            multipath,
             session,
         })
     }
@@ -259,7 +292,8 @@ impl Header {
 
         buf.put_u8(self.aggressive_mode as u8);
         buf.put_u8(self.expresslane_data as u8);
-        buf.put_bytes(0, 2); // RESERVED
+        buf.put_u8(self.multipath.into());
+        buf.put_bytes(0, 1); // RESERVED
 
         buf.put(self.session.as_slice());
 
@@ -267,6 +301,25 @@ impl Header {
     }
 }
 
+/// How a client uses several outside paths to the server for one
+/// session, carried in the [`Header`] of every packet it sends once
+/// granted by a [`Frame::MultipathConfig`] (UDP only). The server adds
+/// each address the session is received from as a path, once it answers
+/// a [`Frame::PathChallenge`], rather than treating it as the client
+/// floating.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+pub enum MultipathMode {
+    /// A single path
+    #[default]
+    Off = 0,
+    /// Each packet is sent on one path, replies go to the path the
+    /// peer last received from
+    Scheduled = 1,
+    /// Every packet is sent on all paths
+    Redundant = 2,
+}
+
 /// The encoding of the frame type. Each frame starts with a byte
 /// indicating the type of frame.
 // Needs repr(u8) in order to be able to convert to and from primitives
@@ -308,6 +361,15 @@ pub(crate) enum FrameKind {
     EncodingResponse = 19,
     /// Express Data
     ExpresslaneConfig = 20,
+    /// Multipath mode request (client -> server) or grant (server -> client)
+    MultipathConfig = 21,
+    /// Challenge to a new path of a multipath session (server -> client only)
+    PathChallenge = 22,
+    /// Answer to a [`FrameKind::PathChallenge`] (client -> server only)
+    PathResponse = 23,
+    /// Authentication Request listing the subnets behind the client
+    /// (client -> server only)
+    SiteAuthRequest = 24,
 }
 
 /// Encapsulates a single frame.
@@ -329,7 +391,9 @@ pub(crate) enum Frame<'data> {
     Ping(ping::Ping),
     /// A pong request in response to a ping
     Pong(pong::Pong),
-    /// Authentication Request (client -> server only)
+    /// Authentication Request (client -> server only), sent as a
+    /// [`FrameKind::SiteAuthRequest`] when it lists subnets behind the
+    /// client
     AuthRequest(auth_request::AuthRequest),
     /// Packets of data to / from the tunnel
     Data(data::Data<'data>),
@@ -353,6 +417,12 @@ pub(crate) enum Frame<'data> {
     EncodingResponse(encoding_response::EncodingResponse),
     /// Expresslane config
     ExpresslaneConfig(expresslane_config::ExpresslaneConfig),
+    /// Multipath mode request (client -> server) or grant (server -> client)
+    MultipathConfig(multipath_config::MultipathConfig),
+    /// Challenge to a new path of a multipath session (server -> client only)
+    PathChallenge(path_challenge::PathChallenge),
+    /// Answer to a [`Frame::PathChallenge`] (client -> server only)
+    PathResponse(path_challenge::PathChallenge),
 }
 
 impl Frame<'_> {
@@ -361,6 +431,7 @@ impl Frame<'_> {
             Self::NoOp => FrameKind::NoOp,
             Self::Ping(_) => FrameKind::Ping,
             Self::Pong(_) => FrameKind::Pong,
+            Self::AuthRequest(auth) if !auth.site_subnets.is_empty() => FrameKind::SiteAuthRequest,
             Self::AuthRequest(_) => FrameKind::AuthRequest,
             Self::Data(_) => FrameKind::Data,
             Self::AuthSuccessWithConfigV4(_) => FrameKind::AuthSuccessWithConfigV4,
@@ -373,6 +444,9 @@ impl Frame<'_> {
             Self::EncodingRequest(_) => FrameKind::EncodingRequest,
             Self::EncodingResponse(_) => FrameKind::EncodingResponse,
             Self::ExpresslaneConfig(_) => FrameKind::ExpresslaneConfig,
+            Self::MultipathConfig(_) => FrameKind::MultipathConfig,
+            Self::PathChallenge(_) => FrameKind::PathChallenge,
+            Self::PathResponse(_) => FrameKind::PathResponse,
         }
     }
 
@@ -413,6 +487,16 @@ impl Frame<'_> {
             FrameKind::ExpresslaneConfig => {
                 Self::ExpresslaneConfig(ExpresslaneConfig::try_from_wire(&mut buf)?)
             }
+            FrameKind::MultipathConfig => {
+                Self::MultipathConfig(MultipathConfig::try_from_wire(&mut buf)?)
+            }
+            FrameKind::PathChallenge => {
+                Self::PathChallenge(PathChallenge::try_from_wire(&mut buf)?)
+            }
+            FrameKind::PathResponse => Self::PathResponse(PathChallenge::try_from_wire(&mut buf)?),
+            FrameKind::SiteAuthRequest => {
+                Self::AuthRequest(AuthRequest::try_from_wire_with_site_subnets(&mut buf)?)
+            }
         };
 
         buf.commit(); // We've successfully parsed a frame, move the
@@ -442,6 +526,9 @@ impl Frame<'_> {
             Self::EncodingRequest(er) => er.append_to_wire(buf),
             Self::EncodingResponse(er) => er.append_to_wire(buf),
             Self::ExpresslaneConfig(conf) => conf.append_to_wire(buf),
+            Self::MultipathConfig(conf) => conf.append_to_wire(buf),
+            Self::PathChallenge(challenge) => challenge.append_to_wire(buf),
+            Self::PathResponse(response) => response.append_to_wire(buf),
         }
     }
 }
@@ -498,6 +585,7 @@ mod test_header {
                 version: crate::Version::try_new(1, 2).unwrap(),
                 aggressive_mode: true,
                 expresslane_data: false,
+                multipath: MultipathMode::Off,
                 session: SessionId([0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0]),
             },
         );
@@ -528,12 +616,44 @@ mod test_header {
         ));
     }
 
+    #[test_case(0x00 => MultipathMode::Off)]
+    #[test_case(0x01 => MultipathMode::Scheduled)]
+    #[test_case(0x02 => MultipathMode::Redundant)]
+    fn from_wire_multipath(multipath: u8) -> MultipathMode {
+        let mut buf = BytesMut::from(
+            &[
+                0x48, 0x65, 0x01, 0x02, 0x00, 0x00, multipath, 0x00, 0x12, 0x34, 0x56, 0x78, 0x9a,
+                0xbc, 0xde, 0xf0,
+            ][..],
+        );
+        let h = Header::try_from_wire(&mut buf).expect("decode");
+        let mut wire = BytesMut::new();
+        h.append_to_wire(&mut wire);
+        assert_eq!(wire[6], multipath);
+        h.multipath
+    }
+
+    #[test]
+    fn from_wire_unknown_multipath() {
+        let mut buf = BytesMut::from(
+            &[
+                0x48, 0x65, 0x01, 0x02, 0x00, 0x00, 0x03, 0x00, 0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc,
+                0xde, 0xf0,
+            ][..],
+        );
+        assert!(matches!(
+            Header::try_from_wire(&mut buf).err().unwrap(),
+            FromWireError::InvalidEnumEncoding
+        ));
+    }
+
     #[test]
     fn into_wire() {
         let h = Header {
             version: crate::Version::try_new(1, 2).unwrap(),
             aggressive_mode: true,
             expresslane_data: false,
+            multipath: MultipathMode::Off,
             session: SessionId([0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0]),
         };
 
@@ -568,6 +688,7 @@ mod test_frame_kind {
     #[test_case(FrameKind::EncodedDataFrag => 17)]
     #[test_case(FrameKind::EncodingRequest => 18)]
     #[test_case(FrameKind::EncodingResponse => 19)]
+    #[test_case(FrameKind::SiteAuthRequest => 24)]
     fn into_primitive(ty: FrameKind) -> u8 {
         ty.into()
     }
@@ -592,13 +713,17 @@ mod test_frame_kind {
     #[test_case(18 => FrameKind::EncodingRequest)]
     #[test_case(19 => FrameKind::EncodingResponse)]
     #[test_case(20 => FrameKind::ExpresslaneConfig)]
+    #[test_case(21 => FrameKind::MultipathConfig)]
+    #[test_case(22 => FrameKind::PathChallenge)]
+    #[test_case(23 => FrameKind::PathResponse)]
+    #[test_case(24 => FrameKind::SiteAuthRequest)]
     fn try_from_primitive(b: u8) -> FrameKind {
         FrameKind::try_from(b).unwrap()
     }
 
     #[test]
     fn try_from_primitive_out_of_range() {
-        for b in 21..=255 {
+        for b in 25..=255 {
             assert!(FrameKind::try_from(b).is_err())
         }
     }
@@ -614,8 +739,9 @@ mod test_frame {
     #[test_case(Frame::NoOp => FrameKind::NoOp)]
     #[test_case(Frame::Ping(Ping{ id: 0, payload: Default::default() }) => FrameKind::Ping)]
     #[test_case(Frame::Pong(Pong{ id: 0, payload: Default::default() }) => FrameKind::Pong)]
-    #[test_case(Frame::AuthRequest(AuthRequest{ auth_method: auth_request::AuthMethod::UserPass{ user: Default::default(), password: Default::default() }}) => FrameKind::AuthRequest)]
-    #[test_case(Frame::AuthRequest(AuthRequest{ auth_method: auth_request::AuthMethod::VersionedToken{ version: crate::Version::MAXIMUM, token: Default::default() }}) => FrameKind::AuthRequest)]
+    #[test_case(Frame::AuthRequest(AuthRequest{ site_subnets: Vec::new(), auth_method: auth_request::AuthMethod::UserPass{ user: Default::default(), password: Default::default() }}) => FrameKind::AuthRequest)]
+    #[test_case(Frame::AuthRequest(AuthRequest{ site_subnets: Vec::new(), auth_method: auth_request::AuthMethod::VersionedToken{ version: crate::Version::MAXIMUM, token: Default::default() }}) => FrameKind::AuthRequest)]
+    #[test_case(Frame::AuthRequest(AuthRequest{ site_subnets: vec!["192.168.10.0/24".parse().unwrap()], auth_method: auth_request::AuthMethod::Token{ token: Default::default() }}) => FrameKind::SiteAuthRequest)]
     #[test_case(Frame::Data(Data{ data: Cow::Owned(BytesMut::new()) }) => FrameKind::Data)]
     #[test_case(Frame::AuthSuccessWithConfigV4(AuthSuccessWithConfigV4{ local_ip: Default::default(), peer_ip: Default::default(), dns_ip: Default::default(), mtu: Default::default(), session: SessionId::EMPTY }) => FrameKind::AuthSuccessWithConfigV4)]
     #[test_case(Frame::AuthFailure(AuthFailure) => FrameKind::AuthFailure)]
@@ -624,6 +750,9 @@ mod test_frame {
     #[test_case(Frame::DataFrag(DataFrag{ id: 0, offset: 0, more_fragments: true, data: Default::default() }) => FrameKind::DataFrag)]
     #[test_case(Frame::EncodingRequest(EncodingRequest{ id: 513, enable: true }) => FrameKind::EncodingRequest)]
     #[test_case(Frame::EncodingResponse(EncodingResponse{ id: 513, enable: true }) => FrameKind::EncodingResponse)]
+    #[test_case(Frame::MultipathConfig(MultipathConfig{ mode: MultipathMode::Scheduled }) => FrameKind::MultipathConfig)]
+    #[test_case(Frame::PathChallenge(PathChallenge{ token: 1 }) => FrameKind::PathChallenge)]
+    #[test_case(Frame::PathResponse(PathChallenge{ token: 1 }) => FrameKind::PathResponse)]
     fn frame_kind(f: Frame) -> FrameKind {
         f.kind()
     }
@@ -631,10 +760,11 @@ mod test_frame {
     #[test_case(Frame::NoOp => vec![0x01]; "noop")]
     #[test_case(Frame::Ping(Ping{ id: 0xf00b, payload: Default::default()}) => vec![0x2, 0xf0, 0x0b, 0x00, 0x00]; "ping")]
     #[test_case(Frame::Pong(Pong{ id: 0xabcd, payload: Default::default() }) => vec![0x3, 0xab, 0xcd, 0x00, 0x00]; "pong")]
-    #[test_case(Frame::AuthRequest(AuthRequest{ auth_method: auth_request::AuthMethod::UserPass{ user: "me".to_string(), password: "secret".to_string() }}) => b"\x04\x01\x02\x06me\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00secret\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec(); "auth request userpass")]
-    #[test_case(Frame::AuthRequest(AuthRequest{ auth_method: auth_request::AuthMethod::Token{ token: "token".to_string() }}) => b"\x04\x02\x00\x05token".to_vec(); "auth request token")]
-    #[test_case(Frame::AuthRequest(AuthRequest{ auth_method: auth_request::AuthMethod::VersionedToken{ version: crate::Version::MAXIMUM, token: "token".to_string() }}) => b"\x04\x03\x01\x03\x00\x05token".to_vec(); "auth request versioned token")]
-    #[test_case(Frame::AuthRequest(AuthRequest{ auth_method: auth_request::AuthMethod::CustomCallback{ data: Bytes::from_static(&[1, 2, 3, 4]) }}) => vec![0x4, 23, 0x00, 0x04, 1, 2, 3, 4]; "auth request custom callback")]
+    #[test_case(Frame::AuthRequest(AuthRequest{ site_subnets: Vec::new(), auth_method: auth_request::AuthMethod::UserPass{ user: "me".to_string(), password: "secret".to_string() }}) => b"\x04\x01\x02\x06me\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00secret\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec(); "auth request userpass")]
+    #[test_case(Frame::AuthRequest(AuthRequest{ site_subnets: Vec::new(), auth_method: auth_request::AuthMethod::Token{ token: "token".to_string() }}) => b"\x04\x02\x00\x05token".to_vec(); "auth request token")]
+    #[test_case(Frame::AuthRequest(AuthRequest{ site_subnets: Vec::new(), auth_method: auth_request::AuthMethod::VersionedToken{ version: crate::Version::MAXIMUM, token: "token".to_string() }}) => b"\x04\x03\x01\x03\x00\x05token".to_vec(); "auth request versioned token")]
+    #[test_case(Frame::AuthRequest(AuthRequest{ site_subnets: Vec::new(), auth_method: auth_request::AuthMethod::CustomCallback{ data: Bytes::from_static(&[1, 2, 3, 4]) }}) => vec![0x4, 23, 0x00, 0x04, 1, 2, 3, 4]; "auth request custom callback")]
+    #[test_case(Frame::AuthRequest(AuthRequest{ site_subnets: vec!["192.168.10.0/24".parse().unwrap(), "10.1.0.0/16".parse().unwrap()], auth_method: auth_request::AuthMethod::Token{ token: "token".to_string() }}) => b"\x18\x02\xc0\xa8\x0a\x00\x18\x0a\x01\x00\x00\x10\x02\x00\x05token".to_vec(); "site auth request")]
     #[test_case(Frame::Data(Data{ data: Cow::Owned(BytesMut::from(&[0xfe, 0xbe, 0xaa][..]))}) => vec![0x5, 0, 3, 0xfe, 0xbe, 0xaa]; "data")]
     #[test_case(Frame::AuthSuccessWithConfigV4(AuthSuccessWithConfigV4{ local_ip: "1.1.1.1".to_string(), peer_ip: "2.2.2.2".to_string(), dns_ip: "3.3.3.3".to_string(), mtu: "1500".to_string(), session: SessionId([0x00, 0x00, 0x00, 0x00, 0x00, 0x00,0x2f, 0x66]) }) => b"\x061.1.1.1\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x002.2.2.2\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x003.3.3.3\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x001500\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x2f\x66".to_vec(); "auth success with config v4")]
     #[test_case(Frame::AuthFailure(AuthFailure) =>  b"\x07\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec(); "auth failure")]
@@ -643,6 +773,9 @@ mod test_frame {
     #[test_case(Frame::DataFrag(DataFrag{ id: 0x1234, offset: 0x5678, more_fragments: true, data: Bytes::from_static(b"fragmentary") }) => b"\x0f\x00\x0b\x12\x34\x2a\xcffragmentary".to_vec() ; "data frag")]
     #[test_case(Frame::EncodingRequest(EncodingRequest{ id: 513, enable: true}) => b"\x12\x00\x00\x00\x00\x00\x00\x02\x01\x01".to_vec(); "encoding request")]
     #[test_case(Frame::EncodingResponse(EncodingResponse{ id: 513, enable: true}) => b"\x13\x00\x00\x00\x00\x00\x00\x02\x01\x01".to_vec(); "encoding response")]
+    #[test_case(Frame::MultipathConfig(MultipathConfig{ mode: MultipathMode::Redundant }) => vec![0x15, 0x02]; "multipath config")]
+    #[test_case(Frame::PathChallenge(PathChallenge{ token: 0x1234 }) => b"\x16\x00\x00\x00\x00\x00\x00\x12\x34".to_vec(); "path challenge")]
+    #[test_case(Frame::PathResponse(PathChallenge{ token: 0x1234 }) => b"\x17\x00\x00\x00\x00\x00\x00\x12\x34".to_vec(); "path response")]
     fn into_wire(f: Frame) -> Vec<u8> {
         let mut buf = BytesMut::new();
         f.append_to_wire(&mut buf);
@@ -652,10 +785,11 @@ mod test_frame {
     #[test_case(&[0x01] => Frame::NoOp; "noop")]
     #[test_case(&[0x2, 0xf0, 0x0b, 0x00, 0x00] => Frame::Ping(Ping{ id: 0xf00b, payload: Default::default() }); "ping")]
     #[test_case(&[0x3, 0xab, 0xcd, 0x00, 0x00] => Frame::Pong(Pong{ id: 0xabcd, payload: Default::default() }); "pong")]
-    #[test_case(b"\x04\x01\x02\x06me\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00secret\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00" => Frame::AuthRequest(AuthRequest{ auth_method: auth_request::AuthMethod::UserPass{ user: "me".to_string(), password: "secret".to_string() }}); "auth request user pass")]
-    #[test_case(b"\x04\x02\x00\x05token" => Frame::AuthRequest(AuthRequest{ auth_method: auth_request::AuthMethod::Token{ token: "token".to_string() }}); "auth request token")]
-    #[test_case(b"\x04\x03\x01\x03\x00\x05token" => Frame::AuthRequest(AuthRequest{ auth_method: auth_request::AuthMethod::VersionedToken{ version: crate::Version::MAXIMUM, token: "token".to_string() }}); "auth request versioned token")]
-    #[test_case(&[0x4, 23, 0x00, 0x04, 1, 2, 3, 4] => Frame::AuthRequest(AuthRequest{ auth_method: auth_request::AuthMethod::CustomCallback{ data: Bytes::from_static(&[1, 2, 3, 4]) }}); "auth request custom callback")]
+    #[test_case(b"\x04\x01\x02\x06me\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00secret\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00" => Frame::AuthRequest(AuthRequest{ site_subnets: Vec::new(), auth_method: auth_request::AuthMethod::UserPass{ user: "me".to_string(), password: "secret".to_string() }}); "auth request user pass")]
+    #[test_case(b"\x04\x02\x00\x05token" => Frame::AuthRequest(AuthRequest{ site_subnets: Vec::new(), auth_method: auth_request::AuthMethod::Token{ token: "token".to_string() }}); "auth request token")]
+    #[test_case(b"\x04\x03\x01\x03\x00\x05token" => Frame::AuthRequest(AuthRequest{ site_subnets: Vec::new(), auth_method: auth_request::AuthMethod::VersionedToken{ version: crate::Version::MAXIMUM, token: "token".to_string() }}); "auth request versioned token")]
+    #[test_case(&[0x4, 23, 0x00, 0x04, 1, 2, 3, 4] => Frame::AuthRequest(AuthRequest{ site_subnets: Vec::new(), auth_method: auth_request::AuthMethod::CustomCallback{ data: Bytes::from_static(&[1, 2, 3, 4]) }}); "auth request custom callback")]
+    #[test_case(b"\x18\x02\xc0\xa8\x0a\x00\x18\x0a\x01\x00\x00\x10\x02\x00\x05token" => Frame::AuthRequest(AuthRequest{ site_subnets: vec!["192.168.10.0/24".parse().unwrap(), "10.1.0.0/16".parse().unwrap()], auth_method: auth_request::AuthMethod::Token{ token: "token".to_string() }}); "site auth request")]
     #[test_case(&[0x5, 0, 3, 0xfe, 0xbe, 0xaa] => Frame::Data(Data{ data: Cow::Owned(BytesMut::from(&[0xfe, 0xbe, 0xaa][..]))}); "data")]
     #[test_case(b"\x061.1.1.1\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x002.2.2.2\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x003.3.3.3\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x001500\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x2f\x66" => Frame::AuthSuccessWithConfigV4(AuthSuccessWithConfigV4{ local_ip: "1.1.1.1".to_string(), peer_ip: "2.2.2.2".to_string(), dns_ip: "3.3.3.3".to_string(), mtu: "1500".to_string(), session: SessionId([0x00, 0x00, 0x00, 0x00, 0x00, 0x00,0x2f, 0x66]) }); "auth success with config v4")]
     #[test_case(b"\x07\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00" => Frame::AuthFailure(AuthFailure); "auth failure")]
@@ -666,6 +800,9 @@ mod test_frame {
     #[test_case(b"\x11\x00\x0b\x12\x34\x2a\xcffragmentary"=> Frame::EncodedDataFrag(DataFrag{ id: 0x1234, offset: 0x5678, more_fragments: true, data: Bytes::from_static(b"fragmentary") }) ; "encoded data frag")]
     #[test_case(b"\x12\x00\x00\x00\x00\x00\x00\x02\x01\x01"=> Frame::EncodingRequest(EncodingRequest{id: 513, enable: true}) ; "encoding request")]
     #[test_case(b"\x13\x00\x00\x00\x00\x00\x00\x02\x02\x00"=> Frame::EncodingResponse(EncodingResponse{id: 514, enable: false}) ; "encoding response")]
+    #[test_case(&[0x15, 0x01] => Frame::MultipathConfig(MultipathConfig{ mode: MultipathMode::Scheduled }); "multipath config")]
+    #[test_case(b"\x16\x00\x00\x00\x00\x00\x00\x12\x34" => Frame::PathChallenge(PathChallenge{ token: 0x1234 }); "path challenge")]
+    #[test_case(b"\x17\x00\x00\x00\x00\x00\x00\x12\x34" => Frame::PathResponse(PathChallenge{ token: 0x1234 }); "path response")]
     fn try_from_wire(buf: &'static [u8]) -> Frame<'static> {
         let mut buf = BytesMut::from(buf);
         let r = Frame::try_from_wire(&mut buf).unwrap();
@@ -691,6 +828,14 @@ mod test_frame {
         ));
     }
 
+    #[test_case(&[0x18, 17] => matches FromWireError::FieldTooLarge; "too many subnets")]
+    #[test_case(&[0x18, 2, 192, 168, 10, 0, 24] => matches FromWireError::InsufficientData; "truncated subnets")]
+    #[test_case(&[0x18, 1, 192, 168, 10, 0, 33, 0x02, 0x00, 0x00] => matches FromWireError::InvalidSubnet; "prefix too long")]
+    fn site_auth_request_from_wire_invalid(buf: &[u8]) -> FromWireError {
+        let mut buf = BytesMut::from(buf);
+        Frame::try_from_wire(&mut buf).err().unwrap()
+    }
+
     #[test]
     fn partial_decode() {
         let mut buf = BytesMut::with_capacity(5);
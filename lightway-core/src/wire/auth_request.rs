@@ -1,8 +1,9 @@
 use crate::{Version, borrowed_bytesmut::BorrowedBytesMut};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
+use ipnet::Ipv4Net;
 use more_asserts::*;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
-use std::fmt;
+use std::{fmt, net::Ipv4Addr};
 
 use super::{FromWireError, FromWireResult};
 
@@ -383,6 +384,10 @@ impl AuthMethod {
     }
 }
 
+/// The maximum number of subnets a client may advertise in a
+/// [`super::FrameKind::SiteAuthRequest`].
+pub const MAX_SITE_SUBNETS: usize = 16;
+
 /// Authentication Request (only sent from client to server)
 ///
 /// See [`super::Frame::AuthSuccessWithConfigV4`] and
@@ -400,20 +405,91 @@ impl AuthMethod {
 /// +-+-+-+-+-+-+-+-+
 ///```
 ///
+/// A client with subnets behind it sends a
+/// [`super::FrameKind::SiteAuthRequest`] instead, listing up to
+/// [`MAX_SITE_SUBNETS`] subnets ahead of the auth method. Each subnet is
+/// its network address followed by its prefix length:
+///
+/// ```text
+///  0                   1                   2                   3
+///  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// | subnet count  |           subnet[0] network[0..=2]            |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |  network[3]   |  prefix len   | ... subnet[1..]
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// | auth kind     | ... per AuthMethod data
+/// +-+-+-+-+-+-+-+-+
+///```
+///
 /// See [`AuthMethod`] for wire format of each variant.
 #[derive(PartialEq, Debug)]
 pub(crate) struct AuthRequest {
     pub(crate) auth_method: AuthMethod,
+    /// Subnets behind the client, only sent in a
+    /// [`super::FrameKind::SiteAuthRequest`]
+    pub(crate) site_subnets: Vec<Ipv4Net>,
 }
 
 impl AuthRequest {
+    /// Wire size of each subnet of `site_subnets`
+    const SITE_SUBNET_WIRE_SIZE: usize = 4 + 1;
+
     pub(crate) fn try_from_wire(buf: &mut BorrowedBytesMut) -> FromWireResult<Self> {
         let auth_method = AuthMethod::try_from_wire(buf)?;
 
-        Ok(Self { auth_method })
+        Ok(Self {
+            auth_method,
+            site_subnets: Vec::new(),
+        })
+    }
+
+    /// Parse the payload of a [`super::FrameKind::SiteAuthRequest`]
+    pub(crate) fn try_from_wire_with_site_subnets(
+        buf: &mut BorrowedBytesMut,
+    ) -> FromWireResult<Self> {
+        // We require at least 1 byte for the subnet count
+        if buf.is_empty() {
+            return Err(FromWireError::InsufficientData);
+        }
+
+        let count = buf.get_u8() as usize;
+        if count > MAX_SITE_SUBNETS {
+            return Err(FromWireError::FieldTooLarge);
+        }
+        if buf.len() < count * Self::SITE_SUBNET_WIRE_SIZE {
+            return Err(FromWireError::InsufficientData);
+        }
+
+        let site_subnets = (0..count)
+            .map(|_| {
+                let network = Ipv4Addr::from(buf.get_u32());
+                let prefix_len = buf.get_u8();
+                Ipv4Net::new(network, prefix_len).map_err(|_| FromWireError::InvalidSubnet)
+            })
+            .collect::<FromWireResult<_>>()?;
+
+        let auth_method = AuthMethod::try_from_wire(buf)?;
+
+        Ok(Self {
+            auth_method,
+            site_subnets,
+        })
     }
 
     pub(crate) fn append_to_wire(&self, buf: &mut BytesMut) {
+        if !self.site_subnets.is_empty() {
+            debug_assert_le!(self.site_subnets.len(), MAX_SITE_SUBNETS);
+
+            buf.reserve(1 + self.site_subnets.len() * Self::SITE_SUBNET_WIRE_SIZE);
+
+            buf.put_u8(self.site_subnets.len() as u8);
+            for subnet in &self.site_subnets {
+                buf.put_u32(subnet.network().into());
+                buf.put_u8(subnet.prefix_len());
+            }
+        }
+
         self.auth_method.append_to_wire(buf)
     }
 }
@@ -0,0 +1,91 @@
+use bytes::{Buf, BufMut, BytesMut};
+
+use super::{FromWireError, FromWireResult, MultipathMode};
+use crate::borrowed_bytesmut::BorrowedBytesMut;
+
+/// Multipath mode requested by the client, or granted by the server in
+/// reply ([`MultipathMode::Off`] if refused). The client only sets the
+/// mode in packet headers once granted.
+///
+/// Wire format (fixed length):
+///
+/// ```text
+///  0 1 2 3 4 5 6 7
+/// +-+-+-+-+-+-+-+-+
+/// |      mode     |
+/// +-+-+-+-+-+-+-+-+
+/// ```
+///
+/// Frame size is fixed at 1 byte.
+#[derive(PartialEq, Debug)]
+pub(crate) struct MultipathConfig {
+    pub(crate) mode: MultipathMode,
+}
+
+impl MultipathConfig {
+    /// Wire Size in bytes
+    const WIRE_SIZE: usize = 1;
+
+    pub(crate) fn try_from_wire(buf: &mut BorrowedBytesMut) -> FromWireResult<Self> {
+        if buf.len() < Self::WIRE_SIZE {
+            return Err(FromWireError::InsufficientData);
+        };
+
+        let mode = MultipathMode::try_from(buf.get_u8())
+            .map_err(|_| FromWireError::InvalidEnumEncoding)?;
+
+        Ok(Self { mode })
+    }
+
+    pub(crate) fn append_to_wire(&self, buf: &mut BytesMut) {
+        buf.reserve(Self::WIRE_SIZE);
+
+        buf.put_u8(self.mode.into());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::borrowed_bytesmut::ImmutableBytesMut;
+    use test_case::test_case;
+
+    #[test_case(&[0x00] => MultipathMode::Off)]
+    #[test_case(&[0x01] => MultipathMode::Scheduled)]
+    #[test_case(&[0x02] => MultipathMode::Redundant)]
+    fn try_from_wire(wire: &'static [u8]) -> MultipathMode {
+        let mut buf = ImmutableBytesMut::from(wire);
+        let mut buf = buf.as_borrowed_bytesmut();
+        MultipathConfig::try_from_wire(&mut buf).unwrap().mode
+    }
+
+    #[test]
+    fn try_from_wire_unknown_mode() {
+        let mut buf = ImmutableBytesMut::from(&[0x03][..]);
+        let mut buf = buf.as_borrowed_bytesmut();
+        assert!(matches!(
+            MultipathConfig::try_from_wire(&mut buf).err().unwrap(),
+            FromWireError::InvalidEnumEncoding
+        ));
+    }
+
+    #[test]
+    fn try_from_wire_too_short() {
+        let mut buf = ImmutableBytesMut::from(&[0u8; 0][..]);
+        let mut buf = buf.as_borrowed_bytesmut();
+        assert!(matches!(
+            MultipathConfig::try_from_wire(&mut buf).err().unwrap(),
+            FromWireError::InsufficientData
+        ));
+    }
+
+    #[test]
+    fn append_to_wire() {
+        let mut buf = BytesMut::new();
+        MultipathConfig {
+            mode: MultipathMode::Redundant,
+        }
+        .append_to_wire(&mut buf);
+        assert_eq!(&buf[..], &[0x02]);
+    }
+}
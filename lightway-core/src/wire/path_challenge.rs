@@ -0,0 +1,83 @@
+use bytes::{Buf, BufMut, BytesMut};
+
+use super::{FromWireError, FromWireResult};
+use crate::borrowed_bytesmut::BorrowedBytesMut;
+
+/// Random token the server sends to a new address of a multipath
+/// session, and the client echoes back, proving it receives packets at
+/// that address. Carried by both [`super::Frame::PathChallenge`] and
+/// [`super::Frame::PathResponse`].
+///
+/// Wire format (fixed length):
+///
+/// ```text
+///  0                   1                   2                   3
+///  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                             token                             |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                             token                             |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// ```
+///
+/// Frame size is fixed at 8 bytes.
+#[derive(PartialEq, Debug)]
+pub(crate) struct PathChallenge {
+    pub(crate) token: u64,
+}
+
+impl PathChallenge {
+    /// Wire Size in bytes
+    const WIRE_SIZE: usize = 8;
+
+    pub(crate) fn try_from_wire(buf: &mut BorrowedBytesMut) -> FromWireResult<Self> {
+        if buf.len() < Self::WIRE_SIZE {
+            return Err(FromWireError::InsufficientData);
+        };
+
+        let token = buf.get_u64();
+
+        Ok(Self { token })
+    }
+
+    pub(crate) fn append_to_wire(&self, buf: &mut BytesMut) {
+        buf.reserve(Self::WIRE_SIZE);
+
+        buf.put_u64(self.token);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::borrowed_bytesmut::ImmutableBytesMut;
+
+    #[test]
+    fn try_from_wire() {
+        let mut buf = ImmutableBytesMut::from(&b"\x01\x23\x45\x67\x89\xab\xcd\xef"[..]);
+        let mut buf = buf.as_borrowed_bytesmut();
+
+        let wire = PathChallenge::try_from_wire(&mut buf).unwrap();
+        assert_eq!(wire.token, 0x0123_4567_89ab_cdef);
+    }
+
+    #[test]
+    fn try_from_wire_too_short() {
+        let mut buf = ImmutableBytesMut::from(&[0u8; PathChallenge::WIRE_SIZE - 1][..]);
+        let mut buf = buf.as_borrowed_bytesmut();
+        assert!(matches!(
+            PathChallenge::try_from_wire(&mut buf).err().unwrap(),
+            FromWireError::InsufficientData
+        ));
+    }
+
+    #[test]
+    fn append_to_wire() {
+        let mut buf = BytesMut::new();
+        PathChallenge {
+            token: 0x0123_4567_89ab_cdef,
+        }
+        .append_to_wire(&mut buf);
+        assert_eq!(&buf[..], b"\x01\x23\x45\x67\x89\xab\xcd\xef");
+    }
+}
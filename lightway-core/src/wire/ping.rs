@@ -63,6 +63,74 @@ impl Ping {
     }
 }
 
+/// Trailer of keepalive [`Ping`] payloads, echoed by the peer at the end
+/// of its [`super::Pong`] payload to measure round trip times.
+///
+/// The trailer ends with the [`Self::TAG`] and [`Self::VERSION`] bytes
+/// identifying it. Keepalive payloads are otherwise either empty or hold
+/// 16 bytes of expresslane counters, which never have the length of a
+/// trailer. Trailers of another version are ignored like those of peers
+/// not knowing the trailer, which ignore it in pings and never echo it.
+///
+/// Wire Format (10 bytes):
+///
+/// ```text
+///  0                   1                   2                   3
+///  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                           sequence                            |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                     timestamp (milliseconds)                  |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |      tag      |    version    |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct KeepaliveStamp {
+    /// Sequence number of the keepalive
+    pub(crate) sequence: u32,
+    /// Send time of the keepalive, only meaningful to its sender
+    pub(crate) timestamp: u32,
+}
+
+impl KeepaliveStamp {
+    /// Size of the trailer in bytes
+    pub(crate) const WIRE_SIZE: usize = 10;
+
+    /// Tag identifying the trailer
+    pub(crate) const TAG: u8 = b'K';
+
+    /// Version of the trailer format
+    pub(crate) const VERSION: u8 = 1;
+
+    /// Split the trailer off a keepalive payload, returning the rest of
+    /// the payload.
+    pub(crate) fn split_from(mut payload: Bytes) -> (Bytes, Option<Self>) {
+        if payload.len() % 16 != Self::WIRE_SIZE
+            || payload[payload.len() - 2..] != [Self::TAG, Self::VERSION]
+        {
+            return (payload, None);
+        }
+        let mut trailer = payload.split_off(payload.len() - Self::WIRE_SIZE);
+        let stamp = Self {
+            sequence: trailer.get_u32(),
+            timestamp: trailer.get_u32(),
+        };
+        (payload, Some(stamp))
+    }
+
+    /// Append the trailer to a keepalive payload.
+    pub(crate) fn append_to(&self, payload: Bytes) -> Bytes {
+        let mut buf = BytesMut::with_capacity(payload.len() + Self::WIRE_SIZE);
+        buf.put(payload);
+        buf.put_u32(self.sequence);
+        buf.put_u32(self.timestamp);
+        buf.put_u8(Self::TAG);
+        buf.put_u8(Self::VERSION);
+        buf.freeze()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -121,6 +189,30 @@ mod tests {
         );
     }
 
+    const STAMP: KeepaliveStamp = KeepaliveStamp {
+        sequence: 0x01020304,
+        timestamp: 0x05060708,
+    };
+
+    #[test_case(&[] => (0, Some(STAMP)); "stamp only")]
+    #[test_case(&[0xaa; 16] => (16, Some(STAMP)); "expresslane counters")]
+    fn keepalive_stamp_round_trip(payload: &'static [u8]) -> (usize, Option<KeepaliveStamp>) {
+        let stamped = STAMP.append_to(Bytes::from_static(payload));
+        let (rest, stamp) = KeepaliveStamp::split_from(stamped);
+        assert_eq!(rest, payload);
+        (rest.len(), stamp)
+    }
+
+    #[test_case(&[]; "empty")]
+    #[test_case(&[0xaa; 16]; "expresslane counters")]
+    #[test_case(b"\x01\x02\x03\x04\x05\x06\x07\x08K\x02"; "other version")]
+    #[test_case(b"\x01\x02\x03\x04\x05\x06\x07\x08\x00\x01"; "no tag")]
+    fn keepalive_stamp_absent(payload: &'static [u8]) {
+        let (rest, stamp) = KeepaliveStamp::split_from(Bytes::from_static(payload));
+        assert_eq!(rest, payload);
+        assert_eq!(stamp, None);
+    }
+
     #[test_case(Ping { id: 0xdee4, payload: Default::default() } => b"\xde\xe4\x00\x00".to_vec(); "no payload")]
     #[test_case(Ping { id: 0xdee4, payload: Bytes::from_static(b"\xff\xfe") } => b"\xde\xe4\x00\x02\xff\xfe".to_vec(); "payload")]
     fn append_to_wire(ping: Ping) -> Vec<u8> {
@@ -24,7 +24,7 @@ pub(crate) struct ServerConfig {
 
 impl ServerConfig {
     /// The maximum number of bytes in the buffer.
-    const MAX_SERVER_CONFIG_BYTES: usize = 1350 - std::mem::size_of::<u16>();
+    pub(crate) const MAX_SERVER_CONFIG_BYTES: usize = 1350 - std::mem::size_of::<u16>();
 
     pub(crate) fn try_from_wire(buf: &mut BorrowedBytesMut) -> FromWireResult<Self> {
         if buf.len() < 2 {
@@ -511,7 +511,7 @@ pub async fn client<S: TestSock>(
                     );
                 }
                 Event::StateChanged(state) => eprintln!("Connection change to {state:?}"),
-                Event::KeepaliveReply => eprintln!("Got keepalive reply"),
+                Event::KeepaliveReply { .. } => eprintln!("Got keepalive reply"),
                 Event::SessionIdRotationStarted { .. } => {
                     eprintln!("Got SessionIdRotationStarted")
                 }
@@ -533,6 +533,10 @@ pub async fn client<S: TestSock>(
                 Event::EncodingStateChanged { enabled } => {
                     println!("Encoding state change to {enabled}")
                 }
+                Event::ServerConfig(data) => println!("Got ServerConfig of {} bytes", data.len()),
+                Event::SessionTicket(ticket) => {
+                    println!("Got SessionTicket of {} bytes", ticket.len())
+                }
             }
         }
     });
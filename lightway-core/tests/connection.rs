@@ -592,6 +592,180 @@ async fn server_nudge_rotates_both_ends_while_client_is_idle() {
         .expect("server nudge did not cascade into a client rotation");
 }
 
+/// UDP socket sending to `peer`. On the server, `peer` follows the
+/// session to the address it was last heard from.
+struct UdpPeerSock {
+    sock: Arc<tokio::net::UdpSocket>,
+    peer: Mutex<std::net::SocketAddr>,
+}
+
+impl UdpPeerSock {
+    fn new(sock: Arc<tokio::net::UdpSocket>, peer: std::net::SocketAddr) -> Arc<Self> {
+        Arc::new(Self {
+            sock,
+            peer: Mutex::new(peer),
+        })
+    }
+}
+
+impl OutsideIOSendCallback for UdpPeerSock {
+    fn send(&self, buf: &[u8]) -> IOCallbackResult<usize> {
+        // Drop rather than block, as TestDatagramSock does
+        let _ = self.sock.try_send_to(buf, *self.peer.lock().unwrap());
+        IOCallbackResult::Ok(buf.len())
+    }
+
+    fn send_gso(&self, _bufs: &[std::io::IoSlice<'_>], _gso_size: u16) -> IOCallbackResult<usize> {
+        IOCallbackResult::Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+
+    fn peer_addr(&self) -> std::net::SocketAddr {
+        *self.peer.lock().unwrap()
+    }
+
+    fn set_peer_addr(&self, addr: std::net::SocketAddr) -> std::net::SocketAddr {
+        std::mem::replace(&mut *self.peer.lock().unwrap(), addr)
+    }
+}
+
+/// What a client migrating to a new outside socket relies on: the
+/// connection keeps its DTLS session across `set_outside_io`, and the
+/// server, finding the session by the ID in the header of a datagram from
+/// an unknown address, follows it there.
+#[tokio::test]
+async fn datagram_session_survives_outside_socket_swap() {
+    let pki = gen_shared_testing_pki();
+
+    let bind = || tokio::net::UdpSocket::bind("127.0.0.1:0");
+    let server_sock = Arc::new(bind().await.unwrap());
+    let old_sock = Arc::new(bind().await.unwrap());
+    let new_sock = Arc::new(bind().await.unwrap());
+    let server_addr = server_sock.local_addr().unwrap();
+    let new_addr = new_sock.local_addr().unwrap();
+
+    let (server_tun, mut server_inside_rx) = ChannelTun::new();
+    let (server_ticker, server_ticker_task) = ConnectionTicker::new();
+    let server = ServerContextBuilder::<ConnectionTicker>::new(
+        ConnectionType::Datagram,
+        Secret::Asn1Buffer(&pki.server.cert_der),
+        Secret::Asn1Buffer(&pki.server.key_der),
+        Arc::new(TestAuth::default()),
+        Arc::new(StaticIpPool),
+        Arc::new(server_tun),
+        connection_ticker_cb,
+    )
+    .unwrap()
+    .with_minimum_protocol_version(Version::MINIMUM)
+    .unwrap()
+    .with_maximum_protocol_version(Version::MAXIMUM)
+    .unwrap()
+    .build()
+    .unwrap()
+    .start_accept(
+        Version::MAXIMUM,
+        UdpPeerSock::new(server_sock.clone(), old_sock.local_addr().unwrap()),
+    )
+    .unwrap()
+    .accept(server_ticker)
+    .unwrap();
+    let server = Arc::new(Mutex::new(server));
+
+    let (client_tun, mut client_inside_rx) = ChannelTun::new();
+    let (client_ticker, client_ticker_task) = ConnectionTicker::new();
+    let client = ClientContextBuilder::new(
+        ConnectionType::Datagram,
+        RootCertificate::Asn1Buffer(&pki.ca_cert_der),
+        Some(Arc::new(client_tun)),
+        Arc::new(Client),
+        connection_ticker_cb,
+    )
+    .unwrap()
+    .build()
+    .start_connect(
+        UdpPeerSock::new(old_sock.clone(), server_addr),
+        MAX_OUTSIDE_MTU,
+    )
+    .unwrap()
+    .with_auth_token("LET ME IN")
+    .connect(ConnectionState {
+        ticker: client_ticker,
+    })
+    .unwrap();
+    let client = Arc::new(Mutex::new(client));
+
+    let mut join_set = JoinSet::new();
+    server_ticker_task.spawn_in(Arc::downgrade(&server), &mut join_set);
+    client_ticker_task.spawn_in(Arc::downgrade(&client), &mut join_set);
+
+    let message = BytesMut::from(&b"\x40Hello World!"[..]);
+    let mut sent = false;
+    let mut migrated = false;
+
+    let test = async move {
+        loop {
+            let mut buf = BytesMut::with_capacity(MAX_OUTSIDE_MTU);
+            tokio::select! {
+                received = server_sock.recv_buf_from(&mut buf) => {
+                    let (_, from) = received.unwrap();
+                    let mut server = server.lock().unwrap();
+                    let floated = from != server.peer_addr();
+                    if floated {
+                        let header = Header::try_from_wire(&mut buf.clone()).unwrap();
+                        assert_eq!(header.session, server.session_id());
+                    }
+                    let frames = server
+                        .outside_data_received(OutsidePacket::Wire(&mut buf, ConnectionType::Datagram))
+                        .unwrap();
+                    // Only once the datagram proved to belong to the session
+                    if floated && frames > 0 {
+                        server.set_peer_addr(from);
+                    }
+                }
+                Some(reflect) = server_inside_rx.recv() => {
+                    let mut reflect = BytesMut::from(&reflect[..]);
+                    server.lock().unwrap().inside_data_received(&mut reflect).unwrap();
+                }
+                received = old_sock.recv_buf(&mut buf) => {
+                    received.unwrap();
+                    let mut client = client.lock().unwrap();
+                    client
+                        .outside_data_received(OutsidePacket::Wire(&mut buf, ConnectionType::Datagram))
+                        .unwrap();
+                    if !sent && matches!(client.state(), State::Online) {
+                        client.inside_data_received(&mut message.clone()).unwrap();
+                        sent = true;
+                    }
+                }
+                received = new_sock.recv_buf(&mut buf) => {
+                    received.unwrap();
+                    assert!(migrated);
+                    client
+                        .lock()
+                        .unwrap()
+                        .outside_data_received(OutsidePacket::Wire(&mut buf, ConnectionType::Datagram))
+                        .unwrap();
+                }
+                Some(reflected) = client_inside_rx.recv() => {
+                    assert_eq!(&reflected[..], &message[..]);
+                    let mut client = client.lock().unwrap();
+                    if migrated {
+                        assert_eq!(server.lock().unwrap().peer_addr(), new_addr);
+                        client.disconnect().unwrap();
+                        return;
+                    }
+                    client.set_outside_io(UdpPeerSock::new(new_sock.clone(), server_addr));
+                    migrated = true;
+                    client.inside_data_received(&mut message.clone()).unwrap();
+                }
+            }
+        }
+    };
+
+    tokio::time::timeout(std::time::Duration::from_millis(get_test_timeout()), test)
+        .await
+        .expect("Timed out");
+}
+
 /// Drives a client/server pair through repeated keepalive windows and
 /// reports how the client's expresslane health check reacted: the final
 /// state, which window (if any) first saw it go Degraded, and how many
@@ -723,7 +897,7 @@ async fn expresslane_health_probe(
                             }
                             el_state = s;
                         }
-                        Event::KeepaliveReply => {
+                        Event::KeepaliveReply { .. } => {
                             windows_done += 1;
                             if windows_done < WINDOWS {
                                 send_burst(&conn).await;
@@ -2,18 +2,26 @@ use std::{
     collections::{HashMap, HashSet},
     fs::File,
     io::{BufRead as _, BufReader, Read},
+    net::AddrParseError,
+    ops::RangeInclusive,
     path::Path,
 };
 
 use anyhow::{Context, Result, anyhow};
+use ipnet::Ipv4Net;
 use jsonwebtoken::{Algorithm, DecodingKey, Validation};
 use lightway_core::LightwayFeature;
 use pwhash::unix;
+use serde::Deserialize;
 
-use lightway_server::{ServerAuth, ServerAuthHandle, ServerAuthResult};
+use lightway_server::{
+    Acl, AclProtocol, AclRule, InsideIpRequest, ServerAuth, ServerAuthHandle, ServerAuthResult,
+};
 
 pub struct Auth {
     user_db: Option<HashMap<String, String>>,
+    /// Subnets each user may have behind it
+    user_subnets: HashMap<String, Vec<Ipv4Net>>,
     token: Option<(DecodingKey, Validation)>,
 }
 
@@ -61,7 +69,11 @@ fn token_from_reader(mut r: impl Read) -> Result<(DecodingKey, Validation)> {
 }
 
 impl Auth {
-    pub fn new(user_db: Option<&Path>, token_rsa_pub_key_pem: Option<&Path>) -> Result<Self> {
+    pub fn new(
+        user_db: Option<&Path>,
+        user_subnets: HashMap<String, Vec<Ipv4Net>>,
+        token_rsa_pub_key_pem: Option<&Path>,
+    ) -> Result<Self> {
         let user_db = user_db
             .map(|path| -> Result<_> {
                 user_db_from_reader(File::open(path)?)
@@ -80,12 +92,123 @@ impl Auth {
             return Err(anyhow!("Neither user db nor token public key provided"));
         }
 
-        Ok(Self { user_db, token })
+        Ok(Self {
+            user_db,
+            user_subnets,
+            token,
+        })
     }
 }
 
 #[derive(Debug)]
-struct AuthHandle;
+struct AuthHandle {
+    /// User name or token subject
+    identity: Option<String>,
+    /// Inside IP or pool from the token
+    inside_ip: Option<InsideIpRequest>,
+    /// Network access from the token
+    acl: Option<Acl>,
+    /// Subnets the client may have behind it, from the token or
+    /// `user_subnets`
+    subnets: Vec<Ipv4Net>,
+}
+
+/// Inside IP requested by the "inside_ip" or "ip_pool" claim of a token
+fn inside_ip_from_claims(
+    claims: &serde_json::Value,
+) -> Result<Option<InsideIpRequest>, AddrParseError> {
+    if let Some(ip) = claims["inside_ip"].as_str() {
+        return Ok(Some(InsideIpRequest::Ip(ip.parse()?)));
+    }
+    Ok(claims["ip_pool"]
+        .as_str()
+        .map(|pool| InsideIpRequest::Pool(pool.to_string())))
+}
+
+/// "acl" claim of a token
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AclClaim {
+    #[serde(default)]
+    allow: Vec<AclRuleClaim>,
+    #[serde(default)]
+    deny: Vec<AclRuleClaim>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AclRuleClaim {
+    network: Ipv4Net,
+    protocol: Option<AclProtocolClaim>,
+    /// A port, e.g. "443", or a range of ports, e.g. "8000-8999"
+    ports: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum AclProtocolClaim {
+    Tcp,
+    Udp,
+    Icmp,
+}
+
+impl From<AclProtocolClaim> for AclProtocol {
+    fn from(protocol: AclProtocolClaim) -> Self {
+        match protocol {
+            AclProtocolClaim::Tcp => AclProtocol::Tcp,
+            AclProtocolClaim::Udp => AclProtocol::Udp,
+            AclProtocolClaim::Icmp => AclProtocol::Icmp,
+        }
+    }
+}
+
+fn parse_ports(ports: &str) -> Result<RangeInclusive<u16>> {
+    let (first, last) = ports.split_once('-').unwrap_or((ports, ports));
+    let (first, last) = (first.trim().parse()?, last.trim().parse()?);
+    if first > last {
+        return Err(anyhow!("Invalid port range {ports}"));
+    }
+    Ok(first..=last)
+}
+
+impl TryFrom<AclRuleClaim> for AclRule {
+    type Error = anyhow::Error;
+
+    fn try_from(claim: AclRuleClaim) -> Result<Self> {
+        let mut rule = AclRule::new(claim.network);
+        if let Some(protocol) = claim.protocol {
+            rule = rule.with_protocol(protocol.into());
+        }
+        if let Some(ports) = claim.ports {
+            rule = rule.with_ports(parse_ports(&ports)?);
+        }
+        Ok(rule)
+    }
+}
+
+/// Network access granted by the "acl" claim of a token
+fn acl_from_claims(claims: &serde_json::Value) -> Result<Option<Acl>> {
+    let Some(acl) = claims.get("acl") else {
+        return Ok(None);
+    };
+    let acl = AclClaim::deserialize(acl)?;
+    let rules = |claims: Vec<AclRuleClaim>| -> Result<Vec<AclRule>> {
+        claims.into_iter().map(AclRule::try_from).collect()
+    };
+
+    Ok(Some(Acl {
+        allow: rules(acl.allow)?,
+        deny: rules(acl.deny)?,
+    }))
+}
+
+/// Subnets behind the client listed by the "subnets" claim of a token
+fn subnets_from_claims(claims: &serde_json::Value) -> Result<Vec<Ipv4Net>> {
+    let Some(subnets) = claims.get("subnets") else {
+        return Ok(Vec::new());
+    };
+    Ok(Vec::deserialize(subnets)?)
+}
 
 impl ServerAuthHandle for AuthHandle {
     fn expired(&self) -> bool {
@@ -93,7 +216,23 @@ impl ServerAuthHandle for AuthHandle {
     }
 
     fn features(&self) -> HashSet<LightwayFeature> {
-        HashSet::from([LightwayFeature::InsidePktCodec])
+        HashSet::from([LightwayFeature::InsidePktCodec, LightwayFeature::Multipath])
+    }
+
+    fn identity(&self) -> Option<&str> {
+        self.identity.as_deref()
+    }
+
+    fn inside_ip(&self) -> Option<InsideIpRequest> {
+        self.inside_ip.clone()
+    }
+
+    fn acl(&self) -> Option<Acl> {
+        self.acl.clone()
+    }
+
+    fn subnets(&self) -> Vec<Ipv4Net> {
+        self.subnets.clone()
     }
 }
 
@@ -115,7 +254,12 @@ impl<AS> ServerAuth<AS> for Auth {
 
         if unix::verify(password, hash) {
             ServerAuthResult::Granted {
-                handle: Some(Box::new(AuthHandle)),
+                handle: Some(Box::new(AuthHandle {
+                    identity: Some(user.to_string()),
+                    inside_ip: None,
+                    acl: None,
+                    subnets: self.user_subnets.get(user).cloned().unwrap_or_default(),
+                })),
                 tunnel_protocol_version: None,
             }
         } else {
@@ -129,15 +273,50 @@ impl<AS> ServerAuth<AS> for Auth {
             return ServerAuthResult::Denied;
         };
 
-        match jsonwebtoken::decode::<serde_json::Value>(token, decoding_key, token_validation) {
-            Ok(_) => ServerAuthResult::Granted {
-                handle: Some(Box::new(AuthHandle)),
-                tunnel_protocol_version: None,
-            },
+        let token = match jsonwebtoken::decode::<serde_json::Value>(
+            token,
+            decoding_key,
+            token_validation,
+        ) {
+            Ok(token) => token,
             Err(err) => {
                 tracing::info!(?err, "Invalid token");
-                ServerAuthResult::Denied
+                return ServerAuthResult::Denied;
+            }
+        };
+
+        let inside_ip = match inside_ip_from_claims(&token.claims) {
+            Ok(inside_ip) => inside_ip,
+            Err(err) => {
+                tracing::info!(?err, "Invalid inside_ip claim");
+                return ServerAuthResult::Denied;
+            }
+        };
+
+        let acl = match acl_from_claims(&token.claims) {
+            Ok(acl) => acl,
+            Err(err) => {
+                tracing::info!(?err, "Invalid acl claim");
+                return ServerAuthResult::Denied;
+            }
+        };
+
+        let subnets = match subnets_from_claims(&token.claims) {
+            Ok(subnets) => subnets,
+            Err(err) => {
+                tracing::info!(?err, "Invalid subnets claim");
+                return ServerAuthResult::Denied;
             }
+        };
+
+        ServerAuthResult::Granted {
+            handle: Some(Box::new(AuthHandle {
+                identity: token.claims["sub"].as_str().map(str::to_string),
+                inside_ip,
+                acl,
+                subnets,
+            })),
+            tunnel_protocol_version: None,
         }
     }
 }
@@ -190,6 +369,7 @@ apachemd5_user:$apr1$dzIISjZV$itIp3R9OU32h.vQ0tm9rm/";
         assert_eq!(db.len(), 5);
         let auth = Auth {
             user_db: Some(db),
+            user_subnets: HashMap::new(),
             token: None,
         };
         auth.authorize_user_password(user, pass, &mut ())
@@ -201,6 +381,7 @@ apachemd5_user:$apr1$dzIISjZV$itIp3R9OU32h.vQ0tm9rm/";
         assert_eq!(db.len(), 5);
         let auth = Auth {
             user_db: Some(db),
+            user_subnets: HashMap::new(),
             token: None,
         };
         let r = auth.authorize_user_password(user, pass, &mut ());
@@ -211,10 +392,33 @@ apachemd5_user:$apr1$dzIISjZV$itIp3R9OU32h.vQ0tm9rm/";
         );
     }
 
+    #[test_case("bcrypt_user" => vec!["192.168.10.0/24".parse::<Ipv4Net>().unwrap()])]
+    #[test_case("sha256_user" => Vec::<Ipv4Net>::new())]
+    fn user_pass_auth_subnets(user: &str) -> Vec<Ipv4Net> {
+        let db = user_db_from_reader(Cursor::new(LWPASSWD)).unwrap();
+        let auth = Auth {
+            user_db: Some(db),
+            user_subnets: HashMap::from([(
+                "bcrypt_user".to_string(),
+                vec!["192.168.10.0/24".parse().unwrap()],
+            )]),
+            token: None,
+        };
+        let password = user.replace("_user", "_password");
+        match auth.authorize_user_password(user, &password, &mut ()) {
+            ServerAuthResult::Granted {
+                handle: Some(handle),
+                ..
+            } => handle.subnets(),
+            r => panic!("Unexpected {r:?}"),
+        }
+    }
+
     #[test]
     fn no_user_db() {
         let auth = Auth {
             user_db: None,
+            user_subnets: HashMap::new(),
             token: None,
         };
         let r = auth.authorize_user_password("user", "pass", &mut ());
@@ -306,6 +510,7 @@ wwIDAQAB
     fn token_auth(pubkey: &[u8], token: &str) -> ServerAuthResult {
         let auth = Auth {
             user_db: None,
+            user_subnets: HashMap::new(),
             token: Some(token_from_reader(Cursor::new(pubkey)).unwrap()),
         };
         auth.authorize_token(token, &mut ())
@@ -315,6 +520,7 @@ wwIDAQAB
     fn token_auth_can_use_inside_pkt_encoding() {
         let auth = Auth {
             user_db: None,
+            user_subnets: HashMap::new(),
             token: Some(token_from_reader(Cursor::new(RSA_PUB)).unwrap()),
         };
         let token = &make_token(Algorithm::RS256, json!({"exp": future_timestamp()}));
@@ -326,10 +532,102 @@ wwIDAQAB
         );
     }
 
+    #[test_case(json!({"exp": future_timestamp(), "sub": "alice"}) => Some("alice".to_string()))]
+    #[test_case(json!({"exp": future_timestamp()}) => None)]
+    fn token_auth_identity(claims: serde_json::Value) -> Option<String> {
+        let auth = Auth {
+            user_db: None,
+            user_subnets: HashMap::new(),
+            token: Some(token_from_reader(Cursor::new(RSA_PUB)).unwrap()),
+        };
+        let token = &make_token(Algorithm::RS256, claims);
+        let ServerAuthResult::Granted {
+            handle: Some(handle),
+            ..
+        } = auth.authorize_token(token, &mut ())
+        else {
+            panic!("Token not granted");
+        };
+        handle.identity().map(str::to_string)
+    }
+
+    #[test_case(json!({"exp": future_timestamp(), "inside_ip": "10.125.0.10"}) => Some(Some(InsideIpRequest::Ip("10.125.0.10".parse().unwrap()))))]
+    #[test_case(json!({"exp": future_timestamp(), "ip_pool": "admins"}) => Some(Some(InsideIpRequest::Pool("admins".to_string()))))]
+    #[test_case(json!({"exp": future_timestamp()}) => Some(None))]
+    #[test_case(json!({"exp": future_timestamp(), "inside_ip": "not an ip"}) => None)]
+    fn token_auth_inside_ip(claims: serde_json::Value) -> Option<Option<InsideIpRequest>> {
+        let auth = Auth {
+            user_db: None,
+            user_subnets: HashMap::new(),
+            token: Some(token_from_reader(Cursor::new(RSA_PUB)).unwrap()),
+        };
+        let token = &make_token(Algorithm::RS256, claims);
+        match auth.authorize_token(token, &mut ()) {
+            ServerAuthResult::Granted {
+                handle: Some(handle),
+                ..
+            } => Some(handle.inside_ip()),
+            _ => None,
+        }
+    }
+
+    #[test_case(json!({"exp": future_timestamp()}) => Some(None))]
+    #[test_case(json!({"exp": future_timestamp(), "acl": {
+        "allow": [{"network": "10.0.0.0/8"}, {"network": "0.0.0.0/0", "protocol": "udp", "ports": "53"}],
+        "deny": [{"network": "10.1.0.0/16", "protocol": "tcp", "ports": "8000-8999"}],
+    }}) => Some(Some(Acl {
+        allow: vec![
+            AclRule::new("10.0.0.0/8".parse().unwrap()),
+            AclRule::new("0.0.0.0/0".parse().unwrap()).with_protocol(AclProtocol::Udp).with_ports(53..=53),
+        ],
+        deny: vec![
+            AclRule::new("10.1.0.0/16".parse().unwrap()).with_protocol(AclProtocol::Tcp).with_ports(8000..=8999),
+        ],
+    })))]
+    #[test_case(json!({"exp": future_timestamp(), "acl": {"allow": [{"network": "not a network"}]}}) => None)]
+    #[test_case(json!({"exp": future_timestamp(), "acl": {"allow": [{"network": "10.0.0.0/8", "ports": "99-1"}]}}) => None)]
+    #[test_case(json!({"exp": future_timestamp(), "acl": {"allow": [{"network": "10.0.0.0/8", "protocol": "sctp"}]}}) => None)]
+    fn token_auth_acl(claims: serde_json::Value) -> Option<Option<Acl>> {
+        let auth = Auth {
+            user_db: None,
+            user_subnets: HashMap::new(),
+            token: Some(token_from_reader(Cursor::new(RSA_PUB)).unwrap()),
+        };
+        let token = &make_token(Algorithm::RS256, claims);
+        match auth.authorize_token(token, &mut ()) {
+            ServerAuthResult::Granted {
+                handle: Some(handle),
+                ..
+            } => Some(handle.acl()),
+            _ => None,
+        }
+    }
+
+    #[test_case(json!({"exp": future_timestamp()}) => Some(vec![]))]
+    #[test_case(json!({"exp": future_timestamp(), "subnets": ["192.168.10.0/24", "172.16.0.0/12"]}) => Some(vec!["192.168.10.0/24".parse().unwrap(), "172.16.0.0/12".parse().unwrap()]))]
+    #[test_case(json!({"exp": future_timestamp(), "subnets": ["192.168.10.0"]}) => None)]
+    #[test_case(json!({"exp": future_timestamp(), "subnets": "192.168.10.0/24"}) => None)]
+    fn token_auth_subnets(claims: serde_json::Value) -> Option<Vec<Ipv4Net>> {
+        let auth = Auth {
+            user_db: None,
+            user_subnets: HashMap::new(),
+            token: Some(token_from_reader(Cursor::new(RSA_PUB)).unwrap()),
+        };
+        let token = &make_token(Algorithm::RS256, claims);
+        match auth.authorize_token(token, &mut ()) {
+            ServerAuthResult::Granted {
+                handle: Some(handle),
+                ..
+            } => Some(handle.subnets()),
+            _ => None,
+        }
+    }
+
     #[test]
     fn no_token() {
         let auth = Auth {
             user_db: None,
+            user_subnets: HashMap::new(),
             token: None,
         };
         let r = auth.authorize_token(&make_token(Algorithm::RS256, json!({})), &mut ());
@@ -0,0 +1,292 @@
+//! Cluster of servers sharing UDP sessions
+//!
+//! A UDP client whose datagrams start hitting another server behind a
+//! load balancer (ECMP rehash, anycast change...) is otherwise rejected
+//! there and has to connect again. In a cluster, the nodes record in a
+//! [`SessionDirectory`] which node owns each session. A node receiving
+//! a datagram of a session it does not have forwards it, as it is
+//! still DTLS protected, to the owner over the cluster channel, a
+//! node-to-node UDP socket. The owner handles the datagram as if it
+//! had received it itself, replying to the client directly. Messages
+//! on the cluster channel are authenticated with a [`ClusterKey`]
+//! shared by the nodes, and numbered so that replayed ones are dropped.
+
+mod directory;
+mod wire;
+
+use anyhow::{Context, Result};
+use aws_lc_rs::hmac;
+use bytes::BytesMut;
+use lightway_core::{Header, MAX_OUTSIDE_MTU, OutsidePacket, SessionId};
+use std::{collections::HashMap, net::SocketAddr, path::Path, str::FromStr, sync::Arc};
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+
+pub use directory::{InMemorySessionDirectory, SessionDirectory};
+
+use crate::{connection_manager::ConnectionManager, io::outside::udp::frame_received, metrics};
+use directory::NetworkSessionDirectory;
+use wire::{Message, ReplayWindow, Sealer};
+
+/// Largest message on the cluster channel: a forwarded frame, its
+/// header, the address of its client, the sequence number and the tag
+const MAX_MESSAGE_SIZE: usize = 20 + Header::WIRE_SIZE + MAX_OUTSIDE_MTU + wire::TRAILER_LEN;
+
+/// Key shared by the nodes of a cluster to authenticate the messages on
+/// the cluster channel.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ClusterKey([u8; Self::LEN]);
+
+impl ClusterKey {
+    /// Length of a key in bytes
+    pub const LEN: usize = 32;
+
+    /// Read a hex encoded key from `path`.
+    pub fn read(path: &Path) -> Result<Self> {
+        let key = std::fs::read_to_string(path)
+            .with_context(|| format!("Reading cluster key {}", path.display()))?;
+        key.parse()
+            .with_context(|| format!("Parsing cluster key {}", path.display()))
+    }
+
+    fn hmac_key(&self) -> hmac::Key {
+        hmac::Key::new(hmac::HMAC_SHA256, &self.0)
+    }
+}
+
+/// Parses `LEN` bytes of hex, e.g. the output of `openssl rand -hex 32`.
+impl FromStr for ClusterKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        anyhow::ensure!(
+            s.is_ascii() && s.len() == Self::LEN * 2,
+            "Cluster key must be {} hex digits",
+            Self::LEN * 2
+        );
+        let mut key = [0; Self::LEN];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16)
+                .context("Cluster key must be hex digits")?;
+        }
+        Ok(Self(key))
+    }
+}
+
+impl std::fmt::Debug for ClusterKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ClusterKey(..)")
+    }
+}
+
+/// Cluster settings of a node
+#[derive(Clone, educe::Educe)]
+#[educe(Debug)]
+pub struct ClusterConfig {
+    /// Address of the cluster channel of this node
+    pub addr: SocketAddr,
+
+    /// Addresses of the cluster channels of the other nodes. Messages
+    /// from any other address are dropped.
+    pub peers: Vec<SocketAddr>,
+
+    /// Key of the cluster, messages not authenticated with it are
+    /// dropped
+    pub key: ClusterKey,
+
+    /// Directory of the owners of the sessions. When `None`, each node
+    /// announces its sessions to `peers` over the cluster channel.
+    #[educe(Debug(ignore))]
+    pub directory: Option<Arc<dyn SessionDirectory>>,
+}
+
+pub(crate) struct Cluster {
+    /// Address of the cluster channel of this node
+    node: SocketAddr,
+    sock: Arc<UdpSocket>,
+    peers: Vec<SocketAddr>,
+    key: hmac::Key,
+    sealer: Arc<Sealer>,
+    directory: Arc<dyn SessionDirectory>,
+}
+
+impl Cluster {
+    pub(crate) async fn bind(config: ClusterConfig) -> Result<Arc<Self>> {
+        let sock = Arc::new(
+            UdpSocket::bind(config.addr)
+                .await
+                .with_context(|| format!("Binding cluster channel {}", config.addr))?,
+        );
+        let key = config.key.hmac_key();
+        let sealer = Arc::new(Sealer::new(key.clone()));
+        let directory = config.directory.unwrap_or_else(|| {
+            Arc::new(NetworkSessionDirectory::new(
+                config.addr,
+                sock.clone(),
+                config.peers.clone(),
+                sealer.clone(),
+            ))
+        });
+
+        Ok(Arc::new(Self {
+            node: config.addr,
+            sock,
+            peers: config.peers,
+            key,
+            sealer,
+            directory,
+        }))
+    }
+
+    /// Record this node as the owner of `session`
+    pub(crate) fn own(&self, session: SessionId) {
+        self.directory.insert(session, self.node);
+    }
+
+    /// Record this node as no longer owning `session`
+    pub(crate) fn release(&self, session: SessionId) {
+        self.directory.remove(session, self.node);
+    }
+
+    /// The other node owning `session`, `None` if it is unknown or
+    /// owned by this node
+    pub(crate) fn owner(&self, session: SessionId) -> Option<SocketAddr> {
+        self.directory
+            .owner(session)
+            .filter(|owner| *owner != self.node)
+    }
+
+    /// Forward `pkt`, received from `peer_addr`, to `owner`
+    pub(crate) fn forward(&self, owner: SocketAddr, peer_addr: SocketAddr, pkt: OutsidePacket) {
+        let OutsidePacket::UdpFrame(payload, header) = pkt else {
+            return;
+        };
+
+        let mut buf = BytesMut::with_capacity(MAX_MESSAGE_SIZE);
+        Message::Forward { peer_addr, header }.append_to_wire(&mut buf);
+        buf.extend_from_slice(payload);
+        self.sealer.seal(&mut buf);
+
+        match self.sock.try_send_to(&buf, owner) {
+            Ok(_) => metrics::udp_session_forwarded(),
+            Err(_) => metrics::cluster_forward_failed(),
+        }
+    }
+
+    /// Receive messages from the other nodes
+    pub(crate) async fn run(self: Arc<Self>, conn_manager: Arc<ConnectionManager>) -> Result<()> {
+        info!("Cluster channel on {}", self.node);
+
+        let mut buf = BytesMut::with_capacity(MAX_MESSAGE_SIZE);
+        let mut replay_windows = HashMap::new();
+        loop {
+            // Recover full capacity
+            buf.clear();
+            buf.reserve(MAX_MESSAGE_SIZE);
+
+            let (_, from) = self.sock.recv_buf_from(&mut buf).await?;
+            let Some(msg) = self.open(&mut replay_windows, from, &mut buf) else {
+                continue;
+            };
+
+            match msg {
+                Message::Forward { peer_addr, header } => {
+                    // Never forwarded again: if this node does not own
+                    // the session (anymore), the nodes disagree on who
+                    // does.
+                    let conn =
+                        conn_manager.find_datagram_connection_by_session(peer_addr, header.session);
+                    match conn {
+                        Ok((conn, update_peer_address)) => frame_received(
+                            &conn_manager,
+                            conn,
+                            update_peer_address,
+                            peer_addr,
+                            OutsidePacket::UdpFrame(&mut buf, header),
+                        ),
+                        Err(_e) => metrics::cluster_forwarded_unknown_session(),
+                    }
+                }
+                Message::Owned(session) => self.directory.insert(session, from),
+                Message::Released(session) => self.directory.remove(session, from),
+            }
+        }
+    }
+
+    /// Authenticate and decode the message in `buf`, received from
+    /// `from`, leaving the payload of a `Forward` in `buf`. Returns
+    /// `None` if the message is to be dropped.
+    fn open(
+        &self,
+        replay_windows: &mut HashMap<SocketAddr, ReplayWindow>,
+        from: SocketAddr,
+        buf: &mut BytesMut,
+    ) -> Option<Message> {
+        if !self.peers.contains(&from) {
+            metrics::cluster_bad_message();
+            warn!("Dropping cluster message from unknown node {from}");
+            return None;
+        }
+        let Some(seq) = wire::open(&self.key, buf) else {
+            metrics::cluster_bad_message();
+            warn!("Dropping unauthenticated cluster message from {from}");
+            return None;
+        };
+        // Reordered datagrams may fall out of the window too, so this
+        // is not worth a warning
+        let replay_window = replay_windows.entry(from).or_default();
+        if !replay_window.check(seq, wire::now_micros()) {
+            metrics::cluster_replayed_message();
+            debug!("Dropping replayed or stale cluster message from {from}");
+            return None;
+        }
+
+        Message::try_from_wire(buf)
+            .inspect_err(|e| {
+                metrics::cluster_bad_message();
+                warn!("Decoding cluster message from {from} failed: {e}");
+            })
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SESSION: SessionId = SessionId::from_const([1, 2, 3, 4, 5, 6, 7, 8]);
+
+    #[tokio::test]
+    async fn replayed_announcements_are_dropped() {
+        let peer: SocketAddr = "10.0.0.2:27700".parse().unwrap();
+        let key = ClusterKey([7; ClusterKey::LEN]);
+        let cluster = Cluster::bind(ClusterConfig {
+            addr: "127.0.0.1:0".parse().unwrap(),
+            peers: vec![peer],
+            key: key.clone(),
+            directory: Some(Arc::new(InMemorySessionDirectory::default())),
+        })
+        .await
+        .unwrap();
+
+        let peer_sealer = Sealer::new(key.hmac_key());
+        let sealed = |msg: Message| {
+            let mut buf = BytesMut::new();
+            msg.append_to_wire(&mut buf);
+            peer_sealer.seal(&mut buf);
+            buf
+        };
+        let owned = sealed(Message::Owned(SESSION));
+        let released = sealed(Message::Released(SESSION));
+
+        let mut replay_windows = HashMap::new();
+        let mut open = |buf: &BytesMut| cluster.open(&mut replay_windows, peer, &mut buf.clone());
+        assert_eq!(open(&owned), Some(Message::Owned(SESSION)));
+        assert_eq!(open(&released), Some(Message::Released(SESSION)));
+        // Would point the directory at the peer again, or remove it as
+        // the owner after it took the session over once more
+        assert_eq!(open(&owned), None);
+        assert_eq!(open(&released), None);
+    }
+}
@@ -0,0 +1,143 @@
+use bytes::BytesMut;
+use lightway_core::SessionId;
+use parking_lot::Mutex;
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+use tokio::net::UdpSocket;
+
+use super::wire::{Message, Sealer};
+
+/// Directory of which node of a cluster owns each UDP session
+///
+/// Nodes are named by their cluster address. The directory is told
+/// about the sessions of the local node, and asked for the owner of
+/// the sessions it does not know.
+pub trait SessionDirectory: Send + Sync {
+    /// `node` owns `session`
+    fn insert(&self, session: SessionId, node: SocketAddr);
+
+    /// `node` does not own `session` anymore. Does nothing if another
+    /// node took `session` over since.
+    fn remove(&self, session: SessionId, node: SocketAddr);
+
+    /// The node owning `session`, if known
+    fn owner(&self, session: SessionId) -> Option<SocketAddr>;
+}
+
+/// [`SessionDirectory`] local to the process
+///
+/// Shared by nodes running in one process, e.g. in tests, and the
+/// building block of directories replicating it to other processes.
+#[derive(Default)]
+pub struct InMemorySessionDirectory {
+    sessions: Mutex<HashMap<SessionId, SocketAddr>>,
+}
+
+impl SessionDirectory for InMemorySessionDirectory {
+    fn insert(&self, session: SessionId, node: SocketAddr) {
+        self.sessions.lock().insert(session, node);
+    }
+
+    fn remove(&self, session: SessionId, node: SocketAddr) {
+        let mut sessions = self.sessions.lock();
+        if sessions.get(&session) == Some(&node) {
+            sessions.remove(&session);
+        }
+    }
+
+    fn owner(&self, session: SessionId) -> Option<SocketAddr> {
+        self.sessions.lock().get(&session).copied()
+    }
+}
+
+/// [`SessionDirectory`] announcing the sessions of the local node to
+/// the other nodes over the cluster channel
+///
+/// Each node keeps its own copy of the directory, updated with the
+/// announcements of the other nodes. This is best effort: an
+/// announcement lost on the way leaves the nodes disagreeing until the
+/// session changes again, and a node joining or restarting only learns
+/// of the sessions created from then on.
+pub(crate) struct NetworkSessionDirectory {
+    sessions: InMemorySessionDirectory,
+    /// Cluster address of the local node
+    node: SocketAddr,
+    sock: Arc<UdpSocket>,
+    peers: Vec<SocketAddr>,
+    sealer: Arc<Sealer>,
+}
+
+impl NetworkSessionDirectory {
+    pub(crate) fn new(
+        node: SocketAddr,
+        sock: Arc<UdpSocket>,
+        peers: Vec<SocketAddr>,
+        sealer: Arc<Sealer>,
+    ) -> Self {
+        Self {
+            sessions: Default::default(),
+            node,
+            sock,
+            peers,
+            sealer,
+        }
+    }
+
+    fn announce(&self, msg: Message) {
+        let mut buf = BytesMut::new();
+        msg.append_to_wire(&mut buf);
+        self.sealer.seal(&mut buf);
+        for peer in &self.peers {
+            // Ignore failure to send, see the type documentation
+            let _ = self.sock.try_send_to(&buf, *peer);
+        }
+    }
+}
+
+impl SessionDirectory for NetworkSessionDirectory {
+    fn insert(&self, session: SessionId, node: SocketAddr) {
+        self.sessions.insert(session, node);
+        // Only announce the sessions of the local node, the other
+        // nodes announce their own
+        if node == self.node {
+            self.announce(Message::Owned(session));
+        }
+    }
+
+    fn remove(&self, session: SessionId, node: SocketAddr) {
+        self.sessions.remove(session, node);
+        if node == self.node {
+            self.announce(Message::Released(session));
+        }
+    }
+
+    fn owner(&self, session: SessionId) -> Option<SocketAddr> {
+        self.sessions.owner(session)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SESSION: SessionId = SessionId::from_const([1, 2, 3, 4, 5, 6, 7, 8]);
+
+    #[test]
+    fn in_memory() {
+        let directory = InMemorySessionDirectory::default();
+        let node_a = "10.0.0.1:7000".parse().unwrap();
+        let node_b = "10.0.0.2:7000".parse().unwrap();
+
+        assert_eq!(directory.owner(SESSION), None);
+
+        directory.insert(SESSION, node_a);
+        assert_eq!(directory.owner(SESSION), Some(node_a));
+
+        // Taken over by node_b, node_a releasing it late is ignored
+        directory.insert(SESSION, node_b);
+        directory.remove(SESSION, node_a);
+        assert_eq!(directory.owner(SESSION), Some(node_b));
+
+        directory.remove(SESSION, node_b);
+        assert_eq!(directory.owner(SESSION), None);
+    }
+}
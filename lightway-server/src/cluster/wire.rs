@@ -0,0 +1,329 @@
+use aws_lc_rs::hmac;
+use bytes::{Buf, BufMut, BytesMut};
+use lightway_core::{FromWireError, FromWireResult, Header, SessionId};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+/// Length of the sequence number and tag ending every message
+pub const TRAILER_LEN: usize = 8 + TAG_LEN;
+
+/// Length of the tag ending every message
+const TAG_LEN: usize = 32;
+
+/// Largest difference between the sequence number of a message and the
+/// clock of the receiving node, beyond which the message is dropped.
+/// Bounds how long a message can be replayed to a node which restarted.
+pub const MAX_CLOCK_OFFSET: Duration = Duration::from_secs(30);
+
+/// Number of sequence numbers below the highest received which are
+/// still accepted, if not received yet, to tolerate reordering
+const REPLAY_WINDOW: u64 = 64;
+
+const FORWARD: u8 = 1;
+const OWNED: u8 = 2;
+const RELEASED: u8 = 3;
+
+const FAMILY_V4: u8 = 4;
+const FAMILY_V6: u8 = 6;
+
+/// Message between the nodes of a cluster
+///
+/// Wire Format:
+///
+/// ```text
+/// Forward:  | 1 | family (4 or 6) | peer IP | peer port | Header | payload | seq | tag |
+/// Owned:    | 2 | session id | seq | tag |
+/// Released: | 3 | session id | seq | tag |
+/// ```
+///
+/// The sequence number `seq` increases with every message sent by a
+/// node, and the tag is the HMAC-SHA256 of the rest of the message keyed
+/// with the cluster key, see [`Sealer`].
+#[derive(Debug, PartialEq)]
+pub enum Message {
+    /// UDP frame, after running the outside plugins, received from
+    /// `peer_addr` for a session owned by the receiving node. The
+    /// payload follows the message.
+    Forward {
+        peer_addr: SocketAddr,
+        header: Header,
+    },
+    /// The sending node owns the session
+    Owned(SessionId),
+    /// The sending node does not own the session anymore
+    Released(SessionId),
+}
+
+impl Message {
+    /// Append the message to `buf`, a `Forward` payload is to be
+    /// appended after it
+    pub fn append_to_wire(&self, buf: &mut BytesMut) {
+        match self {
+            Self::Forward { peer_addr, header } => {
+                buf.put_u8(FORWARD);
+                match peer_addr.ip() {
+                    IpAddr::V4(ip) => {
+                        buf.put_u8(FAMILY_V4);
+                        buf.put_slice(&ip.octets());
+                    }
+                    IpAddr::V6(ip) => {
+                        buf.put_u8(FAMILY_V6);
+                        buf.put_slice(&ip.octets());
+                    }
+                }
+                buf.put_u16(peer_addr.port());
+                header.append_to_wire(buf);
+            }
+            Self::Owned(session) => {
+                buf.put_u8(OWNED);
+                buf.put_slice(session.as_bytes());
+            }
+            Self::Released(session) => {
+                buf.put_u8(RELEASED);
+                buf.put_slice(session.as_bytes());
+            }
+        }
+    }
+
+    /// Decode a message from `buf`, leaving the payload of a `Forward`
+    /// in `buf`
+    pub fn try_from_wire(buf: &mut BytesMut) -> FromWireResult<Self> {
+        if buf.is_empty() {
+            return Err(FromWireError::InsufficientData);
+        }
+
+        match buf.get_u8() {
+            FORWARD => {
+                if buf.is_empty() {
+                    return Err(FromWireError::InsufficientData);
+                }
+                let ip = match buf.get_u8() {
+                    FAMILY_V4 => IpAddr::V4(Ipv4Addr::from(get_array::<4>(buf)?)),
+                    FAMILY_V6 => IpAddr::V6(Ipv6Addr::from(get_array::<16>(buf)?)),
+                    _ => return Err(FromWireError::InvalidEnumEncoding),
+                };
+                let port = u16::from_be_bytes(get_array(buf)?);
+                let header = Header::try_from_wire(buf)?;
+                Ok(Self::Forward {
+                    peer_addr: SocketAddr::new(ip, port),
+                    header,
+                })
+            }
+            OWNED => Ok(Self::Owned(get_array::<8>(buf)?.into())),
+            RELEASED => Ok(Self::Released(get_array::<8>(buf)?.into())),
+            _ => Err(FromWireError::UnknownFrameType),
+        }
+    }
+}
+
+/// Seals the messages sent by a node
+///
+/// Sequence numbers are the microseconds since the UNIX epoch, or one
+/// more than the previous one if the clock did not advance, so that
+/// they keep increasing across restarts of the node.
+pub struct Sealer {
+    key: hmac::Key,
+    last_seq: AtomicU64,
+}
+
+impl Sealer {
+    pub fn new(key: hmac::Key) -> Self {
+        Self {
+            key,
+            last_seq: AtomicU64::new(0),
+        }
+    }
+
+    /// Append the sequence number and the tag authenticating the message
+    /// in `buf`, payload included
+    pub fn seal(&self, buf: &mut BytesMut) {
+        let now = now_micros();
+        let next = |last: u64| now.max(last + 1);
+        let last = self
+            .last_seq
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+                Some(next(last))
+            })
+            .expect("Sequence number update always succeeds");
+        buf.put_u64(next(last));
+        let tag = hmac::sign(&self.key, buf);
+        buf.put_slice(tag.as_ref());
+    }
+}
+
+/// Check and remove the sequence number and tag of the message in
+/// `buf`. Returns the sequence number if the message is authentic,
+/// `buf` is to be dropped otherwise.
+pub fn open(key: &hmac::Key, buf: &mut BytesMut) -> Option<u64> {
+    let len = buf.len().checked_sub(TAG_LEN)?;
+    let (message, tag) = buf.split_at(len);
+    let authentic = hmac::verify(key, message, tag).is_ok();
+    buf.truncate(len);
+    if !authentic {
+        return None;
+    }
+    let len = buf.len().checked_sub(8)?;
+    let seq = u64::from_be_bytes(buf[len..].try_into().ok()?);
+    buf.truncate(len);
+    Some(seq)
+}
+
+/// Sequence numbers of the messages received from a node, to drop
+/// replayed messages
+#[derive(Debug, Default)]
+pub struct ReplayWindow {
+    /// Highest sequence number received
+    highest: u64,
+    /// Bit `n` is set if `highest - n` was received
+    received: u64,
+}
+
+impl ReplayWindow {
+    /// Record `seq`, of a message received when the clock of the node
+    /// read `now` microseconds since the UNIX epoch. Returns whether the
+    /// message is new, it is to be dropped otherwise: replayed, too far
+    /// behind the latest message or too far from `now`.
+    pub fn check(&mut self, seq: u64, now: u64) -> bool {
+        if seq.abs_diff(now) > MAX_CLOCK_OFFSET.as_micros() as u64 {
+            return false;
+        }
+        if seq > self.highest {
+            let shift = seq - self.highest;
+            self.received = if shift < REPLAY_WINDOW {
+                self.received << shift
+            } else {
+                0
+            };
+            self.received |= 1;
+            self.highest = seq;
+            return true;
+        }
+        let offset = self.highest - seq;
+        if offset >= REPLAY_WINDOW || self.received & (1 << offset) != 0 {
+            return false;
+        }
+        self.received |= 1 << offset;
+        true
+    }
+}
+
+/// Microseconds since the UNIX epoch
+pub fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_micros() as u64)
+}
+
+fn get_array<const N: usize>(buf: &mut BytesMut) -> FromWireResult<[u8; N]> {
+    if buf.len() < N {
+        return Err(FromWireError::InsufficientData);
+    }
+    let mut array = [0; N];
+    buf.copy_to_slice(&mut array);
+    Ok(array)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lightway_core::{MultipathMode, Version};
+    use test_case::test_case;
+
+    const SESSION: SessionId = SessionId::from_const([1, 2, 3, 4, 5, 6, 7, 8]);
+
+    fn forward(peer_addr: &str) -> Message {
+        Message::Forward {
+            peer_addr: peer_addr.parse().unwrap(),
+            header: Header {
+                version: Version::MINIMUM,
+                aggressive_mode: false,
+                session: SESSION,
+                expresslane_data: false,
+                multipath: MultipathMode::Off,
+            },
+        }
+    }
+
+    #[test_case(forward("192.168.1.10:27690"); "Forward v4")]
+    #[test_case(forward("[2001:db8::1]:443"); "Forward v6")]
+    #[test_case(Message::Owned(SESSION); "Owned")]
+    #[test_case(Message::Released(SESSION); "Released")]
+    fn roundtrip(msg: Message) {
+        let mut buf = BytesMut::new();
+        msg.append_to_wire(&mut buf);
+        buf.put_slice(b"payload");
+
+        assert_eq!(Message::try_from_wire(&mut buf).unwrap(), msg);
+        assert_eq!(&buf[..], b"payload");
+    }
+
+    #[test_case(&[] => matches FromWireError::InsufficientData; "Empty")]
+    #[test_case(&[9, 0] => matches FromWireError::UnknownFrameType; "Unknown type")]
+    #[test_case(&[FORWARD, 5, 0, 0, 0, 0] => matches FromWireError::InvalidEnumEncoding; "Unknown family")]
+    #[test_case(&[FORWARD, FAMILY_V4, 10, 0] => matches FromWireError::InsufficientData; "Truncated address")]
+    #[test_case(&[OWNED, 1, 2, 3] => matches FromWireError::InsufficientData; "Truncated session id")]
+    fn invalid(data: &[u8]) -> FromWireError {
+        Message::try_from_wire(&mut BytesMut::from(data)).unwrap_err()
+    }
+
+    fn key(byte: u8) -> hmac::Key {
+        hmac::Key::new(hmac::HMAC_SHA256, &[byte; 32])
+    }
+
+    #[test]
+    fn sealed_opens() {
+        let mut buf = BytesMut::new();
+        forward("192.168.1.10:27690").append_to_wire(&mut buf);
+        buf.put_slice(b"payload");
+        let message = buf.clone();
+
+        let before = now_micros();
+        Sealer::new(key(1)).seal(&mut buf);
+        assert_eq!(buf.len(), message.len() + TRAILER_LEN);
+        let seq = open(&key(1), &mut buf).unwrap();
+        assert!(seq >= before);
+        assert_eq!(buf, message);
+    }
+
+    #[test]
+    fn sequence_numbers_increase() {
+        let sealer = Sealer::new(key(1));
+        let seqs: Vec<u64> = (0..100)
+            .map(|_| {
+                let mut buf = BytesMut::new();
+                Message::Owned(SESSION).append_to_wire(&mut buf);
+                sealer.seal(&mut buf);
+                open(&key(1), &mut buf).unwrap()
+            })
+            .collect();
+        assert!(seqs.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    const NOW: u64 = 1_700_000_000_000_000;
+    const MAX_OFFSET: u64 = MAX_CLOCK_OFFSET.as_micros() as u64;
+
+    #[test_case(&[NOW, NOW + 1, NOW + 2] => vec![true, true, true]; "In order")]
+    #[test_case(&[NOW, NOW] => vec![true, false]; "Replayed")]
+    #[test_case(&[NOW, NOW + 2, NOW + 1, NOW + 1] => vec![true, true, true, false]; "Reordered then replayed")]
+    #[test_case(&[NOW, NOW + REPLAY_WINDOW, NOW] => vec![true, true, false]; "Behind the window")]
+    #[test_case(&[NOW - MAX_OFFSET - 1] => vec![false]; "Too old")]
+    #[test_case(&[NOW + MAX_OFFSET + 1] => vec![false]; "Too new")]
+    fn replay_window(seqs: &[u64]) -> Vec<bool> {
+        let mut window = ReplayWindow::default();
+        seqs.iter().map(|seq| window.check(*seq, NOW)).collect()
+    }
+
+    #[test_case(|buf: &mut BytesMut| buf[0] ^= 1, 1; "Tampered message")]
+    #[test_case(|buf: &mut BytesMut| { let last = buf.len() - 1; buf[last] ^= 1 }, 1; "Tampered tag")]
+    #[test_case(|buf: &mut BytesMut| buf.truncate(TAG_LEN - 1), 1; "Truncated")]
+    #[test_case(|_: &mut BytesMut| {}, 2; "Other key")]
+    fn forged_does_not_open(forge: fn(&mut BytesMut), key_byte: u8) {
+        let mut buf = BytesMut::new();
+        Message::Owned(SESSION).append_to_wire(&mut buf);
+        Sealer::new(key(1)).seal(&mut buf);
+
+        forge(&mut buf);
+        assert_eq!(open(&key(key_byte), &mut buf), None);
+    }
+}
@@ -11,8 +11,10 @@ use serde::Deserialize;
 use std::time::Duration as StdDuration;
 use struct_patch::{Patch, Substrate};
 
-use lightway_app_utils::args::{
-    ConnectionType, Duration, IpMap, LogFormat, LogLevel, NonZeroDuration,
+use crate::ClientIsolation;
+use lightway_app_utils::{
+    args::{ConnectionType, Duration, IpMap, LogFormat, LogLevel, NonZeroDuration},
+    split_dns::SplitDnsConfig,
 };
 
 // NOTE
@@ -29,13 +31,22 @@ pub struct Config {
     pub config_file: PathBuf,
 
     #[patch(attribute(clap(short, long, value_enum)))]
-    #[patch(attribute(doc = "Connection mode"))]
+    #[patch(attribute(doc = r#"Connection mode
+    `auto` serves both UDP and TCP, sharing the IP pool"#))]
     pub mode: ConnectionType,
 
     #[patch(attribute(clap(long)))]
     #[patch(attribute(doc = "user database, in Apache htpasswd format"))]
     pub user_db: Option<PathBuf>,
 
+    #[patch(attribute(clap(skip)))]
+    #[patch(attribute(
+        doc = r#"Subnets each user of "user_db" may have behind it (config file only).
+    Of the subnets a client advertises, those within one of its user's
+    are routed to it"#
+    ))]
+    pub user_subnets: Option<HashMap<String, Vec<Ipv4Net>>>,
+
     #[patch(attribute(clap(long)))]
     pub token_rsa_pub_key_pem: Option<PathBuf>,
 
@@ -60,11 +71,36 @@ pub struct Config {
     Maps from incoming IP address to a subnet of "ip_pool" to use for that address."#))]
     pub ip_map: Option<IpMap>,
 
+    #[patch(attribute(clap(skip)))]
+    #[patch(attribute(doc = r#"Named subnets of "ip_pool" (config file only).
+    The auth backend may assign a client to one of them by name."#))]
+    pub ip_pools: Option<HashMap<String, Ipv4Net>>,
+
     #[patch(attribute(clap(long)))]
     #[patch(attribute(doc = r#"The IP assigned to the Tun device.
     If this is within `ip_pool` then it will be reserved."#))]
     pub tun_ip: Option<Ipv4Addr>,
 
+    #[patch(attribute(clap(long)))]
+    #[patch(
+        attribute(doc = r#"How long to remember the IP of a client identity after it
+    disconnected, handing the IP back when it reconnects and the IP is
+    still free. 0 disables sticky IPs"#)
+    )]
+    pub ip_lease_time: Duration,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = "File to persist IP leases to across restarts"))]
+    pub ip_lease_file: Option<PathBuf>,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(
+        attribute(doc = r#"File to persist the state of the IP pools to across restarts
+    The IPs assigned before a restart are then held for their clients
+    to reconnect"#)
+    )]
+    pub ip_pool_state_file: Option<PathBuf>,
+
     #[patch(attribute(clap(long)))]
     #[patch(attribute(doc = "Server IP to send in network_config message"))]
     pub lightway_server_ip: Ipv4Addr,
@@ -77,6 +113,21 @@ pub struct Config {
     #[patch(attribute(doc = "DNS IP to send in network_config message"))]
     pub lightway_dns_ip: Ipv4Addr,
 
+    #[patch(attribute(clap(skip)))]
+    #[patch(
+        attribute(doc = r#"Split DNS configuration pushed to clients (config file only).
+    Queries for each listed domain go to its resolvers, everything else
+    keeps using the client's own resolver."#)
+    )]
+    pub split_dns: Option<SplitDnsConfig>,
+
+    #[patch(attribute(clap(long, value_enum)))]
+    #[patch(
+        attribute(doc = r#"Which other clients a client can reach on their inside IPs.
+    `pool` allows clients assigned IPs from the same pool"#)
+    )]
+    pub client_isolation: ClientIsolation,
+
     #[patch(attribute(clap(long)))]
     #[patch(empty_value = false)]
     #[patch(attribute(serde(default)))]
@@ -95,6 +146,45 @@ pub struct Config {
     #[patch(attribute(doc = "Interval between session statistics reports"))]
     pub statistics_reporting_interval: Duration,
 
+    #[patch(attribute(clap(long)))]
+    #[patch(
+        attribute(doc = r#"Idle time after which the peer of a UDP session is probed
+    with keepalives to detect whether it is gone. 0 disables dead peer detection"#)
+    )]
+    pub dpd_idle_timeout: Duration,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = "Interval between dead peer detection probes"))]
+    pub dpd_interval: NonZeroDuration,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(
+        attribute(doc = r#"Dead peer detection probes going unanswered in a row
+    before the session is closed"#)
+    )]
+    pub dpd_max_missed: u32,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(
+        attribute(doc = r#"Address of the cluster channel of this server (UDP only).
+    Servers of a cluster forward the datagrams of UDP sessions owned by
+    another server to it, so clients keep their session when floating
+    between servers"#)
+    )]
+    pub cluster_address: Option<SocketAddr>,
+
+    #[patch(attribute(clap(long, value_delimiter = ',')))]
+    #[patch(attribute(doc = "Cluster channel addresses of the other servers of the cluster"))]
+    pub cluster_peers: Vec<SocketAddr>,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(
+        attribute(doc = r#"Key file authenticating the messages of the cluster channel.
+    64 hex digits, e.g. from `openssl rand -hex 32`, same on every server
+    of the cluster"#)
+    )]
+    pub cluster_key: Option<PathBuf>,
+
     #[patch(attribute(clap(long)))]
     #[patch(empty_value = false)]
     #[patch(attribute(serde(default)))]
@@ -113,6 +203,24 @@ pub struct Config {
     #[patch(attribute(doc = "Enable IO-uring interface for Tunnel"))]
     pub enable_tun_iouring: bool,
 
+    #[patch(attribute(clap(long)))]
+    #[patch(empty_value = false)]
+    #[patch(attribute(serde(default)))]
+    #[patch(attribute(
+        doc = r#"Translate the clients' traffic to the server's own address in userspace
+    instead of writing it to a TUN device, so that no privileges or kernel NAT rules are needed.
+    Only TCP and UDP are translated."#
+    ))]
+    pub enable_userspace_nat: bool,
+
+    #[patch(attribute(clap(long, value_delimiter = ',')))]
+    #[patch(
+        attribute(doc = r#"Subnets the clients may not reach through the userspace NAT.
+    Loopback, link-local, unspecified, multicast and broadcast
+    destinations are always denied"#)
+    )]
+    pub userspace_nat_deny: Vec<Ipv4Net>,
+
     // Any value more than 1024 negatively impact the throughput
     #[patch(attribute(clap(long)))]
     #[patch(attribute(doc = r#"IO-uring submission queue count.
@@ -137,16 +245,55 @@ pub struct Config {
     #[patch(attribute(doc = "The key update interval for DTLS/TLS 1.3 connections"))]
     pub key_update_interval: NonZeroDuration,
 
+    #[patch(attribute(clap(long)))]
+    #[patch(
+        attribute(doc = r#"File holding the TLS session ticket key, 48 bytes in hex.
+    e.g. `openssl rand -hex 48`. Servers sharing the key resume each
+    other's sessions. Session tickets are disabled when unset.
+    Requires the BoringSSL backend"#)
+    )]
+    pub session_ticket_key: Option<PathBuf>,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = r#"How often to re-read `session_ticket_key`.
+    A changed key is used for new tickets, tickets issued under
+    the previous key still resume"#))]
+    pub session_ticket_key_reload_interval: Duration,
+
     #[patch(attribute(clap(long)))]
     #[patch(attribute(doc = "Address to listen to"))]
     pub bind_address: SocketAddr,
 
+    #[patch(attribute(clap(long, value_delimiter = ',')))]
+    #[patch(attribute(doc = r#"Further addresses to listen to, e.g. other ports.
+    Each serves the same transports as `bind_address`"#))]
+    pub extra_bind_addresses: Vec<SocketAddr>,
+
     #[patch(attribute(clap(long)))]
     #[patch(empty_value = false)]
     #[patch(attribute(serde(default)))]
     #[patch(attribute(doc = "Enable PROXY protocol support (TCP only)"))]
     pub proxy_protocol: bool,
 
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = r#"Key file to scramble UDP datagrams with (UDP only).
+    64 hex digits, e.g. from `openssl rand -hex 32`, same as the clients'"#))]
+    pub obfuscation_key: Option<PathBuf>,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = "Pad UDP datagrams with up to this many random bytes (UDP only)"))]
+    pub obfuscation_padding: u16,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(attribute(doc = "Delay sending UDP datagrams by up to this long (UDP only)"))]
+    pub obfuscation_jitter: Duration,
+
+    #[patch(attribute(clap(long)))]
+    #[patch(empty_value = false)]
+    #[patch(attribute(serde(default)))]
+    #[patch(attribute(doc = "Accept connections wrapped in WebSocket (TCP only)"))]
+    pub websocket: bool,
+
     #[patch(attribute(clap(long)))]
     #[patch(attribute(doc = "Set UDP buffer size. Default value is 15 MiB."))]
     pub udp_buffer_size: ByteSize,
@@ -204,16 +351,23 @@ impl Default for Config {
             config_file: PathBuf::default(),
             mode: ConnectionType::Tcp,
             user_db: None,
+            user_subnets: None,
             token_rsa_pub_key_pem: None,
             server_cert: PathBuf::from("./server.crt"),
             server_key: PathBuf::from("./server.key"),
             tun_name: None,
             ip_pool: IP_POOL,
             ip_map: None,
+            ip_pools: None,
             tun_ip: None,
+            ip_lease_time: Duration::from_std_duration(StdDuration::ZERO),
+            ip_lease_file: None,
+            ip_pool_state_file: None,
             lightway_server_ip: Ipv4Addr::new(10, 125, 0, 6),
             lightway_client_ip: Ipv4Addr::new(10, 125, 0, 5),
             lightway_dns_ip: Ipv4Addr::new(10, 125, 0, 1),
+            split_dns: None,
+            client_isolation: ClientIsolation::Off,
             enable_expresslane: false,
             expresslane_keys_rotation_interval: Duration::from_std_duration(
                 crate::DEFAULT_EXPRESSLANE_KEYS_ROTATION_INTERVAL,
@@ -224,9 +378,17 @@ impl Default for Config {
             statistics_reporting_interval: Duration::from_std_duration(
                 crate::DEFAULT_STATISTICS_REPORTING_INTERVAL,
             ),
+            dpd_idle_timeout: Duration::from_std_duration(StdDuration::ZERO),
+            dpd_interval: NonZeroDuration::from_std_duration(StdDuration::from_secs(10)),
+            dpd_max_missed: 3,
+            cluster_address: None,
+            cluster_peers: Vec::new(),
+            cluster_key: None,
             enable_pqc: false,
             enable_tun_offload: false,
             enable_tun_iouring: false,
+            enable_userspace_nat: false,
+            userspace_nat_deny: Vec::new(),
             iouring_entry_count: 1024,
             iouring_sqpoll_idle_time: Duration::from_std_duration(StdDuration::from_millis(100)),
             log_format: LogFormat::Full,
@@ -235,8 +397,17 @@ impl Default for Config {
             key_update_interval: NonZeroDuration::from_std_duration(StdDuration::from_secs(
                 15 * 60,
             )),
+            session_ticket_key: None,
+            session_ticket_key_reload_interval: Duration::from_std_duration(
+                crate::DEFAULT_SESSION_TICKET_KEY_RELOAD_INTERVAL,
+            ),
             bind_address: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 27690),
+            extra_bind_addresses: Vec::new(),
             proxy_protocol: false,
+            obfuscation_key: None,
+            obfuscation_padding: 0,
+            obfuscation_jitter: Duration::from_std_duration(StdDuration::ZERO),
+            websocket: false,
             udp_buffer_size: ByteSize::mib(15),
             enable_batch_receive: false,
             enable_batch_send: false,
@@ -262,17 +433,119 @@ impl Config {
             }
         }
 
+        // In auto mode UDP only options apply to the UDP connections and
+        // TCP only options to the TCP connections
         if self.enable_expresslane {
-            anyhow::ensure!(self.mode.is_udp(), "Expresslane only work in udp mode")
+            anyhow::ensure!(
+                !self.mode.is_tcp(),
+                "Expresslane only work in udp or auto mode"
+            )
+        }
+
+        if !self.dpd_idle_timeout.is_zero() {
+            anyhow::ensure!(
+                !self.mode.is_tcp(),
+                "Dead peer detection only works in udp or auto mode"
+            );
+            anyhow::ensure!(
+                self.dpd_max_missed > 0,
+                "dpd_max_missed must be greater than 0"
+            );
+        }
+
+        anyhow::ensure!(
+            self.session_ticket_key.is_none() || lightway_core::tls::SESSION_TICKETS_SUPPORTED,
+            "session_ticket_key requires the BoringSSL backend"
+        );
+
+        match self.cluster_address {
+            Some(addr) => {
+                anyhow::ensure!(
+                    !self.mode.is_tcp(),
+                    "Cluster only works in udp or auto mode"
+                );
+                anyhow::ensure!(
+                    !addr.ip().is_unspecified(),
+                    "cluster_address must be the address the other servers reach this one on"
+                );
+                anyhow::ensure!(
+                    !self.cluster_peers.is_empty(),
+                    "cluster_address requires cluster_peers"
+                );
+                anyhow::ensure!(
+                    self.cluster_key.is_some(),
+                    "cluster_address requires cluster_key"
+                );
+            }
+            None => anyhow::ensure!(
+                self.cluster_peers.is_empty() && self.cluster_key.is_none(),
+                "cluster_peers and cluster_key require cluster_address"
+            ),
         }
 
         if self.proxy_protocol {
             anyhow::ensure!(
-                self.mode.is_tcp(),
-                "Proxy protocol only support with tcp mode"
+                !self.mode.is_udp(),
+                "Proxy protocol only support with tcp or auto mode"
             )
         }
 
+        if self.obfuscation_key.is_some()
+            || self.obfuscation_padding > 0
+            || !self.obfuscation_jitter.is_zero()
+        {
+            anyhow::ensure!(
+                !self.mode.is_tcp(),
+                "Obfuscation key, padding and jitter only work in udp or auto mode"
+            );
+        }
+
+        if self.obfuscation_padding > 0 || !self.obfuscation_jitter.is_zero() {
+            anyhow::ensure!(
+                !self.enable_tun_offload,
+                "Obfuscation padding and jitter cannot be used with tun offload"
+            );
+        }
+
+        if self.websocket {
+            anyhow::ensure!(
+                !self.mode.is_udp(),
+                "WebSocket only works in tcp or auto mode"
+            );
+        }
+
+        if let Some(ip_pools) = &self.ip_pools {
+            // An `ip_map` file is only loaded along with the server
+            // config, and checked against the pools then
+            let ip_map = match &self.ip_map {
+                Some(IpMap::Inline(ip_map)) => ip_map.clone(),
+                _ => HashMap::new(),
+            };
+            validate_ip_pools(self.ip_pool, &ip_map, ip_pools, &self.reserved_ips())?;
+        }
+
+        if self.ip_lease_file.is_some() {
+            anyhow::ensure!(
+                !self.ip_lease_time.is_zero(),
+                "ip_lease_file requires a non zero ip_lease_time"
+            );
+        }
+
+        if let Some(split_dns) = &self.split_dns {
+            split_dns.validate()?;
+        }
+
+        if self.enable_userspace_nat {
+            anyhow::ensure!(
+                !self.enable_tun_offload && !self.enable_tun_iouring,
+                "Userspace NAT cannot be used with tun offload or io-uring"
+            );
+            anyhow::ensure!(
+                !self.ip_pool.contains(&self.lightway_dns_ip),
+                "Userspace NAT requires a lightway_dns_ip outside of ip_pool"
+            );
+        }
+
         if self.enable_batch_send {
             anyhow::ensure!(
                 !self.enable_tun_offload,
@@ -282,6 +555,59 @@ impl Config {
 
         Ok(())
     }
+
+    /// IPs of `ip_pool` never assigned to clients
+    pub(crate) fn reserved_ips(&self) -> Vec<Ipv4Addr> {
+        [
+            self.lightway_client_ip,
+            self.lightway_server_ip,
+            self.lightway_dns_ip,
+        ]
+        .into_iter()
+        .chain(self.tun_ip)
+        .collect()
+    }
+}
+
+/// Ensure each named IP pool is a subnet of `ip_pool`, overlaps no other
+/// named pool nor `ip_map` subnet, and has IPs to assign once `reserved`
+/// IPs are left out.
+pub(crate) fn validate_ip_pools(
+    ip_pool: Ipv4Net,
+    ip_map: &HashMap<IpAddr, Ipv4Net>,
+    ip_pools: &HashMap<String, Ipv4Net>,
+    reserved: &[Ipv4Addr],
+) -> anyhow::Result<()> {
+    let overlaps = |a: &Ipv4Net, b: &Ipv4Net| a.contains(b) || b.contains(a);
+
+    let mut names: Vec<_> = ip_pools.keys().collect();
+    names.sort();
+    for (i, name) in names.iter().enumerate() {
+        let subnet = &ip_pools[*name];
+        anyhow::ensure!(
+            ip_pool.contains(subnet),
+            "IP pool {name} is not a subnet of ip_pool"
+        );
+        if let Some(other) = names[i + 1..]
+            .iter()
+            .find(|other| overlaps(subnet, &ip_pools[**other]))
+        {
+            anyhow::bail!("IP pools {name} and {other} overlap");
+        }
+        if let Some(ip) = ip_map
+            .iter()
+            .find_map(|(ip, mapped)| overlaps(subnet, mapped).then_some(ip))
+        {
+            anyhow::bail!("IP pool {name} overlaps the ip_map subnet of {ip}");
+        }
+        let assignable = (subnet.network().to_bits()..=subnet.broadcast().to_bits())
+            .map(Ipv4Addr::from_bits)
+            .any(|ip| {
+                ip != ip_pool.network() && ip != ip_pool.broadcast() && !reserved.contains(&ip)
+            });
+        anyhow::ensure!(assignable, "IP pool {name} has no IP to assign");
+    }
+    Ok(())
 }
 
 // Note it easier to see what is different from default in each testcase
@@ -289,6 +615,7 @@ impl Config {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use test_case::test_case;
 
     #[test]
     fn validate_default_config() {
@@ -304,6 +631,121 @@ mod tests {
         assert!(config.validate().is_err());
     }
 
+    #[test]
+    fn validate_dead_peer_detection() {
+        let mut config = Config::default();
+        config.mode = ConnectionType::Udp;
+        config.dpd_idle_timeout = Duration::from_std_duration(StdDuration::from_secs(30));
+        assert!(config.validate().is_ok());
+
+        config.dpd_max_missed = 0;
+        assert!(config.validate().is_err());
+
+        config.dpd_max_missed = 3;
+        config.mode = ConnectionType::Tcp;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_session_ticket_key() {
+        let mut config = Config::default();
+        config.session_ticket_key = Some(PathBuf::from("ticket.key"));
+        assert_eq!(
+            config.validate().is_ok(),
+            lightway_core::tls::SESSION_TICKETS_SUPPORTED
+        );
+    }
+
+    #[test]
+    fn validate_cluster() {
+        let mut config = Config::default();
+        config.cluster_peers = vec!["10.0.0.2:27700".parse().unwrap()];
+        assert!(config.validate().is_err());
+
+        config.cluster_address = Some("10.0.0.1:27700".parse().unwrap());
+        assert!(config.validate().is_err());
+
+        config.cluster_key = Some(PathBuf::from("cluster.key"));
+        assert!(config.validate().is_ok());
+
+        config.cluster_address = Some("0.0.0.0:27700".parse().unwrap());
+        assert!(config.validate().is_err());
+
+        config.cluster_address = Some("10.0.0.1:27700".parse().unwrap());
+        config.cluster_peers.clear();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_auto_mode() {
+        let mut config = Config::default();
+        config.mode = ConnectionType::Auto;
+        config.enable_expresslane = true;
+        config.proxy_protocol = true;
+        config.websocket = true;
+        config.obfuscation_key = Some(PathBuf::from("obfuscation.key"));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_ip_pools() {
+        let mut config = Config::default();
+        config.ip_pools = Some(HashMap::from([(
+            "admins".to_string(),
+            "10.125.1.0/24".parse().unwrap(),
+        )]));
+        assert!(config.validate().is_ok());
+
+        config.ip_pools = Some(HashMap::from([(
+            "admins".to_string(),
+            "10.126.1.0/24".parse().unwrap(),
+        )]));
+        assert!(config.validate().is_err());
+    }
+
+    #[test_case(&[("admins", "10.125.1.0/24"), ("guests", "10.125.2.0/24")], &[] => true; "disjoint")]
+    #[test_case(&[("admins", "10.125.1.0/24"), ("guests", "10.125.1.128/25")], &[] => false; "overlapping pools")]
+    #[test_case(&[("admins", "10.125.1.0/24")], &[("192.168.1.1", "10.125.1.0/28")] => false; "overlapping ip_map")]
+    #[test_case(&[("admins", "10.125.1.0/24")], &[("192.168.1.1", "10.125.2.0/28")] => true; "next to ip_map")]
+    #[test_case(&[("dns", "10.125.0.1/32")], &[] => false; "only reserved IPs")]
+    #[test_case(&[("first", "10.125.0.0/32")], &[] => false; "only network address")]
+    #[test_case(&[("single", "10.125.0.9/32")], &[] => true; "single IP")]
+    fn validate_named_pools(pools: &[(&str, &str)], ip_map: &[(&str, &str)]) -> bool {
+        let config = Config::default();
+        let pools = pools
+            .iter()
+            .map(|(name, subnet)| (name.to_string(), subnet.parse().unwrap()))
+            .collect();
+        let ip_map = ip_map
+            .iter()
+            .map(|(ip, subnet)| (ip.parse().unwrap(), subnet.parse().unwrap()))
+            .collect();
+        super::validate_ip_pools(config.ip_pool, &ip_map, &pools, &config.reserved_ips()).is_ok()
+    }
+
+    #[test]
+    fn validate_ip_lease() {
+        let mut config = Config::default();
+        config.ip_lease_file = Some(PathBuf::from("leases.json"));
+        assert!(config.validate().is_err());
+
+        config.ip_lease_time = Duration::from_std_duration(StdDuration::from_secs(3600));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_userspace_nat() {
+        let mut config = Config::default();
+        config.enable_userspace_nat = true;
+        assert!(config.validate().is_err());
+
+        config.lightway_dns_ip = Ipv4Addr::new(1, 1, 1, 1);
+        assert!(config.validate().is_ok());
+
+        config.enable_tun_offload = true;
+        assert!(config.validate().is_err());
+    }
+
     #[test]
     fn validate_proxy_protocol() {
         let mut config = Config::default();
@@ -312,6 +754,32 @@ mod tests {
         assert!(config.validate().is_err());
     }
 
+    #[test]
+    fn validate_obfuscation() {
+        let mut config = Config::default();
+        config.mode = ConnectionType::Udp;
+        config.obfuscation_key = Some(PathBuf::from("obfuscation.key"));
+        config.obfuscation_padding = 64;
+        assert!(config.validate().is_ok());
+
+        config.enable_tun_offload = true;
+        assert!(config.validate().is_err());
+
+        config.enable_tun_offload = false;
+        config.mode = ConnectionType::Tcp;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_websocket() {
+        let mut config = Config::default();
+        config.websocket = true;
+        assert!(config.validate().is_ok());
+
+        config.mode = ConnectionType::Udp;
+        assert!(config.validate().is_err());
+    }
+
     #[test]
     fn validate_batch_send_with_tun_offload() {
         let mut config = Config::default();
@@ -327,6 +795,21 @@ mod tests {
         assert!(config.validate().is_ok());
     }
 
+    #[test]
+    fn validate_split_dns() {
+        let yaml = r#"
+split_dns:
+  resolvers:
+    - domain: corp.example
+      servers: []
+"#;
+        let patch = serde_saphyr::from_str::<ConfigPatch>(yaml).expect("should parse");
+        let mut config = Config::default();
+        config.apply(patch);
+        assert!(config.split_dns.is_some());
+        assert!(config.validate().is_err());
+    }
+
     #[test]
     fn config_unknown_fields_cause_error() {
         let yaml = "unknown_field: true\n";
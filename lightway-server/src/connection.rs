@@ -1,20 +1,23 @@
 use bytes::BytesMut;
 use delegate::delegate;
+use ipnet::Ipv4Net;
+use pnet_packet::ipv4::Ipv4Packet;
 use std::{
     net::{Ipv4Addr, SocketAddr},
     sync::{Arc, Mutex, Weak},
+    time::Duration,
 };
 use tracing::{trace, warn};
 
 use crate::{
     connection_manager::{ConnectionManager, ConnectionManagerError},
-    metrics,
+    dead_peer, metrics,
 };
 use lightway_app_utils::{ConnectionTicker, ConnectionTickerState, EventStreamCallback, Tickable};
 use lightway_core::{
-    ConnectionActivity, ConnectionError, ConnectionResult, ConnectionType,
-    OutsideIOSendCallbackArg, OutsidePacket, PacketDecoderType, PacketEncoderType, ProtocolVersion,
-    ServerContext, SessionId, State, TickType, Version,
+    ConnectionActivity, ConnectionError, ConnectionResult, ConnectionType, InsideIpRequest,
+    KeepaliveId, MultipathMode, OutsideIOSendCallbackArg, OutsidePacket, PacketDecoderType,
+    PacketEncoderType, ProtocolVersion, ServerContext, SessionId, State, TickType, Version,
 };
 
 pub struct ConnectionState {
@@ -26,10 +29,33 @@ pub struct ConnectionState {
     pub peer_addr: SocketAddr,
     // The backend IP (from IP pool) associated with this connection
     pub internal_ip: Option<Ipv4Addr>,
+    // Identity of the authenticated client, keying its IP lease
+    pub identity: Option<String>,
+    // Inside IP requested for the authenticated client
+    pub requested_ip: Option<InsideIpRequest>,
+    // Subnets advertised by the authenticated client and allowed by its
+    // auth backend
+    pub subnets: Vec<Ipv4Net>,
+    // Subnets routed to this connection, see `IpManager::add_routes`
+    pub routed_subnets: Vec<Ipv4Net>,
     // The connection
     pub(crate) conn: std::cell::OnceCell<Weak<Connection>>,
 }
 
+impl ConnectionState {
+    /// Whether `packet` comes from a subnet routed to this client,
+    /// rather than from its inside IP
+    pub fn from_routed_subnet(&self, packet: &[u8]) -> bool {
+        !self.routed_subnets.is_empty()
+            && Ipv4Packet::new(packet).is_some_and(|packet| {
+                let source = packet.get_source();
+                self.routed_subnets
+                    .iter()
+                    .any(|subnet| subnet.contains(&source))
+            })
+    }
+}
+
 impl ConnectionTickerState for ConnectionState {
     fn connection_ticker(&self) -> &ConnectionTicker {
         &self.ticker
@@ -40,6 +66,8 @@ pub(crate) struct Connection {
     manager: Arc<ConnectionManager>,
     lw_conn: Mutex<lightway_core::Connection<ConnectionState>>,
     pub(crate) connection_started: std::time::Instant,
+    /// Dead peer detection probes sent to the peer
+    pub(crate) dead_peer_probes: Mutex<dead_peer::Probes>,
 }
 
 impl Tickable for Connection {
@@ -67,6 +95,10 @@ impl Connection {
             local_addr,
             peer_addr: outside_io.peer_addr(),
             internal_ip: None,
+            identity: None,
+            requested_ip: None,
+            subnets: Vec::new(),
+            routed_subnets: Vec::new(),
             conn: std::cell::OnceCell::new(),
         };
 
@@ -81,6 +113,7 @@ impl Connection {
             manager,
             lw_conn,
             connection_started,
+            dead_peer_probes: Mutex::default(),
         });
 
         conn.lw_conn
@@ -105,12 +138,17 @@ impl Connection {
             pub fn mark_offload_activity(&self, rx: bool, tx: bool);
             pub fn peer_addr(&self) -> SocketAddr;
             pub fn set_peer_addr(&self, addr: SocketAddr) -> SocketAddr;
+            pub fn multipath_mode(&self) -> MultipathMode;
+            pub fn use_peer_path(&self, addr: SocketAddr) -> ConnectionResult<bool>;
+            pub fn peer_paths(&self) -> Vec<SocketAddr>;
+            pub fn expire_peer_paths(&self, max_idle: Duration) -> Vec<SocketAddr>;
             pub fn current_cipher(&self) -> Option<String>;
             pub fn current_curve(&self) -> Option<String>;
             pub fn state(&self) -> State;
             pub fn activity(&self) -> ConnectionActivity;
             pub fn tick(&self, t: TickType) -> ConnectionResult<()>;
             pub fn authentication_expired(&self) -> ConnectionResult<bool>;
+            pub fn keepalive(&self) -> ConnectionResult<Option<KeepaliveId>>;
 
             pub fn outside_data_received(&self, buf: OutsidePacket) -> ConnectionResult<usize>;
             pub fn inside_data_received(&self, pkt: &mut BytesMut) -> ConnectionResult<()>;
@@ -119,6 +157,17 @@ impl Connection {
         }
     }
 
+    /// Send `reply`, returned by an inside plugin dropping a packet from
+    /// the client (e.g. the ACL rejecting it), back to the client. Unlike
+    /// [`Self::inside_data_received`] the inside plugins do not see it.
+    pub fn send_plugin_reply(&self, mut reply: BytesMut) {
+        let _ = self
+            .lw_conn
+            .lock()
+            .unwrap()
+            .send_to_outside(&mut reply, false);
+    }
+
     /// Handle an outside data error. On a fatal error will disconnect
     /// and return [`std::ops::ControlFlow::Break`], the caller should
     /// stop processing further traffic for this connection (closing
@@ -1,7 +1,6 @@
 mod connection_map;
 
 use bytes::BytesMut;
-use delegate::delegate;
 use parking_lot::Mutex;
 use std::time::Duration;
 use std::{
@@ -19,14 +18,16 @@ use tracing::{info, instrument, warn};
 
 use crate::connection_manager::connection_map::InsertError;
 use crate::{
+    cluster::Cluster,
     connection::{Connection, ConnectionState},
     metrics,
 };
 use connection_map::ConnectionMap;
 use lightway_app_utils::{EventStream, EventStreamCallback, PacketCodecFactoryType};
 use lightway_core::{
-    ConnectionActivity, ConnectionBuilderError, ConnectionError, ContextError, Event,
-    OutsideIOSendCallbackArg, OutsidePacket, ServerContext, SessionId, State, Version,
+    ConnectionActivity, ConnectionBuilderError, ConnectionError, ConnectionType, ContextError,
+    Event, MultipathMode, OutsideIOSendCallbackArg, OutsidePacket, ServerContext, SessionId,
+    SessionTicketKey, State, Version,
 };
 
 use crate::handle_inside_io_error;
@@ -40,6 +41,13 @@ const CONNECTION_AUTH_EXPIRATION_INTERVAL: Duration = Duration::from_hours(6);
 /// How often to check for pending session ids to cleanup
 const PENDING_SESSION_ID_EXPIRATION_INTERVAL: Duration = Duration::from_hours(6);
 
+/// How often to check for multipath paths to expire
+const PEER_PATH_EXPIRATION_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a path of a multipath connection can be idle for, well
+/// beyond the keepalive interval
+const PEER_PATH_MAX_IDLE_AGE: Duration = Duration::from_mins(2);
+
 /// How long a connection can be idle for
 const CONNECTION_MAX_IDLE_AGE: Duration = Duration::from_hours(24);
 
@@ -52,6 +60,10 @@ impl connection_map::Value for Connection {
         self.peer_addr()
     }
 
+    fn path_addrs(&self) -> Vec<SocketAddr> {
+        self.peer_paths()
+    }
+
     fn session_id(&self) -> SessionId {
         self.session_id()
     }
@@ -82,10 +94,17 @@ pub(crate) enum ConnectionManagerError {
     /// LW Context Builder error occurred
     #[error("Context Error: {0}")]
     LwContextError(#[from] ContextError),
+
+    /// The server does not listen on this transport
+    #[error("{0:?} connections are not served")]
+    ConnectionTypeNotServed(ConnectionType),
 }
 
 pub(crate) struct ConnectionManager {
-    ctx: ServerContext<ConnectionState>,
+    /// Context of UDP connections, `None` when not served
+    datagram_ctx: Option<ServerContext<ConnectionState>>,
+    /// Context of TCP connections, `None` when not served
+    stream_ctx: Option<ServerContext<ConnectionState>>,
     connections: Mutex<ConnectionMap<Connection>>,
     pending_session_id_rotations: Mutex<HashMap<SessionId, Weak<Connection>>>,
     /// Total number of sessions there have ever been
@@ -95,6 +114,8 @@ pub(crate) struct ConnectionManager {
     event_cb: Option<crate::ServerEventCbType>,
     /// How often to check for aged connections to expire
     connection_age_expiration_interval: Duration,
+    /// Cluster sharing the UDP sessions, `None` when standalone
+    cluster: Option<Arc<Cluster>>,
 }
 
 #[instrument(level = "trace", skip_all)]
@@ -188,7 +209,7 @@ async fn handle_events(
 
         match event {
             Event::StateChanged(state) => handle_state_change(state, &conn).await,
-            Event::KeepaliveReply => {}
+            Event::KeepaliveReply { .. } => {}
             Event::SessionIdRotationStarted { .. } => {}
             Event::SessionIdRotationAcknowledged { old, new } => {
                 handle_finalize_session_rotation(&conn, old, new);
@@ -198,7 +219,7 @@ async fn handle_events(
             Event::ExpresslaneStateChanged(s) => {
                 info!("Setting expresslane state to {:?}", s);
             }
-            Event::FirstPacketReceived => {
+            Event::FirstPacketReceived | Event::ServerConfig(_) | Event::SessionTicket(_) => {
                 unreachable!("client only event received");
             }
             Event::EncodingStateChanged { enabled } => handle_encoding_state_changed(enabled),
@@ -314,19 +335,23 @@ fn new_connection(
 
 impl ConnectionManager {
     pub(crate) fn new(
-        ctx: ServerContext<ConnectionState>,
+        datagram_ctx: Option<ServerContext<ConnectionState>>,
+        stream_ctx: Option<ServerContext<ConnectionState>>,
         inside_io_codec_factory: Option<PacketCodecFactoryType>,
         event_cb: Option<crate::ServerEventCbType>,
         connection_age_expiration_interval: Duration,
+        cluster: Option<Arc<Cluster>>,
     ) -> Arc<Self> {
         let conn_manager = Arc::new(Self {
-            ctx,
+            datagram_ctx,
+            stream_ctx,
             connections: Mutex::new(Default::default()),
             pending_session_id_rotations: Mutex::new(Default::default()),
             total_sessions: Default::default(),
             inside_io_codec_factory,
             event_cb,
             connection_age_expiration_interval,
+            cluster,
         });
 
         conn_manager.spawn_periodic_task(
@@ -341,6 +366,8 @@ impl ConnectionManager {
             PENDING_SESSION_ID_EXPIRATION_INTERVAL,
             Self::cleanup_pending_session_ids,
         );
+        conn_manager
+            .spawn_periodic_task(PEER_PATH_EXPIRATION_INTERVAL, Self::expire_idle_peer_paths);
 
         conn_manager
     }
@@ -366,11 +393,50 @@ impl ConnectionManager {
         });
     }
 
-    delegate! {
-        to self.ctx {
-            pub(crate) fn is_supported_version(&self, v: Version) -> bool;
-            pub(crate) fn parse_raw_outside_packet<'pkt>(&self, buf: OutsidePacket<'pkt>) -> Result<OutsidePacket<'pkt>, ContextError>;
+    fn ctx(
+        &self,
+        connection_type: ConnectionType,
+    ) -> Result<&ServerContext<ConnectionState>, ConnectionManagerError> {
+        match connection_type {
+            ConnectionType::Datagram => self.datagram_ctx.as_ref(),
+            ConnectionType::Stream => self.stream_ctx.as_ref(),
         }
+        .ok_or(ConnectionManagerError::ConnectionTypeNotServed(
+            connection_type,
+        ))
+    }
+
+    fn contexts(&self) -> impl Iterator<Item = &ServerContext<ConnectionState>> {
+        self.datagram_ctx.iter().chain(self.stream_ctx.iter())
+    }
+
+    /// Predicate returning whether `v` is a supported `Version`, the
+    /// same for every transport
+    pub(crate) fn is_supported_version(&self, v: Version) -> bool {
+        self.contexts().any(|ctx| ctx.is_supported_version(v))
+    }
+
+    /// Run the ingress outside plugins of UDP connections on `pkt`
+    pub(crate) fn parse_raw_outside_packet<'pkt>(
+        &self,
+        pkt: OutsidePacket<'pkt>,
+    ) -> Result<OutsidePacket<'pkt>, ConnectionManagerError> {
+        Ok(self
+            .ctx(ConnectionType::Datagram)?
+            .parse_raw_outside_packet(pkt)?)
+    }
+
+    /// Rotate the session ticket key of every transport, returns
+    /// false when `key` is already in use
+    pub(crate) fn rotate_session_ticket_key(
+        &self,
+        key: &SessionTicketKey,
+    ) -> Result<bool, ContextError> {
+        let mut rotated = false;
+        for ctx in self.contexts() {
+            rotated |= ctx.rotate_session_ticket_key(key)?;
+        }
+        Ok(rotated)
     }
 
     pub(crate) fn total_sessions(&self) -> usize {
@@ -381,6 +447,10 @@ impl ConnectionManager {
         self.connection_age_expiration_interval
     }
 
+    pub(crate) fn cluster(&self) -> Option<&Arc<Cluster>> {
+        self.cluster.as_ref()
+    }
+
     pub(crate) fn pending_session_id_rotations_count(&self) -> usize {
         self.pending_session_id_rotations.lock().len()
     }
@@ -393,13 +463,14 @@ impl ConnectionManager {
     ) -> Result<Arc<Connection>, ConnectionManagerError> {
         let conn = new_connection(
             self.clone(),
-            &self.ctx,
+            self.ctx(ConnectionType::Stream)?,
             protocol_version,
             socket_addr,
             outside_io,
         )?;
-        // TODO: what if addr was already present?
-        self.connections.lock().insert(&conn)?;
+        // Streams are never looked up by address, which may be the
+        // address of a UDP connection too.
+        self.connections.lock().insert_by_session_id(&conn)?;
         Ok(conn)
     }
 
@@ -434,47 +505,90 @@ impl ConnectionManager {
     {
         match self.connections.lock().lookup(addr, session_id) {
             connection_map::Entry::Occupied(c) => {
-                if session_id == SessionId::EMPTY || c.session_id() == session_id {
-                    let update_peer_address = addr != c.peer_addr();
-                    Ok((c.clone(), update_peer_address))
-                } else {
-                    // If the session id of the client does not match
-                    // the session id of our connection then reject.
-                    Err(ConnectionManagerError::SessionIdMismatch)
-                }
+                Self::matching_datagram_connection(c, addr, session_id)
             }
             connection_map::Entry::Vacant(e) if session_id == SessionId::EMPTY => {
                 info!(?addr, %protocol_version, "New Client");
+                let ctx = self.ctx(ConnectionType::Datagram)?;
                 let outside_io = create_io();
-                let c = new_connection(
-                    self.clone(),
-                    &self.ctx,
-                    protocol_version,
-                    local_addr,
-                    outside_io,
-                )?;
+                let c =
+                    new_connection(self.clone(), ctx, protocol_version, local_addr, outside_io)?;
                 e.insert(&c)?;
+                if let Some(cluster) = &self.cluster {
+                    cluster.own(c.session_id());
+                }
                 Ok((c, false))
             }
             connection_map::Entry::Vacant(_e) => {
-                let mut pending_session_id_rotations = self.pending_session_id_rotations.lock();
-                // Maybe this is a pending session rotation
-                if let Some(c) = pending_session_id_rotations.get(&session_id) {
-                    let Some(c) = c.upgrade() else {
-                        pending_session_id_rotations.remove(&session_id);
-                        return Err(ConnectionManagerError::NoActiveSession);
-                    };
-                    let update_peer_address = addr != c.peer_addr();
-
-                    return Ok((c.clone(), update_peer_address));
-                }
-
-                // Client thinks we should have a session, but we don't, reject.
-                Err(ConnectionManagerError::NoActiveSession)
+                self.find_pending_session_id_rotation(addr, session_id)
             }
         }
     }
 
+    /// Lookup the [`Connection`] of `session_id`, a session of a
+    /// datagram received from `addr` by another node of the cluster
+    ///
+    /// Like [`Self::find_or_create_datagram_connection_with`], except
+    /// that no connection is ever created.
+    pub(crate) fn find_datagram_connection_by_session(
+        &self,
+        addr: SocketAddr,
+        session_id: SessionId,
+    ) -> Result<(Arc<Connection>, bool), ConnectionManagerError> {
+        if session_id == SessionId::EMPTY {
+            return Err(ConnectionManagerError::NoActiveSession);
+        }
+        let conn = self.connections.lock().find(addr, session_id);
+        match conn {
+            Some(c) => Self::matching_datagram_connection(c, addr, session_id),
+            None => self.find_pending_session_id_rotation(addr, session_id),
+        }
+    }
+
+    /// Check that `c`, found for a datagram of `session_id` received
+    /// from `addr`, is the connection of that datagram
+    fn matching_datagram_connection(
+        c: Arc<Connection>,
+        addr: SocketAddr,
+        session_id: SessionId,
+    ) -> Result<(Arc<Connection>, bool), ConnectionManagerError> {
+        if !c.connection_type().is_datagram() {
+            // A TCP connection's session id, never sent over UDP
+            return Err(ConnectionManagerError::NoActiveSession);
+        }
+        if session_id == SessionId::EMPTY || c.session_id() == session_id {
+            let update_peer_address = addr != c.peer_addr();
+            Ok((c, update_peer_address))
+        } else {
+            // If the session id of the client does not match
+            // the session id of our connection then reject.
+            Err(ConnectionManagerError::SessionIdMismatch)
+        }
+    }
+
+    /// The connection rotating to `session_id`, for a datagram of a
+    /// session no connection has (yet)
+    fn find_pending_session_id_rotation(
+        &self,
+        addr: SocketAddr,
+        session_id: SessionId,
+    ) -> Result<(Arc<Connection>, bool), ConnectionManagerError> {
+        let mut pending_session_id_rotations = self.pending_session_id_rotations.lock();
+        // Maybe this is a pending session rotation
+        if let Some(c) = pending_session_id_rotations.get(&session_id) {
+            let Some(c) = c.upgrade() else {
+                pending_session_id_rotations.remove(&session_id);
+                return Err(ConnectionManagerError::NoActiveSession);
+            };
+            let update_peer_address = addr != c.peer_addr();
+
+            return Ok((c, update_peer_address));
+        }
+
+        // Client thinks we should have a session, but we don't, reject.
+        Err(ConnectionManagerError::NoActiveSession)
+    }
+
     pub(crate) fn find_datagram_connection_with(
         self: &Arc<Self>,
         addr: SocketAddr,
@@ -489,8 +603,18 @@ impl ConnectionManager {
             .update_socketaddr_for_connection(old_addr, new_addr);
     }
 
+    /// Map `addr`, a new path of multipath `conn`, to it
+    pub(crate) fn add_peer_path(&self, conn: &Arc<Connection>, addr: SocketAddr) {
+        self.connections.lock().insert_path(addr, conn);
+    }
+
     pub(crate) fn remove_connection(&self, conn: &Connection) {
-        self.connections.lock().remove(conn)
+        self.connections.lock().remove(conn);
+        if let Some(cluster) = &self.cluster
+            && conn.connection_type().is_datagram()
+        {
+            cluster.release(conn.session_id());
+        }
     }
 
     pub(crate) fn begin_session_id_rotation(
@@ -501,6 +625,11 @@ impl ConnectionManager {
         self.pending_session_id_rotations
             .lock()
             .insert(new_session_id, Arc::downgrade(conn));
+        // The client may float to another node before the rotation
+        // completes, when it is already using the new session id
+        if let Some(cluster) = &self.cluster {
+            cluster.own(new_session_id);
+        }
 
         metrics::udp_session_rotation_begin();
     }
@@ -515,6 +644,9 @@ impl ConnectionManager {
         self.connections
             .lock()
             .update_session_id_for_connection(old, new);
+        if let Some(cluster) = &self.cluster {
+            cluster.release(old);
+        }
 
         metrics::udp_session_rotation_finalized();
     }
@@ -533,7 +665,7 @@ impl ConnectionManager {
     /// Snapshot of the currently-online connections (Arc clones). The map
     /// lock is released with the returned Vec, so callers can do per-connection
     /// work without holding it - unlike iter_connections.
-    pub(crate) fn online_connections(self: &Arc<Self>) -> Vec<Arc<Connection>> {
+    pub(crate) fn online_connections(&self) -> Vec<Arc<Connection>> {
         self.connections
             .lock()
             .iter_connections()
@@ -583,13 +715,41 @@ impl ConnectionManager {
         }
     }
 
+    #[instrument(level = "trace", skip_all)]
+    fn expire_idle_peer_paths(&self) {
+        tracing::trace!("Expiring multipath paths");
+
+        // Collected first: `iter_connections` holds the connection map lock
+        let multipath: Vec<_> = self
+            .connections
+            .lock()
+            .iter_connections()
+            .filter(|c| c.multipath_mode() != MultipathMode::Off)
+            .cloned()
+            .collect();
+
+        for conn in multipath {
+            for addr in conn.expire_peer_paths(PEER_PATH_MAX_IDLE_AGE) {
+                tracing::debug!(session = ?conn.session_id(), ?addr, "Expiring idle multipath path");
+                metrics::udp_multipath_path_expired();
+                self.connections.lock().remove_path(addr, &conn);
+            }
+        }
+    }
+
     #[instrument(level = "trace", skip_all)]
     fn cleanup_pending_session_ids(&self) {
         tracing::trace!("Cleaning up pending_session_id_rotations");
 
         self.pending_session_id_rotations
             .lock()
-            .retain(|_session_id, conn| conn.upgrade().is_some());
+            .retain(|session_id, conn| {
+                let alive = conn.upgrade().is_some();
+                if !alive && let Some(cluster) = &self.cluster {
+                    cluster.release(*session_id);
+                }
+                alive
+            });
     }
 
     pub(crate) fn shutdown(&self) {
@@ -599,6 +759,11 @@ impl ConnectionManager {
         }
         let connections = self.connections.lock().remove_connections();
         for conn in connections {
+            if let Some(cluster) = &self.cluster
+                && conn.connection_type().is_datagram()
+            {
+                cluster.release(conn.session_id());
+            }
             let _ = conn.lw_disconnect();
         }
     }
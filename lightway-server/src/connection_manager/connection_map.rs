@@ -19,6 +19,12 @@ pub(crate) enum InsertError {
 pub(crate) trait Value {
     fn socket_addr(&self) -> SocketAddr;
     fn session_id(&self) -> SessionId;
+
+    /// Further addresses of a multipath connection, see
+    /// [`ConnectionMap::insert_path`]
+    fn path_addrs(&self) -> Vec<SocketAddr> {
+        Vec::new()
+    }
 }
 
 pub(crate) struct VacantEntry<'a, T> {
@@ -69,15 +75,16 @@ impl<T> Default for ConnectionMap<T> {
 }
 
 impl<T> ConnectionMap<T> {
+    /// Each connection once, whatever the number of its addresses
     pub(crate) fn iter_connections(
         &self,
-    ) -> std::collections::hash_map::Values<'_, SocketAddr, Arc<T>> {
-        self.by_socket_addr.values()
+    ) -> std::collections::hash_map::Values<'_, SessionId, Arc<T>> {
+        self.by_session_id.values()
     }
 
     pub(crate) fn remove_connections(&mut self) -> Vec<Arc<T>> {
-        self.by_session_id.clear();
-        self.by_socket_addr.drain().map(|e| e.1).collect()
+        self.by_socket_addr.clear();
+        self.by_session_id.drain().map(|e| e.1).collect()
     }
 
     pub(crate) fn lookup(&mut self, sock: SocketAddr, session: SessionId) -> Entry<'_, T> {
@@ -102,6 +109,15 @@ impl<T> ConnectionMap<T> {
         self.by_socket_addr.get(&sock).cloned()
     }
 
+    /// Like [`Self::lookup`], without an entry to insert into when
+    /// there is no value
+    pub(crate) fn find(&self, sock: SocketAddr, session: SessionId) -> Option<Arc<T>> {
+        self.by_socket_addr
+            .get(&sock)
+            .or_else(|| self.by_session_id.get(&session))
+            .cloned()
+    }
+
     /// Update the current connection mapped by `old_addr` to be
     /// mapped instead by `new_addr`.
     ///
@@ -117,6 +133,13 @@ impl<T> ConnectionMap<T> {
         }
     }
 
+    /// Map `addr`, another path of a multipath connection, to `value`
+    /// as well. Removed along with `value` provided
+    /// [`Value::path_addrs`] reports it.
+    pub(crate) fn insert_path(&mut self, addr: SocketAddr, value: &Arc<T>) {
+        self.by_socket_addr.insert(addr, value.clone());
+    }
+
     /// Update the current connection mapped by [`SessionId`] `old` to
     /// be mapped instead by `new`.
     ///
@@ -142,8 +165,38 @@ impl<T: Value> ConnectionMap<T> {
         Ok(())
     }
 
+    /// Map `value` by its session id only, for connections never
+    /// looked up by address
+    pub(crate) fn insert_by_session_id(&mut self, value: &Arc<T>) -> Result<(), InsertError> {
+        if value.session_id().is_reserved() {
+            return Err(InsertError::InsertReservedSessionId);
+        };
+
+        self.by_session_id.insert(value.session_id(), value.clone());
+
+        Ok(())
+    }
+
+    /// Unmap `addr`, a path of a multipath connection, if still mapped
+    /// to `value`
+    pub(crate) fn remove_path(&mut self, addr: SocketAddr, value: &T) {
+        if let hash_map::Entry::Occupied(e) = self.by_socket_addr.entry(addr)
+            && std::ptr::eq(Arc::as_ptr(e.get()), value)
+        {
+            e.remove();
+        }
+    }
+
     pub(crate) fn remove(&mut self, value: &T) {
-        self.by_socket_addr.remove(&value.socket_addr());
+        // An address may be mapped to another value when `value` was
+        // inserted by session id only
+        for addr in std::iter::once(value.socket_addr()).chain(value.path_addrs()) {
+            if let hash_map::Entry::Occupied(e) = self.by_socket_addr.entry(addr)
+                && std::ptr::eq(Arc::as_ptr(e.get()), value)
+            {
+                e.remove();
+            }
+        }
         self.by_session_id.remove(&value.session_id());
     }
 }
@@ -169,6 +222,7 @@ mod tests {
     struct V {
         socket_addr: SocketAddr,
         session_id: SessionId,
+        path_addrs: Vec<SocketAddr>,
     }
 
     impl Value for V {
@@ -179,6 +233,10 @@ mod tests {
         fn session_id(&self) -> lightway_core::SessionId {
             self.session_id
         }
+
+        fn path_addrs(&self) -> Vec<SocketAddr> {
+            self.path_addrs.clone()
+        }
     }
 
     #[test_case(SessionId::EMPTY => panics "`Err` value: InsertReservedSessionId")]
@@ -188,6 +246,7 @@ mod tests {
         let v = Arc::new(V {
             socket_addr: SOCKET_ADDR_A,
             session_id,
+            path_addrs: vec![],
         });
         m.insert(&v).unwrap()
     }
@@ -199,6 +258,7 @@ mod tests {
         let v = Arc::new(V {
             socket_addr: SOCKET_ADDR_A,
             session_id,
+            path_addrs: vec![],
         });
 
         match m.lookup(SOCKET_ADDR_A, session_id) {
@@ -220,6 +280,7 @@ mod tests {
         let v = Arc::new(V {
             socket_addr: SOCKET_ADDR_A,
             session_id: SESSION_ID_A,
+            path_addrs: vec![],
         });
 
         m.insert(&v).unwrap();
@@ -241,6 +302,7 @@ mod tests {
         let v = Arc::new(V {
             socket_addr: SOCKET_ADDR_A,
             session_id: SESSION_ID_A,
+            path_addrs: vec![],
         });
         m.insert(&v).unwrap();
 
@@ -248,6 +310,29 @@ mod tests {
         may_be_conn.expect("connection must exist").socket_addr
     }
 
+    #[test]
+    fn find_works_by_socket_addr_or_session_id() {
+        let mut m = ConnectionMap::<V>::default();
+
+        let v = Arc::new(V {
+            socket_addr: SOCKET_ADDR_A,
+            session_id: SESSION_ID_A,
+            path_addrs: vec![],
+        });
+        m.insert(&v).unwrap();
+
+        assert!(Arc::ptr_eq(
+            &v,
+            &m.find(SOCKET_ADDR_A, SESSION_ID_B).unwrap()
+        ));
+        assert!(Arc::ptr_eq(
+            &v,
+            &m.find(SOCKET_ADDR_B, SESSION_ID_A).unwrap()
+        ));
+        assert!(m.find(SOCKET_ADDR_B, SESSION_ID_B).is_none());
+        assert!(m.find(SOCKET_ADDR_B, SessionId::EMPTY).is_none());
+    }
+
     #[test_case(SOCKET_ADDR_A ; "Consistent SocketAddr")]
     #[test_case(SOCKET_ADDR_B => panics "`Err` value: InconsistentSocketAddr" ; "Inconsistent SocketAddr")]
     fn insert_via_entry_works(socket_addr: SocketAddr) {
@@ -257,6 +342,7 @@ mod tests {
         let v = Arc::new(V {
             socket_addr: SOCKET_ADDR_A,
             session_id,
+            path_addrs: vec![],
         });
 
         match m.lookup(socket_addr, SessionId::EMPTY) {
@@ -275,6 +361,7 @@ mod tests {
         let v = Arc::new(V {
             socket_addr: SOCKET_ADDR_A,
             session_id: SESSION_ID_A,
+            path_addrs: vec![],
         });
 
         m.insert(&v).unwrap();
@@ -303,6 +390,7 @@ mod tests {
         let v = Arc::new(V {
             socket_addr: SOCKET_ADDR_A,
             session_id: SESSION_ID_A,
+            path_addrs: vec![],
         });
 
         m.insert(&v).unwrap();
@@ -331,10 +419,12 @@ mod tests {
         let va = Arc::new(V {
             socket_addr: SOCKET_ADDR_A,
             session_id: SESSION_ID_A,
+            path_addrs: vec![],
         });
         let vb = Arc::new(V {
             socket_addr: SOCKET_ADDR_B,
             session_id: SESSION_ID_B,
+            path_addrs: vec![],
         });
 
         m.insert(&va).unwrap();
@@ -373,6 +463,88 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn paths_map_to_connection_until_removed() {
+        let mut m = ConnectionMap::<V>::default();
+
+        let v = Arc::new(V {
+            socket_addr: SOCKET_ADDR_A,
+            session_id: SESSION_ID_A,
+            path_addrs: vec![SOCKET_ADDR_B],
+        });
+
+        m.insert(&v).unwrap();
+        m.insert_path(SOCKET_ADDR_B, &v);
+
+        assert!(Arc::ptr_eq(&v, &m.find_by(SOCKET_ADDR_B).unwrap()));
+        assert_eq!(1, m.iter_connections().count());
+
+        m.remove(&v);
+
+        assert!(m.by_socket_addr.is_empty());
+        assert!(m.by_session_id.is_empty());
+    }
+
+    #[test]
+    fn expired_path_keeps_address_of_other() {
+        let mut m = ConnectionMap::<V>::default();
+
+        let v = Arc::new(V {
+            socket_addr: SOCKET_ADDR_A,
+            session_id: SESSION_ID_A,
+            path_addrs: vec![],
+        });
+        let other = Arc::new(V {
+            socket_addr: SOCKET_ADDR_B,
+            session_id: SESSION_ID_B,
+            path_addrs: vec![],
+        });
+
+        m.insert(&v).unwrap();
+        m.insert_path(SOCKET_ADDR_B, &v);
+        m.remove_path(SOCKET_ADDR_B, &v);
+        assert!(m.find_by(SOCKET_ADDR_B).is_none());
+
+        m.insert_path(SOCKET_ADDR_B, &v);
+        m.insert(&other).unwrap();
+        m.remove_path(SOCKET_ADDR_B, &v);
+        assert!(Arc::ptr_eq(&other, &m.find_by(SOCKET_ADDR_B).unwrap()));
+
+        m.remove(&v);
+        m.remove(&other);
+
+        assert!(m.by_socket_addr.is_empty());
+        assert!(m.by_session_id.is_empty());
+    }
+
+    #[test]
+    fn inserted_by_session_id_keeps_address_of_other() {
+        let mut m = ConnectionMap::<V>::default();
+
+        let datagram = Arc::new(V {
+            socket_addr: SOCKET_ADDR_A,
+            session_id: SESSION_ID_A,
+            path_addrs: vec![],
+        });
+        let stream = Arc::new(V {
+            socket_addr: SOCKET_ADDR_A,
+            session_id: SESSION_ID_B,
+            path_addrs: vec![],
+        });
+
+        m.insert(&datagram).unwrap();
+        m.insert_by_session_id(&stream).unwrap();
+        assert!(Arc::ptr_eq(&datagram, &m.find_by(SOCKET_ADDR_A).unwrap()));
+        assert_eq!(2, m.iter_connections().count());
+
+        m.remove(&stream);
+        assert!(Arc::ptr_eq(&datagram, &m.find_by(SOCKET_ADDR_A).unwrap()));
+        assert!(!m.by_session_id.contains_key(&SESSION_ID_B));
+
+        m.insert_by_session_id(&stream).unwrap();
+        assert_eq!(2, m.remove_connections().len());
+    }
+
     #[test]
     fn iter_connections() {
         let mut m = ConnectionMap::<V>::default();
@@ -380,10 +552,12 @@ mod tests {
         let va = Arc::new(V {
             socket_addr: SOCKET_ADDR_A,
             session_id: SESSION_ID_A,
+            path_addrs: vec![],
         });
         let vb = Arc::new(V {
             socket_addr: SOCKET_ADDR_B,
             session_id: SESSION_ID_B,
+            path_addrs: vec![],
         });
 
         m.insert(&va).unwrap();
@@ -0,0 +1,211 @@
+//! Dead peer detection for UDP sessions
+//!
+//! A UDP session whose client went away silently is otherwise only
+//! evicted once idle for a day. With dead peer detection, a session idle
+//! for [`DeadPeerDetection::idle_timeout`] is sent a keepalive every
+//! [`DeadPeerDetection::interval`], and closed, freeing its inside IP,
+//! once [`DeadPeerDetection::max_missed`] of them in a row go
+//! unanswered.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tracing::instrument;
+
+use crate::{connection_manager::ConnectionManager, metrics};
+
+/// Dead peer detection settings
+#[derive(Clone, Copy, Debug)]
+pub struct DeadPeerDetection {
+    /// How long a session is idle before its peer is probed
+    pub idle_timeout: Duration,
+    /// Interval between probes
+    pub interval: Duration,
+    /// Probes going unanswered in a row before the session is closed
+    pub max_missed: u32,
+}
+
+/// Probes sent to the peer of a session
+#[derive(Debug, Default)]
+pub(crate) struct Probes {
+    /// When the last probe was sent, `None` while not probing
+    sent: Option<Instant>,
+    /// Probes in a row which went unanswered
+    missed: u32,
+}
+
+/// What to do with a session
+#[derive(Debug, PartialEq)]
+enum Verdict {
+    /// The peer was heard from recently
+    Alive,
+    /// The peer is quiet, probe it
+    Probe,
+    /// The peer did not answer enough probes, close the session
+    Dead,
+}
+
+impl Probes {
+    /// Check the session at `now`, its peer last heard from at
+    /// `last_received`. Checks are expected every
+    /// [`DeadPeerDetection::interval`], giving each probe that long to
+    /// be answered.
+    fn check(
+        &mut self,
+        config: &DeadPeerDetection,
+        last_received: Instant,
+        now: Instant,
+    ) -> Verdict {
+        if let Some(sent) = self.sent {
+            if last_received >= sent {
+                *self = Self::default();
+            } else {
+                self.missed += 1;
+                metrics::dpd_probe_missed();
+            }
+        }
+
+        if now.saturating_duration_since(last_received) < config.idle_timeout {
+            *self = Self::default();
+            return Verdict::Alive;
+        }
+
+        if self.missed >= config.max_missed {
+            return Verdict::Dead;
+        }
+
+        self.sent = Some(now);
+        Verdict::Probe
+    }
+}
+
+/// Start checking the UDP sessions of `conn_manager` for dead peers.
+pub(crate) fn spawn(conn_manager: &Arc<ConnectionManager>, config: DeadPeerDetection) {
+    conn_manager.spawn_periodic_task(config.interval, move |conn_manager| {
+        check_peers(conn_manager, &config)
+    });
+}
+
+#[instrument(level = "trace", skip_all)]
+fn check_peers(conn_manager: &ConnectionManager, config: &DeadPeerDetection) {
+    let now = Instant::now();
+    for conn in conn_manager.online_connections() {
+        if !conn.connection_type().is_datagram() {
+            continue;
+        }
+
+        let last_received = conn.activity().last_outside_data_received;
+        let verdict = conn
+            .dead_peer_probes
+            .lock()
+            .unwrap()
+            .check(config, last_received, now);
+        match verdict {
+            Verdict::Alive => {}
+            Verdict::Probe => {
+                tracing::debug!(session = ?conn.session_id(), idle = ?now.saturating_duration_since(last_received), "Probing quiet peer");
+                metrics::dpd_probe_sent();
+                if let Err(err) = conn.keepalive() {
+                    tracing::warn!(session = ?conn.session_id(), ?err, "Failed to probe peer");
+                }
+            }
+            Verdict::Dead => {
+                tracing::info!(session = ?conn.session_id(), "Disconnecting dead peer");
+                metrics::dpd_peer_dead();
+                let _ = conn.disconnect();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    const CONFIG: DeadPeerDetection = DeadPeerDetection {
+        idle_timeout: Duration::from_secs(30),
+        interval: Duration::from_secs(10),
+        max_missed: 3,
+    };
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test_case(0 => Verdict::Alive; "just heard from")]
+    #[test_case(29 => Verdict::Alive; "below idle timeout")]
+    #[test_case(30 => Verdict::Probe; "idle")]
+    fn first_check(idle: u64) -> Verdict {
+        let start = Instant::now();
+        Probes::default().check(&CONFIG, start, start + secs(idle))
+    }
+
+    #[test]
+    fn unanswered_probes_kill() {
+        let start = Instant::now();
+        let mut probes = Probes::default();
+        let verdicts: Vec<_> = (3..=7)
+            .map(|n| probes.check(&CONFIG, start, start + secs(n * 10)))
+            .collect();
+        assert_eq!(
+            verdicts,
+            [
+                Verdict::Probe,
+                Verdict::Probe,
+                Verdict::Probe,
+                Verdict::Dead,
+                Verdict::Dead
+            ]
+        );
+    }
+
+    #[test]
+    fn answered_probe_resets() {
+        let start = Instant::now();
+        let mut probes = Probes::default();
+        assert_eq!(
+            probes.check(&CONFIG, start, start + secs(30)),
+            Verdict::Probe
+        );
+        assert_eq!(
+            probes.check(&CONFIG, start, start + secs(40)),
+            Verdict::Probe
+        );
+        assert_eq!(probes.missed, 1);
+
+        // The second probe is answered
+        let answered = start + secs(41);
+        assert_eq!(
+            probes.check(&CONFIG, answered, start + secs(50)),
+            Verdict::Alive
+        );
+        assert_eq!(probes.missed, 0);
+
+        // and probing starts over once idle again
+        assert_eq!(
+            probes.check(&CONFIG, answered, start + secs(80)),
+            Verdict::Probe
+        );
+        assert_eq!(probes.missed, 0);
+    }
+
+    #[test]
+    fn answered_probe_resets_when_idle_again() {
+        // Probes answered but the session idle again by the next check,
+        // as with an idle timeout below the interval
+        let config = DeadPeerDetection {
+            idle_timeout: secs(5),
+            ..CONFIG
+        };
+        let start = Instant::now();
+        let mut probes = Probes::default();
+        for n in 1..=5 {
+            let now = start + secs(n * 10);
+            assert_eq!(probes.check(&config, now - secs(9), now), Verdict::Probe);
+        }
+        assert_eq!(probes.missed, 0);
+    }
+}
@@ -1,5 +1,9 @@
+pub(crate) mod isolation;
+pub(crate) mod nat;
 pub(crate) mod tun;
 
+pub(crate) use isolation::IsolatingInsideIO;
+pub(crate) use nat::UserspaceNat;
 pub(crate) use tun::Tun;
 
 use crate::connection::ConnectionState;
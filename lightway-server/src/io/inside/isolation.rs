@@ -0,0 +1,126 @@
+//! Client isolation
+//!
+//! A packet a client sends to the inside IP of another client goes to
+//! the inside IO, which routes it straight back to the other client.
+//! [`IsolatingInsideIO`] drops such packets before they reach the
+//! inside IO, unless [`ClientIsolation`] allows them. Packets to a
+//! subnet routed behind another client are treated as sent to that
+//! client.
+
+use std::{net::Ipv4Addr, sync::Arc};
+
+use bytes::BytesMut;
+use clap::ValueEnum;
+use delegate::delegate;
+use lightway_core::{IOCallbackResult, InsideIOSendCallback, InsideIOSendCallbackArg};
+use pnet_packet::ipv4::Ipv4Packet;
+use serde::Deserialize;
+
+use crate::{connection::ConnectionState, ip_manager::IpManager, metrics};
+
+/// Which other clients a client can reach on their inside IPs
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[value(rename_all = "lowercase")]
+pub enum ClientIsolation {
+    /// All other clients
+    #[default]
+    Off,
+    /// Clients assigned an IP from the same pool, i.e. `ip_pool`, one
+    /// of the `ip_map` subnets or one of the named pools
+    Pool,
+    /// No other client
+    Full,
+}
+
+impl ClientIsolation {
+    /// Whether a client can reach another client, `same_pool` telling
+    /// whether their IPs belong to the same pool
+    fn allows(&self, same_pool: bool) -> bool {
+        match self {
+            ClientIsolation::Off => true,
+            ClientIsolation::Pool => same_pool,
+            ClientIsolation::Full => false,
+        }
+    }
+}
+
+/// Inside IO enforcing [`ClientIsolation`] on the packets sent by
+/// clients
+pub(crate) struct IsolatingInsideIO {
+    inner: InsideIOSendCallbackArg<ConnectionState>,
+    ip_manager: Arc<IpManager>,
+    isolation: ClientIsolation,
+}
+
+impl IsolatingInsideIO {
+    pub(crate) fn new(
+        inner: InsideIOSendCallbackArg<ConnectionState>,
+        ip_manager: Arc<IpManager>,
+        isolation: ClientIsolation,
+    ) -> Self {
+        Self {
+            inner,
+            ip_manager,
+            isolation,
+        }
+    }
+
+    /// Whether the client at `source` can send to `destination`
+    fn allows(&self, source: Ipv4Addr, destination: Ipv4Addr) -> bool {
+        // Not client to client traffic
+        let Some(destination) = self.ip_manager.client_of(destination) else {
+            return true;
+        };
+        if source == destination {
+            return true;
+        }
+        let Some(same_pool) = self.ip_manager.same_pool(source, destination) else {
+            return true;
+        };
+
+        let allowed = self.isolation.allows(same_pool);
+        if allowed {
+            metrics::client_to_client_allowed();
+        } else {
+            metrics::client_to_client_dropped();
+        }
+        allowed
+    }
+}
+
+impl InsideIOSendCallback<ConnectionState> for IsolatingInsideIO {
+    fn send(&self, buf: BytesMut, state: &mut ConnectionState) -> IOCallbackResult<usize> {
+        let destination = Ipv4Packet::new(&buf).map(|packet| packet.get_destination());
+        if let (Some(source), Some(destination)) = (state.internal_ip, destination)
+            && !self.allows(source, destination)
+        {
+            return IOCallbackResult::Ok(buf.len());
+        }
+        self.inner.send(buf, state)
+    }
+
+    delegate! {
+        to self.inner {
+            fn mtu(&self) -> usize;
+            fn if_index(&self) -> std::io::Result<u32>;
+            fn name(&self) -> std::io::Result<String>;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(ClientIsolation::Off, true => true)]
+    #[test_case(ClientIsolation::Off, false => true)]
+    #[test_case(ClientIsolation::Pool, true => true)]
+    #[test_case(ClientIsolation::Pool, false => false)]
+    #[test_case(ClientIsolation::Full, true => false)]
+    #[test_case(ClientIsolation::Full, false => false)]
+    fn isolation_allows(isolation: ClientIsolation, same_pool: bool) -> bool {
+        isolation.allows(same_pool)
+    }
+}
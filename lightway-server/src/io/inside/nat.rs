@@ -0,0 +1,261 @@
+//! Userspace NAT
+//!
+//! [`UserspaceNat`] translates the clients' inside traffic to the
+//! server's own address in userspace, instead of writing it to a TUN
+//! device and relying on kernel NAT rules, so that the server needs no
+//! privileges. TCP connections are terminated by a userspace TCP/IP
+//! stack and relayed over host connections, UDP datagrams are relayed
+//! over a host socket per flow. Other protocols are dropped.
+//!
+//! The host connections come from the server, so the destinations the
+//! clients may reach are filtered by [`DestinationFilter`]: the server's
+//! own loopback and the like are never reachable.
+
+mod tcp;
+mod udp;
+
+use std::{
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::Arc,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use ipnet::Ipv4Net;
+use lightway_core::{
+    IOCallbackResult, InsideIOSendCallback, InsideIOSendCallbackArg, ipv4_update_source,
+};
+use pnet_packet::{ip::IpNextHeaderProtocols, ipv4::Ipv4Packet};
+use tokio::sync::{Mutex, Notify, mpsc};
+
+use crate::{
+    connection::ConnectionState,
+    io::inside::{InsideIO, InsideIORecv},
+    ip_manager::IpManager,
+    metrics,
+};
+use tcp::TcpNat;
+use udp::UdpNat;
+
+/// Inside MTU, the default of a TUN device
+const MTU: usize = 1500;
+
+/// Packets queued from and to the clients
+const PACKET_QUEUE_SIZE: usize = 1024;
+
+/// Longest wait between two polls of the TCP/IP stack
+const MAX_POLL_DELAY: Duration = Duration::from_secs(1);
+
+/// How often idle UDP flows are cleaned up
+const UDP_SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A client's flow, from its inside address to a remote address
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+struct FlowKey {
+    client: SocketAddrV4,
+    remote: SocketAddrV4,
+}
+
+/// Destinations the clients may reach through the NAT
+#[derive(Clone, Debug)]
+struct DestinationFilter {
+    deny: Vec<Ipv4Net>,
+    /// Only cleared by tests, to reach servers on loopback
+    deny_special: bool,
+}
+
+impl DestinationFilter {
+    fn new(deny: Vec<Ipv4Net>) -> Self {
+        Self {
+            deny,
+            deny_special: true,
+        }
+    }
+
+    #[cfg(test)]
+    fn allow_all() -> Self {
+        Self {
+            deny: Vec::new(),
+            deny_special: false,
+        }
+    }
+
+    /// Whether a flow to `ip` may be opened. Loopback, link-local,
+    /// unspecified, multicast and broadcast addresses are denied on top
+    /// of the configured subnets: they would reach the server itself or
+    /// its local network rather than a remote host.
+    fn allows(&self, ip: Ipv4Addr) -> bool {
+        let special = ip.is_loopback()
+            || ip.is_link_local()
+            || ip.is_unspecified()
+            || ip.is_multicast()
+            || ip.is_broadcast();
+        !(special && self.deny_special) && !self.deny.iter().any(|net| net.contains(&ip))
+    }
+}
+
+/// Queue a packet for the clients, dropping it if the queue is full
+fn send_to_clients(to_clients: &mpsc::Sender<BytesMut>, packet: BytesMut) {
+    if to_clients.try_send(packet).is_err() {
+        metrics::nat_queue_full();
+    }
+}
+
+/// Inside IO translating the clients' traffic in userspace
+pub(crate) struct UserspaceNat {
+    /// Destinations within the pool are other clients
+    ip_pool: Ipv4Net,
+    /// Destinations routed to a client are subnets behind it
+    ip_manager: Arc<IpManager>,
+    from_clients: mpsc::Sender<BytesMut>,
+    to_clients: mpsc::Sender<BytesMut>,
+    to_clients_rx: Mutex<mpsc::Receiver<BytesMut>>,
+}
+
+impl UserspaceNat {
+    /// Start translating. Packets to `ip_pool` and to the subnets
+    /// routed behind clients by `ip_manager` are handed straight back
+    /// to the clients, flows to `deny` are refused.
+    pub(crate) fn new(ip_pool: Ipv4Net, ip_manager: Arc<IpManager>, deny: Vec<Ipv4Net>) -> Self {
+        let (from_clients, from_clients_rx) = mpsc::channel(PACKET_QUEUE_SIZE);
+        let (to_clients, to_clients_rx) = mpsc::channel(PACKET_QUEUE_SIZE);
+
+        tokio::spawn(nat_task(
+            from_clients_rx,
+            to_clients.clone(),
+            DestinationFilter::new(deny),
+        ));
+
+        Self {
+            ip_pool,
+            ip_manager,
+            from_clients,
+            to_clients,
+            to_clients_rx: Mutex::new(to_clients_rx),
+        }
+    }
+}
+
+/// Translate the packets from the clients until [`UserspaceNat`] is
+/// dropped
+async fn nat_task(
+    mut from_clients: mpsc::Receiver<BytesMut>,
+    to_clients: mpsc::Sender<BytesMut>,
+    filter: DestinationFilter,
+) {
+    // Notified by the host side of the flows
+    let wake = Arc::new(Notify::new());
+    let mut tcp = TcpNat::new(MTU, to_clients.clone(), wake.clone(), filter.clone());
+    let mut udp = UdpNat::new(MTU, to_clients, filter);
+    let mut udp_sweep = tokio::time::interval(UDP_SWEEP_INTERVAL);
+
+    loop {
+        let delay = tcp
+            .poll_delay()
+            .unwrap_or(MAX_POLL_DELAY)
+            .min(MAX_POLL_DELAY);
+        tokio::select! {
+            packet = from_clients.recv() => {
+                let Some(packet) = packet else {
+                    break;
+                };
+                handle_packet(&mut tcp, &mut udp, packet);
+                while let Ok(packet) = from_clients.try_recv() {
+                    handle_packet(&mut tcp, &mut udp, packet);
+                }
+            }
+            _ = wake.notified() => {}
+            _ = tokio::time::sleep(delay) => {}
+            _ = udp_sweep.tick() => udp.sweep(),
+        }
+        tcp.poll();
+    }
+}
+
+fn handle_packet(tcp: &mut TcpNat, udp: &mut UdpNat, packet: BytesMut) {
+    let protocol = Ipv4Packet::new(&packet).map(|p| p.get_next_level_protocol());
+    match protocol {
+        Some(IpNextHeaderProtocols::Tcp) => tcp.handle(packet),
+        Some(IpNextHeaderProtocols::Udp) => udp.handle(&packet),
+        _ => metrics::nat_unsupported_packet(),
+    }
+}
+
+#[async_trait]
+impl InsideIORecv for UserspaceNat {
+    async fn recv_buf(&self, buf: &mut BytesMut) -> IOCallbackResult<usize> {
+        let Some(packet) = self.to_clients_rx.lock().await.recv().await else {
+            return IOCallbackResult::Err(std::io::Error::other("Userspace NAT stopped"));
+        };
+        buf.clear();
+        buf.extend_from_slice(&packet);
+        metrics::tun_to_client(packet.len());
+        IOCallbackResult::Ok(packet.len())
+    }
+
+    fn into_io_send_callback(self: Arc<Self>) -> InsideIOSendCallbackArg<ConnectionState> {
+        self
+    }
+}
+
+impl InsideIOSendCallback<ConnectionState> for UserspaceNat {
+    fn send(&self, mut buf: BytesMut, state: &mut ConnectionState) -> IOCallbackResult<usize> {
+        let len = buf.len();
+        let Some(client_ip) = state.internal_ip else {
+            metrics::tun_rejected_packet_no_client_ip();
+            // Ip address not found, dropping the packet
+            return IOCallbackResult::Ok(len);
+        };
+
+        // Packets from the subnets behind the client keep their source
+        if !state.from_routed_subnet(&buf) {
+            ipv4_update_source(buf.as_mut(), client_ip);
+        }
+        metrics::tun_from_client(len);
+
+        let to_client = Ipv4Packet::new(&buf).is_some_and(|p| {
+            let destination = p.get_destination();
+            self.ip_pool.contains(&destination) || self.ip_manager.find_route(destination).is_some()
+        });
+        if to_client {
+            send_to_clients(&self.to_clients, buf);
+        } else if self.from_clients.try_send(buf).is_err() {
+            metrics::nat_queue_full();
+        }
+        IOCallbackResult::Ok(len)
+    }
+
+    fn mtu(&self) -> usize {
+        MTU
+    }
+
+    fn if_index(&self) -> std::io::Result<u32> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+
+    fn name(&self) -> std::io::Result<String> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+}
+
+impl InsideIO for UserspaceNat {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("1.1.1.1" => true; "public")]
+    #[test_case("192.168.1.1" => true; "private")]
+    #[test_case("127.0.0.1" => false; "loopback")]
+    #[test_case("169.254.169.254" => false; "link-local")]
+    #[test_case("0.0.0.0" => false; "unspecified")]
+    #[test_case("224.0.0.251" => false; "multicast")]
+    #[test_case("255.255.255.255" => false; "broadcast")]
+    #[test_case("10.0.0.1" => false; "denied")]
+    fn filters_destinations(ip: &str) -> bool {
+        let filter = DestinationFilter::new(vec!["10.0.0.0/8".parse().unwrap()]);
+        filter.allows(ip.parse().unwrap())
+    }
+}
@@ -0,0 +1,479 @@
+//! TCP translation
+//!
+//! Client connections are terminated by a userspace TCP/IP stack
+//! ([smoltcp]) which accepts connections to any address. A socket
+//! listening for the remote address is added on each new SYN, and the
+//! data of the accepted connection is relayed over a host connection to
+//! that address.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bytes::{Buf, Bytes, BytesMut};
+use pnet_packet::{
+    Packet,
+    ipv4::Ipv4Packet,
+    tcp::{TcpFlags, TcpPacket},
+};
+use smoltcp::{
+    iface::{Config, Interface, SocketHandle, SocketSet},
+    phy::{self, Device, DeviceCapabilities, Medium},
+    socket::tcp,
+    wire::{HardwareAddress, IpAddress, IpCidr, IpListenEndpoint},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        TcpStream,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
+    sync::{Notify, mpsc},
+    task::JoinHandle,
+};
+
+use super::{DestinationFilter, FlowKey, send_to_clients};
+use crate::metrics;
+
+/// Receive and send buffer of each connection of the stack
+const TCP_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Most flows translated at a time
+const MAX_TCP_FLOWS: usize = 8192;
+
+/// Chunks of data queued between a flow and its host connection
+const HOST_QUEUE_SIZE: usize = 16;
+
+/// Largest chunk of data read from a host connection
+const HOST_READ_SIZE: usize = 16 * 1024;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connections unacknowledged for this long are aborted
+const TCP_TIMEOUT: Duration = Duration::from_secs(120);
+
+const TCP_KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+/// The only address of the stack, which accepts connections to any
+/// address routed through it
+const STACK_IP: Ipv4Addr = Ipv4Addr::new(0, 0, 0, 1);
+
+/// Device of the stack, exchanging packets with the clients
+struct ClientDevice {
+    mtu: usize,
+    /// Packets from the clients, yet to be processed by the stack
+    received: VecDeque<BytesMut>,
+    to_clients: mpsc::Sender<BytesMut>,
+}
+
+struct ClientRxToken(BytesMut);
+
+struct ClientTxToken<'a>(&'a mpsc::Sender<BytesMut>);
+
+impl phy::RxToken for ClientRxToken {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        f(&self.0)
+    }
+}
+
+impl phy::TxToken for ClientTxToken<'_> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut packet = BytesMut::zeroed(len);
+        let result = f(&mut packet);
+        send_to_clients(self.0, packet);
+        result
+    }
+}
+
+impl Device for ClientDevice {
+    type RxToken<'a> = ClientRxToken;
+    type TxToken<'a> = ClientTxToken<'a>;
+
+    fn receive(
+        &mut self,
+        _timestamp: smoltcp::time::Instant,
+    ) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let packet = self.received.pop_front()?;
+        Some((ClientRxToken(packet), ClientTxToken(&self.to_clients)))
+    }
+
+    fn transmit(&mut self, _timestamp: smoltcp::time::Instant) -> Option<Self::TxToken<'_>> {
+        Some(ClientTxToken(&self.to_clients))
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut capabilities = DeviceCapabilities::default();
+        capabilities.medium = Medium::Ip;
+        capabilities.max_transmission_unit = self.mtu;
+        capabilities
+    }
+}
+
+/// Sent by the host connection of a flow
+enum HostEvent {
+    Data(Bytes),
+    /// The host connection failed, reset the client's
+    Failed,
+}
+
+struct TcpFlow {
+    key: FlowKey,
+    created: Instant,
+    /// `None` once the client closed its side
+    to_host: Option<mpsc::Sender<Bytes>>,
+    /// Closed once the host closed its side
+    from_host: mpsc::Receiver<HostEvent>,
+    /// Data from the host not accepted by the client's connection yet
+    pending: Bytes,
+    host_closed: bool,
+    host: JoinHandle<()>,
+}
+
+impl Drop for TcpFlow {
+    fn drop(&mut self) {
+        self.host.abort();
+    }
+}
+
+impl TcpFlow {
+    /// Relay data between the client's connection and the host's.
+    /// Returns `false` once the flow is over.
+    fn relay(&mut self, socket: &mut tcp::Socket) -> bool {
+        // Client to host
+        while socket.can_recv()
+            && let Some(permit) = self.to_host.as_ref().and_then(|tx| tx.try_reserve().ok())
+        {
+            match socket.recv(|data| (data.len(), Bytes::copy_from_slice(data))) {
+                Ok(data) => permit.send(data),
+                Err(_) => break,
+            }
+        }
+        if !socket.can_recv()
+            && matches!(
+                socket.state(),
+                tcp::State::CloseWait | tcp::State::LastAck | tcp::State::Closing
+            )
+        {
+            // Shuts the host connection down for writing
+            self.to_host = None;
+        }
+
+        // Host to client
+        loop {
+            if self.pending.is_empty() {
+                match self.from_host.try_recv() {
+                    Ok(HostEvent::Data(data)) => self.pending = data,
+                    Ok(HostEvent::Failed) => {
+                        socket.abort();
+                        return false;
+                    }
+                    Err(mpsc::error::TryRecvError::Empty) => break,
+                    Err(mpsc::error::TryRecvError::Disconnected) => {
+                        if !self.host_closed {
+                            self.host_closed = true;
+                            socket.close();
+                        }
+                        break;
+                    }
+                }
+            }
+            if !socket.can_send() {
+                break;
+            }
+            match socket.send_slice(&self.pending) {
+                Ok(len) => self.pending.advance(len),
+                Err(_) => {
+                    socket.abort();
+                    return false;
+                }
+            }
+        }
+
+        match socket.state() {
+            tcp::State::Closed => false,
+            // The SYN never made it to the socket
+            tcp::State::Listen => self.created.elapsed() < CONNECT_TIMEOUT,
+            _ => true,
+        }
+    }
+}
+
+pub(super) struct TcpNat {
+    iface: Interface,
+    device: ClientDevice,
+    sockets: SocketSet<'static>,
+    flows: HashMap<SocketHandle, TcpFlow>,
+    handles: HashMap<FlowKey, SocketHandle>,
+    /// Notified by the host connections
+    wake: Arc<Notify>,
+    filter: DestinationFilter,
+}
+
+impl TcpNat {
+    pub(super) fn new(
+        mtu: usize,
+        to_clients: mpsc::Sender<BytesMut>,
+        wake: Arc<Notify>,
+        filter: DestinationFilter,
+    ) -> Self {
+        let mut device = ClientDevice {
+            mtu,
+            received: VecDeque::new(),
+            to_clients,
+        };
+        let mut iface = Interface::new(
+            Config::new(HardwareAddress::Ip),
+            &mut device,
+            smoltcp::time::Instant::now(),
+        );
+        iface.set_any_ip(true);
+        iface.update_ip_addrs(|addrs| {
+            addrs
+                .push(IpCidr::new(IpAddress::Ipv4(STACK_IP), 0))
+                .expect("Room for one address");
+        });
+        iface
+            .routes_mut()
+            .add_default_ipv4_route(STACK_IP)
+            .expect("Room for one route");
+
+        Self {
+            iface,
+            device,
+            sockets: SocketSet::new(Vec::new()),
+            flows: HashMap::new(),
+            handles: HashMap::new(),
+            wake,
+            filter,
+        }
+    }
+
+    /// Hand a TCP packet from a client to the stack
+    pub(super) fn handle(&mut self, packet: BytesMut) {
+        if let Some(key) = new_connection(&packet)
+            && !self.handles.contains_key(&key)
+        {
+            self.listen(key);
+        }
+        self.device.received.push_back(packet);
+    }
+
+    /// Listen for the new connection of `key` and open its host
+    /// connection. Without a listening socket, the stack resets it.
+    fn listen(&mut self, key: FlowKey) {
+        if !self.filter.allows(*key.remote.ip()) {
+            metrics::nat_destination_denied();
+            return;
+        }
+        if self.flows.len() >= MAX_TCP_FLOWS {
+            metrics::nat_flow_limit_reached();
+            return;
+        }
+
+        let mut socket = tcp::Socket::new(
+            tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+            tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+        );
+        socket.set_timeout(Some(TCP_TIMEOUT.into()));
+        socket.set_keep_alive(Some(TCP_KEEP_ALIVE.into()));
+        let endpoint = IpListenEndpoint {
+            addr: Some(IpAddress::Ipv4(*key.remote.ip())),
+            port: key.remote.port(),
+        };
+        if let Err(err) = socket.listen(endpoint) {
+            tracing::debug!(?err, remote = %key.remote, "Failed to listen");
+            return;
+        }
+
+        let (to_host, from_client) = mpsc::channel(HOST_QUEUE_SIZE);
+        let (to_client, from_host) = mpsc::channel(HOST_QUEUE_SIZE);
+        let host = tokio::spawn(relay_host(
+            key.remote,
+            from_client,
+            to_client,
+            self.wake.clone(),
+        ));
+
+        let handle = self.sockets.add(socket);
+        self.handles.insert(key, handle);
+        self.flows.insert(
+            handle,
+            TcpFlow {
+                key,
+                created: Instant::now(),
+                to_host: Some(to_host),
+                from_host,
+                pending: Bytes::new(),
+                host_closed: false,
+                host,
+            },
+        );
+    }
+
+    /// How long until the stack must be polled again, at the latest
+    pub(super) fn poll_delay(&mut self) -> Option<Duration> {
+        self.iface
+            .poll_delay(smoltcp::time::Instant::now(), &self.sockets)
+            .map(Into::into)
+    }
+
+    /// Process the packets from the clients, relay data between the
+    /// flows and their host connections and send the resulting packets
+    pub(super) fn poll(&mut self) {
+        self.iface.poll(
+            smoltcp::time::Instant::now(),
+            &mut self.device,
+            &mut self.sockets,
+        );
+
+        let mut over = Vec::new();
+        for (handle, flow) in self.flows.iter_mut() {
+            if !flow.relay(self.sockets.get_mut::<tcp::Socket>(*handle)) {
+                over.push(*handle);
+            }
+        }
+        for handle in over {
+            if let Some(flow) = self.flows.remove(&handle) {
+                self.handles.remove(&flow.key);
+            }
+            self.sockets.remove(handle);
+        }
+
+        // Send what was relayed right away
+        self.iface.poll(
+            smoltcp::time::Instant::now(),
+            &mut self.device,
+            &mut self.sockets,
+        );
+    }
+}
+
+/// Flow of a packet opening a new connection, i.e. a SYN
+fn new_connection(packet: &[u8]) -> Option<FlowKey> {
+    let ip = Ipv4Packet::new(packet)?;
+    let tcp = TcpPacket::new(ip.payload())?;
+    let flags = tcp.get_flags();
+    if flags & TcpFlags::SYN == 0 || flags & TcpFlags::ACK != 0 {
+        return None;
+    }
+    Some(FlowKey {
+        client: SocketAddrV4::new(ip.get_source(), tcp.get_source()),
+        remote: SocketAddrV4::new(ip.get_destination(), tcp.get_destination()),
+    })
+}
+
+/// Connect to `remote` and relay data between the connection and the
+/// flow
+async fn relay_host(
+    remote: SocketAddrV4,
+    from_client: mpsc::Receiver<Bytes>,
+    to_client: mpsc::Sender<HostEvent>,
+    wake: Arc<Notify>,
+) {
+    let stream = match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(remote)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(_)) | Err(_) => {
+            metrics::nat_tcp_connect_failed();
+            let _ = to_client.send(HostEvent::Failed).await;
+            wake.notify_one();
+            return;
+        }
+    };
+    let (reader, writer) = stream.into_split();
+
+    // The flow sees the host closing once `download` dropped its sender,
+    // failures of `upload` are reported unless that already happened
+    let failed = to_client.downgrade();
+    let result = tokio::try_join!(
+        upload(writer, from_client, &wake),
+        download(reader, to_client, &wake),
+    );
+    if result.is_err()
+        && let Some(to_client) = failed.upgrade()
+    {
+        let _ = to_client.send(HostEvent::Failed).await;
+    }
+    wake.notify_one();
+}
+
+/// Write the data of the client to the host connection
+async fn upload(
+    mut writer: OwnedWriteHalf,
+    mut from_client: mpsc::Receiver<Bytes>,
+    wake: &Notify,
+) -> std::io::Result<()> {
+    while let Some(data) = from_client.recv().await {
+        writer.write_all(&data).await?;
+        // Room for more data in the queue
+        wake.notify_one();
+    }
+    writer.shutdown().await
+}
+
+/// Read data from the host connection for the client
+async fn download(
+    mut reader: OwnedReadHalf,
+    to_client: mpsc::Sender<HostEvent>,
+    wake: &Notify,
+) -> std::io::Result<()> {
+    loop {
+        let mut buf = BytesMut::with_capacity(HOST_READ_SIZE);
+        match reader.read_buf(&mut buf).await {
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            Err(err) => {
+                let _ = to_client.send(HostEvent::Failed).await;
+                return Err(err);
+            }
+        }
+        if to_client.send(HostEvent::Data(buf.freeze())).await.is_err() {
+            return Ok(());
+        }
+        wake.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet_packet::{ipv4::MutableIpv4Packet, tcp::MutableTcpPacket};
+    use test_case::test_case;
+
+    fn tcp_packet(flags: u8) -> BytesMut {
+        let mut buf = BytesMut::zeroed(40);
+        let mut ip = MutableIpv4Packet::new(&mut buf).unwrap();
+        ip.set_version(4);
+        ip.set_header_length(5);
+        ip.set_total_length(40);
+        ip.set_source("10.125.0.2".parse().unwrap());
+        ip.set_destination("1.1.1.1".parse().unwrap());
+        let mut tcp = MutableTcpPacket::new(&mut buf[20..]).unwrap();
+        tcp.set_source(40000);
+        tcp.set_destination(443);
+        tcp.set_data_offset(5);
+        tcp.set_flags(flags);
+        buf
+    }
+
+    #[test_case(TcpFlags::SYN => true)]
+    #[test_case(TcpFlags::SYN | TcpFlags::ACK => false)]
+    #[test_case(TcpFlags::ACK => false)]
+    #[test_case(TcpFlags::FIN | TcpFlags::ACK => false)]
+    fn detects_new_connection(flags: u8) -> bool {
+        let key = new_connection(&tcp_packet(flags));
+        if let Some(key) = key {
+            assert_eq!(key.client, "10.125.0.2:40000".parse().unwrap());
+            assert_eq!(key.remote, "1.1.1.1:443".parse().unwrap());
+        }
+        key.is_some()
+    }
+}
@@ -0,0 +1,268 @@
+//! UDP translation
+//!
+//! Each flow is relayed over its own host socket, bound to an
+//! ephemeral port of the server and connected to the remote address.
+//! A flow expires once no datagram came back for [`UDP_IDLE_TIMEOUT`].
+//! Datagrams coming back which would not fit the inside MTU are dropped,
+//! as they cannot be fragmented.
+
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::Arc,
+    time::Duration,
+};
+
+use bytes::BytesMut;
+use pnet_packet::{
+    Packet,
+    ip::IpNextHeaderProtocols,
+    ipv4::{self, Ipv4Packet, MutableIpv4Packet},
+    udp::{self, MutableUdpPacket, UdpPacket},
+};
+use tokio::{net::UdpSocket, sync::mpsc, task::JoinHandle};
+
+use super::{DestinationFilter, FlowKey, send_to_clients};
+use crate::metrics;
+
+const UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Most flows translated at a time
+const MAX_UDP_FLOWS: usize = 16384;
+
+const IPV4_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+
+struct UdpFlow {
+    socket: Arc<UdpSocket>,
+    /// Relays the datagrams coming back, ends when the flow expires
+    replies: JoinHandle<()>,
+}
+
+impl Drop for UdpFlow {
+    fn drop(&mut self) {
+        self.replies.abort();
+    }
+}
+
+pub(super) struct UdpNat {
+    flows: HashMap<FlowKey, UdpFlow>,
+    /// Largest datagram relayed to the clients
+    max_payload: usize,
+    to_clients: mpsc::Sender<BytesMut>,
+    filter: DestinationFilter,
+}
+
+impl UdpNat {
+    pub(super) fn new(
+        mtu: usize,
+        to_clients: mpsc::Sender<BytesMut>,
+        filter: DestinationFilter,
+    ) -> Self {
+        Self {
+            flows: HashMap::new(),
+            max_payload: mtu - IPV4_HEADER_LEN - UDP_HEADER_LEN,
+            to_clients,
+            filter,
+        }
+    }
+
+    /// Relay a UDP packet from a client
+    pub(super) fn handle(&mut self, packet: &[u8]) {
+        let Some(ip) = Ipv4Packet::new(packet) else {
+            return;
+        };
+        // Non initial fragments have no UDP header
+        if ip.get_fragment_offset() != 0 {
+            metrics::nat_unsupported_packet();
+            return;
+        }
+        let Some(udp) = UdpPacket::new(ip.payload()) else {
+            return;
+        };
+        let key = FlowKey {
+            client: SocketAddrV4::new(ip.get_source(), udp.get_source()),
+            remote: SocketAddrV4::new(ip.get_destination(), udp.get_destination()),
+        };
+
+        let socket = match self.flows.get(&key) {
+            Some(flow) if !flow.replies.is_finished() => flow.socket.clone(),
+            _ => match self.open(key) {
+                Some(socket) => socket,
+                None => return,
+            },
+        };
+        // Dropped like any datagram if the socket buffer is full
+        let _ = socket.try_send(udp.payload());
+    }
+
+    /// Open the host socket of a new flow
+    fn open(&mut self, key: FlowKey) -> Option<Arc<UdpSocket>> {
+        if !self.filter.allows(*key.remote.ip()) {
+            metrics::nat_destination_denied();
+            return None;
+        }
+        if self.flows.len() >= MAX_UDP_FLOWS {
+            self.sweep();
+            if self.flows.len() >= MAX_UDP_FLOWS {
+                metrics::nat_flow_limit_reached();
+                return None;
+            }
+        }
+
+        let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+            .and_then(|socket| {
+                socket.set_nonblocking(true)?;
+                socket.connect(key.remote)?;
+                UdpSocket::from_std(socket)
+            })
+            .inspect_err(
+                |err| tracing::warn!(?err, remote = %key.remote, "Failed to open NAT socket"),
+            )
+            .ok()?;
+        let socket = Arc::new(socket);
+
+        let replies = tokio::spawn(relay_replies(
+            socket.clone(),
+            key,
+            self.max_payload,
+            self.to_clients.clone(),
+        ));
+        self.flows.insert(
+            key,
+            UdpFlow {
+                socket: socket.clone(),
+                replies,
+            },
+        );
+        Some(socket)
+    }
+
+    /// Forget the expired flows
+    pub(super) fn sweep(&mut self) {
+        self.flows.retain(|_, flow| !flow.replies.is_finished());
+    }
+}
+
+/// Relay the datagrams from the remote address of a flow to its client,
+/// up to `max_payload` bytes long
+async fn relay_replies(
+    socket: Arc<UdpSocket>,
+    key: FlowKey,
+    max_payload: usize,
+    to_clients: mpsc::Sender<BytesMut>,
+) {
+    // Room for the largest datagram, so that longer ones are not
+    // mistaken for truncated ones
+    let mut buf = vec![0; u16::MAX as usize];
+    loop {
+        let len = match tokio::time::timeout(UDP_IDLE_TIMEOUT, socket.recv(&mut buf)).await {
+            Ok(Ok(len)) => len,
+            // Idle, or the remote is unreachable
+            Ok(Err(_)) | Err(_) => break,
+        };
+        if len > max_payload {
+            metrics::nat_oversized_reply();
+            continue;
+        }
+        send_to_clients(&to_clients, udp_packet(key.remote, key.client, &buf[..len]));
+    }
+}
+
+/// IPv4 packet of a UDP datagram
+fn udp_packet(source: SocketAddrV4, destination: SocketAddrV4, payload: &[u8]) -> BytesMut {
+    let udp_len = UDP_HEADER_LEN + payload.len();
+    let total_len = IPV4_HEADER_LEN + udp_len;
+    let mut buf = BytesMut::zeroed(total_len);
+
+    let mut udp = MutableUdpPacket::new(&mut buf[IPV4_HEADER_LEN..]).expect("Buffer large enough");
+    udp.set_source(source.port());
+    udp.set_destination(destination.port());
+    udp.set_length(udp_len as u16);
+    udp.set_payload(payload);
+    let checksum = udp::ipv4_checksum(&udp.to_immutable(), source.ip(), destination.ip());
+    udp.set_checksum(checksum);
+
+    let mut ip = MutableIpv4Packet::new(&mut buf).expect("Buffer large enough");
+    ip.set_version(4);
+    ip.set_header_length((IPV4_HEADER_LEN / 4) as u8);
+    ip.set_total_length(total_len as u16);
+    ip.set_ttl(64);
+    ip.set_next_level_protocol(IpNextHeaderProtocols::Udp);
+    ip.set_source(*source.ip());
+    ip.set_destination(*destination.ip());
+    let checksum = ipv4::checksum(&ip.to_immutable());
+    ip.set_checksum(checksum);
+
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn udp_packet_is_valid() {
+        let source = "1.1.1.1:53".parse().unwrap();
+        let destination = "10.125.0.2:40000".parse().unwrap();
+        let buf = udp_packet(source, destination, b"answer");
+
+        let ip = Ipv4Packet::new(&buf).unwrap();
+        assert_eq!(ip.get_total_length() as usize, buf.len());
+        assert_eq!(ip.get_checksum(), ipv4::checksum(&ip));
+        assert_eq!(ip.get_source(), *source.ip());
+        assert_eq!(ip.get_destination(), *destination.ip());
+
+        let udp = UdpPacket::new(ip.payload()).unwrap();
+        assert_eq!(udp.get_source(), source.port());
+        assert_eq!(udp.get_destination(), destination.port());
+        assert_eq!(
+            udp.get_checksum(),
+            udp::ipv4_checksum(&udp, source.ip(), destination.ip())
+        );
+        assert_eq!(udp.payload(), b"answer");
+    }
+
+    #[tokio::test]
+    async fn relays_datagrams() {
+        let remote = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let remote_addr = match remote.local_addr().unwrap() {
+            std::net::SocketAddr::V4(addr) => addr,
+            addr => panic!("Unexpected address {addr}"),
+        };
+        let client = "10.125.0.2:40000".parse().unwrap();
+        let (to_clients, mut to_clients_rx) = mpsc::channel(2);
+        let mut nat = UdpNat::new(1500, to_clients, DestinationFilter::allow_all());
+
+        nat.handle(&udp_packet(client, remote_addr, b"query"));
+
+        let mut buf = [0; 16];
+        let (len, nat_addr) = remote.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"query");
+
+        // Does not fit the MTU, dropped
+        remote.send_to(&[0; 1500 - 27], nat_addr).await.unwrap();
+        remote.send_to(&[0; 1500 - 28], nat_addr).await.unwrap();
+        remote.send_to(b"answer", nat_addr).await.unwrap();
+
+        let reply = to_clients_rx.recv().await.unwrap();
+        assert_eq!(reply.len(), 1500);
+        let reply = to_clients_rx.recv().await.unwrap();
+        assert_eq!(reply, udp_packet(remote_addr, client, b"answer"));
+    }
+
+    #[tokio::test]
+    async fn denies_destinations() {
+        let remote = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let remote_addr = match remote.local_addr().unwrap() {
+            std::net::SocketAddr::V4(addr) => addr,
+            addr => panic!("Unexpected address {addr}"),
+        };
+        let client = "10.125.0.2:40000".parse().unwrap();
+        let (to_clients, _to_clients_rx) = mpsc::channel(1);
+        let mut nat = UdpNat::new(1500, to_clients, DestinationFilter::new(Vec::new()));
+
+        nat.handle(&udp_packet(client, remote_addr, b"query"));
+        assert!(nat.flows.is_empty());
+    }
+}
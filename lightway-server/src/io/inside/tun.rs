@@ -120,7 +120,10 @@ impl InsideIOSendCallback<ConnectionState> for Tun {
             return IOCallbackResult::Ok(buf.len());
         };
 
-        ipv4_update_source(buf.as_mut(), client_ip);
+        // Packets from the subnets behind the client keep their source
+        if !state.from_routed_subnet(&buf) {
+            ipv4_update_source(buf.as_mut(), client_ip);
+        }
         metrics::tun_from_client(buf.len());
         self.0.try_send(buf)
     }
@@ -4,8 +4,8 @@ use anyhow::{Result, anyhow};
 use async_trait::async_trait;
 use bytes::BytesMut;
 use lightway_core::{
-    ConnectionType, IOCallbackResult, MAX_OUTSIDE_MTU, OutsideIOSendCallback, OutsidePacket, State,
-    Version,
+    ConnectionError, ConnectionType, IOCallbackResult, MAX_OUTSIDE_MTU, OutsideIOSendCallback,
+    OutsidePacket, State, Version,
 };
 use socket2::SockRef;
 use tokio::io::AsyncReadExt as _;
@@ -165,10 +165,14 @@ async fn handle_connection(
         };
 
         let pkt = OutsidePacket::Wire(&mut buf, ConnectionType::Stream);
-        if let Err(err) = conn.outside_data_received(pkt) {
-            warn!("Failed to process outside data: {err}");
-            if conn.handle_outside_data_error(&err).is_break() {
-                break anyhow!(err).context("Outside data fatal error");
+        match conn.outside_data_received(pkt) {
+            Ok(_) => {}
+            Err(ConnectionError::PluginDropWithReply(reply)) => conn.send_plugin_reply(reply),
+            Err(err) => {
+                warn!("Failed to process outside data: {err}");
+                if conn.handle_outside_data_error(&err).is_break() {
+                    break anyhow!(err).context("Outside data fatal error");
+                }
             }
         }
     };
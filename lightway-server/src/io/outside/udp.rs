@@ -6,12 +6,13 @@ use async_trait::async_trait;
 use bytes::BytesMut;
 use bytesize::ByteSize;
 use lightway_app_utils::cmsg;
+use lightway_app_utils::obfuscation::Obfuscation;
 #[cfg(target_os = "linux")]
 use lightway_app_utils::sockopt;
 use lightway_app_utils::sockopt::socket_enable_pktinfo;
 use lightway_core::{
-    ConnectionType, Header, IOCallbackResult, MAX_IO_BATCH_SIZE, MAX_OUTSIDE_MTU,
-    OutsideIOSendCallback, OutsidePacket, SessionId, Version,
+    ConnectionError, ConnectionType, Header, IOCallbackResult, MAX_IO_BATCH_SIZE, MAX_OUTSIDE_MTU,
+    MultipathMode, OutsideIOSendCallback, OutsidePacket, PeerPath, SessionId, Version,
 };
 use socket2::{MaybeUninitSlice, MsgHdr, MsgHdrMut, SockAddr, SockRef};
 use std::os::fd::AsRawFd;
@@ -19,6 +20,7 @@ use std::{
     io::IoSlice,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     sync::{Arc, RwLock},
+    time::{Duration, Instant},
 };
 use tokio::io::Interest;
 use tracing::{info, warn};
@@ -26,7 +28,7 @@ use tracing::{info, warn};
 use super::Server;
 use crate::io::outside::udp::batch_receive::{BatchRecvSlot, recv_multiple_with_metadata};
 use crate::io::outside::udp::send_queue::SendQueue;
-use crate::{connection_manager::ConnectionManager, metrics};
+use crate::{connection::Connection, connection_manager::ConnectionManager, metrics};
 
 enum BindMode {
     UnspecifiedAddress { local_port: u16 },
@@ -107,45 +109,301 @@ fn send_to_socket(
     }
 }
 
+/// Most paths a multipath client may use besides its first address,
+/// and most addresses challenged at once
+const MAX_PEER_PATHS: usize = 7;
+
+/// Least time between two challenges of the same address
+const PATH_CHALLENGE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Least time between two updates of when a path was last received
+/// from, keeping the common case on the read lock
+const PATH_RECEIVED_RESOLUTION: Duration = Duration::from_secs(1);
+
+/// A path of a multipath client besides its first address
+struct Path {
+    addr: SocketAddr,
+    sock_addr: SockAddr,
+    last_received: Instant,
+}
+
+/// An address challenged to prove it is a path of the client, see
+/// [`PeerPath::Unvalidated`]
+struct PendingPath {
+    addr: SocketAddr,
+    token: u64,
+    challenged: Instant,
+    validated: bool,
+}
+
+/// Paths of a multipath client besides its first address, see
+/// [`MultipathMode`].
+#[derive(Default)]
+struct PeerPaths {
+    mode: MultipathMode,
+    paths: Vec<Path>,
+    /// Index in `paths` of the path received from last, `None` for the
+    /// first address
+    active: Option<usize>,
+    pending: Vec<PendingPath>,
+    /// Path every send goes to, see [`OutsideIOSendCallback::pin_peer_path`]
+    pinned: Option<SockAddr>,
+}
+
+impl PeerPaths {
+    /// `Some(None)` for the first address `peer_addr`, `Some(index)`
+    /// for a path, `None` for an address which is neither
+    fn find(&self, peer_addr: SocketAddr, addr: SocketAddr) -> Option<Option<usize>> {
+        if addr == peer_addr {
+            Some(None)
+        } else {
+            self.paths
+                .iter()
+                .position(|path| path.addr == addr)
+                .map(Some)
+        }
+    }
+
+    /// Whether [`Self::use_path`] would change nothing
+    fn in_use(
+        &self,
+        peer_addr: SocketAddr,
+        addr: SocketAddr,
+        mode: MultipathMode,
+        now: Instant,
+    ) -> bool {
+        if self.mode != mode {
+            return false;
+        }
+        match self.find(peer_addr, addr) {
+            Some(None) => self.active.is_none(),
+            Some(Some(index)) => {
+                self.active == Some(index)
+                    && now.duration_since(self.paths[index].last_received)
+                        < PATH_RECEIVED_RESOLUTION
+            }
+            None => false,
+        }
+    }
+
+    fn use_path(
+        &mut self,
+        peer_addr: SocketAddr,
+        addr: SocketAddr,
+        mode: MultipathMode,
+        now: Instant,
+    ) -> PeerPath {
+        self.mode = mode;
+        if let Some(active) = self.find(peer_addr, addr) {
+            if let Some(index) = active {
+                self.paths[index].last_received = now;
+            }
+            self.active = active;
+            return PeerPath::Known;
+        }
+
+        let Some(pending) = self
+            .pending
+            .iter()
+            .position(|pending| pending.addr == addr && pending.validated)
+        else {
+            return PeerPath::Unvalidated;
+        };
+        if self.paths.len() >= MAX_PEER_PATHS {
+            warn!(?addr, "Too many multipath paths, ignoring");
+            return PeerPath::Known;
+        }
+
+        self.pending.swap_remove(pending);
+        self.paths.push(Path {
+            addr,
+            sock_addr: addr.into(),
+            last_received: now,
+        });
+        self.active = Some(self.paths.len() - 1);
+        PeerPath::Added
+    }
+
+    fn challenge(&mut self, addr: SocketAddr, token: u64, now: Instant) -> bool {
+        if let Some(pending) = self.pending.iter_mut().find(|pending| pending.addr == addr) {
+            if now.duration_since(pending.challenged) < PATH_CHALLENGE_INTERVAL {
+                return false;
+            }
+            pending.token = token;
+            pending.challenged = now;
+            return true;
+        }
+
+        let pending = PendingPath {
+            addr,
+            token,
+            challenged: now,
+            validated: false,
+        };
+        if self.pending.len() < MAX_PEER_PATHS {
+            self.pending.push(pending);
+            return true;
+        }
+
+        // Replace the address challenged longest ago, unless all were
+        // just challenged
+        let oldest = self
+            .pending
+            .iter_mut()
+            .min_by_key(|pending| pending.challenged)
+            .expect("MAX_PEER_PATHS is not zero");
+        if now.duration_since(oldest.challenged) < PATH_CHALLENGE_INTERVAL {
+            return false;
+        }
+        *oldest = pending;
+        true
+    }
+
+    fn validated(&mut self, token: u64) {
+        if let Some(pending) = self
+            .pending
+            .iter_mut()
+            .find(|pending| pending.token == token)
+        {
+            pending.validated = true;
+        }
+    }
+
+    fn expire(&mut self, max_idle: Duration, now: Instant) -> Vec<SocketAddr> {
+        let active = self.active.map(|index| self.paths[index].addr);
+        let mut expired = Vec::new();
+        self.paths.retain(|path| {
+            let idle = now.duration_since(path.last_received) >= max_idle;
+            if idle {
+                expired.push(path.addr);
+            }
+            !idle
+        });
+        // The first address takes over from an expired active path
+        self.active = active.and_then(|addr| self.paths.iter().position(|path| path.addr == addr));
+        self.pending
+            .retain(|pending| now.duration_since(pending.challenged) < max_idle);
+        expired
+    }
+}
+
 struct UdpSocket {
     sock: Arc<tokio::net::UdpSocket>,
     peer_addr: RwLock<(SocketAddr, SockAddr)>,
+    peer_paths: RwLock<PeerPaths>,
     reply_pktinfo: Option<libc::in_pktinfo>,
     send_queue: Option<Arc<SendQueue>>,
 }
 
-impl OutsideIOSendCallback for UdpSocket {
-    fn send(&self, buf: &[u8]) -> IOCallbackResult<usize> {
+impl UdpSocket {
+    /// Send with `send` to the peer, on every path of a redundant
+    /// multipath session. Succeeds if any path does.
+    fn send_to_peer(
+        &self,
+        send: impl Fn(&SockAddr) -> IOCallbackResult<usize>,
+    ) -> IOCallbackResult<usize> {
         let peer_addr = self.peer_addr.read().unwrap();
-        if let Some(queue) = &self.send_queue
-            && queue.try_enqueue(peer_addr.1.clone(), self.reply_pktinfo, buf)
-        {
-            return IOCallbackResult::Ok(buf.len());
+        let peer_paths = self.peer_paths.read().unwrap();
+        if let Some(pinned) = &peer_paths.pinned {
+            return send(pinned);
+        }
+        match (peer_paths.mode, peer_paths.active) {
+            (MultipathMode::Off, _) | (MultipathMode::Scheduled, None) => send(&peer_addr.1),
+            (MultipathMode::Scheduled, Some(active)) => send(&peer_paths.paths[active].sock_addr),
+            (MultipathMode::Redundant, _) => {
+                let mut result = send(&peer_addr.1);
+                for path in &peer_paths.paths {
+                    let path_result = send(&path.sock_addr);
+                    if !matches!(result, IOCallbackResult::Ok(_)) {
+                        result = path_result;
+                    }
+                }
+                result
+            }
         }
-        send_to_socket(
-            &self.sock,
-            &[IoSlice::new(buf)],
-            &peer_addr.1,
-            self.reply_pktinfo,
-            None,
-        )
+    }
+}
+
+impl OutsideIOSendCallback for UdpSocket {
+    fn send(&self, buf: &[u8]) -> IOCallbackResult<usize> {
+        self.send_to_peer(|addr| {
+            if let Some(queue) = &self.send_queue
+                && queue.try_enqueue(addr.clone(), self.reply_pktinfo, buf)
+            {
+                return IOCallbackResult::Ok(buf.len());
+            }
+            send_to_socket(
+                &self.sock,
+                &[IoSlice::new(buf)],
+                addr,
+                self.reply_pktinfo,
+                None,
+            )
+        })
     }
 
     fn send_gso(&self, bufs: &[IoSlice<'_>], gso_size: u16) -> IOCallbackResult<usize> {
-        let peer_addr = self.peer_addr.read().unwrap();
-        send_to_socket(
-            &self.sock,
-            bufs,
-            &peer_addr.1,
-            self.reply_pktinfo,
-            Some(gso_size),
-        )
+        self.send_to_peer(|addr| {
+            send_to_socket(&self.sock, bufs, addr, self.reply_pktinfo, Some(gso_size))
+        })
     }
 
     fn peer_addr(&self) -> SocketAddr {
         self.peer_addr.read().unwrap().0
     }
 
+    fn use_peer_path(&self, addr: SocketAddr, mode: MultipathMode) -> PeerPath {
+        let peer_addr = self.peer_addr.read().unwrap().0;
+        let now = Instant::now();
+
+        // Common case: on the path received from last
+        if self
+            .peer_paths
+            .read()
+            .unwrap()
+            .in_use(peer_addr, addr, mode, now)
+        {
+            return PeerPath::Known;
+        }
+
+        self.peer_paths
+            .write()
+            .unwrap()
+            .use_path(peer_addr, addr, mode, now)
+    }
+
+    fn challenge_peer_path(&self, addr: SocketAddr, token: u64) -> bool {
+        self.peer_paths
+            .write()
+            .unwrap()
+            .challenge(addr, token, Instant::now())
+    }
+
+    fn peer_path_validated(&self, token: u64) {
+        self.peer_paths.write().unwrap().validated(token)
+    }
+
+    fn pin_peer_path(&self, addr: Option<SocketAddr>) {
+        self.peer_paths.write().unwrap().pinned = addr.map(Into::into);
+    }
+
+    fn peer_paths(&self) -> Vec<SocketAddr> {
+        self.peer_paths
+            .read()
+            .unwrap()
+            .paths
+            .iter()
+            .map(|path| path.addr)
+            .collect()
+    }
+
+    fn expire_peer_paths(&self, max_idle: Duration) -> Vec<SocketAddr> {
+        self.peer_paths
+            .write()
+            .unwrap()
+            .expire(max_idle, Instant::now())
+    }
+
     fn set_peer_addr(&self, addr: SocketAddr) -> SocketAddr {
         let mut peer_addr = self.peer_addr.write().unwrap();
         let old_addr = peer_addr.0;
@@ -154,12 +412,69 @@ impl OutsideIOSendCallback for UdpSocket {
     }
 }
 
+/// Handle `pkt`, a frame of `conn` received from `peer_addr`, directly
+/// or forwarded by another node of the cluster
+pub(crate) fn frame_received(
+    conn_manager: &ConnectionManager,
+    conn: Arc<Connection>,
+    update_peer_address: bool,
+    peer_addr: SocketAddr,
+    pkt: OutsidePacket,
+) {
+    let Some(&hdr) = pkt.header() else {
+        return;
+    };
+
+    match conn.outside_data_received(pkt) {
+        Ok(0) => {
+            // We will hit this case when there is UDP packet duplication.
+            // TLS library skips duplicate packets and thus no frames read.
+            // It is also possible that adversary can capture the packet
+            // and replay it. In any case, skip processing further
+            if update_peer_address {
+                metrics::udp_session_rotation_attempted_via_replay();
+            }
+        }
+        Ok(_) => {
+            // NOTE: We wait until the first successful TLS
+            // decrypt to protect against the case where a crafted
+            // packet with a session ID causes us to change the
+            // connection IP without verifying the SSL connection
+            // first
+            if hdr.multipath != MultipathMode::Off && conn.multipath_mode() != MultipathMode::Off {
+                // Another path of the session, not the client floating
+                match conn.use_peer_path(peer_addr) {
+                    Ok(true) => {
+                        metrics::udp_multipath_path_added();
+                        conn_manager.add_peer_path(&conn, peer_addr);
+                    }
+                    Ok(false) => {}
+                    Err(err) => warn!(?peer_addr, "Failed to challenge multipath path: {err}"),
+                }
+            } else if update_peer_address {
+                metrics::udp_conn_recovered_via_session(hdr.session);
+                // Address first: the rotation announce must go to the
+                // address the client roamed to.
+                conn_manager.set_peer_addr(&conn, peer_addr);
+                conn.begin_session_id_rotation();
+            }
+        }
+        Err(ConnectionError::PluginDropWithReply(reply)) => conn.send_plugin_reply(reply),
+        Err(err) => {
+            warn!("Failed to process outside data: {err}");
+            let _ = conn.handle_outside_data_error(&err);
+            // Fatal or not, we are done with this packet.
+        }
+    }
+}
+
 pub(crate) struct UdpServer {
     conn_manager: Arc<ConnectionManager>,
     sock: Arc<tokio::net::UdpSocket>,
     bind_mode: BindMode,
     batch_receive_enabled: bool,
     send_queue: Option<Arc<SendQueue>>,
+    obfuscation: Obfuscation,
 }
 
 impl UdpServer {
@@ -169,6 +484,7 @@ impl UdpServer {
         udp_buffer_size: ByteSize,
         enable_batch_receive: bool,
         enable_batch_send: bool,
+        obfuscation: Obfuscation,
         sock: Option<tokio::net::UdpSocket>,
     ) -> Result<UdpServer> {
         let sock = match sock {
@@ -230,6 +546,7 @@ impl UdpServer {
             bind_mode,
             batch_receive_enabled,
             send_queue,
+            obfuscation,
         })
     }
 
@@ -256,7 +573,7 @@ impl UdpServer {
             }
         };
 
-        let Some(hdr) = pkt.header() else {
+        let Some(&hdr) = pkt.header() else {
             metrics::udp_no_header();
             warn!("Packet parsing error: Not a UDP frame");
             return;
@@ -278,18 +595,30 @@ impl UdpServer {
                     hdr.session,
                     local_addr,
                     || {
-                        Arc::new(UdpSocket {
-                            sock: self.sock.clone(),
-                            peer_addr: RwLock::new((peer_addr, peer_addr.into())),
-                            reply_pktinfo,
-                            send_queue: self.send_queue.clone(),
-                        })
+                        self.obfuscation.wrap_outside_io(
+                            ConnectionType::Datagram,
+                            Arc::new(UdpSocket {
+                                sock: self.sock.clone(),
+                                peer_addr: RwLock::new((peer_addr, peer_addr.into())),
+                                peer_paths: RwLock::new(PeerPaths::default()),
+                                reply_pktinfo,
+                                send_queue: self.send_queue.clone(),
+                            }),
+                        )
                     },
                 );
 
                 match conn_result {
                     Ok(conn) => conn,
                     Err(_e) => {
+                        // The session may be owned by another node of
+                        // the cluster
+                        if let Some(cluster) = self.conn_manager.cluster()
+                            && let Some(owner) = cluster.owner(hdr.session)
+                        {
+                            cluster.forward(owner, peer_addr, pkt);
+                            return;
+                        }
                         self.send_reject(peer_addr.into(), reply_pktinfo);
                         return;
                     }
@@ -297,47 +626,28 @@ impl UdpServer {
             }
         };
 
-        let session = hdr.session;
-
-        match conn.outside_data_received(pkt) {
-            Ok(0) => {
-                // We will hit this case when there is UDP packet duplication.
-                // TLS library skips duplicate packets and thus no frames read.
-                // It is also possible that adversary can capture the packet
-                // and replay it. In any case, skip processing further
-                if update_peer_address {
-                    metrics::udp_session_rotation_attempted_via_replay();
-                }
-            }
-            Ok(_) => {
-                // NOTE: We wait until the first successful TLS
-                // decrypt to protect against the case where a crafted
-                // packet with a session ID causes us to change the
-                // connection IP without verifying the SSL connection
-                // first
-                if update_peer_address {
-                    metrics::udp_conn_recovered_via_session(session);
-                    // Address first: the rotation announce must go to the
-                    // address the client roamed to.
-                    self.conn_manager.set_peer_addr(&conn, peer_addr);
-                    conn.begin_session_id_rotation();
-                }
-            }
-            Err(err) => {
-                warn!("Failed to process outside data: {err}");
-                let _ = conn.handle_outside_data_error(&err);
-                // Fatal or not, we are done with this packet.
-            }
-        }
+        frame_received(
+            &self.conn_manager,
+            conn,
+            update_peer_address,
+            peer_addr,
+            pkt,
+        );
     }
 
     fn send_reject(&self, peer_addr: SockAddr, reply_pktinfo: Option<libc::in_pktinfo>) {
         metrics::udp_rejected_session();
+        // The reject bypasses the outside plugins, unobfuscated it would
+        // give the server away
+        if self.obfuscation.is_enabled(ConnectionType::Datagram) {
+            return;
+        }
         let msg = Header {
             version: Version::MINIMUM,
             aggressive_mode: false,
             session: SessionId::REJECTED,
             expresslane_data: false,
+            multipath: MultipathMode::Off,
         };
 
         let mut buf = BytesMut::with_capacity(Header::WIRE_SIZE);
@@ -606,3 +916,104 @@ fn read_multiple_from_socket<const N: usize>(
 
     Ok(metadata)
 }
+
+// Tests START -> panic, unwrap, expect allowed
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 1234);
+    const PATH_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 4567);
+    const MODE: MultipathMode = MultipathMode::Scheduled;
+
+    #[test]
+    fn path_added_only_once_validated() {
+        let mut paths = PeerPaths::default();
+        let now = Instant::now();
+
+        assert_eq!(
+            paths.use_path(PEER_ADDR, PEER_ADDR, MODE, now),
+            PeerPath::Known
+        );
+        assert_eq!(
+            paths.use_path(PEER_ADDR, PATH_ADDR, MODE, now),
+            PeerPath::Unvalidated
+        );
+        assert!(paths.challenge(PATH_ADDR, 42, now));
+        // A wrong token validates nothing
+        paths.validated(7);
+        assert_eq!(
+            paths.use_path(PEER_ADDR, PATH_ADDR, MODE, now),
+            PeerPath::Unvalidated
+        );
+
+        paths.validated(42);
+        assert_eq!(
+            paths.use_path(PEER_ADDR, PATH_ADDR, MODE, now),
+            PeerPath::Added
+        );
+        assert_eq!(paths.active, Some(0));
+        assert!(paths.pending.is_empty());
+        assert_eq!(
+            paths.use_path(PEER_ADDR, PATH_ADDR, MODE, now),
+            PeerPath::Known
+        );
+    }
+
+    #[test]
+    fn challenges_are_rate_limited() {
+        let mut paths = PeerPaths::default();
+        let now = Instant::now();
+
+        assert!(paths.challenge(PATH_ADDR, 1, now));
+        assert!(!paths.challenge(PATH_ADDR, 2, now + PATH_CHALLENGE_INTERVAL / 2));
+        assert!(paths.challenge(PATH_ADDR, 3, now + PATH_CHALLENGE_INTERVAL));
+
+        // Only the latest token counts
+        paths.validated(1);
+        assert!(!paths.pending[0].validated);
+        paths.validated(3);
+        assert!(paths.pending[0].validated);
+    }
+
+    #[test]
+    fn challenged_addresses_are_bounded() {
+        let mut paths = PeerPaths::default();
+        let now = Instant::now();
+        let addr = |port| SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
+
+        for port in 0..MAX_PEER_PATHS as u16 {
+            assert!(paths.challenge(addr(port), port.into(), now));
+        }
+        assert!(!paths.challenge(PATH_ADDR, 42, now));
+
+        let later = now + PATH_CHALLENGE_INTERVAL;
+        assert!(paths.challenge(PATH_ADDR, 42, later));
+        assert_eq!(paths.pending.len(), MAX_PEER_PATHS);
+        assert!(
+            paths
+                .pending
+                .iter()
+                .any(|pending| pending.addr == PATH_ADDR)
+        );
+    }
+
+    #[test]
+    fn idle_paths_expire() {
+        let mut paths = PeerPaths::default();
+        let now = Instant::now();
+        let max_idle = Duration::from_secs(60);
+
+        assert!(paths.challenge(PATH_ADDR, 42, now));
+        paths.validated(42);
+        assert_eq!(
+            paths.use_path(PEER_ADDR, PATH_ADDR, MODE, now),
+            PeerPath::Added
+        );
+
+        assert!(paths.expire(max_idle, now + max_idle / 2).is_empty());
+        assert_eq!(paths.expire(max_idle, now + max_idle), [PATH_ADDR]);
+        assert!(paths.paths.is_empty());
+        assert_eq!(paths.active, None);
+    }
+}
@@ -1,11 +1,19 @@
 mod ip_pool;
+mod lease;
+mod pool_state;
+mod routes;
+mod state_file;
 
+use anyhow::Result;
 use ipnet::Ipv4Net;
-use lightway_core::{InsideIpConfig, ServerIpPool};
+use lightway_core::{InsideIpConfig, InsideIpRequest, ServerIpPool};
 use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
-use tracing::info;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_stream::StreamExt;
+use tracing::{info, warn};
 
 use crate::{
     connection::{Connection, ConnectionState},
@@ -13,6 +21,27 @@ use crate::{
 };
 
 use ip_pool::IpPool;
+use lease::Leases;
+use pool_state::PoolState;
+use routes::Routes;
+
+/// How often expired IP leases are dropped, and the leases and pool
+/// state persisted
+const MAINTENANCE_INTERVAL: Duration = Duration::from_mins(1);
+
+/// How long the IPs allocated before a restart are held for their
+/// clients to reconnect
+const RESTORED_IP_HOLD_TIME: Duration = Duration::from_mins(5);
+
+/// Sticky IP assignment settings
+#[derive(Clone, Debug)]
+pub struct IpLeases {
+    /// How long the IP of a client identity is remembered after its
+    /// connection closed
+    pub lease_time: Duration,
+    /// File to persist the leases to across restarts
+    pub file: Option<PathBuf>,
+}
 
 /// IpManager - Manages IP pool to assign to clients
 /// Similar to DHCP server
@@ -28,12 +57,45 @@ struct IpManagerInner<T> {
     ip_to_conn_map: HashMap<Ipv4Addr, T>,
     /// Mapping of pool's for specific IPs
     ip_map: HashMap<IpAddr, IpPool>,
+    /// Pools clients are assigned to by name
+    named_pools: HashMap<String, IpPool>,
     /// IP pool
     ip_pool: IpPool,
+    /// Networks of all the pools, which subnets routed to clients must
+    /// not overlap
+    pool_nets: Vec<Ipv4Net>,
+    /// Subnets behind clients, routed to their inside IP and connection
+    routes: Routes<(Ipv4Addr, T)>,
     /// Static inside ip config which should be sent to clients in case of IP translation
     static_ip_config: InsideIpConfig,
     /// Use static IP or actual assigned IP address
     use_dynamic_client_ip: bool,
+    /// IPs last assigned to client identities, `None` when IPs are not
+    /// sticky
+    leases: Option<Leases>,
+    /// File to persist `leases` to
+    lease_file: Option<PathBuf>,
+    /// File to persist the state of the pools to
+    pool_state_file: Option<PathBuf>,
+    /// IPs allocated before a restart, held until then for the client
+    /// which had them, see [`IpManager::with_pool_state`]
+    held: HashMap<Ipv4Addr, SystemTime>,
+}
+
+/// Allocate `preferred` from `pool`, taking it over if it is `held`,
+/// or any available IP of `pool`
+fn allocate_preferring(
+    pool: &mut IpPool,
+    held: &mut HashMap<Ipv4Addr, SystemTime>,
+    preferred: Option<Ipv4Addr>,
+) -> Option<Ipv4Addr> {
+    if let Some(ip) = preferred
+        && pool.is_allocated(ip)
+        && held.remove(&ip).is_some()
+    {
+        return Some(ip);
+    }
+    pool.allocate_ip_preferring(preferred)
 }
 
 impl ServerIpPool<ConnectionState> for IpManager {
@@ -49,9 +111,15 @@ impl ServerIpPool<ConnectionState> for IpManager {
         match state.internal_ip {
             Some(ip) => Some(self.inside_ip_config(ip)),
             None => {
-                let (allocation, config) = self.alloc(conn, state.local_addr.ip())?;
+                let (allocation, config) = self.alloc(
+                    conn.clone(),
+                    state.local_addr.ip(),
+                    state.identity.as_deref(),
+                    state.requested_ip.as_ref(),
+                )?;
 
                 state.internal_ip = Some(allocation);
+                state.routed_subnets = self.add_routes(conn, allocation, &state.subnets);
 
                 Some(config)
             }
@@ -60,8 +128,9 @@ impl ServerIpPool<ConnectionState> for IpManager {
 
     fn free(&self, state: &mut ConnectionState) {
         if let Some(ip) = state.internal_ip.take() {
-            self.free(ip, state.local_addr.ip());
+            self.free(ip, state.identity.as_deref());
         }
+        self.remove_routes(&std::mem::take(&mut state.routed_subnets));
     }
 }
 
@@ -74,6 +143,8 @@ impl<T> IpManager<T> {
         use_dynamic_client_ip: bool,
         randomize_ippool: bool,
     ) -> Self {
+        let mut pool_nets = vec![ip_pool];
+        pool_nets.extend(ip_map.values());
         let mut ip_pool = IpPool::new(ip_pool, reserved_ips);
         if randomize_ippool {
             ip_pool.shuffle_ips();
@@ -90,38 +161,178 @@ impl<T> IpManager<T> {
             inner: RwLock::new(IpManagerInner {
                 ip_to_conn_map: HashMap::new(),
                 ip_map,
+                named_pools: HashMap::new(),
                 ip_pool,
+                pool_nets,
+                routes: Routes::default(),
                 static_ip_config,
                 use_dynamic_client_ip,
+                leases: None,
+                lease_file: None,
+                pool_state_file: None,
+                held: HashMap::new(),
             }),
         }
     }
 
+    /// Split pools off `ip_pool` which clients can request by name
+    pub(crate) fn with_named_pools(self, pools: HashMap<String, Ipv4Net>) -> Self {
+        {
+            let mut inner = self.inner.write().unwrap();
+            inner.pool_nets.extend(pools.values());
+            let named_pools = pools
+                .into_iter()
+                .map(|(name, subnet)| (name, inner.ip_pool.split_subnet(subnet)))
+                .collect();
+            inner.named_pools = named_pools;
+        }
+        self
+    }
+
+    /// Hand clients the IP they were last assigned when it is free,
+    /// loading the leases persisted to `config.file`
+    pub(crate) fn with_leases(self, config: &IpLeases) -> Result<Self> {
+        let mut leases = Leases::new(config.lease_time);
+        if let Some(file) = &config.file {
+            leases.load(file, SystemTime::now())?;
+        }
+        {
+            let mut inner = self.inner.write().unwrap();
+            inner.leases = Some(leases);
+            inner.lease_file = config.file.clone();
+        }
+        Ok(self)
+    }
+
+    /// Restore the state of the pools persisted to `file`, and persist
+    /// it there from now on. The IPs assigned before a restart are held
+    /// for [`RESTORED_IP_HOLD_TIME`], for a client to get its IP back
+    /// when reconnecting with the identity leased it or requesting it,
+    /// and the others are reused in the same order.
+    pub(crate) fn with_pool_state(self, file: &Path) -> Result<Self> {
+        let state = pool_state::load(file)?;
+        {
+            let mut inner = self.inner.write().unwrap();
+            if let Some(state) = state {
+                inner.restore_pools(state, SystemTime::now());
+            }
+            inner.pool_state_file = Some(file.to_path_buf());
+        }
+        Ok(self)
+    }
+
     pub(crate) fn allocated_ips_count(&self) -> usize {
         let inner = self.inner.read().unwrap();
         inner.ip_to_conn_map.len()
     }
 
+    pub(crate) fn leases_count(&self) -> Option<usize> {
+        let inner = self.inner.read().unwrap();
+        inner.leases.as_ref().map(Leases::len)
+    }
+
+    /// Drop expired leases and holds, then persist the rest and the
+    /// state of the pools to their files
+    fn maintain(&self) -> Result<()> {
+        let now = SystemTime::now();
+        let (leases, pools) = {
+            let mut inner = self.inner.write().unwrap();
+            if let Some(leases) = inner.leases.as_mut() {
+                leases.expire(now);
+            }
+            inner.expire_held(now);
+            let leases = inner
+                .lease_file
+                .clone()
+                .zip(inner.leases.as_ref())
+                .map(|(file, leases)| (file, leases.records(now)));
+            let pools = inner
+                .pool_state_file
+                .clone()
+                .map(|file| (file, inner.pool_state()));
+            (leases, pools)
+        };
+        if let Some((file, records)) = leases {
+            lease::save(&file, &records)?;
+        }
+        if let Some((file, state)) = pools {
+            pool_state::save(&file, &state)?;
+        }
+        Ok(())
+    }
+
     fn inside_ip_config(&self, ip: Ipv4Addr) -> InsideIpConfig {
         let inner = self.inner.read().unwrap();
         inner.config_for_ip(ip)
     }
 
-    fn alloc(&self, conn: T, local_ip: IpAddr) -> Option<(Ipv4Addr, InsideIpConfig)> {
+    fn alloc(
+        &self,
+        conn: T,
+        local_ip: IpAddr,
+        identity: Option<&str>,
+        request: Option<&InsideIpRequest>,
+    ) -> Option<(Ipv4Addr, InsideIpConfig)> {
         let mut inner = self.inner.write().unwrap();
 
-        let IpManagerInner {
-            ip_map, ip_pool, ..
-        } = &mut *inner;
-
-        let ip_pool = ip_map.get_mut(&local_ip).unwrap_or(ip_pool);
-
-        let Some(ip) = ip_pool.allocate_ip() else {
-            metrics::connection_rejected_no_free_ip();
-            return None;
+        // The leased IP may be in use by another client, or belong to
+        // another pool since the client connected to another server IP
+        let leased_ip = inner
+            .leases
+            .as_ref()
+            .zip(identity)
+            .and_then(|(leases, identity)| leases.lookup(identity, SystemTime::now()));
+
+        let ip = match request {
+            Some(InsideIpRequest::Ip(ip)) => {
+                // Reserved from whichever pool holds it
+                if inner.held.remove(ip).is_none()
+                    && !inner.pools_mut().any(|pool| pool.allocate_specific_ip(*ip))
+                {
+                    warn!(ip = ?ip, "Requested IP is not available");
+                    metrics::connection_rejected_inside_ip_unavailable();
+                    return None;
+                }
+                *ip
+            }
+            Some(InsideIpRequest::Pool(name)) => {
+                let IpManagerInner {
+                    named_pools, held, ..
+                } = &mut *inner;
+                let Some(pool) = named_pools.get_mut(name) else {
+                    warn!(pool = name, "Requested IP pool does not exist");
+                    metrics::connection_rejected_inside_ip_unavailable();
+                    return None;
+                };
+                let Some(ip) = allocate_preferring(pool, held, leased_ip) else {
+                    metrics::connection_rejected_no_free_ip();
+                    return None;
+                };
+                ip
+            }
+            None => {
+                let IpManagerInner {
+                    ip_map,
+                    ip_pool,
+                    held,
+                    ..
+                } = &mut *inner;
+
+                let ip_pool = ip_map.get_mut(&local_ip).unwrap_or(ip_pool);
+
+                let Some(ip) = allocate_preferring(ip_pool, held, leased_ip) else {
+                    metrics::connection_rejected_no_free_ip();
+                    return None;
+                };
+                ip
+            }
         };
 
-        info!(ip = ?ip, "Alloc");
+        info!(ip = ?ip, leased = leased_ip == Some(ip), "Alloc");
+
+        if let Some((leases, identity)) = inner.leases.as_mut().zip(identity) {
+            leases.bind(identity, ip);
+        }
 
         inner.ip_to_conn_map.insert(ip, conn);
 
@@ -130,20 +341,117 @@ impl<T> IpManager<T> {
         Some((ip, config))
     }
 
-    fn free(&self, ip: Ipv4Addr, local_ip: IpAddr) {
+    fn free(&self, ip: Ipv4Addr, identity: Option<&str>) {
         let mut inner = self.inner.write().unwrap();
 
-        let IpManagerInner {
-            ip_map, ip_pool, ..
-        } = &mut *inner;
-
-        let ip_pool = ip_map.get_mut(&local_ip).unwrap_or(ip_pool);
-
         info!(ip = ?ip, "Free");
 
-        ip_pool.free_ip(ip);
+        if let Some((leases, identity)) = inner.leases.as_mut().zip(identity) {
+            leases.release(identity, ip, SystemTime::now());
+        }
+
+        // Requested IPs may come from any pool
+        match inner.pools_mut().find(|pool| pool.is_allocated(ip)) {
+            Some(pool) => pool.free_ip(ip),
+            None => warn!(ip = ?ip, "Attempt to free unallocated IP address"),
+        }
         inner.ip_to_conn_map.remove(&ip);
     }
+
+    /// Route `subnets` to `conn`, the client assigned `ip`. Subnets
+    /// overlapping any of the pools or a subnet already routed to
+    /// another client are skipped. Returns the subnets routed.
+    fn add_routes(&self, conn: T, ip: Ipv4Addr, subnets: &[Ipv4Net]) -> Vec<Ipv4Net>
+    where
+        T: Clone,
+    {
+        let mut inner = self.inner.write().unwrap();
+        let mut routed = Vec::with_capacity(subnets.len());
+        for subnet in subnets {
+            let subnet = subnet.trunc();
+            if inner
+                .pool_nets
+                .iter()
+                .any(|net| routes::overlaps(subnet, *net))
+                || !inner.routes.insert(subnet, (ip, conn.clone()))
+            {
+                warn!(%subnet, "Subnet overlaps an IP pool or another client's subnet");
+                metrics::site_subnet_rejected();
+                continue;
+            }
+            info!(%subnet, "Route");
+            routed.push(subnet);
+        }
+        routed
+    }
+
+    fn remove_routes(&self, subnets: &[Ipv4Net]) {
+        if subnets.is_empty() {
+            return;
+        }
+        let mut inner = self.inner.write().unwrap();
+        for subnet in subnets {
+            inner.routes.remove(*subnet);
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static> IpManager<T> {
+    /// Drop expired leases every [`MAINTENANCE_INTERVAL`], persisting
+    /// the rest and the state of the pools
+    pub(crate) fn spawn_maintenance(self: &Arc<Self>) {
+        {
+            let inner = self.inner.read().unwrap();
+            if inner.leases.is_none() && inner.pool_state_file.is_none() {
+                return;
+            }
+        }
+        let ip_manager = Arc::downgrade(self);
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(MAINTENANCE_INTERVAL);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            let mut ticker = tokio_stream::wrappers::IntervalStream::new(ticker);
+
+            while ticker.next().await.is_some() {
+                let Some(ip_manager) = ip_manager.upgrade() else {
+                    return;
+                };
+                if let Err(e) = ip_manager.maintain() {
+                    warn!("Failed to persist IP manager state: {e:#}");
+                }
+            }
+        });
+    }
+
+    /// Persist the leases and the state of the pools, e.g. on shutdown
+    pub(crate) fn save_state(&self) -> Result<()> {
+        self.maintain()
+    }
+}
+
+impl<T> IpManager<T> {
+    /// Inside IP of the client `ip` is assigned to or routed to, `None`
+    /// when `ip` does not belong to a client
+    pub(crate) fn client_of(&self, ip: Ipv4Addr) -> Option<Ipv4Addr> {
+        let inner = self.inner.read().unwrap();
+        if inner.ip_to_conn_map.contains_key(&ip) {
+            return Some(ip);
+        }
+        inner.routes.lookup(ip).map(|(client_ip, _)| *client_ip)
+    }
+
+    /// Whether client IPs `ip` and `other_ip` were allocated from the
+    /// same pool, `None` when `other_ip` is not allocated to a client
+    pub(crate) fn same_pool(&self, ip: Ipv4Addr, other_ip: Ipv4Addr) -> Option<bool> {
+        let inner = self.inner.read().unwrap();
+        if !inner.ip_to_conn_map.contains_key(&other_ip) {
+            return None;
+        }
+        let pool_of = |ip| inner.pools().position(|pool| pool.is_allocated(ip));
+        let pool = pool_of(ip);
+        Some(pool.is_some() && pool == pool_of(other_ip))
+    }
 }
 
 impl<T: Clone> IpManager<T> {
@@ -151,9 +459,101 @@ impl<T: Clone> IpManager<T> {
         let inner = self.inner.read().unwrap();
         inner.ip_to_conn_map.get(&ip).cloned()
     }
+
+    /// Connection of the client with the most specific subnet
+    /// containing `ip` behind it
+    pub(crate) fn find_route(&self, ip: Ipv4Addr) -> Option<T> {
+        let inner = self.inner.read().unwrap();
+        inner.routes.lookup(ip).map(|(_, conn)| conn.clone())
+    }
 }
 
 impl<T> IpManagerInner<T> {
+    fn pools(&self) -> impl Iterator<Item = &IpPool> {
+        std::iter::once(&self.ip_pool)
+            .chain(self.ip_map.values())
+            .chain(self.named_pools.values())
+    }
+
+    fn pools_mut(&mut self) -> impl Iterator<Item = &mut IpPool> {
+        std::iter::once(&mut self.ip_pool)
+            .chain(self.ip_map.values_mut())
+            .chain(self.named_pools.values_mut())
+    }
+
+    fn pool_state(&self) -> PoolState {
+        PoolState {
+            ip_pool: self.ip_pool.clone(),
+            ip_map: self.ip_map.clone(),
+            named_pools: self.named_pools.clone(),
+            held: self
+                .held
+                .iter()
+                .map(|(ip, until)| {
+                    let until = until.duration_since(UNIX_EPOCH).unwrap_or_default();
+                    (*ip, until.as_secs())
+                })
+                .collect(),
+        }
+    }
+
+    /// Restore each pool from its saved state, pools which changed
+    /// since are left as is. The IPs allocated then are held until
+    /// [`RESTORED_IP_HOLD_TIME`] from `now`, or until the hold saved
+    /// ran out if they were still held.
+    fn restore_pools(&mut self, state: PoolState, now: SystemTime) {
+        let mut restored = self.ip_pool.restore(state.ip_pool);
+        for (ip, saved) in state.ip_map {
+            restored &= self
+                .ip_map
+                .get_mut(&ip)
+                .is_some_and(|pool| pool.restore(saved));
+        }
+        for (name, saved) in state.named_pools {
+            restored &= self
+                .named_pools
+                .get_mut(&name)
+                .is_some_and(|pool| pool.restore(saved));
+        }
+        if restored {
+            info!("IP pool state restored");
+        } else {
+            warn!("IP pools changed since their state was saved, not all were restored");
+        }
+
+        let allocated: Vec<_> = self.pools().flat_map(IpPool::allocated_ips).collect();
+        for ip in allocated {
+            let until = state
+                .held
+                .get(&ip)
+                .map(|until| UNIX_EPOCH + Duration::from_secs(*until))
+                .unwrap_or(now + RESTORED_IP_HOLD_TIME);
+            self.held.insert(ip, until);
+        }
+        if !self.held.is_empty() {
+            info!(
+                count = self.held.len(),
+                "Holding IPs allocated before restart"
+            );
+        }
+    }
+
+    /// Free the IPs held past their time
+    fn expire_held(&mut self, now: SystemTime) {
+        let expired: Vec<_> = self
+            .held
+            .iter()
+            .filter(|(_, until)| **until <= now)
+            .map(|(ip, _)| *ip)
+            .collect();
+        for ip in expired {
+            self.held.remove(&ip);
+            if let Some(pool) = self.pools_mut().find(|pool| pool.is_allocated(ip)) {
+                pool.free_ip(ip);
+            }
+        }
+    }
+
     fn config_for_ip(&self, client_ip: Ipv4Addr) -> InsideIpConfig {
         let client_ip = if self.use_dynamic_client_ip {
             client_ip
@@ -172,6 +572,7 @@ impl<T> IpManagerInner<T> {
 mod tests {
     use ipnet::Ipv4Net;
     use std::sync::Arc;
+    use test_case::test_case;
 
     use super::*;
 
@@ -229,7 +630,7 @@ mod tests {
         let subnet: Ipv4Net = "10.125.2.0/28".parse().unwrap();
 
         let (local_ip1, ip_config) = ip_manager
-            .alloc(conn1, "192.168.23.54".parse().unwrap())
+            .alloc(conn1, "192.168.23.54".parse().unwrap(), None, None)
             .unwrap();
         assert!(!subnet.contains(&local_ip1));
         assert_eq!(
@@ -239,7 +640,7 @@ mod tests {
         );
 
         let (local_ip2, ip_config) = ip_manager
-            .alloc(conn2, "192.168.125.13".parse().unwrap())
+            .alloc(conn2, "192.168.125.13".parse().unwrap(), None, None)
             .unwrap();
         assert!(!subnet.contains(&local_ip2));
         assert_eq!(
@@ -262,7 +663,7 @@ mod tests {
         let subnet: Ipv4Net = "10.125.2.0/28".parse().unwrap();
 
         let (local_ip1, ip_config) = ip_manager
-            .alloc(conn1, "192.168.23.54".parse().unwrap())
+            .alloc(conn1, "192.168.23.54".parse().unwrap(), None, None)
             .unwrap();
         assert!(!subnet.contains(&local_ip1));
         assert_eq!(
@@ -279,7 +680,7 @@ mod tests {
         let subnet: Ipv4Net = "10.125.2.0/28".parse().unwrap();
 
         let (local_ip1, ip_config) = ip_manager
-            .alloc(conn1, "192.168.85.208".parse().unwrap())
+            .alloc(conn1, "192.168.85.208".parse().unwrap(), None, None)
             .unwrap();
         assert!(subnet.contains(&local_ip1));
         assert_eq!(
@@ -289,7 +690,7 @@ mod tests {
         );
 
         let (local_ip2, ip_config) = ip_manager
-            .alloc(conn2, "192.168.85.208".parse().unwrap())
+            .alloc(conn2, "192.168.85.208".parse().unwrap(), None, None)
             .unwrap();
         assert!(subnet.contains(&local_ip2));
         assert_eq!(
@@ -313,21 +714,21 @@ mod tests {
         let ip1 = "192.168.24.64".parse().unwrap();
         let ip2 = "192.168.11.45".parse().unwrap();
 
-        let (alloc1, _) = ip_manager.alloc(conn1.clone(), ip1).unwrap();
-        let (alloc2, _) = ip_manager.alloc(conn2.clone(), ip2).unwrap();
+        let (alloc1, _) = ip_manager.alloc(conn1.clone(), ip1, None, None).unwrap();
+        let (alloc2, _) = ip_manager.alloc(conn2.clone(), ip2, None, None).unwrap();
 
         {
             let inner = ip_manager.inner.read().unwrap();
             assert_eq!(inner.ip_to_conn_map.len(), 2);
         }
 
-        ip_manager.free(alloc1, ip1);
+        ip_manager.free(alloc1, None);
         {
             let inner = ip_manager.inner.read().unwrap();
             assert_eq!(inner.ip_to_conn_map.len(), 1);
         }
 
-        ip_manager.free(alloc2, ip2);
+        ip_manager.free(alloc2, None);
         {
             let inner = ip_manager.inner.read().unwrap();
             assert_eq!(inner.ip_to_conn_map.len(), 0);
@@ -341,21 +742,21 @@ mod tests {
         let conn2 = TestConnection::new(2);
         let ip = "192.168.85.208".parse().unwrap();
 
-        let (alloc1, _) = ip_manager.alloc(conn1.clone(), ip).unwrap();
-        let (alloc2, _) = ip_manager.alloc(conn2.clone(), ip).unwrap();
+        let (alloc1, _) = ip_manager.alloc(conn1.clone(), ip, None, None).unwrap();
+        let (alloc2, _) = ip_manager.alloc(conn2.clone(), ip, None, None).unwrap();
 
         {
             let inner = ip_manager.inner.read().unwrap();
             assert_eq!(inner.ip_to_conn_map.len(), 2);
         }
 
-        ip_manager.free(alloc1, ip);
+        ip_manager.free(alloc1, None);
         {
             let inner = ip_manager.inner.read().unwrap();
             assert_eq!(inner.ip_to_conn_map.len(), 1);
         }
 
-        ip_manager.free(alloc2, ip);
+        ip_manager.free(alloc2, None);
         {
             let inner = ip_manager.inner.read().unwrap();
             assert_eq!(inner.ip_to_conn_map.len(), 0);
@@ -370,8 +771,8 @@ mod tests {
         let ip1 = "192.168.190.7".parse().unwrap();
         let ip2 = "192.168.187.186".parse().unwrap();
 
-        let (ip1, _) = ip_manager.alloc(conn1.clone(), ip1).unwrap();
-        let (ip2, _) = ip_manager.alloc(conn2.clone(), ip2).unwrap();
+        let (ip1, _) = ip_manager.alloc(conn1.clone(), ip1, None, None).unwrap();
+        let (ip2, _) = ip_manager.alloc(conn2.clone(), ip2, None, None).unwrap();
 
         // Getting ip1 and verify
         let conn = ip_manager.find_connection(ip1).unwrap();
@@ -386,6 +787,322 @@ mod tests {
         assert!(!Arc::ptr_eq(&conn.0, &conn1.0));
     }
 
+    fn get_ip_manager_with_leases() -> IpManager<TestConnection> {
+        get_ip_manager(false)
+            .with_leases(&IpLeases {
+                lease_time: Duration::from_secs(3600),
+                file: None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn leased_ip_handed_back_when_free() {
+        let ip_manager = get_ip_manager_with_leases();
+        let local_ip = "192.168.23.54".parse().unwrap();
+
+        let (ip, _) = ip_manager
+            .alloc(TestConnection::new(1), local_ip, Some("alice"), None)
+            .unwrap();
+        ip_manager.free(ip, Some("alice"));
+
+        let (other_ip, _) = ip_manager
+            .alloc(TestConnection::new(2), local_ip, Some("bob"), None)
+            .unwrap();
+        assert_ne!(other_ip, ip);
+
+        let (alice_ip, _) = ip_manager
+            .alloc(TestConnection::new(3), local_ip, Some("alice"), None)
+            .unwrap();
+        assert_eq!(alice_ip, ip);
+
+        // The leased IP is in use, a second connection gets another IP
+        let (second_ip, _) = ip_manager
+            .alloc(TestConnection::new(4), local_ip, Some("alice"), None)
+            .unwrap();
+        assert_ne!(second_ip, ip);
+        assert_eq!(ip_manager.leases_count(), Some(2));
+    }
+
+    #[test]
+    fn leased_ip_from_other_pool_not_handed_back() {
+        let ip_manager = get_ip_manager_with_leases();
+        let global_local_ip = "192.168.23.54".parse().unwrap();
+        let subrange_local_ip = "192.168.85.208".parse().unwrap();
+        let subnet: Ipv4Net = "10.125.2.0/28".parse().unwrap();
+
+        let (ip, _) = ip_manager
+            .alloc(TestConnection::new(1), global_local_ip, Some("alice"), None)
+            .unwrap();
+        ip_manager.free(ip, Some("alice"));
+
+        let (alice_ip, _) = ip_manager
+            .alloc(
+                TestConnection::new(2),
+                subrange_local_ip,
+                Some("alice"),
+                None,
+            )
+            .unwrap();
+        assert!(subnet.contains(&alice_ip));
+    }
+
+    #[test_case("10.125.0.10"; "From global")]
+    #[test_case("10.125.2.10"; "From subrange")]
+    #[test_case("10.125.3.10"; "From named pool")]
+    fn alloc_requested_ip(requested_ip: &str) {
+        let ip_manager = get_ip_manager(true).with_named_pools(HashMap::from([(
+            "admins".to_string(),
+            "10.125.3.0/24".parse().unwrap(),
+        )]));
+        let local_ip = "192.168.23.54".parse().unwrap();
+        let request = InsideIpRequest::Ip(requested_ip.parse().unwrap());
+
+        let (ip, ip_config) = ip_manager
+            .alloc(TestConnection::new(1), local_ip, None, Some(&request))
+            .unwrap();
+        assert_eq!(ip, requested_ip.parse::<Ipv4Addr>().unwrap());
+        assert_eq!(ip_config.client_ip, ip);
+
+        // Taken
+        assert!(
+            ip_manager
+                .alloc(TestConnection::new(2), local_ip, None, Some(&request))
+                .is_none()
+        );
+
+        // Freed back to its pool
+        ip_manager.free(ip, None);
+        assert!(
+            ip_manager
+                .alloc(TestConnection::new(3), local_ip, None, Some(&request))
+                .is_some()
+        );
+    }
+
+    #[test_case("10.125.0.1"; "Reserved")]
+    #[test_case("10.126.0.10"; "Outside pool")]
+    fn alloc_requested_ip_unavailable(requested_ip: &str) {
+        let ip_manager = get_ip_manager_with_test_connection();
+        let request = InsideIpRequest::Ip(requested_ip.parse().unwrap());
+
+        assert!(
+            ip_manager
+                .alloc(
+                    TestConnection::new(1),
+                    "192.168.23.54".parse().unwrap(),
+                    None,
+                    Some(&request)
+                )
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn alloc_from_named_pool() {
+        let ip_manager = get_ip_manager_with_test_connection().with_named_pools(HashMap::from([(
+            "admins".to_string(),
+            "10.125.3.0/30".parse().unwrap(),
+        )]));
+        let subnet: Ipv4Net = "10.125.3.0/30".parse().unwrap();
+        let local_ip = "192.168.85.208".parse().unwrap();
+        let request = InsideIpRequest::Pool("admins".to_string());
+
+        // A /30 has 2 hosts
+        for n in 0..2 {
+            let (ip, _) = ip_manager
+                .alloc(TestConnection::new(n), local_ip, None, Some(&request))
+                .unwrap();
+            assert!(subnet.contains(&ip));
+        }
+        assert!(
+            ip_manager
+                .alloc(TestConnection::new(2), local_ip, None, Some(&request))
+                .is_none()
+        );
+
+        let unknown = InsideIpRequest::Pool("unknown".to_string());
+        assert!(
+            ip_manager
+                .alloc(TestConnection::new(3), local_ip, None, Some(&unknown))
+                .is_none()
+        );
+
+        // Named pool IPs are not allocated to other clients
+        let global_local_ip = "192.168.23.54".parse().unwrap();
+        while let Some((ip, _)) =
+            ip_manager.alloc(TestConnection::new(4), global_local_ip, None, None)
+        {
+            assert!(!subnet.contains(&ip));
+        }
+    }
+
+    #[test]
+    fn same_pool() {
+        let ip_manager = get_ip_manager_with_test_connection();
+        let global_local_ip = "192.168.23.54".parse().unwrap();
+        let subrange_local_ip = "192.168.85.208".parse().unwrap();
+
+        let (global_ip1, _) = ip_manager
+            .alloc(TestConnection::new(1), global_local_ip, None, None)
+            .unwrap();
+        let (global_ip2, _) = ip_manager
+            .alloc(TestConnection::new(2), global_local_ip, None, None)
+            .unwrap();
+        let (subrange_ip, _) = ip_manager
+            .alloc(TestConnection::new(3), subrange_local_ip, None, None)
+            .unwrap();
+
+        assert_eq!(ip_manager.same_pool(global_ip1, global_ip2), Some(true));
+        assert_eq!(ip_manager.same_pool(global_ip1, subrange_ip), Some(false));
+        assert_eq!(ip_manager.same_pool(subrange_ip, global_ip2), Some(false));
+        assert_eq!(
+            ip_manager.same_pool(global_ip1, "8.8.8.8".parse().unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn pool_state_restored() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("ip_pool_state.json");
+        let local_ip = "192.168.23.54".parse().unwrap();
+
+        let ip_manager = get_ip_manager_with_test_connection()
+            .with_pool_state(&file)
+            .unwrap();
+        let (ip, _) = ip_manager
+            .alloc(TestConnection::new(1), local_ip, None, None)
+            .unwrap();
+        let next_ip = {
+            let inner = ip_manager.inner.read().unwrap();
+            inner.ip_pool.clone().allocate_ip().unwrap()
+        };
+        ip_manager.save_state().unwrap();
+
+        let ip_manager = get_ip_manager_with_test_connection()
+            .with_pool_state(&file)
+            .unwrap();
+        let (restored_ip, _) = ip_manager
+            .alloc(TestConnection::new(2), local_ip, None, None)
+            .unwrap();
+        assert_eq!(restored_ip, next_ip);
+        assert_ne!(restored_ip, ip);
+    }
+
+    #[test]
+    fn ip_held_across_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool_file = dir.path().join("ip_pool_state.json");
+        let leases = IpLeases {
+            lease_time: Duration::from_secs(3600),
+            file: Some(dir.path().join("ip_leases.json")),
+        };
+        let restart = || {
+            get_ip_manager(false)
+                .with_leases(&leases)
+                .unwrap()
+                .with_pool_state(&pool_file)
+                .unwrap()
+        };
+        let local_ip = "192.168.23.54".parse().unwrap();
+
+        let ip_manager = restart();
+        let (alice_ip, _) = ip_manager
+            .alloc(TestConnection::new(1), local_ip, Some("alice"), None)
+            .unwrap();
+        let (bob_ip, _) = ip_manager
+            .alloc(TestConnection::new(2), local_ip, Some("bob"), None)
+            .unwrap();
+        ip_manager.save_state().unwrap();
+
+        let ip_manager = restart();
+        let (carol_ip, _) = ip_manager
+            .alloc(TestConnection::new(3), local_ip, Some("carol"), None)
+            .unwrap();
+        assert_ne!(carol_ip, alice_ip);
+        assert_ne!(carol_ip, bob_ip);
+        let (ip, _) = ip_manager
+            .alloc(TestConnection::new(4), local_ip, Some("alice"), None)
+            .unwrap();
+        assert_eq!(ip, alice_ip);
+
+        // Bob does not come back in time
+        let mut inner = ip_manager.inner.write().unwrap();
+        inner.expire_held(SystemTime::now() + RESTORED_IP_HOLD_TIME);
+        assert!(inner.held.is_empty());
+        assert!(!inner.ip_pool.is_allocated(bob_ip));
+        assert!(inner.ip_pool.is_allocated(alice_ip));
+    }
+
+    #[test]
+    fn routes() {
+        let ip_manager = get_ip_manager_with_test_connection();
+        let conn1 = TestConnection::new(1);
+        let conn2 = TestConnection::new(2);
+        let ip1: Ipv4Addr = "10.125.0.10".parse().unwrap();
+        let ip2: Ipv4Addr = "10.125.0.11".parse().unwrap();
+        let subnets: Vec<Ipv4Net> = ["192.168.1.0/24", "10.125.4.0/24", "172.16.0.0/12"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+
+        // Within the IP pool
+        let routed = ip_manager.add_routes(conn1.clone(), ip1, &subnets);
+        assert_eq!(routed, [subnets[0], subnets[2]]);
+
+        // Within a subnet routed to conn1
+        let more_specific: Ipv4Net = "172.16.8.0/24".parse().unwrap();
+        assert!(
+            ip_manager
+                .add_routes(conn2.clone(), ip2, &[more_specific])
+                .is_empty()
+        );
+
+        let conn = ip_manager
+            .find_route("172.16.8.1".parse().unwrap())
+            .unwrap();
+        assert!(Arc::ptr_eq(&conn.0, &conn1.0));
+        assert_eq!(
+            ip_manager.client_of("172.16.8.1".parse().unwrap()),
+            Some(ip1)
+        );
+        assert!(
+            ip_manager
+                .find_route("10.125.4.1".parse().unwrap())
+                .is_none()
+        );
+
+        ip_manager.remove_routes(&routed);
+        assert!(
+            ip_manager
+                .find_route("192.168.1.1".parse().unwrap())
+                .is_none()
+        );
+        assert_eq!(
+            ip_manager.add_routes(conn2, ip2, &[more_specific]),
+            [more_specific]
+        );
+    }
+
+    #[test_case("10.125.2.0/28"; "ip_map subnet")]
+    #[test_case("10.125.3.0/24"; "named pool")]
+    #[test_case("10.0.0.0/8"; "containing the IP pool")]
+    fn routes_skip_pools(subnet: &str) {
+        let ip_manager = get_ip_manager_with_test_connection()
+            .with_named_pools([("site".to_string(), "10.125.3.0/24".parse().unwrap())].into());
+        let subnet: Ipv4Net = subnet.parse().unwrap();
+        assert!(
+            ip_manager
+                .add_routes(
+                    TestConnection::new(1),
+                    "10.125.0.10".parse().unwrap(),
+                    &[subnet]
+                )
+                .is_empty()
+        );
+    }
+
     #[test]
     fn reserved_ips_never_allocated() {
         let ip_pool: Ipv4Net = "10.125.0.0/24".parse().unwrap();
@@ -405,7 +1122,7 @@ mod tests {
         );
         // Allocate every possible IP and check we never get a reserved one
         let mut count = 0;
-        while let Some((ip, _)) = ip_manager.alloc(DummyConnection, conn_local_ip) {
+        while let Some((ip, _)) = ip_manager.alloc(DummyConnection, conn_local_ip, None, None) {
             count += 1;
             assert_ne!(ip, local_ip);
             assert_ne!(ip, dns_ip);
@@ -1,5 +1,5 @@
 use ipnet::Ipv4Net;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashSet, VecDeque},
     net::Ipv4Addr,
@@ -8,7 +8,7 @@ use struct_patch::Patch;
 use tracing::warn;
 
 /// Manages the alloction of a pool of IPs
-#[derive(Deserialize, Patch, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Patch, PartialEq, Serialize)]
 pub struct IpPool {
     /// Reserved IPs, must never be allocated to a client.
     reserved_ips: HashSet<Ipv4Addr>,
@@ -53,6 +53,27 @@ impl IpPool {
         Some(ip)
     }
 
+    /// Allocate `ip` if it is available
+    pub fn allocate_specific_ip(&mut self, ip: Ipv4Addr) -> bool {
+        let Some(index) = self.available_ips.iter().position(|a| *a == ip) else {
+            return false;
+        };
+        self.available_ips.remove(index);
+        self.allocated_ips.insert(ip);
+        true
+    }
+
+    /// Allocate `preferred` if it is available, any IP otherwise
+    pub fn allocate_ip_preferring(&mut self, preferred: Option<Ipv4Addr>) -> Option<Ipv4Addr> {
+        preferred
+            .filter(|ip| self.allocate_specific_ip(*ip))
+            .or_else(|| self.allocate_ip())
+    }
+
+    pub fn is_allocated(&self, ip: Ipv4Addr) -> bool {
+        self.allocated_ips.contains(&ip)
+    }
+
     pub fn free_ip(&mut self, ip: Ipv4Addr) {
         if !self.allocated_ips.remove(&ip) {
             warn!(ip = ?ip, "Attempt to free unallocated IP address");
@@ -62,6 +83,60 @@ impl IpPool {
         self.available_ips.push_back(ip);
     }
 
+    /// Restore `saved`, the state of this pool before a restart: the
+    /// IPs allocated back then are allocated again, for the caller to
+    /// hold them for their clients, and the others keep their LRU
+    /// order. The reservations of this pool win over the saved ones: IPs
+    /// reserved since are left out, IPs no longer reserved are queued
+    /// last. Returns false, leaving the pool as is, unless `saved` holds
+    /// the same IPs.
+    pub fn restore(&mut self, saved: IpPool) -> bool {
+        if !self.allocated_ips.is_empty() {
+            return false;
+        }
+        let ips: HashSet<_> = self
+            .available_ips
+            .iter()
+            .chain(&self.reserved_ips)
+            .collect();
+        let saved_ips: HashSet<_> = saved
+            .available_ips
+            .iter()
+            .chain(&saved.allocated_ips)
+            .chain(&saved.reserved_ips)
+            .collect();
+        let saved_len =
+            saved.available_ips.len() + saved.allocated_ips.len() + saved.reserved_ips.len();
+        if saved_ips != ips || saved_ips.len() != saved_len {
+            return false;
+        }
+
+        let unreserved: Vec<_> = saved
+            .reserved_ips
+            .iter()
+            .filter(|ip| !self.reserved_ips.contains(ip))
+            .copied()
+            .collect();
+        let reserved = &self.reserved_ips;
+        self.allocated_ips = saved
+            .allocated_ips
+            .into_iter()
+            .filter(|ip| !reserved.contains(ip))
+            .collect();
+        self.available_ips = saved
+            .available_ips
+            .into_iter()
+            .filter(|ip| !reserved.contains(ip))
+            .chain(unreserved)
+            .collect();
+        true
+    }
+
+    /// IPs allocated from this pool
+    pub fn allocated_ips(&self) -> impl Iterator<Item = Ipv4Addr> + '_ {
+        self.allocated_ips.iter().copied()
+    }
+
     pub fn split_subnet(&mut self, subnet: Ipv4Net) -> Self {
         let available_ips: VecDeque<_> = self
             .available_ips
@@ -193,6 +268,21 @@ mod tests {
         assert_eq!(pool.available_ips.len(), pool_size);
     }
 
+    #[test_case("10.125.0.9" => true; "Available ip")]
+    #[test_case("10.125.0.1" => false; "Reserved ip")]
+    #[test_case("192.168.1.1" => false; "Unrelated ip")]
+    fn allocate_specific_ip(ip: &str) -> bool {
+        let mut pool = get_ip_pool();
+        let ip = ip.parse().unwrap();
+
+        let allocated = pool.allocate_specific_ip(ip);
+        assert_eq!(pool.allocated_ips.contains(&ip), allocated);
+        assert!(!pool.available_ips.contains(&ip));
+        // Cannot be allocated twice
+        assert!(!pool.allocate_specific_ip(ip));
+        allocated
+    }
+
     #[test]
     fn split_subnet_initial_range_omits_network_and_reserved_addresses() {
         let mut pool = get_ip_pool();
@@ -352,6 +442,53 @@ mod tests {
         }
     }
 
+    #[test]
+    fn restore() {
+        let mut pool = get_ip_pool();
+        pool.shuffle_ips();
+        let allocated = pool.allocate_ip().unwrap();
+        let saved = pool.clone();
+        let next_ips: Vec<_> = (0..3).map(|_| pool.allocate_ip().unwrap()).collect();
+
+        let mut restored = get_ip_pool();
+        assert!(restored.restore(saved));
+        // Allocated when saved, so still allocated
+        assert!(restored.is_allocated(allocated));
+        assert!(!restored.available_ips.contains(&allocated));
+        let restored_ips: Vec<_> = (0..3).map(|_| restored.allocate_ip().unwrap()).collect();
+        assert_eq!(restored_ips, next_ips);
+    }
+
+    #[test]
+    fn restore_keeps_current_reservations() {
+        let unreserved: Ipv4Addr = "10.125.0.2".parse().unwrap();
+        let reserved: Ipv4Addr = "10.125.0.3".parse().unwrap();
+        let mut saved = get_ip_pool();
+        assert!(saved.allocate_specific_ip(reserved));
+
+        let mut pool = IpPool::new(
+            "10.125.0.0/16".parse().unwrap(),
+            ["10.125.0.1".parse().unwrap(), reserved],
+        );
+        assert!(pool.restore(saved));
+        assert!(!pool.is_allocated(reserved));
+        assert!(!pool.available_ips.contains(&reserved));
+        assert_eq!(pool.available_ips.back(), Some(&unreserved));
+    }
+
+    #[test_case("10.126.0.0/16", &["10.126.0.1", "10.126.0.2"]; "Other network")]
+    #[test_case("10.125.0.0/24", &["10.125.0.1", "10.125.0.2"]; "Smaller network")]
+    fn restore_other_pool(ip_pool: &str, reserved_ips: &[&str]) {
+        let saved = IpPool::new(
+            ip_pool.parse().unwrap(),
+            reserved_ips.iter().map(|ip| ip.parse().unwrap()),
+        );
+        let mut pool = get_ip_pool();
+        let before = pool.clone();
+        assert!(!pool.restore(saved));
+        assert_eq!(pool, before);
+    }
+
     #[test]
     fn test_no_shuffle() {
         // 10.125.0.1 is used for local ip
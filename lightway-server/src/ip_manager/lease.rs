@@ -0,0 +1,205 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::Ipv4Addr,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::state_file;
+
+/// Remembers the IP last assigned to each client identity, so that a
+/// returning client can be handed the same IP again
+pub struct Leases {
+    /// How long a lease outlives the connection using it
+    lease_time: Duration,
+    /// Identity to lease hashmap
+    leases: HashMap<String, Lease>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Lease {
+    ip: Ipv4Addr,
+    /// `None` while a connection uses the IP
+    expires: Option<SystemTime>,
+}
+
+/// A [`Lease`] as persisted to disk
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct LeaseRecord {
+    identity: String,
+    ip: Ipv4Addr,
+    /// Seconds since the Unix epoch
+    expires: u64,
+}
+
+impl Leases {
+    pub fn new(lease_time: Duration) -> Self {
+        Self {
+            lease_time,
+            leases: HashMap::new(),
+        }
+    }
+
+    /// Load the leases persisted to `path`, a missing file holds none
+    pub fn load(&mut self, path: &Path, now: SystemTime) -> Result<()> {
+        let records: Vec<LeaseRecord> = match std::fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .with_context(|| format!("Parsing IP leases {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Reading IP leases {}", path.display()));
+            }
+        };
+
+        for record in records {
+            let expires = UNIX_EPOCH + Duration::from_secs(record.expires);
+            if expires > now {
+                self.leases.insert(
+                    record.identity,
+                    Lease {
+                        ip: record.ip,
+                        expires: Some(expires),
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The leases to persist, leases in use are saved as if released
+    /// `now`
+    pub fn records(&self, now: SystemTime) -> Vec<LeaseRecord> {
+        self.leases
+            .iter()
+            .map(|(identity, lease)| LeaseRecord {
+                identity: identity.clone(),
+                ip: lease.ip,
+                expires: lease
+                    .expires
+                    .unwrap_or(now + self.lease_time)
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+            })
+            .collect()
+    }
+
+    /// The IP leased to `identity`, if the lease has not expired
+    pub fn lookup(&self, identity: &str, now: SystemTime) -> Option<Ipv4Addr> {
+        let lease = self.leases.get(identity)?;
+        match lease.expires {
+            Some(expires) if expires <= now => None,
+            _ => Some(lease.ip),
+        }
+    }
+
+    /// Lease `ip` to `identity` for as long as it is in use
+    pub fn bind(&mut self, identity: &str, ip: Ipv4Addr) {
+        self.leases
+            .insert(identity.to_string(), Lease { ip, expires: None });
+    }
+
+    /// Start the lease of `ip` to `identity` running out, unless the
+    /// identity has been leased another IP since
+    pub fn release(&mut self, identity: &str, ip: Ipv4Addr, now: SystemTime) {
+        if let Some(lease) = self.leases.get_mut(identity)
+            && lease.ip == ip
+        {
+            lease.expires = Some(now + self.lease_time);
+        }
+    }
+
+    /// Forget expired leases
+    pub fn expire(&mut self, now: SystemTime) {
+        self.leases
+            .retain(|_, lease| lease.expires.is_none_or(|expires| expires > now));
+    }
+
+    pub fn len(&self) -> usize {
+        self.leases.len()
+    }
+}
+
+/// Persist leases to `path`, as taken from [`Leases::records`]
+pub fn save(path: &Path, records: &[LeaseRecord]) -> Result<()> {
+    state_file::save(path, "IP leases", records)
+}
+
+// Tests START -> panic, unwrap, expect allowed
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEASE_TIME: Duration = Duration::from_secs(3600);
+
+    fn ip(n: u8) -> Ipv4Addr {
+        Ipv4Addr::new(10, 125, 0, n)
+    }
+
+    #[test]
+    fn lease_outlives_release_by_lease_time() {
+        let now = SystemTime::now();
+        let mut leases = Leases::new(LEASE_TIME);
+
+        leases.bind("alice", ip(10));
+        // In use leases never expire
+        assert_eq!(leases.lookup("alice", now + 2 * LEASE_TIME), Some(ip(10)));
+
+        leases.release("alice", ip(10), now);
+        assert_eq!(leases.lookup("alice", now + LEASE_TIME / 2), Some(ip(10)));
+        assert_eq!(leases.lookup("alice", now + LEASE_TIME), None);
+        assert_eq!(leases.lookup("bob", now), None);
+
+        leases.expire(now + LEASE_TIME);
+        assert_eq!(leases.len(), 0);
+    }
+
+    #[test]
+    fn release_of_older_ip_keeps_newer_lease() {
+        let now = SystemTime::now();
+        let mut leases = Leases::new(LEASE_TIME);
+
+        leases.bind("alice", ip(10));
+        leases.bind("alice", ip(11));
+        leases.release("alice", ip(10), now);
+
+        leases.expire(now + 2 * LEASE_TIME);
+        assert_eq!(leases.lookup("alice", now + 2 * LEASE_TIME), Some(ip(11)));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("leases.json");
+        let now = SystemTime::now();
+
+        let mut leases = Leases::new(LEASE_TIME);
+        leases.bind("alice", ip(10));
+        leases.bind("bob", ip(11));
+        leases.release("bob", ip(11), now - 2 * LEASE_TIME);
+        save(&path, &leases.records(now)).unwrap();
+
+        let mut loaded = Leases::new(LEASE_TIME);
+        loaded.load(&path, now).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded.lookup("alice", now), Some(ip(10)));
+        assert_eq!(loaded.lookup("alice", now + LEASE_TIME), None);
+    }
+
+    #[test]
+    fn load_missing_and_invalid_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("leases.json");
+        let mut leases = Leases::new(LEASE_TIME);
+
+        leases.load(&path, SystemTime::now()).unwrap();
+        assert_eq!(leases.len(), 0);
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(leases.load(&path, SystemTime::now()).is_err());
+    }
+}
+
+// Tests END -> panic, unwrap, expect allowed
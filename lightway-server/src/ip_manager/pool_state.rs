@@ -0,0 +1,87 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr},
+    path::Path,
+};
+
+use super::{ip_pool::IpPool, state_file};
+
+/// Snapshot of the pools of an [`IpManager`](super::IpManager), to keep
+/// the IPs assigned stable across restarts
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct PoolState {
+    pub ip_pool: IpPool,
+    pub ip_map: HashMap<IpAddr, IpPool>,
+    pub named_pools: HashMap<String, IpPool>,
+    /// IPs allocated before the last restart and still held for their
+    /// clients, until these seconds since the Unix epoch
+    #[serde(default)]
+    pub held: HashMap<Ipv4Addr, u64>,
+}
+
+/// Load the pool state persisted to `path`, `None` if there is none
+pub fn load(path: &Path) -> Result<Option<PoolState>> {
+    match std::fs::read(path) {
+        Ok(data) => serde_json::from_slice(&data)
+            .map(Some)
+            .with_context(|| format!("Parsing IP pool state {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Reading IP pool state {}", path.display())),
+    }
+}
+
+/// Persist the pool state to `path`
+pub fn save(path: &Path, state: &PoolState) -> Result<()> {
+    state_file::save(path, "IP pool state", state)
+}
+
+// Tests START -> panic, unwrap, expect allowed
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool_state() -> PoolState {
+        let mut ip_pool = IpPool::new("10.125.0.0/16".parse().unwrap(), []);
+        ip_pool.shuffle_ips();
+        ip_pool.allocate_ip();
+        let ip_map = HashMap::from([(
+            "192.168.85.208".parse().unwrap(),
+            ip_pool.split_subnet("10.125.2.0/28".parse().unwrap()),
+        )]);
+        let named_pools = HashMap::from([(
+            "admins".to_string(),
+            ip_pool.split_subnet("10.125.3.0/24".parse().unwrap()),
+        )]);
+        let held = HashMap::from([("10.125.0.10".parse().unwrap(), 1_700_000_000)]);
+        PoolState {
+            ip_pool,
+            ip_map,
+            named_pools,
+            held,
+        }
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ip_pool_state.json");
+
+        let state = pool_state();
+        save(&path, &state).unwrap();
+        assert_eq!(load(&path).unwrap(), Some(state));
+    }
+
+    #[test]
+    fn load_missing_and_invalid_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ip_pool_state.json");
+        assert_eq!(load(&path).unwrap(), None);
+
+        std::fs::write(&path, b"not json").unwrap();
+        assert!(load(&path).is_err());
+    }
+}
+
+// Tests END -> panic, unwrap, expect allowed
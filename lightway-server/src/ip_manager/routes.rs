@@ -0,0 +1,103 @@
+use ipnet::Ipv4Net;
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::Ipv4Addr,
+};
+
+/// Subnets routed to clients, looked up by longest prefix match
+pub struct Routes<T> {
+    /// Subnet to route target hashmap
+    routes: HashMap<Ipv4Net, T>,
+    /// Number of routes of each prefix length in use, so a lookup only
+    /// probes those prefix lengths
+    prefix_lens: BTreeMap<u8, usize>,
+}
+
+impl<T> Default for Routes<T> {
+    fn default() -> Self {
+        Self {
+            routes: HashMap::new(),
+            prefix_lens: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> Routes<T> {
+    /// Route `subnet` to `target`. Returns false if `subnet` overlaps a
+    /// subnet already routed.
+    pub fn insert(&mut self, subnet: Ipv4Net, target: T) -> bool {
+        let subnet = subnet.trunc();
+        if self.routes.keys().any(|routed| overlaps(*routed, subnet)) {
+            return false;
+        }
+        self.routes.insert(subnet, target);
+        *self.prefix_lens.entry(subnet.prefix_len()).or_default() += 1;
+        true
+    }
+
+    pub fn remove(&mut self, subnet: Ipv4Net) -> Option<T> {
+        let subnet = subnet.trunc();
+        let target = self.routes.remove(&subnet)?;
+        if let Some(count) = self.prefix_lens.get_mut(&subnet.prefix_len()) {
+            *count -= 1;
+            if *count == 0 {
+                self.prefix_lens.remove(&subnet.prefix_len());
+            }
+        }
+        Some(target)
+    }
+
+    /// Target of the most specific subnet containing `ip`
+    pub fn lookup(&self, ip: Ipv4Addr) -> Option<&T> {
+        self.prefix_lens.keys().rev().find_map(|prefix_len| {
+            let subnet = Ipv4Net::new(ip, *prefix_len).ok()?.trunc();
+            self.routes.get(&subnet)
+        })
+    }
+}
+
+/// Whether `a` and `b` share any address
+pub fn overlaps(a: Ipv4Net, b: Ipv4Net) -> bool {
+    a.contains(&b) || b.contains(&a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn routes() -> Routes<usize> {
+        let mut routes = Routes::default();
+        assert!(routes.insert("192.168.0.0/16".parse().unwrap(), 1));
+        assert!(routes.insert("172.16.1.0/24".parse().unwrap(), 2));
+        assert!(routes.insert("172.16.2.128/25".parse().unwrap(), 3));
+        routes
+    }
+
+    #[test_case("192.168.4.1" => Some(1))]
+    #[test_case("172.16.1.255" => Some(2))]
+    #[test_case("172.16.2.200" => Some(3))]
+    #[test_case("172.16.2.100" => None)]
+    #[test_case("10.0.0.1" => None)]
+    fn lookup(ip: &str) -> Option<usize> {
+        routes().lookup(ip.parse().unwrap()).copied()
+    }
+
+    #[test_case("192.168.4.0/24" => false; "Within routed subnet")]
+    #[test_case("172.16.0.0/12" => false; "Containing routed subnet")]
+    #[test_case("172.16.1.0/24" => false; "Already routed")]
+    #[test_case("172.16.2.0/25" => true; "Next to routed subnet")]
+    fn insert(subnet: &str) -> bool {
+        routes().insert(subnet.parse().unwrap(), 4)
+    }
+
+    #[test]
+    fn remove() {
+        let mut routes = routes();
+        assert_eq!(routes.remove("172.16.2.128/25".parse().unwrap()), Some(3));
+        assert_eq!(routes.remove("172.16.2.128/25".parse().unwrap()), None);
+        assert_eq!(routes.lookup("172.16.2.200".parse().unwrap()), None);
+        assert_eq!(routes.prefix_lens.get(&25), None);
+        assert_eq!(routes.routes.len(), 2);
+    }
+}
@@ -0,0 +1,50 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Persist `value` as JSON to `path`, `what` naming the file in errors.
+///
+/// A temporary file next to `path` is written and synced first, then
+/// renamed over it, so that a crash leaves either the previous or the
+/// new file behind, never a truncated one.
+pub fn save<T: Serialize + ?Sized>(path: &Path, what: &str, value: &T) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let data = serde_json::to_vec(value).with_context(|| format!("Serializing {what}"))?;
+    let write = || -> std::io::Result<()> {
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(&data)?;
+        file.sync_all()
+    };
+    write().with_context(|| format!("Writing {what} {}", tmp_path.display()))?;
+    std::fs::rename(&tmp_path, path).with_context(|| format!("Writing {what} {}", path.display()))
+}
+
+// Tests START -> panic, unwrap, expect allowed
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_replaces_file() {
+        let dir = tempfile::tempdir().unwrap();
+        // Files sharing a stem must not share a temporary file
+        let path = dir.path().join("state.leases");
+        let other_path = dir.path().join("state.pools");
+
+        save(&path, "test state", &[1, 2]).unwrap();
+        save(&other_path, "test state", &[3]).unwrap();
+        save(&path, "test state", &[4]).unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "[4]");
+        assert_eq!(std::fs::read_to_string(&other_path).unwrap(), "[3]");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+}
+
+// Tests END -> panic, unwrap, expect allowed
@@ -1,23 +1,30 @@
+mod cluster;
 pub mod config;
 mod connection;
 mod connection_manager;
+mod dead_peer;
 mod io;
 mod ip_manager;
 pub mod metrics;
 mod offload_stats;
+mod session_ticket;
 mod statistics;
 
 // re-export so server app does not need to depend on lightway-core
+pub use crate::cluster::{ClusterConfig, ClusterKey, InMemorySessionDirectory, SessionDirectory};
 pub use crate::connection_manager::DEFAULT_CONNECTION_AGE_EXPIRATION_INTERVAL;
+pub use crate::dead_peer::DeadPeerDetection;
+pub use crate::ip_manager::IpLeases;
+pub use crate::session_ticket::DEFAULT_SESSION_TICKET_KEY_RELOAD_INTERVAL;
 pub use crate::statistics::DEFAULT_STATISTICS_REPORTING_INTERVAL;
 use bytesize::ByteSize;
 use connection::Connection;
 #[cfg(feature = "debug")]
 pub use lightway_core::enable_tls_debug;
 pub use lightway_core::{
-    ConnectionType, DEFAULT_EXPRESSLANE_KEYS_ROTATION_INTERVAL, Event, ExpresslaneCbType,
-    ExpresslaneMetricsType, PluginFactoryError, PluginFactoryList, ServerAuth, ServerAuthHandle,
-    ServerAuthResult, SessionId, Version,
+    Acl, AclProtocol, AclRule, ConnectionType, DEFAULT_EXPRESSLANE_KEYS_ROTATION_INTERVAL, Event,
+    ExpresslaneCbType, ExpresslaneMetricsType, InsideIpRequest, PluginFactoryError,
+    PluginFactoryList, ServerAuth, ServerAuthHandle, ServerAuthResult, SessionId, Version,
 };
 
 /// Callback type for receiving per-connection events with session ID.
@@ -27,10 +34,15 @@ pub type ServerEventCbType = Arc<dyn Fn(SessionId, &Event) + Send + Sync>;
 use anyhow::{Context, Result, anyhow};
 use bytes::BytesMut;
 use ipnet::Ipv4Net;
-use lightway_app_utils::{PacketCodecFactoryType, TunConfig, connection_ticker_cb};
+use lightway_app_utils::{
+    PacketCodecFactoryType, TunConfig, connection_ticker_cb,
+    obfuscation::{Obfuscation, ObfuscationKey, WebSocket},
+    split_dns::{ServerConfigPayload, SplitDnsConfig},
+};
 use lightway_core::{
     AuthMethod, BuilderPredicates, ConnectionError, ConnectionResult, IOCallbackResult,
-    InsideIpConfig, MAX_IO_BATCH_SIZE, Secret, ServerContextBuilder, ipv4_update_destination,
+    InsideIOSendCallbackArg, InsideIpConfig, MAX_IO_BATCH_SIZE, Secret, ServerContext,
+    ServerContextBuilder, ipv4_update_destination,
 };
 use pnet_packet::ipv4::Ipv4Packet;
 use std::{
@@ -42,13 +54,14 @@ use std::{
 };
 use tokio::{
     net::{TcpListener, UdpSocket},
-    task::JoinHandle,
+    task::{JoinHandle, JoinSet},
 };
 use tracing::info;
 
 pub use crate::connection::ConnectionState;
 #[cfg(linux)]
 pub use crate::io::inside::InsideIORecvGso;
+pub use crate::io::inside::isolation::ClientIsolation;
 pub use crate::io::inside::{InsideIO, InsideIORecv, InsideIORecvBatch};
 
 use crate::io::outside::udp::send_queue::SendQueue;
@@ -78,6 +91,8 @@ fn debug_pkt_codec_fac(
 pub struct AuthState<'a> {
     pub local_addr: &'a SocketAddr,
     pub peer_addr: &'a SocketAddr,
+    /// IP assigned on an earlier authentication of this connection,
+    /// IPs are only allocated once authorized
     pub internal_ip: &'a Option<Ipv4Addr>,
     pub tunnel_protocol_version: Option<Version>,
 }
@@ -91,6 +106,15 @@ impl<SA: for<'a> ServerAuth<AuthState<'a>>> ServerAuth<connection::ConnectionSta
         &self,
         method: &AuthMethod,
         app_state: &mut connection::ConnectionState,
+    ) -> ServerAuthResult {
+        self.authorize_with_site_subnets(method, &[], app_state)
+    }
+
+    fn authorize_with_site_subnets(
+        &self,
+        method: &AuthMethod,
+        site_subnets: &[Ipv4Net],
+        app_state: &mut connection::ConnectionState,
     ) -> ServerAuthResult {
         let tunnel_protocol_version = match method {
             AuthMethod::VersionedToken { version, .. } => Some(*version),
@@ -103,13 +127,44 @@ impl<SA: for<'a> ServerAuth<AuthState<'a>>> ServerAuth<connection::ConnectionSta
             tunnel_protocol_version,
         };
         let authorized = self.0.authorize(method, &mut auth_state);
-        if matches!(authorized, ServerAuthResult::Denied) {
-            metrics::connection_rejected_access_denied();
+        match &authorized {
+            ServerAuthResult::Granted { handle, .. } => {
+                app_state.requested_ip = handle.as_ref().and_then(|handle| handle.inside_ip());
+                let identity = handle.as_ref().and_then(|handle| handle.identity());
+                app_state.identity = match (identity, method) {
+                    (Some(identity), _) => Some(identity.to_string()),
+                    (None, AuthMethod::UserPass { user, .. }) => Some(user.clone()),
+                    (None, _) => None,
+                };
+                let allowed = handle
+                    .as_ref()
+                    .map(|handle| handle.subnets())
+                    .unwrap_or_default();
+                app_state.subnets = allowed_site_subnets(site_subnets, &allowed);
+            }
+            ServerAuthResult::Denied => metrics::connection_rejected_access_denied(),
         }
         authorized
     }
 }
 
+/// The subnets of `advertised` by a client which are within one of the
+/// subnets `allowed` by its auth backend
+fn allowed_site_subnets(advertised: &[Ipv4Net], allowed: &[Ipv4Net]) -> Vec<Ipv4Net> {
+    advertised
+        .iter()
+        .filter(|subnet| {
+            let is_allowed = allowed.iter().any(|allowed| allowed.contains(*subnet));
+            if !is_allowed {
+                tracing::warn!(%subnet, "Site subnet advertised by client is not allowed");
+                metrics::site_subnet_not_allowed();
+            }
+            is_allowed
+        })
+        .copied()
+        .collect()
+}
+
 /// Connection mode
 ///
 /// Application can also attach server socket for library to use directly,
@@ -138,11 +193,23 @@ impl From<&ServerConnectionMode> for ConnectionType {
     }
 }
 
+/// A transport served on an address
+#[derive(Debug)]
+pub struct ServerListener {
+    /// Connection mode
+    pub mode: ServerConnectionMode,
+
+    /// Address to listen to, unused when `mode` carries a socket
+    pub bind_address: SocketAddr,
+}
+
 #[derive(educe::Educe)]
 #[educe(Debug)]
 pub struct ServerConfig<SA: for<'a> ServerAuth<AuthState<'a>>> {
-    /// Connection mode
-    pub mode: ServerConnectionMode,
+    /// Transports to serve, at least one. Connections over all of them
+    /// share the IP pool, a client switching transport keeps clear of
+    /// the IPs of every other client.
+    pub listeners: Vec<ServerListener>,
 
     /// Authentication manager
     #[educe(Debug(ignore))]
@@ -175,6 +242,18 @@ pub struct ServerConfig<SA: for<'a> ServerAuth<AuthState<'a>>> {
     /// exclusively for that particular incoming IP.
     pub ip_map: HashMap<IpAddr, Ipv4Net>,
 
+    /// Named subnets of `ip_pool` for auth backends to assign clients
+    /// to with [`InsideIpRequest::Pool`]
+    pub named_ip_pools: HashMap<String, Ipv4Net>,
+
+    /// Hand clients the IP they had before, IPs are not sticky when
+    /// `None`
+    pub ip_leases: Option<IpLeases>,
+
+    /// File to persist the state of the IP pools to across restarts,
+    /// so that the IPs assigned before a restart are reused last
+    pub ip_pool_state_file: Option<PathBuf>,
+
     /// Server IP to send in network_config message
     pub lightway_server_ip: Ipv4Addr,
 
@@ -184,6 +263,12 @@ pub struct ServerConfig<SA: for<'a> ServerAuth<AuthState<'a>>> {
     /// DNS IP to send in network_config message
     pub lightway_dns_ip: Ipv4Addr,
 
+    /// Split DNS configuration pushed to clients
+    pub split_dns: Option<SplitDnsConfig>,
+
+    /// Which other clients a client can reach on their inside IPs
+    pub client_isolation: ClientIsolation,
+
     /// Boolean flag to select actual client ip assigned or above static ip
     /// in network_config message
     pub use_dynamic_client_ip: bool,
@@ -219,6 +304,14 @@ pub struct ServerConfig<SA: for<'a> ServerAuth<AuthState<'a>>> {
     /// Enable IO-uring interface for Tunnel
     pub enable_tun_iouring: bool,
 
+    /// Translate the clients' traffic in userspace instead of using a
+    /// TUN device
+    pub enable_userspace_nat: bool,
+
+    /// Subnets the clients may not reach through the userspace NAT, on
+    /// top of the always denied special purpose addresses
+    pub userspace_nat_deny: Vec<Ipv4Net>,
+
     #[cfg(feature = "io-uring")]
     /// IO-uring submission queue count
     pub iouring_entry_count: usize,
@@ -236,6 +329,15 @@ pub struct ServerConfig<SA: for<'a> ServerAuth<AuthState<'a>>> {
     /// Interval between session statistics reports
     pub statistics_reporting_interval: Duration,
 
+    /// Close UDP sessions whose peer stopped answering keepalives,
+    /// disabled when `None`
+    pub dead_peer_detection: Option<DeadPeerDetection>,
+
+    /// Share the UDP sessions with the other nodes of a cluster, so a
+    /// client floating to another node keeps its session, standalone
+    /// when `None`
+    pub cluster: Option<ClusterConfig>,
+
     /// Inside plugins to use
     #[educe(Debug(method(debug_fmt_plugin_list)))]
     pub inside_plugins: PluginFactoryList,
@@ -248,12 +350,20 @@ pub struct ServerConfig<SA: for<'a> ServerAuth<AuthState<'a>>> {
     #[educe(Debug(method(debug_pkt_codec_fac)))]
     pub inside_pkt_codec: Option<PacketCodecFactoryType>,
 
-    /// Address to listen to
-    pub bind_address: SocketAddr,
+    /// File holding the hex encoded TLS session ticket key, session
+    /// tickets are disabled when `None`
+    pub session_ticket_key: Option<PathBuf>,
+
+    /// How often to re-read `session_ticket_key`, rotating to the key
+    /// it holds when it changed
+    pub session_ticket_key_reload_interval: Duration,
 
     /// Enable PROXY protocol support (TCP only)
     pub proxy_protocol: bool,
 
+    /// Obfuscation of the outside traffic, clients must match
+    pub obfuscation: Obfuscation,
+
     /// UDP Buffer size for the server
     pub udp_buffer_size: ByteSize,
 
@@ -273,35 +383,62 @@ pub struct ServerConfig<SA: for<'a> ServerAuth<AuthState<'a>>> {
 }
 
 impl<SA: for<'a> ServerAuth<AuthState<'a>>> ServerConfig<SA> {
-    pub fn try_from_auth_and_config(auth: SA, config: config::Config) -> Result<Self> {
+    pub fn try_from_auth_and_config(auth: SA, mut config: config::Config) -> Result<Self> {
         config.validate()?;
 
+        let ip_map = config.ip_map.take().unwrap_or_default().try_into()?;
+        let named_ip_pools = config.ip_pools.take().unwrap_or_default();
+        config::validate_ip_pools(
+            config.ip_pool,
+            &ip_map,
+            &named_ip_pools,
+            &config.reserved_ips(),
+        )?;
+
         let mut tun_config = lightway_app_utils::TunConfig::default();
         if let Some(tun_name) = config.tun_name {
             tun_config.tun_name(tun_name);
         }
         tun_config.up();
 
+        let mut listeners = Vec::new();
+        for bind_address in std::iter::once(config.bind_address).chain(config.extra_bind_addresses)
+        {
+            if !config.mode.is_tcp() {
+                listeners.push(ServerListener {
+                    mode: ServerConnectionMode::Datagram(None),
+                    bind_address,
+                });
+            }
+            if !config.mode.is_udp() {
+                listeners.push(ServerListener {
+                    mode: ServerConnectionMode::Stream(None),
+                    bind_address,
+                });
+            }
+        }
+
         Ok(crate::ServerConfig {
-            mode: match config.mode {
-                lightway_app_utils::args::ConnectionType::Udp => {
-                    crate::ServerConnectionMode::Datagram(None)
-                }
-                lightway_app_utils::args::ConnectionType::Tcp => {
-                    crate::ServerConnectionMode::Stream(None)
-                }
-            },
+            listeners,
             auth,
             server_cert: config.server_cert,
             server_key: config.server_key,
             tun_config,
             ip_pool: config.ip_pool,
-            ip_map: config.ip_map.unwrap_or_default().try_into()?,
+            ip_map,
+            named_ip_pools,
+            ip_leases: (!config.ip_lease_time.is_zero()).then(|| IpLeases {
+                lease_time: config.ip_lease_time.into(),
+                file: config.ip_lease_file,
+            }),
+            ip_pool_state_file: config.ip_pool_state_file,
             inside_io: None,
             tun_ip: config.tun_ip,
             lightway_server_ip: config.lightway_server_ip,
             lightway_client_ip: config.lightway_client_ip,
             lightway_dns_ip: config.lightway_dns_ip,
+            split_dns: config.split_dns,
+            client_isolation: config.client_isolation,
             use_dynamic_client_ip: false,
             enable_expresslane: config.enable_expresslane,
             expresslane_keys_rotation_interval: config.expresslane_keys_rotation_interval.into(),
@@ -313,6 +450,8 @@ impl<SA: for<'a> ServerAuth<AuthState<'a>>> ServerConfig<SA> {
             enable_tun_offload: config.enable_tun_offload,
             #[cfg(feature = "io-uring")]
             enable_tun_iouring: config.enable_tun_iouring,
+            enable_userspace_nat: config.enable_userspace_nat,
+            userspace_nat_deny: config.userspace_nat_deny,
             #[cfg(feature = "io-uring")]
             iouring_entry_count: config.iouring_entry_count,
             #[cfg(feature = "io-uring")]
@@ -320,11 +459,41 @@ impl<SA: for<'a> ServerAuth<AuthState<'a>>> ServerConfig<SA> {
             key_update_interval: config.key_update_interval.into(),
             connection_age_expiration_interval: config.connection_age_expiration_interval.into(),
             statistics_reporting_interval: config.statistics_reporting_interval.into(),
+            dead_peer_detection: (!config.dpd_idle_timeout.is_zero()).then(|| DeadPeerDetection {
+                idle_timeout: config.dpd_idle_timeout.into(),
+                interval: config.dpd_interval.into(),
+                max_missed: config.dpd_max_missed,
+            }),
+            cluster: match config.cluster_address {
+                Some(addr) => Some(ClusterConfig {
+                    addr,
+                    peers: config.cluster_peers,
+                    key: ClusterKey::read(
+                        config
+                            .cluster_key
+                            .as_deref()
+                            .context("cluster_address requires cluster_key")?,
+                    )?,
+                    directory: None,
+                }),
+                None => None,
+            },
             inside_plugins: Default::default(),
             outside_plugins: Default::default(),
             inside_pkt_codec: None,
-            bind_address: config.bind_address,
+            session_ticket_key: config.session_ticket_key,
+            session_ticket_key_reload_interval: config.session_ticket_key_reload_interval.into(),
             proxy_protocol: config.proxy_protocol,
+            obfuscation: Obfuscation {
+                key: config
+                    .obfuscation_key
+                    .as_deref()
+                    .map(ObfuscationKey::read)
+                    .transpose()?,
+                max_padding: config.obfuscation_padding,
+                max_jitter: config.obfuscation_jitter.into(),
+                websocket: config.websocket.then_some(WebSocket::Server),
+            },
             udp_buffer_size: config.udp_buffer_size,
             enable_batch_receive: config.enable_batch_receive,
             enable_batch_send: config.enable_batch_send,
@@ -356,6 +525,24 @@ pub(crate) fn handle_inside_io_error(conn: Arc<Connection>, result: ConnectionRe
     }
 }
 
+/// Connection of the client `destination` is routed to. Packets to a
+/// client's inside IP are translated to `lightway_client_ip`, packets
+/// to a subnet behind a client are passed on as is.
+fn route_inside_packet(
+    ip_manager: &IpManager<Arc<Connection>>,
+    buf: &mut [u8],
+    destination: Ipv4Addr,
+    lightway_client_ip: Ipv4Addr,
+) -> Option<Arc<Connection>> {
+    match ip_manager.find_connection(destination) {
+        Some(conn) => {
+            ipv4_update_destination(buf, lightway_client_ip);
+            Some(conn)
+        }
+        None => ip_manager.find_route(destination),
+    }
+}
+
 async fn inside_io_loop_default(
     inside_io: Arc<dyn InsideIO>,
     ip_manager: Arc<IpManager<Arc<Connection>>>,
@@ -382,9 +569,8 @@ async fn inside_io_loop_default(
             eprintln!("Invalid inside packet size (less than Ipv4 header)!");
             continue;
         };
-        let conn = ip_manager.find_connection(packet.get_destination());
-
-        ipv4_update_destination(buf.as_mut(), lightway_client_ip);
+        let destination = packet.get_destination();
+        let conn = route_inside_packet(&ip_manager, buf.as_mut(), destination, lightway_client_ip);
 
         if let Some(conn) = conn {
             let result = conn.inside_data_received(&mut buf);
@@ -396,9 +582,10 @@ async fn inside_io_loop_default(
 }
 
 /// Like [`inside_io_loop_default`], but pops packets in batches and
-/// holds every resulting encrypted datagram in a send queue that is
-/// flushed with one batched syscall per receive batch. Only runs for
-/// UDP servers — stream transports have no batched send path.
+/// holds every resulting encrypted datagram in the send queue of its
+/// UDP socket, each queue flushed with one batched syscall per receive
+/// batch. Only runs for UDP servers — stream transports have no batched
+/// send path, their sends go out immediately.
 ///
 /// `recv_buf_many` waits only when no packet is available, so batches
 /// form under backlog without adding latency.
@@ -406,7 +593,7 @@ async fn inside_io_loop_batched(
     inside_io: Arc<dyn InsideIORecvBatch>,
     ip_manager: Arc<IpManager<Arc<Connection>>>,
     lightway_client_ip: Ipv4Addr,
-    send_queue: Arc<SendQueue>,
+    send_queues: Vec<Arc<SendQueue>>,
 ) -> anyhow::Result<()> {
     let mut pkts: Vec<BytesMut> = Vec::with_capacity(MAX_IO_BATCH_SIZE);
     loop {
@@ -426,7 +613,7 @@ async fn inside_io_loop_batched(
         // the batch has been processed. There are no await points inside
         // the window; the flush itself may wait for socket writability,
         // which backpressures this loop.
-        let batch_guard = send_queue.begin_batch();
+        let batch_guards: Vec<_> = send_queues.iter().map(SendQueue::begin_batch).collect();
 
         for mut buf in pkts.drain(..) {
             let packet = Ipv4Packet::new(buf.as_ref());
@@ -434,9 +621,9 @@ async fn inside_io_loop_batched(
                 eprintln!("Invalid inside packet size (less than Ipv4 header)!");
                 continue;
             };
-            let conn = ip_manager.find_connection(packet.get_destination());
-
-            ipv4_update_destination(buf.as_mut(), lightway_client_ip);
+            let destination = packet.get_destination();
+            let conn =
+                route_inside_packet(&ip_manager, buf.as_mut(), destination, lightway_client_ip);
 
             if let Some(conn) = conn {
                 let result = conn.inside_data_received(&mut buf);
@@ -446,7 +633,9 @@ async fn inside_io_loop_batched(
             }
         }
 
-        batch_guard.flush().await;
+        for batch_guard in batch_guards {
+            batch_guard.flush().await;
+        }
     }
 }
 
@@ -496,9 +685,8 @@ async fn inside_io_loop_gso(
             pkt.clear();
             continue;
         };
-        let conn = ip_manager.find_connection(packet.get_destination());
-
-        ipv4_update_destination(pkt.as_mut(), lightway_client_ip);
+        let destination = packet.get_destination();
+        let conn = route_inside_packet(&ip_manager, pkt.as_mut(), destination, lightway_client_ip);
 
         if let Some(conn) = conn {
             let result = if hdr.is_gso_none() {
@@ -518,9 +706,6 @@ async fn inside_io_loop_gso(
 pub async fn server<SA: for<'a> ServerAuth<AuthState<'a>> + Sync + Send + 'static>(
     mut config: ServerConfig<SA>,
 ) -> Result<()> {
-    let server_key = Secret::PemFile(&config.server_key);
-    let server_cert = Secret::PemFile(&config.server_cert);
-
     info!("Server starting with config:\n{:#?}", &config);
 
     if let Some(tun_ip) = config.tun_ip {
@@ -549,14 +734,40 @@ pub async fn server<SA: for<'a> ServerAuth<AuthState<'a>> + Sync + Send + 'stati
         inside_ip_config,
         config.use_dynamic_client_ip,
         randomize_ippool,
-    );
+    )
+    .with_named_pools(config.named_ip_pools);
+    let ip_manager = match &config.ip_leases {
+        Some(ip_leases) => ip_manager.with_leases(ip_leases)?,
+        None => ip_manager,
+    };
+    let ip_manager = match &config.ip_pool_state_file {
+        Some(file) => ip_manager.with_pool_state(file)?,
+        None => ip_manager,
+    };
     let ip_manager = Arc::new(ip_manager);
-
-    let connection_type = config.mode;
+    ip_manager.spawn_maintenance();
+
+    anyhow::ensure!(!config.listeners.is_empty(), "No listener configured");
+    let serves = |connection_type: ConnectionType| {
+        config
+            .listeners
+            .iter()
+            .any(|l| ConnectionType::from(&l.mode) == connection_type)
+    };
+    let serves_datagram = serves(ConnectionType::Datagram);
+    let serves_stream = serves(ConnectionType::Stream);
     let auth = Arc::new(AuthAdapter(config.auth));
 
     let inside_io: Arc<dyn InsideIO> = match config.inside_io.take() {
         Some(io) => io,
+        None if config.enable_userspace_nat => {
+            info!("Translating inside traffic with userspace NAT");
+            Arc::new(io::inside::UserspaceNat::new(
+                config.ip_pool,
+                ip_manager.clone(),
+                config.userspace_nat_deny.clone(),
+            ))
+        }
         None => {
             use io::inside::Tun;
             #[cfg(target_os = "linux")]
@@ -583,37 +794,97 @@ pub async fn server<SA: for<'a> ServerAuth<AuthState<'a>> + Sync + Send + 'stati
         }
     };
 
-    let ctx = ServerContextBuilder::new(
-        (&connection_type).into(),
-        server_cert,
-        server_key,
-        auth,
-        ip_manager.clone(),
-        inside_io.clone().into_io_send_callback(),
-        connection_ticker_cb,
-    )?
-    .with_key_update_interval(config.key_update_interval)
-    .when(config.enable_expresslane, |b| {
-        b.with_expresslane(config.expresslane_keys_rotation_interval)
-    })
-    .when(config.expresslane_cb.is_some(), |b| {
-        b.with_expresslane_cb(config.expresslane_cb.clone().unwrap())
-    })
-    .when(config.expresslane_metrics.is_some(), |b| {
-        b.with_expresslane_metrics(config.expresslane_metrics.clone().unwrap())
-    })
-    .try_when(config.enable_pqc, |b| b.enable_pq_crypto())?
-    .with_inside_plugins(config.inside_plugins)
-    .with_outside_plugins(config.outside_plugins)
-    .build()?;
+    let session_ticket_key = config
+        .session_ticket_key
+        .as_deref()
+        .map(session_ticket::read_key)
+        .transpose()?;
+
+    let inside_io_send = inside_io.clone().into_io_send_callback();
+    let inside_io_send: InsideIOSendCallbackArg<ConnectionState> = match config.client_isolation {
+        ClientIsolation::Off => inside_io_send,
+        isolation => Arc::new(io::inside::IsolatingInsideIO::new(
+            inside_io_send,
+            ip_manager.clone(),
+            isolation,
+        )),
+    };
+
+    // One context per transport, both issuing connections to the same
+    // connection manager and IP pool
+    let build_ctx = |connection_type: ConnectionType| -> Result<ServerContext<ConnectionState>> {
+        let mut outside_plugins = config.outside_plugins.clone();
+        config
+            .obfuscation
+            .add_outside_plugins(connection_type, &mut outside_plugins);
+
+        Ok(ServerContextBuilder::new(
+            connection_type,
+            Secret::PemFile(&config.server_cert),
+            Secret::PemFile(&config.server_key),
+            auth.clone(),
+            ip_manager.clone(),
+            inside_io_send.clone(),
+            connection_ticker_cb,
+        )?
+        .with_key_update_interval(config.key_update_interval)
+        .when(
+            config.enable_expresslane && connection_type.is_datagram(),
+            |b| b.with_expresslane(config.expresslane_keys_rotation_interval),
+        )
+        .when(config.expresslane_cb.is_some(), |b| {
+            b.with_expresslane_cb(config.expresslane_cb.clone().unwrap())
+        })
+        .when(config.expresslane_metrics.is_some(), |b| {
+            b.with_expresslane_metrics(config.expresslane_metrics.clone().unwrap())
+        })
+        .try_when(config.enable_pqc, |b| b.enable_pq_crypto())?
+        .try_when_some(config.split_dns.clone(), |b, split_dns| {
+            b.with_server_config(
+                ServerConfigPayload {
+                    split_dns: Some(split_dns),
+                }
+                .to_bytes(),
+            )
+        })?
+        .try_when_some(session_ticket_key.as_ref(), |b, key| {
+            b.with_session_ticket_key(key)
+        })?
+        .with_inside_plugins(config.inside_plugins.clone())
+        .with_outside_plugins(outside_plugins)
+        .build()?)
+    };
+
+    let cluster = match config.cluster {
+        Some(cluster) => Some(cluster::Cluster::bind(cluster).await?),
+        None => None,
+    };
 
     let conn_manager = ConnectionManager::new(
-        ctx,
+        serves_datagram
+            .then(|| build_ctx(ConnectionType::Datagram))
+            .transpose()?,
+        serves_stream
+            .then(|| build_ctx(ConnectionType::Stream))
+            .transpose()?,
         config.inside_pkt_codec,
         config.event_cb,
         config.connection_age_expiration_interval,
+        cluster.clone(),
     );
 
+    if let Some(dead_peer_detection) = config.dead_peer_detection {
+        dead_peer::spawn(&conn_manager, dead_peer_detection);
+    }
+
+    if let Some(path) = &config.session_ticket_key {
+        session_ticket::spawn_reload(
+            &conn_manager,
+            path,
+            config.session_ticket_key_reload_interval,
+        );
+    }
+
     tokio::spawn(statistics::run(
         conn_manager.clone(),
         ip_manager.clone(),
@@ -634,7 +905,14 @@ pub async fn server<SA: for<'a> ServerAuth<AuthState<'a>> + Sync + Send + 'stati
         "enable_batch_send cannot be used with enable_tun_offload"
     );
 
-    let mut send_queue: Option<Arc<SendQueue>> = None;
+    // Also enforced by config::Config::validate: segments of a GSO send
+    // must be the same size and cannot be delayed one by one.
+    anyhow::ensure!(
+        !(gso && (config.obfuscation.max_padding > 0 || !config.obfuscation.max_jitter.is_zero())),
+        "Obfuscation padding and jitter cannot be used with enable_tun_offload"
+    );
+
+    let mut send_queues: Vec<Arc<SendQueue>> = Vec::new();
 
     if let Some(provider) = config.expresslane_metrics.clone() {
         tokio::spawn(offload_stats::run(
@@ -644,30 +922,38 @@ pub async fn server<SA: for<'a> ServerAuth<AuthState<'a>> + Sync + Send + 'stati
         ));
     }
 
-    let mut server: Box<dyn Server> = match connection_type {
-        ServerConnectionMode::Datagram(may_be_sock) => {
-            let udp_server = io::outside::UdpServer::new(
-                conn_manager.clone(),
-                config.bind_address,
-                config.udp_buffer_size,
-                config.enable_batch_receive,
-                config.enable_batch_send,
-                may_be_sock,
-            )
-            .await?;
-            send_queue = udp_server.send_queue();
-            Box::new(udp_server)
-        }
-        ServerConnectionMode::Stream(may_be_sock) => Box::new(
-            io::outside::TcpServer::new(
-                conn_manager.clone(),
-                config.bind_address,
-                config.proxy_protocol,
-                may_be_sock,
-            )
-            .await?,
-        ),
-    };
+    let mut servers = JoinSet::new();
+    for listener in config.listeners {
+        let mut server: Box<dyn Server + Send> = match listener.mode {
+            ServerConnectionMode::Datagram(may_be_sock) => {
+                let udp_server = io::outside::UdpServer::new(
+                    conn_manager.clone(),
+                    listener.bind_address,
+                    config.udp_buffer_size,
+                    config.enable_batch_receive,
+                    config.enable_batch_send,
+                    config.obfuscation.clone(),
+                    may_be_sock,
+                )
+                .await?;
+                send_queues.extend(udp_server.send_queue());
+                Box::new(udp_server)
+            }
+            ServerConnectionMode::Stream(may_be_sock) => Box::new(
+                io::outside::TcpServer::new(
+                    conn_manager.clone(),
+                    listener.bind_address,
+                    config.proxy_protocol,
+                    may_be_sock,
+                )
+                .await?,
+            ),
+        };
+        servers.spawn(async move { server.run().await });
+    }
+    if let Some(cluster) = cluster {
+        servers.spawn(cluster.run(conn_manager.clone()));
+    }
 
     let inside_io_loop: JoinHandle<anyhow::Result<()>> = {
         if gso {
@@ -684,10 +970,8 @@ pub async fn server<SA: for<'a> ServerAuth<AuthState<'a>> + Sync + Send + 'stati
             }
             #[cfg(not(target_os = "linux"))]
             unreachable!()
-        } else if config.enable_batch_send
-            && let Some(send_queue) = send_queue
-        {
-            // send_queue exists only for UDP servers; on stream
+        } else if config.enable_batch_send && !send_queues.is_empty() {
+            // Send queues exist only for UDP servers; on stream
             // transports there is no batched send path, so the flag is
             // a no-op and the default loop runs below.
             let batch_io = inside_io.clone().as_batch().context(
@@ -697,7 +981,7 @@ pub async fn server<SA: for<'a> ServerAuth<AuthState<'a>> + Sync + Send + 'stati
                 batch_io,
                 ip_manager.clone(),
                 config.lightway_client_ip,
-                send_queue,
+                send_queues,
             ))
         } else {
             tokio::spawn(inside_io_loop_default(
@@ -738,11 +1022,12 @@ pub async fn server<SA: for<'a> ServerAuth<AuthState<'a>> + Sync + Send + 'stati
     }
 
     tokio::select! {
-        err = server.run() => err.context("Outside IO loop exited"),
+        Some(result) = servers.join_next() => result.map_err(|e| anyhow!(e).context("Outside IO loop panicked"))?.context("Outside IO loop exited"),
         io = inside_io_loop => io.map_err(|e| anyhow!(e).context("Inside IO loop panicked"))?.context("Inside IO loop exited"),
         _ = ctrlc_rx => {
             info!("Sigterm or Sigint received");
             conn_manager.shutdown();
+            ip_manager.save_state()?;
             Ok(())
         }
     }
@@ -754,6 +1039,7 @@ mod tests {
     use lightway_core::InsideIOSendCallbackArg;
     use std::collections::VecDeque;
     use std::sync::Mutex;
+    use test_case::test_case;
 
     /// InsideIO whose recv_buf_many pops scripted batches, then errors.
     struct ScriptedInsideIO {
@@ -860,7 +1146,7 @@ mod tests {
             io,
             test_ip_manager(),
             Ipv4Addr::new(10, 125, 0, 5),
-            send_queue,
+            vec![send_queue],
         )
         .await;
 
@@ -870,4 +1156,22 @@ mod tests {
             "loop must consume every scripted batch before the error"
         );
     }
+
+    #[test_case(&["192.168.10.0/24"], &["192.168.10.0/24"], &["192.168.10.0/24"]; "allowed")]
+    #[test_case(&["192.168.10.0/25", "192.168.11.0/24"], &["192.168.10.0/24"], &["192.168.10.0/25"]; "within an allowed subnet")]
+    #[test_case(&["192.168.0.0/16"], &["192.168.10.0/24"], &[]; "wider than allowed")]
+    #[test_case(&["192.168.10.0/24"], &[], &[]; "none allowed")]
+    #[test_case(&[], &["192.168.10.0/24"], &[]; "none advertised")]
+    fn allowed_site_subnets(advertised: &[&str], allowed: &[&str], expected: &[&str]) {
+        let parse = |subnets: &[&str]| -> Vec<Ipv4Net> {
+            subnets
+                .iter()
+                .map(|subnet| subnet.parse().unwrap())
+                .collect()
+        };
+        assert_eq!(
+            super::allowed_site_subnets(&parse(advertised), &parse(allowed)),
+            parse(expected)
+        );
+    }
 }
@@ -117,6 +117,7 @@ async fn main() -> Result<()> {
     let server_config = crate::ServerConfig::try_from_auth_and_config(
         crate::auth::Auth::new(
             config.user_db.as_ref().map(AsRef::as_ref),
+            config.user_subnets.clone().unwrap_or_default(),
             config.token_rsa_pub_key_pem.as_ref().map(AsRef::as_ref),
         )?,
         config,
@@ -17,6 +17,8 @@ const METRIC_CONNECTION_LINK_UP: &str = "conn_link_up";
 const METRIC_CONNECTION_ONLINE: &str = "conn_online";
 static METRIC_CONNECTION_REJECTED_NO_FREE_IP: LazyLock<Counter> =
     LazyLock::new(|| counter!("conn_rejected_no_free_ip"));
+static METRIC_CONNECTION_REJECTED_INSIDE_IP_UNAVAILABLE: LazyLock<Counter> =
+    LazyLock::new(|| counter!("conn_rejected_inside_ip_unavailable"));
 static METRIC_CONNECTION_REJECTED_ACCESS_DENIED: LazyLock<Counter> =
     LazyLock::new(|| counter!("conn_rejected_access_denied"));
 static METRIC_CONNECTION_DATA_AFT_DISCONNECT: LazyLock<Counter> =
@@ -31,6 +33,12 @@ static METRIC_CONNECTION_CLIENT_CLOSED: LazyLock<Counter> =
     LazyLock::new(|| counter!("conn_client_closed"));
 static METRIC_CONNECTION_STALE_CLOSED: LazyLock<Counter> =
     LazyLock::new(|| counter!("conn_stale_closed"));
+static METRIC_CONNECTION_DPD_PROBE_SENT: LazyLock<Counter> =
+    LazyLock::new(|| counter!("conn_dpd_probe_sent"));
+static METRIC_CONNECTION_DPD_PROBE_MISSED: LazyLock<Counter> =
+    LazyLock::new(|| counter!("conn_dpd_probe_missed"));
+static METRIC_CONNECTION_DPD_PEER_DEAD: LazyLock<Counter> =
+    LazyLock::new(|| counter!("conn_dpd_peer_dead"));
 static METRIC_CONNECTION_KEY_UPDATE_START: LazyLock<Counter> =
     LazyLock::new(|| counter!("key_update_start"));
 static METRIC_CONNECTION_KEY_UPDATE_COMPLETE: LazyLock<Counter> =
@@ -51,12 +59,18 @@ static METRIC_UDP_SESSION_ROTATION_FINALIZED: LazyLock<Counter> =
     LazyLock::new(|| counter!("udp_session_rotation_finalized"));
 static METRIC_UDP_SESSION_ROTATION_ATTEMPTED_VIA_REPLAY: LazyLock<Counter> =
     LazyLock::new(|| counter!("udp_session_rotation_attempted_via_replay"));
+static METRIC_UDP_MULTIPATH_PATH_ADDED: LazyLock<Counter> =
+    LazyLock::new(|| counter!("udp_multipath_path_added"));
+static METRIC_UDP_MULTIPATH_PATH_EXPIRED: LazyLock<Counter> =
+    LazyLock::new(|| counter!("udp_multipath_path_expired"));
 static METRIC_UDP_RECV_TRUNCATED: LazyLock<Counter> =
     LazyLock::new(|| counter!("udp_recv_truncated"));
 static METRIC_UDP_RECV_INVALID_ADDR: LazyLock<Counter> =
     LazyLock::new(|| counter!("udp_recv_invalid_addr"));
 static METRIC_UDP_RECV_MISSING_PKTINFO: LazyLock<Counter> =
     LazyLock::new(|| counter!("udp_recv_missing_pktinfo"));
+static METRIC_UDP_SESSION_FORWARDED: LazyLock<Counter> =
+    LazyLock::new(|| counter!("udp_session_forwarded"));
 static METRIC_UDP_SEND_BATCH_SIZE: LazyLock<Histogram> =
     LazyLock::new(|| histogram!("udp_send_batch_size"));
 static METRIC_UDP_SEND_BATCH_DROPPED: LazyLock<Counter> =
@@ -66,6 +80,16 @@ static METRIC_UDP_SEND_BATCH_BLOCKED: LazyLock<Counter> =
 static METRIC_UDP_SEND_BATCH_MISSED_FLUSH: LazyLock<Counter> =
     LazyLock::new(|| counter!("udp_send_batch_missed_flush"));
 
+// Cluster
+static METRIC_CLUSTER_FORWARD_FAILED: LazyLock<Counter> =
+    LazyLock::new(|| counter!("cluster_forward_failed"));
+static METRIC_CLUSTER_FORWARDED_UNKNOWN_SESSION: LazyLock<Counter> =
+    LazyLock::new(|| counter!("cluster_forwarded_unknown_session"));
+static METRIC_CLUSTER_BAD_MESSAGE: LazyLock<Counter> =
+    LazyLock::new(|| counter!("cluster_bad_message"));
+static METRIC_CLUSTER_REPLAYED_MESSAGE: LazyLock<Counter> =
+    LazyLock::new(|| counter!("cluster_replayed_message"));
+
 // Connection performance
 static METRIC_TO_LINK_UP_TIME: LazyLock<Histogram> =
     LazyLock::new(|| histogram!("to_link_up_time"));
@@ -81,6 +105,25 @@ static METRIC_TUN_REJECTED_NO_CONNECTION: LazyLock<Counter> =
     LazyLock::new(|| counter!("tun_rejected_packet_no_connection"));
 static METRIC_TUN_REJECTED_NO_CLIENT_IP: LazyLock<Counter> =
     LazyLock::new(|| counter!("tun_rejected_packet_no_client_ip"));
+static METRIC_CLIENT_TO_CLIENT_ALLOWED: LazyLock<Counter> =
+    LazyLock::new(|| counter!("client_to_client_allowed"));
+static METRIC_CLIENT_TO_CLIENT_DROPPED: LazyLock<Counter> =
+    LazyLock::new(|| counter!("client_to_client_dropped"));
+static METRIC_NAT_QUEUE_FULL: LazyLock<Counter> = LazyLock::new(|| counter!("nat_queue_full"));
+static METRIC_NAT_UNSUPPORTED_PACKET: LazyLock<Counter> =
+    LazyLock::new(|| counter!("nat_unsupported_packet"));
+static METRIC_NAT_FLOW_LIMIT_REACHED: LazyLock<Counter> =
+    LazyLock::new(|| counter!("nat_flow_limit_reached"));
+static METRIC_NAT_TCP_CONNECT_FAILED: LazyLock<Counter> =
+    LazyLock::new(|| counter!("nat_tcp_connect_failed"));
+static METRIC_NAT_DESTINATION_DENIED: LazyLock<Counter> =
+    LazyLock::new(|| counter!("nat_destination_denied"));
+static METRIC_NAT_OVERSIZED_REPLY: LazyLock<Counter> =
+    LazyLock::new(|| counter!("nat_oversized_reply"));
+static METRIC_SITE_SUBNET_REJECTED: LazyLock<Counter> =
+    LazyLock::new(|| counter!("site_subnet_rejected"));
+static METRIC_SITE_SUBNET_NOT_ALLOWED: LazyLock<Counter> =
+    LazyLock::new(|| counter!("site_subnet_not_allowed"));
 
 // Traffic volume
 static METRIC_TUN_FROM_CLIENT: LazyLock<Counter> = LazyLock::new(|| counter!("tun_from_client"));
@@ -206,6 +249,12 @@ pub(crate) fn connection_rejected_no_free_ip() {
     METRIC_CONNECTION_REJECTED_NO_FREE_IP.increment(1);
 }
 
+/// Connection lifecycle: [`lightway_core::Connection`] rejected, the
+/// inside IP requested by its auth handle is taken or unknown.
+pub(crate) fn connection_rejected_inside_ip_unavailable() {
+    METRIC_CONNECTION_REJECTED_INSIDE_IP_UNAVAILABLE.increment(1);
+}
+
 /// Connection lifecycle: [`lightway_core::Connection`] rejected,
 /// authentication failed.
 pub(crate) fn connection_rejected_access_denied() {
@@ -241,6 +290,22 @@ pub(crate) fn connection_closed() {
     METRIC_CONNECTION_CLOSED.increment(1);
 }
 
+/// Connection lifecycle: dead peer detection probed a quiet peer
+pub(crate) fn dpd_probe_sent() {
+    METRIC_CONNECTION_DPD_PROBE_SENT.increment(1);
+}
+
+/// Connection lifecycle: a dead peer detection probe went unanswered
+pub(crate) fn dpd_probe_missed() {
+    METRIC_CONNECTION_DPD_PROBE_MISSED.increment(1);
+}
+
+/// Connection lifecycle: [`lightway_core::Connection`] closed by dead
+/// peer detection
+pub(crate) fn dpd_peer_dead() {
+    METRIC_CONNECTION_DPD_PEER_DEAD.increment(1);
+}
+
 pub(crate) fn connection_key_update_start() {
     METRIC_CONNECTION_KEY_UPDATE_START.increment(1);
 }
@@ -259,6 +324,16 @@ pub(crate) fn udp_session_rotation_attempted_via_replay() {
     METRIC_UDP_SESSION_ROTATION_ATTEMPTED_VIA_REPLAY.increment(1);
 }
 
+/// UDP: Path added to a multipath session
+pub(crate) fn udp_multipath_path_added() {
+    METRIC_UDP_MULTIPATH_PATH_ADDED.increment(1);
+}
+
+/// UDP: Idle path removed from a multipath session
+pub(crate) fn udp_multipath_path_expired() {
+    METRIC_UDP_MULTIPATH_PATH_EXPIRED.increment(1);
+}
+
 /// UDP: Session ID rotation started
 pub(crate) fn udp_session_rotation_begin() {
     trace!("Begin session rotation");
@@ -285,6 +360,34 @@ pub(crate) fn udp_rejected_session() {
     METRIC_UDP_REJECTED_SESSION.increment(1);
 }
 
+/// UDP: Datagram of a session owned by another node of the cluster
+/// forwarded to it
+pub(crate) fn udp_session_forwarded() {
+    METRIC_UDP_SESSION_FORWARDED.increment(1);
+}
+
+/// Cluster: Datagram could not be forwarded to the owner of its session
+pub(crate) fn cluster_forward_failed() {
+    METRIC_CLUSTER_FORWARD_FAILED.increment(1);
+}
+
+/// Cluster: Datagram forwarded by another node is of a session this
+/// node does not own (anymore)
+pub(crate) fn cluster_forwarded_unknown_session() {
+    METRIC_CLUSTER_FORWARDED_UNKNOWN_SESSION.increment(1);
+}
+
+/// Cluster: Message from another node could not be decoded
+pub(crate) fn cluster_bad_message() {
+    METRIC_CLUSTER_BAD_MESSAGE.increment(1);
+}
+
+/// Cluster: Message from another node was received before, or is too
+/// old
+pub(crate) fn cluster_replayed_message() {
+    METRIC_CLUSTER_REPLAYED_MESSAGE.increment(1);
+}
+
 pub(crate) fn udp_parse_wire_failed() {
     METRIC_UDP_PARSE_WIRE_FAILED.increment(1);
 }
@@ -370,6 +473,58 @@ pub fn tun_rejected_packet_no_client_ip() {
     METRIC_TUN_REJECTED_NO_CLIENT_IP.increment(1);
 }
 
+/// Client isolation let a packet from a client to another client through
+pub(crate) fn client_to_client_allowed() {
+    METRIC_CLIENT_TO_CLIENT_ALLOWED.increment(1);
+}
+
+/// Client isolation dropped a packet from a client to another client
+pub(crate) fn client_to_client_dropped() {
+    METRIC_CLIENT_TO_CLIENT_DROPPED.increment(1);
+}
+
+/// Userspace NAT dropped a packet since its queue was full
+pub(crate) fn nat_queue_full() {
+    METRIC_NAT_QUEUE_FULL.increment(1);
+}
+
+/// Userspace NAT dropped a packet of a protocol other than TCP and UDP
+pub(crate) fn nat_unsupported_packet() {
+    METRIC_NAT_UNSUPPORTED_PACKET.increment(1);
+}
+
+/// Userspace NAT refused a new flow since it translates too many
+pub(crate) fn nat_flow_limit_reached() {
+    METRIC_NAT_FLOW_LIMIT_REACHED.increment(1);
+}
+
+/// Userspace NAT failed to open the host connection of a TCP flow
+pub(crate) fn nat_tcp_connect_failed() {
+    METRIC_NAT_TCP_CONNECT_FAILED.increment(1);
+}
+
+/// Userspace NAT refused a new flow to a denied destination
+pub(crate) fn nat_destination_denied() {
+    METRIC_NAT_DESTINATION_DENIED.increment(1);
+}
+
+/// Userspace NAT dropped a UDP datagram too large for the inside MTU
+pub(crate) fn nat_oversized_reply() {
+    METRIC_NAT_OVERSIZED_REPLY.increment(1);
+}
+
+/// A subnet behind a client was not routed to it since it overlaps
+/// the IP pool or a subnet routed to another client
+pub(crate) fn site_subnet_rejected() {
+    METRIC_SITE_SUBNET_REJECTED.increment(1);
+}
+
+/// A subnet advertised by a client was not routed to it since its
+/// auth backend does not allow it
+pub(crate) fn site_subnet_not_allowed() {
+    METRIC_SITE_SUBNET_NOT_ALLOWED.increment(1);
+}
+
 /// Bytes sent from client to the TUN device.
 pub fn tun_from_client(sz: usize) {
     METRIC_TUN_FROM_CLIENT.increment(sz as u64);
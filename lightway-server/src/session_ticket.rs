@@ -0,0 +1,62 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use lightway_core::SessionTicketKey;
+use tracing::{info, warn};
+
+use crate::connection_manager::ConnectionManager;
+
+/// Default interval between reads of the session ticket key file
+pub const DEFAULT_SESSION_TICKET_KEY_RELOAD_INTERVAL: Duration = Duration::from_mins(1);
+
+/// Read a hex encoded [`SessionTicketKey`] from `path`.
+pub(crate) fn read_key(path: &Path) -> Result<SessionTicketKey> {
+    let key = std::fs::read_to_string(path)
+        .with_context(|| format!("Reading session ticket key {}", path.display()))?;
+    key.parse()
+        .with_context(|| format!("Parsing session ticket key {}", path.display()))
+}
+
+/// Re-read the key file every `interval`, rotating to the key it holds
+/// whenever it changed. Keeps the current key when the file is unreadable.
+pub(crate) fn spawn_reload(conn_manager: &Arc<ConnectionManager>, path: &Path, interval: Duration) {
+    let path = path.to_path_buf();
+    conn_manager.spawn_periodic_task(interval, move |conn_manager| {
+        let key = match read_key(&path) {
+            Ok(key) => key,
+            Err(e) => {
+                warn!("Keeping the current session ticket key: {e:#}");
+                return;
+            }
+        };
+        match conn_manager.rotate_session_ticket_key(&key) {
+            Ok(true) => info!("Session ticket key rotated"),
+            Ok(false) => {}
+            Err(e) => warn!("Failed to rotate session ticket key: {e}"),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn read_key_from_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "{}", "ab".repeat(SessionTicketKey::LEN)).unwrap();
+        let key = read_key(file.path()).unwrap();
+        assert_eq!(key.as_bytes(), &[0xab; SessionTicketKey::LEN]);
+    }
+
+    #[test]
+    fn read_invalid_key() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "not a key").unwrap();
+        assert!(read_key(file.path()).is_err());
+        assert!(read_key(Path::new("/nonexistent/ticket.key")).is_err());
+    }
+}
@@ -103,7 +103,8 @@ fn ip_manager_stats(ip_manager: &Weak<IpManager>) {
     };
 
     let count = ip_manager.allocated_ips_count();
-    info!(current = count, "IP Statistics");
+    let leases = ip_manager.leases_count();
+    info!(current = count, ?leases, "IP Statistics");
     metrics::assigned_internal_ips(count);
 }
 
@@ -175,6 +176,8 @@ mod tests {
             last_outside_data_received: now - outside_data_age,
             last_data_traffic_from_peer: now - data_traffic_age,
             last_data_delivered_to_inside: now - data_traffic_age,
+            last_outside_data_sent: now - outside_data_age,
+            last_data_sent: now - data_traffic_age,
         }];
 
         let (standby, active) = calculate_session_stats(&sessions);